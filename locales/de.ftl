@@ -0,0 +1,31 @@
+# Built-in strings for dots-notifier (German)
+
+# Application name shown by the notification daemon
+app-name = Systembenachrichtigung
+
+# Footer naming who sent a broadcast
+sent-by = Gesendet von { $sender }
+
+# Summary of the notifications suppressed as duplicates of others
+suppressed-title = Ähnliche Meldungen unterdrückt
+suppressed-body = { $count ->
+        [one] Eine ähnliche Meldung wurde
+       *[other] { $count } ähnliche Meldungen wurden
+    } in { $minutes ->
+        [one] der letzten Minute
+       *[other] den letzten { $minutes } Minuten
+    } unterdrückt. Mit `dots-notifier history` lassen sie sich ansehen.
+
+# Line written above a notification on the user's terminals
+terminal-heading = Rundnachricht von { $app }:
+
+# Confirmation asked by the command line before a large send
+confirm-recipients = Die Benachrichtigung würde { $recipients } Benutzer erreichen.
+confirm-recipients-unknown = Die Benachrichtigung könnte mehr als { $threshold } Benutzer erreichen.
+confirm-hosts = Die Benachrichtigung würde auf { $hosts ->
+        [one] einem Rechner
+       *[other] { $hosts } Rechnern
+    } gesendet.
+confirm-question = Senden? [y/N]
+confirm-refused = Ohne Bestätigung nicht gesendet; mit --yes wird sie gesendet
+not-sent = Nicht gesendet
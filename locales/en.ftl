@@ -0,0 +1,31 @@
+# Built-in strings for dots-notifier (English)
+
+# Application name shown by the notification daemon
+app-name = System Notifier
+
+# Footer naming who sent a broadcast
+sent-by = Sent by { $sender }
+
+# Summary of the notifications suppressed as duplicates of others
+suppressed-title = Similar alerts suppressed
+suppressed-body = { $count ->
+        [one] One similar alert was
+       *[other] { $count } similar alerts were
+    } suppressed in the last { $minutes ->
+        [one] minute
+       *[other] { $minutes } minutes
+    }. Run `dots-notifier history` to review them.
+
+# Line written above a notification on the user's terminals
+terminal-heading = Broadcast message from { $app }:
+
+# Confirmation asked by the command line before a large send
+confirm-recipients = The notification would reach { $recipients } users.
+confirm-recipients-unknown = The notification may reach more than { $threshold } users.
+confirm-hosts = The notification would be sent on { $hosts ->
+        [one] one host
+       *[other] { $hosts } hosts
+    }.
+confirm-question = Send it? [y/N]
+confirm-refused = Not sent without confirmation; pass --yes to send it
+not-sent = Not sent
@@ -60,6 +60,25 @@ let
     </busconfig>
   '';
 
+  polkitAction = "me.section.notifier.send";
+
+  polkitPolicyFile = pkgs.writeText "dbus-notifier.policy" ''
+    <?xml version="1.0" encoding="UTF-8"?>
+    <!DOCTYPE policyconfig PUBLIC "-//freedesktop//DTD PolicyKit Policy Configuration 1.0//EN"
+     "http://www.freedesktop.org/standards/PolicyKit/1/policyconfig.dtd">
+    <policyconfig>
+      <action id="${polkitAction}">
+        <description>Send notifications to all graphical users</description>
+        <message>Authentication is required to broadcast notifications</message>
+        <defaults>
+          <allow_any>no</allow_any>
+          <allow_inactive>no</allow_inactive>
+          <allow_active>auth_admin</allow_active>
+        </defaults>
+      </action>
+    </policyconfig>
+  '';
+
 in
 {
   options.services.system-notifier = {
@@ -75,7 +94,13 @@ in
   config = lib.mkIf cfg.enable {
     users.groups.${cfg.group} = {};
     users.groups.broadcast = {};
-    environment.systemPackages = [ notifier-pkg ];
+    environment.systemPackages = [
+      notifier-pkg
+      (pkgs.runCommand "dbus-notifier-polkit" {} ''
+        mkdir -p $out/share/polkit-1/actions
+        cp ${polkitPolicyFile} $out/share/polkit-1/actions/${polkitAction}.policy
+      '')
+    ];
 
     services.dbus.packages = [
       (pkgs.runCommand "dbus-notifier-config" {} ''
@@ -89,7 +114,7 @@ in
 
     security.polkit.extraConfig = ''
       polkit.addRule(function(action, subject) {
-        if (action.id == "${dbusName}.send_to_all") {
+        if (action.id == "${polkitAction}") {
           if (subject.isInGroup("${cfg.group}")) {
             return polkit.Result.YES;
           } else {
@@ -0,0 +1,124 @@
+//! Broadcasts kept for users who log in later
+//!
+//! A broadcast only reaches users with an active graphical session, so someone
+//! logging in an hour after a critical alert never sees it. Broadcasts sent with
+//! `queue_for_absent` are kept here for `absent_queue_ttl_secs`, with the group,
+//! seat and exclusions they were narrowed down by, and delivered to each user they
+//! are meant for as soon as the server sees them log in. Like the late join grace,
+//! the queue is only kept in memory.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::broadcast::BroadcastId;
+use crate::request::SendOptions;
+use crate::types::TargetUser;
+
+/// How long broadcasts are kept for absent users unless configured otherwise
+pub const DEFAULT_ABSENT_QUEUE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Users a queued broadcast is meant for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Audience {
+    /// Unix group the users must be members of
+    pub group: Option<String>,
+    /// Seat the user's session must be on
+    pub seat: Option<String>,
+    /// Users left out, by username or uid
+    pub exclude_users: Vec<String>,
+}
+
+impl Audience {
+    /// Get the users a broadcast with these options is meant for
+    pub fn from_options(options: &SendOptions) -> Self {
+        Self {
+            group: options.group.clone(),
+            seat: options.seat.clone(),
+            exclude_users: options.exclude_users.clone(),
+        }
+    }
+
+    /// Whether a user is meant to receive the broadcast, given whether they are a member of a group
+    pub fn includes(&self, user: &TargetUser, is_member: impl Fn(&str) -> bool) -> bool {
+        self.seat.as_deref().is_none_or(|seat| user.seat() == Some(seat))
+            && !self.exclude_users.iter().any(|excluded| user.is(excluded))
+            && self.group.as_deref().is_none_or(is_member)
+    }
+}
+
+#[derive(Debug)]
+struct Queued {
+    expires_at: Instant,
+    audience: Audience,
+}
+
+/// Broadcasts kept for users who have not logged in yet, by broadcast id
+#[derive(Debug, Default)]
+pub struct AbsentQueue {
+    queued: Mutex<BTreeMap<BroadcastId, Queued>>,
+}
+
+impl AbsentQueue {
+    /// Create an empty queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep a broadcast for users of an audience who log in within `ttl`
+    pub fn queue(&self, broadcast_id: BroadcastId, audience: Audience, ttl: Duration) {
+        let queued = Queued { expires_at: Instant::now() + ttl, audience };
+        self.queued.lock().unwrap().insert(broadcast_id, queued);
+    }
+
+    /// Get the broadcasts still kept and who they are meant for, oldest first, dropping expired ones
+    pub fn pending(&self) -> Vec<(BroadcastId, Audience)> {
+        let mut queued = self.queued.lock().unwrap();
+        let now = Instant::now();
+        queued.retain(|_, entry| entry.expires_at > now);
+        queued.iter().map(|(&broadcast_id, entry)| (broadcast_id, entry.audience.clone())).collect()
+    }
+
+    /// Number of broadcasts kept, including expired ones not dropped yet
+    pub fn len(&self) -> usize {
+        self.queued.lock().unwrap().len()
+    }
+
+    /// Whether no broadcast is kept
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired_broadcasts_dropped() {
+        let queue = AbsentQueue::new();
+        queue.queue(2, Audience::default(), Duration::from_secs(3600));
+        queue.queue(1, Audience::default(), Duration::ZERO);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pending(), vec![(2, Audience::default())]);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_audience_narrowed() {
+        let options = SendOptions {
+            group: Some("wheel".to_string()),
+            seat: Some("seat0".to_string()),
+            exclude_users: vec!["1001".to_string()],
+            ..SendOptions::default()
+        };
+        let audience = Audience::from_options(&options);
+        let alice = TargetUser::new(1000, "alice".to_string()).with_seat("seat0");
+        let bob = TargetUser::new(1001, "bob".to_string()).with_seat("seat0");
+        assert!(audience.includes(&alice, |group| group == "wheel"));
+        assert!(!audience.includes(&alice, |_| false));
+        assert!(!audience.includes(&bob, |_| true));
+        assert!(!audience.includes(&TargetUser::new(1000, "alice".to_string()), |_| true));
+        assert!(Audience::default().includes(&bob, |_| false));
+    }
+}
@@ -0,0 +1,131 @@
+//! Export and import of server state
+//!
+//! Pending notifications, tracked broadcasts with their channels and tags,
+//! polls, scheduled broadcasts, the maintenance mode and whether delivery is
+//! paused can be dumped into a versioned JSON archive, so state can be backed up
+//! before an upgrade or migrated to another host. Archives written by earlier
+//! releases are upgraded when read, see [`crate::migrate`].
+
+use serde::{Deserialize, Serialize};
+
+use crate::broadcast::{BroadcastId, BroadcastRecord};
+use crate::migrate::migrate;
+use crate::poll::Poll;
+use crate::schedule::ScheduledBroadcast;
+use crate::spool::SpooledNotification;
+
+/// Version of the archive format written by this build
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// A snapshot of the server state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateArchive {
+    pub version: u32,
+    pub maintenance: bool,
+    /// Whether delivery is paused
+    #[serde(default)]
+    pub paused: bool,
+    pub broadcasts: Vec<BroadcastRecord>,
+    pub spool: Vec<SpooledNotification>,
+    /// Notifications taken from the spool whose delivery had not finished
+    #[serde(default)]
+    pub in_flight: Vec<SpooledNotification>,
+    pub polls: Vec<(BroadcastId, Poll)>,
+    /// Broadcasts scheduled for later
+    #[serde(default)]
+    pub scheduled: Vec<ScheduledBroadcast>,
+}
+
+impl StateArchive {
+    /// Serialize the archive as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Parse an archive, refusing formats newer than this build understands
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::from_value(serde_json::from_str(json)?)
+    }
+
+    /// Read an archive from its JSON value, migrating formats of earlier releases
+    pub fn from_value(mut value: serde_json::Value) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        migrate(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Number of broadcasts, pending notifications, polls and scheduled broadcasts in the archive
+    pub fn len(&self) -> usize {
+        self.broadcasts.len() + self.spool.len() + self.polls.len() + self.scheduled.len()
+    }
+
+    /// Whether the archive holds no broadcasts, pending notifications, polls or scheduled broadcasts
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::broadcast::BroadcastRegistry;
+    use crate::delivery::{DeliveryErrorKind, DeliveryStatus};
+    use crate::payload::BroadcastPayload;
+    use crate::types::{TargetUser, Urgency};
+
+    fn archive() -> StateArchive {
+        let registry = BroadcastRegistry::new();
+        let user = TargetUser::new(1000, "alice".to_string()).with_desktop("sway");
+        let payload = BroadcastPayload::new("Reboot", "Tonight?")
+            .with_urgency(Some(Urgency::Critical))
+            .with_actions(vec!["Yes".to_string()]);
+        let id = registry.register_with(Some("ops".to_string()), vec!["incident-421".to_string()], None);
+        registry.record_delivery(id, user.clone(), 7);
+        registry.record_status(id, user.clone(), DeliveryStatus::Failed(DeliveryErrorKind::DaemonMissing));
+        StateArchive {
+            version: ARCHIVE_VERSION,
+            maintenance: true,
+            paused: true,
+            broadcasts: registry.list(None),
+            spool: vec![SpooledNotification::new(user.clone(), Arc::new(payload)).with_broadcast_id(id)],
+            in_flight: Vec::new(),
+            polls: vec![(
+                id,
+                Poll {
+                    options: vec!["Yes".to_string()],
+                    responses: vec![(user, "Yes".to_string())],
+                },
+            )],
+            scheduled: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let archive = archive();
+        let json = archive.to_json().unwrap();
+        assert!(json.contains("\"daemon-missing\""));
+        assert_eq!(StateArchive::from_json(&json).unwrap(), archive);
+        assert_eq!(archive.len(), 3);
+    }
+
+    #[test]
+    fn test_newer_versions_refused() {
+        let mut archive = archive();
+        archive.version = ARCHIVE_VERSION + 1;
+        let json = archive.to_json().unwrap();
+        assert!(StateArchive::from_json(&json).is_err());
+        assert!(StateArchive::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_archives_without_in_flight_read() {
+        let mut json: serde_json::Value = serde_json::from_str(&archive().to_json().unwrap()).unwrap();
+        json.as_object_mut().unwrap().remove("in_flight");
+        json.as_object_mut().unwrap().remove("scheduled");
+        let archive = StateArchive::from_json(&json.to_string()).unwrap();
+        assert!(archive.in_flight.is_empty());
+        assert!(archive.scheduled.is_empty());
+    }
+}
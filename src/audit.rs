@@ -0,0 +1,233 @@
+//! Audit log of requests made to the server
+//!
+//! Multi-user servers often have to show who sent what to whom. When an
+//! `[audit]` path is configured, every D-Bus request that sends, changes or
+//! withdraws notifications, or changes how the server delivers them, is
+//! appended as a JSON line with the caller's uid, pid and unit as vouched for
+//! by the bus daemon, a summary of the request and its outcome. Read-only
+//! queries are not recorded. The log is kept apart from the tracing output, so
+//! changing log levels never loses entries, and is rotated once it grows past
+//! `max_bytes`, keeping `keep` older files as `<path>.1` (newest) onwards.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::caller::Caller;
+
+/// Size above which the audit log is rotated unless configured otherwise
+pub const DEFAULT_AUDIT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Rotated audit logs kept unless configured otherwise
+pub const DEFAULT_AUDIT_KEEP: usize = 5;
+
+/// Outcome recorded for requests that succeeded
+pub const AUDIT_OK: &str = "ok";
+
+/// Configuration of the audit log
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// File requests are appended to; no audit log is kept if unset
+    pub path: Option<PathBuf>,
+    /// Size in bytes above which the log is rotated
+    pub max_bytes: Option<u64>,
+    /// Number of rotated logs kept; 0 discards the log when rotating
+    pub keep: Option<usize>,
+}
+
+impl AuditConfig {
+    /// Get the size above which the log is rotated
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes.unwrap_or(DEFAULT_AUDIT_MAX_BYTES)
+    }
+
+    /// Get the number of rotated logs kept
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(DEFAULT_AUDIT_KEEP)
+    }
+}
+
+/// A request, as recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the request arrived, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// D-Bus method called, such as `SendToAll`
+    pub method: String,
+    /// Unique bus name of the calling connection, if known
+    pub bus_name: Option<String>,
+    pub uid: Option<u32>,
+    pub pid: Option<u32>,
+    /// Systemd service the caller runs in, if any
+    pub unit: Option<String>,
+    /// What was asked for, such as the title and size of a broadcast
+    pub summary: String,
+    /// [`AUDIT_OK`], or the error the request was answered with
+    pub outcome: String,
+}
+
+impl AuditEntry {
+    /// Describe a request by a caller
+    pub fn new(timestamp: u64, method: &str, caller: Option<&Caller>, summary: String, outcome: String) -> Self {
+        Self {
+            timestamp,
+            method: method.to_string(),
+            bus_name: caller.map(|caller| caller.bus_name.clone()),
+            uid: caller.and_then(|caller| caller.uid),
+            pid: caller.and_then(|caller| caller.pid),
+            unit: caller.and_then(|caller| caller.unit.clone()),
+            summary,
+            outcome,
+        }
+    }
+}
+
+/// Summarize a notification for the audit log by its title and the size of its body
+pub fn summarize(title: &str, body: &str) -> String {
+    format!("title={:?} body_bytes={}", title, body.len())
+}
+
+/// Append-only, rotated audit log, off unless opened on a file
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    max_bytes: u64,
+    keep: usize,
+    /// Serializes appends and rotations, so concurrent requests never interleave their lines
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    /// Open the audit log configured, if any
+    pub fn open(config: &AuditConfig) -> Self {
+        Self {
+            path: config.path.clone(),
+            max_bytes: config.max_bytes(),
+            keep: config.keep(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Whether requests are recorded
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Record a request, rotating the log first if the entry would make it too large
+    ///
+    /// The log is only readable by its owner and group, as entries name users and processes.
+    /// Appending blocks on the file system, so async callers run it with `spawn_blocking`.
+    pub fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        line.push('\n');
+        let _guard = self.lock.lock().unwrap();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        if size > 0 && size + line.len() as u64 > self.max_bytes {
+            self.rotate(path)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o640)
+            .open(path)?
+            .write_all(line.as_bytes())
+    }
+
+    /// Shift the rotated logs up by one, dropping the oldest, and move the log to `<path>.1`
+    fn rotate(&self, path: &Path) -> io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(path);
+        }
+        for index in (1..self.keep).rev() {
+            match fs::rename(rotated_path(path, index), rotated_path(path, index + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(path, rotated_path(path, 1))
+    }
+}
+
+/// Get the path of the `index`th newest rotated log
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: u64) -> AuditEntry {
+        let caller = Caller {
+            bus_name: ":1.42".to_string(),
+            uid: Some(990),
+            pid: Some(4242),
+            unit: Some("backup.service".to_string()),
+        };
+        AuditEntry::new(timestamp, "SendToAll", Some(&caller), summarize("Backup", "Done"), AUDIT_OK.to_string())
+    }
+
+    fn read(path: &Path) -> Vec<AuditEntry> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit").join("audit.jsonl");
+        let log = AuditLog::open(&AuditConfig { path: Some(path.clone()), ..AuditConfig::default() });
+        assert!(log.is_enabled());
+        log.append(&entry(1)).unwrap();
+        log.append(&entry(2)).unwrap();
+
+        let entries = read(&path);
+        assert_eq!(entries, vec![entry(1), entry(2)]);
+        assert_eq!(entries[0].uid, Some(990));
+        assert_eq!(entries[0].summary, "title=\"Backup\" body_bytes=4");
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let line_len = serde_json::to_string(&entry(1)).unwrap().len() as u64 + 1;
+        let config = AuditConfig {
+            path: Some(path.clone()),
+            max_bytes: Some(2 * line_len),
+            keep: Some(2),
+        };
+        let log = AuditLog::open(&config);
+        for timestamp in 1..=7 {
+            log.append(&entry(timestamp)).unwrap();
+        }
+
+        assert_eq!(read(&path), vec![entry(7)]);
+        assert_eq!(read(&rotated_path(&path, 1)), vec![entry(5), entry(6)]);
+        assert_eq!(read(&rotated_path(&path, 2)), vec![entry(3), entry(4)]);
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_disabled_log() {
+        let log = AuditLog::default();
+        assert!(!log.is_enabled());
+        log.append(&entry(1)).unwrap();
+    }
+}
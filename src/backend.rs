@@ -0,0 +1,267 @@
+//! Chain of backends notifications are delivered through
+//!
+//! A user's notification daemon can be reached in several ways: by connecting
+//! to the user's session bus from the server, or by running the helper as the
+//! user through `systemd-run`, `machinectl shell` or `sudo`. The configured
+//! backends are tried in order until one delivers. A daemon that is missing or
+//! rejects the notification would do so whichever way it is reached, so such
+//! failures end the chain; any other failure falls through to the next backend.
+
+use std::fmt;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::delivery::{DeliveryError, DeliveryErrorKind};
+use crate::helper::{HelperSink, Launcher};
+use crate::helper_stats::HelperStats;
+use crate::payload::BroadcastPayload;
+use crate::sink::{DbusSink, DeliveryOptions, NotificationSink};
+use crate::types::TargetUser;
+
+/// A way of reaching a user's notification daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeliveryBackend {
+    /// Connect to the user's session bus from the server
+    Direct,
+    /// Run the helper as the user with `systemd-run`
+    Helper,
+    /// Run the helper as the user with `machinectl shell`
+    Machinectl,
+    /// Run the helper as the user with `sudo`
+    Sudo,
+}
+
+impl DeliveryBackend {
+    /// Get the name of this backend, as configured
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryBackend::Direct => "direct",
+            DeliveryBackend::Helper => "helper",
+            DeliveryBackend::Machinectl => "machinectl",
+            DeliveryBackend::Sudo => "sudo",
+        }
+    }
+
+    /// Get how the helper is started for this backend, if it runs the helper
+    fn launcher(self) -> Option<Launcher> {
+        match self {
+            DeliveryBackend::Direct => None,
+            DeliveryBackend::Helper => Some(Launcher::SystemdRun),
+            DeliveryBackend::Machinectl => Some(Launcher::Machinectl),
+            DeliveryBackend::Sudo => Some(Launcher::Sudo),
+        }
+    }
+}
+
+impl fmt::Display for DeliveryBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether a failure of one backend might not happen with another
+fn falls_through(kind: DeliveryErrorKind) -> bool {
+    !matches!(kind, DeliveryErrorKind::DaemonMissing | DeliveryErrorKind::NotifyRejected)
+}
+
+/// Sink trying a list of backends in order
+#[derive(Debug, Clone)]
+pub struct BackendChain {
+    backends: Vec<(DeliveryBackend, Arc<dyn NotificationSink>)>,
+}
+
+impl BackendChain {
+    /// Create a chain of backends, tried in the given order
+    pub fn new(backends: Vec<(DeliveryBackend, Arc<dyn NotificationSink>)>) -> Self {
+        Self { backends }
+    }
+
+    /// Create the chain set up by the `delivery_backends` setting, recording runs of the helper in `helper_stats`
+    pub fn from_config(config: &Config, helper_stats: Arc<HelperStats>) -> Self {
+        let backends = config.delivery_backends();
+        let helper = backends
+            .iter()
+            .any(|backend| backend.launcher().is_some())
+            .then(|| HelperSink::from_config(config).with_stats(helper_stats));
+        let backends = backends
+            .into_iter()
+            .map(|backend| {
+                let sink: Arc<dyn NotificationSink> = match (backend.launcher(), &helper) {
+                    (Some(launcher), Some(helper)) => Arc::new(helper.clone().with_launcher(launcher)),
+                    _ => Arc::new(DbusSink),
+                };
+                (backend, sink)
+            })
+            .collect();
+        let chain = Self::new(backends);
+        debug!(backends = ?chain.backends(), "Configured delivery backends.");
+        chain
+    }
+
+    /// Get the backends, in the order they are tried
+    pub fn backends(&self) -> Vec<DeliveryBackend> {
+        self.backends.iter().map(|(backend, _)| *backend).collect()
+    }
+}
+
+impl NotificationSink for BackendChain {
+    fn notify<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        payload: Arc<BroadcastPayload>,
+        options: &'a DeliveryOptions,
+    ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+        Box::pin(async move {
+            let mut last_error = DeliveryError::new(DeliveryErrorKind::Other, "no delivery backend configured");
+            for (backend, sink) in &self.backends {
+                debug!(uid = user.uid, %backend, "Delivering through backend.");
+                match sink.notify(user, payload.clone(), options).await {
+                    Ok(notification_id) => return Ok(notification_id),
+                    Err(e) if falls_through(e.kind()) => {
+                        let kind = e.kind();
+                        warn!(uid = user.uid, %backend, %kind, "Delivery backend failed, trying the next: {}", e.message());
+                        last_error = e;
+                    }
+                    Err(e) => {
+                        debug!(uid = user.uid, %backend, kind = %e.kind(), "Delivery backend failed: {}", e.message());
+                        return Err(e);
+                    }
+                }
+            }
+            Err(last_error)
+        })
+    }
+
+    fn close<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        bus_name: &'a str,
+        notification_id: u32,
+    ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+        Box::pin(async move {
+            let mut last_error = DeliveryError::new(DeliveryErrorKind::Other, "no delivery backend configured");
+            for (backend, sink) in &self.backends {
+                match sink.close(user, bus_name, notification_id).await {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        debug!(uid = user.uid, %backend, "Failed to close notification through backend: {}", e);
+                        last_error = e;
+                    }
+                }
+            }
+            Err(last_error)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::nested::NestedBusPolicy;
+    use crate::sound::Sound;
+
+    /// Sink failing every delivery with a kind, counting its attempts
+    #[derive(Debug)]
+    struct FailingSink {
+        kind: Option<DeliveryErrorKind>,
+        attempts: Mutex<u32>,
+    }
+
+    impl FailingSink {
+        fn new(kind: Option<DeliveryErrorKind>) -> Arc<Self> {
+            Arc::new(Self { kind, attempts: Mutex::new(0) })
+        }
+
+        fn attempts(&self) -> u32 {
+            *self.attempts.lock().unwrap()
+        }
+    }
+
+    impl NotificationSink for FailingSink {
+        fn notify<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _payload: Arc<BroadcastPayload>,
+            _options: &'a DeliveryOptions,
+        ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+            *self.attempts.lock().unwrap() += 1;
+            let result = match self.kind {
+                Some(kind) => Err(DeliveryError::new(kind, "failed")),
+                None => Ok(7),
+            };
+            Box::pin(async move { result })
+        }
+
+        fn close<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _bus_name: &'a str,
+            _notification_id: u32,
+        ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn options() -> DeliveryOptions {
+        DeliveryOptions {
+            app_name: "System Notifier".to_string(),
+            icon: "dialog-warning".to_string(),
+            desktop_entry: None,
+            timeout: -1,
+            replaces_id: 0,
+            sound: Sound::DaemonDefault,
+            bus_name: crate::dbus::NOTIFICATIONS_BUS_NAME.to_string(),
+            responses: None,
+            correlation_id: None,
+            footer: None,
+            terminal_heading: None,
+            profiles: Arc::default(),
+            nested_buses: NestedBusPolicy::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_through() {
+        let user = TargetUser::new(1000, "alice".to_string());
+        let payload = Arc::new(BroadcastPayload::new("t", "b"));
+        let unreachable = FailingSink::new(Some(DeliveryErrorKind::NoSessionBus));
+        let helper = FailingSink::new(None);
+        let chain = BackendChain::new(vec![
+            (DeliveryBackend::Direct, unreachable.clone()),
+            (DeliveryBackend::Helper, helper.clone()),
+        ]);
+        assert_eq!(chain.notify(&user, payload.clone(), &options()).await.unwrap(), 7);
+        assert_eq!((unreachable.attempts(), helper.attempts()), (1, 1));
+
+        let missing = FailingSink::new(Some(DeliveryErrorKind::DaemonMissing));
+        let chain = BackendChain::new(vec![
+            (DeliveryBackend::Direct, missing.clone()),
+            (DeliveryBackend::Sudo, helper.clone()),
+        ]);
+        let error = chain.notify(&user, payload.clone(), &options()).await.unwrap_err();
+        assert_eq!(error.kind(), DeliveryErrorKind::DaemonMissing);
+        assert_eq!(helper.attempts(), 1);
+
+        let chain = BackendChain::new(vec![(DeliveryBackend::Machinectl, unreachable.clone())]);
+        let error = chain.notify(&user, payload.clone(), &options()).await.unwrap_err();
+        assert_eq!(error.kind(), DeliveryErrorKind::NoSessionBus);
+        assert!(BackendChain::new(Vec::new()).notify(&user, payload, &options()).await.is_err());
+    }
+
+    #[test]
+    fn test_chain_from_config() {
+        let config = Config::from_toml_str("delivery_backends = [\"sudo\", \"direct\"]").unwrap();
+        let chain = BackendChain::from_config(&config, Arc::default());
+        assert_eq!(chain.backends(), vec![DeliveryBackend::Sudo, DeliveryBackend::Direct]);
+        let chain = BackendChain::from_config(&Config::default(), Arc::default());
+        assert_eq!(chain.backends(), vec![DeliveryBackend::Direct]);
+        assert_eq!(DeliveryBackend::Machinectl.to_string(), "machinectl");
+    }
+}
@@ -0,0 +1,161 @@
+//! Load test of the server pipeline built into the binary
+//!
+//! `dots-notifier bench` sends broadcasts through a server whose sessions are
+//! made up and whose notifications are discarded by a [`NullSink`], so the
+//! throughput and latency of queueing, templating and routing can be measured
+//! on the hardware a terminal server would run on, without any desktop. The
+//! server uses the configuration it would run with, but keeps no state.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::delivery::DeliveryStatus;
+use crate::sink::NullSink;
+use crate::store::StoreBackend;
+use crate::types::TargetUser;
+use crate::NotifierService;
+
+/// First uid of the made-up users, above the range of regular accounts
+const FIRST_BENCH_UID: u32 = 100_000;
+
+/// Make up users with graphical sessions
+pub fn fake_users(count: u32) -> HashSet<TargetUser> {
+    (0..count)
+        .map(|i| TargetUser::new(FIRST_BENCH_UID + i, format!("bench{}", i)))
+        .collect()
+}
+
+/// Throughput and latency measured by a load test
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    pub users: u32,
+    pub messages: u32,
+    /// Notifications handed to the sink
+    pub deliveries: usize,
+    /// Notifications spooled or failed instead, e.g. by delivery windows or maintenance mode
+    pub undelivered: usize,
+    pub elapsed: Duration,
+    /// Time each broadcast took from submission to the last user, sorted
+    pub latencies: Vec<Duration>,
+}
+
+impl BenchReport {
+    /// Get the latency below which a share of the broadcasts completed, e.g. 0.95
+    pub fn percentile(&self, share: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (share * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    /// Get the number of broadcasts completed per second
+    pub fn messages_per_sec(&self) -> f64 {
+        f64::from(self.messages) / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Get the number of notifications delivered per second
+    pub fn deliveries_per_sec(&self) -> f64 {
+        self.deliveries as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "users: {}", self.users)?;
+        writeln!(f, "messages: {}", self.messages)?;
+        writeln!(f, "deliveries: {}", self.deliveries)?;
+        if self.undelivered > 0 {
+            writeln!(f, "undelivered: {}", self.undelivered)?;
+        }
+        writeln!(f, "elapsed: {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "throughput: {:.1} messages/s, {:.1} deliveries/s",
+            self.messages_per_sec(),
+            self.deliveries_per_sec()
+        )?;
+        write!(
+            f,
+            "latency: p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+            self.percentile(0.50),
+            self.percentile(0.95),
+            self.percentile(0.99),
+            self.latencies.last().copied().unwrap_or_default(),
+        )
+    }
+}
+
+/// Send `messages` broadcasts, one after the other, to `users` made-up users
+///
+/// Everything the server would keep across restarts stays in memory, and the
+/// made-up sessions never expire, so the run depends on neither the disk nor logind.
+pub async fn run(mut config: Config, users: u32, messages: u32) -> Result<BenchReport, Box<dyn std::error::Error>> {
+    config.state_dir = Some(std::env::temp_dir().join(format!("dots-notifier-bench-{}", std::process::id())));
+    config.store = StoreBackend::Memory;
+    config.journal = false;
+    config.persist_notification_ids = false;
+    config.socket_path = None;
+    config.session_cache_ttl_secs = Some(u64::from(u32::MAX));
+    let service = NotifierService::new(config).with_sink(Arc::new(NullSink::default()));
+    service.sessions().store(fake_users(users));
+
+    let mut report = BenchReport {
+        users,
+        messages,
+        deliveries: 0,
+        undelivered: 0,
+        elapsed: Duration::ZERO,
+        latencies: Vec::with_capacity(messages as usize),
+    };
+    let started = Instant::now();
+    for i in 0..messages {
+        let sent = Instant::now();
+        let title = format!("Benchmark message {}", i + 1);
+        let payload = service.prepare_payload(title, "Sent by dots-notifier bench".to_string(), Vec::new(), None)?;
+        let (broadcast_id, _) = service.broadcast(None, Vec::new(), payload).await?;
+        report.latencies.push(sent.elapsed());
+        let outcomes = service.state.broadcasts.get(broadcast_id).map(|record| record.report).unwrap_or_default();
+        let delivered = outcomes.iter().filter(|(_, status)| *status == DeliveryStatus::Delivered).count();
+        report.deliveries += delivered;
+        report.undelivered += users as usize - delivered;
+    }
+    report.elapsed = started.elapsed();
+    report.latencies.sort();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bench_run() {
+        let report = run(Config::default(), 3, 5).await.unwrap();
+        assert_eq!((report.users, report.messages), (3, 5));
+        assert_eq!((report.deliveries, report.undelivered), (15, 0));
+        assert_eq!(report.latencies.len(), 5);
+        assert!(report.latencies.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(report.to_string().contains("deliveries: 15"));
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let report = BenchReport {
+            users: 1,
+            messages: 4,
+            deliveries: 4,
+            undelivered: 0,
+            elapsed: Duration::from_secs(2),
+            latencies: (1..=4).map(Duration::from_millis).collect(),
+        };
+        assert_eq!(report.percentile(0.5), Duration::from_millis(2));
+        assert_eq!(report.percentile(0.99), Duration::from_millis(4));
+        assert_eq!(report.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(report.messages_per_sec(), 2.0);
+        assert_eq!(fake_users(3).len(), 3);
+    }
+}
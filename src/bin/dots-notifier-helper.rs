@@ -0,0 +1,80 @@
+//! Delivers one notification on the session bus of the user running it
+//!
+//! Started by the server with the `helper` delivery strategy. Prints the id of
+//! the delivered notification, or exits with the code of the failure's kind.
+//! With `--listen` it then prints the key of each action the user invokes.
+//! With `--protocol-version` it only prints the version of the protocol it speaks.
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+
+use dots_notifier::{
+    helper::{HelperArgs, ACTION_LINE_PREFIX, PROTOCOL_VERSION, PROTOCOL_VERSION_ARG},
+    image::NotificationImage,
+    session::owning_user,
+    sink::{DbusSink, NotificationSink},
+};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    if std::env::args().nth(1).as_deref() == Some(PROTOCOL_VERSION_ARG) {
+        println!("{}", PROTOCOL_VERSION);
+        return ExitCode::SUCCESS;
+    }
+    let args = match HelperArgs::from_process() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let user = match owning_user() {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let user = match &args.desktop {
+        Some(desktop) => user.with_desktop(desktop.as_str()),
+        None => user,
+    };
+
+    let mut options = args.options();
+    let (responses, mut actions) = mpsc::unbounded_channel();
+    if args.listen {
+        options.responses = Some(responses);
+    } else {
+        drop(responses);
+    }
+
+    // Runs as the user, so it may read the image itself
+    let mut payload = args.payload();
+    if let Some(path) = &payload.image {
+        match NotificationImage::load(path) {
+            Ok(image) => payload = payload.with_loaded_image(Some(Arc::new(image))),
+            Err(e) => {
+                eprintln!("Sending the notification without its image: {}", e);
+                payload = payload.with_image(None);
+            }
+        }
+    }
+
+    match DbusSink.notify(&user, Arc::new(payload), &options).await {
+        Ok(notification_id) => {
+            println!("{}", notification_id);
+            // Ends once the notification is closed or stops being listened to
+            drop(options);
+            while let Some(action) = actions.recv().await {
+                println!("{}{}", ACTION_LINE_PREFIX, action);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(e.kind().exit_code() as u8)
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Minimal client sending broadcasts over the server's Unix socket
+//!
+//! Built with the `client-light` feature. It needs no D-Bus stack, so it fits
+//! into initramfs images and minimal containers with the host's socket mounted.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use dots_notifier::socket::{send_request, SocketRequest, DEFAULT_SOCKET_PATH};
+
+/// Environment variable overriding the default socket path
+const SOCKET_ENV: &str = "DOTS_NOTIFIER_SOCKET";
+
+const USAGE: &str =
+    "Usage: dots-notifier-light [--socket PATH] [--channel NAME] [--tag TAG]... [--allow-duplicate] TITLE BODY";
+
+/// Parse the command line into the socket path and request
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<(PathBuf, SocketRequest), String> {
+    let mut socket = std::env::var_os(SOCKET_ENV).map_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH), PathBuf::from);
+    let (mut channel, mut tags, mut positional) = (None, Vec::new(), Vec::new());
+    let mut allow_duplicate = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--socket" => socket = PathBuf::from(value()?),
+            "--channel" => channel = Some(value()?),
+            "--tag" => tags.push(value()?),
+            "--allow-duplicate" => allow_duplicate = true,
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            "--" => positional.extend(args.by_ref()),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}\n{}", flag, USAGE)),
+            _ => positional.push(arg),
+        }
+    }
+    let [title, body] = <[String; 2]>::try_from(positional).map_err(|_| USAGE.to_string())?;
+    Ok((socket, SocketRequest { title, body, channel, tags, allow_duplicate }))
+}
+
+fn main() -> ExitCode {
+    let (socket, request) = match parse_args(std::env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    match send_request(&socket, &request) {
+        Ok(broadcast_id) => {
+            println!("{}", broadcast_id);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to send broadcast via {}: {}", socket.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let (socket, request) =
+            parse_args(args(&["--socket", "/host/run/notifier.sock", "--tag", "boot", "Disk", "fsck done"]))
+                .unwrap();
+        assert_eq!(socket, PathBuf::from("/host/run/notifier.sock"));
+        assert_eq!(request.tags, vec!["boot".to_string()]);
+        assert_eq!((request.title.as_str(), request.body.as_str()), ("Disk", "fsck done"));
+
+        let (_, request) = parse_args(args(&["--channel", "status", "--", "--title", "body"])).unwrap();
+        assert_eq!(request.channel.as_deref(), Some("status"));
+        assert_eq!(request.title, "--title");
+        assert!(!request.allow_duplicate);
+
+        let (_, request) = parse_args(args(&["--allow-duplicate", "Disk", "fsck done"])).unwrap();
+        assert!(request.allow_duplicate);
+
+        assert!(parse_args(args(&["only-title"])).is_err());
+        assert!(parse_args(args(&["--urgency", "critical", "t", "b"])).is_err());
+        assert!(parse_args(args(&["t", "b", "--tag"])).is_err());
+    }
+}
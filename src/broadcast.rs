@@ -0,0 +1,633 @@
+//! Tracking of delivered broadcasts
+//!
+//! Every broadcast gets an id, and the notification ids returned by each user's
+//! notification daemon are recorded against it so the broadcast can later be
+//! withdrawn from every desktop. Broadcasts can carry free-form tags, so all
+//! notifications related to an incident can be managed together.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::delivery::DeliveryStatus;
+use crate::payload::BroadcastPayload;
+use crate::types::{TargetUser, Urgency};
+
+/// Identifier assigned to each broadcast by the server
+pub type BroadcastId = u64;
+
+/// Maximum number of broadcasts kept for later closing; the oldest are forgotten first
+pub const MAX_TRACKED_BROADCASTS: usize = 1024;
+
+/// A broadcast and the notifications it produced on each desktop
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastRecord {
+    pub id: BroadcastId,
+    /// Unique id tying together the log messages of every process involved in the broadcast
+    pub correlation_id: Uuid,
+    pub channel: Option<String>,
+    pub tags: Vec<String>,
+    /// Latest content of the broadcast, kept so it can be replayed
+    pub payload: Option<Arc<BroadcastPayload>>,
+    pub deliveries: Vec<(TargetUser, u32)>,
+    /// Latest delivery outcome for each recipient
+    pub report: Vec<(TargetUser, DeliveryStatus)>,
+    /// When the broadcast was sent, in seconds since the Unix epoch; 0 for records saved before it was kept
+    #[serde(default)]
+    pub sent_at: u64,
+    /// Message of the latest delivery failure for each recipient whose delivery failed, by uid
+    #[serde(default)]
+    pub errors: BTreeMap<u32, String>,
+    /// Notification ids of replaced broadcasts, by uid, updated in place when the user is next delivered to
+    #[serde(default)]
+    pub replaces: BTreeMap<u32, u32>,
+}
+
+impl BroadcastRecord {
+    /// Whether the broadcast was sent at least `age` before `now`, in seconds since the Unix epoch
+    pub fn older_than(&self, age: Duration, now: u64) -> bool {
+        self.sent_at.saturating_add(age.as_secs()) <= now
+    }
+
+    /// Whether the broadcast carries a tag
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Approximate number of bytes of memory held by this record
+    ///
+    /// The payload is shared with pending deliveries and counted by the spool instead.
+    pub fn size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.channel.as_ref().map_or(0, String::len)
+            + self.tags.iter().map(|tag| mem::size_of::<String>() + tag.len()).sum::<usize>()
+            + self
+                .deliveries
+                .iter()
+                .map(|(user, _)| mem::size_of::<(TargetUser, u32)>() + user.username.len())
+                .sum::<usize>()
+            + self
+                .report
+                .iter()
+                .map(|(user, _)| mem::size_of::<(TargetUser, DeliveryStatus)>() + user.username.len())
+                .sum::<usize>()
+            + self.errors.values().map(|error| mem::size_of::<(u32, String)>() + error.len()).sum::<usize>()
+            + self.replaces.len() * mem::size_of::<(u32, u32)>()
+    }
+
+    /// Get the message of the latest delivery failure for a recipient
+    pub fn error(&self, uid: u32) -> Option<&str> {
+        self.errors.get(&uid).map(String::as_str)
+    }
+}
+
+/// A notification of a tracked broadcast shown on a user's desktop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Displayed {
+    pub broadcast_id: BroadcastId,
+    pub notification_id: u32,
+    /// Whether the broadcast is critical, and so is never closed to make room for others
+    pub critical: bool,
+}
+
+#[derive(Debug, Default)]
+struct RegistryInner {
+    next_id: BroadcastId,
+    records: BTreeMap<BroadcastId, BroadcastRecord>,
+}
+
+/// Registry of recent broadcasts
+#[derive(Debug, Default)]
+pub struct BroadcastRegistry {
+    inner: Mutex<RegistryInner>,
+}
+
+impl BroadcastRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new broadcast, optionally posted to a channel, and return its id
+    pub fn register(&self, channel: Option<String>) -> BroadcastId {
+        self.register_with(channel, Vec::new(), None)
+    }
+
+    /// Register a new broadcast with its tags and content, and return its id
+    pub fn register_with(
+        &self,
+        channel: Option<String>,
+        tags: Vec<String>,
+        payload: Option<Arc<BroadcastPayload>>,
+    ) -> BroadcastId {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.records.insert(
+            id,
+            BroadcastRecord {
+                id,
+                correlation_id: Uuid::new_v4(),
+                channel,
+                tags,
+                payload,
+                deliveries: Vec::new(),
+                report: Vec::new(),
+                sent_at: unix_now(),
+                errors: BTreeMap::new(),
+                replaces: BTreeMap::new(),
+            },
+        );
+        while inner.records.len() > MAX_TRACKED_BROADCASTS {
+            inner.records.pop_first();
+        }
+        id
+    }
+
+    /// Record the notification id a user's daemon assigned for a broadcast
+    pub fn record_delivery(&self, id: BroadcastId, user: TargetUser, notification_id: u32) {
+        if let Some(record) = self.inner.lock().unwrap().records.get_mut(&id) {
+            record.deliveries.push((user, notification_id));
+        }
+    }
+
+    /// Record the latest delivery outcome of a broadcast for a user
+    ///
+    /// The message of an earlier failure is forgotten once the user is delivered to.
+    pub fn record_status(&self, id: BroadcastId, user: TargetUser, status: DeliveryStatus) {
+        if let Some(record) = self.inner.lock().unwrap().records.get_mut(&id) {
+            if matches!(status, DeliveryStatus::Delivered | DeliveryStatus::Spooled) {
+                record.errors.remove(&user.uid);
+            }
+            match record.report.iter_mut().find(|(recipient, _)| recipient.uid == user.uid) {
+                Some((_, previous)) => *previous = status,
+                None => record.report.push((user, status)),
+            }
+        }
+    }
+
+    /// Record the message of a failure to deliver a broadcast to a user
+    pub fn record_error(&self, id: BroadcastId, uid: u32, message: impl Into<String>) {
+        if let Some(record) = self.inner.lock().unwrap().records.get_mut(&id) {
+            record.errors.insert(uid, message.into());
+        }
+    }
+
+    /// Get the notifications of tracked broadcasts shown to a user, oldest broadcast first
+    pub fn displayed(&self, uid: u32) -> Vec<Displayed> {
+        let inner = self.inner.lock().unwrap();
+        let mut displayed = Vec::new();
+        for record in inner.records.values() {
+            let critical = record.payload.as_ref().is_some_and(|payload| payload.urgency == Some(Urgency::Critical));
+            for (user, notification_id) in &record.deliveries {
+                if user.uid == uid {
+                    displayed.push(Displayed { broadcast_id: record.id, notification_id: *notification_id, critical });
+                }
+            }
+        }
+        displayed
+    }
+
+    /// Stop tracking a notification of a broadcast shown to a user, e.g. once it was closed
+    pub fn forget_delivery(&self, id: BroadcastId, uid: u32, notification_id: u32) {
+        if let Some(record) = self.inner.lock().unwrap().records.get_mut(&id) {
+            record.deliveries.retain(|(user, delivered)| user.uid != uid || *delivered != notification_id);
+        }
+    }
+
+    /// Get the correlation id of a tracked broadcast
+    pub fn correlation_id(&self, id: BroadcastId) -> Option<Uuid> {
+        self.inner.lock().unwrap().records.get(&id).map(|record| record.correlation_id)
+    }
+
+    /// Get the name of the caller a tracked broadcast is attributed to
+    pub fn sender(&self, id: BroadcastId) -> Option<Arc<str>> {
+        let inner = self.inner.lock().unwrap();
+        inner.records.get(&id)?.payload.as_ref()?.sender.clone()
+    }
+
+    /// Get the channel a tracked broadcast was posted to
+    pub fn channel(&self, id: BroadcastId) -> Option<String> {
+        self.inner.lock().unwrap().records.get(&id)?.channel.clone()
+    }
+
+    /// Replace the content kept for a broadcast
+    pub fn set_payload(&self, id: BroadcastId, payload: Arc<BroadcastPayload>) {
+        if let Some(record) = self.inner.lock().unwrap().records.get_mut(&id) {
+            record.payload = Some(payload);
+        }
+    }
+
+    /// Get a copy of a tracked broadcast
+    pub fn get(&self, id: BroadcastId) -> Option<BroadcastRecord> {
+        self.inner.lock().unwrap().records.get(&id).cloned()
+    }
+
+    /// Take the recorded deliveries of a broadcast, leaving the broadcast tracked without any
+    pub fn take_deliveries(&self, id: BroadcastId) -> Option<Vec<(TargetUser, u32)>> {
+        self.inner
+            .lock()
+            .unwrap()
+            .records
+            .get_mut(&id)
+            .map(|record| std::mem::take(&mut record.deliveries))
+    }
+
+    /// Have a broadcast replace others, which stop being tracked
+    ///
+    /// The notifications of the replaced broadcasts are updated in place as the broadcast
+    /// is delivered, and their tags and channel carry over to it. Returns the number of
+    /// notifications to update.
+    pub fn supersede(&self, id: BroadcastId, replaced: &[BroadcastId]) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let replaced: Vec<BroadcastRecord> = replaced
+            .iter()
+            .filter(|&&old| old != id)
+            .filter_map(|old| inner.records.remove(old))
+            .collect();
+        let Some(record) = inner.records.get_mut(&id) else {
+            return 0;
+        };
+        for old in replaced {
+            record.replaces.extend(old.replaces);
+            record.replaces.extend(old.deliveries.iter().map(|(user, notification_id)| (user.uid, *notification_id)));
+            for tag in old.tags {
+                if !record.has_tag(&tag) {
+                    record.tags.push(tag);
+                }
+            }
+            record.channel = record.channel.take().or(old.channel);
+        }
+        record.replaces.len()
+    }
+
+    /// Take the id of the notification a broadcast updates in place for a user, or 0 if none
+    pub fn take_replaced(&self, id: BroadcastId, uid: u32) -> u32 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.records.get_mut(&id).and_then(|record| record.replaces.remove(&uid)).unwrap_or(0)
+    }
+
+    /// Get the ids of every broadcast posted to a channel, oldest first
+    pub fn channel_broadcasts(&self, channel: &str) -> Vec<BroadcastId> {
+        self.inner
+            .lock()
+            .unwrap()
+            .records
+            .values()
+            .filter(|record| record.channel.as_deref() == Some(channel))
+            .map(|record| record.id)
+            .collect()
+    }
+
+    /// Get the ids of every broadcast carrying a tag, oldest first
+    pub fn tagged_broadcasts(&self, tag: &str) -> Vec<BroadcastId> {
+        self.inner
+            .lock()
+            .unwrap()
+            .records
+            .values()
+            .filter(|record| record.has_tag(tag))
+            .map(|record| record.id)
+            .collect()
+    }
+
+    /// Get copies of every tracked broadcast, optionally only those carrying a tag, oldest first
+    pub fn list(&self, tag: Option<&str>) -> Vec<BroadcastRecord> {
+        self.inner
+            .lock()
+            .unwrap()
+            .records
+            .values()
+            .filter(|record| tag.is_none_or(|tag| record.has_tag(tag)))
+            .cloned()
+            .collect()
+    }
+
+    /// Track broadcasts restored from elsewhere, replacing any with the same id
+    ///
+    /// Ids assigned afterwards continue after the highest imported id.
+    pub fn import(&self, records: Vec<BroadcastRecord>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let imported = records.len();
+        for record in records {
+            inner.next_id = inner.next_id.max(record.id);
+            inner.records.insert(record.id, record);
+        }
+        while inner.records.len() > MAX_TRACKED_BROADCASTS {
+            inner.records.pop_first();
+        }
+        imported
+    }
+
+    /// Stop tracking a broadcast and return it
+    pub fn remove(&self, id: BroadcastId) -> Option<BroadcastRecord> {
+        self.inner.lock().unwrap().records.remove(&id)
+    }
+
+    /// Stop tracking every broadcast posted to a channel and return them
+    pub fn remove_channel(&self, channel: &str) -> Vec<BroadcastRecord> {
+        let ids = self.channel_broadcasts(channel);
+        let mut inner = self.inner.lock().unwrap();
+        ids.iter().filter_map(|id| inner.records.remove(id)).collect()
+    }
+
+    /// Stop tracking every broadcast carrying a tag and return them
+    pub fn remove_tagged(&self, tag: &str) -> Vec<BroadcastRecord> {
+        let mut inner = self.inner.lock().unwrap();
+        let ids: Vec<BroadcastId> = inner.records.values().filter(|r| r.has_tag(tag)).map(|r| r.id).collect();
+        ids.iter().filter_map(|id| inner.records.remove(id)).collect()
+    }
+
+    /// Get the id of the newest tracked broadcast matching a predicate
+    pub fn latest_matching(&self, matches: impl Fn(&BroadcastRecord) -> bool) -> Option<BroadcastId> {
+        self.inner.lock().unwrap().records.values().rev().find(|record| matches(record)).map(|record| record.id)
+    }
+
+    /// Stop tracking every broadcast matching a predicate and return them, oldest first
+    pub fn remove_matching(&self, matches: impl Fn(&BroadcastRecord) -> bool) -> Vec<BroadcastRecord> {
+        let mut inner = self.inner.lock().unwrap();
+        let ids: Vec<BroadcastId> = inner.records.values().filter(|r| matches(r)).map(|r| r.id).collect();
+        ids.iter().filter_map(|id| inner.records.remove(id)).collect()
+    }
+
+    /// Number of tracked broadcasts
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().records.len()
+    }
+
+    /// Whether no broadcasts are tracked
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate number of bytes of memory held by tracked broadcasts
+    pub fn memory_usage(&self) -> usize {
+        self.inner.lock().unwrap().records.values().map(BroadcastRecord::size).sum()
+    }
+}
+
+/// Get the current time in seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::DeliveryErrorKind;
+
+    fn user(uid: u32) -> TargetUser {
+        TargetUser::new(uid, format!("user{}", uid))
+    }
+
+    #[test]
+    fn test_register_assigns_increasing_ids() {
+        let registry = BroadcastRegistry::new();
+        let first = registry.register(None);
+        let second = registry.register(Some("backups".to_string()));
+        assert!(second > first);
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get(second).unwrap().channel.as_deref(), Some("backups"));
+    }
+
+    #[test]
+    fn test_displayed_notifications() {
+        let registry = BroadcastRegistry::new();
+        let first = registry.register(None);
+        let second = registry.register(None);
+        let critical = BroadcastPayload::new("Power", "Shutting down").with_urgency(Some(Urgency::Critical));
+        registry.set_payload(second, Arc::new(critical));
+        registry.record_delivery(second, user(1000), 7);
+        registry.record_delivery(first, user(1000), 3);
+        registry.record_delivery(first, user(1001), 3);
+
+        let displayed = registry.displayed(1000);
+        assert_eq!(displayed, vec![
+            Displayed { broadcast_id: first, notification_id: 3, critical: false },
+            Displayed { broadcast_id: second, notification_id: 7, critical: true },
+        ]);
+        registry.forget_delivery(first, 1000, 3);
+        assert_eq!(registry.displayed(1000).len(), 1);
+        assert_eq!(registry.displayed(1001).len(), 1);
+    }
+
+    #[test]
+    fn test_correlation_ids_unique() {
+        let registry = BroadcastRegistry::new();
+        let first = registry.register(None);
+        let second = registry.register(None);
+        let correlation_id = registry.correlation_id(first).unwrap();
+        assert_eq!(registry.get(first).unwrap().correlation_id, correlation_id);
+        assert_ne!(registry.correlation_id(second), Some(correlation_id));
+        assert_eq!(registry.correlation_id(second + 1), None);
+    }
+
+    #[test]
+    fn test_record_delivery() {
+        let registry = BroadcastRegistry::new();
+        let id = registry.register(None);
+        registry.record_delivery(id, user(1000), 7);
+        registry.record_delivery(id, user(1001), 3);
+        registry.record_delivery(id + 1, user(1002), 1);
+
+        let record = registry.get(id).unwrap();
+        assert_eq!(record.deliveries, vec![(user(1000), 7), (user(1001), 3)]);
+    }
+
+    #[test]
+    fn test_record_status() {
+        let registry = BroadcastRegistry::new();
+        let id = registry.register(None);
+        registry.record_status(id, user(1000), DeliveryStatus::Spooled);
+        registry.record_status(id, user(1001), DeliveryStatus::Delivered);
+        registry.record_status(id, user(1000), DeliveryStatus::Delivered);
+
+        let record = registry.get(id).unwrap();
+        assert_eq!(
+            record.report,
+            vec![(user(1000), DeliveryStatus::Delivered), (user(1001), DeliveryStatus::Delivered)]
+        );
+    }
+
+    #[test]
+    fn test_record_error() {
+        let registry = BroadcastRegistry::new();
+        let id = registry.register(None);
+        registry.record_error(id, 1000, "Connection reset by peer");
+        registry.record_status(id, user(1000), DeliveryStatus::Failed(DeliveryErrorKind::NoSessionBus));
+        assert_eq!(registry.get(id).unwrap().error(1000), Some("Connection reset by peer"));
+
+        registry.record_status(id, user(1000), DeliveryStatus::Delivered);
+        assert_eq!(registry.get(id).unwrap().error(1000), None);
+    }
+
+    #[test]
+    fn test_take_deliveries() {
+        let registry = BroadcastRegistry::new();
+        let id = registry.register(None);
+        registry.record_delivery(id, user(1000), 7);
+
+        assert_eq!(registry.take_deliveries(id), Some(vec![(user(1000), 7)]));
+        assert_eq!(registry.take_deliveries(id), Some(vec![]));
+        assert!(registry.get(id).is_some());
+        assert_eq!(registry.take_deliveries(id + 1), None);
+    }
+
+    #[test]
+    fn test_supersede() {
+        let registry = BroadcastRegistry::new();
+        let first = registry.register_with(Some("backups".to_string()), vec!["nightly".to_string()], None);
+        registry.record_delivery(first, user(1000), 7);
+        registry.record_delivery(first, user(1001), 3);
+        let second = registry.register(None);
+
+        assert_eq!(registry.supersede(second, &[first]), 2);
+        assert!(registry.get(first).is_none());
+        let record = registry.get(second).unwrap();
+        assert_eq!(record.channel.as_deref(), Some("backups"));
+        assert_eq!(record.tags, vec!["nightly".to_string()]);
+
+        assert_eq!(registry.take_replaced(second, 1000), 7);
+        assert_eq!(registry.take_replaced(second, 1000), 0);
+        assert_eq!(registry.take_replaced(second, 1002), 0);
+
+        // Notifications not updated yet carry over to the next replacement
+        let third = registry.register(None);
+        assert_eq!(registry.supersede(third, &[second]), 1);
+        assert_eq!(registry.take_replaced(third, 1001), 3);
+        assert_eq!(registry.supersede(third, &[third]), 0);
+    }
+
+    #[test]
+    fn test_channel_broadcasts() {
+        let registry = BroadcastRegistry::new();
+        let a = registry.register(Some("status".to_string()));
+        registry.register(None);
+        let c = registry.register(Some("status".to_string()));
+        assert_eq!(registry.channel_broadcasts("status"), vec![a, c]);
+        assert!(registry.channel_broadcasts("other").is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let registry = BroadcastRegistry::new();
+        let id = registry.register(None);
+        assert!(registry.remove(id).is_some());
+        assert!(registry.remove(id).is_none());
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_remove_channel() {
+        let registry = BroadcastRegistry::new();
+        let a = registry.register(Some("backups".to_string()));
+        let b = registry.register(Some("updates".to_string()));
+        let c = registry.register(Some("backups".to_string()));
+
+        let removed: Vec<BroadcastId> = registry.remove_channel("backups").iter().map(|r| r.id).collect();
+        assert_eq!(removed, vec![a, c]);
+        assert!(registry.get(b).is_some());
+        assert!(registry.remove_channel("backups").is_empty());
+    }
+
+    #[test]
+    fn test_latest_matching() {
+        let registry = BroadcastRegistry::new();
+        let a = registry.register(Some("backups".to_string()));
+        let b = registry.register(Some("backups".to_string()));
+        registry.register(None);
+        assert_eq!(registry.latest_matching(|r| r.channel.as_deref() == Some("backups")), Some(b));
+        assert_eq!(registry.latest_matching(|r| r.id < b), Some(a));
+        assert_eq!(registry.latest_matching(|r| r.has_tag("drill")), None);
+        assert_eq!(registry.channel(a).as_deref(), Some("backups"));
+    }
+
+    #[test]
+    fn test_remove_older_than() {
+        let registry = BroadcastRegistry::new();
+        let old = registry.register(Some("backups".to_string()));
+        let recent = registry.register(Some("backups".to_string()));
+        let mut record = registry.get(old).unwrap();
+        record.sent_at -= 7200;
+        registry.import(vec![record]);
+
+        let now = unix_now();
+        let hour = Duration::from_secs(3600);
+        assert!(registry.get(old).unwrap().older_than(hour, now));
+        assert!(!registry.get(recent).unwrap().older_than(hour, now));
+        let removed: Vec<BroadcastId> =
+            registry.remove_matching(|r| r.older_than(hour, now)).iter().map(|r| r.id).collect();
+        assert_eq!(removed, vec![old]);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_tagged_broadcasts() {
+        let registry = BroadcastRegistry::new();
+        let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect();
+        let a = registry.register_with(None, tags(&["incident-421"]), None);
+        let b = registry.register_with(Some("status".to_string()), tags(&["incident-421", "db"]), None);
+        let c = registry.register_with(None, tags(&["db"]), None);
+
+        assert_eq!(registry.tagged_broadcasts("incident-421"), vec![a, b]);
+        let listed: Vec<BroadcastId> = registry.list(Some("db")).iter().map(|r| r.id).collect();
+        assert_eq!(listed, vec![b, c]);
+        assert_eq!(registry.list(None).len(), 3);
+
+        let removed: Vec<BroadcastId> = registry.remove_tagged("incident-421").iter().map(|r| r.id).collect();
+        assert_eq!(removed, vec![a, b]);
+        assert_eq!(registry.len(), 1);
+        assert!(registry.remove_tagged("incident-421").is_empty());
+    }
+
+    #[test]
+    fn test_import_continues_ids() {
+        let source = BroadcastRegistry::new();
+        source.register(Some("backups".to_string()));
+        let last = source.register(None);
+        source.record_status(last, user(1000), DeliveryStatus::Spooled);
+
+        let registry = BroadcastRegistry::new();
+        assert_eq!(registry.import(source.list(None)), 2);
+        assert_eq!(registry.list(None), source.list(None));
+        assert!(registry.register(None) > last);
+    }
+
+    #[test]
+    fn test_set_payload() {
+        let registry = BroadcastRegistry::new();
+        let id = registry.register(None);
+        assert!(registry.get(id).unwrap().payload.is_none());
+        let payload = Arc::new(BroadcastPayload::new("title", "body"));
+        registry.set_payload(id, payload.clone());
+        assert_eq!(registry.get(id).unwrap().payload, Some(payload));
+    }
+
+    #[test]
+    fn test_oldest_broadcasts_evicted() {
+        let registry = BroadcastRegistry::new();
+        let first = registry.register(None);
+        for _ in 0..MAX_TRACKED_BROADCASTS {
+            registry.register(None);
+        }
+        assert_eq!(registry.len(), MAX_TRACKED_BROADCASTS);
+        assert!(registry.get(first).is_none());
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let registry = BroadcastRegistry::new();
+        assert_eq!(registry.memory_usage(), 0);
+
+        let id = registry.register(Some("backups".to_string()));
+        let empty = registry.memory_usage();
+        assert_eq!(empty, registry.get(id).unwrap().size());
+
+        registry.record_delivery(id, user(1000), 7);
+        assert!(registry.memory_usage() > empty);
+    }
+}
@@ -0,0 +1,66 @@
+//! Message bus the server is reachable on
+//!
+//! The server normally owns its name on the system bus and notifies every
+//! active graphical user. On the session bus it needs neither root nor a
+//! system bus policy and only notifies the user owning that bus, which suits
+//! single-user setups and developer laptops.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use zbus::connection::Builder;
+use zbus::Connection;
+
+/// A message bus the server can own its name on
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BusType {
+    /// The system bus, notifying all active graphical users
+    #[default]
+    System,
+    /// The caller's session bus, notifying only the user owning it
+    Session,
+}
+
+impl BusType {
+    /// Connect to the bus
+    pub async fn connect(self) -> zbus::Result<Connection> {
+        match self {
+            BusType::System => Connection::system().await,
+            BusType::Session => Connection::session().await,
+        }
+    }
+
+    /// Start building a connection to the bus
+    pub fn builder(self) -> zbus::Result<Builder<'static>> {
+        match self {
+            BusType::System => Builder::system(),
+            BusType::Session => Builder::session(),
+        }
+    }
+
+    /// Get the name of the bus, as accepted by `--bus`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BusType::System => "system",
+            BusType::Session => "session",
+        }
+    }
+}
+
+impl fmt::Display for BusType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bus_names() {
+        assert_eq!(BusType::default(), BusType::System);
+        assert_eq!(BusType::Session.to_string(), "session");
+        assert_eq!(BusType::from_str("system", false), Ok(BusType::System));
+    }
+}
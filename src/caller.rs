@@ -0,0 +1,277 @@
+//! Identity of the clients calling the server
+//!
+//! The bus daemon vouches for the uid and pid behind each connection. The pid
+//! is mapped to the systemd unit it runs in, so a broadcast can be attributed
+//! to e.g. the patching service rather than just to root. Friendly names for
+//! units and users are taken from the `sender_names` configuration table, and
+//! `allowed_senders`, `allowed_uids` and `allowed_groups` can restrict who may
+//! send broadcasts at all.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use tracing::debug;
+use zbus::message::Header;
+use zbus::names::BusName;
+use zbus::Connection;
+
+use crate::socket::SOCKET_INPUT;
+
+/// Who may send broadcasts, by the `allowed_senders`, `allowed_uids` and `allowed_groups` settings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SenderAcl<'a> {
+    /// Systemd units, usernames and `uid:<uid>` entries
+    pub senders: &'a [String],
+    pub uids: &'a [u32],
+    /// Groups whose members, by primary or supplementary group, may send
+    pub groups: &'a [String],
+}
+
+impl SenderAcl<'_> {
+    /// Whether the ACL lists no one, allowing everyone
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty() && self.uids.is_empty() && self.groups.is_empty()
+    }
+}
+
+/// The client behind a D-Bus call
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Caller {
+    /// Unique bus name of the calling connection, or `unix-socket` for socket clients
+    pub bus_name: String,
+    /// Uid the calling process runs as
+    pub uid: Option<u32>,
+    /// Id of the calling process
+    pub pid: Option<u32>,
+    /// Systemd service the calling process belongs to
+    pub unit: Option<String>,
+}
+
+impl Caller {
+    /// Ask the bus daemon who sent a message
+    ///
+    /// Details the bus daemon cannot provide are left empty.
+    pub async fn identify(connection: &Connection, header: &Header<'_>) -> Option<Self> {
+        let sender = header.sender()?;
+        let mut caller = Self {
+            bus_name: sender.to_string(),
+            ..Self::default()
+        };
+        let dbus = zbus::fdo::DBusProxy::new(connection).await.ok()?;
+        let name = BusName::from(sender.to_owned());
+        caller.uid = dbus.get_connection_unix_user(name.clone()).await.ok();
+        caller.pid = dbus.get_connection_unix_process_id(name).await.ok();
+        caller.unit = caller.pid.and_then(unit_of);
+        debug!(caller = %caller, "Identified caller.");
+        Some(caller)
+    }
+
+    /// Describe a client of the Unix socket from its peer credentials
+    pub fn from_socket_peer(uid: u32, pid: Option<u32>) -> Self {
+        Self {
+            bus_name: SOCKET_INPUT.to_string(),
+            uid: Some(uid),
+            pid,
+            unit: pid.and_then(unit_of),
+        }
+    }
+
+    /// Get the name a broadcast from this caller is attributed to
+    ///
+    /// Names configured for the caller's unit take precedence over those for
+    /// its user (by name, or as `uid:<uid>`). Without a configured name the
+    /// unit, then the username, is used.
+    pub fn display_name(&self, names: &HashMap<String, String>) -> String {
+        self.display_name_with(names, self.username().as_deref())
+    }
+
+    /// Whether this caller may send broadcasts under an ACL
+    ///
+    /// An empty ACL allows everyone. Otherwise the caller's unit, username or
+    /// `uid:<uid>` must be in `senders`, its uid in `uids`, or it must be a member of
+    /// one of `groups` according to `is_member(username, group)`; root and the user
+    /// running the server are always allowed.
+    pub fn is_allowed(&self, acl: &SenderAcl<'_>, is_member: impl Fn(&str, &str) -> bool) -> bool {
+        self.is_allowed_with(acl, self.username().as_deref(), is_member)
+    }
+
+    /// Whether a name is configured in `sender_names` for this caller, making it a known sender
+    pub fn is_named(&self, names: &HashMap<String, String>) -> bool {
+        self.configured_name(names, self.username().as_deref()).is_some()
+    }
+
+    fn username(&self) -> Option<String> {
+        self.uid
+            .and_then(|uid| nix::unistd::User::from_uid(uid.into()).ok().flatten())
+            .map(|user| user.name)
+    }
+
+    fn is_allowed_with(
+        &self,
+        acl: &SenderAcl<'_>,
+        username: Option<&str>,
+        is_member: impl Fn(&str, &str) -> bool,
+    ) -> bool {
+        if acl.is_empty() || self.uid.is_some_and(|uid| uid == 0 || uid == nix::unistd::geteuid().as_raw()) {
+            return true;
+        }
+        let uid_key = self.uid.map(|uid| format!("uid:{}", uid));
+        let listed = acl.senders.iter().any(|entry| {
+            [self.unit.as_deref(), username, uid_key.as_deref()].contains(&Some(entry.as_str()))
+        });
+        listed
+            || self.uid.is_some_and(|uid| acl.uids.contains(&uid))
+            || username.is_some_and(|username| acl.groups.iter().any(|group| is_member(username, group)))
+    }
+
+    fn configured_name(&self, names: &HashMap<String, String>, username: Option<&str>) -> Option<String> {
+        let uid_key = self.uid.map(|uid| format!("uid:{}", uid));
+        let configured = [self.unit.as_deref(), username, uid_key.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|key| names.get(key).cloned());
+        configured
+    }
+
+    fn display_name_with(&self, names: &HashMap<String, String>, username: Option<&str>) -> String {
+        self.configured_name(names, username)
+            .or_else(|| self.unit.clone())
+            .or_else(|| username.map(str::to_string))
+            .or_else(|| self.uid.map(|uid| format!("uid:{}", uid)))
+            .unwrap_or_else(|| self.bus_name.clone())
+    }
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.bus_name)?;
+        if let Some(uid) = self.uid {
+            write!(f, " uid={}", uid)?;
+        }
+        if let Some(pid) = self.pid {
+            write!(f, " pid={}", pid)?;
+        }
+        if let Some(unit) = &self.unit {
+            write!(f, " unit={}", unit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Get the systemd service a running process belongs to
+fn unit_of(pid: u32) -> Option<String> {
+    let cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    unit_from_cgroup(&cgroup)
+}
+
+/// Get the systemd service a process runs in from its `/proc/<pid>/cgroup`
+///
+/// Processes in login sessions (`session-*.scope`) belong to no service. Neither do those
+/// run by a user's own service manager (below `user@<uid>.service`), since any user can
+/// name their units after system ones.
+pub fn unit_from_cgroup(cgroup: &str) -> Option<String> {
+    // The unified hierarchy's entry is `0::<path>`
+    let path = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .or_else(|| cgroup.lines().find_map(|line| line.split_once(":name=systemd:").map(|(_, path)| path)))?;
+    if path.split('/').any(|component| component.starts_with("user@")) {
+        return None;
+    }
+    path.rsplit('/')
+        .find(|component| component.ends_with(".service"))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_from_cgroup() {
+        assert_eq!(
+            unit_from_cgroup("0::/system.slice/patching.service\n"),
+            Some("patching.service".to_string())
+        );
+        // Units of a user's service manager are named by the user, so they are not trusted
+        assert_eq!(
+            unit_from_cgroup("0::/user.slice/user-1000.slice/user@1000.service/app.slice/backup.service"),
+            None
+        );
+        assert_eq!(unit_from_cgroup("0::/user.slice/user-1000.slice/user@1000.service/init.scope"), None);
+        assert_eq!(unit_from_cgroup("0::/user.slice/user-1000.slice/session-3.scope"), None);
+        assert_eq!(
+            unit_from_cgroup("12:cpu:/\n1:name=systemd:/system.slice/cron.service\n"),
+            Some("cron.service".to_string())
+        );
+        assert_eq!(unit_from_cgroup(""), None);
+    }
+
+    #[test]
+    fn test_display_names() {
+        let names = HashMap::from([
+            ("patching.service".to_string(), "Patching system".to_string()),
+            ("alice".to_string(), "Alice (ops)".to_string()),
+            ("uid:1001".to_string(), "Bob".to_string()),
+        ]);
+        let patching = Caller {
+            bus_name: ":1.7".to_string(),
+            uid: Some(0),
+            unit: Some("patching.service".to_string()),
+            ..Caller::default()
+        };
+        assert_eq!(patching.display_name_with(&names, Some("root")), "Patching system");
+
+        let alice = Caller {
+            bus_name: ":1.8".to_string(),
+            uid: Some(1000),
+            ..Caller::default()
+        };
+        assert_eq!(alice.display_name_with(&names, Some("alice")), "Alice (ops)");
+
+        let bob = Caller { uid: Some(1001), ..alice.clone() };
+        assert_eq!(bob.display_name_with(&names, Some("bob")), "Bob");
+
+        let cron = Caller {
+            unit: Some("cron.service".to_string()),
+            ..patching.clone()
+        };
+        assert_eq!(cron.display_name_with(&names, Some("root")), "cron.service");
+        assert_eq!(Caller { uid: Some(1002), ..alice }.display_name_with(&names, None), "uid:1002");
+        assert_eq!(Caller::default().display_name_with(&names, None), "");
+        assert!(patching.configured_name(&names, Some("root")).is_some());
+        assert!(cron.configured_name(&names, Some("root")).is_none());
+    }
+
+    #[test]
+    fn test_allowed_senders() {
+        let senders = vec!["backup.service".to_string(), "alice".to_string(), "uid:1001".to_string()];
+        let acl = SenderAcl { senders: &senders, ..SenderAcl::default() };
+        let no_groups = |_: &str, _: &str| false;
+        let backup = Caller {
+            uid: Some(990),
+            unit: Some("backup.service".to_string()),
+            ..Caller::default()
+        };
+        assert!(backup.is_allowed_with(&acl, Some("backup"), no_groups));
+        let alice = Caller { uid: Some(1000), ..Caller::default() };
+        assert!(alice.is_allowed_with(&acl, Some("alice"), no_groups));
+        assert!(Caller { uid: Some(1001), ..Caller::default() }.is_allowed_with(&acl, None, no_groups));
+        assert!(!Caller { uid: Some(1002), ..Caller::default() }.is_allowed_with(&acl, Some("mallory"), no_groups));
+        assert!(!Caller::default().is_allowed_with(&acl, None, no_groups));
+        assert!(Caller { uid: Some(0), ..Caller::default() }.is_allowed_with(&acl, Some("root"), no_groups));
+        assert!(Caller::default().is_allowed_with(&SenderAcl::default(), None, no_groups));
+    }
+
+    #[test]
+    fn test_allowed_uids_and_groups() {
+        let (uids, groups) = (vec![1001], vec!["ops".to_string()]);
+        let acl = SenderAcl { uids: &uids, groups: &groups, ..SenderAcl::default() };
+        let is_member = |username: &str, group: &str| username == "alice" && group == "ops";
+        let alice = Caller { uid: Some(1000), ..Caller::default() };
+        assert!(alice.is_allowed_with(&acl, Some("alice"), is_member));
+        assert!(Caller { uid: Some(1001), ..Caller::default() }.is_allowed_with(&acl, Some("bob"), is_member));
+        assert!(!Caller { uid: Some(1002), ..Caller::default() }.is_allowed_with(&acl, Some("mallory"), is_member));
+        assert!(!Caller::default().is_allowed_with(&acl, None, is_member));
+    }
+}
@@ -1,17 +1,35 @@
 //! Command-line argument parsing module
 
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::bus::BusType;
+use crate::recurrence::Recurrence;
+use crate::config::DEFAULT_CONFIG_PATH;
+use crate::fleet::DEFAULT_PARALLELISM;
+use crate::poll::DEFAULT_POLL_OPTIONS;
+use crate::types::Urgency;
 
 /// Command-line interface definition
 #[derive(Parser, Debug, Clone, PartialEq)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// The bus the server owns its name on. On the session bus only the user owning it is notified.
+    #[arg(long, value_enum, global = true, default_value_t = BusType::System)]
+    pub bus: BusType,
+    /// The configuration file the server reads.
+    #[arg(long, global = true, default_value = DEFAULT_CONFIG_PATH)]
+    pub config: PathBuf,
     #[command(subcommand)]
     pub command: Commands,
 }
 
 /// Available commands for the application
 #[derive(Subcommand, Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     /// Run in server mode, listening for D-Bus requests. (For systemd/D-Bus activation)
     Server,
@@ -20,10 +38,534 @@ pub enum Commands {
         /// The title of the notification.
         title: String,
         /// The body message of the notification.
+        #[arg(default_value = "", required_unless_present_any = ["extra_bodies", "body_files", "body_commands"])]
+        body: String,
+        /// Add a line of text to the body. May be given multiple times.
+        #[arg(long = "body")]
+        extra_bodies: Vec<String>,
+        /// Add the contents of a file to the body. May be given multiple times.
+        #[arg(long = "body-file")]
+        body_files: Vec<PathBuf>,
+        /// Add the output of a command run by the server, which must be allowlisted in its configuration.
+        /// May be given multiple times. Fragments are joined in the order body, --body, --body-file, --body-command.
+        #[arg(long = "body-command")]
+        body_commands: Vec<String>,
+        /// Post the notification to a named channel, so it can be closed by channel later.
+        #[arg(long)]
+        channel: Option<String>,
+        /// Categorize the notification, such as monitoring.disk, posting it to the channel the server maps
+        /// the category to unless --channel is given.
+        #[arg(long)]
+        category: Option<String>,
+        /// Identify the alert the notification is about, naming its channel through the server's category
+        /// mapping. Defaults to the title.
+        #[arg(long, requires = "category")]
+        fingerprint: Option<String>,
+        /// Close the notifications of the channel the notification would be posted to instead of sending
+        /// it, e.g. once the alert it was about is resolved.
+        #[arg(
+            long,
+            conflicts_with_all = ["at", "delay", "every", "cron", "follow_report", "dry_run", "hosts", "hosts_file"]
+        )]
+        resolved: bool,
+        /// Tag the notification, e.g. with an incident id. May be given multiple times.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Run this hook from the server's hooks directory for each user once the notification is displayed.
+        #[arg(long)]
+        hook: Option<String>,
+        /// Send with this urgency (low, normal or critical) instead of the one inferred from the content.
+        #[arg(long)]
+        urgency: Option<Urgency>,
+        /// Offer an action as KEY:LABEL. Invoked keys are announced with the NotificationActionInvoked signal.
+        /// May be given multiple times.
+        #[arg(long = "action", value_name = "KEY:LABEL")]
+        actions: Vec<String>,
+        /// Group the notification with related ones sharing this key, so desktops stack them and
+        /// a pending notification of the group is replaced by a newer one.
+        #[arg(long)]
+        group_key: Option<String>,
+        /// Show the notification again even if the same title and body were sent within the
+        /// server's deduplication window, instead of updating the earlier notification.
+        #[arg(long)]
+        allow_duplicate: bool,
+        /// Render bold, italic and links written in Markdown in the body, as markup for notification
+        /// daemons supporting it and as plain text for others.
+        #[arg(long)]
+        markdown: bool,
+        /// Show this image with the notification, such as a screenshot or logo. Binary PPM and PAM
+        /// files are sent as pixels, downscaled to fit; other formats are read by the notification daemon.
+        #[arg(long)]
+        image: Option<PathBuf>,
+        /// Only notify members of this Unix group, such as wheel, by primary or supplementary membership.
+        #[arg(long)]
+        group: Option<String>,
+        /// Skip this user, by username or uid. May be given multiple times.
+        #[arg(long = "exclude-user")]
+        exclude_users: Vec<String>,
+        /// Only notify users whose session is on this seat, such as seat0, leaving out remote sessions.
+        #[arg(long)]
+        seat: Option<String>,
+        /// Also deliver the notification to users it is meant for who log in later, within the server's
+        /// absent_queue_ttl_secs, so nobody misses a critical alert.
+        #[arg(long)]
+        queue_for_absent: bool,
+        /// Update the notifications of an earlier send in place instead of showing new ones, for progress
+        /// such as "backup 40%". Takes the broadcast id printed by `send`, or with --host the batch id.
+        #[arg(long, value_name = "ID", conflicts_with_all = ["every", "cron"])]
+        replace: Option<String>,
+        /// Have the server send the notification at this local time, such as 2024-06-01T09:00, instead of now.
+        #[arg(long, value_parser = parse_time, conflicts_with_all = ["delay", "hosts", "hosts_file"])]
+        at: Option<NaiveDateTime>,
+        /// Have the server send the notification after this long, such as 30m, 1h or 2d, instead of now.
+        #[arg(long = "in", value_parser = parse_age, conflicts_with_all = ["hosts", "hosts_file"])]
+        delay: Option<Duration>,
+        /// Have the server send the notification again and again, this long apart, such as 1h or 7d, starting then.
+        #[arg(long, value_parser = parse_every, conflicts_with_all = ["at", "delay", "cron", "hosts", "hosts_file"])]
+        every: Option<Recurrence>,
+        /// Have the server send the notification whenever its local time matches a cron expression,
+        /// such as "0 9 * * 1".
+        #[arg(long, value_parser = parse_cron, conflicts_with_all = ["at", "delay", "hosts", "hosts_file"])]
+        cron: Option<Recurrence>,
+        /// Print delivery events line by line as they happen, as human-readable text or, with
+        /// --follow-report=json, JSON, instead of a summary at the end. With actions, answers are
+        /// followed until interrupted.
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "human",
+            conflicts_with_all = ["at", "delay", "every", "cron", "hosts", "hosts_file"]
+        )]
+        follow_report: Option<ReportFormat>,
+        /// Print the notification as the server would show it, with the urgency and timeout read from the
+        /// configuration, and exit without sending it. Output of --body-command is not included.
+        #[arg(long, conflicts_with = "follow_report")]
+        dry_run: bool,
+        /// Send without asking for confirmation, however many users or remote hosts the notification would reach.
+        #[arg(short, long)]
+        yes: bool,
+        #[command(flatten)]
+        remote: RemoteArgs,
+    },
+    /// Send a notification to a single user with an active graphical session.
+    SendToUser {
+        /// The recipient, by username or uid.
+        user: String,
+        /// The title of the notification.
+        title: String,
+        /// The body message of the notification.
+        body: String,
+    },
+    /// Send many notifications to all users in one request.
+    SendBatch {
+        /// JSON file holding an array of notifications, each an object with a `title` and an optional `body`.
+        #[arg(long)]
+        file: PathBuf,
+    },
+    /// Withdraw a previously sent notification from all desktops.
+    Close {
+        /// The broadcast id printed by `send`.
+        #[arg(
+            required_unless_present_any = ["channel", "tag", "batch"],
+            conflicts_with_all = ["channel", "tag", "batch"]
+        )]
+        broadcast_id: Option<u64>,
+        /// Close every notification posted to this channel instead.
+        #[arg(long, conflicts_with_all = ["tag", "batch"])]
+        channel: Option<String>,
+        /// Close every notification carrying this tag instead.
+        #[arg(long, conflicts_with = "batch")]
+        tag: Option<String>,
+        /// Close every notification of the batch id printed by a send on remote hosts instead.
+        #[arg(long)]
+        batch: Option<String>,
+        #[command(flatten)]
+        remote: RemoteArgs,
+    },
+    /// Withdraw every tracked notification from all desktops, e.g. stale alerts after an incident.
+    CloseAll {
+        /// Only close notifications sent at least this long ago, such as 30m, 1h or 2d.
+        #[arg(long, value_parser = parse_age)]
+        older_than: Option<Duration>,
+        /// Only close notifications posted to this channel.
+        #[arg(long)]
+        channel: Option<String>,
+    },
+    /// Replace the title and body of a previously sent notification on all desktops.
+    Update {
+        /// Update every notification posted to this channel instead of a single broadcast.
+        #[arg(long)]
+        channel: Option<String>,
+        /// The broadcast id printed by `send` (omitted with --channel), the new title and the new body.
+        #[arg(required = true, num_args = 2..=3, value_names = ["BROADCAST_ID", "TITLE", "BODY"])]
+        args: Vec<String>,
+    },
+    /// Show the state of the running server, such as its uptime and the last delivery error.
+    Status {
+        /// Only check that the server answers, printing its version and uptime, e.g. for monitoring scripts.
+        #[arg(long)]
+        ping: bool,
+    },
+    /// Check the surroundings of the running server for what commonly breaks delivery, such as an SELinux
+    /// or AppArmor policy confining it.
+    Doctor,
+    /// Show the session bus, runtime directory and notification daemon the server sees for a user's sessions.
+    Inspect {
+        /// The user to inspect, by name or uid.
+        user: String,
+    },
+    /// Show the optional features the notification daemon of a logged-in user supports, such as
+    /// actions or body markup, which notifications sent to them are adapted to.
+    Capabilities {
+        /// The uid of the user.
+        uid: u32,
+    },
+    /// Show delivery latency percentiles and the users whose notification daemon is slow.
+    Stats {
+        /// Print the metrics in the Prometheus text format instead, e.g. for the node exporter's textfile collector.
+        #[arg(long)]
+        prometheus: bool,
+        /// Show how often the delivery helper ran and failed for each user instead.
+        #[arg(long, conflicts_with = "prometheus")]
+        helper: bool,
+    },
+    /// List the notifications the server still tracks, or every dispatch recorded in its journal.
+    #[command(args_conflicts_with_subcommands = true)]
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+        /// Only list notifications carrying this tag.
+        #[arg(long, conflicts_with = "journal")]
+        tag: Option<String>,
+        /// List the dispatches recorded in the journal instead, with their outcome for each user.
+        #[arg(long)]
+        journal: bool,
+        /// Only list this many of the latest journal entries.
+        #[arg(long, requires = "journal")]
+        limit: Option<u32>,
+    },
+    /// Send a previously sent notification again to users who are not showing it.
+    Replay {
+        /// The broadcast id printed by `send`.
+        #[arg(required_unless_present = "tag", conflicts_with = "tag")]
+        broadcast_id: Option<u64>,
+        /// Replay every notification carrying this tag instead.
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Send the latest critical notification again to every user, as the server does on SIGUSR1.
+    ReAnnounce,
+    /// Tell the server a user's session is ready, delivering what waits for them right away. (For pam_exec)
+    SessionHook {
+        /// The user who logged in, by username or uid; taken from `PAM_USER` unless given.
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Show how a previously sent notification was delivered to each user.
+    Report {
+        /// The broadcast id printed by `send`.
+        broadcast_id: u64,
+    },
+    /// Ask all users a question, offering each option as a notification action.
+    Poll {
+        /// The title of the notification.
+        title: String,
+        /// The question to ask.
         body: String,
+        /// An answer users can pick. May be given multiple times.
+        #[arg(long = "option", default_values = DEFAULT_POLL_OPTIONS)]
+        options: Vec<String>,
+    },
+    /// Show the answers given to a poll so far.
+    PollResults {
+        /// The broadcast id printed by `poll`.
+        broadcast_id: u64,
+    },
+    /// Show which users and notification services a broadcast would reach, without sending it.
+    RouteTest {
+        /// Title matched against the urgency rules.
+        #[arg(long, default_value = "")]
+        title: String,
+        /// Body matched against the urgency rules.
+        #[arg(long, default_value = "")]
+        body: String,
+        /// Assume this urgency instead of inferring it from the content.
+        #[arg(long)]
+        urgency: Option<Urgency>,
+        /// Only evaluate this user, by name or uid.
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Dump the server state (pending notifications, broadcasts, polls) to a JSON archive.
+    ExportState {
+        /// Write the archive to this file instead of standard output.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Restore server state from a JSON archive written by `export-state`.
+    ImportState {
+        /// The archive to restore.
+        input: PathBuf,
+    },
+    /// Only deliver critical notifications, spooling everything else until switched off.
+    Maintenance {
+        /// Switch maintenance mode on or off. Shows the current mode if omitted.
+        state: Option<Switch>,
+    },
+    /// Hold all notifications, even critical ones, until delivery is resumed.
+    Pause,
+    /// Resume delivery, sending the notifications held while it was paused.
+    Resume,
+    /// Check the saved server state for damage and notifications that would be shown twice.
+    VerifyStore {
+        /// Fix the problems found. Run this while the server is stopped.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Manage notifications scheduled with `send --at` or `send --in`.
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
+    /// Review broadcasts from unknown senders held until approved.
+    Quarantine {
+        #[command(subcommand)]
+        command: QuarantineCommand,
+    },
+    /// Manage notifications recurring with `send --every` or `send --cron`.
+    Recurring {
+        #[command(subcommand)]
+        command: RecurringCommand,
+    },
+    /// Show the live progress of a long-running job on every desktop.
+    NotifyProgress {
+        #[command(subcommand)]
+        command: ProgressCommand,
+    },
+    /// Measure the throughput and latency of the server pipeline with made-up sessions, delivering nowhere.
+    Bench {
+        /// Number of users with a graphical session to make up.
+        #[arg(long, default_value_t = 100)]
+        users: u32,
+        /// Number of broadcasts to send, one after the other.
+        #[arg(long, default_value_t = 1000)]
+        messages: u32,
+    },
+    /// Run a notification daemon printing every notification it receives, for tests without a desktop.
+    #[cfg(feature = "echo-daemon")]
+    EchoDaemon {
+        /// Serve on the bus at this address instead of starting a private one.
+        #[arg(long)]
+        address: Option<String>,
+        /// Print each event as a JSON object instead of a line of text.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Remote hosts a notification is sent on over SSH
+#[derive(Args, Debug, Clone, PartialEq)]
+pub struct RemoteArgs {
+    /// Run on this host over SSH instead of locally. May be given multiple times.
+    #[arg(long = "host")]
+    pub hosts: Vec<String>,
+    /// Run on every host listed in this file, one per line.
+    #[arg(long)]
+    pub hosts_file: Option<PathBuf>,
+    /// Number of hosts contacted at the same time.
+    #[arg(long, default_value_t = DEFAULT_PARALLELISM)]
+    pub parallel: usize,
+    /// Print the report of the remote hosts as JSON instead of a table.
+    #[arg(long)]
+    pub json: bool,
+}
+
+impl Default for RemoteArgs {
+    fn default() -> Self {
+        Self { hosts: Vec::new(), hosts_file: None, parallel: DEFAULT_PARALLELISM, json: false }
+    }
+}
+
+impl RemoteArgs {
+    /// Whether the notification is sent on remote hosts
+    pub fn is_remote(&self) -> bool {
+        !self.hosts.is_empty() || self.hosts_file.is_some()
+    }
+}
+
+/// Commands showing the progress of a job
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum ProgressCommand {
+    /// Show a progress bar at 0% and print the batch id to update it with.
+    Start {
+        /// The title of the notification, such as the name of the job.
+        title: String,
+        /// The body message of the notification, such as the current step.
+        text: String,
+    },
+    /// Move the progress bar of a batch.
+    Update {
+        /// The batch id printed by `notify-progress start`.
+        batch_id: String,
+        /// The percentage of the job done, from 0 to 100.
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: u8,
+        /// The new body message, or the current one if left out.
+        #[arg(default_value = "")]
+        text: String,
     },
 }
 
+/// Commands managing scheduled notifications
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum ScheduleCommand {
+    /// List the notifications waiting to be sent, soonest first.
+    List,
+    /// Cancel a scheduled notification before it is sent.
+    Cancel {
+        /// The schedule id printed by `send --at` or `send --in`.
+        id: u64,
+    },
+}
+
+/// Commands reviewing broadcasts held in quarantine
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum QuarantineCommand {
+    /// List the broadcasts awaiting approval, oldest first.
+    List,
+    /// Send a held broadcast and print its broadcast id.
+    Approve {
+        /// The quarantine id shown by `quarantine list`.
+        id: u64,
+    },
+    /// Drop a held broadcast without sending it.
+    Deny {
+        /// The quarantine id shown by `quarantine list`.
+        id: u64,
+    },
+}
+
+/// Commands working on the journal of dispatched notifications
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum HistoryCommand {
+    /// Write the journal as one row per notification and recipient, for spreadsheets or compliance tooling.
+    Export {
+        /// Format to write.
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Only export notifications dispatched at or after this local time, such as 2024-06-01T09:00.
+        #[arg(long, value_parser = parse_time)]
+        since: Option<NaiveDateTime>,
+        /// Write the export to this file instead of standard output.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Formats the journal can be exported in
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+/// Commands managing recurring notifications
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum RecurringCommand {
+    /// List the recurring notifications, soonest first.
+    List,
+    /// Stop a recurring notification.
+    Remove {
+        /// The schedule id printed by `send --every` or `send --cron`.
+        id: u64,
+    },
+}
+
+/// How delivery events are printed
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One sentence per event
+    Human,
+    /// One JSON object per event
+    Json,
+}
+
+/// State of a mode that can be switched on or off
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+impl Switch {
+    /// Whether the mode is switched on
+    pub fn is_on(self) -> bool {
+        self == Switch::On
+    }
+}
+
+/// Broadcasts addressed by a close or update request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastTarget {
+    /// A single broadcast by id
+    Broadcast(u64),
+    /// Every broadcast posted to a channel
+    Channel(String),
+}
+
+/// Resolve the positional arguments of `update` into its target, title and body
+pub fn parse_update_args(
+    channel: Option<String>,
+    args: &[String],
+) -> Result<(BroadcastTarget, String, String), String> {
+    match (channel, args) {
+        (Some(channel), [title, body]) => Ok((BroadcastTarget::Channel(channel), title.clone(), body.clone())),
+        (None, [broadcast_id, title, body]) => {
+            let broadcast_id = broadcast_id
+                .parse()
+                .map_err(|_| format!("Invalid broadcast id '{}'", broadcast_id))?;
+            Ok((BroadcastTarget::Broadcast(broadcast_id), title.clone(), body.clone()))
+        }
+        (Some(_), _) => Err("Expected a title and body after --channel".to_string()),
+        (None, _) => Err("Expected a broadcast id, title and body (or --channel with a title and body)".to_string()),
+    }
+}
+
+/// Parse an age such as `90s`, `30m`, `1h` or `2d`; a bare number counts seconds
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (amount, unit) = age.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| format!("Invalid age '{}'", age))?;
+    let unit_secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit '{}' in age '{}', expected s, m, h or d", unit, age)),
+    };
+    Ok(Duration::from_secs(amount.saturating_mul(unit_secs)))
+}
+
+/// Parse a local date and time such as `2024-06-01T09:00`, with optional seconds and a space instead of the `T`
+pub fn parse_time(time: &str) -> Result<NaiveDateTime, String> {
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())
+        .ok_or_else(|| format!("Invalid time '{}', expected YYYY-MM-DDTHH:MM", time))
+}
+
+/// Parse the interval of `--every`, such as `1h`, into a recurrence
+pub fn parse_every(interval: &str) -> Result<Recurrence, String> {
+    format!("every {}", interval).parse()
+}
+
+/// Parse the cron expression of `--cron`, such as `0 9 * * 1`, into a recurrence
+pub fn parse_cron(expression: &str) -> Result<Recurrence, String> {
+    expression.parse().map(Recurrence::Cron)
+}
+
 impl Cli {
     /// Parse command line arguments
     pub fn parse() -> Self {
@@ -50,12 +592,59 @@ mod tests {
         assert_eq!(cli.command, Commands::Server);
     }
 
+    #[test]
+    fn test_cli_bus() {
+        let cli = Cli::try_parse_from(["test", "server"]).unwrap();
+        assert_eq!(cli.bus, BusType::System);
+        let cli = Cli::try_parse_from(["test", "--bus", "session", "server"]).unwrap();
+        assert_eq!(cli.bus, BusType::Session);
+        let cli = Cli::try_parse_from(["test", "status", "--bus", "session"]).unwrap();
+        assert_eq!(cli.bus, BusType::Session);
+        assert!(Cli::try_parse_from(["test", "--bus", "user", "server"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_config() {
+        let cli = Cli::try_parse_from(["test", "server"]).unwrap();
+        assert_eq!(cli.config, PathBuf::from(DEFAULT_CONFIG_PATH));
+        let cli = Cli::try_parse_from(["test", "server", "--config", "/tmp/notifier.toml"]).unwrap();
+        assert_eq!(cli.config, PathBuf::from("/tmp/notifier.toml"));
+    }
+
     #[test]
     fn test_cli_send_command() {
         let cli = Cli::try_parse_from(["test", "send", "Test Title", "Test Body"]).unwrap();
         assert_eq!(cli.command, Commands::Send {
             title: "Test Title".to_string(),
             body: "Test Body".to_string(),
+            extra_bodies: vec![],
+            body_files: vec![],
+            body_commands: vec![],
+            channel: None,
+            category: None,
+            fingerprint: None,
+            resolved: false,
+            tags: vec![],
+            hook: None,
+            urgency: None,
+            actions: vec![],
+            group_key: None,
+            allow_duplicate: false,
+            markdown: false,
+            image: None,
+            group: None,
+            exclude_users: vec![],
+            seat: None,
+            queue_for_absent: false,
+            replace: None,
+            at: None,
+            delay: None,
+            every: None,
+            cron: None,
+            follow_report: None,
+            dry_run: false,
+            yes: false,
+            remote: RemoteArgs::default(),
         });
     }
 
@@ -65,9 +654,618 @@ mod tests {
         assert_eq!(cli.command, Commands::Send {
             title: "Title with spaces".to_string(),
             body: "Body with spaces".to_string(),
+            extra_bodies: vec![],
+            body_files: vec![],
+            body_commands: vec![],
+            channel: None,
+            category: None,
+            fingerprint: None,
+            resolved: false,
+            tags: vec![],
+            hook: None,
+            urgency: None,
+            actions: vec![],
+            group_key: None,
+            allow_duplicate: false,
+            markdown: false,
+            image: None,
+            group: None,
+            exclude_users: vec![],
+            seat: None,
+            queue_for_absent: false,
+            replace: None,
+            at: None,
+            delay: None,
+            every: None,
+            cron: None,
+            follow_report: None,
+            dry_run: false,
+            yes: false,
+            remote: RemoteArgs::default(),
+        });
+    }
+
+    #[test]
+    fn test_cli_send_with_channel() {
+        let cli = Cli::try_parse_from(["test", "send", "--channel", "backups", "Title", "Body"]).unwrap();
+        assert_eq!(cli.command, Commands::Send {
+            title: "Title".to_string(),
+            body: "Body".to_string(),
+            extra_bodies: vec![],
+            body_files: vec![],
+            body_commands: vec![],
+            channel: Some("backups".to_string()),
+            category: None,
+            fingerprint: None,
+            resolved: false,
+            tags: vec![],
+            hook: None,
+            urgency: None,
+            actions: vec![],
+            group_key: None,
+            allow_duplicate: false,
+            markdown: false,
+            image: None,
+            group: None,
+            exclude_users: vec![],
+            seat: None,
+            queue_for_absent: false,
+            replace: None,
+            at: None,
+            delay: None,
+            every: None,
+            cron: None,
+            follow_report: None,
+            dry_run: false,
+            yes: false,
+            remote: RemoteArgs::default(),
+        });
+    }
+
+    #[test]
+    fn test_cli_send_body_sources() {
+        let args = [
+            "test", "send", "Disk", "--body", "Root is filling up", "--body-command", "df -h /", "--body-file", "/tmp/du",
+        ];
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Send { body, extra_bodies, body_files, body_commands, .. } => {
+                assert_eq!(body, "");
+                assert_eq!(extra_bodies, vec!["Root is filling up".to_string()]);
+                assert_eq!(body_files, vec![PathBuf::from("/tmp/du")]);
+                assert_eq!(body_commands, vec!["df -h /".to_string()]);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["test", "send", "Disk"]).is_err());
+        assert!(Cli::try_parse_from(["test", "send", "Disk", "--body-command", "uptime"]).is_ok());
+    }
+
+    #[test]
+    fn test_cli_send_to_user() {
+        let cli = Cli::try_parse_from(["test", "send-to-user", "alice", "Title", "Body"]).unwrap();
+        assert_eq!(cli.command, Commands::SendToUser {
+            user: "alice".to_string(),
+            title: "Title".to_string(),
+            body: "Body".to_string(),
+        });
+        assert!(Cli::try_parse_from(["test", "send-to-user", "alice", "Title"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_batch() {
+        let cli = Cli::try_parse_from(["test", "send-batch", "--file", "batch.json"]).unwrap();
+        assert_eq!(cli.command, Commands::SendBatch { file: PathBuf::from("batch.json") });
+        assert!(Cli::try_parse_from(["test", "send-batch"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_with_urgency() {
+        let cli = Cli::try_parse_from(["test", "send", "--urgency", "critical", "Title", "Body"]).unwrap();
+        match cli.command {
+            Commands::Send { urgency, .. } => assert_eq!(urgency, Some(Urgency::Critical)),
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["test", "send", "--urgency", "urgent", "Title", "Body"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_with_actions() {
+        let args = ["test", "send", "--action", "ack:Acknowledge", "--action", "snooze:Later", "Title", "Body"];
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Send { actions, .. } => {
+                assert_eq!(actions, vec!["ack:Acknowledge".to_string(), "snooze:Later".to_string()])
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_send_to_group() {
+        let cli = Cli::try_parse_from(["test", "send", "--group", "wheel", "Title", "Body"]).unwrap();
+        match cli.command {
+            Commands::Send { group, group_key, .. } => {
+                assert_eq!((group, group_key), (Some("wheel".to_string()), None))
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_send_filters() {
+        let args = ["test", "send", "--exclude-user", "kiosk", "--exclude-user", "1001", "--seat", "seat0", "T", "B"];
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Send { exclude_users, seat, .. } => {
+                assert_eq!(exclude_users, vec!["kiosk".to_string(), "1001".to_string()]);
+                assert_eq!(seat, Some("seat0".to_string()));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_send_replace() {
+        let cli = Cli::try_parse_from(["test", "send", "--replace", "42", "Backup", "40%"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { replace: Some(id), .. } if id == "42"));
+        assert!(Cli::try_parse_from(["test", "send", "--replace", "42", "--every", "1h", "Backup", "40%"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_follow_report() {
+        let cli = Cli::try_parse_from(["test", "send", "--follow-report", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { follow_report: Some(ReportFormat::Human), .. }));
+        let cli = Cli::try_parse_from(["test", "send", "--follow-report=json", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { follow_report: Some(ReportFormat::Json), .. }));
+        let cli = Cli::try_parse_from(["test", "send", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { follow_report: None, .. }));
+        assert!(Cli::try_parse_from(["test", "send", "--follow-report", "--in", "1h", "Title", "Body"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_dry_run() {
+        let cli = Cli::try_parse_from(["test", "send", "--dry-run", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { dry_run: true, .. }));
+        assert!(Cli::try_parse_from(["test", "send", "--dry-run", "--follow-report", "Title", "Body"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_resolved() {
+        let args = ["test", "send", "--category", "monitoring.disk", "--fingerprint", "a1b2", "--resolved", "Disk", ""];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(cli.command, Commands::Send { resolved: true, fingerprint: Some(_), .. }));
+        assert!(Cli::try_parse_from(["test", "send", "--fingerprint", "a1b2", "Disk", ""]).is_err());
+        assert!(Cli::try_parse_from(["test", "send", "--resolved", "--in", "1h", "Disk", ""]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_yes() {
+        let cli = Cli::try_parse_from(["test", "send", "-y", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { yes: true, .. }));
+        let cli = Cli::try_parse_from(["test", "send", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { yes: false, .. }));
+    }
+
+    #[test]
+    fn test_cli_send_with_group_key() {
+        let cli = Cli::try_parse_from(["test", "send", "--group-key", "backups", "Title", "Body"]).unwrap();
+        match cli.command {
+            Commands::Send { group_key, .. } => assert_eq!(group_key, Some("backups".to_string())),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_send_allow_duplicate() {
+        let cli = Cli::try_parse_from(["test", "send", "--allow-duplicate", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { allow_duplicate: true, .. }));
+    }
+
+    #[test]
+    fn test_cli_send_markdown() {
+        let cli = Cli::try_parse_from(["test", "send", "--markdown", "Title", "**Body**"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { markdown: true, .. }));
+
+        let cli = Cli::try_parse_from(["test", "send", "--queue-for-absent", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { queue_for_absent: true, .. }));
+    }
+
+    #[test]
+    fn test_cli_send_scheduled() {
+        let cli = Cli::try_parse_from(["test", "send", "--at", "2024-06-01T09:00", "Title", "Body"]).unwrap();
+        let expected = NaiveDateTime::parse_from_str("2024-06-01 09:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert!(matches!(cli.command, Commands::Send { at: Some(at), delay: None, .. } if at == expected));
+        let cli = Cli::try_parse_from(["test", "send", "--in", "30m", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { at: None, delay: Some(delay), .. } if delay.as_secs() == 1800));
+
+        assert!(Cli::try_parse_from(["test", "send", "--at", "tomorrow", "Title", "Body"]).is_err());
+        assert!(Cli::try_parse_from(["test", "send", "--at", "2024-06-01T09:00", "--in", "1h", "T", "B"]).is_err());
+        assert!(Cli::try_parse_from(["test", "send", "--in", "1h", "--host", "web1", "Title", "Body"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_schedule_commands() {
+        let cli = Cli::try_parse_from(["test", "schedule", "list"]).unwrap();
+        assert_eq!(cli.command, Commands::Schedule { command: ScheduleCommand::List });
+        let cli = Cli::try_parse_from(["test", "schedule", "cancel", "3"]).unwrap();
+        assert_eq!(cli.command, Commands::Schedule { command: ScheduleCommand::Cancel { id: 3 } });
+        assert!(Cli::try_parse_from(["test", "schedule"]).is_err());
+
+        let cli = Cli::try_parse_from(["test", "quarantine", "approve", "2"]).unwrap();
+        assert_eq!(cli.command, Commands::Quarantine { command: QuarantineCommand::Approve { id: 2 } });
+        assert!(Cli::try_parse_from(["test", "quarantine", "deny"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_notify_progress() {
+        let cli = Cli::try_parse_from(["test", "notify-progress", "start", "Backup", "Copying /home"]).unwrap();
+        assert_eq!(cli.command, Commands::NotifyProgress {
+            command: ProgressCommand::Start { title: "Backup".to_string(), text: "Copying /home".to_string() },
+        });
+        let cli = Cli::try_parse_from(["test", "notify-progress", "update", "3f9c2a7b1d04", "40"]).unwrap();
+        assert_eq!(cli.command, Commands::NotifyProgress {
+            command: ProgressCommand::Update { batch_id: "3f9c2a7b1d04".to_string(), percent: 40, text: String::new() },
+        });
+        assert!(Cli::try_parse_from(["test", "notify-progress", "update", "3f9c2a7b1d04", "101"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_send_recurring() {
+        let cli = Cli::try_parse_from(["test", "send", "--every", "1h", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { every: Some(Recurrence::Every(3600)), cron: None, .. }));
+        let cli = Cli::try_parse_from(["test", "send", "--cron", "0 9 * * 1", "Title", "Body"]).unwrap();
+        assert!(matches!(cli.command, Commands::Send { cron: Some(Recurrence::Cron(_)), .. }));
+
+        assert!(Cli::try_parse_from(["test", "send", "--every", "0s", "Title", "Body"]).is_err());
+        assert!(Cli::try_parse_from(["test", "send", "--cron", "every 1h", "Title", "Body"]).is_err());
+        assert!(Cli::try_parse_from(["test", "send", "--every", "1h", "--cron", "0 9 * * 1", "T", "B"]).is_err());
+        assert!(Cli::try_parse_from(["test", "send", "--every", "1h", "--in", "1h", "Title", "Body"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_recurring_commands() {
+        let cli = Cli::try_parse_from(["test", "recurring", "list"]).unwrap();
+        assert_eq!(cli.command, Commands::Recurring { command: RecurringCommand::List });
+        let cli = Cli::try_parse_from(["test", "recurring", "remove", "3"]).unwrap();
+        assert_eq!(cli.command, Commands::Recurring { command: RecurringCommand::Remove { id: 3 } });
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let expected = NaiveDateTime::parse_from_str("2024-06-01 09:00:30", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(parse_time("2024-06-01T09:00:30"), Ok(expected));
+        assert_eq!(parse_time("2024-06-01 09:00:30"), Ok(expected));
+        assert!(parse_time("2024-06-01").is_err());
+        assert!(parse_time("2024-13-01T09:00").is_err());
+    }
+
+    #[test]
+    fn test_cli_send_with_hook() {
+        let cli = Cli::try_parse_from(["test", "send", "--hook", "flash-backlight", "Title", "Body"]).unwrap();
+        match cli.command {
+            Commands::Send { hook, .. } => assert_eq!(hook, Some("flash-backlight".to_string())),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_send_to_hosts() {
+        let cli = Cli::try_parse_from(["test", "send", "--host", "desk-01", "--host", "desk-02", "Title", "Body"])
+            .unwrap();
+        match cli.command {
+            Commands::Send { remote, .. } => {
+                assert_eq!(remote.hosts, vec!["desk-01".to_string(), "desk-02".to_string()]);
+                assert_eq!(remote.parallel, DEFAULT_PARALLELISM);
+                assert!(remote.is_remote());
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        let args = ["test", "send", "--hosts-file", "/etc/desks", "--parallel", "32", "--json", "Title", "Body"];
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Send { remote, .. } => assert_eq!(remote, RemoteArgs {
+                hosts: vec![],
+                hosts_file: Some(PathBuf::from("/etc/desks")),
+                parallel: 32,
+                json: true,
+            }),
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(!RemoteArgs::default().is_remote());
+    }
+
+    #[test]
+    fn test_cli_close_broadcast() {
+        let cli = Cli::try_parse_from(["test", "close", "42"]).unwrap();
+        assert_eq!(cli.command, Commands::Close {
+            broadcast_id: Some(42),
+            channel: None,
+            tag: None,
+            batch: None,
+            remote: RemoteArgs::default(),
         });
     }
 
+    #[test]
+    fn test_cli_close_channel() {
+        let cli = Cli::try_parse_from(["test", "close", "--channel", "backups"]).unwrap();
+        assert_eq!(cli.command, Commands::Close {
+            broadcast_id: None,
+            channel: Some("backups".to_string()),
+            tag: None,
+            batch: None,
+            remote: RemoteArgs::default(),
+        });
+    }
+
+    #[test]
+    fn test_cli_send_with_tags() {
+        let cli = Cli::try_parse_from(["test", "send", "--tag", "incident-421", "--tag", "db", "Title", "Body"])
+            .unwrap();
+        match cli.command {
+            Commands::Send { tags, .. } => assert_eq!(tags, vec!["incident-421".to_string(), "db".to_string()]),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_close_tag() {
+        let cli = Cli::try_parse_from(["test", "close", "--tag", "incident-421"]).unwrap();
+        assert_eq!(cli.command, Commands::Close {
+            broadcast_id: None,
+            channel: None,
+            tag: Some("incident-421".to_string()),
+            batch: None,
+            remote: RemoteArgs::default(),
+        });
+        assert!(Cli::try_parse_from(["test", "close", "42", "--tag", "incident-421"]).is_err());
+        assert!(Cli::try_parse_from(["test", "close", "--channel", "backups", "--tag", "incident-421"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_close_batch_on_hosts() {
+        let args = ["test", "close", "--batch", "3f9c2a7b1d04", "--hosts-file", "/etc/desks"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.command, Commands::Close {
+            broadcast_id: None,
+            channel: None,
+            tag: None,
+            batch: Some("3f9c2a7b1d04".to_string()),
+            remote: RemoteArgs { hosts_file: Some(PathBuf::from("/etc/desks")), ..RemoteArgs::default() },
+        });
+        assert!(Cli::try_parse_from(["test", "close", "42", "--batch", "3f9c2a7b1d04"]).is_err());
+        assert!(Cli::try_parse_from(["test", "close", "--tag", "incident-421", "--batch", "3f9c2a7b1d04"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_history_and_replay() {
+        let cli = Cli::try_parse_from(["test", "history"]).unwrap();
+        assert_eq!(cli.command, Commands::History { command: None, tag: None, journal: false, limit: None });
+        let cli = Cli::try_parse_from(["test", "history", "--tag", "db"]).unwrap();
+        let tag = Some("db".to_string());
+        assert_eq!(cli.command, Commands::History { command: None, tag, journal: false, limit: None });
+        let cli = Cli::try_parse_from(["test", "history", "--journal", "--limit", "20"]).unwrap();
+        assert_eq!(cli.command, Commands::History { command: None, tag: None, journal: true, limit: Some(20) });
+        assert!(Cli::try_parse_from(["test", "history", "--limit", "20"]).is_err());
+        assert!(Cli::try_parse_from(["test", "history", "--journal", "--tag", "db"]).is_err());
+
+        let cli = Cli::try_parse_from(["test", "history", "export", "--since", "2024-06-01T09:00"]).unwrap();
+        let export = HistoryCommand::Export {
+            format: ExportFormat::Csv,
+            since: Some(parse_time("2024-06-01T09:00").unwrap()),
+            output: None,
+        };
+        assert_eq!(cli.command, Commands::History { command: Some(export), tag: None, journal: false, limit: None });
+        assert!(Cli::try_parse_from(["test", "history", "export", "--format", "xlsx"]).is_err());
+        assert!(Cli::try_parse_from(["test", "history", "--journal", "export"]).is_err());
+
+        let cli = Cli::try_parse_from(["test", "replay", "42"]).unwrap();
+        assert_eq!(cli.command, Commands::Replay { broadcast_id: Some(42), tag: None });
+        let cli = Cli::try_parse_from(["test", "replay", "--tag", "db"]).unwrap();
+        assert_eq!(cli.command, Commands::Replay { broadcast_id: None, tag: Some("db".to_string()) });
+        assert!(Cli::try_parse_from(["test", "replay"]).is_err());
+        assert!(Cli::try_parse_from(["test", "replay", "42", "--tag", "db"]).is_err());
+        let cli = Cli::try_parse_from(["test", "re-announce"]).unwrap();
+        assert_eq!(cli.command, Commands::ReAnnounce);
+        let cli = Cli::try_parse_from(["test", "session-hook", "--user", "alice"]).unwrap();
+        assert_eq!(cli.command, Commands::SessionHook { user: Some("alice".to_string()) });
+    }
+
+    #[test]
+    fn test_cli_close_invalid() {
+        assert!(Cli::try_parse_from(["test", "close"]).is_err());
+        assert!(Cli::try_parse_from(["test", "close", "42", "--channel", "backups"]).is_err());
+        assert!(Cli::try_parse_from(["test", "close", "not-a-number"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_close_all_command() {
+        let cli = Cli::try_parse_from(["test", "close-all"]).unwrap();
+        assert_eq!(cli.command, Commands::CloseAll { older_than: None, channel: None });
+        let cli = Cli::try_parse_from(["test", "close-all", "--older-than", "1h", "--channel", "incidents"]).unwrap();
+        assert_eq!(cli.command, Commands::CloseAll {
+            older_than: Some(Duration::from_secs(3600)),
+            channel: Some("incidents".to_string()),
+        });
+        assert!(Cli::try_parse_from(["test", "close-all", "--older-than", "1w"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_age("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_age("2d"), Ok(Duration::from_secs(172800)));
+        assert!(parse_age("h").is_err());
+        assert!(parse_age("1.5h").is_err());
+    }
+
+    fn update_args(cli: Cli) -> Result<(BroadcastTarget, String, String), String> {
+        match cli.command {
+            Commands::Update { channel, args } => parse_update_args(channel, &args),
+            other => panic!("Expected Update command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_update_broadcast() {
+        let cli = Cli::try_parse_from(["test", "update", "42", "New title", "New body"]).unwrap();
+        assert_eq!(
+            update_args(cli).unwrap(),
+            (BroadcastTarget::Broadcast(42), "New title".to_string(), "New body".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cli_update_channel() {
+        let cli = Cli::try_parse_from(["test", "update", "--channel", "status", "New title", "New body"]).unwrap();
+        assert_eq!(
+            update_args(cli).unwrap(),
+            (BroadcastTarget::Channel("status".to_string()), "New title".to_string(), "New body".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cli_update_invalid() {
+        assert!(Cli::try_parse_from(["test", "update", "title"]).is_err());
+        assert!(Cli::try_parse_from(["test", "update", "1", "2", "3", "4"]).is_err());
+
+        let cli = Cli::try_parse_from(["test", "update", "42", "title"]).unwrap();
+        assert!(update_args(cli).is_err());
+        let cli = Cli::try_parse_from(["test", "update", "--channel", "status", "42", "title", "body"]).unwrap();
+        assert!(update_args(cli).is_err());
+        let cli = Cli::try_parse_from(["test", "update", "latest", "title", "body"]).unwrap();
+        assert!(update_args(cli).is_err());
+    }
+
+    #[test]
+    fn test_cli_status_command() {
+        let cli = Cli::try_parse_from(["test", "status"]).unwrap();
+        assert_eq!(cli.command, Commands::Status { ping: false });
+        let cli = Cli::try_parse_from(["test", "status", "--ping"]).unwrap();
+        assert_eq!(cli.command, Commands::Status { ping: true });
+    }
+
+    #[test]
+    fn test_cli_capabilities_command() {
+        let cli = Cli::try_parse_from(["test", "capabilities", "1000"]).unwrap();
+        assert_eq!(cli.command, Commands::Capabilities { uid: 1000 });
+        assert!(Cli::try_parse_from(["test", "capabilities", "alice"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_inspect_command() {
+        let cli = Cli::try_parse_from(["test", "inspect", "alice"]).unwrap();
+        assert_eq!(cli.command, Commands::Inspect { user: "alice".to_string() });
+        assert!(Cli::try_parse_from(["test", "inspect"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_stats_command() {
+        let cli = Cli::try_parse_from(["test", "stats"]).unwrap();
+        assert_eq!(cli.command, Commands::Stats { prometheus: false, helper: false });
+        let cli = Cli::try_parse_from(["test", "stats", "--prometheus"]).unwrap();
+        assert_eq!(cli.command, Commands::Stats { prometheus: true, helper: false });
+        let cli = Cli::try_parse_from(["test", "stats", "--helper"]).unwrap();
+        assert_eq!(cli.command, Commands::Stats { prometheus: false, helper: true });
+        assert!(Cli::try_parse_from(["test", "stats", "--helper", "--prometheus"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_report_command() {
+        let cli = Cli::try_parse_from(["test", "report", "42"]).unwrap();
+        assert_eq!(cli.command, Commands::Report { broadcast_id: 42 });
+        assert!(Cli::try_parse_from(["test", "report"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_poll_command() {
+        let cli = Cli::try_parse_from(["test", "poll", "Reboot", "Reboot tonight?"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Commands::Poll {
+                title: "Reboot".to_string(),
+                body: "Reboot tonight?".to_string(),
+                options: vec!["Yes".to_string(), "No".to_string(), "Later".to_string()],
+            }
+        );
+
+        let cli = Cli::try_parse_from(["test", "poll", "Lunch", "Where?", "--option", "Pizza", "--option", "Sushi"])
+            .unwrap();
+        match cli.command {
+            Commands::Poll { options, .. } => assert_eq!(options, vec!["Pizza".to_string(), "Sushi".to_string()]),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cli_poll_results_command() {
+        let cli = Cli::try_parse_from(["test", "poll-results", "7"]).unwrap();
+        assert_eq!(cli.command, Commands::PollResults { broadcast_id: 7 });
+        assert!(Cli::try_parse_from(["test", "poll-results"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_route_test_command() {
+        let cli = Cli::try_parse_from(["test", "route-test", "--urgency", "critical", "--user", "alice"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Commands::RouteTest {
+                title: String::new(),
+                body: String::new(),
+                urgency: Some(Urgency::Critical),
+                user: Some("alice".to_string()),
+            }
+        );
+        assert!(Cli::try_parse_from(["test", "route-test", "--urgency", "urgent"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_state_commands() {
+        let cli = Cli::try_parse_from(["test", "export-state"]).unwrap();
+        assert_eq!(cli.command, Commands::ExportState { output: None });
+        let cli = Cli::try_parse_from(["test", "export-state", "-o", "state.json"]).unwrap();
+        assert_eq!(cli.command, Commands::ExportState { output: Some(PathBuf::from("state.json")) });
+
+        let cli = Cli::try_parse_from(["test", "import-state", "state.json"]).unwrap();
+        assert_eq!(cli.command, Commands::ImportState { input: PathBuf::from("state.json") });
+        assert!(Cli::try_parse_from(["test", "import-state"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_maintenance_command() {
+        let cli = Cli::try_parse_from(["test", "maintenance", "on"]).unwrap();
+        assert_eq!(cli.command, Commands::Maintenance { state: Some(Switch::On) });
+        let cli = Cli::try_parse_from(["test", "maintenance", "off"]).unwrap();
+        assert_eq!(cli.command, Commands::Maintenance { state: Some(Switch::Off) });
+        assert!(!Switch::Off.is_on());
+        let cli = Cli::try_parse_from(["test", "maintenance"]).unwrap();
+        assert_eq!(cli.command, Commands::Maintenance { state: None });
+        assert!(Cli::try_parse_from(["test", "maintenance", "maybe"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_pause_and_resume_commands() {
+        let cli = Cli::try_parse_from(["test", "pause"]).unwrap();
+        assert_eq!(cli.command, Commands::Pause);
+        let cli = Cli::try_parse_from(["test", "resume"]).unwrap();
+        assert_eq!(cli.command, Commands::Resume);
+        assert!(Cli::try_parse_from(["test", "pause", "on"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_verify_store_command() {
+        let cli = Cli::try_parse_from(["test", "verify-store"]).unwrap();
+        assert_eq!(cli.command, Commands::VerifyStore { repair: false });
+        let cli = Cli::try_parse_from(["test", "verify-store", "--repair"]).unwrap();
+        assert_eq!(cli.command, Commands::VerifyStore { repair: true });
+    }
+
+    #[test]
+    fn test_cli_bench_command() {
+        let cli = Cli::try_parse_from(["test", "bench"]).unwrap();
+        assert_eq!(cli.command, Commands::Bench { users: 100, messages: 1000 });
+        let cli = Cli::try_parse_from(["test", "bench", "--users", "500", "--messages", "20"]).unwrap();
+        assert_eq!(cli.command, Commands::Bench { users: 500, messages: 20 });
+    }
+
     #[test]
     fn test_cli_invalid_command() {
         let result = Cli::try_parse_from(["test", "invalid"]);
@@ -109,6 +1307,34 @@ mod tests {
         let cmd = Commands::Send {
             title: "Test".to_string(),
             body: "Body".to_string(),
+            extra_bodies: vec![],
+            body_files: vec![],
+            body_commands: vec![],
+            channel: None,
+            category: None,
+            fingerprint: None,
+            resolved: false,
+            tags: vec![],
+            hook: None,
+            urgency: None,
+            actions: vec![],
+            group_key: None,
+            allow_duplicate: false,
+            markdown: false,
+            image: None,
+            group: None,
+            exclude_users: vec![],
+            seat: None,
+            queue_for_absent: false,
+            replace: None,
+            at: None,
+            delay: None,
+            every: None,
+            cron: None,
+            follow_report: None,
+            dry_run: false,
+            yes: false,
+            remote: RemoteArgs::default(),
         };
         let debug_str = format!("{:?}", cmd);
         assert!(debug_str.contains("Send"));
@@ -0,0 +1,226 @@
+//! Client for applications embedding the notifier
+//!
+//! Wraps the D-Bus proxy of a running server and turns its signals into a
+//! stream of typed delivery events, so applications can follow broadcasts
+//! reactively instead of polling delivery reports.
+
+use std::fmt;
+
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::Serialize;
+
+use crate::bus::BusType;
+use crate::dbus::NotifierProxy;
+use crate::error::{NotifierError, NotifierResult};
+
+/// Progress of a broadcast, as announced by the server
+///
+/// Serialized as an object whose `event` field names the variant, such as `user_delivered`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DeliveryEvent {
+    /// A broadcast is about to be delivered to its recipients
+    BroadcastStarted { broadcast_id: u64, recipients: u32 },
+    /// A broadcast was shown to a user
+    UserDelivered { broadcast_id: u64, uid: u32, username: String, status: String },
+    /// A broadcast could not be delivered to a user
+    UserFailed { broadcast_id: u64, uid: u32, username: String, status: String },
+    /// A broadcast is held back for a user, until `until` (RFC 3339) if known
+    UserDeferred { broadcast_id: u64, uid: u32, username: String, until: Option<String>, reason: String },
+    /// A user invoked an action of a broadcast, such as a poll answer
+    ActionInvoked { broadcast_id: u64, uid: u32, username: String, action: String },
+    /// A broadcast was delivered to, failed for or spooled for each recipient
+    Completed { broadcast_id: u64, delivered: u32, failed: u32, deferred: u32 },
+}
+
+impl DeliveryEvent {
+    /// Get the id of the broadcast the event is about
+    pub fn broadcast_id(&self) -> u64 {
+        match self {
+            DeliveryEvent::BroadcastStarted { broadcast_id, .. }
+            | DeliveryEvent::UserDelivered { broadcast_id, .. }
+            | DeliveryEvent::UserFailed { broadcast_id, .. }
+            | DeliveryEvent::UserDeferred { broadcast_id, .. }
+            | DeliveryEvent::ActionInvoked { broadcast_id, .. }
+            | DeliveryEvent::Completed { broadcast_id, .. } => *broadcast_id,
+        }
+    }
+
+    /// Whether this is the last event of a broadcast's dispatch
+    pub fn is_completed(&self) -> bool {
+        matches!(self, DeliveryEvent::Completed { .. })
+    }
+}
+
+impl fmt::Display for DeliveryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeliveryEvent::BroadcastStarted { broadcast_id, recipients } => {
+                write!(f, "broadcast {} started for {} users", broadcast_id, recipients)
+            }
+            DeliveryEvent::UserDelivered { broadcast_id, username, status, .. }
+            | DeliveryEvent::UserFailed { broadcast_id, username, status, .. } => {
+                write!(f, "broadcast {} {} for {}", broadcast_id, status, username)
+            }
+            DeliveryEvent::UserDeferred { broadcast_id, username, until, reason, .. } => {
+                write!(f, "broadcast {} deferred for {}: {}", broadcast_id, username, reason)?;
+                match until {
+                    Some(until) => write!(f, " (until {})", until),
+                    None => Ok(()),
+                }
+            }
+            DeliveryEvent::ActionInvoked { broadcast_id, username, action, .. } => {
+                write!(f, "{} chose '{}' on broadcast {}", username, action, broadcast_id)
+            }
+            DeliveryEvent::Completed { broadcast_id, delivered, failed, deferred } => write!(
+                f,
+                "broadcast {} completed: {} delivered, {} failed, {} deferred",
+                broadcast_id, delivered, failed, deferred
+            ),
+        }
+    }
+}
+
+/// Connection to a running notifier server
+#[derive(Debug, Clone)]
+pub struct NotifierClient {
+    proxy: NotifierProxy<'static>,
+}
+
+impl NotifierClient {
+    /// Connect to the server on a bus
+    pub async fn connect(bus: BusType) -> NotifierResult<Self> {
+        let connect_error = |e| NotifierError::connect(format!("the {} bus", bus), e);
+        let connection = bus.connect().await.map_err(connect_error)?;
+        Ok(Self::new(NotifierProxy::new(&connection).await.map_err(connect_error)?))
+    }
+
+    /// Create a client using an existing proxy
+    pub fn new(proxy: NotifierProxy<'static>) -> Self {
+        Self { proxy }
+    }
+
+    /// Get the proxy, to call the server's methods
+    pub fn proxy(&self) -> &NotifierProxy<'static> {
+        &self.proxy
+    }
+
+    /// Subscribe to the delivery events of all broadcasts
+    ///
+    /// Events are only received while the stream is alive. Subscribe before
+    /// sending a broadcast so none of its events are missed.
+    pub async fn subscribe_events(&self) -> zbus::Result<BoxStream<'static, DeliveryEvent>> {
+        let started = self.proxy.receive_broadcast_started().await?.filter_map(|signal| async move {
+            let args = signal.args().ok()?;
+            Some(DeliveryEvent::BroadcastStarted { broadcast_id: args.broadcast_id, recipients: args.recipients })
+        });
+        let delivered = self.proxy.receive_user_delivered().await?.filter_map(|signal| async move {
+            let args = signal.args().ok()?;
+            Some(DeliveryEvent::UserDelivered {
+                broadcast_id: args.broadcast_id,
+                uid: args.uid,
+                username: args.username,
+                status: args.status,
+            })
+        });
+        let failed = self.proxy.receive_user_failed().await?.filter_map(|signal| async move {
+            let args = signal.args().ok()?;
+            Some(DeliveryEvent::UserFailed {
+                broadcast_id: args.broadcast_id,
+                uid: args.uid,
+                username: args.username,
+                status: args.status,
+            })
+        });
+        let deferred = self.proxy.receive_broadcast_deferred().await?.filter_map(|signal| async move {
+            let args = signal.args().ok()?;
+            Some(DeliveryEvent::UserDeferred {
+                broadcast_id: args.broadcast_id,
+                uid: args.uid,
+                username: args.username,
+                until: Some(args.until).filter(|until| !until.is_empty()),
+                reason: args.reason,
+            })
+        });
+        let actions = self.proxy.receive_broadcast_action_invoked().await?.filter_map(|signal| async move {
+            let args = signal.args().ok()?;
+            Some(DeliveryEvent::ActionInvoked {
+                broadcast_id: args.broadcast_id,
+                uid: args.uid,
+                username: args.username,
+                action: args.action,
+            })
+        });
+        let completed = self.proxy.receive_completed().await?.filter_map(|signal| async move {
+            let args = signal.args().ok()?;
+            Some(DeliveryEvent::Completed {
+                broadcast_id: args.broadcast_id,
+                delivered: args.delivered,
+                failed: args.failed,
+                deferred: args.deferred,
+            })
+        });
+        Ok(stream::select_all([
+            started.boxed(),
+            delivered.boxed(),
+            failed.boxed(),
+            deferred.boxed(),
+            actions.boxed(),
+            completed.boxed(),
+        ])
+        .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_broadcast_ids() {
+        let events = [
+            DeliveryEvent::BroadcastStarted { broadcast_id: 7, recipients: 2 },
+            DeliveryEvent::UserFailed {
+                broadcast_id: 7,
+                uid: 1000,
+                username: "alice".to_string(),
+                status: "failed (no notification daemon)".to_string(),
+            },
+            DeliveryEvent::Completed { broadcast_id: 7, delivered: 1, failed: 1, deferred: 0 },
+        ];
+        assert!(events.iter().all(|event| event.broadcast_id() == 7));
+        assert!(events[2].is_completed());
+        assert!(!events[0].is_completed());
+    }
+
+    #[test]
+    fn test_event_display() {
+        let deferred = DeliveryEvent::UserDeferred {
+            broadcast_id: 3,
+            uid: 1001,
+            username: "bob".to_string(),
+            until: Some("2026-10-17T08:00:00+02:00".to_string()),
+            reason: "spool until delivery window opens".to_string(),
+        };
+        assert_eq!(
+            deferred.to_string(),
+            "broadcast 3 deferred for bob: spool until delivery window opens (until 2026-10-17T08:00:00+02:00)"
+        );
+        let completed = DeliveryEvent::Completed { broadcast_id: 3, delivered: 4, failed: 0, deferred: 1 };
+        assert_eq!(completed.to_string(), "broadcast 3 completed: 4 delivered, 0 failed, 1 deferred");
+    }
+
+    #[test]
+    fn test_event_json() {
+        let acked = DeliveryEvent::ActionInvoked {
+            broadcast_id: 3,
+            uid: 1000,
+            username: "alice".to_string(),
+            action: "ack".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&acked).unwrap(),
+            r#"{"event":"action_invoked","broadcast_id":3,"uid":1000,"username":"alice","action":"ack"}"#
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Broadcast bodies composed from several fragments
+//!
+//! A body can combine text given on the command line, files read by the
+//! client and the output of commands run by the server, e.g. `df -h /`. The
+//! server only runs a command whose exact command line is allowlisted in its
+//! configuration, without a shell, with a minimal environment and a time
+//! limit, and keeps only the start of its output.
+
+use std::process::Stdio;
+use std::time::Duration;
+
+/// How long a body command may run before it is killed
+pub const BODY_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest output of a body command kept in the body, in bytes
+pub const MAX_BODY_COMMAND_OUTPUT: usize = 4096;
+
+/// Search path given to body commands
+const BODY_COMMAND_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Join body fragments, one per line, skipping empty ones
+pub fn join_fragments<I, S>(fragments: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    fragments
+        .into_iter()
+        .map(|fragment| fragment.as_ref().trim_end().to_string())
+        .filter(|fragment| !fragment.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Split a command line into its arguments if it is allowlisted
+///
+/// Command lines are compared after collapsing whitespace, so `df  -h /`
+/// matches an allowlisted `df -h /`, but no other difference is tolerated.
+pub fn resolve_command(allowed: &[String], command: &str) -> Result<Vec<String>, String> {
+    let argv: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+    let is_allowed = allowed
+        .iter()
+        .any(|entry| entry.split_whitespace().eq(argv.iter().map(String::as_str)));
+    if argv.is_empty() || !is_allowed {
+        return Err(format!("Body command '{}' is not allowed", command));
+    }
+    Ok(argv)
+}
+
+/// Run a resolved body command and return its output
+pub async fn run_command(argv: &[String]) -> Result<String, String> {
+    let (program, args) = argv.split_first().ok_or("Empty body command")?;
+    let command_line = argv.join(" ");
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .env_clear()
+        .env("PATH", BODY_COMMAND_PATH)
+        .env("LC_ALL", "C")
+        .current_dir("/")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(BODY_COMMAND_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => output,
+        Ok(Ok(output)) => return Err(format!("Body command '{}' failed: {}", command_line, output.status)),
+        Ok(Err(e)) => return Err(format!("Failed to run body command '{}': {}", command_line, e)),
+        Err(_) => {
+            return Err(format!("Body command '{}' timed out after {:?}", command_line, BODY_COMMAND_TIMEOUT))
+        }
+    };
+    Ok(truncate_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Keep the start of a command's output, marking where it was cut
+fn truncate_output(output: &str) -> String {
+    if output.len() <= MAX_BODY_COMMAND_OUTPUT {
+        return output.to_string();
+    }
+    let mut end = MAX_BODY_COMMAND_OUTPUT;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &output[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_fragments() {
+        assert_eq!(join_fragments(["Backups failed", "", "disk full\n"]), "Backups failed\ndisk full");
+        assert_eq!(join_fragments(Vec::<String>::new()), "");
+    }
+
+    #[test]
+    fn test_resolve_command() {
+        let allowed = vec!["df -h /".to_string(), "uptime".to_string()];
+        assert_eq!(resolve_command(&allowed, "df -h /").unwrap(), vec!["df", "-h", "/"]);
+        assert_eq!(resolve_command(&allowed, " df  -h / ").unwrap(), vec!["df", "-h", "/"]);
+        assert!(resolve_command(&allowed, "df -h /home").is_err());
+        assert!(resolve_command(&allowed, "uptime; rm -rf /").is_err());
+        assert!(resolve_command(&allowed, "").is_err());
+        assert!(resolve_command(&[], "uptime").is_err());
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        assert_eq!(truncate_output("short"), "short");
+        let long = "é".repeat(MAX_BODY_COMMAND_OUTPUT);
+        let truncated = truncate_output(&long);
+        assert!(truncated.len() <= MAX_BODY_COMMAND_OUTPUT + '…'.len_utf8());
+        assert!(truncated.ends_with('…'));
+    }
+
+    #[tokio::test]
+    async fn test_run_command() {
+        let argv = vec!["echo".to_string(), "42% used".to_string()];
+        assert_eq!(run_command(&argv).await.unwrap(), "42% used\n");
+        assert!(run_command(&["false".to_string()]).await.unwrap_err().contains("failed"));
+        assert!(run_command(&["/nonexistent/df".to_string()]).await.is_err());
+    }
+}
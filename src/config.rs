@@ -0,0 +1,673 @@
+//! Server configuration loaded from disk
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::absent::DEFAULT_ABSENT_QUEUE_TTL;
+use crate::audit::AuditConfig;
+use crate::backend::DeliveryBackend;
+use crate::caller::SenderAcl;
+use crate::dbus::NOTIFICATIONS_BUS_NAME;
+use crate::delivery::{RetryPolicy, DEFAULT_MAX_CONCURRENT_DELIVERIES, DEFAULT_USER_DELIVERY_TIMEOUT};
+use crate::helper::{ArgumentPassing, DeliveryStrategy};
+use crate::hook::DEFAULT_HOOKS_DIR;
+use crate::host::HostContextConfig;
+use crate::i18n::DEFAULT_LOCALES_DIR;
+use crate::latency::DEFAULT_SLOW_DELIVERY_THRESHOLD;
+use crate::limits::LimitsConfig;
+use crate::maintenance::DEFAULT_STATE_DIR;
+use crate::nested::NestedBusPolicy;
+use crate::notification::NotificationDefaults;
+use crate::nss::DEFAULT_NSS_CACHE_TTL;
+use crate::polkit::PolkitMode;
+use crate::pool::DEFAULT_SESSION_BUS_IDLE_TIMEOUT;
+use crate::profile::RenderingProfiles;
+use crate::request::SendOptions;
+use crate::session::DEFAULT_SESSION_CACHE_TTL;
+use crate::sound::SoundConfig;
+use crate::store::StoreBackend;
+use crate::urgency::UrgencyRule;
+use crate::window::DeliveryWindow;
+
+/// Default location of the server configuration file
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/dots-notifier/config.toml";
+
+/// Server configuration
+///
+/// Read from [`DEFAULT_CONFIG_PATH`] unless the server is started with `--config`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Per-user delivery windows, keyed by username
+    pub delivery_windows: HashMap<String, DeliveryWindow>,
+    /// Directory containing additional Fluent catalogs
+    pub locales_dir: Option<PathBuf>,
+    /// Locales built-in strings are shown in, such as `de_DE.UTF-8`, keyed by username,
+    /// for shared accounts or kiosks whose session locale is wrong
+    pub language_overrides: HashMap<String, String>,
+    /// Sound theme event mapping
+    pub sound: SoundConfig,
+    /// Rules inferring the urgency of notifications from their content
+    pub urgency_rules: Vec<UrgencyRule>,
+    /// How long user and group lookups are cached, in seconds
+    pub nss_cache_ttl_secs: Option<u64>,
+    /// How long an enumerated session list is reused, in seconds
+    pub session_cache_ttl_secs: Option<u64>,
+    /// How long the server keeps an unused connection to a user's session bus for later deliveries,
+    /// in seconds; 0 connects anew for every delivery
+    pub session_bus_idle_secs: Option<u64>,
+    /// Limits on broadcast payloads and pending notifications
+    pub limits: LimitsConfig,
+    /// Bus names to deliver notifications to, keyed by desktop environment
+    /// (`XDG_SESSION_DESKTOP`), for sessions where a proxy owns a different name
+    pub notification_services: HashMap<String, String>,
+    /// Which session buses of a user are notified: `primary`, the one in their runtime directory,
+    /// or `all`, also those of nested sessions such as a compositor under test
+    pub nested_buses: NestedBusPolicy,
+    /// Write broadcasts to the terminals of users without a notification daemon
+    pub terminal_fallback: bool,
+    /// Hold notifications whose delivery to a logged-in user failed, and deliver them again
+    /// once a notification daemon appears on that user's session bus, such as after a crash
+    pub park_failed: bool,
+    /// How notifications reach the users' notification daemons, unless `delivery_backends` is set
+    pub delivery_strategy: DeliveryStrategy,
+    /// Backends tried in order to reach the users' notification daemons (`direct`, `helper`,
+    /// `machinectl` or `sudo`); derived from `delivery_strategy` if empty
+    pub delivery_backends: Vec<DeliveryBackend>,
+    /// Absolute path of the helper used by the `helper` delivery strategy,
+    /// looked up on `PATH` if unset
+    pub helper_path: Option<PathBuf>,
+    /// How the notification to deliver is passed to the helper
+    pub helper_args: ArgumentPassing,
+    /// Directory holding state persisted across restarts
+    pub state_dir: Option<PathBuf>,
+    /// Where pending notifications and the broadcast history are kept across restarts
+    pub store: StoreBackend,
+    /// Queue for the bus name as a warm standby while another instance owns it, instead of failing,
+    /// and take over with the state it saved once it exits; needs a persistent `store` both share
+    pub warm_standby: bool,
+    /// Record every dispatched broadcast in a journal in the state directory
+    pub journal: bool,
+    /// Record requests with the identity of their callers in an audit log
+    pub audit: AuditConfig,
+    /// Number of users above which `send` asks for confirmation, or needs `--yes` when not run
+    /// from a terminal; it never asks if unset
+    pub confirm_above_users: Option<usize>,
+    /// Save the state as soon as notifications are delivered, updated or closed, not only every minute,
+    /// so their ids survive a crash and later updates and closes still reach them; needs a persistent `store`
+    pub persist_notification_ids: bool,
+    /// Directory holding the hooks broadcasts may ask to run
+    pub hooks_dir: Option<PathBuf>,
+    /// Delivery latency above which a user's notification daemon counts as slow, in milliseconds
+    pub slow_delivery_threshold_ms: Option<u64>,
+    /// Longest a single delivery attempt to a user may take before it is abandoned and reported
+    /// as stalled, so a hung helper or session bus does not hold up a broadcast, in milliseconds;
+    /// 0 waits indefinitely
+    pub user_delivery_timeout_ms: Option<u64>,
+    /// Most deliveries, updates and closes of notifications running at the same time, so a broadcast
+    /// to hundreds of sessions on a terminal server does not start a helper for each of them at once
+    pub max_concurrent_deliveries: Option<usize>,
+    /// Maximum random delay of scheduled fire times, such as delivery windows
+    /// opening, so hosts of a fleet do not all deliver at the same second, in seconds
+    pub fire_jitter_secs: u64,
+    /// How long a broadcast repeating the title and body of an earlier one updates that one's
+    /// notifications in place instead of stacking a duplicate, in seconds; 0 turns this off
+    pub dedup_window_secs: u64,
+    /// How long notifications suppressed for a user, as duplicates or superseded in the spool, are counted
+    /// before a single notification tells the user how many there were, in seconds; 0 keeps suppression silent
+    pub suppression_summary_secs: u64,
+    /// How long after a broadcast users who log in still receive it, in seconds; 0 only reaches
+    /// those who logged in while it was being dispatched, and unset leaves late users to `replay`
+    pub late_join_grace_secs: Option<u64>,
+    /// How long broadcasts sent with `queue_for_absent` are kept for users who log in later, in seconds
+    pub absent_queue_ttl_secs: Option<u64>,
+    /// Names broadcasts are attributed to, keyed by the sender's systemd unit,
+    /// username or `uid:<uid>`
+    pub sender_names: HashMap<String, String>,
+    /// Name the sender of a broadcast in a footer of its body, not only in a hint
+    pub sender_footer: bool,
+    /// Unix socket accepting broadcasts as JSON, for clients without D-Bus; off if unset
+    pub socket_path: Option<PathBuf>,
+    /// Group whose members may connect to the Unix socket; without one only root can
+    pub socket_group: Option<String>,
+    /// Hold broadcasts arriving on the Unix socket from senders without a `sender_names` entry
+    /// in quarantine until approved with `dots-notifier quarantine approve`
+    pub quarantine_unknown_senders: bool,
+    /// Command lines whose output broadcasts may ask to append to their body, e.g. `df -h /`
+    pub body_commands: Vec<String>,
+    /// Line about this host appended to the body of broadcasts
+    pub host_context: HostContextConfig,
+    /// Application name, icon, desktop entry and timeout of delivered notifications
+    pub notification: NotificationDefaults,
+    /// Application identity of broadcasts posted to a channel, keyed by channel, overriding
+    /// `notification` so a channel looks the same whichever script posts to it
+    pub channels: HashMap<String, NotificationDefaults>,
+    /// Channels broadcasts naming no channel are posted to by their category, keyed by category or by
+    /// a class of them such as `monitoring.*`. `{fingerprint}` in a channel stands for the fingerprint
+    /// of the broadcast, or its title without one, so each alert gets a channel of its own.
+    pub category_channels: HashMap<String, String>,
+    /// Callers allowed to send broadcasts, by systemd unit, username or `uid:<uid>`; everyone if
+    /// this, `allowed_uids` and `allowed_groups` are empty. Root and the user running the server
+    /// are always allowed.
+    pub allowed_senders: Vec<String>,
+    /// Uids of callers allowed to send broadcasts, besides those in `allowed_senders`
+    pub allowed_uids: Vec<u32>,
+    /// Groups whose members are allowed to send broadcasts, besides those in `allowed_senders`
+    pub allowed_groups: Vec<String>,
+    /// Whether callers of the D-Bus methods sending broadcasts must be authorized by polkit
+    /// for `me.section.notifier.send`; `off` trusts everyone the `allowed_*` lists let through
+    pub polkit: PolkitMode,
+    /// How deliveries failing in a retryable way are retried
+    pub retry: RetryPolicy,
+    /// Rendering profiles keyed by notification daemon (`gnome`, `kde`, `mako`, `dunst`
+    /// or the lowercased server name), overriding the built-in ones
+    pub profiles: Arc<RenderingProfiles>,
+}
+
+impl Config {
+    /// Load the configuration from a file, falling back to defaults if it does not exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml_str(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read config file {}: {}", path.display(), e).into()),
+        }
+    }
+
+    /// Parse the configuration from a TOML string
+    pub fn from_toml_str(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Get the backends tried in order to reach the users' notification daemons
+    ///
+    /// The `helper` strategy falls back to direct delivery when the helper cannot be run.
+    pub fn delivery_backends(&self) -> Vec<DeliveryBackend> {
+        match (self.delivery_backends.is_empty(), self.delivery_strategy) {
+            (false, _) => self.delivery_backends.clone(),
+            (true, DeliveryStrategy::Direct) => vec![DeliveryBackend::Direct],
+            (true, DeliveryStrategy::Helper) => vec![DeliveryBackend::Helper, DeliveryBackend::Direct],
+        }
+    }
+
+    /// Get the delivery window configured for a user, if any
+    pub fn delivery_window(&self, username: &str) -> Option<&DeliveryWindow> {
+        self.delivery_windows.get(username)
+    }
+
+    /// Get the channel a broadcast is posted to: the one it names, or the one mapped to its category
+    ///
+    /// A category is looked up as is, then by ever broader classes, so `monitoring.disk.full`
+    /// falls back to `monitoring.disk.*` and `monitoring.*`.
+    pub fn broadcast_channel(&self, options: &SendOptions, title: &str) -> Option<String> {
+        if options.channel.is_some() {
+            return options.channel.clone();
+        }
+        let category = options.category.as_deref()?;
+        let mut channel = self.category_channels.get(category);
+        let mut class = category;
+        while let (None, Some((parent, _))) = (channel, class.rsplit_once('.')) {
+            channel = self.category_channels.get(&format!("{}.*", parent));
+            class = parent;
+        }
+        let fingerprint = options.fingerprint.as_deref().unwrap_or(title);
+        Some(channel?.replace("{fingerprint}", fingerprint))
+    }
+
+    /// Get the defaults of notifications delivered for a broadcast, posted to a channel or not
+    pub fn notification_defaults(&self, channel: Option<&str>) -> NotificationDefaults {
+        match channel.and_then(|channel| self.channels.get(channel)) {
+            Some(identity) => self.notification.merged(identity),
+            None => self.notification.clone(),
+        }
+    }
+
+    /// Get the bus name notifications are delivered to for a desktop environment
+    pub fn notification_bus_name(&self, desktop: Option<&str>) -> &str {
+        desktop
+            .and_then(|desktop| self.notification_services.get(desktop))
+            .map_or(NOTIFICATIONS_BUS_NAME, String::as_str)
+    }
+
+    /// Get how long user and group lookups are cached
+    pub fn nss_cache_ttl(&self) -> Duration {
+        self.nss_cache_ttl_secs
+            .map_or(DEFAULT_NSS_CACHE_TTL, Duration::from_secs)
+    }
+
+    /// Get how long an enumerated session list is reused
+    pub fn session_cache_ttl(&self) -> Duration {
+        self.session_cache_ttl_secs
+            .map_or(DEFAULT_SESSION_CACHE_TTL, Duration::from_secs)
+    }
+
+    /// Get how long an unused connection to a session bus is kept
+    pub fn session_bus_idle_timeout(&self) -> Duration {
+        self.session_bus_idle_secs
+            .map_or(DEFAULT_SESSION_BUS_IDLE_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Get the delivery latency above which a user's notification daemon counts as slow
+    pub fn slow_delivery_threshold(&self) -> Duration {
+        self.slow_delivery_threshold_ms
+            .map_or(DEFAULT_SLOW_DELIVERY_THRESHOLD, Duration::from_millis)
+    }
+
+    /// Get the longest a single delivery attempt to a user may take, if limited
+    pub fn user_delivery_timeout(&self) -> Option<Duration> {
+        match self.user_delivery_timeout_ms {
+            Some(0) => None,
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => Some(DEFAULT_USER_DELIVERY_TIMEOUT),
+        }
+    }
+
+    /// Get the most deliveries running at the same time, at least one
+    pub fn max_concurrent_deliveries(&self) -> usize {
+        self.max_concurrent_deliveries
+            .map_or(DEFAULT_MAX_CONCURRENT_DELIVERIES, |limit| limit.max(1))
+    }
+
+    /// Get the maximum random delay of scheduled fire times
+    pub fn fire_jitter(&self) -> Duration {
+        Duration::from_secs(self.fire_jitter_secs)
+    }
+
+    /// Get how long a repeated broadcast updates the earlier one instead of being shown again
+    pub fn dedup_window(&self) -> Duration {
+        Duration::from_secs(self.dedup_window_secs)
+    }
+
+    /// Get how long suppressed notifications are counted before the user is told about them
+    pub fn suppression_summary_period(&self) -> Duration {
+        Duration::from_secs(self.suppression_summary_secs)
+    }
+
+    /// Get how long users who log in after a broadcast still receive it, if they do at all
+    pub fn late_join_grace(&self) -> Option<Duration> {
+        self.late_join_grace_secs.map(Duration::from_secs)
+    }
+
+    /// Get how long broadcasts are kept for users they are meant for who have not logged in yet
+    pub fn absent_queue_ttl(&self) -> Duration {
+        self.absent_queue_ttl_secs.map_or(DEFAULT_ABSENT_QUEUE_TTL, Duration::from_secs)
+    }
+
+    /// Get who may send broadcasts
+    pub fn sender_acl(&self) -> SenderAcl<'_> {
+        SenderAcl {
+            senders: &self.allowed_senders,
+            uids: &self.allowed_uids,
+            groups: &self.allowed_groups,
+        }
+    }
+
+    /// Get the directory searched for additional Fluent catalogs
+    pub fn locales_dir(&self) -> &Path {
+        self.locales_dir
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_LOCALES_DIR))
+    }
+
+    /// Get the directory holding state persisted across restarts
+    pub fn state_dir(&self) -> &Path {
+        self.state_dir
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_STATE_DIR))
+    }
+
+    /// Get the directory holding the hooks broadcasts may ask to run
+    pub fn hooks_dir(&self) -> &Path {
+        self.hooks_dir
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_HOOKS_DIR))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::DeliveryErrorKind;
+
+    #[test]
+    fn test_empty_config() {
+        let config = Config::from_toml_str("").unwrap();
+        assert_eq!(config, Config::default());
+        assert!(config.delivery_window("alice").is_none());
+        assert_eq!(config.locales_dir(), Path::new(DEFAULT_LOCALES_DIR));
+        assert_eq!(config.nss_cache_ttl(), DEFAULT_NSS_CACHE_TTL);
+        assert_eq!(config.session_cache_ttl(), DEFAULT_SESSION_CACHE_TTL);
+        assert_eq!(config.state_dir(), Path::new(DEFAULT_STATE_DIR));
+        assert_eq!(config.hooks_dir(), Path::new(DEFAULT_HOOKS_DIR));
+        assert_eq!(config.slow_delivery_threshold(), DEFAULT_SLOW_DELIVERY_THRESHOLD);
+    }
+
+    #[test]
+    fn test_cache_ttls() {
+        let config = Config::from_toml_str("nss_cache_ttl_secs = 30\nsession_cache_ttl_secs = 2").unwrap();
+        assert_eq!(config.nss_cache_ttl(), Duration::from_secs(30));
+        assert_eq!(config.session_cache_ttl(), Duration::from_secs(2));
+        assert_eq!(Config::default().session_bus_idle_timeout(), DEFAULT_SESSION_BUS_IDLE_TIMEOUT);
+
+        let config = Config::from_toml_str("session_bus_idle_secs = 0").unwrap();
+        assert_eq!(config.session_bus_idle_timeout(), Duration::ZERO);
+
+        let config = Config::from_toml_str("slow_delivery_threshold_ms = 500").unwrap();
+        assert_eq!(config.slow_delivery_threshold(), Duration::from_millis(500));
+
+        assert_eq!(Config::default().user_delivery_timeout(), Some(DEFAULT_USER_DELIVERY_TIMEOUT));
+        let config = Config::from_toml_str("user_delivery_timeout_ms = 2000").unwrap();
+        assert_eq!(config.user_delivery_timeout(), Some(Duration::from_secs(2)));
+        let config = Config::from_toml_str("user_delivery_timeout_ms = 0").unwrap();
+        assert_eq!(config.user_delivery_timeout(), None);
+
+        assert_eq!(Config::default().max_concurrent_deliveries(), DEFAULT_MAX_CONCURRENT_DELIVERIES);
+        let config = Config::from_toml_str("max_concurrent_deliveries = 4").unwrap();
+        assert_eq!(config.max_concurrent_deliveries(), 4);
+        let config = Config::from_toml_str("max_concurrent_deliveries = 0").unwrap();
+        assert_eq!(config.max_concurrent_deliveries(), 1);
+
+        let config = Config::from_toml_str("fire_jitter_secs = 120").unwrap();
+        assert_eq!(config.fire_jitter(), Duration::from_secs(120));
+        assert_eq!(Config::default().fire_jitter(), Duration::ZERO);
+
+        let config = Config::from_toml_str("dedup_window_secs = 300").unwrap();
+        assert_eq!(config.dedup_window(), Duration::from_secs(300));
+        assert_eq!(Config::default().dedup_window(), Duration::ZERO);
+
+        let config = Config::from_toml_str("suppression_summary_secs = 300").unwrap();
+        assert_eq!(config.suppression_summary_period(), Duration::from_secs(300));
+        assert_eq!(Config::default().suppression_summary_period(), Duration::ZERO);
+
+        let config = Config::from_toml_str("late_join_grace_secs = 60").unwrap();
+        assert_eq!(config.late_join_grace(), Some(Duration::from_secs(60)));
+        assert_eq!(Config::default().late_join_grace(), None);
+
+        let config = Config::from_toml_str("absent_queue_ttl_secs = 3600").unwrap();
+        assert_eq!(config.absent_queue_ttl(), Duration::from_secs(3600));
+        assert_eq!(Config::default().absent_queue_ttl(), DEFAULT_ABSENT_QUEUE_TTL);
+    }
+
+    #[test]
+    fn test_limits_section() {
+        let config = Config::from_toml_str(
+            r#"
+            [limits]
+            max_payload_bytes = 4096
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.limits.max_payload_bytes(), 4096);
+        assert_eq!(config.limits.max_pending_bytes(), crate::limits::DEFAULT_MAX_PENDING_BYTES);
+    }
+
+    #[test]
+    fn test_language_overrides() {
+        let config = Config::from_toml_str("[language_overrides]
+kiosk = \"de_DE.UTF-8\"\n").unwrap();
+        assert_eq!(config.language_overrides.get("kiosk").map(String::as_str), Some("de_DE.UTF-8"));
+        assert!(Config::default().language_overrides.is_empty());
+    }
+
+    #[test]
+    fn test_notification_services() {
+        let config = Config::from_toml_str(
+            r#"
+            [notification_services]
+            nested = "org.example.NotificationProxy"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.notification_bus_name(Some("nested")), "org.example.NotificationProxy");
+        assert_eq!(config.notification_bus_name(Some("gnome")), NOTIFICATIONS_BUS_NAME);
+        assert_eq!(config.notification_bus_name(None), NOTIFICATIONS_BUS_NAME);
+        assert!(!config.terminal_fallback);
+    }
+
+    #[test]
+    fn test_profiles_section() {
+        let config = Config::from_toml_str("[profiles.dunst]\nmarkup = false\nmax_body_length = 120\n").unwrap();
+        let dunst = config.profiles.for_server("dunst").unwrap();
+        assert_eq!(dunst.markup, Some(false));
+        assert_eq!(dunst.max_body_length, Some(120));
+        assert!(Config::default().profiles.for_server("gnome-shell").is_some());
+    }
+
+    #[test]
+    fn test_delivery_strategy() {
+        assert_eq!(Config::default().delivery_strategy, DeliveryStrategy::Direct);
+        let config = Config::from_toml_str("delivery_strategy = \"helper\"").unwrap();
+        assert_eq!(config.delivery_strategy, DeliveryStrategy::Helper);
+        assert!(Config::from_toml_str("delivery_strategy = \"carrier-pigeon\"").is_err());
+    }
+
+    #[test]
+    fn test_nested_bus_policy() {
+        assert_eq!(Config::default().nested_buses, NestedBusPolicy::Primary);
+        let config = Config::from_toml_str("nested_buses = \"all\"").unwrap();
+        assert_eq!(config.nested_buses, NestedBusPolicy::All);
+        assert!(Config::from_toml_str("nested_buses = \"some\"").is_err());
+    }
+
+    #[test]
+    fn test_polkit_mode() {
+        assert_eq!(Config::default().polkit, PolkitMode::Enforce);
+        let config = Config::from_toml_str("polkit = \"off\"").unwrap();
+        assert_eq!(config.polkit, PolkitMode::Off);
+        assert!(Config::from_toml_str("polkit = false").is_err());
+    }
+
+    #[test]
+    fn test_delivery_backends() {
+        assert_eq!(Config::default().delivery_backends(), vec![DeliveryBackend::Direct]);
+        let config = Config::from_toml_str("delivery_strategy = \"helper\"").unwrap();
+        assert_eq!(config.delivery_backends(), vec![DeliveryBackend::Helper, DeliveryBackend::Direct]);
+        let config = Config::from_toml_str(
+            "delivery_strategy = \"helper\"\ndelivery_backends = [\"direct\", \"machinectl\", \"sudo\"]",
+        )
+        .unwrap();
+        let backends = vec![DeliveryBackend::Direct, DeliveryBackend::Machinectl, DeliveryBackend::Sudo];
+        assert_eq!(config.delivery_backends(), backends);
+        assert!(Config::from_toml_str("delivery_backends = [\"ssh\"]").is_err());
+    }
+
+    #[test]
+    fn test_notification_defaults_section() {
+        let config = Config::from_toml_str(
+            r#"
+            allowed_senders = ["backup.service", "uid:1000"]
+            allowed_uids = [1001]
+            allowed_groups = ["ops"]
+
+            [notification]
+            app_name = "IT Department"
+            icon = "dialog-warning"
+            timeout_ms = 10000
+
+            [retry]
+            retries = 2
+            delay_ms = 500
+            max_delay_ms = 4000
+            jitter_percent = 20
+            retry_on = ["no-session-bus", "timeout"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.notification.app_name.as_deref(), Some("IT Department"));
+        assert_eq!(config.notification.icon(), "dialog-warning");
+        assert_eq!(config.notification.timeout(), 10000);
+        assert_eq!(config.allowed_senders, vec!["backup.service".to_string(), "uid:1000".to_string()]);
+        assert_eq!(config.sender_acl().uids, &[1001]);
+        assert_eq!(config.sender_acl().groups, &["ops".to_string()]);
+        assert_eq!(config.retry.retries, 2);
+        assert_eq!(config.retry.delay(), Duration::from_millis(500));
+        assert_eq!(config.retry.backoff_delay(1), Duration::from_secs(1));
+        assert_eq!(config.retry.max_delay(), Duration::from_secs(4));
+        assert!(!config.retry.retries_on(DeliveryErrorKind::Other));
+        assert!(Config::from_toml_str("[notification]\ncolor = \"red\"\n").is_err());
+    }
+
+    #[test]
+    fn test_channel_identities() {
+        let config = Config::from_toml_str(
+            r#"
+            [notification]
+            app_name = "IT Department"
+            timeout_ms = 10000
+
+            [channels.backups]
+            app_name = "Backup Service"
+            icon = "drive-harddisk"
+            desktop_entry = "backup-service"
+            "#,
+        )
+        .unwrap();
+        let backups = config.notification_defaults(Some("backups"));
+        assert_eq!(backups.app_name.as_deref(), Some("Backup Service"));
+        assert_eq!(backups.icon(), "drive-harddisk");
+        assert_eq!(backups.desktop_entry.as_deref(), Some("backup-service"));
+        assert_eq!(backups.timeout(), 10000);
+        assert_eq!(config.notification_defaults(Some("status")), config.notification);
+        assert_eq!(config.notification_defaults(None), config.notification);
+        assert!(Config::from_toml_str("[channels.backups]\ncolor = \"red\"\n").is_err());
+    }
+
+    #[test]
+    fn test_store_backend() {
+        assert_eq!(Config::default().store, StoreBackend::Memory);
+        let config = Config::from_toml_str("store = \"sqlite\"").unwrap();
+        assert_eq!(config.store, StoreBackend::Sqlite);
+        let config = Config::from_toml_str("store = \"file\"").unwrap();
+        assert_eq!(config.store, StoreBackend::File);
+        assert!(Config::from_toml_str("store = \"postgres\"").is_err());
+        assert!(!Config::default().journal);
+        assert!(Config::from_toml_str("journal = true").unwrap().journal);
+    }
+
+    #[test]
+    fn test_body_commands() {
+        assert!(Config::default().body_commands.is_empty());
+        let config = Config::from_toml_str("body_commands = [\"df -h /\", \"uptime\"]").unwrap();
+        assert_eq!(config.body_commands, vec!["df -h /".to_string(), "uptime".to_string()]);
+    }
+
+    #[test]
+    fn test_host_context_section() {
+        assert_eq!(Config::default().host_context.template(), None);
+        let config = Config::from_toml_str("[host_context]\nenabled = true\ntemplate = \"from {hostname}\"\n").unwrap();
+        assert_eq!(config.host_context.template(), Some("from {hostname}"));
+        assert!(Config::from_toml_str("[host_context]\nhostname = true\n").is_err());
+    }
+
+    #[test]
+    fn test_locales_dir_override() {
+        let config = Config::from_toml_str("locales_dir = \"/opt/notifier/locales\"").unwrap();
+        assert_eq!(config.locales_dir(), Path::new("/opt/notifier/locales"));
+    }
+
+    #[test]
+    fn test_delivery_windows() {
+        let config = Config::from_toml_str(
+            r#"
+            [delivery_windows]
+            nightshift = "20:00-06:00"
+            "#,
+        )
+        .unwrap();
+
+        let window = config.delivery_window("nightshift").unwrap();
+        assert_eq!(window.to_string(), "20:00-06:00");
+        assert!(config.delivery_window("dayshift").is_none());
+    }
+
+    #[test]
+    fn test_sound_section() {
+        let config = Config::from_toml_str(
+            r#"
+            [sound]
+            mute = true
+            default = "message-new-instant"
+            "#,
+        )
+        .unwrap();
+        assert!(config.sound.mute);
+        assert_eq!(config.sound.default.as_deref(), Some("message-new-instant"));
+    }
+
+    #[test]
+    fn test_category_channels() {
+        let config = Config::from_toml_str(
+            r#"
+            [category_channels]
+            "monitoring.*" = "alert-{fingerprint}"
+            "monitoring.backup" = "backups"
+            "#,
+        )
+        .unwrap();
+        let options = |category: &str, fingerprint: Option<&str>| SendOptions {
+            category: Some(category.to_string()),
+            fingerprint: fingerprint.map(str::to_string),
+            ..SendOptions::default()
+        };
+        let channel = |options: SendOptions| config.broadcast_channel(&options, "Disk full");
+
+        assert_eq!(channel(options("monitoring.disk.full", Some("a1b2"))).as_deref(), Some("alert-a1b2"));
+        assert_eq!(channel(options("monitoring.disk", None)).as_deref(), Some("alert-Disk full"));
+        assert_eq!(channel(options("monitoring.backup", Some("a1b2"))).as_deref(), Some("backups"));
+        assert_eq!(channel(options("network", None)), None);
+        let named = SendOptions { channel: Some("status".to_string()), ..options("monitoring.disk", None) };
+        assert_eq!(channel(named).as_deref(), Some("status"));
+    }
+
+    #[test]
+    fn test_urgency_rules() {
+        let config = Config::from_toml_str(
+            r#"
+            [[urgency_rules]]
+            keywords = ["FAILED", "disk full"]
+            urgency = "critical"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.urgency_rules.len(), 1);
+        assert!(config.urgency_rules[0].matches("backup failed", ""));
+    }
+
+    #[test]
+    fn test_invalid_delivery_window() {
+        let result = Config::from_toml_str(
+            r#"
+            [delivery_windows]
+            nightshift = "late"
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        assert!(Config::from_toml_str("bogus = true").is_err());
+    }
+
+    #[test]
+    fn test_load_missing_file_uses_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::load(dir.path().join("missing.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_load_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[delivery_windows]\nalice = \"09:00-17:00\"\n").unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert!(config.delivery_window("alice").is_some());
+    }
+}
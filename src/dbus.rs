@@ -1,7 +1,7 @@
 //! D-Bus interface definitions and proxy traits
 
 use std::collections::HashMap;
-use zbus::{zvariant::{OwnedObjectPath, Value}, Result as ZbusResult};
+use zbus::{zvariant::{OwnedFd, OwnedObjectPath, OwnedValue, Value}, Result as ZbusResult};
 
 /// D-Bus interface name for the notifier service
 pub const DBUS_INTERFACE_NAME: &str = "me.section.Notifier";
@@ -9,6 +9,9 @@ pub const DBUS_INTERFACE_NAME: &str = "me.section.Notifier";
 /// D-Bus path for the notifier service
 pub const DBUS_PATH: &str = "/me/section/Notifier";
 
+/// Well-known bus name of the desktop notification daemon
+pub const NOTIFICATIONS_BUS_NAME: &str = "org.freedesktop.Notifications";
+
 /// Proxy trait for systemd login manager
 #[zbus::proxy(
     interface = "org.freedesktop.login1.Manager",
@@ -18,11 +21,62 @@ pub const DBUS_PATH: &str = "/me/section/Notifier";
 pub trait LoginManager {
     #[zbus(name = "ListSessions")]
     fn list_sessions(&self) -> ZbusResult<Vec<SessionInfo>>;
+
+    /// Get the object path of a session by its id
+    fn get_session(&self, session_id: &str) -> ZbusResult<OwnedObjectPath>;
+
+    /// Take an inhibitor lock, held until the returned file descriptor is closed
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> ZbusResult<OwnedFd>;
+
+    /// Emitted when a session is created
+    #[zbus(signal)]
+    fn session_new(&self, session_id: String, object_path: OwnedObjectPath) -> ZbusResult<()>;
+
+    /// Emitted when a session ends
+    #[zbus(signal)]
+    fn session_removed(&self, session_id: String, object_path: OwnedObjectPath) -> ZbusResult<()>;
 }
 
 /// Type alias for session information returned by LoginManager
 pub type SessionInfo = (String, u32, String, String, OwnedObjectPath);
 
+/// Id, channel (empty if none), tags and title of a tracked broadcast, as listed by `ListBroadcasts`
+pub type BroadcastSummary = (u64, String, Vec<String>, String);
+
+/// Uid, username, routing decision and notification service of a recipient, as listed by `RouteTest`
+pub type RouteSummary = (u32, String, String, String);
+
+/// Uid, username, delivery status and error message (empty if none) of each recipient of a broadcast,
+/// as returned by `SendToAll` and `GetDeliveryResults`
+pub type DeliverySummary = (u32, String, String, String);
+
+/// Uid, username, time delivery is deferred until (RFC 3339, empty if unknown) and reason,
+/// for each recipient of a broadcast that was spooled, as returned by `SendWithDeferrals`
+pub type DeferralSummary = (u32, String, String, String);
+
+/// Id, time to send at (seconds since the Unix epoch), channel (empty if none) and title of a
+/// scheduled broadcast, as returned by `ListScheduled`
+pub type ScheduledSummary = (u64, u64, String, String);
+
+/// Id, rule, next time to send at (seconds since the Unix epoch), channel (empty if none) and
+/// title of a recurring broadcast, as returned by `ListRecurring`
+pub type RecurringSummary = (u64, String, u64, String, String);
+
+/// Uid, username, number of helper runs, failed runs and average runtime in milliseconds of a user,
+/// as returned by `GetHelperStats`
+pub type HelperUserSummary = (u32, String, u64, u64, u64);
+
+/// Number of failed helper runs by reason, and the runs of each user, as returned by `GetHelperStats`
+pub type HelperStatsSummary = (Vec<(String, u64)>, Vec<HelperUserSummary>);
+
+/// Quarantine id, time of arrival (seconds since the Unix epoch), input, sender, title and body
+/// of a broadcast awaiting approval, as listed by `ListQuarantined`
+pub type QuarantineSummary = (u64, u64, String, String, String, String);
+
+/// Time (seconds since the Unix epoch), broadcast id, sender (empty if unknown), title hash and the uid,
+/// username and delivery status of each recipient of a dispatch, as returned by `GetHistory`
+pub type HistoryEntry = (u64, u64, String, String, Vec<(u32, String, String)>);
+
 /// Proxy trait for systemd login session
 #[zbus::proxy(
     interface = "org.freedesktop.login1.Session",
@@ -37,6 +91,35 @@ pub trait Session {
 
     #[zbus(property)]
     fn user(&self) -> ZbusResult<(u32, OwnedObjectPath)>;
+
+    #[zbus(property)]
+    fn desktop(&self) -> ZbusResult<String>;
+
+    #[zbus(property)]
+    fn name(&self) -> ZbusResult<String>;
+
+    /// Id and object path of the seat the session is on; the id is empty for sessions without one
+    #[zbus(property)]
+    fn seat(&self) -> ZbusResult<(String, OwnedObjectPath)>;
+}
+
+/// Proxy trait for the polkit authority
+#[zbus::proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+pub trait PolicyKitAuthority {
+    /// Check whether a subject is authorized for an action, returning whether it is,
+    /// whether it could be after authenticating, and details of the decision
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: &HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> ZbusResult<(bool, bool, HashMap<String, String>)>;
 }
 
 /// Proxy trait for freedesktop notifications
@@ -59,6 +142,23 @@ pub trait Notifications {
         hints: &HashMap<&str, Value<'_>>,
         expire_timeout: i32,
     ) -> ZbusResult<u32>;
+
+    /// Close a notification previously sent
+    fn close_notification(&self, id: u32) -> ZbusResult<()>;
+
+    /// Get the name, vendor, version and spec version of the notification server
+    fn get_server_information(&self) -> ZbusResult<(String, String, String, String)>;
+
+    /// Get the optional features the notification server supports
+    fn get_capabilities(&self) -> ZbusResult<Vec<String>>;
+
+    /// Emitted when the user invokes an action of a notification
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> ZbusResult<()>;
+
+    /// Emitted when a notification is closed
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> ZbusResult<()>;
 }
 
 /// Proxy trait for the notifier client
@@ -68,7 +168,165 @@ pub trait Notifications {
     default_path = "/me/section/Notifier"
 )]
 pub trait Notifier {
-    async fn send_to_all(&self, title: &str, body: &str) -> ZbusResult<()>;
+    async fn send_to_all(&self, title: &str, body: &str) -> ZbusResult<(u64, Vec<DeliverySummary>)>;
+
+    async fn send_to_user(&self, user: &str, title: &str, body: &str) -> ZbusResult<u64>;
+
+    async fn send_batch(&self, notifications: &[(&str, &str)]) -> ZbusResult<Vec<u64>>;
+
+    async fn send_to_group(&self, group: &str, title: &str, body: &str) -> ZbusResult<u64>;
+
+    async fn send_to_channel(&self, channel: &str, title: &str, body: &str) -> ZbusResult<u64>;
+
+    async fn close_broadcast(&self, broadcast_id: u64) -> ZbusResult<u32>;
+
+    async fn close_channel(&self, channel: &str) -> ZbusResult<u32>;
+
+    async fn resolve(&self, title: &str, options: HashMap<&str, Value<'_>>) -> ZbusResult<u32>;
+
+    async fn update_broadcast(&self, broadcast_id: u64, title: &str, body: &str) -> ZbusResult<u32>;
+
+    async fn update_channel(&self, channel: &str, title: &str, body: &str) -> ZbusResult<u32>;
+
+    async fn flush(&self) -> ZbusResult<u32>;
+
+    async fn ping(&self) -> ZbusResult<(String, u64)>;
+
+    async fn get_status(&self) -> ZbusResult<HashMap<String, OwnedValue>>;
+
+    async fn get_stats(&self) -> ZbusResult<HashMap<String, OwnedValue>>;
+
+    async fn get_metrics(&self) -> ZbusResult<String>;
+
+    async fn get_helper_stats(&self) -> ZbusResult<HelperStatsSummary>;
+
+    async fn get_delivery_report(&self, broadcast_id: u64) -> ZbusResult<Vec<(u32, String, String)>>;
+
+    async fn get_delivery_results(&self, broadcast_id: u64) -> ZbusResult<Vec<DeliverySummary>>;
+
+    async fn get_lint_warnings(&self, broadcast_id: u64) -> ZbusResult<Vec<String>>;
+
+    async fn send_tagged(&self, channel: &str, tags: &[String], title: &str, body: &str) -> ZbusResult<u64>;
+
+    async fn close_tag(&self, tag: &str) -> ZbusResult<u32>;
+
+    async fn close_batch(&self, batch_id: &str) -> ZbusResult<u32>;
+
+    async fn start_progress(&self, title: &str, text: &str) -> ZbusResult<String>;
+
+    async fn update_progress(&self, batch_id: &str, percent: u32, text: &str) -> ZbusResult<u32>;
+
+    async fn close_all(&self, older_than_secs: u64, channel: &str) -> ZbusResult<u32>;
+
+    async fn list_broadcasts(&self, tag: &str) -> ZbusResult<Vec<BroadcastSummary>>;
+
+    async fn get_history(&self, limit: u32) -> ZbusResult<Vec<HistoryEntry>>;
+
+    async fn replay_broadcast(&self, broadcast_id: u64) -> ZbusResult<u32>;
+
+    async fn replay_tag(&self, tag: &str) -> ZbusResult<u32>;
+
+    async fn re_announce(&self) -> ZbusResult<u32>;
+
+    async fn session_ready(&self, user: &str) -> ZbusResult<u32>;
+
+    async fn list_quarantined(&self) -> ZbusResult<Vec<QuarantineSummary>>;
+
+    async fn approve_quarantined(&self, quarantine_id: u64) -> ZbusResult<u64>;
+
+    async fn deny_quarantined(&self, quarantine_id: u64) -> ZbusResult<()>;
+
+    async fn route_test(
+        &self,
+        title: &str,
+        body: &str,
+        urgency: &str,
+        user: &str,
+    ) -> ZbusResult<(String, Vec<RouteSummary>)>;
+
+    async fn list_active_users(&self, options: HashMap<&str, Value<'_>>) -> ZbusResult<Vec<(u32, String)>>;
+
+    async fn export_state(&self) -> ZbusResult<String>;
+
+    async fn import_state(&self, archive: &str) -> ZbusResult<u32>;
+
+    async fn set_maintenance(&self, enabled: bool) -> ZbusResult<()>;
+
+    async fn send_with_options(&self, title: &str, body: &str, options: HashMap<&str, Value<'_>>) -> ZbusResult<u64>;
+
+    #[zbus(property)]
+    fn maintenance(&self) -> ZbusResult<bool>;
+
+    async fn pause_delivery(&self) -> ZbusResult<()>;
+
+    async fn resume_delivery(&self) -> ZbusResult<()>;
+
+    #[zbus(property)]
+    fn paused(&self) -> ZbusResult<bool>;
+
+    async fn send_poll(&self, title: &str, body: &str, options: &[String]) -> ZbusResult<u64>;
+
+    #[allow(clippy::type_complexity)]
+    async fn get_poll_results(&self, broadcast_id: u64)
+        -> ZbusResult<(Vec<(String, u32)>, Vec<(u32, String, String)>)>;
+
+    async fn inspect_session(&self, session_id: &str) -> ZbusResult<HashMap<String, OwnedValue>>;
+
+    async fn get_user_capabilities(&self, uid: u32) -> ZbusResult<Vec<String>>;
+
+    async fn send_with_deferrals(
+        &self,
+        title: &str,
+        body: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> ZbusResult<(u64, Vec<DeferralSummary>)>;
+
+    async fn schedule(&self, at: u64, title: &str, body: &str, options: HashMap<&str, Value<'_>>) -> ZbusResult<u64>;
+
+    async fn list_scheduled(&self) -> ZbusResult<Vec<ScheduledSummary>>;
+
+    async fn cancel_scheduled(&self, schedule_id: u64) -> ZbusResult<()>;
+
+    async fn schedule_recurring(
+        &self,
+        rule: &str,
+        title: &str,
+        body: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> ZbusResult<u64>;
+
+    async fn list_recurring(&self) -> ZbusResult<Vec<RecurringSummary>>;
+
+    async fn remove_recurring(&self, schedule_id: u64) -> ZbusResult<()>;
+
+    /// Emitted when a broadcast is about to be delivered to its recipients
+    #[zbus(signal)]
+    fn broadcast_started(&self, broadcast_id: u64, recipients: u32) -> ZbusResult<()>;
+
+    /// Emitted when a broadcast was shown to a user
+    #[zbus(signal)]
+    fn user_delivered(&self, broadcast_id: u64, uid: u32, username: String, status: String) -> ZbusResult<()>;
+
+    /// Emitted when a broadcast could not be delivered to a user
+    #[zbus(signal)]
+    fn user_failed(&self, broadcast_id: u64, uid: u32, username: String, status: String) -> ZbusResult<()>;
+
+    /// Emitted when a user invokes an action of a broadcast
+    #[zbus(signal)]
+    fn broadcast_action_invoked(&self, broadcast_id: u64, uid: u32, username: String, action: String) -> ZbusResult<()>;
+
+    /// Emitted when a user invokes an action of a notification
+    #[zbus(signal)]
+    fn notification_action_invoked(&self, uid: u32, action_key: String) -> ZbusResult<()>;
+
+    /// Emitted once a broadcast was delivered to, failed for or spooled for each recipient
+    #[zbus(signal)]
+    fn completed(&self, broadcast_id: u64, delivered: u32, failed: u32, deferred: u32) -> ZbusResult<()>;
+
+    /// Emitted for each recipient a broadcast is spooled for instead of delivered right away
+    #[zbus(signal)]
+    fn broadcast_deferred(&self, broadcast_id: u64, uid: u32, username: String, until: String, reason: String)
+        -> ZbusResult<()>;
 }
 
 /// Helper function to determine if a session type is graphical
@@ -84,6 +342,7 @@ mod tests {
     fn test_constants() {
         assert_eq!(DBUS_INTERFACE_NAME, "me.section.Notifier");
         assert_eq!(DBUS_PATH, "/me/section/Notifier");
+        assert_eq!(NOTIFICATIONS_BUS_NAME, "org.freedesktop.Notifications");
     }
 
     #[test]
@@ -0,0 +1,511 @@
+//! Classification of per-user delivery failures
+//!
+//! Failures are sorted into a small set of kinds so the server can report them
+//! meaningfully and decide whether retrying makes sense. Each kind also has a
+//! stable name and process exit code, so a delivery helper running in the
+//! user's context can report the same taxonomy across the process boundary.
+
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use zbus::DBusError;
+
+use crate::error::NotifierError;
+use crate::mac;
+
+/// Delay before the first retry of a failed delivery unless configured otherwise
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Factor the delay grows by with each retry unless configured otherwise
+pub const DEFAULT_RETRY_BACKOFF: u32 = 2;
+
+/// Longest delay between retries unless configured otherwise
+pub const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Longest a single delivery attempt to a user may take unless configured otherwise
+pub const DEFAULT_USER_DELIVERY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Most deliveries run at the same time unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT_DELIVERIES: usize = 16;
+
+/// How deliveries failing in a retryable way are retried
+///
+/// The delay before each retry grows exponentially from `delay_ms` up to
+/// `max_delay_ms`, and is randomly shortened by up to `jitter_percent` so the
+/// retries of a broadcast to many users do not hit their buses in lockstep.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Number of times a delivery is retried, so it is attempted at most `retries + 1` times; none by default
+    pub retries: u32,
+    /// Delay before the first retry, in milliseconds
+    pub delay_ms: Option<u64>,
+    /// Factor the delay grows by with each retry; 1 keeps it constant
+    pub backoff: Option<u32>,
+    /// Longest delay between retries, in milliseconds
+    pub max_delay_ms: Option<u64>,
+    /// Share of each delay, in percent, that is randomly cut from it
+    pub jitter_percent: u8,
+    /// Kinds of failure that are retried; those that might succeed later by default
+    pub retry_on: Option<Vec<DeliveryErrorKind>>,
+}
+
+impl RetryPolicy {
+    /// Get the delay before the first retry
+    pub fn delay(&self) -> Duration {
+        self.delay_ms.map_or(DEFAULT_RETRY_DELAY, Duration::from_millis)
+    }
+
+    /// Get the longest delay between retries
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay_ms.map_or(DEFAULT_MAX_RETRY_DELAY, Duration::from_millis)
+    }
+
+    /// Get the delay before retrying a delivery that failed after `attempt` retries, without jitter
+    pub fn backoff_delay(&self, attempt: u32) -> Duration {
+        let factor = self.backoff.unwrap_or(DEFAULT_RETRY_BACKOFF).max(1);
+        let factor = factor.checked_pow(attempt).unwrap_or(u32::MAX);
+        self.delay().saturating_mul(factor).min(self.max_delay().max(self.delay()))
+    }
+
+    /// Get the delay before retrying a delivery that failed after `attempt` retries
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let delay = self.backoff_delay(attempt);
+        let percent = u64::from(self.jitter_percent.min(100));
+        if percent == 0 {
+            return delay;
+        }
+        let max_cut = delay.as_millis().min(u64::MAX as u128) as u64 * percent / 100;
+        let cut = RandomState::new().build_hasher().finish() % (max_cut + 1);
+        delay.saturating_sub(Duration::from_millis(cut))
+    }
+
+    /// Whether failures of a kind are retried
+    pub fn retries_on(&self, kind: DeliveryErrorKind) -> bool {
+        match &self.retry_on {
+            Some(kinds) => kinds.contains(&kind),
+            None => kind.is_retryable(),
+        }
+    }
+
+    /// Whether a delivery that failed after `attempt` retries should be retried again
+    pub fn should_retry(&self, attempt: u32, kind: DeliveryErrorKind) -> bool {
+        attempt < self.retries && self.retries_on(kind)
+    }
+}
+
+/// The kind of failure that prevented a notification from being delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeliveryErrorKind {
+    /// The user's session bus could not be reached
+    NoSessionBus,
+    /// Nothing owns `org.freedesktop.Notifications` on the user's session bus
+    DaemonMissing,
+    /// The notification daemon returned an error for the request
+    NotifyRejected,
+    /// The notification daemon did not answer in time
+    Timeout,
+    /// An SELinux or AppArmor policy denied reaching the user's session
+    PolicyDenied,
+    /// The delivery was abandoned after hanging past the per-user timeout,
+    /// such as on a stuck helper process or session bus
+    Stalled,
+    /// Any other failure
+    Other,
+}
+
+impl DeliveryErrorKind {
+    /// Every kind, in exit code order
+    pub const ALL: [DeliveryErrorKind; 7] = [
+        DeliveryErrorKind::Other,
+        DeliveryErrorKind::NoSessionBus,
+        DeliveryErrorKind::DaemonMissing,
+        DeliveryErrorKind::NotifyRejected,
+        DeliveryErrorKind::Timeout,
+        DeliveryErrorKind::PolicyDenied,
+        DeliveryErrorKind::Stalled,
+    ];
+
+    /// Get the stable name of this kind
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryErrorKind::NoSessionBus => "no-session-bus",
+            DeliveryErrorKind::DaemonMissing => "daemon-missing",
+            DeliveryErrorKind::NotifyRejected => "notify-rejected",
+            DeliveryErrorKind::Timeout => "timeout",
+            DeliveryErrorKind::PolicyDenied => "policy-denied",
+            DeliveryErrorKind::Stalled => "stalled",
+            DeliveryErrorKind::Other => "other",
+        }
+    }
+
+    /// Get a human-readable description of this kind for delivery reports
+    pub fn description(self) -> &'static str {
+        match self {
+            DeliveryErrorKind::NoSessionBus => "session bus unreachable",
+            DeliveryErrorKind::DaemonMissing => "no notification daemon",
+            DeliveryErrorKind::NotifyRejected => "rejected by notification daemon",
+            DeliveryErrorKind::Timeout => "notification daemon timed out",
+            DeliveryErrorKind::PolicyDenied => "denied by SELinux or AppArmor policy",
+            DeliveryErrorKind::Stalled => "abandoned after the per-user timeout",
+            DeliveryErrorKind::Other => "delivery error",
+        }
+    }
+
+    /// Get the process exit code reporting this kind
+    pub fn exit_code(self) -> i32 {
+        match self {
+            DeliveryErrorKind::Other => 1,
+            DeliveryErrorKind::NoSessionBus => 10,
+            DeliveryErrorKind::DaemonMissing => 11,
+            DeliveryErrorKind::NotifyRejected => 12,
+            DeliveryErrorKind::Timeout => 13,
+            DeliveryErrorKind::PolicyDenied => 14,
+            DeliveryErrorKind::Stalled => 15,
+        }
+    }
+
+    /// Map a process exit code back to its kind; unknown non-zero codes are `Other`
+    pub fn from_exit_code(code: i32) -> Option<Self> {
+        match code {
+            0 => None,
+            code => Some(
+                Self::ALL
+                    .into_iter()
+                    .find(|kind| kind.exit_code() == code)
+                    .unwrap_or(DeliveryErrorKind::Other),
+            ),
+        }
+    }
+
+    /// Whether delivering again later might succeed
+    ///
+    /// A missing daemon, a rejected request or a policy denial will fail the same way again,
+    /// and a session that stalled one delivery is likely to stall the next.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            DeliveryErrorKind::NoSessionBus | DeliveryErrorKind::Timeout | DeliveryErrorKind::Other
+        )
+    }
+
+    /// Classify an error returned while delivering a notification
+    pub fn classify(error: &(dyn Error + 'static)) -> Self {
+        if let Some(error) = error.downcast_ref::<zbus::Error>() {
+            return Self::classify_zbus(error);
+        }
+        if let Some(error) = error.downcast_ref::<zbus::fdo::Error>() {
+            return Self::classify_error_name(error.name().as_str());
+        }
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return Self::classify_io(error);
+        }
+        if let Some(error) = error.downcast_ref::<DeliveryError>() {
+            return error.kind();
+        }
+        if let Some(error) = error.downcast_ref::<NotifierError>() {
+            return match error {
+                NotifierError::DbusConnect { source, .. } => Self::classify_zbus(source),
+                NotifierError::Delivery(error) => error.kind(),
+                NotifierError::Timeout(_) => DeliveryErrorKind::Timeout,
+                _ => DeliveryErrorKind::Other,
+            };
+        }
+        DeliveryErrorKind::Other
+    }
+
+    fn classify_zbus(error: &zbus::Error) -> Self {
+        match error {
+            zbus::Error::InputOutput(error) => Self::classify_io(error),
+            zbus::Error::Address(_) | zbus::Error::Handshake(_) => DeliveryErrorKind::NoSessionBus,
+            zbus::Error::MethodError(name, _, _) => Self::classify_error_name(name.as_str()),
+            zbus::Error::FDO(error) => Self::classify_error_name(error.name().as_str()),
+            _ => DeliveryErrorKind::Other,
+        }
+    }
+
+    fn classify_io(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::TimedOut => DeliveryErrorKind::Timeout,
+            io::ErrorKind::NotFound
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::PermissionDenied => DeliveryErrorKind::NoSessionBus,
+            _ => DeliveryErrorKind::Other,
+        }
+    }
+
+    fn classify_error_name(name: &str) -> Self {
+        match name {
+            "org.freedesktop.DBus.Error.ServiceUnknown" | "org.freedesktop.DBus.Error.NameHasNoOwner" => {
+                DeliveryErrorKind::DaemonMissing
+            }
+            "org.freedesktop.DBus.Error.NoReply"
+            | "org.freedesktop.DBus.Error.Timeout"
+            | "org.freedesktop.DBus.Error.TimedOut" => DeliveryErrorKind::Timeout,
+            "org.freedesktop.DBus.Error.Disconnected" | "org.freedesktop.DBus.Error.NoServer" => {
+                DeliveryErrorKind::NoSessionBus
+            }
+            _ => DeliveryErrorKind::NotifyRejected,
+        }
+    }
+}
+
+impl FromStr for DeliveryErrorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("Unknown delivery error kind '{}'", s))
+    }
+}
+
+impl fmt::Display for DeliveryErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A classified delivery failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryError {
+    kind: DeliveryErrorKind,
+    message: String,
+}
+
+impl DeliveryError {
+    /// Create a new delivery error
+    pub fn new(kind: DeliveryErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Classify an arbitrary error
+    ///
+    /// Permission errors explained by an access control policy carry a hint on fixing it.
+    pub fn from_error(error: &(dyn Error + 'static)) -> Self {
+        if let Some(NotifierError::Delivery(error)) = error.downcast_ref::<NotifierError>() {
+            return error.clone();
+        }
+        if let Some(hint) = mac::diagnose(error) {
+            return Self::new(DeliveryErrorKind::PolicyDenied, format!("{} ({})", error, hint));
+        }
+        Self::new(DeliveryErrorKind::classify(error), error.to_string())
+    }
+
+    /// Get the kind of failure
+    pub fn kind(&self) -> DeliveryErrorKind {
+        self.kind
+    }
+
+    /// Get the underlying error message
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl Error for DeliveryError {}
+
+/// Outcome of delivering a broadcast to one user, as shown in delivery reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeliveryStatus {
+    /// Shown by the user's notification daemon
+    Delivered,
+    /// Held back until the user's delivery window opens
+    Spooled,
+    /// Written to the user's terminals because the desktop could not be notified
+    TerminalFallback(DeliveryErrorKind),
+    /// Not delivered
+    Failed(DeliveryErrorKind),
+    /// Taken from the spool for delivery when the server stopped, and not
+    /// delivered again after the restart so it cannot be shown twice
+    Interrupted,
+    /// Replaced while spooled by a newer broadcast of the same group
+    Superseded,
+}
+
+impl fmt::Display for DeliveryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeliveryStatus::Delivered => f.write_str("delivered"),
+            DeliveryStatus::Spooled => f.write_str("spooled"),
+            DeliveryStatus::TerminalFallback(kind) => write!(f, "written to terminal ({})", kind.description()),
+            DeliveryStatus::Failed(kind) => write!(f, "failed ({})", kind.description()),
+            DeliveryStatus::Interrupted => f.write_str("interrupted by a server restart"),
+            DeliveryStatus::Superseded => f.write_str("superseded by a newer broadcast of its group"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_kind_names_round_trip() {
+        for kind in DeliveryErrorKind::ALL {
+            assert_eq!(kind.as_str().parse::<DeliveryErrorKind>().unwrap(), kind);
+        }
+        assert!("segfault".parse::<DeliveryErrorKind>().is_err());
+    }
+
+    #[test]
+    fn test_exit_codes_round_trip() {
+        for kind in DeliveryErrorKind::ALL {
+            assert_eq!(DeliveryErrorKind::from_exit_code(kind.exit_code()), Some(kind));
+        }
+        assert_eq!(DeliveryErrorKind::from_exit_code(0), None);
+        assert_eq!(DeliveryErrorKind::from_exit_code(77), Some(DeliveryErrorKind::Other));
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::default();
+        assert!(!policy.should_retry(0, DeliveryErrorKind::Timeout));
+        assert_eq!(policy.delay(), DEFAULT_RETRY_DELAY);
+        let policy = RetryPolicy {
+            retries: 2,
+            delay_ms: Some(50),
+            ..RetryPolicy::default()
+        };
+        assert!(policy.should_retry(1, DeliveryErrorKind::Timeout));
+        assert!(!policy.should_retry(2, DeliveryErrorKind::Timeout));
+        assert!(!policy.should_retry(0, DeliveryErrorKind::DaemonMissing));
+        assert_eq!(policy.delay(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            retries: 10,
+            delay_ms: Some(100),
+            max_delay_ms: Some(1000),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay_for(0), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(1), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(800));
+        assert_eq!(policy.delay_for(4), Duration::from_secs(1));
+        assert_eq!(policy.delay_for(40), Duration::from_secs(1));
+
+        let constant = RetryPolicy { backoff: Some(1), ..policy.clone() };
+        assert_eq!(constant.delay_for(5), Duration::from_millis(100));
+
+        let jittered = RetryPolicy { jitter_percent: 50, ..policy };
+        for attempt in 0..5 {
+            let delay = jittered.delay_for(attempt);
+            assert!(delay <= jittered.backoff_delay(attempt));
+            assert!(delay >= jittered.backoff_delay(attempt) / 2);
+        }
+    }
+
+    #[test]
+    fn test_retry_on() {
+        let policy = RetryPolicy {
+            retries: 1,
+            retry_on: Some(vec![DeliveryErrorKind::NoSessionBus]),
+            ..RetryPolicy::default()
+        };
+        assert!(policy.should_retry(0, DeliveryErrorKind::NoSessionBus));
+        assert!(!policy.should_retry(0, DeliveryErrorKind::Timeout));
+
+        let policy = RetryPolicy { retries: 1, ..RetryPolicy::default() };
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(policy.should_retry(0, DeliveryErrorKind::classify(&reset)));
+        let invalid = zbus::fdo::Error::InvalidArgs("bad hint".to_string());
+        assert!(!policy.should_retry(0, DeliveryErrorKind::classify(&invalid)));
+    }
+
+    #[test]
+    fn test_retryable() {
+        assert!(DeliveryErrorKind::NoSessionBus.is_retryable());
+        assert!(DeliveryErrorKind::Timeout.is_retryable());
+        assert!(!DeliveryErrorKind::DaemonMissing.is_retryable());
+        assert!(!DeliveryErrorKind::NotifyRejected.is_retryable());
+        assert!(!DeliveryErrorKind::PolicyDenied.is_retryable());
+        assert!(!DeliveryErrorKind::Stalled.is_retryable());
+    }
+
+    #[test]
+    fn test_classify_io_errors() {
+        let missing_socket = zbus::Error::InputOutput(Arc::new(io::Error::from(io::ErrorKind::NotFound)));
+        assert_eq!(DeliveryErrorKind::classify(&missing_socket), DeliveryErrorKind::NoSessionBus);
+
+        let timed_out = io::Error::from(io::ErrorKind::TimedOut);
+        assert_eq!(DeliveryErrorKind::classify(&timed_out), DeliveryErrorKind::Timeout);
+    }
+
+    #[test]
+    fn test_classify_fdo_errors() {
+        let unknown = zbus::fdo::Error::ServiceUnknown("org.freedesktop.Notifications".to_string());
+        assert_eq!(DeliveryErrorKind::classify(&unknown), DeliveryErrorKind::DaemonMissing);
+
+        let wrapped = zbus::Error::FDO(Box::new(zbus::fdo::Error::NoReply("no reply".to_string())));
+        assert_eq!(DeliveryErrorKind::classify(&wrapped), DeliveryErrorKind::Timeout);
+
+        let rejected = zbus::fdo::Error::InvalidArgs("bad hint".to_string());
+        assert_eq!(DeliveryErrorKind::classify(&rejected), DeliveryErrorKind::NotifyRejected);
+    }
+
+    #[test]
+    fn test_classify_boxed_errors() {
+        let boxed: Box<dyn Error> = Box::new(zbus::Error::Address("bad address".to_string()));
+        assert_eq!(DeliveryErrorKind::classify(boxed.as_ref()), DeliveryErrorKind::NoSessionBus);
+
+        let boxed: Box<dyn Error> = "something odd".into();
+        assert_eq!(DeliveryErrorKind::classify(boxed.as_ref()), DeliveryErrorKind::Other);
+    }
+
+    #[test]
+    fn test_classify_notifier_errors() {
+        let refused = zbus::Error::InputOutput(Arc::new(io::Error::from(io::ErrorKind::ConnectionRefused)));
+        let unreachable = NotifierError::connect("unix:path=/run/user/1000/bus", refused);
+        assert_eq!(DeliveryErrorKind::classify(&unreachable), DeliveryErrorKind::NoSessionBus);
+        let timed_out = NotifierError::Timeout("helper".to_string());
+        assert_eq!(DeliveryError::from_error(&timed_out).to_string(), "timeout: helper timed out");
+
+        let missing = DeliveryError::new(DeliveryErrorKind::DaemonMissing, "no owner");
+        assert_eq!(DeliveryError::from_error(&NotifierError::Delivery(missing.clone())), missing);
+    }
+
+    #[test]
+    fn test_delivery_status_display() {
+        assert_eq!(DeliveryStatus::Delivered.to_string(), "delivered");
+        assert_eq!(
+            DeliveryStatus::Failed(DeliveryErrorKind::DaemonMissing).to_string(),
+            "failed (no notification daemon)"
+        );
+        assert_eq!(
+            DeliveryStatus::TerminalFallback(DeliveryErrorKind::DaemonMissing).to_string(),
+            "written to terminal (no notification daemon)"
+        );
+        assert_eq!(DeliveryStatus::Interrupted.to_string(), "interrupted by a server restart");
+        assert_eq!(DeliveryStatus::Superseded.to_string(), "superseded by a newer broadcast of its group");
+    }
+
+    #[test]
+    fn test_delivery_error_display() {
+        let error = DeliveryError::new(DeliveryErrorKind::DaemonMissing, "no owner");
+        assert_eq!(error.to_string(), "daemon-missing: no owner");
+        assert_eq!(DeliveryErrorKind::classify(&error), DeliveryErrorKind::DaemonMissing);
+    }
+}
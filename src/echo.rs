@@ -0,0 +1,260 @@
+//! A notification daemon echoing what it receives
+//!
+//! `dots-notifier echo-daemon` owns `org.freedesktop.Notifications` on a bus of
+//! its own and records every notification it is sent, so deliveries can be
+//! tested end to end and developed locally without a desktop environment. By
+//! default it starts a private `dbus-daemon` and prints its address, to be used
+//! as `DBUS_SESSION_BUS_ADDRESS` by the server or helper under test.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::Connection;
+
+use crate::dbus::NOTIFICATIONS_BUS_NAME;
+
+/// Object path the notification daemon is served at
+pub const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+
+/// Reason reported in `NotificationClosed` for a notification closed by `CloseNotification`
+const CLOSED_BY_CALL: u32 = 3;
+
+/// Something the echo daemon was asked to do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum EchoEvent {
+    /// A notification was shown
+    Notify {
+        id: u32,
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        /// Hint values of basic types, others by their D-Bus signature such as `<(iiibiiay)>`
+        hints: BTreeMap<String, String>,
+        expire_timeout: i32,
+    },
+    /// A notification was closed
+    Close { id: u32 },
+}
+
+impl fmt::Display for EchoEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EchoEvent::Notify { id, app_name, summary, body, .. } => {
+                write!(f, "notify {} [{}] {}", id, app_name, summary)?;
+                if !body.is_empty() {
+                    write!(f, ": {}", body.replace('\n', " / "))?;
+                }
+                Ok(())
+            }
+            EchoEvent::Close { id } => write!(f, "close {}", id),
+        }
+    }
+}
+
+/// Implementation of `org.freedesktop.Notifications` reporting every call as an [`EchoEvent`]
+pub struct EchoDaemon {
+    next_id: AtomicU32,
+    events: mpsc::UnboundedSender<EchoEvent>,
+}
+
+impl EchoDaemon {
+    /// Create a daemon along with the receiving end of its events
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<EchoEvent>) {
+        let (events, received) = mpsc::unbounded_channel();
+        (Self { next_id: AtomicU32::new(1), events }, received)
+    }
+
+    /// Serve the daemon on a bus, taking over its notification daemon name
+    pub async fn serve(self, connection: &Connection) -> zbus::Result<()> {
+        connection.object_server().at(NOTIFICATIONS_PATH, self).await?;
+        connection.request_name(NOTIFICATIONS_BUS_NAME).await?;
+        Ok(())
+    }
+
+    fn record(&self, event: EchoEvent) {
+        // Nobody listening any more is no reason to fail the caller
+        let _ = self.events.send(event);
+    }
+}
+
+#[zbus::interface(name = "org.freedesktop.Notifications")]
+impl EchoDaemon {
+    #[allow(clippy::too_many_arguments)]
+    async fn notify(
+        &self,
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let id = match replaces_id {
+            0 => self.next_id.fetch_add(1, Ordering::Relaxed),
+            id => id,
+        };
+        let hints = hints.iter().map(|(name, value)| (name.clone(), describe_hint(value))).collect();
+        self.record(EchoEvent::Notify {
+            id,
+            app_name,
+            replaces_id,
+            app_icon,
+            summary,
+            body,
+            actions,
+            hints,
+            expire_timeout,
+        });
+        id
+    }
+
+    async fn close_notification(
+        &self,
+        id: u32,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.record(EchoEvent::Close { id });
+        Self::notification_closed(&emitter, id, CLOSED_BY_CALL).await?;
+        Ok(())
+    }
+
+    fn get_capabilities(&self) -> Vec<String> {
+        ["actions", "body", "body-markup", "persistence"].map(String::from).to_vec()
+    }
+
+    fn get_server_information(&self) -> (String, String, String, String) {
+        (
+            "dots-notifier-echo".to_string(),
+            "dots-notifier".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+            "1.2".to_string(),
+        )
+    }
+
+    #[zbus(signal)]
+    async fn notification_closed(emitter: &SignalEmitter<'_>, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// Describe a hint value, showing values of basic types and the signature of others
+fn describe_hint(value: &Value<'_>) -> String {
+    match value {
+        Value::Value(inner) => describe_hint(inner),
+        Value::Str(s) => s.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::U8(n) => n.to_string(),
+        Value::I16(n) => n.to_string(),
+        Value::U16(n) => n.to_string(),
+        Value::I32(n) => n.to_string(),
+        Value::U32(n) => n.to_string(),
+        Value::I64(n) => n.to_string(),
+        Value::U64(n) => n.to_string(),
+        Value::F64(n) => n.to_string(),
+        other => format!("<{}>", other.value_signature()),
+    }
+}
+
+/// A `dbus-daemon` started for the echo daemon, stopped when dropped
+pub struct PrivateBus {
+    /// Address clients connect to the bus at
+    pub address: String,
+    _daemon: Child,
+}
+
+impl PrivateBus {
+    /// Start a session bus of its own
+    pub async fn start() -> io::Result<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdout = daemon.stdout.take().ok_or_else(|| io::Error::other("dbus-daemon has no output"))?;
+        let mut address = String::new();
+        BufReader::new(stdout).read_line(&mut address).await?;
+        let address = address.trim().to_string();
+        if address.is_empty() {
+            return Err(io::Error::other("dbus-daemon exited without printing its address"));
+        }
+        Ok(Self { address, _daemon: daemon })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus::NotificationsProxy;
+
+    #[test]
+    fn test_describe_hint() {
+        assert_eq!(describe_hint(&Value::from("im.received")), "im.received");
+        assert_eq!(describe_hint(&Value::from(2u8)), "2");
+        assert_eq!(describe_hint(&Value::from(true)), "true");
+        assert_eq!(describe_hint(&Value::from(vec![0u8, 1, 2])), "<ay>");
+    }
+
+    #[test]
+    fn test_event_format() {
+        let event = EchoEvent::Notify {
+            id: 7,
+            app_name: "dots-notifier".to_string(),
+            replaces_id: 0,
+            app_icon: String::new(),
+            summary: "Maintenance".to_string(),
+            body: "Starting\nnow".to_string(),
+            actions: Vec::new(),
+            hints: BTreeMap::from([("urgency".to_string(), "2".to_string())]),
+            expire_timeout: -1,
+        };
+        assert_eq!(event.to_string(), "notify 7 [dots-notifier] Maintenance: Starting / now");
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "notify");
+        assert_eq!(json["hints"]["urgency"], "2");
+        assert_eq!(EchoEvent::Close { id: 7 }.to_string(), "close 7");
+    }
+
+    #[tokio::test]
+    async fn test_notifications_echoed_on_private_bus() {
+        let Ok(bus) = PrivateBus::start().await else {
+            // dbus-daemon is not installed
+            return;
+        };
+        let server = zbus::connection::Builder::address(bus.address.as_str()).unwrap().build().await.unwrap();
+        let (daemon, mut events) = EchoDaemon::new();
+        daemon.serve(&server).await.unwrap();
+
+        let client = zbus::connection::Builder::address(bus.address.as_str()).unwrap().build().await.unwrap();
+        let proxy = NotificationsProxy::new(&client).await.unwrap();
+        let hints = HashMap::from([("urgency", Value::from(2u8))]);
+        let id = proxy.notify("test", 0, "", "Maintenance", "Starting", &[], &hints, -1).await.unwrap();
+        assert_eq!(proxy.notify("test", id, "", "Maintenance", "Started", &[], &hints, -1).await.unwrap(), id);
+        proxy.close_notification(id).await.unwrap();
+
+        match events.recv().await.unwrap() {
+            EchoEvent::Notify { id: echoed, summary, hints, .. } => {
+                assert_eq!(echoed, id);
+                assert_eq!(summary, "Maintenance");
+                assert_eq!(hints["urgency"], "2");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(events.recv().await.unwrap(), EchoEvent::Notify { replaces_id, .. } if replaces_id == id));
+        assert_eq!(events.recv().await.unwrap(), EchoEvent::Close { id });
+    }
+}
@@ -0,0 +1,122 @@
+//! Errors of the server and its clients
+//!
+//! Failures reaching buses, enumerating sessions, validating requests, starting
+//! the delivery helper and delivering are told apart by [`NotifierError`]. Each
+//! variant maps to a distinct `org.freedesktop.DBus.Error` name, so clients can
+//! handle failures programmatically rather than parse messages.
+
+use std::io;
+
+use thiserror::Error;
+
+use crate::delivery::{DeliveryError, DeliveryErrorKind};
+
+/// Result of an operation of the server or its clients
+pub type NotifierResult<T> = Result<T, NotifierError>;
+
+/// What went wrong in the server or one of its clients
+#[derive(Debug, Error)]
+pub enum NotifierError {
+    /// A bus could not be connected to
+    #[error("failed to connect to {address}: {source}")]
+    DbusConnect {
+        address: String,
+        #[source]
+        source: zbus::Error,
+    },
+    /// The sessions could not be enumerated through logind
+    #[error("failed to enumerate sessions: {0}")]
+    SessionEnumeration(#[source] zbus::Error),
+    /// A request or notification was invalid
+    #[error("{0}")]
+    Validation(String),
+    /// The delivery helper could not be started
+    #[error("failed to start helper: {0}")]
+    HelperSpawn(#[source] io::Error),
+    /// A notification could not be delivered
+    #[error(transparent)]
+    Delivery(#[from] DeliveryError),
+    /// Something did not answer in time
+    #[error("{0} timed out")]
+    Timeout(String),
+}
+
+impl NotifierError {
+    /// Describe a failure to connect to the bus at `address`
+    pub fn connect(address: impl Into<String>, source: impl Into<zbus::Error>) -> Self {
+        NotifierError::DbusConnect {
+            address: address.into(),
+            source: source.into(),
+        }
+    }
+}
+
+/// Calls on a connected bus fail deliveries, classified by [`DeliveryErrorKind::classify`]
+impl From<zbus::Error> for NotifierError {
+    fn from(error: zbus::Error) -> Self {
+        NotifierError::Delivery(DeliveryError::from_error(&error))
+    }
+}
+
+impl From<zbus::fdo::Error> for NotifierError {
+    fn from(error: zbus::fdo::Error) -> Self {
+        NotifierError::Delivery(DeliveryError::from_error(&error))
+    }
+}
+
+impl From<NotifierError> for zbus::fdo::Error {
+    fn from(error: NotifierError) -> Self {
+        let message = error.to_string();
+        match error {
+            NotifierError::DbusConnect { .. } => zbus::fdo::Error::NoServer(message),
+            NotifierError::SessionEnumeration(_) => zbus::fdo::Error::IOError(message),
+            NotifierError::Validation(_) => zbus::fdo::Error::InvalidArgs(message),
+            NotifierError::HelperSpawn(_) => zbus::fdo::Error::SpawnFailed(message),
+            NotifierError::Delivery(error) => match error.kind() {
+                DeliveryErrorKind::NoSessionBus => zbus::fdo::Error::Disconnected(message),
+                DeliveryErrorKind::DaemonMissing => zbus::fdo::Error::NameHasNoOwner(message),
+                DeliveryErrorKind::Timeout => zbus::fdo::Error::NoReply(message),
+                DeliveryErrorKind::Stalled => zbus::fdo::Error::TimedOut(message),
+                DeliveryErrorKind::PolicyDenied => zbus::fdo::Error::AccessDenied(message),
+                DeliveryErrorKind::NotifyRejected | DeliveryErrorKind::Other => zbus::fdo::Error::Failed(message),
+            },
+            NotifierError::Timeout(_) => zbus::fdo::Error::TimedOut(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zbus::DBusError;
+
+    use super::*;
+
+    #[test]
+    fn test_variants_map_to_distinct_errors() {
+        let errors = [
+            NotifierError::connect("unix:path=/run/user/1000/bus", zbus::Error::Address("bad".to_string())),
+            NotifierError::SessionEnumeration(zbus::Error::Unsupported),
+            NotifierError::Validation("Notification summary cannot be empty".to_string()),
+            NotifierError::HelperSpawn(io::Error::from(io::ErrorKind::NotFound)),
+            NotifierError::Delivery(DeliveryError::new(DeliveryErrorKind::DaemonMissing, "no owner")),
+            NotifierError::Timeout("helper".to_string()),
+        ];
+        let names: Vec<String> = errors
+            .into_iter()
+            .map(|error| zbus::fdo::Error::from(error).name().to_string())
+            .collect();
+        let mut distinct = names.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), names.len(), "{:?}", names);
+        assert_eq!(names[2], "org.freedesktop.DBus.Error.InvalidArgs");
+        assert_eq!(names[4], "org.freedesktop.DBus.Error.NameHasNoOwner");
+    }
+
+    #[test]
+    fn test_bus_errors_classified_as_delivery_failures() {
+        let error = NotifierError::from(zbus::fdo::Error::ServiceUnknown("gone".to_string()));
+        assert!(matches!(&error, NotifierError::Delivery(error) if error.kind() == DeliveryErrorKind::DaemonMissing));
+        assert_eq!(NotifierError::Timeout("helper".to_string()).to_string(), "helper timed out");
+    }
+}
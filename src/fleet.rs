@@ -0,0 +1,396 @@
+//! Sending a broadcast to many hosts over SSH
+//!
+//! The client can run itself on remote hosts with `ssh`, sending the same
+//! broadcast on each and collecting its delivery report. Hosts are contacted
+//! in parallel up to a limit, and the per-user outcomes of every host are
+//! aggregated into a single report printed as a table or as JSON.
+//!
+//! The broadcasts of one fleet-wide send form a batch: each host tags its
+//! broadcast with the batch id, so the batch can later be withdrawn from every
+//! desktop of every host at once.
+
+use std::process::Stdio;
+
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::bus::BusType;
+use crate::request::SendOptions;
+
+/// Number of hosts contacted at the same time unless configured otherwise
+pub const DEFAULT_PARALLELISM: usize = 8;
+
+/// Name of the client on the remote hosts
+pub const REMOTE_COMMAND: &str = "dots-notifier";
+
+/// Delivery outcome for one user of a host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserResult {
+    pub uid: u32,
+    pub username: String,
+    pub status: String,
+}
+
+/// Outcome of sending the broadcast to one host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostResult {
+    pub host: String,
+    /// Id of the broadcast on the host, if it was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broadcast_id: Option<u64>,
+    pub users: Vec<UserResult>,
+    /// Why the host could not be notified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HostResult {
+    /// Whether the broadcast was sent on the host
+    pub fn is_sent(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Prefix of the tag marking the broadcasts of a batch
+pub const BATCH_TAG_PREFIX: &str = "batch-";
+
+/// Outcome of closing a batch on one host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CloseResult {
+    pub host: String,
+    /// Why the batch could not be closed on the host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Generate the id of a new batch
+pub fn new_batch_id() -> String {
+    Uuid::new_v4().simple().to_string()[..12].to_string()
+}
+
+/// Get the tag marking the broadcasts of a batch
+pub fn batch_tag(batch_id: &str) -> String {
+    format!("{}{}", BATCH_TAG_PREFIX, batch_id)
+}
+
+/// Parse a hosts file: one host per line, blank lines and `#` comments are skipped
+pub fn parse_hosts(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Quote an argument for a POSIX shell
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Build the shell command sending the broadcast on a host and printing its id, then its delivery report
+///
+/// The send is confirmed once on the operator's side for the whole fleet, so the host does not ask again.
+pub fn remote_script(bus: BusType, title: &str, body: &str, options: &SendOptions) -> String {
+    let mut send = vec![REMOTE_COMMAND.to_string(), "--bus".to_string(), bus.to_string(), "send".to_string()];
+    if let Some(channel) = &options.channel {
+        send.extend(["--channel".to_string(), shell_quote(channel)]);
+    }
+    if let Some(category) = &options.category {
+        send.extend(["--category".to_string(), shell_quote(category)]);
+    }
+    if let Some(fingerprint) = &options.fingerprint {
+        send.extend(["--fingerprint".to_string(), shell_quote(fingerprint)]);
+    }
+    for tag in &options.tags {
+        send.extend(["--tag".to_string(), shell_quote(tag)]);
+    }
+    if let Some(hook) = &options.hook {
+        send.extend(["--hook".to_string(), shell_quote(hook)]);
+    }
+    if let Some(urgency) = options.urgency {
+        send.extend(["--urgency".to_string(), urgency.to_string()]);
+    }
+    for action in &options.actions {
+        send.extend(["--action".to_string(), shell_quote(action)]);
+    }
+    for command in &options.body_commands {
+        send.extend(["--body-command".to_string(), shell_quote(command)]);
+    }
+    if let Some(group_key) = &options.group_key {
+        send.extend(["--group-key".to_string(), shell_quote(group_key)]);
+    }
+    if options.allow_duplicate {
+        send.push("--allow-duplicate".to_string());
+    }
+    if options.markdown {
+        send.push("--markdown".to_string());
+    }
+    if let Some(image) = &options.image {
+        send.extend(["--image".to_string(), shell_quote(image)]);
+    }
+    if let Some(group) = &options.group {
+        send.extend(["--group".to_string(), shell_quote(group)]);
+    }
+    for user in &options.exclude_users {
+        send.extend(["--exclude-user".to_string(), shell_quote(user)]);
+    }
+    if let Some(seat) = &options.seat {
+        send.extend(["--seat".to_string(), shell_quote(seat)]);
+    }
+    if let Some(replaces) = &options.replaces {
+        send.extend(["--replace".to_string(), shell_quote(replaces)]);
+    }
+    send.extend(["--yes".to_string(), "--".to_string(), shell_quote(title), shell_quote(body)]);
+
+    // Logs go to stdout, which carries the id and report
+    format!(
+        "export RUST_LOG=off; id=$({}) && echo \"$id\" && {} --bus {} report \"$id\"",
+        send.join(" "),
+        REMOTE_COMMAND,
+        bus
+    )
+}
+
+/// Build the shell command closing the broadcasts of a batch on a host
+pub fn remote_close_script(bus: BusType, batch_id: &str) -> String {
+    format!(
+        "export RUST_LOG=off; {} --bus {} close --batch {}",
+        REMOTE_COMMAND,
+        bus,
+        shell_quote(batch_id)
+    )
+}
+
+/// Parse the output of the remote command into the broadcast id and the report lines
+pub fn parse_remote_output(output: &str) -> Result<(u64, Vec<UserResult>), String> {
+    let mut lines = output.lines().filter(|line| !line.trim().is_empty());
+    let first = lines.next().ok_or("The host printed no broadcast id")?;
+    let broadcast_id = first
+        .trim()
+        .parse()
+        .map_err(|_| format!("Unexpected output '{}'", first.trim()))?;
+    let users = lines
+        .map(|line| {
+            // Lines are "username(uid): status"
+            let unexpected = || format!("Unexpected report line '{}'", line);
+            let (user, status) = line.split_once(": ").ok_or_else(unexpected)?;
+            let (username, uid) = user
+                .strip_suffix(')')
+                .and_then(|user| user.rsplit_once('('))
+                .ok_or_else(unexpected)?;
+            Ok(UserResult {
+                uid: uid.parse().map_err(|_| unexpected())?,
+                username: username.to_string(),
+                status: status.to_string(),
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok((broadcast_id, users))
+}
+
+/// Run a shell command on a host, returning its standard output or why it failed
+async fn run_on_host(host: &str, script: &str) -> Result<String, String> {
+    // ssh would take the host for an option
+    if host.starts_with('-') {
+        return Err(format!("Invalid host '{}'", host));
+    }
+    let output = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "-T", host, script])
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    match output {
+        Err(e) => Err(format!("Failed to run ssh: {}", e)),
+        Ok(output) if !output.status.success() => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(stderr
+                .lines()
+                .rfind(|line| !line.trim().is_empty())
+                .map(|line| line.trim().to_string())
+                .unwrap_or_else(|| format!("ssh exited with {}", output.status)))
+        }
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+    }
+}
+
+/// Send the broadcast on one host
+pub async fn send_to_host(host: &str, script: &str) -> HostResult {
+    let mut result = HostResult { host: host.to_string(), broadcast_id: None, users: Vec::new(), error: None };
+    let parsed = run_on_host(host, script).await.and_then(|output| parse_remote_output(&output));
+    match parsed {
+        Ok((broadcast_id, users)) => {
+            result.broadcast_id = Some(broadcast_id);
+            result.users = users;
+        }
+        Err(e) => result.error = Some(e),
+    }
+    result
+}
+
+/// Send the broadcast on every host, contacting at most `parallelism` at once
+///
+/// Results are in the order of `hosts`.
+pub async fn send_to_hosts(hosts: &[String], script: &str, parallelism: usize) -> Vec<HostResult> {
+    stream::iter(hosts)
+        .map(|host| send_to_host(host, script))
+        .buffered(parallelism.max(1))
+        .collect()
+        .await
+}
+
+/// Close a batch on every host, contacting at most `parallelism` at once
+///
+/// Results are in the order of `hosts`.
+pub async fn close_on_hosts(hosts: &[String], script: &str, parallelism: usize) -> Vec<CloseResult> {
+    stream::iter(hosts)
+        .map(|host| async move {
+            CloseResult {
+                host: host.to_string(),
+                error: run_on_host(host, script).await.err(),
+            }
+        })
+        .buffered(parallelism.max(1))
+        .collect()
+        .await
+}
+
+/// Format the aggregated report as a table with a row per host and user
+pub fn format_table(results: &[HostResult]) -> String {
+    let mut rows = vec![["HOST".to_string(), "USER".to_string(), "UID".to_string(), "STATUS".to_string()]];
+    for result in results {
+        match &result.error {
+            Some(error) => rows.push([result.host.clone(), "-".to_string(), "-".to_string(), error.clone()]),
+            None if result.users.is_empty() => {
+                rows.push([result.host.clone(), "-".to_string(), "-".to_string(), "no recipients".to_string()])
+            }
+            None => rows.extend(result.users.iter().map(|user| {
+                [result.host.clone(), user.username.clone(), user.uid.to_string(), user.status.clone()]
+            })),
+        }
+    }
+
+    let widths: Vec<usize> = (0..3)
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for (cell, width) in row.iter().zip(&widths) {
+                line.push_str(&format!("{:<width$}  ", cell, width = width));
+            }
+            line.push_str(&row[3]);
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hosts() {
+        let hosts = parse_hosts("# Building A\ndesk-01\n  desk-02  # by the window\n\nadmin@desk-03\n");
+        assert_eq!(hosts, vec!["desk-01", "desk-02", "admin@desk-03"]);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+    }
+
+    #[test]
+    fn test_remote_script() {
+        let options = SendOptions {
+            channel: Some("fire".to_string()),
+            tags: vec!["drill".to_string()],
+            body_commands: vec!["uptime".to_string()],
+            group: Some("wardens".to_string()),
+            exclude_users: vec!["kiosk".to_string()],
+            replaces: Some("3f9c2a7b1d04".to_string()),
+            ..Default::default()
+        };
+        let script = remote_script(BusType::System, "Fire drill", "Leave at 10:00", &options);
+        assert_eq!(
+            script,
+            "export RUST_LOG=off; id=$(dots-notifier --bus system send --channel 'fire' --tag 'drill' \
+             --body-command 'uptime' --group 'wardens' --exclude-user 'kiosk' --replace '3f9c2a7b1d04' --yes -- \
+             'Fire drill' 'Leave at 10:00') && echo \"$id\" && dots-notifier --bus system report \"$id\""
+        );
+    }
+
+    #[test]
+    fn test_remote_close_script() {
+        assert_eq!(batch_tag("3f9c2a7b1d04"), "batch-3f9c2a7b1d04");
+        assert_eq!(new_batch_id().len(), 12);
+        assert_ne!(new_batch_id(), new_batch_id());
+        assert_eq!(
+            remote_close_script(BusType::Session, "3f9c2a7b1d04"),
+            "export RUST_LOG=off; dots-notifier --bus session close --batch '3f9c2a7b1d04'"
+        );
+    }
+
+    #[test]
+    fn test_remote_script_confirmed() {
+        // Hosts have no terminal to confirm a send on
+        let script = remote_script(BusType::System, "Reboot", "Tonight", &SendOptions::default());
+        assert!(script.contains(" send --yes -- 'Reboot' 'Tonight'"));
+    }
+
+    #[test]
+    fn test_parse_remote_output() {
+        let output = "42\nalice(1000): delivered\nbob(1001): failed: timeout\n";
+        let (broadcast_id, users) = parse_remote_output(output).unwrap();
+        assert_eq!(broadcast_id, 42);
+        assert_eq!(users, vec![
+            UserResult { uid: 1000, username: "alice".to_string(), status: "delivered".to_string() },
+            UserResult { uid: 1001, username: "bob".to_string(), status: "failed: timeout".to_string() },
+        ]);
+        assert_eq!(parse_remote_output("7\n").unwrap(), (7, vec![]));
+        assert!(parse_remote_output("").is_err());
+        assert!(parse_remote_output("Starting in client mode...\n").is_err());
+        assert!(parse_remote_output("7\nalice: delivered\n").is_err());
+    }
+
+    #[test]
+    fn test_format_table() {
+        let results = vec![
+            HostResult {
+                host: "desk-01".to_string(),
+                broadcast_id: Some(3),
+                users: vec![UserResult { uid: 1000, username: "alice".to_string(), status: "delivered".to_string() }],
+                error: None,
+            },
+            HostResult {
+                host: "desk-02".to_string(),
+                broadcast_id: None,
+                users: vec![],
+                error: Some("Connection refused".to_string()),
+            },
+        ];
+        assert_eq!(
+            format_table(&results),
+            "HOST     USER   UID   STATUS\n\
+             desk-01  alice  1000  delivered\n\
+             desk-02  -      -     Connection refused\n"
+        );
+        assert!(results[0].is_sent());
+        assert!(!results[1].is_sent());
+    }
+
+    #[tokio::test]
+    async fn test_option_hosts_are_rejected() {
+        let hosts = vec!["-oProxyCommand=touch /tmp/pwned".to_string(); 3];
+        let results = send_to_hosts(&hosts, "true", 2).await;
+        assert_eq!(results.len(), 3);
+        let error = "Invalid host '-oProxyCommand=touch /tmp/pwned'";
+        assert!(results.iter().all(|result| result.error.as_deref() == Some(error)));
+        let results = close_on_hosts(&hosts, "true", 2).await;
+        assert!(results.iter().all(|result| result.error.as_deref() == Some(error)));
+    }
+}
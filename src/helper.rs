@@ -0,0 +1,1111 @@
+//! Delivery through a helper process in the recipient's context
+//!
+//! The `helper`, `machinectl` and `sudo` delivery backends hand each
+//! notification to `dots-notifier-helper`, started as the recipient with
+//! `systemd-run`, `machinectl shell` or `sudo` respectively. The helper reports
+//! failures through the exit codes of [`DeliveryErrorKind`]. A missing or
+//! non-executable helper is reported at startup and fails each delivery, which
+//! then falls through to the next backend of the chain.
+//!
+//! The notification is passed to the helper as command line arguments, as
+//! JSON on its standard input or in environment variables, so its content
+//! need not show up in process listings.
+//!
+//! The helper prints the id of the notification. When the server listens for
+//! actions, the helper keeps running and prints an `action <key>` line for
+//! each action the user invokes, which the server relays to its signals.
+//!
+//! During a rolling upgrade the server may find a helper older than itself.
+//! Before its first delivery, and again whenever the helper is replaced, the
+//! server asks the helper for its protocol with `--protocol-version`; helpers
+//! predating the question fail it and are taken to speak version 1, the
+//! original command line contract. The server then only passes what the
+//! helper understands. Newer helpers accept everything older servers pass.
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::Parser;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::dbus::NOTIFICATIONS_BUS_NAME;
+use crate::delivery::{DeliveryError, DeliveryErrorKind};
+use crate::error::NotifierError;
+use crate::helper_stats::{HelperFailure, HelperStats};
+use crate::notification::{DEFAULT_APP_ICON, DEFAULT_EXPIRE_TIMEOUT};
+use crate::nested::NestedBusPolicy;
+use crate::payload::BroadcastPayload;
+use crate::session::{session_bus_address, user_runtime_dir};
+use crate::sink::{DbusSink, DeliveryOptions, NotificationSink};
+use crate::sound::Sound;
+use crate::types::{TargetUser, Urgency};
+
+/// Name of the helper executable
+pub const HELPER_NAME: &str = "dots-notifier-helper";
+
+/// Time a helper may take to deliver a notification before it is killed
+pub const HELPER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Prefix of the lines a listening helper reports invoked actions with
+pub const ACTION_LINE_PREFIX: &str = "action ";
+
+/// Environment variable overriding the configured `helper_path`
+pub const HELPER_PATH_ENV: &str = "DOTS_NOTIFIER_HELPER";
+
+/// Environment variable overriding the configured `helper_args`
+pub const HELPER_ARGS_ENV: &str = "DOTS_NOTIFIER_HELPER_ARGS";
+
+/// First argument telling the helper to read the notification as JSON from its standard input
+pub const ARGS_FROM_STDIN: &str = "--args-from-stdin";
+
+/// First argument telling the helper to read the notification from `DOTS_NOTIFIER_*` variables
+pub const ARGS_FROM_ENV: &str = "--args-from-env";
+
+/// Argument asking the helper to print the protocol version it speaks and exit
+pub const PROTOCOL_VERSION_ARG: &str = "--protocol-version";
+
+/// Version of the protocol between the server and the helper
+///
+/// 1. The notification as command line arguments, with the application name,
+///    bus name, replaced id, urgency, sound, correlation id, sender, footer,
+///    desktop and actions.
+/// 2. Passing the notification as JSON or in the environment, reporting invoked
+///    actions, and the icon, desktop entry, timeout, action labels, group key,
+///    image, Markdown and nested bus options.
+/// 3. The progress option.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Protocol of helpers that do not answer `--protocol-version`
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// How notifications reach a user's notification daemon
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeliveryStrategy {
+    /// Connect to the user's session bus from the server
+    #[default]
+    Direct,
+    /// Run `dots-notifier-helper` as the user
+    Helper,
+}
+
+/// How the notification to deliver is passed to the helper
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArgumentPassing {
+    /// As command line arguments
+    #[default]
+    Argv,
+    /// As a JSON object on the helper's standard input
+    StdinJson,
+    /// In `DOTS_NOTIFIER_*` environment variables
+    Env,
+}
+
+impl std::str::FromStr for ArgumentPassing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "argv" => Ok(ArgumentPassing::Argv),
+            "stdin-json" => Ok(ArgumentPassing::StdinJson),
+            "env" => Ok(ArgumentPassing::Env),
+            _ => Err(format!("Unknown argument passing mode '{}', expected argv, stdin-json or env", s)),
+        }
+    }
+}
+
+/// How the helper is started as the recipient, when the server runs as another user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Launcher {
+    /// A transient service started with `systemd-run --uid`
+    #[default]
+    SystemdRun,
+    /// A shell of the host started with `machinectl shell --uid`; environment
+    /// values show up on its command line, as it cannot pass them on otherwise
+    Machinectl,
+    /// `sudo --user`, for hosts without a systemd manager to ask
+    Sudo,
+}
+
+/// Find the helper in the directories of a `PATH` value
+pub fn find_helper(path_var: Option<&OsStr>) -> Result<PathBuf, String> {
+    let candidates: Vec<PathBuf> = path_var
+        .map(|path_var| std::env::split_paths(path_var).map(|dir| dir.join(HELPER_NAME)).collect())
+        .unwrap_or_default();
+    if let Some(helper) = candidates.iter().find(|candidate| candidate.is_file()) {
+        check_helper(helper)?;
+        return Ok(helper.clone());
+    }
+    let searched: Vec<String> = candidates.iter().map(|candidate| candidate.display().to_string()).collect();
+    Err(format!("helper not found at {}", searched.join(", ")))
+}
+
+/// Check that the helper at a path can be run
+pub fn check_helper(helper: &Path) -> Result<(), String> {
+    let meta = std::fs::metadata(helper).map_err(|_| format!("helper not found at {}", helper.display()))?;
+    if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 {
+        return Err(format!("helper at {} is not executable", helper.display()));
+    }
+    Ok(())
+}
+
+/// Command line of the helper, describing the notification to deliver
+#[derive(Parser, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[command(name = HELPER_NAME, about = "Deliver a notification on the session bus of the calling user.")]
+pub struct HelperArgs {
+    /// Application name shown to the user.
+    #[arg(long)]
+    pub app_name: String,
+    /// Icon name shown with the notification.
+    #[arg(long)]
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// Desktop entry of the application the notification is shown as.
+    #[arg(long)]
+    #[serde(default)]
+    pub desktop_entry: Option<String>,
+    /// Expiration timeout in milliseconds; -1 leaves it to the server and 0 never expires.
+    #[arg(long, allow_hyphen_values = true)]
+    #[serde(default)]
+    pub timeout: Option<i32>,
+    /// Bus name of the notification server to deliver to.
+    #[arg(long, default_value = NOTIFICATIONS_BUS_NAME)]
+    pub bus_name: String,
+    /// Notification to replace in place.
+    #[arg(long, default_value_t = 0)]
+    pub replaces_id: u32,
+    #[arg(long)]
+    pub urgency: Option<Urgency>,
+    /// Sound theme event to play.
+    #[arg(long, conflicts_with = "mute")]
+    pub sound: Option<String>,
+    /// Ask the notification daemon not to play any sound.
+    #[arg(long)]
+    pub mute: bool,
+    #[arg(long)]
+    pub correlation_id: Option<Uuid>,
+    /// Name of the caller the broadcast is attributed to.
+    #[arg(long)]
+    pub sender: Option<String>,
+    /// Line appended to the body.
+    #[arg(long)]
+    pub footer: Option<String>,
+    /// Key grouping the notification with related ones.
+    #[arg(long)]
+    #[serde(default)]
+    pub group_key: Option<String>,
+    /// Desktop environment of the session, for daemon quirks.
+    #[arg(long)]
+    pub desktop: Option<String>,
+    /// Action offered with the notification. May be given multiple times.
+    #[arg(long = "action")]
+    pub actions: Vec<String>,
+    /// Label shown for an action, as KEY:LABEL. May be given multiple times.
+    #[arg(long = "action-label", value_name = "KEY:LABEL")]
+    #[serde(default)]
+    pub action_labels: Vec<String>,
+    /// Show the image in this file with the notification.
+    #[arg(long)]
+    #[serde(default)]
+    pub image: Option<PathBuf>,
+    /// Render bold, italic and links written in Markdown in the body.
+    #[arg(long)]
+    #[serde(default)]
+    pub markdown: bool,
+    /// Show a progress bar at this percentage.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    #[serde(default)]
+    pub progress: Option<u8>,
+    /// Also notify the nested session buses of the user, such as a compositor under test.
+    #[arg(long)]
+    #[serde(default)]
+    pub all_buses: bool,
+    /// Keep running after delivering and print the key of each action invoked.
+    #[arg(long)]
+    #[serde(default)]
+    pub listen: bool,
+    pub title: String,
+    pub body: String,
+}
+
+impl HelperArgs {
+    /// Describe a delivery to a user
+    pub fn new(user: &TargetUser, payload: &BroadcastPayload, options: &DeliveryOptions) -> Self {
+        Self {
+            app_name: options.app_name.clone(),
+            icon: Some(options.icon.clone()),
+            desktop_entry: options.desktop_entry.clone(),
+            timeout: Some(options.timeout),
+            bus_name: options.bus_name.clone(),
+            replaces_id: options.replaces_id,
+            urgency: payload.urgency,
+            sound: match &options.sound {
+                Sound::Named(name) => Some(name.clone()),
+                _ => None,
+            },
+            mute: options.sound == Sound::Muted,
+            correlation_id: options.correlation_id,
+            sender: payload.sender.as_deref().map(str::to_string),
+            footer: options.footer.clone(),
+            group_key: payload.group_key.clone(),
+            desktop: user.desktop().map(str::to_string),
+            actions: payload.actions.clone(),
+            action_labels: payload
+                .action_labels
+                .iter()
+                .map(|(key, label)| format!("{}:{}", key, label))
+                .collect(),
+            image: payload.image.clone(),
+            markdown: payload.markdown,
+            progress: payload.progress,
+            all_buses: options.nested_buses == NestedBusPolicy::All,
+            listen: options.responses.is_some(),
+            title: payload.title.to_string(),
+            body: payload.body.to_string(),
+        }
+    }
+
+    /// Read the notification the way the server passed it, exiting on invalid arguments
+    pub fn from_process() -> Result<Self, String> {
+        match std::env::args().nth(1).as_deref() {
+            Some(ARGS_FROM_STDIN) => serde_json::from_reader(std::io::stdin())
+                .map_err(|e| format!("Invalid notification on standard input: {}", e)),
+            Some(ARGS_FROM_ENV) => Self::from_env(|name| std::env::var(name).ok()),
+            _ => Ok(Self::parse()),
+        }
+    }
+
+    /// Convert into command line arguments
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec!["--app-name".to_string(), self.app_name.clone()];
+        args.extend(["--bus-name".to_string(), self.bus_name.clone()]);
+        args.extend(["--replaces-id".to_string(), self.replaces_id.to_string()]);
+        let optional = [
+            ("--icon", self.icon.clone()),
+            ("--desktop-entry", self.desktop_entry.clone()),
+            ("--timeout", self.timeout.map(|timeout| timeout.to_string())),
+            ("--urgency", self.urgency.map(|urgency| urgency.to_string())),
+            ("--sound", self.sound.clone()),
+            ("--correlation-id", self.correlation_id.map(|id| id.to_string())),
+            ("--sender", self.sender.clone()),
+            ("--footer", self.footer.clone()),
+            ("--group-key", self.group_key.clone()),
+            ("--desktop", self.desktop.clone()),
+            ("--image", self.image.as_ref().map(|image| image.display().to_string())),
+            ("--progress", self.progress.map(|progress| progress.to_string())),
+        ];
+        for (flag, value) in optional {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value]);
+            }
+        }
+        if self.mute {
+            args.push("--mute".to_string());
+        }
+        if self.markdown {
+            args.push("--markdown".to_string());
+        }
+        if self.all_buses {
+            args.push("--all-buses".to_string());
+        }
+        if self.listen {
+            args.push("--listen".to_string());
+        }
+        for action in &self.actions {
+            args.extend(["--action".to_string(), action.clone()]);
+        }
+        for label in &self.action_labels {
+            args.extend(["--action-label".to_string(), label.clone()]);
+        }
+        // Content may start with a dash
+        args.extend(["--".to_string(), self.title.clone(), self.body.clone()]);
+        args
+    }
+
+    /// Convert into `DOTS_NOTIFIER_*` environment variables
+    ///
+    /// Actions and their labels are separated by newlines; unset options are left out.
+    pub fn to_env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("DOTS_NOTIFIER_APP_NAME", self.app_name.clone()),
+            ("DOTS_NOTIFIER_BUS_NAME", self.bus_name.clone()),
+            ("DOTS_NOTIFIER_REPLACES_ID", self.replaces_id.to_string()),
+            ("DOTS_NOTIFIER_TITLE", self.title.clone()),
+            ("DOTS_NOTIFIER_BODY", self.body.clone()),
+        ];
+        let optional = [
+            ("DOTS_NOTIFIER_ICON", self.icon.clone()),
+            ("DOTS_NOTIFIER_DESKTOP_ENTRY", self.desktop_entry.clone()),
+            ("DOTS_NOTIFIER_TIMEOUT", self.timeout.map(|timeout| timeout.to_string())),
+            ("DOTS_NOTIFIER_URGENCY", self.urgency.map(|urgency| urgency.to_string())),
+            ("DOTS_NOTIFIER_SOUND", self.sound.clone()),
+            ("DOTS_NOTIFIER_MUTE", self.mute.then(|| "1".to_string())),
+            ("DOTS_NOTIFIER_CORRELATION_ID", self.correlation_id.map(|id| id.to_string())),
+            ("DOTS_NOTIFIER_SENDER", self.sender.clone()),
+            ("DOTS_NOTIFIER_FOOTER", self.footer.clone()),
+            ("DOTS_NOTIFIER_GROUP_KEY", self.group_key.clone()),
+            ("DOTS_NOTIFIER_DESKTOP", self.desktop.clone()),
+            ("DOTS_NOTIFIER_IMAGE", self.image.as_ref().map(|image| image.display().to_string())),
+            ("DOTS_NOTIFIER_ACTIONS", Some(self.actions.join("\n")).filter(|_| !self.actions.is_empty())),
+            (
+                "DOTS_NOTIFIER_ACTION_LABELS",
+                Some(self.action_labels.join("\n")).filter(|_| !self.action_labels.is_empty()),
+            ),
+            ("DOTS_NOTIFIER_MARKDOWN", self.markdown.then(|| "1".to_string())),
+            ("DOTS_NOTIFIER_PROGRESS", self.progress.map(|progress| progress.to_string())),
+            ("DOTS_NOTIFIER_ALL_BUSES", self.all_buses.then(|| "1".to_string())),
+            ("DOTS_NOTIFIER_LISTEN", self.listen.then(|| "1".to_string())),
+        ];
+        env.extend(optional.into_iter().filter_map(|(name, value)| Some((name, value?))));
+        env
+    }
+
+    /// Read the notification from `DOTS_NOTIFIER_*` environment variables
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let required = |name: &str| var(name).ok_or_else(|| format!("{} is not set", name));
+        let replaces_id = match var("DOTS_NOTIFIER_REPLACES_ID") {
+            Some(id) => id.parse().map_err(|_| format!("Invalid DOTS_NOTIFIER_REPLACES_ID '{}'", id))?,
+            None => 0,
+        };
+        let correlation_id = match var("DOTS_NOTIFIER_CORRELATION_ID") {
+            Some(id) => Some(id.parse().map_err(|_| format!("Invalid DOTS_NOTIFIER_CORRELATION_ID '{}'", id))?),
+            None => None,
+        };
+        let progress = match var("DOTS_NOTIFIER_PROGRESS") {
+            Some(progress) => Some(
+                progress
+                    .parse()
+                    .ok()
+                    .filter(|progress| *progress <= 100)
+                    .ok_or_else(|| format!("Invalid DOTS_NOTIFIER_PROGRESS '{}'", progress))?,
+            ),
+            None => None,
+        };
+        let timeout = match var("DOTS_NOTIFIER_TIMEOUT") {
+            Some(timeout) => Some(timeout.parse().map_err(|_| format!("Invalid DOTS_NOTIFIER_TIMEOUT '{}'", timeout))?),
+            None => None,
+        };
+        Ok(Self {
+            app_name: required("DOTS_NOTIFIER_APP_NAME")?,
+            icon: var("DOTS_NOTIFIER_ICON"),
+            desktop_entry: var("DOTS_NOTIFIER_DESKTOP_ENTRY"),
+            timeout,
+            bus_name: var("DOTS_NOTIFIER_BUS_NAME").unwrap_or_else(|| NOTIFICATIONS_BUS_NAME.to_string()),
+            replaces_id,
+            urgency: var("DOTS_NOTIFIER_URGENCY").map(|urgency| urgency.parse()).transpose()?,
+            sound: var("DOTS_NOTIFIER_SOUND"),
+            mute: var("DOTS_NOTIFIER_MUTE").is_some_and(|mute| mute == "1"),
+            correlation_id,
+            sender: var("DOTS_NOTIFIER_SENDER"),
+            footer: var("DOTS_NOTIFIER_FOOTER"),
+            group_key: var("DOTS_NOTIFIER_GROUP_KEY"),
+            desktop: var("DOTS_NOTIFIER_DESKTOP"),
+            actions: var("DOTS_NOTIFIER_ACTIONS")
+                .map(|actions| actions.split('\n').map(str::to_string).collect())
+                .unwrap_or_default(),
+            action_labels: var("DOTS_NOTIFIER_ACTION_LABELS")
+                .map(|labels| labels.split('\n').map(str::to_string).collect())
+                .unwrap_or_default(),
+            image: var("DOTS_NOTIFIER_IMAGE").map(PathBuf::from),
+            markdown: var("DOTS_NOTIFIER_MARKDOWN").is_some_and(|markdown| markdown == "1"),
+            progress,
+            all_buses: var("DOTS_NOTIFIER_ALL_BUSES").is_some_and(|all_buses| all_buses == "1"),
+            listen: var("DOTS_NOTIFIER_LISTEN").is_some_and(|listen| listen == "1"),
+            title: required("DOTS_NOTIFIER_TITLE")?,
+            body: required("DOTS_NOTIFIER_BODY")?,
+        })
+    }
+
+    /// Get the payload to deliver
+    pub fn payload(&self) -> BroadcastPayload {
+        BroadcastPayload::new(self.title.as_str(), self.body.as_str())
+            .with_urgency(self.urgency)
+            .with_actions(self.actions.clone())
+            .with_action_labels(
+                self.action_labels
+                    .iter()
+                    .filter_map(|label| label.split_once(':'))
+                    .map(|(key, label)| (key.to_string(), label.to_string()))
+                    .collect(),
+            )
+            .with_sender(self.sender.as_deref().map(Arc::from))
+            .with_group_key(self.group_key.clone())
+            .with_markdown(self.markdown)
+            .with_image(self.image.clone())
+            .with_progress(self.progress)
+    }
+
+    /// Leave out what a helper speaking an older protocol would reject
+    pub fn for_protocol(&self, version: u32) -> Self {
+        let mut args = self.clone();
+        if version < 3 {
+            args.progress = None;
+        }
+        if version >= 2 {
+            return args;
+        }
+        Self {
+            icon: None,
+            desktop_entry: None,
+            timeout: None,
+            group_key: None,
+            action_labels: Vec::new(),
+            image: None,
+            markdown: false,
+            all_buses: false,
+            listen: false,
+            ..args
+        }
+    }
+
+    /// Get the parameters to deliver the payload with
+    pub fn options(&self) -> DeliveryOptions {
+        let sound = match (&self.sound, self.mute) {
+            (_, true) => Sound::Muted,
+            (Some(name), false) => Sound::Named(name.clone()),
+            (None, false) => Sound::DaemonDefault,
+        };
+        DeliveryOptions {
+            app_name: self.app_name.clone(),
+            icon: self.icon.clone().unwrap_or_else(|| DEFAULT_APP_ICON.to_string()),
+            desktop_entry: self.desktop_entry.clone(),
+            timeout: self.timeout.unwrap_or(DEFAULT_EXPIRE_TIMEOUT),
+            replaces_id: self.replaces_id,
+            sound,
+            bus_name: self.bus_name.clone(),
+            responses: None,
+            correlation_id: self.correlation_id,
+            footer: self.footer.clone(),
+            terminal_heading: None,
+            profiles: Arc::default(),
+            nested_buses: if self.all_buses { NestedBusPolicy::All } else { NestedBusPolicy::Primary },
+        }
+    }
+}
+
+/// Sink delivering through the helper
+///
+/// Deliveries fail when the helper is missing, so a [`crate::backend::BackendChain`]
+/// can fall through to its next backend. Notifications are closed directly.
+#[derive(Debug, Clone)]
+pub struct HelperSink {
+    helper: Result<PathBuf, String>,
+    passing: ArgumentPassing,
+    launcher: Launcher,
+    direct: DbusSink,
+    /// Protocol version of the helper, and the modification time of the helper it was asked
+    protocol: Arc<Mutex<Option<(SystemTime, u32)>>>,
+    stats: Arc<HelperStats>,
+}
+
+impl HelperSink {
+    /// Create a sink running the helper at a path
+    pub fn new(helper: PathBuf) -> Self {
+        Self {
+            helper: Ok(helper),
+            passing: ArgumentPassing::default(),
+            launcher: Launcher::default(),
+            direct: DbusSink,
+            protocol: Arc::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Record the runs of the helper in shared statistics
+    pub fn with_stats(mut self, stats: Arc<HelperStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Pass the notification to the helper in a different way
+    pub fn with_passing(mut self, passing: ArgumentPassing) -> Self {
+        self.passing = passing;
+        self
+    }
+
+    /// Start the helper as the recipient in a different way
+    pub fn with_launcher(mut self, launcher: Launcher) -> Self {
+        self.launcher = launcher;
+        self
+    }
+
+    /// Create the sink set up by the `helper_path` and `helper_args` settings, reporting
+    /// if the helper is missing
+    ///
+    /// The `DOTS_NOTIFIER_HELPER` and `DOTS_NOTIFIER_HELPER_ARGS` environment
+    /// variables override the settings. Without a path the helper is looked up on `PATH`.
+    pub fn from_config(config: &Config) -> Self {
+        let sink = Self::from_settings(config, |name| std::env::var_os(name));
+        match &sink.helper {
+            Ok(helper) => debug!(helper = %helper.display(), passing = ?sink.passing, "Delivering through helper."),
+            Err(e) => error!("Cannot deliver through the helper, {}; falling through to the next delivery backend.", e),
+        }
+        sink
+    }
+
+    fn from_settings(config: &Config, var: impl Fn(&str) -> Option<OsString>) -> Self {
+        let helper = match var(HELPER_PATH_ENV).map(PathBuf::from).or_else(|| config.helper_path.clone()) {
+            Some(helper) if !helper.is_absolute() => {
+                Err(format!("helper path {} is not absolute", helper.display()))
+            }
+            Some(helper) => check_helper(&helper).map(|_| helper),
+            None => find_helper(var("PATH").as_deref()),
+        };
+        let passing = match var(HELPER_ARGS_ENV).map(|passing| passing.to_string_lossy().parse()) {
+            Some(Ok(passing)) => passing,
+            Some(Err(e)) => {
+                warn!("Ignoring {}: {}", HELPER_ARGS_ENV, e);
+                config.helper_args
+            }
+            None => config.helper_args,
+        };
+        Self {
+            helper,
+            passing,
+            launcher: Launcher::default(),
+            direct: DbusSink,
+            protocol: Arc::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Get the helper, or why it cannot be run
+    pub fn helper(&self) -> Result<&Path, String> {
+        let helper = self.helper.as_deref().map_err(Clone::clone)?;
+        check_helper(helper)?;
+        Ok(helper)
+    }
+
+    /// Get the protocol version of the helper, asking it again once it is replaced
+    async fn protocol_version(&self, helper: &Path) -> u32 {
+        let modified = std::fs::metadata(helper).and_then(|meta| meta.modified()).ok();
+        let cached = *self.protocol.lock().unwrap();
+        if let (Some(modified), Some((asked, version))) = (modified, cached) {
+            if asked == modified {
+                return version;
+            }
+        }
+        let version = probe_protocol_version(helper).await;
+        if version < PROTOCOL_VERSION {
+            warn!(
+                helper = %helper.display(),
+                version,
+                "Helper speaks an older protocol; passing only what it understands until it is upgraded."
+            );
+        }
+        if let Some(modified) = modified {
+            *self.protocol.lock().unwrap() = Some((modified, version));
+        }
+        version
+    }
+
+    /// Run the helper as a user, returning the id of the notification it delivered
+    ///
+    /// A listening helper keeps running in the background, its invoked actions sent to `responses`.
+    /// The run is recorded in the helper statistics.
+    async fn run(
+        &self,
+        helper: &Path,
+        user: &TargetUser,
+        args: &HelperArgs,
+        responses: Option<UnboundedSender<String>>,
+    ) -> Result<u32, DeliveryError> {
+        let started = Instant::now();
+        let result = self.spawn(helper, user, args, responses).await;
+        let outcome = result.as_ref().map(|_| ()).map_err(|(_, failure)| *failure);
+        self.stats.record(user, started.elapsed(), outcome);
+        result.map_err(|(error, _)| error)
+    }
+
+    /// Start the helper as a user and wait for it to deliver, telling why it failed if it did
+    async fn spawn(
+        &self,
+        helper: &Path,
+        user: &TargetUser,
+        args: &HelperArgs,
+        responses: Option<UnboundedSender<String>>,
+    ) -> Result<u32, (DeliveryError, HelperFailure)> {
+        let protocol = self.protocol_version(helper).await;
+        let args = &args.for_protocol(protocol);
+        let passing = if protocol < 2 { ArgumentPassing::Argv } else { self.passing };
+        let responses = responses.filter(|_| args.listen);
+        let env = match passing {
+            ArgumentPassing::Env => args.to_env(),
+            _ => Vec::new(),
+        };
+        let mut command = if nix::unistd::geteuid().as_raw() == user.uid {
+            tokio::process::Command::new(helper)
+        } else {
+            self.launch_command(helper, user, &env)
+        };
+        command.envs(env.iter().map(|(name, value)| (name, value)));
+        match passing {
+            ArgumentPassing::Argv => command.args(args.to_args()),
+            ArgumentPassing::StdinJson => command.arg(ARGS_FROM_STDIN),
+            ArgumentPassing::Env => command.arg(ARGS_FROM_ENV),
+        };
+        let stdin = match passing {
+            ArgumentPassing::StdinJson => Stdio::piped(),
+            _ => Stdio::null(),
+        };
+        command
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let start_error = |e| (DeliveryError::from_error(&NotifierError::HelperSpawn(e)), HelperFailure::Spawn);
+        let mut child = command.spawn().map_err(start_error)?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let request = serde_json::to_vec(args)
+                .map_err(|e| (DeliveryError::new(DeliveryErrorKind::Other, e.to_string()), HelperFailure::Spawn))?;
+            stdin.write_all(&request).await.map_err(start_error)?;
+        }
+        let timed_out =
+            |_| (DeliveryError::from_error(&NotifierError::Timeout("helper".to_string())), HelperFailure::Timeout);
+        let no_notification_id = || {
+            let error = DeliveryError::new(DeliveryErrorKind::Other, "helper did not print a notification id");
+            (error, HelperFailure::NoNotificationId)
+        };
+        let stdout = child.stdout.take().ok_or_else(|| {
+            (DeliveryError::new(DeliveryErrorKind::Other, "helper has no standard output"), HelperFailure::Spawn)
+        })?;
+        let mut lines = BufReader::new(stdout).lines();
+        let first_line = tokio::time::timeout(HELPER_TIMEOUT, lines.next_line())
+            .await
+            .map_err(timed_out)?
+            .map_err(start_error)?;
+
+        let Some(first_line) = first_line else {
+            // The helper exited without delivering; its exit code tells why
+            let output = tokio::time::timeout(HELPER_TIMEOUT, child.wait_with_output())
+                .await
+                .map_err(timed_out)?
+                .map_err(start_error)?;
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            let failure = output.status.code().map_or(HelperFailure::Signal, HelperFailure::Exit);
+            if let Some(kind) = output.status.code().and_then(DeliveryErrorKind::from_exit_code) {
+                return Err((DeliveryError::new(kind, stderr), failure));
+            }
+            if !output.status.success() {
+                let error = DeliveryError::new(DeliveryErrorKind::Other, format!("helper {}", output.status));
+                return Err((error, failure));
+            }
+            return Err(no_notification_id());
+        };
+        let notification_id = first_line.trim().parse().map_err(|_| no_notification_id())?;
+
+        tokio::spawn(async move {
+            let Some(responses) = responses else {
+                // Killed on drop if it does not exit on its own
+                let _ = tokio::time::timeout(HELPER_TIMEOUT, child.wait()).await;
+                return;
+            };
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some(action) = line.strip_prefix(ACTION_LINE_PREFIX) {
+                    if responses.send(action.to_string()).is_err() {
+                        break;
+                    }
+                }
+            }
+            let _ = child.wait().await;
+        });
+        Ok(notification_id)
+    }
+}
+
+/// Ask a helper for the protocol version it speaks
+///
+/// Helpers predating the question exit with a usage error and speak [`LEGACY_PROTOCOL_VERSION`].
+async fn probe_protocol_version(helper: &Path) -> u32 {
+    let output = tokio::process::Command::new(helper)
+        .arg(PROTOCOL_VERSION_ARG)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(HELPER_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .unwrap_or(LEGACY_PROTOCOL_VERSION),
+        Ok(Ok(_)) => LEGACY_PROTOCOL_VERSION,
+        Ok(Err(e)) => {
+            debug!(helper = %helper.display(), "Failed to ask the helper for its protocol version: {}", e);
+            LEGACY_PROTOCOL_VERSION
+        }
+        Err(_) => {
+            debug!(helper = %helper.display(), "Helper did not tell its protocol version in time.");
+            LEGACY_PROTOCOL_VERSION
+        }
+    }
+}
+
+impl HelperSink {
+    /// Build the command starting the helper as a user, passed `env` in its environment
+    fn launch_command(
+        &self,
+        helper: &Path,
+        user: &TargetUser,
+        env: &[(&'static str, String)],
+    ) -> tokio::process::Command {
+        let session = [
+            ("DBUS_SESSION_BUS_ADDRESS", session_bus_address(user.uid)),
+            ("XDG_RUNTIME_DIR", user_runtime_dir(user.uid)),
+        ];
+        let mut command;
+        match self.launcher {
+            Launcher::SystemdRun => {
+                command = tokio::process::Command::new("systemd-run");
+                command
+                    .arg(format!("--uid={}", user.uid))
+                    .args(["--pipe", "--quiet", "--wait", "--collect", "--service-type=exec"]);
+                for (name, value) in &session {
+                    command.arg(format!("--setenv={}={}", name, value));
+                }
+                // Without a value, systemd-run passes on the variable from its own environment
+                for (name, _) in env {
+                    command.arg(format!("--setenv={}", name));
+                }
+                command.arg("--").arg(helper);
+            }
+            Launcher::Machinectl => {
+                command = tokio::process::Command::new("machinectl");
+                command.args(["shell", "--quiet"]).arg(format!("--uid={}", user.uid));
+                for (name, value) in session.iter().chain(env) {
+                    command.arg(format!("--setenv={}={}", name, value));
+                }
+                command.arg(".host").arg(helper);
+            }
+            Launcher::Sudo => {
+                command = tokio::process::Command::new("sudo");
+                command.args(["--non-interactive"]).arg(format!("--user=#{}", user.uid));
+                if !env.is_empty() {
+                    let names: Vec<&str> = env.iter().map(|(name, _)| *name).collect();
+                    command.arg(format!("--preserve-env={}", names.join(",")));
+                }
+                command.args(["--", "env"]);
+                for (name, value) in &session {
+                    command.arg(format!("{}={}", name, value));
+                }
+                command.arg(helper);
+            }
+        }
+        command
+    }
+}
+
+impl NotificationSink for HelperSink {
+    fn notify<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        payload: Arc<BroadcastPayload>,
+        options: &'a DeliveryOptions,
+    ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+        Box::pin(async move {
+            match self.helper() {
+                Ok(helper) => {
+                    let args = HelperArgs::new(user, &payload, options);
+                    self.run(helper, user, &args, options.responses.clone()).await
+                }
+                Err(e) => Err(DeliveryError::new(
+                    DeliveryErrorKind::Other,
+                    format!("cannot deliver through the helper, {}", e),
+                )),
+            }
+        })
+    }
+
+    fn close<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        bus_name: &'a str,
+        notification_id: u32,
+    ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+        self.direct.close(user, bus_name, notification_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> DeliveryOptions {
+        DeliveryOptions {
+            app_name: "System Notifier".to_string(),
+            icon: "dialog-warning".to_string(),
+            desktop_entry: Some("backup-service".to_string()),
+            timeout: DEFAULT_EXPIRE_TIMEOUT,
+            replaces_id: 4,
+            sound: Sound::Named("message-new-instant".to_string()),
+            bus_name: NOTIFICATIONS_BUS_NAME.to_string(),
+            responses: None,
+            correlation_id: Some(Uuid::new_v4()),
+            footer: Some("Sent by backup.service".to_string()),
+            terminal_heading: None,
+            profiles: Arc::default(),
+            nested_buses: NestedBusPolicy::default(),
+        }
+    }
+
+    #[test]
+    fn test_args_round_trip() {
+        let user = TargetUser::new(1000, "alice".to_string()).with_desktop("KDE");
+        let payload = BroadcastPayload::new("-Reboot", "Tonight")
+            .with_urgency(Some(Urgency::Critical))
+            .with_actions(vec!["Yes".to_string(), "No".to_string()])
+            .with_action_labels([("Yes".to_string(), "Yes: reboot".to_string())].into())
+            .with_sender(Some(Arc::from("backup.service")))
+            .with_group_key(Some("backups".to_string()));
+        let args = HelperArgs::new(&user, &payload, &options());
+
+        let parsed = HelperArgs::try_parse_from(std::iter::once(HELPER_NAME.to_string()).chain(args.to_args()))
+            .unwrap();
+        assert_eq!(parsed, args);
+        assert_eq!(parsed.payload(), payload);
+        let parsed_options = parsed.options();
+        assert_eq!(parsed_options.sound, options().sound);
+        assert_eq!(parsed_options.replaces_id, 4);
+        assert_eq!(parsed_options.icon, "dialog-warning");
+        assert_eq!(parsed_options.desktop_entry.as_deref(), Some("backup-service"));
+        assert_eq!(parsed_options.timeout, DEFAULT_EXPIRE_TIMEOUT);
+        assert_eq!(parsed.desktop.as_deref(), Some("KDE"));
+    }
+
+    #[test]
+    fn test_env_and_json_round_trip() {
+        let user = TargetUser::new(1000, "alice".to_string());
+        let payload = BroadcastPayload::new("Reboot", "Tonight\nat 22:00")
+            .with_urgency(Some(Urgency::Low))
+            .with_actions(vec!["Yes".to_string(), "Later".to_string()])
+            .with_action_labels([("Later".to_string(), "Remind me".to_string())].into())
+            .with_group_key(Some("updates".to_string()))
+            .with_progress(Some(60));
+        let (responses, _answers) = tokio::sync::mpsc::unbounded_channel();
+        let args = HelperArgs::new(&user, &payload, &DeliveryOptions { responses: Some(responses), ..options() });
+        assert!(args.listen);
+        assert_eq!(args.payload(), payload);
+
+        let env: std::collections::HashMap<_, _> = args.to_env().into_iter().collect();
+        assert_eq!(HelperArgs::from_env(|name| env.get(name).cloned()).unwrap(), args);
+        assert!(!env.contains_key("DOTS_NOTIFIER_MUTE"));
+        assert!(HelperArgs::from_env(|_| None).is_err());
+        let overflowing = |name: &str| match name {
+            "DOTS_NOTIFIER_PROGRESS" => Some("101".to_string()),
+            name => env.get(name).cloned(),
+        };
+        assert!(HelperArgs::from_env(overflowing).is_err());
+
+        let json = serde_json::to_string(&args).unwrap();
+        assert_eq!(serde_json::from_str::<HelperArgs>(&json).unwrap(), args);
+    }
+
+    #[test]
+    fn test_settings_from_config_and_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let helper = dir.path().join(HELPER_NAME);
+        std::fs::write(&helper, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let config = Config::from_toml_str(&format!(
+            "helper_path = \"{}\"\nhelper_args = \"stdin-json\"\n",
+            helper.display()
+        ))
+        .unwrap();
+
+        let sink = HelperSink::from_settings(&config, |_| None);
+        assert_eq!(sink.helper().unwrap(), helper);
+        assert_eq!(sink.passing, ArgumentPassing::StdinJson);
+
+        let env = |name: &str| match name {
+            HELPER_PATH_ENV => Some(OsString::from("libexec/dots-notifier-helper")),
+            HELPER_ARGS_ENV => Some(OsString::from("env")),
+            _ => None,
+        };
+        let sink = HelperSink::from_settings(&config, env);
+        assert_eq!(sink.helper().unwrap_err(), "helper path libexec/dots-notifier-helper is not absolute");
+        assert_eq!(sink.passing, ArgumentPassing::Env);
+
+        let sink = HelperSink::from_settings(&Config::default(), |name| {
+            (name == "PATH").then(|| dir.path().as_os_str().to_owned())
+        });
+        assert_eq!(sink.helper().unwrap(), helper);
+        assert_eq!(sink.passing, ArgumentPassing::Argv);
+        assert!("pigeon".parse::<ArgumentPassing>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_helper_relays_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let helper = dir.path().join(HELPER_NAME);
+        let script = "#!/bin/sh\n[ \"$1\" = --protocol-version ] && echo 2 && exit 0\n\
+                      echo 7\necho 'action ack'\necho 'unrelated output'\n";
+        std::fs::write(&helper, script).unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let account = nix::unistd::User::from_uid(nix::unistd::geteuid()).unwrap().unwrap();
+        let user = TargetUser::new(account.uid.as_raw(), account.name);
+
+        let (responses, mut answers) = tokio::sync::mpsc::unbounded_channel();
+        let options = DeliveryOptions { responses: Some(responses), ..options() };
+        let payload = Arc::new(BroadcastPayload::new("t", "b").with_actions(vec!["ack".to_string()]));
+        let sink = HelperSink::new(helper);
+        assert_eq!(sink.notify(&user, payload, &options).await.unwrap(), 7);
+        drop(options);
+        assert_eq!(answers.recv().await.as_deref(), Some("ack"));
+        assert_eq!(answers.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_helper_failure_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let helper = dir.path().join(HELPER_NAME);
+        let code = DeliveryErrorKind::Timeout.exit_code();
+        std::fs::write(&helper, format!("#!/bin/sh\necho 'daemon did not answer' >&2\nexit {}\n", code)).unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let account = nix::unistd::User::from_uid(nix::unistd::geteuid()).unwrap().unwrap();
+        let user = TargetUser::new(account.uid.as_raw(), account.name);
+
+        let stats = Arc::new(HelperStats::new());
+        let sink = HelperSink::new(helper).with_stats(stats.clone());
+        let error = sink.notify(&user, Arc::new(BroadcastPayload::new("t", "b")), &options()).await.unwrap_err();
+        assert_eq!(error.kind(), DeliveryErrorKind::Timeout);
+        // Asking the helper for its protocol version is not a run
+        assert_eq!(stats.failures(), vec![(HelperFailure::Exit(code), 1)]);
+        let users = stats.users();
+        assert_eq!((users.len(), users[0].1.spawns, users[0].1.failures), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_args_for_legacy_protocol() {
+        let user = TargetUser::new(1000, "alice".to_string());
+        let payload = BroadcastPayload::new("Reboot", "**Tonight**")
+            .with_actions(vec!["Yes".to_string()])
+            .with_action_labels([("Yes".to_string(), "Reboot now".to_string())].into())
+            .with_group_key(Some("updates".to_string()))
+            .with_markdown(true)
+            .with_progress(Some(40));
+        let args = HelperArgs::new(&user, &payload, &options());
+        assert_eq!(args.for_protocol(PROTOCOL_VERSION), args);
+        assert_eq!(args.for_protocol(2), HelperArgs { progress: None, ..args.clone() });
+
+        let legacy = args.for_protocol(LEGACY_PROTOCOL_VERSION).to_args();
+        let flags: Vec<&str> = legacy.iter().map(String::as_str).filter(|arg| arg.starts_with("--")).collect();
+        let known = [
+            "--app-name", "--bus-name", "--replaces-id", "--urgency", "--sound", "--mute", "--correlation-id",
+            "--sender", "--footer", "--desktop", "--action", "--",
+        ];
+        assert!(flags.iter().all(|flag| known.contains(flag)), "{:?}", flags);
+        assert!(legacy.contains(&"--action".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_helper_gets_argv() {
+        let dir = tempfile::tempdir().unwrap();
+        let helper = dir.path().join(HELPER_NAME);
+        // Rejects what the original helper did not know, like clap would
+        let script = "#!/bin/sh\n\
+                      case \"$*\" in *--protocol-version*|*--args-from-stdin*|*--icon*) exit 2;; esac\n\
+                      echo 9\n";
+        std::fs::write(&helper, script).unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let account = nix::unistd::User::from_uid(nix::unistd::geteuid()).unwrap().unwrap();
+        let user = TargetUser::new(account.uid.as_raw(), account.name);
+
+        let sink = HelperSink::new(helper.clone()).with_passing(ArgumentPassing::StdinJson);
+        let payload = Arc::new(BroadcastPayload::new("t", "b"));
+        assert_eq!(sink.notify(&user, payload.clone(), &options()).await.unwrap(), 9);
+        assert_eq!(sink.protocol.lock().unwrap().map(|(_, version)| version), Some(LEGACY_PROTOCOL_VERSION));
+
+        // Upgraded in place, the helper is asked again
+        let script = "#!/bin/sh\n[ \"$1\" = --protocol-version ] && echo 2 && exit 0\n\
+                      [ \"$1\" = --args-from-stdin ] && echo 10\n";
+        std::fs::write(&helper, script).unwrap();
+        let modified = std::fs::metadata(&helper).unwrap().modified().unwrap();
+        let file = std::fs::File::options().write(true).open(&helper).unwrap();
+        file.set_modified(modified + Duration::from_secs(1)).unwrap();
+        drop(file);
+        assert_eq!(sink.notify(&user, payload, &options()).await.unwrap(), 10);
+        assert_eq!(probe_protocol_version(&helper).await, 2);
+    }
+
+    #[test]
+    fn test_muted_sound() {
+        let options = DeliveryOptions { sound: Sound::Muted, ..options() };
+        let user = TargetUser::new(1000, "alice".to_string());
+        let args = HelperArgs::new(&user, &BroadcastPayload::new("t", "b"), &options);
+        assert!(args.to_args().contains(&"--mute".to_string()));
+        assert_eq!(args.options().sound, Sound::Muted);
+    }
+
+    #[test]
+    fn test_launch_commands() {
+        let user = TargetUser::new(1000, "alice".to_string());
+        let helper = Path::new("/usr/libexec/dots-notifier-helper");
+        let env = vec![("DOTS_NOTIFIER_TITLE", "Reboot".to_string())];
+        let command = |launcher| {
+            let sink = HelperSink::new(helper.to_path_buf()).with_launcher(launcher);
+            let command = sink.launch_command(helper, &user, &env);
+            let command = command.as_std();
+            let args: Vec<String> = command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+            (command.get_program().to_string_lossy().into_owned(), args)
+        };
+
+        let (program, args) = command(Launcher::SystemdRun);
+        assert_eq!(program, "systemd-run");
+        assert!(args.contains(&"--setenv=DOTS_NOTIFIER_TITLE".to_string()));
+        assert!(args.contains(&"--setenv=XDG_RUNTIME_DIR=/run/user/1000".to_string()));
+
+        let (program, args) = command(Launcher::Machinectl);
+        assert_eq!(program, "machinectl");
+        assert_eq!(args[..3], ["shell", "--quiet", "--uid=1000"]);
+        assert!(args.contains(&"--setenv=DOTS_NOTIFIER_TITLE=Reboot".to_string()));
+        assert_eq!(args[args.len() - 2..], [".host", "/usr/libexec/dots-notifier-helper"]);
+
+        let (program, args) = command(Launcher::Sudo);
+        assert_eq!(program, "sudo");
+        assert_eq!(args[..3], ["--non-interactive", "--user=#1000", "--preserve-env=DOTS_NOTIFIER_TITLE"]);
+        assert!(args.contains(&"XDG_RUNTIME_DIR=/run/user/1000".to_string()));
+        assert_eq!(args.last().unwrap(), "/usr/libexec/dots-notifier-helper");
+    }
+
+    #[tokio::test]
+    async fn test_missing_helper_fails_delivery() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = HelperSink::new(dir.path().join(HELPER_NAME));
+        let user = TargetUser::new(1000, "alice".to_string());
+        let error = sink.notify(&user, Arc::new(BroadcastPayload::new("t", "b")), &options()).await.unwrap_err();
+        assert_eq!(error.kind(), DeliveryErrorKind::Other);
+        assert!(error.message().starts_with("cannot deliver through the helper, helper not found"));
+    }
+
+    #[test]
+    fn test_missing_helper_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_var = std::env::join_paths([dir.path()]).unwrap();
+        let error = find_helper(Some(&path_var)).unwrap_err();
+        assert_eq!(error, format!("helper not found at {}", dir.path().join(HELPER_NAME).display()));
+
+        let helper = dir.path().join(HELPER_NAME);
+        std::fs::write(&helper, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(
+            find_helper(Some(&path_var)).unwrap_err(),
+            format!("helper at {} is not executable", helper.display())
+        );
+        assert!(HelperSink::new(helper.clone()).helper().is_err());
+
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(find_helper(Some(&path_var)).unwrap(), helper);
+        assert_eq!(HelperSink::new(helper.clone()).helper().unwrap(), helper);
+    }
+}
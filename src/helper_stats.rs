@@ -0,0 +1,172 @@
+//! Lifecycle statistics of the delivery helper
+//!
+//! Every run of the helper is counted per recipient, with how long it took to
+//! deliver and, if it failed, why: the exit code it reported, or that it could
+//! not be started, did not answer in time or was killed by a signal. Reported
+//! through `GetHelperStats` and as Prometheus metrics, they tell whether delivery
+//! problems lie with the server or with the helper and the user's desktop.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::types::TargetUser;
+
+/// Why a run of the helper failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HelperFailure {
+    /// The helper could not be started or given its request
+    Spawn,
+    /// The helper did not answer in time
+    Timeout,
+    /// The helper exited with a failure code
+    Exit(i32),
+    /// The helper was killed by a signal
+    Signal,
+    /// The helper did not print a notification id
+    NoNotificationId,
+}
+
+impl fmt::Display for HelperFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HelperFailure::Spawn => f.write_str("spawn"),
+            HelperFailure::Timeout => f.write_str("timeout"),
+            HelperFailure::Exit(code) => write!(f, "exit-{}", code),
+            HelperFailure::Signal => f.write_str("signal"),
+            HelperFailure::NoNotificationId => f.write_str("no-notification-id"),
+        }
+    }
+}
+
+/// Runs of the helper for one user
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserHelperStats {
+    pub username: String,
+    /// Number of times the helper was started
+    pub spawns: u64,
+    /// Number of runs that failed
+    pub failures: u64,
+    /// Time all runs took until the helper delivered or failed
+    pub runtime: Duration,
+}
+
+impl UserHelperStats {
+    /// Get how long a run took on average
+    pub fn average_runtime(&self) -> Duration {
+        match self.spawns {
+            0 => Duration::ZERO,
+            spawns => self.runtime.div_f64(spawns as f64),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    users: BTreeMap<u32, UserHelperStats>,
+    failures: BTreeMap<HelperFailure, u64>,
+}
+
+/// Runs of the helper since the server started, per user and by failure
+#[derive(Debug, Default)]
+pub struct HelperStats {
+    counts: Mutex<Counts>,
+}
+
+impl HelperStats {
+    /// Create statistics without any run
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a run of the helper for a user, how long it took and whether it failed
+    pub fn record(&self, user: &TargetUser, runtime: Duration, outcome: Result<(), HelperFailure>) {
+        let mut counts = self.counts.lock().unwrap();
+        let entry = counts.users.entry(user.uid).or_default();
+        entry.username.clone_from(&user.username);
+        entry.spawns += 1;
+        entry.runtime += runtime;
+        if let Err(failure) = outcome {
+            entry.failures += 1;
+            *counts.failures.entry(failure).or_default() += 1;
+        }
+    }
+
+    /// Get the runs of each user, by uid
+    pub fn users(&self) -> Vec<(u32, UserHelperStats)> {
+        let counts = self.counts.lock().unwrap();
+        counts.users.iter().map(|(&uid, stats)| (uid, stats.clone())).collect()
+    }
+
+    /// Get the number of failed runs, by why they failed
+    pub fn failures(&self) -> Vec<(HelperFailure, u64)> {
+        let counts = self.counts.lock().unwrap();
+        counts.failures.iter().map(|(&failure, &count)| (failure, count)).collect()
+    }
+
+    /// Render the statistics in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let users = self.users();
+        let mut out = String::new();
+        out.push_str("# HELP dots_notifier_helper_spawns_total Runs of the delivery helper.\n");
+        out.push_str("# TYPE dots_notifier_helper_spawns_total counter\n");
+        for (uid, stats) in &users {
+            let _ = writeln!(out, "dots_notifier_helper_spawns_total{{{}}} {}", user_labels(*uid, stats), stats.spawns);
+        }
+        out.push_str("# HELP dots_notifier_helper_failures_total Failed runs of the delivery helper, by reason.\n");
+        out.push_str("# TYPE dots_notifier_helper_failures_total counter\n");
+        for (failure, count) in self.failures() {
+            let _ = writeln!(out, "dots_notifier_helper_failures_total{{reason=\"{}\"}} {}", failure, count);
+        }
+        out.push_str("# HELP dots_notifier_helper_runtime_seconds Time runs of the delivery helper took to deliver.\n");
+        out.push_str("# TYPE dots_notifier_helper_runtime_seconds summary\n");
+        for (uid, stats) in &users {
+            let labels = user_labels(*uid, stats);
+            let runtime = stats.runtime.as_secs_f64();
+            let _ = writeln!(out, "dots_notifier_helper_runtime_seconds_sum{{{}}} {}", labels, runtime);
+            let _ = writeln!(out, "dots_notifier_helper_runtime_seconds_count{{{}}} {}", labels, stats.spawns);
+        }
+        out
+    }
+}
+
+/// Get the labels naming a user in Prometheus metrics
+fn user_labels(uid: u32, stats: &UserHelperStats) -> String {
+    let username = stats.username.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("uid=\"{}\",username=\"{}\"", uid, username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_counted_per_user() {
+        let stats = HelperStats::new();
+        let alice = TargetUser::new(1000, "alice".to_string());
+        stats.record(&alice, Duration::from_millis(100), Ok(()));
+        stats.record(&alice, Duration::from_millis(300), Err(HelperFailure::Exit(3)));
+        stats.record(&TargetUser::new(1001, "bob".to_string()), Duration::ZERO, Err(HelperFailure::Spawn));
+
+        let users = stats.users();
+        assert_eq!(users.len(), 2);
+        let (uid, alice) = &users[0];
+        assert_eq!((*uid, alice.spawns, alice.failures), (1000, 2, 1));
+        assert_eq!(alice.average_runtime(), Duration::from_millis(200));
+        assert_eq!(stats.failures(), vec![(HelperFailure::Spawn, 1), (HelperFailure::Exit(3), 1)]);
+        assert_eq!(UserHelperStats::default().average_runtime(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_prometheus_output() {
+        let stats = HelperStats::new();
+        let alice = TargetUser::new(1000, "alice".to_string());
+        stats.record(&alice, Duration::from_millis(250), Err(HelperFailure::Timeout));
+        let metrics = stats.to_prometheus();
+        assert!(metrics.contains("dots_notifier_helper_spawns_total{uid=\"1000\",username=\"alice\"} 1\n"));
+        assert!(metrics.contains("dots_notifier_helper_failures_total{reason=\"timeout\"} 1\n"));
+        assert!(metrics.contains("dots_notifier_helper_runtime_seconds_sum{uid=\"1000\",username=\"alice\"} 0.25\n"));
+        assert_eq!(HelperFailure::Exit(69).to_string(), "exit-69");
+    }
+}
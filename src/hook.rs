@@ -0,0 +1,245 @@
+//! User-context hooks run after a notification is displayed
+//!
+//! A broadcast may name a hook, e.g. to also flash the keyboard backlight or
+//! raise a window. Hooks are only taken from an admin-controlled directory:
+//! the name must be a plain file name, and the directory and the hook must be
+//! owned by root and writable by nobody else. The hook runs with the
+//! recipient's uid and gid, a minimal environment and a time limit.
+
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::session::{session_bus_address, user_runtime_dir};
+use crate::types::TargetUser;
+
+/// Default directory hooks are taken from
+pub const DEFAULT_HOOKS_DIR: &str = "/etc/dots-notifier/hooks";
+
+/// How long a hook may run before it is killed
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Search path given to hooks
+const HOOK_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Details of the delivery passed to a hook through its environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookContext {
+    pub broadcast_id: u64,
+    pub notification_id: u32,
+    pub correlation_id: Option<String>,
+    pub title: String,
+    pub body: String,
+}
+
+/// The directory hooks are allowlisted in
+#[derive(Debug, Clone)]
+pub struct HookDir {
+    path: PathBuf,
+    owner: u32,
+}
+
+impl HookDir {
+    /// Allow hooks from a directory owned by root
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self::with_owner(path, 0)
+    }
+
+    /// Allow hooks from a directory owned by another trusted user
+    pub fn with_owner(path: impl Into<PathBuf>, owner: u32) -> Self {
+        Self {
+            path: path.into(),
+            owner,
+        }
+    }
+
+    /// Get the directory hooks are taken from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Resolve a hook name to the executable it runs, checking it may be trusted
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, String> {
+        validate_hook_name(name)?;
+        self.check_trusted(&self.path, true)
+            .map_err(|e| format!("Hook directory {}: {}", self.path.display(), e))?;
+        let hook = self.path.join(name);
+        self.check_trusted(&hook, false)
+            .map_err(|e| format!("Hook {}: {}", hook.display(), e))?;
+        Ok(hook)
+    }
+
+    /// Check that a path is owned by the trusted owner and not writable by anyone else
+    fn check_trusted(&self, path: &Path, directory: bool) -> Result<(), String> {
+        // Symlinks are not followed, so a hook cannot point outside the directory
+        let meta = std::fs::symlink_metadata(path).map_err(|e| e.to_string())?;
+        match (directory, meta.is_dir(), meta.is_file()) {
+            (true, true, _) | (false, _, true) => {}
+            (true, _, _) => return Err("not a directory".to_string()),
+            (false, _, _) => return Err("not a regular file".to_string()),
+        }
+        if meta.uid() != self.owner {
+            return Err(format!("owned by uid {} instead of {}", meta.uid(), self.owner));
+        }
+        let mode = meta.permissions().mode();
+        if mode & 0o022 != 0 {
+            return Err("writable by group or others".to_string());
+        }
+        if !directory && mode & 0o111 == 0 {
+            return Err("not executable".to_string());
+        }
+        Ok(())
+    }
+
+    /// Run a hook for a user after their notification was displayed
+    pub async fn run(&self, name: &str, user: &TargetUser, context: &HookContext) -> Result<(), String> {
+        let hook = self.resolve(name)?;
+        let account = nix::unistd::User::from_uid(user.uid.into())
+            .map_err(|e| format!("Failed to look up uid {}: {}", user.uid, e))?
+            .ok_or_else(|| format!("Unknown uid {}", user.uid))?;
+
+        let mut command = tokio::process::Command::new(&hook);
+        command
+            .env_clear()
+            .env("PATH", HOOK_PATH)
+            .env("HOME", &account.dir)
+            .env("USER", &account.name)
+            .env("LOGNAME", &account.name)
+            .env("XDG_RUNTIME_DIR", user_runtime_dir(user.uid))
+            .env("DBUS_SESSION_BUS_ADDRESS", session_bus_address(user.uid))
+            .env("DOTS_NOTIFIER_BROADCAST_ID", context.broadcast_id.to_string())
+            .env("DOTS_NOTIFIER_NOTIFICATION_ID", context.notification_id.to_string())
+            .env("DOTS_NOTIFIER_CORRELATION_ID", context.correlation_id.as_deref().unwrap_or_default())
+            .env("DOTS_NOTIFIER_TITLE", &context.title)
+            .env("DOTS_NOTIFIER_BODY", &context.body)
+            .current_dir(&account.dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        // Switching credentials is only needed (and only allowed) when running as someone else
+        if nix::unistd::geteuid().as_raw() != user.uid {
+            command.uid(user.uid).gid(account.gid.as_raw());
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("Failed to start hook {}: {}", hook.display(), e))?;
+        match tokio::time::timeout(HOOK_TIMEOUT, child.wait()).await {
+            Ok(Ok(status)) if status.success() => {
+                debug!(uid = user.uid, hook = %hook.display(), "Hook finished.");
+                Ok(())
+            }
+            Ok(Ok(status)) => Err(format!("Hook {} failed: {}", hook.display(), status)),
+            Ok(Err(e)) => Err(format!("Failed to wait for hook {}: {}", hook.display(), e)),
+            Err(_) => {
+                warn!(uid = user.uid, hook = %hook.display(), "Hook timed out, killing it.");
+                Err(format!("Hook {} timed out after {:?}", hook.display(), HOOK_TIMEOUT))
+            }
+        }
+    }
+}
+
+impl Default for HookDir {
+    fn default() -> Self {
+        Self::new(DEFAULT_HOOKS_DIR)
+    }
+}
+
+/// Check that a hook name is a plain file name
+pub fn validate_hook_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid hook name '{}': expected a file name of letters, digits, '.', '_' and '-'",
+            name
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::Permissions;
+
+    fn current_uid() -> u32 {
+        nix::unistd::getuid().as_raw()
+    }
+
+    fn hook_dir() -> (tempfile::TempDir, HookDir) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), Permissions::from_mode(0o755)).unwrap();
+        let hooks = HookDir::with_owner(dir.path(), current_uid());
+        (dir, hooks)
+    }
+
+    fn write_hook(dir: &Path, name: &str, script: &str, mode: u32) {
+        let path = dir.join(name);
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn test_hook_names() {
+        assert!(validate_hook_name("flash-backlight").is_ok());
+        assert!(validate_hook_name("raise_window.sh").is_ok());
+        assert!(validate_hook_name("").is_err());
+        assert!(validate_hook_name("..").is_err());
+        assert!(validate_hook_name(".hidden").is_err());
+        assert!(validate_hook_name("../bin/sh").is_err());
+        assert!(validate_hook_name("/bin/sh").is_err());
+        assert!(validate_hook_name("a b").is_err());
+    }
+
+    #[test]
+    fn test_resolve_checks_permissions() {
+        let (dir, hooks) = hook_dir();
+        write_hook(dir.path(), "ok", "#!/bin/sh\n", 0o755);
+        write_hook(dir.path(), "writable", "#!/bin/sh\n", 0o775);
+        write_hook(dir.path(), "plain", "#!/bin/sh\n", 0o644);
+        std::os::unix::fs::symlink("/bin/sh", dir.path().join("link")).unwrap();
+
+        assert_eq!(hooks.resolve("ok").unwrap(), dir.path().join("ok"));
+        assert!(hooks.resolve("writable").unwrap_err().contains("writable"));
+        assert!(hooks.resolve("plain").unwrap_err().contains("not executable"));
+        assert!(hooks.resolve("link").unwrap_err().contains("not a regular file"));
+        assert!(hooks.resolve("missing").is_err());
+
+        let untrusted = HookDir::with_owner(dir.path(), current_uid().wrapping_add(1));
+        assert!(untrusted.resolve("ok").unwrap_err().contains("owned by"));
+    }
+
+    #[tokio::test]
+    async fn test_run_passes_context() {
+        let (dir, hooks) = hook_dir();
+        let output = dir.path().join("output");
+        let script = format!(
+            "#!/bin/sh\nprintf '%s %s %s' \"$DOTS_NOTIFIER_BROADCAST_ID\" \"$DOTS_NOTIFIER_TITLE\" \"$USER\" > {}\n",
+            output.display()
+        );
+        write_hook(dir.path(), "record", &script, 0o755);
+        write_hook(dir.path(), "fail", "#!/bin/sh\nexit 3\n", 0o755);
+
+        let account = nix::unistd::User::from_uid(nix::unistd::getuid()).unwrap().unwrap();
+        let user = TargetUser::new(current_uid(), account.name.clone());
+        let context = HookContext {
+            broadcast_id: 7,
+            notification_id: 3,
+            correlation_id: None,
+            title: "Reboot".to_string(),
+            body: "Tonight".to_string(),
+        };
+        hooks.run("record", &user, &context).await.unwrap();
+        assert_eq!(std::fs::read_to_string(output).unwrap(), format!("7 Reboot {}", account.name));
+        assert!(hooks.run("fail", &user, &context).await.is_err());
+    }
+}
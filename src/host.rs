@@ -0,0 +1,138 @@
+//! Context about the sending host appended to broadcasts
+//!
+//! Broadcasts from a fleet look alike on a shared desktop, so the server can
+//! append a line naming the host with its load and uptime. The line is built
+//! from a template with `{hostname}`, `{load1}`, `{load5}`, `{load15}` and
+//! `{uptime}` variables, read from `/proc` when the broadcast is sent.
+
+use std::io;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Template used when host context is enabled without one
+pub const DEFAULT_HOST_CONTEXT_TEMPLATE: &str = "{hostname} · load {load1} · up {uptime}";
+
+/// Configuration of the host context line
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostContextConfig {
+    /// Append the line to the body of every broadcast
+    pub enabled: bool,
+    /// Template of the line
+    pub template: Option<String>,
+}
+
+impl HostContextConfig {
+    /// Get the template of the line, if it is enabled
+    pub fn template(&self) -> Option<&str> {
+        self.enabled
+            .then(|| self.template.as_deref().unwrap_or(DEFAULT_HOST_CONTEXT_TEMPLATE))
+    }
+}
+
+/// State of the host at the time a broadcast is sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostInfo {
+    pub hostname: String,
+    /// Load averages over 1, 5 and 15 minutes
+    pub load: [f64; 3],
+    pub uptime: Duration,
+}
+
+impl HostInfo {
+    /// Read the state of this host
+    pub fn read() -> io::Result<Self> {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")?;
+        let loadavg = std::fs::read_to_string("/proc/loadavg")?;
+        let uptime = std::fs::read_to_string("/proc/uptime")?;
+        Self::parse(&hostname, &loadavg, &uptime).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Unexpected format of /proc/loadavg or /proc/uptime")
+        })
+    }
+
+    /// Parse the contents of the hostname, `/proc/loadavg` and `/proc/uptime` files
+    pub fn parse(hostname: &str, loadavg: &str, uptime: &str) -> Option<Self> {
+        let mut averages = loadavg.split_whitespace().map(|value| value.parse::<f64>().ok());
+        let load = [averages.next()??, averages.next()??, averages.next()??];
+        let uptime = uptime.split_whitespace().next()?.parse::<f64>().ok()?;
+        Some(Self {
+            hostname: hostname.trim().to_string(),
+            load,
+            uptime: Duration::try_from_secs_f64(uptime).ok()?,
+        })
+    }
+
+    /// Fill in the variables of a template; unknown variables are kept as they are
+    pub fn render(&self, template: &str) -> String {
+        let variables = [
+            ("{hostname}", self.hostname.clone()),
+            ("{load1}", format!("{:.2}", self.load[0])),
+            ("{load5}", format!("{:.2}", self.load[1])),
+            ("{load15}", format!("{:.2}", self.load[2])),
+            ("{uptime}", format_uptime(self.uptime)),
+        ];
+        variables
+            .iter()
+            .fold(template.to_string(), |line, (name, value)| line.replace(name, value))
+    }
+}
+
+/// Format an uptime with its two most significant units, e.g. `3d 4h`
+pub fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, hours) => format!("{}h {}m", hours, minutes),
+        (days, hours) => format!("{}d {}h", days, hours),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> HostInfo {
+        HostInfo::parse("build-07\n", "0.52 1.10 0.98 2/711 48122\n", "273840.12 1042731.55\n").unwrap()
+    }
+
+    #[test]
+    fn test_parse_proc_files() {
+        let host = host();
+        assert_eq!(host.hostname, "build-07");
+        assert_eq!(host.load, [0.52, 1.10, 0.98]);
+        assert_eq!(host.uptime.as_secs(), 273840);
+        assert!(HostInfo::parse("h", "0.52 1.10", "1.0").is_none());
+        assert!(HostInfo::parse("h", "0.52 1.10 0.98", "").is_none());
+    }
+
+    #[test]
+    fn test_render_template() {
+        assert_eq!(host().render(DEFAULT_HOST_CONTEXT_TEMPLATE), "build-07 · load 0.52 · up 3d 4h");
+        assert_eq!(host().render("{hostname}: {load5}/{load15} {unknown}"), "build-07: 1.10/0.98 {unknown}");
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(59)), "0m");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 120)), "3h 2m");
+        assert_eq!(format_uptime(Duration::from_secs(2 * 86400 + 3600)), "2d 1h");
+    }
+
+    #[test]
+    fn test_enabled_template() {
+        assert_eq!(HostContextConfig::default().template(), None);
+        let config = HostContextConfig { enabled: true, template: None };
+        assert_eq!(config.template(), Some(DEFAULT_HOST_CONTEXT_TEMPLATE));
+        let config = HostContextConfig { enabled: true, template: Some("{hostname}".to_string()) };
+        assert_eq!(config.template(), Some("{hostname}"));
+    }
+
+    #[test]
+    fn test_read_this_host() {
+        if std::path::Path::new("/proc/loadavg").exists() {
+            assert!(!HostInfo::read().unwrap().hostname.is_empty());
+        }
+    }
+}
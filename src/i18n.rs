@@ -0,0 +1,357 @@
+//! Localization of built-in strings
+//!
+//! Built-in strings are looked up by message id in Fluent catalogs, using the
+//! target user's locale. English and German catalogs are embedded in the binary;
+//! administrators can add or override translations by installing `<lang>.ftl`
+//! files in the locales directory, and pin the locale of users whose session
+//! locale is wrong.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use tracing::warn;
+use unic_langid::LanguageIdentifier;
+
+/// Default directory searched for additional Fluent catalogs
+pub const DEFAULT_LOCALES_DIR: &str = "/usr/share/dots-notifier/locales";
+
+/// Language used when no catalog matches the user's locale
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Path to the AccountsService per-user settings directory
+const ACCOUNTS_SERVICE_DIR: &str = "/var/lib/AccountsService/users";
+
+/// Path to the system-wide locale configuration
+const SYSTEM_LOCALE_CONF: &str = "/etc/locale.conf";
+
+/// Catalogs compiled into the binary
+const BUILTIN_CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+];
+
+/// Looks up localized built-in strings
+pub struct Localizer {
+    bundles: HashMap<LanguageIdentifier, FluentBundle<FluentResource>>,
+}
+
+impl Localizer {
+    /// Create a localizer containing only the built-in catalogs
+    pub fn builtin() -> Self {
+        let mut localizer = Self {
+            bundles: HashMap::new(),
+        };
+        for (lang, source) in BUILTIN_CATALOGS {
+            localizer
+                .add_catalog(lang, source)
+                .expect("built-in catalogs must be valid");
+        }
+        localizer
+    }
+
+    /// Create a localizer from the built-in catalogs plus any `<lang>.ftl` files in `dir`
+    pub fn load(dir: impl AsRef<Path>) -> Self {
+        let mut localizer = Self::builtin();
+        let Ok(entries) = std::fs::read_dir(dir.as_ref()) else {
+            return localizer;
+        };
+
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if path.extension().and_then(|e| e.to_str()) != Some("ftl") {
+                continue;
+            }
+            let Some(lang) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let result = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| localizer.add_catalog(lang, &source));
+            if let Err(e) = result {
+                warn!("Ignoring locale catalog {}: {}", path.display(), e);
+            }
+        }
+        localizer
+    }
+
+    /// Add a Fluent catalog for a language, overriding existing messages with the same id
+    pub fn add_catalog(&mut self, lang: &str, source: &str) -> Result<(), String> {
+        let langid: LanguageIdentifier = lang
+            .parse()
+            .map_err(|e| format!("Invalid language '{}': {}", lang, e))?;
+        let resource = FluentResource::try_new(source.to_string())
+            .map_err(|(_, errors)| format!("Invalid Fluent catalog: {:?}", errors))?;
+
+        let bundle = self.bundles.entry(langid.clone()).or_insert_with(|| {
+            let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+            // Isolation marks would show up verbatim in most notification daemons
+            bundle.set_use_isolating(false);
+            bundle
+        });
+        bundle.add_resource_overriding(resource);
+        Ok(())
+    }
+
+    /// Look up a message for the given locale, falling back to the default language
+    pub fn message(&self, locale: Option<&str>, id: &str) -> String {
+        self.message_with_args(locale, id, &[])
+    }
+
+    /// Look up a message with arguments for the given locale, falling back to the default language
+    ///
+    /// If no catalog contains the message, the message id itself is returned.
+    pub fn message_with_args(&self, locale: Option<&str>, id: &str, args: &[(&str, &str)]) -> String {
+        let fluent_args = (!args.is_empty()).then(|| {
+            let mut fluent_args = FluentArgs::new();
+            for (key, value) in args {
+                fluent_args.set(*key, *value);
+            }
+            fluent_args
+        });
+        self.format(locale, id, fluent_args.as_ref())
+    }
+
+    /// Look up a message with numeric arguments for the given locale, falling back to the default language
+    ///
+    /// Unlike string arguments, numbers select plural variants such as `[one]`.
+    pub fn message_with_numbers(&self, locale: Option<&str>, id: &str, args: &[(&str, u64)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (key, value) in args {
+            fluent_args.set(*key, *value);
+        }
+        self.format(locale, id, Some(&fluent_args))
+    }
+
+    fn format(&self, locale: Option<&str>, id: &str, args: Option<&FluentArgs>) -> String {
+        for langid in candidate_languages(locale) {
+            let Some(bundle) = self.bundles.get(&langid) else {
+                continue;
+            };
+            let Some(pattern) = bundle.get_message(id).and_then(|m| m.value()) else {
+                continue;
+            };
+            let mut errors = vec![];
+            let value = bundle.format_pattern(pattern, args, &mut errors);
+            if !errors.is_empty() {
+                warn!(%langid, id, "Errors while formatting message: {:?}", errors);
+            }
+            return value.into_owned();
+        }
+        id.to_string()
+    }
+}
+
+impl Default for Localizer {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl fmt::Debug for Localizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut languages: Vec<String> = self.bundles.keys().map(|l| l.to_string()).collect();
+        languages.sort();
+        f.debug_struct("Localizer").field("languages", &languages).finish()
+    }
+}
+
+/// Languages to try for a locale, most specific first, ending with the default language
+fn candidate_languages(locale: Option<&str>) -> Vec<LanguageIdentifier> {
+    let mut candidates = vec![];
+    if let Some(langid) = locale.and_then(normalize_locale) {
+        let mut language_only = LanguageIdentifier::default();
+        language_only.language = langid.language;
+        candidates.push(langid);
+        candidates.push(language_only);
+    }
+    candidates.push(DEFAULT_LANGUAGE.parse().expect("default language must be valid"));
+    candidates.dedup();
+    candidates
+}
+
+/// Convert a POSIX locale name such as `de_DE.UTF-8@euro` into a language identifier
+///
+/// Returns `None` for the `C`/`POSIX` locales and unparseable names.
+pub fn normalize_locale(locale: &str) -> Option<LanguageIdentifier> {
+    let base = locale
+        .split(['.', '@'])
+        .next()
+        .unwrap_or_default()
+        .trim();
+    if base.is_empty() || base == "C" || base == "POSIX" {
+        return None;
+    }
+    base.replace('_', "-").parse().ok()
+}
+
+/// Determine the preferred locale of a user
+///
+/// The language stored by AccountsService takes precedence over the system-wide
+/// `LANG` from `/etc/locale.conf`.
+pub fn user_locale(username: &str) -> Option<String> {
+    let accounts_service = Path::new(ACCOUNTS_SERVICE_DIR).join(username);
+    std::fs::read_to_string(accounts_service)
+        .ok()
+        .and_then(|contents| parse_accounts_service_language(&contents))
+        .or_else(|| {
+            std::fs::read_to_string(SYSTEM_LOCALE_CONF)
+                .ok()
+                .and_then(|contents| parse_locale_conf(&contents))
+        })
+}
+
+/// Determine the locale built-in strings are shown to a user in
+///
+/// A locale configured for the user in `overrides` takes precedence over the
+/// one found by [`user_locale`].
+pub fn effective_locale(username: &str, overrides: &HashMap<String, String>) -> Option<String> {
+    overrides.get(username).cloned().or_else(|| user_locale(username))
+}
+
+/// Determine the locale of the running process from `LC_ALL`, `LC_MESSAGES` and `LANG`, in that order
+pub fn process_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Extract the `Language=` setting from an AccountsService user file
+pub fn parse_accounts_service_language(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Language="))
+        .map(|value| value.trim().to_string())
+        .find(|value| !value.is_empty())
+}
+
+/// Extract the `LANG=` setting from a `locale.conf` file
+pub fn parse_locale_conf(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("LANG="))
+        .map(|value| value.trim().trim_matches('"').to_string())
+        .find(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_catalogs() {
+        let localizer = Localizer::builtin();
+        assert_eq!(localizer.message(None, "app-name"), "System Notifier");
+        assert_eq!(localizer.message(Some("de_DE.UTF-8"), "app-name"), "Systembenachrichtigung");
+    }
+
+    #[test]
+    fn test_fallback_to_default_language() {
+        let localizer = Localizer::builtin();
+        assert_eq!(localizer.message(Some("fr_FR.UTF-8"), "app-name"), "System Notifier");
+        assert_eq!(localizer.message(Some("C"), "app-name"), "System Notifier");
+    }
+
+    #[test]
+    fn test_plural_numbers() {
+        let localizer = Localizer::builtin();
+        let body = |locale, count| {
+            localizer.message_with_numbers(locale, "suppressed-body", &[("count", count), ("minutes", 5)])
+        };
+        assert!(body(None, 1).starts_with("One similar alert was suppressed in the last 5 minutes."));
+        assert!(body(None, 12).starts_with("12 similar alerts were suppressed"));
+        assert!(body(Some("de_DE.UTF-8"), 12).starts_with("12 ähnliche Meldungen wurden in den letzten 5 Minuten"));
+
+        let hosts = |locale, hosts| localizer.message_with_numbers(locale, "confirm-hosts", &[("hosts", hosts)]);
+        assert_eq!(hosts(None, 1), "The notification would be sent on one host.");
+        assert_eq!(hosts(Some("de"), 3), "Die Benachrichtigung würde auf 3 Rechnern gesendet.");
+    }
+
+    #[test]
+    fn test_builtin_catalogs_define_the_same_messages() {
+        let ids = |source: &str| {
+            source
+                .lines()
+                .filter(|line| line.starts_with(|c: char| c.is_ascii_lowercase()))
+                .filter_map(|line| line.split_once(" =").map(|(id, _)| id.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let (_, english) = BUILTIN_CATALOGS[0];
+        for (lang, source) in BUILTIN_CATALOGS {
+            assert_eq!(ids(source), ids(english), "catalog {}", lang);
+        }
+    }
+
+    #[test]
+    fn test_missing_message_returns_id() {
+        let localizer = Localizer::builtin();
+        assert_eq!(localizer.message(None, "no-such-message"), "no-such-message");
+    }
+
+    #[test]
+    fn test_add_catalog_overrides_and_extends() {
+        let mut localizer = Localizer::builtin();
+        localizer
+            .add_catalog("fr", "app-name = Notificateur système\ngreeting = Bonjour { $name }")
+            .unwrap();
+        localizer.add_catalog("en", "app-name = Acme Notifier").unwrap();
+
+        assert_eq!(localizer.message(Some("fr_CA"), "app-name"), "Notificateur système");
+        assert_eq!(localizer.message(None, "app-name"), "Acme Notifier");
+        assert_eq!(
+            localizer.message_with_args(Some("fr"), "greeting", &[("name", "Alice")]),
+            "Bonjour Alice"
+        );
+    }
+
+    #[test]
+    fn test_add_catalog_invalid() {
+        let mut localizer = Localizer::builtin();
+        assert!(localizer.add_catalog("not a language!", "a = b").is_err());
+        assert!(localizer.add_catalog("fr", "= broken").is_err());
+    }
+
+    #[test]
+    fn test_load_from_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("nl.ftl"), "app-name = Systeemmelding\n").unwrap();
+        std::fs::write(dir.path().join("README"), "not a catalog").unwrap();
+
+        let localizer = Localizer::load(dir.path());
+        assert_eq!(localizer.message(Some("nl_NL.UTF-8"), "app-name"), "Systeemmelding");
+        assert_eq!(localizer.message(None, "app-name"), "System Notifier");
+    }
+
+    #[test]
+    fn test_effective_locale_prefers_override() {
+        let overrides = HashMap::from([("kiosk".to_string(), "de_DE.UTF-8".to_string())]);
+        assert_eq!(effective_locale("kiosk", &overrides).as_deref(), Some("de_DE.UTF-8"));
+        assert_eq!(effective_locale("nobody-here", &overrides), user_locale("nobody-here"));
+    }
+
+    #[test]
+    fn test_normalize_locale() {
+        assert_eq!(normalize_locale("de_DE.UTF-8").unwrap().to_string(), "de-DE");
+        assert_eq!(normalize_locale("sr_RS@latin").unwrap().to_string(), "sr-RS");
+        assert_eq!(normalize_locale("en").unwrap().to_string(), "en");
+        assert!(normalize_locale("C").is_none());
+        assert!(normalize_locale("POSIX").is_none());
+        assert!(normalize_locale("").is_none());
+    }
+
+    #[test]
+    fn test_parse_accounts_service_language() {
+        let contents = "[User]\nSession=gnome\nLanguage=de_DE.UTF-8\nXSession=\n";
+        assert_eq!(parse_accounts_service_language(contents).as_deref(), Some("de_DE.UTF-8"));
+        assert!(parse_accounts_service_language("[User]\nLanguage=\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_locale_conf() {
+        assert_eq!(parse_locale_conf("LANG=\"en_GB.UTF-8\"\n").as_deref(), Some("en_GB.UTF-8"));
+        assert_eq!(parse_locale_conf("LC_TIME=C\nLANG=nl_NL.UTF-8").as_deref(), Some("nl_NL.UTF-8"));
+        assert!(parse_locale_conf("LC_TIME=C\n").is_none());
+    }
+}
@@ -0,0 +1,453 @@
+//! Images shown with notifications
+//!
+//! Notifications can carry an image, such as a screenshot or a logo, through the
+//! `image-data` hint holding raw pixels or the `image-path` hint naming a file.
+//! Raw pixels are read from binary Netpbm files (PPM for RGB, PAM for RGB or
+//! RGBA), which any image tool can write, and are downscaled so that a large
+//! screenshot does not have to travel across every user's session bus. Files in
+//! other formats are passed to the daemon by path, after checking their size.
+//!
+//! The server may read files its callers cannot, so it reads an image file only
+//! on behalf of a caller who could read it themselves, see [`ImageReader`].
+
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{getgrouplist, Uid, User};
+use zbus::zvariant::{Structure, Value};
+
+/// Largest image file accepted, in bytes
+pub const MAX_IMAGE_FILE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Longest side of images sent as raw pixels; larger ones are downscaled to fit
+pub const MAX_IMAGE_EDGE: u32 = 256;
+
+/// Longest side of an image file read as raw pixels, before downscaling
+const MAX_SOURCE_EDGE: u32 = 8192;
+
+/// Why an image file is not read on behalf of a caller, the same whatever the reason,
+/// so callers learn nothing about files they cannot see
+const UNREADABLE: &str = "the file cannot be read or is too large";
+
+/// An image to show with a notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotificationImage {
+    /// A file the notification daemon reads itself
+    Path(PathBuf),
+    /// Raw pixels sent along with the notification
+    Data(ImageData),
+}
+
+impl NotificationImage {
+    /// Load the image in a file, reading Netpbm files as raw pixels fitting [`MAX_IMAGE_EDGE`]
+    pub fn load(path: &Path) -> Result<Self, ImageError> {
+        check_image_file(path)?;
+        let contents = fs::read(path).map_err(|e| ImageError::new(path, e))?;
+        Self::decode(path, contents)
+    }
+
+    /// Load the image in a file on behalf of a reader, who must be able to read it themselves
+    ///
+    /// The checks are made on the file opened, wherever links led, so swapping the
+    /// path for a link after checking it does not reach other files.
+    pub fn load_as(path: &Path, reader: &ImageReader) -> Result<Self, ImageError> {
+        if !path.is_absolute() {
+            return Err(ImageError::new(path, "the path must be absolute"));
+        }
+        let unreadable = || ImageError::new(path, UNREADABLE);
+        let file = File::open(path).map_err(|_| unreadable())?;
+        let metadata = file.metadata().map_err(|_| unreadable())?;
+        let opened = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).map_err(|_| unreadable())?;
+        if !metadata.is_file() || metadata.len() > MAX_IMAGE_FILE_BYTES || !reader.may_read(&opened, &metadata) {
+            return Err(unreadable());
+        }
+        let mut contents = Vec::new();
+        file.take(MAX_IMAGE_FILE_BYTES).read_to_end(&mut contents).map_err(|_| unreadable())?;
+        Self::decode(path, contents)
+    }
+
+    /// Decode the contents of an image file, keeping files other than Netpbm ones as a path
+    fn decode(path: &Path, contents: Vec<u8>) -> Result<Self, ImageError> {
+        if !contents.starts_with(b"P6") && !contents.starts_with(b"P7") {
+            return Ok(Self::Path(path.to_path_buf()));
+        }
+        let image = ImageData::from_netpbm(&contents).map_err(|e| ImageError::new(path, e))?;
+        Ok(Self::Data(image.downscaled(MAX_IMAGE_EDGE)))
+    }
+}
+
+/// A user image files are read on behalf of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageReader {
+    uid: u32,
+    gids: Vec<u32>,
+}
+
+impl ImageReader {
+    /// Create a reader with the given uid and groups
+    pub fn new(uid: u32, gids: Vec<u32>) -> Self {
+        Self { uid, gids }
+    }
+
+    /// Create a reader for the user with a uid, in the groups the user database lists
+    ///
+    /// A user missing from the database reads with their uid alone.
+    pub fn of_user(uid: u32) -> Self {
+        let gids = User::from_uid(Uid::from_raw(uid)).ok().flatten().and_then(|user| {
+            let name = CString::new(user.name).ok()?;
+            getgrouplist(&name, user.gid).ok()
+        });
+        Self::new(uid, gids.unwrap_or_default().into_iter().map(|gid| gid.as_raw()).collect())
+    }
+
+    /// Create a reader who may only read files anyone can
+    pub fn anyone() -> Self {
+        // No file is owned by the invalid uid
+        Self::new(u32::MAX, Vec::new())
+    }
+
+    /// Whether the reader may read a file, and search every directory leading to it
+    fn may_read(&self, path: &Path, metadata: &Metadata) -> bool {
+        self.permits(metadata, 0o4)
+            && path.ancestors().skip(1).all(|dir| fs::metadata(dir).is_ok_and(|dir| self.permits(&dir, 0o1)))
+    }
+
+    /// Whether the permission bits of a file grant the reader all of `bits`
+    fn permits(&self, metadata: &Metadata, bits: u32) -> bool {
+        if self.uid == 0 {
+            return true;
+        }
+        let shift = if metadata.uid() == self.uid {
+            6
+        } else if self.gids.contains(&metadata.gid()) {
+            3
+        } else {
+            0
+        };
+        (metadata.mode() >> shift) & bits == bits
+    }
+}
+
+/// Check that an image file can be shown: an absolute path to a regular file
+/// no larger than [`MAX_IMAGE_FILE_BYTES`]
+fn check_image_file(path: &Path) -> Result<(), ImageError> {
+    if !path.is_absolute() {
+        return Err(ImageError::new(path, "the path must be absolute"));
+    }
+    let metadata = fs::metadata(path).map_err(|e| ImageError::new(path, e))?;
+    if !metadata.is_file() {
+        return Err(ImageError::new(path, "not a regular file"));
+    }
+    if metadata.len() > MAX_IMAGE_FILE_BYTES {
+        let message = format!("{} bytes exceed the limit of {} bytes", metadata.len(), MAX_IMAGE_FILE_BYTES);
+        return Err(ImageError::new(path, message));
+    }
+    Ok(())
+}
+
+/// Raw 8-bit RGB or RGBA pixels, rows packed without padding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub has_alpha: bool,
+    pub data: Vec<u8>,
+}
+
+impl ImageData {
+    /// Create an image from raw pixels, checking that they fill its dimensions exactly
+    pub fn new(width: u32, height: u32, has_alpha: bool, data: Vec<u8>) -> Result<Self, String> {
+        if width == 0 || height == 0 || width > MAX_SOURCE_EDGE || height > MAX_SOURCE_EDGE {
+            return Err(format!("{}x{} pixels is not a supported image size", width, height));
+        }
+        let channels = if has_alpha { 4 } else { 3 };
+        let expected = width as usize * height as usize * channels;
+        if data.len() != expected {
+            return Err(format!("expected {} bytes of pixels for {}x{}, got {}", expected, width, height, data.len()));
+        }
+        Ok(Self { width, height, has_alpha, data })
+    }
+
+    /// Parse a binary PPM (`P6`) or PAM (`P7`) file with 8-bit samples
+    pub fn from_netpbm(contents: &[u8]) -> Result<Self, String> {
+        match contents.get(..2) {
+            Some(b"P6") => {
+                let mut header = Header::new(&contents[2..]);
+                let width = header.number()?;
+                let height = header.number()?;
+                check_maxval(header.number()?)?;
+                let pixels = header.pixels()?;
+                Self::new(width, height, false, pixels.to_vec())
+            }
+            Some(b"P7") => {
+                let mut header = Header::new(&contents[2..]);
+                let (mut width, mut height, mut depth) = (0, 0, 0);
+                loop {
+                    match header.token()? {
+                        "WIDTH" => width = header.number()?,
+                        "HEIGHT" => height = header.number()?,
+                        "DEPTH" => depth = header.number()?,
+                        "MAXVAL" => check_maxval(header.number()?)?,
+                        "TUPLTYPE" => {
+                            header.token()?;
+                        }
+                        "ENDHDR" => break,
+                        token => return Err(format!("unexpected '{}' in the PAM header", token)),
+                    }
+                }
+                let has_alpha = match depth {
+                    3 => false,
+                    4 => true,
+                    _ => return Err(format!("{} channels are not supported, only RGB and RGBA", depth)),
+                };
+                let pixels = header.pixels()?;
+                Self::new(width, height, has_alpha, pixels.to_vec())
+            }
+            _ => Err("not a binary PPM or PAM file".to_string()),
+        }
+    }
+
+    /// Number of bytes per pixel
+    pub fn channels(&self) -> u32 {
+        if self.has_alpha {
+            4
+        } else {
+            3
+        }
+    }
+
+    /// Get the image shrunk so its longest side is at most `max_edge`, averaging the
+    /// pixels each new one covers; smaller images are returned as they are
+    pub fn downscaled(self, max_edge: u32) -> Self {
+        let longest = self.width.max(self.height);
+        if longest <= max_edge {
+            return self;
+        }
+        let scale = |side: u32| (u64::from(side) * u64::from(max_edge) / u64::from(longest)).max(1) as u32;
+        let (width, height) = (scale(self.width), scale(self.height));
+        let channels = self.channels() as usize;
+        let mut data = Vec::with_capacity(width as usize * height as usize * channels);
+        let span = |index: u32, from: u32, to: u32| {
+            let start = u64::from(index) * u64::from(from) / u64::from(to);
+            let end = (u64::from(index + 1) * u64::from(from)).div_ceil(u64::from(to));
+            start as usize..end as usize
+        };
+        for y in 0..height {
+            let rows = span(y, self.height, height);
+            for x in 0..width {
+                let columns = span(x, self.width, width);
+                let mut sums = [0u64; 4];
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        let offset = (row * self.width as usize + column) * channels;
+                        for (sum, &sample) in sums.iter_mut().zip(&self.data[offset..offset + channels]) {
+                            *sum += u64::from(sample);
+                        }
+                    }
+                }
+                let count = (rows.len() * columns.len()) as u64;
+                data.extend(sums[..channels].iter().map(|sum| (sum / count) as u8));
+            }
+        }
+        Self { width, height, has_alpha: self.has_alpha, data }
+    }
+
+    /// Convert into the `(iiibiiay)` structure of the `image-data` hint
+    pub fn to_value(&self) -> Value<'_> {
+        let width = self.width as i32;
+        let channels = self.channels() as i32;
+        Value::from(Structure::from((
+            width,
+            self.height as i32,
+            width * channels,
+            self.has_alpha,
+            8i32,
+            channels,
+            self.data.as_slice(),
+        )))
+    }
+}
+
+/// Reject samples other than 8 bits
+fn check_maxval(maxval: u32) -> Result<(), String> {
+    match maxval {
+        255 => Ok(()),
+        _ => Err(format!("a maximum sample value of {} is not supported, only 255", maxval)),
+    }
+}
+
+/// Reader of the whitespace separated tokens of a Netpbm header
+struct Header<'a> {
+    contents: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Header<'a> {
+    fn new(contents: &'a [u8]) -> Self {
+        Self { contents, position: 0 }
+    }
+
+    /// Read the next token, skipping whitespace and comments
+    fn token(&mut self) -> Result<&'a str, String> {
+        loop {
+            match self.contents.get(self.position) {
+                Some(c) if c.is_ascii_whitespace() => self.position += 1,
+                Some(b'#') => {
+                    while self.contents.get(self.position).is_some_and(|&c| c != b'\n') {
+                        self.position += 1;
+                    }
+                }
+                Some(_) => break,
+                None => return Err("the header ends early".to_string()),
+            }
+        }
+        let start = self.position;
+        while self.contents.get(self.position).is_some_and(|c| !c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.contents[start..self.position]).map_err(|_| "invalid header".to_string())
+    }
+
+    /// Read the next token as a number
+    fn number(&mut self) -> Result<u32, String> {
+        let token = self.token()?;
+        token.parse().map_err(|_| format!("expected a number in the header, got '{}'", token))
+    }
+
+    /// Get the pixels following the single whitespace character that ends the header
+    fn pixels(self) -> Result<&'a [u8], String> {
+        match self.contents.get(self.position) {
+            Some(c) if c.is_ascii_whitespace() => Ok(&self.contents[self.position + 1..]),
+            _ => Err("no pixels follow the header".to_string()),
+        }
+    }
+}
+
+/// Why an image cannot be shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageError {
+    path: PathBuf,
+    message: String,
+}
+
+impl ImageError {
+    fn new(path: &Path, message: impl fmt::Display) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot show image {}: {}", self.path.display(), self.message)
+    }
+}
+
+impl Error for ImageError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ppm(width: u32, height: u32, pixel: [u8; 3]) -> Vec<u8> {
+        let mut contents = format!("P6\n# test image\n{} {}\n255\n", width, height).into_bytes();
+        contents.extend(pixel.repeat((width * height) as usize));
+        contents
+    }
+
+    #[test]
+    fn test_parse_ppm_and_pam() {
+        let image = ImageData::from_netpbm(&ppm(2, 1, [255, 0, 0])).unwrap();
+        assert_eq!((image.width, image.height, image.has_alpha), (2, 1, false));
+        assert_eq!(image.data, vec![255, 0, 0, 255, 0, 0]);
+
+        let mut pam = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n".to_vec();
+        pam.extend([1, 2, 3, 4]);
+        let image = ImageData::from_netpbm(&pam).unwrap();
+        assert!(image.has_alpha);
+        assert_eq!(image.data, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_invalid_images_rejected() {
+        let mut truncated = ppm(2, 2, [0, 0, 0]);
+        truncated.pop();
+        assert!(ImageData::from_netpbm(&truncated).is_err());
+        assert!(ImageData::from_netpbm(b"P6\n1 1\n65535\n\0\0\0\0\0\0").is_err());
+        assert!(ImageData::from_netpbm(b"P3\n1 1\n255\n0 0 0").is_err());
+        assert!(ImageData::new(0, 1, false, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_downscaled_to_fit() {
+        let image = ImageData::from_netpbm(&ppm(1024, 512, [10, 20, 30])).unwrap().downscaled(MAX_IMAGE_EDGE);
+        assert_eq!((image.width, image.height), (256, 128));
+        assert_eq!(image.data.len(), 256 * 128 * 3);
+        assert!(image.data.chunks(3).all(|pixel| pixel == [10, 20, 30]));
+
+        // Averaging a black and a white pixel gives grey
+        let image = ImageData::new(2, 1, false, vec![0, 0, 0, 255, 255, 255]).unwrap().downscaled(1);
+        assert_eq!(image.data, vec![127, 127, 127]);
+        let small = ImageData::new(1, 1, false, vec![1, 2, 3]).unwrap();
+        assert_eq!(small.clone().downscaled(MAX_IMAGE_EDGE), small);
+    }
+
+    #[test]
+    fn test_image_data_hint() {
+        let image = ImageData::new(1, 1, true, vec![1, 2, 3, 4]).unwrap();
+        assert_eq!(image.to_value().value_signature().to_string(), "(iiibiiay)");
+    }
+
+    #[test]
+    fn test_image_files_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let logo = dir.path().join("logo.png");
+        fs::write(&logo, b"not really a png").unwrap();
+        assert_eq!(NotificationImage::load(&logo).unwrap(), NotificationImage::Path(logo.clone()));
+        let screenshot = dir.path().join("screenshot.ppm");
+        fs::write(&screenshot, ppm(512, 512, [0, 0, 0])).unwrap();
+        match NotificationImage::load(&screenshot).unwrap() {
+            NotificationImage::Data(image) => assert_eq!((image.width, image.height), (256, 256)),
+            image => panic!("expected raw pixels, got {:?}", image),
+        }
+        assert!(check_image_file(Path::new("logo.png")).is_err());
+        assert!(check_image_file(dir.path()).is_err());
+        assert!(check_image_file(&dir.path().join("missing.png")).is_err());
+    }
+
+    #[test]
+    fn test_images_loaded_only_if_reader_may() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        let screenshot = dir.path().join("screenshot.ppm");
+        fs::write(&screenshot, ppm(2, 2, [0, 0, 0])).unwrap();
+        let owner = fs::metadata(&screenshot).unwrap().uid();
+        let stranger = ImageReader::new(owner + 4242, Vec::new());
+        assert!(matches!(NotificationImage::load_as(&screenshot, &stranger), Ok(NotificationImage::Data(_))));
+
+        fs::set_permissions(&screenshot, fs::Permissions::from_mode(0o600)).unwrap();
+        let denied = NotificationImage::load_as(&screenshot, &stranger).unwrap_err();
+        let missing = NotificationImage::load_as(&dir.path().join("missing.ppm"), &stranger).unwrap_err();
+        assert_eq!(denied.message, missing.message);
+        assert!(NotificationImage::load_as(&screenshot, &ImageReader::new(owner, Vec::new())).is_ok());
+
+        // Readable files in directories the reader cannot search stay hidden
+        fs::set_permissions(&screenshot, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(NotificationImage::load_as(&screenshot, &stranger).is_err());
+        let link = tempfile::tempdir().unwrap();
+        fs::set_permissions(link.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink(&screenshot, link.path().join("link.ppm")).unwrap();
+        assert!(NotificationImage::load_as(&link.path().join("link.ppm"), &stranger).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+//! Shutdown and sleep inhibitor locks
+//!
+//! While a broadcast is being delivered the server holds a logind "delay"
+//! inhibitor, so a shutdown racing the broadcast waits for the deliveries to
+//! finish instead of cutting off half the users. Delay locks are bounded by
+//! logind's `InhibitDelayMaxSec`, so a stuck delivery cannot block shutdown.
+
+use tracing::debug;
+use zbus::zvariant::OwnedFd;
+use zbus::Connection;
+
+use crate::dbus::LoginManagerProxy;
+
+/// What is inhibited while broadcasts are delivered
+pub const INHIBIT_WHAT: &str = "shutdown:sleep";
+
+/// Name the inhibitor is registered under
+pub const INHIBIT_WHO: &str = "dots-notifier";
+
+/// An inhibitor lock, released when dropped
+#[derive(Debug)]
+pub struct InhibitorLock {
+    _fd: OwnedFd,
+}
+
+impl InhibitorLock {
+    /// Take a delay inhibitor lock on shutdown and sleep
+    pub async fn delay(why: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let sys_bus = Connection::system().await?;
+        let manager_proxy = LoginManagerProxy::new(&sys_bus).await?;
+        let fd = manager_proxy.inhibit(INHIBIT_WHAT, INHIBIT_WHO, why, "delay").await?;
+        debug!(why, "Took shutdown/sleep delay inhibitor.");
+        Ok(Self { _fd: fd })
+    }
+}
+
+impl Drop for InhibitorLock {
+    fn drop(&mut self) {
+        debug!("Releasing shutdown/sleep delay inhibitor.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(INHIBIT_WHAT.split(':').collect::<Vec<_>>(), vec!["shutdown", "sleep"]);
+        assert_eq!(INHIBIT_WHO, "dots-notifier");
+    }
+
+    // Note: InhibitorLock::delay() requires an actual D-Bus connection to logind
+}
@@ -0,0 +1,177 @@
+//! Inspection of user sessions, for debugging deliveries
+//!
+//! Reports what the server sees of a logind session: the session bus and
+//! runtime directory it would deliver through, and the notification daemon
+//! answering there with its capabilities. Failures along the way are reported
+//! rather than returned, so a single call shows where delivery breaks down.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::Connection;
+
+use crate::config::Config;
+use crate::dbus::{LoginManagerProxy, SessionProxy};
+use crate::error::{NotifierError, NotifierResult};
+use crate::nested::nested_bus_addresses;
+use crate::notification::{inspect_notification_server, NotificationServerInfo};
+use crate::profile::profile_name;
+use crate::session::{connect_system_bus, session_bus_address, user_runtime_dir};
+use crate::types::TargetUser;
+
+/// What the server sees of a session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionReport {
+    pub session_id: String,
+    pub uid: u32,
+    pub username: String,
+    pub session_type: String,
+    pub desktop: String,
+    pub active: bool,
+    /// Address notifications to the user are sent to
+    pub bus_address: String,
+    /// Session buses of nested sessions of the user, notified only if configured
+    pub nested_bus_addresses: Vec<String>,
+    pub runtime_dir: String,
+    pub runtime_dir_exists: bool,
+    /// Bus name the notification daemon is expected to own
+    pub daemon_bus_name: String,
+    /// The daemon answering for that name, if any
+    pub daemon: Option<NotificationServerInfo>,
+    /// Why the daemon could not be reached
+    pub error: Option<String>,
+}
+
+impl SessionReport {
+    /// Inspect a logind session by its id
+    ///
+    /// Only failing to look the session up is an error.
+    pub async fn inspect(session_id: &str, config: &Config) -> NotifierResult<Self> {
+        let sys_bus = connect_system_bus().await?;
+        let mut report = Self::describe(&sys_bus, session_id)
+            .await
+            .map_err(NotifierError::SessionEnumeration)?;
+        report.locate(config);
+
+        let mut user =
+            TargetUser::new(report.uid, report.username.clone()).with_session_type(report.session_type.clone());
+        if !report.desktop.is_empty() {
+            user = user.with_desktop(report.desktop.clone());
+        }
+        match inspect_notification_server(&user, &report.daemon_bus_name).await {
+            Ok(daemon) => report.daemon = Some(daemon),
+            Err(e) => report.error = Some(e.to_string()),
+        }
+        Ok(report)
+    }
+
+    /// Describe a logind session as logind reports it
+    async fn describe(sys_bus: &Connection, session_id: &str) -> zbus::Result<Self> {
+        let session_path = LoginManagerProxy::new(sys_bus).await?.get_session(session_id).await?;
+        let session = SessionProxy::builder(sys_bus).path(session_path)?.build().await?;
+        let (uid, _user_path) = session.user().await?;
+        Ok(Self {
+            session_id: session_id.to_string(),
+            uid,
+            username: session.name().await?,
+            session_type: session.session_type().await?,
+            desktop: session.desktop().await?,
+            active: session.active().await?,
+            ..Self::default()
+        })
+    }
+
+    /// Fill in where notifications to the session's user are delivered
+    fn locate(&mut self, config: &Config) {
+        self.bus_address = session_bus_address(self.uid);
+        self.nested_bus_addresses = nested_bus_addresses(self.uid);
+        self.runtime_dir = user_runtime_dir(self.uid);
+        self.runtime_dir_exists = Path::new(&self.runtime_dir).is_dir();
+        let desktop = Some(self.desktop.as_str()).filter(|desktop| !desktop.is_empty());
+        self.daemon_bus_name = config.notification_bus_name(desktop).to_string();
+    }
+
+    /// Convert the report into a dictionary of values keyed by name
+    ///
+    /// Details of the daemon are omitted if it could not be reached.
+    pub fn to_dict(&self) -> HashMap<String, OwnedValue> {
+        let mut dict = HashMap::new();
+        let mut insert = |name: &str, value: Value<'_>| {
+            if let Ok(value) = OwnedValue::try_from(value) {
+                dict.insert(name.to_string(), value);
+            }
+        };
+        insert("session_id", Value::from(self.session_id.as_str()));
+        insert("uid", Value::from(self.uid));
+        insert("username", Value::from(self.username.as_str()));
+        insert("session_type", Value::from(self.session_type.as_str()));
+        insert("desktop", Value::from(self.desktop.as_str()));
+        insert("active", Value::from(self.active));
+        insert("bus_address", Value::from(self.bus_address.as_str()));
+        insert("nested_bus_addresses", Value::from(self.nested_bus_addresses.clone()));
+        insert("runtime_dir", Value::from(self.runtime_dir.as_str()));
+        insert("runtime_dir_exists", Value::from(self.runtime_dir_exists));
+        insert("daemon_bus_name", Value::from(self.daemon_bus_name.as_str()));
+        if let Some(daemon) = &self.daemon {
+            insert("daemon_owner", Value::from(daemon.owner.as_str()));
+            let server = format!("{} {} ({})", daemon.name, daemon.version, daemon.vendor);
+            insert("daemon_server", Value::from(server));
+            insert("daemon_spec_version", Value::from(daemon.spec_version.as_str()));
+            insert("daemon_capabilities", Value::from(daemon.capabilities.clone()));
+            insert("daemon_profile", Value::from(profile_name(&daemon.name)));
+        }
+        if let Some(error) = &self.error {
+            insert("error", Value::from(error.as_str()));
+        }
+        dict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_uses_configured_daemon() {
+        let config = Config::from_toml_str("[notification_services]\nsway = \"org.example.Proxy\"\n").unwrap();
+        let mut report = SessionReport {
+            uid: 4242,
+            desktop: "sway".to_string(),
+            ..SessionReport::default()
+        };
+        report.locate(&config);
+        assert_eq!(report.runtime_dir, "/run/user/4242");
+        assert_eq!(report.bus_address, "unix:path=/run/user/4242/bus");
+        assert_eq!(report.daemon_bus_name, "org.example.Proxy");
+
+        report.desktop.clear();
+        report.locate(&config);
+        assert_eq!(report.daemon_bus_name, "org.freedesktop.Notifications");
+    }
+
+    #[test]
+    fn test_dict_reports_daemon_or_error() {
+        let mut report = SessionReport {
+            session_id: "3".to_string(),
+            error: Some("no notification daemon owns org.freedesktop.Notifications".to_string()),
+            ..SessionReport::default()
+        };
+        let dict = report.to_dict();
+        assert_eq!(String::try_from(dict["session_id"].clone()).unwrap(), "3");
+        assert!(dict.contains_key("error"));
+        assert!(!dict.contains_key("daemon_owner"));
+
+        report.error = None;
+        report.daemon = Some(NotificationServerInfo {
+            owner: ":1.12".to_string(),
+            capabilities: vec!["actions".to_string(), "body".to_string()],
+            ..NotificationServerInfo::default()
+        });
+        let dict = report.to_dict();
+        assert_eq!(String::try_from(dict["daemon_owner"].clone()).unwrap(), ":1.12");
+        assert!(dict.contains_key("daemon_capabilities"));
+        assert!(dict.contains_key("daemon_profile"));
+        assert!(!dict.contains_key("error"));
+    }
+}
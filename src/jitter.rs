@@ -0,0 +1,106 @@
+//! Per-host jitter of scheduled fire times
+//!
+//! Hosts of a fleet sharing a configuration would otherwise release spooled
+//! broadcasts at the same second, e.g. when a common delivery window opens.
+//! Each host shifts its fire times by a random offset up to a configured
+//! maximum. The offset is derived from the machine id, so it is stable across
+//! restarts while still spreading the fleet.
+
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::time::Duration;
+
+use tracing::debug;
+
+/// File holding the id of the host, used to derive its offset
+pub const MACHINE_ID_PATH: &str = "/etc/machine-id";
+
+/// Offset applied to the scheduled fire times of this host
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Jitter {
+    offset: Duration,
+}
+
+impl Jitter {
+    /// No jitter
+    pub const NONE: Jitter = Jitter { offset: Duration::ZERO };
+
+    /// Jitter with a fixed offset
+    pub fn fixed(offset: Duration) -> Self {
+        Self { offset }
+    }
+
+    /// Derive an offset below `max` from a seed, such as the machine id
+    pub fn from_seed(seed: &str, max: Duration) -> Self {
+        // DefaultHasher::new() uses fixed keys, so the offset is stable across restarts
+        let mut hasher = DefaultHasher::new();
+        seed.trim().hash(&mut hasher);
+        Self::from_hash(hasher.finish(), max)
+    }
+
+    /// Derive an offset below `max` for this host
+    ///
+    /// Hosts without a machine id get a random offset for each start instead.
+    pub fn for_host(max: Duration) -> Self {
+        let jitter = match std::fs::read_to_string(MACHINE_ID_PATH) {
+            Ok(machine_id) if !machine_id.trim().is_empty() => Self::from_seed(&machine_id, max),
+            _ => Self::from_hash(RandomState::new().build_hasher().finish(), max),
+        };
+        debug!(offset_secs = jitter.offset.as_secs(), "Derived jitter of scheduled fire times.");
+        jitter
+    }
+
+    fn from_hash(hash: u64, max: Duration) -> Self {
+        let max_ms = max.as_millis().min(u64::MAX as u128) as u64;
+        let offset = if max_ms == 0 { 0 } else { hash % (max_ms + 1) };
+        Self::fixed(Duration::from_millis(offset))
+    }
+
+    /// Get the offset fire times are shifted by
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    /// Shift a wall-clock time back by the offset, so schedules checked
+    /// against it fire the offset later than configured
+    pub fn shift<T>(&self, time: T) -> T
+    where
+        T: std::ops::Sub<chrono::TimeDelta, Output = T>,
+    {
+        time - chrono::TimeDelta::from_std(self.offset).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    #[test]
+    fn test_offset_stable_and_bounded() {
+        let max = Duration::from_secs(300);
+        let jitter = Jitter::from_seed("0123456789abcdef0123456789abcdef\n", max);
+        assert_eq!(jitter, Jitter::from_seed("0123456789abcdef0123456789abcdef", max));
+        assert!(jitter.offset() <= max);
+
+        let offsets: std::collections::HashSet<_> = (0..20)
+            .map(|host| Jitter::from_seed(&format!("host-{}", host), max).offset())
+            .collect();
+        assert!(offsets.len() > 1);
+    }
+
+    #[test]
+    fn test_no_jitter_without_maximum() {
+        assert_eq!(Jitter::from_seed("host", Duration::ZERO), Jitter::NONE);
+        assert_eq!(Jitter::for_host(Duration::ZERO), Jitter::NONE);
+    }
+
+    #[test]
+    fn test_shift_delays_schedules() {
+        let jitter = Jitter::fixed(Duration::from_secs(90));
+        let now = NaiveTime::from_hms_opt(9, 1, 0).unwrap();
+        // A window opening at 09:00 has not opened yet on this host
+        assert_eq!(jitter.shift(now), NaiveTime::from_hms_opt(8, 59, 30).unwrap());
+        assert_eq!(Jitter::NONE.shift(now), now);
+    }
+}
@@ -0,0 +1,185 @@
+//! Journal of dispatched broadcasts
+//!
+//! The broadcast history only tracks broadcasts the server can still update or
+//! close, and forgets them as newer ones arrive. When `journal` is switched on,
+//! every dispatch is also appended as a JSON line to a file in the state
+//! directory, with when it happened, who sent it and how it went for each
+//! recipient. Only a hash of the title is kept, so the journal can tell repeats
+//! of a notification apart without holding on to its content.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::broadcast::BroadcastId;
+use crate::delivery::DeliveryStatus;
+use crate::types::TargetUser;
+
+/// Name of the journal in the state directory
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// How a dispatched broadcast went for one recipient
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalOutcome {
+    pub uid: u32,
+    pub username: String,
+    /// Delivery status, as reported to clients
+    pub status: String,
+}
+
+impl JournalOutcome {
+    /// Get the outcome of a delivery to a user
+    pub fn new(user: &TargetUser, status: &DeliveryStatus) -> Self {
+        Self {
+            uid: user.uid,
+            username: user.username.clone(),
+            status: status.to_string(),
+        }
+    }
+}
+
+/// A dispatched broadcast, as recorded in the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// When the broadcast was dispatched, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub broadcast_id: BroadcastId,
+    /// Name the broadcast is attributed to, if its sender was identified
+    pub sender: Option<String>,
+    /// Hash of the title, see [`title_hash`]
+    pub title_hash: String,
+    pub outcomes: Vec<JournalOutcome>,
+}
+
+/// Get the hash of a title recorded in the journal
+///
+/// This is the 64-bit FNV-1a hash in hexadecimal, which unlike the standard
+/// library's hashers stays the same across Rust releases.
+pub fn title_hash(title: &str) -> String {
+    let hash = title.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Append-only journal of dispatched broadcasts, off unless opened on a file
+#[derive(Debug, Default)]
+pub struct Journal {
+    path: Option<PathBuf>,
+    /// Serializes appends, so concurrent dispatches never interleave their lines
+    lock: Mutex<()>,
+}
+
+impl Journal {
+    /// Open the journal kept in a state directory
+    pub fn open(state_dir: &Path) -> Self {
+        Self {
+            path: Some(state_dir.join(JOURNAL_FILE)),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Whether dispatched broadcasts are recorded
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Record a dispatched broadcast
+    pub fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        line.push('\n');
+        let _guard = self.lock.lock().unwrap();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())
+    }
+
+    /// Read the latest entries, oldest first; all of them if `limit` is 0
+    ///
+    /// Lines that cannot be parsed, such as one cut short by a crash, are skipped.
+    pub fn read(&self, limit: usize) -> io::Result<Vec<JournalEntry>> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!(path = %path.display(), line = index + 1, "Skipping unreadable journal entry: {}", e),
+            }
+        }
+        if limit > 0 && entries.len() > limit {
+            entries.drain(..entries.len() - limit);
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(broadcast_id: BroadcastId) -> JournalEntry {
+        JournalEntry {
+            timestamp: 1_700_000_000 + broadcast_id,
+            broadcast_id,
+            sender: Some("backup.service".to_string()),
+            title_hash: title_hash("Backup done"),
+            outcomes: vec![JournalOutcome {
+                uid: 1000,
+                username: "alice".to_string(),
+                status: "delivered".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_title_hash() {
+        assert_eq!(title_hash(""), "cbf29ce484222325");
+        assert_eq!(title_hash("a"), "af63dc4c8601ec8c");
+        assert_ne!(title_hash("Backup done"), title_hash("Backup failed"));
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(&dir.path().join("state"));
+        assert!(journal.read(0).unwrap().is_empty());
+        for id in 1..=3 {
+            journal.append(&entry(id)).unwrap();
+        }
+        assert_eq!(journal.read(0).unwrap(), vec![entry(1), entry(2), entry(3)]);
+        assert_eq!(journal.read(2).unwrap(), vec![entry(2), entry(3)]);
+
+        // A line cut short by a crash does not hide the others
+        let path = dir.path().join("state").join(JOURNAL_FILE);
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(b"{\"timestamp\":").unwrap();
+        assert_eq!(Journal::open(&dir.path().join("state")).read(0).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_disabled_journal() {
+        let journal = Journal::default();
+        assert!(!journal.is_enabled());
+        journal.append(&entry(1)).unwrap();
+        assert!(journal.read(0).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+//! Delivery latency tracking
+//!
+//! The time each notification takes to reach a user's notification daemon is
+//! recorded per user. Percentiles over recent deliveries are reported through
+//! `GetStats` and as Prometheus metrics, and a session whose daemon is slow
+//! several times in a row is logged, to find desktops with a hung daemon.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::types::TargetUser;
+
+/// Default latency above which a delivery counts as slow
+pub const DEFAULT_SLOW_DELIVERY_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Number of consecutive slow deliveries after which a session is reported
+pub const SLOW_STREAK: u32 = 3;
+
+/// Number of recent deliveries percentiles are computed over
+const WINDOW: usize = 1024;
+
+/// Latency percentiles over recent deliveries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl LatencyStats {
+    /// Compute percentiles over a set of samples
+    pub fn from_samples(samples: impl IntoIterator<Item = Duration>) -> Self {
+        let mut samples: Vec<_> = samples.into_iter().collect();
+        samples.sort_unstable();
+        // Nearest-rank percentile
+        let percentile = |p: usize| match samples.len() {
+            0 => Duration::ZERO,
+            n => samples[(n * p).div_ceil(100).clamp(1, n) - 1],
+        };
+        Self {
+            count: samples.len(),
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+        }
+    }
+}
+
+/// Recent deliveries to one user
+#[derive(Debug, Default)]
+struct UserLatency {
+    username: String,
+    samples: VecDeque<Duration>,
+    slow_streak: u32,
+}
+
+/// Delivery latencies of recent deliveries, overall and per user
+#[derive(Debug)]
+pub struct LatencyTracker {
+    threshold: Duration,
+    overall: Mutex<VecDeque<Duration>>,
+    users: Mutex<HashMap<u32, UserLatency>>,
+}
+
+impl LatencyTracker {
+    /// Create a tracker counting deliveries above a threshold as slow
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            overall: Mutex::new(VecDeque::new()),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record how long a delivery to a user took
+    ///
+    /// Returns true when this delivery makes the user's session consistently
+    /// slow, which is also logged.
+    pub fn record(&self, user: &TargetUser, latency: Duration) -> bool {
+        push_sample(&mut self.overall.lock().unwrap(), latency);
+
+        let mut users = self.users.lock().unwrap();
+        let entry = users.entry(user.uid).or_default();
+        entry.username.clone_from(&user.username);
+        push_sample(&mut entry.samples, latency);
+        if latency <= self.threshold {
+            entry.slow_streak = 0;
+            return false;
+        }
+        entry.slow_streak += 1;
+        if entry.slow_streak != SLOW_STREAK {
+            return false;
+        }
+        let stats = LatencyStats::from_samples(entry.samples.iter().copied());
+        warn!(
+            uid = user.uid,
+            username = %user.username,
+            desktop = user.desktop().unwrap_or_default(),
+            latency_ms = latency.as_millis() as u64,
+            p50_ms = stats.p50.as_millis() as u64,
+            threshold_ms = self.threshold.as_millis() as u64,
+            "Notification daemon is consistently slow, it may be hung."
+        );
+        true
+    }
+
+    /// Get percentiles over recent deliveries to all users
+    pub fn stats(&self) -> LatencyStats {
+        LatencyStats::from_samples(self.overall.lock().unwrap().iter().copied())
+    }
+
+    /// Get percentiles over recent deliveries to each user, keyed by uid
+    pub fn user_stats(&self) -> Vec<(u32, String, LatencyStats)> {
+        let users = self.users.lock().unwrap();
+        let mut stats: Vec<_> = users
+            .iter()
+            .map(|(&uid, entry)| {
+                (uid, entry.username.clone(), LatencyStats::from_samples(entry.samples.iter().copied()))
+            })
+            .collect();
+        stats.sort_by_key(|(uid, _, _)| *uid);
+        stats
+    }
+
+    /// Get the users whose recent deliveries were all slow
+    pub fn slow_users(&self) -> Vec<(u32, String)> {
+        let users = self.users.lock().unwrap();
+        let mut slow: Vec<_> = users
+            .iter()
+            .filter(|(_, entry)| entry.slow_streak >= SLOW_STREAK)
+            .map(|(&uid, entry)| (uid, entry.username.clone()))
+            .collect();
+        slow.sort();
+        slow
+    }
+
+    /// Render the latencies in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP dots_notifier_delivery_latency_seconds Latency of recent notification deliveries.\n");
+        out.push_str("# TYPE dots_notifier_delivery_latency_seconds summary\n");
+        write_summary(&mut out, "", &self.stats());
+        for (uid, username, stats) in self.user_stats() {
+            write_summary(&mut out, &format!("uid=\"{}\",username=\"{}\",", uid, escape_label(&username)), &stats);
+        }
+        out.push_str("# HELP dots_notifier_slow_sessions Sessions whose recent deliveries were all slow.\n");
+        out.push_str("# TYPE dots_notifier_slow_sessions gauge\n");
+        let _ = writeln!(out, "dots_notifier_slow_sessions {}", self.slow_users().len());
+        out
+    }
+}
+
+impl Default for LatencyTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOW_DELIVERY_THRESHOLD)
+    }
+}
+
+/// Add a sample, dropping the oldest once the window is full
+fn push_sample(samples: &mut VecDeque<Duration>, latency: Duration) {
+    if samples.len() == WINDOW {
+        samples.pop_front();
+    }
+    samples.push_back(latency);
+}
+
+/// Write the quantiles and count of one summary series
+fn write_summary(out: &mut String, labels: &str, stats: &LatencyStats) {
+    let name = "dots_notifier_delivery_latency_seconds";
+    for (quantile, value) in [("0.5", stats.p50), ("0.95", stats.p95), ("0.99", stats.p99)] {
+        let _ = writeln!(out, "{}{{{}quantile=\"{}\"}} {}", name, labels, quantile, value.as_secs_f64());
+    }
+    let labels = labels.trim_end_matches(',');
+    let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+    let _ = writeln!(out, "{}_count{} {}", name, labels, stats.count);
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let stats = LatencyStats::from_samples((1..=100).map(ms));
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.p50, ms(50));
+        assert_eq!(stats.p95, ms(95));
+        assert_eq!(stats.p99, ms(99));
+        assert_eq!(LatencyStats::from_samples([]), LatencyStats::default());
+        assert_eq!(LatencyStats::from_samples([ms(7)]).p99, ms(7));
+    }
+
+    #[test]
+    fn test_consistently_slow_sessions_reported_once() {
+        let tracker = LatencyTracker::new(ms(100));
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let bob = TargetUser::new(1001, "bob".to_string());
+
+        assert!(!tracker.record(&alice, ms(500)));
+        assert!(!tracker.record(&alice, ms(500)));
+        assert!(!tracker.record(&bob, ms(10)));
+        assert!(tracker.record(&alice, ms(500)));
+        assert!(!tracker.record(&alice, ms(500)));
+        assert_eq!(tracker.slow_users(), vec![(1000, "alice".to_string())]);
+
+        // A fast delivery ends the streak
+        tracker.record(&alice, ms(10));
+        assert!(tracker.slow_users().is_empty());
+        assert_eq!(tracker.stats().count, 6);
+        assert_eq!(tracker.user_stats()[0].2.count, 5);
+    }
+
+    #[test]
+    fn test_prometheus_output() {
+        let tracker = LatencyTracker::new(ms(100));
+        tracker.record(&TargetUser::new(1000, "al\"ice".to_string()), ms(250));
+        let metrics = tracker.to_prometheus();
+        assert!(metrics.contains("dots_notifier_delivery_latency_seconds{quantile=\"0.5\"} 0.25\n"));
+        assert!(metrics.contains("dots_notifier_delivery_latency_seconds_count 1\n"));
+        assert!(metrics.contains("{uid=\"1000\",username=\"al\\\"ice\",quantile=\"0.99\"} 0.25\n"));
+        assert!(metrics.contains("dots_notifier_delivery_latency_seconds_count{uid=\"1000\",username=\"al\\\"ice\"} 1\n"));
+        assert!(metrics.contains("dots_notifier_slow_sessions 0\n"));
+    }
+
+    #[test]
+    fn test_window_is_bounded() {
+        let tracker = LatencyTracker::default();
+        let user = TargetUser::new(1000, "alice".to_string());
+        for _ in 0..WINDOW + 10 {
+            tracker.record(&user, ms(1));
+        }
+        assert_eq!(tracker.stats().count, WINDOW);
+    }
+}
@@ -5,81 +5,5716 @@
 //! This library provides the core functionality for the dots-notifier application,
 //! including D-Bus communication, user session detection, and notification dispatch.
 
+pub mod absent;
+pub mod archive;
+pub mod audit;
+pub mod bench;
+pub mod backend;
+pub mod broadcast;
+pub mod bus;
+pub mod caller;
 pub mod cli;
+pub mod client;
+pub mod compose;
+pub mod config;
 pub mod dbus;
+pub mod delivery;
+#[cfg(feature = "echo-daemon")]
+pub mod echo;
+pub mod error;
+pub mod fleet;
+pub mod helper;
+pub mod helper_stats;
+pub mod hook;
+pub mod host;
+pub mod i18n;
+pub mod image;
+pub mod inhibit;
+pub mod inspect;
+pub mod jitter;
+pub mod journal;
+pub mod latency;
+pub mod limits;
+pub mod lint;
+pub mod mac;
+pub mod maintenance;
+pub mod markup;
+pub mod migrate;
 pub mod notification;
+pub mod nested;
+pub mod nss;
+pub mod payload;
+pub mod polkit;
+pub mod poll;
+pub mod pool;
+pub mod profile;
+pub mod quarantine;
+pub mod quirks;
+pub mod ratelimit;
+pub mod recovery;
+pub mod recurrence;
+pub mod rejection;
+pub mod request;
+pub mod route;
+pub mod schedule;
 pub mod session;
+pub mod sink;
+pub mod socket;
+pub mod sound;
+pub mod spool;
+pub mod standby;
+pub mod store;
+pub mod suppression;
+pub mod systemd;
+pub mod terminal;
 pub mod types;
+pub mod urgency;
+pub mod window;
 
 #[cfg(test)]
 mod proptests;
 
-use futures::future::join_all;
-use tracing::{error, info, warn};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, error, info, warn, Instrument};
 use zbus::interface;
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Str};
 
-use crate::session::get_active_graphical_users;
-use crate::notification::send_notification_to_user;
+use crate::absent::{AbsentQueue, Audience};
+use crate::archive::{StateArchive, ARCHIVE_VERSION};
+use crate::audit::{summarize, AuditEntry, AuditLog, AUDIT_OK};
+use crate::backend::BackendChain;
+use crate::broadcast::{unix_now, BroadcastId, BroadcastRecord, BroadcastRegistry, Displayed};
+use crate::caller::Caller;
+use crate::compose::{join_fragments, resolve_command, run_command};
+use crate::config::Config;
+use crate::dbus::{
+    BroadcastSummary, DeferralSummary, DeliverySummary, HelperStatsSummary, HistoryEntry, QuarantineSummary,
+    RecurringSummary, RouteSummary, ScheduledSummary, DBUS_PATH,
+};
+use crate::delivery::{DeliveryError, DeliveryErrorKind, DeliveryStatus};
+use crate::error::NotifierError;
+use crate::helper_stats::HelperStats;
+use crate::hook::{HookContext, HookDir};
+use crate::host::HostInfo;
+use crate::i18n::{effective_locale, Localizer};
+use crate::image::{ImageReader, NotificationImage};
+use crate::inhibit::InhibitorLock;
+use crate::inspect::SessionReport;
+use crate::jitter::Jitter;
+use crate::journal::{title_hash, Journal, JournalEntry, JournalOutcome};
+use crate::latency::LatencyTracker;
+use crate::lint::lint;
+use crate::maintenance::{PersistedSwitch, MAINTENANCE_FILE, PAUSED_FILE};
+use crate::session::{find_user, SessionCache, SessionTracker, SESSION_READY_TIMEOUT, SESSION_TRACKING_RETRY};
+use crate::notification::{
+    inspect_notification_server, session_bus_pool, wait_for_notification_server, with_shared_connections,
+};
+use crate::nss::{NssCache, NssResolver};
+use crate::payload::BroadcastPayload;
+use crate::polkit::PolkitMode;
+use crate::poll::PollRegistry;
+use crate::ratelimit::{sender_key, RateLimiter, SendError};
+use crate::rejection::{check_content, Rejection, RejectionRule};
+use crate::request::{parse_action, SendOptions};
+use crate::route::{Deferral, RouteDecision};
+use crate::recurrence::Recurrence;
+use crate::schedule::Scheduler;
+use crate::sink::{DbusSink, DeliveryOptions, NotificationSink};
+use crate::quarantine::{Quarantine, QuarantinedBroadcast};
+use crate::socket::{
+    parse_request, SocketRequest, SocketResponse, MAX_CLIENTS, MAX_REQUEST_BYTES, REQUEST_TIMEOUT, SOCKET_INPUT,
+};
+use crate::spool::{Spool, SpooledNotification};
+use crate::store::{MemoryStore, Store, StoreBackend};
+use crate::suppression::SuppressionCounter;
+use crate::terminal::TerminalSink;
+use crate::types::{TargetUser, Urgency};
+use crate::urgency::infer_urgency;
 
 /// The main NotifierService implementation for D-Bus interface.
-#[derive(Debug, Default)]
-pub struct NotifierService;
+///
+/// The service is a cheap handle to [`ServiceState`]; clones share the same
+/// caches, queues and registries, so it can be served on the bus and driven by
+/// the spool flush loop at the same time.
+#[derive(Debug, Clone, Default)]
+pub struct NotifierService {
+    state: Arc<ServiceState>,
+}
+
+/// State shared by every handle to the service
+///
+/// Each component guards its own data with a short-lived lock or an atomic, and
+/// no lock is held across an await point, so deliveries to many users can run
+/// concurrently without contending on the service as a whole.
+#[derive(Debug)]
+pub struct ServiceState {
+    config: Config,
+    localizer: Localizer,
+    spool: Spool,
+    /// Users whose session bus is watched for a notification daemon to deliver parked notifications
+    daemon_watches: Mutex<HashSet<u32>>,
+    broadcasts: BroadcastRegistry,
+    polls: PollRegistry,
+    scheduler: Scheduler,
+    nss: NssCache,
+    sessions: SessionCache,
+    maintenance: PersistedSwitch,
+    paused: PersistedSwitch,
+    store: Arc<dyn Store>,
+    hooks: HookDir,
+    latency: LatencyTracker,
+    helper_stats: Arc<HelperStats>,
+    jitter: Jitter,
+    journal: Journal,
+    audit: Arc<AuditLog>,
+    rate_limiter: RateLimiter,
+    suppressed: SuppressionCounter,
+    quarantine: Quarantine,
+    /// Broadcasts to all users still delivered to users logging in, until their late join grace ends
+    late_joinable: Mutex<HashSet<BroadcastId>>,
+    /// Broadcasts kept for the users they are meant for who log in later
+    absent: AbsentQueue,
+    /// Users being welcomed after logging in, so a login reported twice delivers once
+    welcoming: Mutex<HashSet<u32>>,
+    /// When the service started, for its uptime
+    started_at: Instant,
+    /// The latest failed delivery: when, in seconds since the Unix epoch, to whom and why
+    last_delivery_error: Mutex<Option<(u64, u32, DeliveryError)>>,
+    /// Permits for deliveries, updates and closes of notifications, shared by every broadcast
+    deliveries: Semaphore,
+    connection: OnceLock<zbus::Connection>,
+    /// Connection polkit is asked through, the service's own when it is on the system bus
+    system_bus: tokio::sync::OnceCell<zbus::Connection>,
+    sink: Arc<dyn NotificationSink>,
+    fallback_sink: Arc<dyn NotificationSink>,
+}
 
-#[interface(name = "me.section.Notifier")]
 impl NotifierService {
-    /// Send notifications to all active graphical users.
-    /// 
-    /// # Arguments
-    /// * `title` - The notification title
-    /// * `body` - The notification body text
-    /// 
-    /// # Returns
-    /// A D-Bus result indicating success or failure
-    pub async fn send_to_all(&self, title: String, body: String) -> zbus::fdo::Result<()> {
-        info!(%title, %body, "Received 'send_to_all' request via D-Bus.");
+    /// Create a new service using the given configuration
+    pub fn new(config: Config) -> Self {
+        let localizer = Localizer::load(config.locales_dir());
+        let nss = NssCache::new(config.nss_cache_ttl());
+        let spool = Spool::with_limit(config.limits.max_pending_bytes());
+        let sessions = SessionCache::new(config.session_cache_ttl());
+        let maintenance = PersistedSwitch::load(config.state_dir(), MAINTENANCE_FILE);
+        if maintenance.is_enabled() {
+            info!("Maintenance mode is on, only critical broadcasts will be delivered.");
+        }
+        let paused = PersistedSwitch::load(config.state_dir(), PAUSED_FILE);
+        if paused.is_enabled() {
+            info!("Delivery is paused, all broadcasts will be spooled.");
+        }
+        let store = store::open(config.store, config.state_dir()).unwrap_or_else(|e| {
+            warn!(
+                "Failed to open the {:?} store, pending notifications will not survive a restart: {}",
+                config.store, e
+            );
+            Arc::new(MemoryStore::new())
+        });
+        if config.persist_notification_ids && config.store == StoreBackend::Memory {
+            warn!("Notification ids are only persisted with a file or sqlite store, not the memory store.");
+        }
+        if config.warm_standby && config.store == StoreBackend::Memory {
+            warn!("A warm standby can only take over pending notifications from a shared file or sqlite store.");
+        }
+        let hooks = HookDir::new(config.hooks_dir());
+        let latency = LatencyTracker::new(config.slow_delivery_threshold());
+        let jitter = Jitter::for_host(config.fire_jitter());
+        let rate_limiter = RateLimiter::new(config.limits.sender_rate_limit());
+        let journal = if config.journal { Journal::open(config.state_dir()) } else { Journal::default() };
+        let audit = Arc::new(AuditLog::open(&config.audit));
+        let helper_stats = Arc::new(HelperStats::new());
+        let sink: Arc<dyn NotificationSink> = Arc::new(BackendChain::from_config(&config, helper_stats.clone()));
+        let deliveries = Semaphore::new(config.max_concurrent_deliveries());
+        let state = ServiceState {
+            config,
+            localizer,
+            spool,
+            daemon_watches: Mutex::default(),
+            broadcasts: BroadcastRegistry::new(),
+            polls: PollRegistry::new(),
+            scheduler: Scheduler::new(),
+            nss,
+            sessions,
+            maintenance,
+            paused,
+            store,
+            hooks,
+            latency,
+            helper_stats,
+            jitter,
+            journal,
+            audit,
+            rate_limiter,
+            suppressed: SuppressionCounter::new(),
+            quarantine: Quarantine::new(),
+            late_joinable: Mutex::default(),
+            absent: AbsentQueue::default(),
+            welcoming: Mutex::default(),
+            started_at: Instant::now(),
+            last_delivery_error: Mutex::default(),
+            deliveries,
+            connection: OnceLock::new(),
+            system_bus: tokio::sync::OnceCell::new(),
+            sink,
+            fallback_sink: Arc::new(TerminalSink::new()),
+        };
+        Self { state: Arc::new(state) }
+    }
 
-        let users = match get_active_graphical_users().await {
-            Ok(users) => users,
-            Err(e) => {
-                error!("Failed to get active users: {}", e);
-                return Err(zbus::fdo::Error::Failed(e.to_string()));
+    /// Get the state for configuration, which is only possible before the service is shared
+    fn state_mut(&mut self) -> &mut ServiceState {
+        Arc::get_mut(&mut self.state).expect("service must be configured before it is cloned")
+    }
+
+    /// Deliver notifications through a different sink
+    pub fn with_sink(mut self, sink: Arc<dyn NotificationSink>) -> Self {
+        self.state_mut().sink = sink;
+        self
+    }
+
+    /// Deliver to a different sink when a user has no notification daemon
+    pub fn with_fallback_sink(mut self, fallback_sink: Arc<dyn NotificationSink>) -> Self {
+        self.state_mut().fallback_sink = fallback_sink;
+        self
+    }
+
+    /// Only notify the user owning the session bus the server runs on
+    pub fn with_session_owner(mut self, owner: TargetUser) -> Self {
+        let ttl = self.state.config.session_cache_ttl();
+        self.state_mut().sessions = SessionCache::for_owner(ttl, owner);
+        self
+    }
+
+    /// Keep snapshots of the state in a different store
+    pub fn with_store(mut self, store: Arc<dyn Store>) -> Self {
+        self.state_mut().store = store;
+        self
+    }
+
+    /// Shift scheduled fire times by a different jitter
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.state_mut().jitter = jitter;
+        self
+    }
+
+    /// Take the hooks broadcasts may ask to run from a different directory
+    pub fn with_hooks(mut self, hooks: HookDir) -> Self {
+        self.state_mut().hooks = hooks;
+        self
+    }
+
+    /// Look up users and groups through a different resolver
+    pub fn with_nss_resolver(mut self, resolver: Arc<dyn NssResolver>) -> Self {
+        let ttl = self.state.config.nss_cache_ttl();
+        self.state_mut().nss = NssCache::with_resolver(ttl, resolver);
+        self
+    }
+
+    /// Identify callers through the connection the service is served on
+    pub fn set_connection(&self, connection: zbus::Connection) {
+        let _ = self.state.connection.set(connection);
+    }
+
+    /// Ask polkit through a connection to the system bus, rather than opening one when first needed
+    pub fn set_system_bus(&self, connection: zbus::Connection) {
+        let _ = self.state.system_bus.set(connection);
+    }
+
+    /// Get the notifications held back until their recipients' delivery windows open
+    pub fn spool(&self) -> &Spool {
+        &self.state.spool
+    }
+
+    /// Get the registry of recent broadcasts
+    pub fn broadcasts(&self) -> &BroadcastRegistry {
+        &self.state.broadcasts
+    }
+
+    /// Get the registry of polls and their answers
+    pub fn polls(&self) -> &PollRegistry {
+        &self.state.polls
+    }
+
+    /// Get the latencies of recent deliveries
+    pub fn latency(&self) -> &LatencyTracker {
+        &self.state.latency
+    }
+
+    /// Get the cache of user and group lookups
+    pub fn nss(&self) -> &NssCache {
+        &self.state.nss
+    }
+
+    /// Get the cache of active graphical users
+    pub fn sessions(&self) -> &SessionCache {
+        &self.state.sessions
+    }
+
+    /// Whether only critical broadcasts are currently delivered
+    pub fn in_maintenance(&self) -> bool {
+        self.state.maintenance.is_enabled()
+    }
+
+    /// Switch maintenance mode on or off, delivering held back notifications when it ends
+    pub async fn set_maintenance_mode(&self, enabled: bool) -> zbus::fdo::Result<()> {
+        let persisted = self.state.maintenance.set(enabled);
+        info!(enabled, "Maintenance mode switched.");
+        if !enabled {
+            self.flush_spool().await;
+        }
+        persisted.map_err(|e| {
+            zbus::fdo::Error::Failed(format!("Maintenance mode changed but will not survive a restart: {}", e))
+        })
+    }
+
+    /// Whether all delivery is currently paused
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.is_enabled()
+    }
+
+    /// Pause or resume all delivery, delivering held back notifications on resuming
+    pub async fn set_paused(&self, paused: bool) -> zbus::fdo::Result<()> {
+        let persisted = self.state.paused.set(paused);
+        info!(paused, "Delivery paused or resumed.");
+        if !paused {
+            self.flush_spool().await;
+        }
+        persisted.map_err(|e| {
+            zbus::fdo::Error::Failed(format!("Delivery paused or resumed but will not survive a restart: {}", e))
+        })
+    }
+
+    /// Take a snapshot of the state worth keeping across hosts and upgrades
+    pub fn export_archive(&self) -> StateArchive {
+        StateArchive {
+            version: ARCHIVE_VERSION,
+            maintenance: self.state.maintenance.is_enabled(),
+            paused: self.state.paused.is_enabled(),
+            broadcasts: self.state.broadcasts.list(None),
+            spool: self.state.spool.entries(),
+            in_flight: self.state.spool.in_flight(),
+            polls: self.state.polls.list(),
+            scheduled: self.state.scheduler.list(),
+        }
+    }
+
+    /// Merge a snapshot into the current state, returning the number of items restored
+    ///
+    /// Broadcasts, polls and scheduled broadcasts replace ones with the same id, and pending
+    /// notifications are added to the spool as long as it has room for them.
+    /// Notifications that may already have been shown are not delivered again,
+    /// see [`recovery`].
+    pub async fn import_archive(&self, mut archive: StateArchive) -> zbus::fdo::Result<u32> {
+        for problem in recovery::repair(&mut archive) {
+            warn!("Recovering saved state: {}", problem);
+        }
+        let mut imported = self.state.broadcasts.import(archive.broadcasts);
+        for (id, poll) in archive.polls {
+            self.state.polls.insert(id, poll);
+            imported += 1;
+        }
+        imported += self.state.scheduler.import(archive.scheduled);
+        for entry in archive.spool {
+            match self.state.spool.push(entry) {
+                Ok(()) => imported += 1,
+                Err(e) => warn!("Dropping imported notification that cannot be spooled: {}", e),
+            }
+        }
+        if archive.maintenance != self.state.maintenance.is_enabled() {
+            self.set_maintenance_mode(archive.maintenance).await?;
+        }
+        if archive.paused != self.state.paused.is_enabled() {
+            self.set_paused(archive.paused).await?;
+        }
+        info!(imported, "Imported server state.");
+        Ok(imported as u32)
+    }
+
+    /// Restore the snapshot last saved to the store, returning the number of items restored
+    pub async fn restore_state(&self) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+        match self.state.store.load()? {
+            Some(snapshot) => Ok(self.import_archive(snapshot).await?),
+            None => Ok(0),
+        }
+    }
+
+    /// Save a snapshot of the state to the store
+    pub fn save_state(&self) {
+        if let Err(e) = self.state.store.save(&self.export_archive()) {
+            error!("Failed to save the server state: {}", e);
+        }
+    }
+
+    /// Save the state after notification ids were recorded or dropped, if they are persisted right away
+    fn persist_notification_ids(&self) {
+        if self.state.config.persist_notification_ids {
+            self.save_state();
+        }
+    }
+
+    /// Whether maintenance mode holds back a payload
+    fn held_for_maintenance(&self, payload: &BroadcastPayload) -> bool {
+        self.state.maintenance.is_enabled() && payload.urgency != Some(Urgency::Critical)
+    }
+
+    /// Enumerate the active sessions ahead of the first broadcast
+    pub async fn prefetch_sessions(&self) {
+        match self.state.sessions.refresh().await {
+            Ok(users) => info!("Prefetched {} active graphical user sessions.", users.len()),
+            Err(e) => warn!("Failed to prefetch active sessions: {}", e),
+        }
+    }
+
+    /// Keep the active sessions up to date by following logind's signals
+    ///
+    /// Users seen logging in are welcomed with what they are owed, such as
+    /// broadcasts kept for absent users. Whenever the signals are lost, such as
+    /// while logind restarts, sessions are enumerated on demand until they can be
+    /// followed again. A server on a session bus only ever notifies its owner and
+    /// has nothing to follow.
+    pub async fn track_sessions(&self) {
+        if self.state.sessions.owner().is_some() {
+            self.prefetch_sessions().await;
+            return;
+        }
+        let (logins, mut logged_in) = mpsc::unbounded_channel();
+        let service = self.clone();
+        tokio::spawn(async move {
+            while let Some(user) = logged_in.recv().await {
+                if service.waiting_on_login(&user) > 0 {
+                    info!(uid = user.uid, "Welcoming a user who logged in.");
+                    service.welcome_when_ready(user);
+                }
             }
+        });
+        loop {
+            match SessionTracker::new().run(&self.state.sessions, &logins).await {
+                Ok(()) => info!("logind stopped signalling session changes, enumerating sessions on demand."),
+                Err(e) => warn!("Failed to follow session changes, enumerating sessions on demand: {}", e),
+            }
+            tokio::time::sleep(SESSION_TRACKING_RETRY).await;
+        }
+    }
+
+    /// Build the payload of a broadcast, inferring its urgency and checking it against the size limit
+    fn prepare_payload(
+        &self,
+        title: String,
+        body: String,
+        actions: Vec<String>,
+        sender: Option<Arc<str>>,
+    ) -> zbus::fdo::Result<Arc<BroadcastPayload>> {
+        check_content(&title, &body).inspect_err(|e| warn!("Rejecting broadcast: {}", e))?;
+        let urgency = infer_urgency(&self.state.config.urgency_rules, &title, &body);
+        if let Some(urgency) = urgency {
+            info!(%urgency, "Inferred urgency from notification content.");
+        }
+
+        let lint_warnings: Vec<String> = lint(&title, &body).iter().map(ToString::to_string).collect();
+        for warning in &lint_warnings {
+            warn!("Lint: {}", warning);
+        }
+
+        let body = match self.state.config.host_context.template() {
+            Some(template) => match HostInfo::read() {
+                Ok(host) => format!("{}\n\n{}", body, host.render(template)),
+                Err(e) => {
+                    warn!("Failed to read host context: {}", e);
+                    body
+                }
+            },
+            None => body,
         };
+        let payload = BroadcastPayload::new(title, body)
+            .with_urgency(urgency)
+            .with_actions(actions)
+            .with_sender(sender)
+            .with_lint_warnings(lint_warnings);
+        self.check_payload_size(&payload)?;
+        Ok(Arc::new(payload))
+    }
 
-        if users.is_empty() {
-            warn!("No active graphical user sessions found to notify.");
+    /// Check the content of a payload against the size limit
+    fn check_payload_size(&self, payload: &BroadcastPayload) -> zbus::fdo::Result<()> {
+        self.state.config
+            .limits
+            .check_payload(payload.content_size())
+            .map_err(Rejection::from)
+            .inspect_err(|e| warn!("Rejecting broadcast: {}", e))?;
+        Ok(())
+    }
+
+    /// Get the name a call is attributed to, if the caller can be identified and is allowed to send
+    async fn sender(&self, header: &Header<'_>) -> Result<Option<Arc<str>>, SendError> {
+        let caller = self.caller(header).await;
+        self.authorize_polkit(caller.as_ref()).await?;
+        self.authorize_sender(caller)
+    }
+
+    /// Get the name a caller's broadcasts are attributed to, without authorizing it to send
+    fn sender_name(&self, caller: &Caller) -> Arc<str> {
+        caller.display_name(&self.state.config.sender_names).into()
+    }
+
+    /// Reject callers polkit does not authorize to send broadcasts, unless `polkit` is off
+    ///
+    /// Calls that cannot be attributed to a caller, such as when the service is not
+    /// on a bus, are left to `allowed_senders`.
+    async fn authorize_polkit(&self, caller: Option<&Caller>) -> zbus::fdo::Result<()> {
+        let Some(caller) = caller.filter(|_| self.state.config.polkit == PolkitMode::Enforce) else {
             return Ok(());
+        };
+        let authorized = async {
+            let system_bus = self.state.system_bus.get_or_try_init(zbus::Connection::system).await?;
+            // Bus names identify callers to polkit only if they are names on the system bus
+            let on_system_bus = self.state.connection.get()
+                .is_some_and(|connection| connection.unique_name() == system_bus.unique_name());
+            polkit::may_send(system_bus, caller, on_system_bus).await
+        };
+        match authorized.await {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                warn!(%caller, "Rejecting broadcast from a sender polkit does not authorize.");
+                Err(zbus::fdo::Error::AccessDenied("Not authorized to send broadcasts".to_string()))
+            }
+            Err(e) => {
+                warn!(%caller, "Rejecting broadcast, failed to check authorization with polkit: {}", e);
+                Err(zbus::fdo::Error::AccessDenied("Failed to check authorization with polkit".to_string()))
+            }
+        }
+    }
+
+    /// Answer a request, recording it in the audit log if it is on
+    ///
+    /// The request is recorded with the time it arrived, once its outcome is known.
+    async fn audited<T, E: fmt::Display>(
+        &self,
+        header: &Header<'_>,
+        summary: String,
+        request: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let received_at = unix_now();
+        let result = request.await;
+        if self.state.audit.is_enabled() {
+            let method = header.member().map_or_else(String::new, ToString::to_string);
+            let outcome = result.as_ref().map_or_else(ToString::to_string, |_| AUDIT_OK.to_string());
+            let caller = self.caller(header).await;
+            let entry = AuditEntry::new(received_at, &method, caller.as_ref(), summary, outcome);
+            // Appending and rotating touch the disk, so they stay off the runtime's threads
+            let audit = self.state.audit.clone();
+            let appended = tokio::task::spawn_blocking(move || audit.append(&entry))
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+            if let Err(e) = appended {
+                warn!(%method, "Failed to record the request in the audit log: {}", e);
+            }
+        }
+        result
+    }
+
+    /// Identify the caller of a method, if the service is on a bus
+    async fn caller(&self, header: &Header<'_>) -> Option<Caller> {
+        match self.state.connection.get() {
+            Some(connection) => Caller::identify(connection, header).await,
+            None => None,
+        }
+    }
+
+    /// Get the name a caller's broadcasts are attributed to, unless the sender ACL rejects it
+    fn authorize_sender(&self, caller: Option<Caller>) -> Result<Option<Arc<str>>, SendError> {
+        let acl = self.state.config.sender_acl();
+        let is_member = |username: &str, group: &str| {
+            self.state.nss.is_member(username, group).unwrap_or_else(|e| {
+                warn!(username, group, "Failed to check group membership of sender: {}", e);
+                false
+            })
+        };
+        match caller {
+            Some(caller) if caller.is_allowed(&acl, is_member) => {
+                info!(%caller, "Identified sender of broadcast.");
+                self.state.rate_limiter
+                    .check(&sender_key(&caller))
+                    .inspect_err(|e| warn!(%caller, "Rejecting broadcast: {}", e))?;
+                Ok(Some(self.sender_name(&caller)))
+            }
+            None if acl.is_empty() => Ok(None),
+            caller => {
+                warn!(?caller, "Rejecting broadcast from a sender that is not allowed.");
+                Err(zbus::fdo::Error::AccessDenied("Not allowed to send broadcasts".to_string()).into())
+            }
+        }
+    }
+
+    /// Accept broadcasts on a Unix socket, see [`socket`]
+    pub async fn serve_socket(&self, listener: tokio::net::UnixListener) {
+        let clients = Arc::new(Semaphore::new(MAX_CLIENTS));
+        loop {
+            // The semaphore is never closed
+            let Ok(permit) = clients.clone().acquire_owned().await else {
+                return;
+            };
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let service = self.clone();
+                    tokio::spawn(async move {
+                        service.handle_socket_client(stream).await;
+                        drop(permit);
+                    });
+                }
+                Err(e) => warn!("Failed to accept socket client: {}", e),
+            }
+        }
+    }
+
+    /// Answer the request of a socket client
+    async fn handle_socket_client(&self, stream: tokio::net::UnixStream) {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+        let caller = stream
+            .peer_cred()
+            .ok()
+            .map(|cred| Caller::from_socket_peer(cred.uid(), cred.pid().map(|pid| pid as u32)));
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+        let mut reader = tokio::io::BufReader::new(reader.take(MAX_REQUEST_BYTES as u64));
+        let read = tokio::time::timeout(REQUEST_TIMEOUT, reader.read_line(&mut line)).await;
+        let response = match read {
+            Ok(Ok(_)) => match parse_request(&line) {
+                Ok(request) => self.socket_broadcast(request, caller).await,
+                Err(e) => SocketResponse::error(e),
+            },
+            Ok(Err(e)) => SocketResponse::error(format!("Failed to read request: {}", e)),
+            Err(_) => SocketResponse::error("Timed out waiting for the request"),
+        };
+        if let Err(e) = writer.write_all(response.to_line().as_bytes()).await {
+            debug!("Failed to answer socket client: {}", e);
+        }
+    }
+
+    /// Send the broadcast a socket client asked for, or hold it in quarantine if its sender is unknown
+    async fn socket_broadcast(&self, request: SocketRequest, caller: Option<Caller>) -> SocketResponse {
+        info!(title = %request.title, body = %request.body, "Received request via Unix socket.");
+        let quarantined = self.state.config.quarantine_unknown_senders
+            && !caller.as_ref().is_some_and(|caller| caller.is_named(&self.state.config.sender_names));
+        let described = caller.as_ref().map_or_else(|| "unknown".to_string(), ToString::to_string);
+        let sent = async {
+            self.authorize_polkit(caller.as_ref()).await?;
+            let sender = self.authorize_sender(caller)?;
+            if quarantined {
+                // Invalid broadcasts are rejected right away rather than once approved
+                normalize_tags(request.tags.clone())?;
+                self.prepare_payload(request.title.clone(), request.body.clone(), Vec::new(), sender.clone())?;
+                let id = self.state.quarantine.hold(SOCKET_INPUT, described.clone(), sender, request, unix_now());
+                let id = id.ok_or_else(|| {
+                    zbus::fdo::Error::LimitsExceeded("Too many broadcasts await approval".to_string())
+                })?;
+                warn!(quarantine_id = id, caller = %described, "Holding broadcast from unknown sender for approval.");
+                return Ok(SocketResponse::quarantined(id));
+            }
+            Ok::<_, SendError>(SocketResponse::sent(self.send_request(request, sender).await?))
+        };
+        match sent.await {
+            Ok(response) => response,
+            Err(e) => {
+                let description = zbus::DBusError::description(&e).unwrap_or_default();
+                match Rejection::from_message(description) {
+                    Some(rejection) => SocketResponse::rejected(rejection),
+                    None => SocketResponse::error(description),
+                }
+            }
+        }
+    }
+
+    /// Send the broadcast a request arriving on an input other than D-Bus asks for
+    async fn send_request(&self, request: SocketRequest, sender: Option<Arc<str>>) -> Result<BroadcastId, SendError> {
+        let tags = normalize_tags(request.tags)?;
+        let payload = self.prepare_payload(request.title, request.body, Vec::new(), sender)?;
+        let sent = if request.allow_duplicate {
+            self.broadcast_duplicate(request.channel, tags, payload).await?
+        } else {
+            self.broadcast(request.channel, tags, payload).await?
+        };
+        Ok(sent.0)
+    }
+
+    /// Decide whether a payload is delivered to a user now or spooled
+    fn route(&self, user: &TargetUser, payload: &BroadcastPayload) -> RouteDecision {
+        if self.state.paused.is_enabled() {
+            RouteDecision::Paused
+        } else if self.held_for_maintenance(payload) {
+            RouteDecision::Maintenance
+        } else if !self.is_deliverable_now(user) {
+            RouteDecision::OutsideWindow
+        } else if self.is_crowded(user, payload) {
+            RouteDecision::Crowded
+        } else {
+            RouteDecision::Deliver
+        }
+    }
+
+    /// Whether a user is shown as many critical broadcasts as allowed, leaving no room for a non-critical one
+    fn is_crowded(&self, user: &TargetUser, payload: &BroadcastPayload) -> bool {
+        let Some(max) = self.state.config.limits.max_displayed_per_user() else {
+            return false;
+        };
+        if payload.urgency == Some(Urgency::Critical) {
+            return false;
+        }
+        let displayed = self.state.broadcasts.displayed(user.uid);
+        displayed.len() >= max && displayed.iter().all(|shown| shown.critical)
+    }
+
+    /// Close the oldest non-critical notifications shown to a user, so another one stays within
+    /// `max_displayed_per_user`
+    async fn make_room(&self, broadcast_id: BroadcastId, user: &TargetUser) {
+        let Some(max) = self.state.config.limits.max_displayed_per_user() else {
+            return;
+        };
+        let displayed = self.state.broadcasts.displayed(user.uid);
+        let displayed: Vec<Displayed> = displayed.into_iter().filter(|shown| shown.broadcast_id != broadcast_id).collect();
+        let excess = (displayed.len() + 1).saturating_sub(max);
+        let bus_name = self.state.config.notification_bus_name(user.desktop());
+        for shown in displayed.into_iter().filter(|shown| !shown.critical).take(excess) {
+            info!(broadcast_id, closed = shown.broadcast_id, uid = user.uid, "Closing an older notification to make room.");
+            if let Err(e) = self.state.sink.close(user, bus_name, shown.notification_id).await {
+                warn!(uid = user.uid, notification_id = shown.notification_id, "Failed to close notification: {}", e);
+            }
+            self.state.broadcasts.forget_delivery(shown.broadcast_id, user.uid, shown.notification_id);
+        }
+    }
+
+    /// Check whether a user may currently be notified according to their delivery window
+    ///
+    /// Windows open and close later by this host's jitter.
+    fn is_deliverable_now(&self, user: &TargetUser) -> bool {
+        let now = self.state.jitter.shift(Local::now().time());
+        self.state.config
+            .delivery_window(user.username())
+            .is_none_or(|window| window.contains(now))
+    }
+
+    /// Send every scheduled broadcast whose time has come, returning the number sent
+    ///
+    /// Broadcasts that fell due while the server was down are sent late rather than dropped.
+    pub async fn fire_scheduled(&self) -> u32 {
+        let due = self.state.scheduler.take_due(unix_now());
+        if due.is_empty() {
+            return 0;
+        }
+        let mut sent = 0;
+        for entry in due {
+            let late = unix_now().saturating_sub(entry.at);
+            match self.broadcast_options(entry.title, entry.body, entry.options, entry.sender, None).await {
+                Ok((broadcast_id, _)) => {
+                    info!(schedule_id = entry.id, broadcast_id, late, "Sent scheduled broadcast.");
+                    sent += 1;
+                }
+                Err(e) => error!(schedule_id = entry.id, "Failed to send scheduled broadcast: {}", e),
+            }
+        }
+        self.save_state();
+        sent
+    }
+
+    /// Deliver every spooled notification whose recipient's delivery window is now open,
+    /// unless maintenance mode or paused delivery still holds it back
+    ///
+    /// Parked notifications wait for their recipient's notification daemon instead,
+    /// which is watched for again in case the user logged out and back in.
+    pub async fn flush_spool(&self) {
+        for entry in self.state.spool.entries().into_iter().filter(|entry| entry.parked) {
+            self.watch_for_daemon(entry.user);
+        }
+        let ready = self
+            .state
+            .spool
+            .start_delivery(|entry| !entry.parked && self.route(&entry.user, &entry.payload).delivers_now());
+        self.deliver_spooled(ready).await;
+    }
+
+    /// Deliver the notifications parked for a user after their delivery failed, unless delivery is paused
+    pub async fn retry_parked(&self, uid: u32) {
+        if self.state.paused.is_enabled() {
+            return;
+        }
+        let ready = self.state.spool.start_delivery(|entry| entry.parked && entry.user.uid == uid);
+        self.deliver_spooled(ready).await;
+    }
+
+    /// Deliver notifications taken from the spool
+    ///
+    /// The state is saved before and after delivering, so a restart in between
+    /// knows which notifications may already have been shown.
+    async fn deliver_spooled(&self, ready: Vec<SpooledNotification>) {
+        if ready.is_empty() {
+            return;
+        }
+        self.save_state();
+        let delivering = ready.clone();
+
+        info!("Delivering {} spooled notifications.", ready.len());
+        let notification_tasks = ready.into_iter().map(|entry| async move {
+            match entry.broadcast_id {
+                Some(broadcast_id) => {
+                    self.deliver_broadcast(broadcast_id, entry.user, entry.payload)
+                        .instrument(self.broadcast_span(broadcast_id))
+                        .await;
+                }
+                None => {
+                    let _ = self.deliver(&entry.user, entry.payload, 0, None).await;
+                }
+            }
+        });
+        self.run_limited(notification_tasks).await;
+        self.state.spool.finish_delivery(&delivering);
+        self.save_state();
+    }
+
+    /// Deliver a broadcast to a user, recording the notification id and the outcome in its report
+    ///
+    /// Users without a notification daemon get the broadcast on their terminals
+    /// instead, if the terminal fallback is enabled.
+    ///
+    /// The outcome is also announced with the `UserDelivered` or `UserFailed` signal.
+    async fn deliver_broadcast(
+        &self,
+        broadcast_id: BroadcastId,
+        user: TargetUser,
+        payload: Arc<BroadcastPayload>,
+    ) -> DeliveryStatus {
+        let replaces_id = self.state.broadcasts.take_replaced(broadcast_id, user.uid);
+        // Updating a notification in place shows no more of them
+        if replaces_id == 0 {
+            self.make_room(broadcast_id, &user).await;
+        }
+        let delivered = self.deliver(&user, payload.clone(), replaces_id, Some(broadcast_id)).await;
+        if delivered.is_err() && replaces_id != 0 {
+            // Keep tracking the replaced notification, so it can still be closed
+            self.state.broadcasts.record_delivery(broadcast_id, user.clone(), replaces_id);
+        }
+        let status = match delivered {
+            Ok(notification_id) => {
+                self.state.broadcasts.record_delivery(broadcast_id, user.clone(), notification_id);
+                self.spawn_hook(broadcast_id, &user, &payload, notification_id);
+                DeliveryStatus::Delivered
+            }
+            Err(e) if e.kind() == DeliveryErrorKind::DaemonMissing && self.state.config.terminal_fallback => {
+                self.state.broadcasts.record_error(broadcast_id, user.uid, e.message());
+                let options = self.delivery_options(&user, &payload, 0, None);
+                match self.state.fallback_sink.notify(&user, payload.clone(), &options).await {
+                    Ok(_) => {
+                        info!(uid = user.uid, "No notification daemon, wrote notification to terminals instead.");
+                        DeliveryStatus::TerminalFallback(e.kind())
+                    }
+                    Err(fallback_error) => {
+                        warn!(uid = user.uid, "Terminal fallback failed: {}", fallback_error);
+                        DeliveryStatus::Failed(e.kind())
+                    }
+                }
+            }
+            Err(e) => {
+                self.state.broadcasts.record_error(broadcast_id, user.uid, e.message());
+                DeliveryStatus::Failed(e.kind())
+            }
+        };
+        if let DeliveryStatus::Failed(kind) = status {
+            // A daemon rejecting the notification would reject it again
+            if self.state.config.park_failed && kind != DeliveryErrorKind::NotifyRejected {
+                self.park(broadcast_id, &user, payload);
+            }
+        }
+        if let Some(emitter) = self.state.emitter() {
+            let (uid, username, outcome) = (user.uid, user.username.as_str(), status.to_string());
+            log_signal_error(match status {
+                DeliveryStatus::Failed(_) => Self::user_failed(&emitter, broadcast_id, uid, username, &outcome).await,
+                _ => Self::user_delivered(&emitter, broadcast_id, uid, username, &outcome).await,
+            });
+        }
+        self.state.broadcasts.record_status(broadcast_id, user, status);
+        status
+    }
+
+    /// Hold a notification whose delivery failed until the user's notification daemon returns
+    fn park(&self, broadcast_id: BroadcastId, user: &TargetUser, payload: Arc<BroadcastPayload>) {
+        let entry = SpooledNotification::new(user.clone(), payload).with_broadcast_id(broadcast_id).parked();
+        match self.state.spool.push(entry) {
+            Ok(()) => {
+                info!(broadcast_id, uid = user.uid, "Parked notification until a notification daemon appears.");
+                self.watch_for_daemon(user.clone());
+            }
+            Err(e) => warn!(broadcast_id, uid = user.uid, "Dropping notification that cannot be parked: {}", e),
         }
+    }
 
-        info!("Dispatching notifications to {} users: {:?}", users.len(), users);
+    /// Watch a user's session bus in the background, delivering their parked notifications
+    /// whenever a notification daemon appears on it, until none are left
+    ///
+    /// The watch ends when the session bus goes away; the next spool flush watches
+    /// again, so a user logging back in gets their notifications.
+    fn watch_for_daemon(&self, user: TargetUser) {
+        if !self.state.daemon_watches.lock().unwrap().insert(user.uid) {
+            return;
+        }
+        let service = self.clone();
+        tokio::spawn(
+            async move {
+                let bus_name = service.state.config.notification_bus_name(user.desktop()).to_string();
+                let mut accept_current = true;
+                loop {
+                    if let Err(e) = wait_for_notification_server(&user, &bus_name, accept_current).await {
+                        debug!(uid = user.uid, "Stopped watching for a notification daemon: {}", e);
+                        service.state.daemon_watches.lock().unwrap().remove(&user.uid);
+                        return;
+                    }
+                    info!(uid = user.uid, %bus_name, "Notification daemon appeared, delivering parked notifications.");
+                    service.retry_parked(user.uid).await;
+                    // Notifications failing again wait for the daemon to be replaced
+                    accept_current = false;
+                    let mut watches = service.state.daemon_watches.lock().unwrap();
+                    if !service.state.spool.entries().iter().any(|entry| entry.parked && entry.user.uid == user.uid) {
+                        watches.remove(&user.uid);
+                        return;
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+    }
 
-        let notification_tasks = users.into_iter().map(|user| {
-            let title_clone = title.clone();
-            let body_clone = body.clone();
+    /// Run the hook a payload asks for in the background, once its notification is displayed
+    fn spawn_hook(&self, broadcast_id: BroadcastId, user: &TargetUser, payload: &BroadcastPayload, notification_id: u32) {
+        let Some(hook) = payload.hook.clone() else {
+            return;
+        };
+        let context = HookContext {
+            broadcast_id,
+            notification_id,
+            correlation_id: self.state.broadcasts.correlation_id(broadcast_id).map(|id| id.to_string()),
+            title: payload.title.to_string(),
+            body: payload.body.to_string(),
+        };
+        let state = self.state.clone();
+        let user = user.clone();
+        tokio::spawn(
             async move {
-                let user_span = tracing::info_span!("user_notification", uid = user.uid, username = %user.username);
-                let _enter = user_span.enter();
-                if let Err(e) = send_notification_to_user(&user, &title_clone, &body_clone).await {
-                    error!("Failed to send notification: {}", e);
+                if let Err(e) = state.hooks.run(&hook, &user, &context).await {
+                    warn!(uid = user.uid, %hook, "Hook failed: {}", e);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// Build the per-recipient parameters of a delivery
+    ///
+    /// Answers are collected from the user if the notification belongs to a poll.
+    fn delivery_options(
+        &self,
+        user: &TargetUser,
+        payload: &BroadcastPayload,
+        replaces_id: u32,
+        broadcast_id: Option<BroadcastId>,
+    ) -> DeliveryOptions {
+        let locale = effective_locale(user.username(), &self.state.config.language_overrides);
+        let channel = broadcast_id.and_then(|id| self.state.broadcasts.channel(id));
+        let defaults = self.state.config.notification_defaults(channel.as_deref());
+        let app_name = match &defaults.app_name {
+            Some(app_name) => app_name.clone(),
+            None => self.state.localizer.message(locale.as_deref(), "app-name"),
+        };
+        let terminal_heading =
+            self.state.localizer.message_with_args(locale.as_deref(), "terminal-heading", &[("app", &app_name)]);
+        DeliveryOptions {
+            app_name,
+            icon: defaults.icon().to_string(),
+            desktop_entry: defaults.desktop_entry.clone(),
+            timeout: defaults.timeout_for(payload.urgency),
+            replaces_id,
+            sound: self.state.config.sound.resolve(payload.urgency, payload.category.as_deref()),
+            bus_name: self.state.config.notification_bus_name(user.desktop()).to_string(),
+            responses: broadcast_id.and_then(|id| self.poll_responder(id, user)),
+            correlation_id: broadcast_id.and_then(|id| self.state.broadcasts.correlation_id(id)),
+            footer: payload.sender.as_deref().filter(|_| self.state.config.sender_footer).map(|sender| {
+                self.state.localizer.message_with_args(locale.as_deref(), "sent-by", &[("sender", sender)])
+            }),
+            terminal_heading: Some(terminal_heading),
+            profiles: self.state.config.profiles.clone(),
+            nested_buses: self.state.config.nested_buses,
+        }
+    }
+
+    /// Create a channel recording a user's answers to a poll, if the broadcast is one
+    fn poll_responder(&self, broadcast_id: BroadcastId, user: &TargetUser) -> Option<mpsc::UnboundedSender<String>> {
+        if !self.state.polls.contains(broadcast_id) {
+            return None;
+        }
+        let (responses, mut answers) = mpsc::unbounded_channel::<String>();
+        let state = self.state.clone();
+        let user = user.clone();
+        tokio::spawn(async move {
+            while let Some(answer) = answers.recv().await {
+                if let Some(emitter) = state.emitter() {
+                    let invoked = Self::broadcast_action_invoked(&emitter, broadcast_id, user.uid, &user.username, &answer);
+                    log_signal_error(invoked.await);
+                    log_signal_error(Self::notification_action_invoked(&emitter, user.uid, &answer).await);
+                }
+                if state.polls.record_response(broadcast_id, &user, &answer) {
+                    info!(broadcast_id, uid = user.uid, %answer, "Recorded poll answer.");
                 } else {
-                    info!("Notification sent successfully.");
+                    debug!(broadcast_id, uid = user.uid, %answer, "Ignoring unknown poll answer.");
                 }
             }
         });
+        Some(responses)
+    }
 
-        join_all(notification_tasks).await;
-        Ok(())
+    /// Get the payload to deliver for a broadcast, keeping the answers of polls and its sender
+    fn payload_for(&self, broadcast_id: BroadcastId, payload: &Arc<BroadcastPayload>) -> Arc<BroadcastPayload> {
+        let mut payload = payload.clone();
+        if let Some(poll) = self.state.polls.get(broadcast_id).filter(|_| payload.actions.is_empty()) {
+            payload = Arc::new(payload.as_ref().clone().with_actions(poll.options));
+        }
+        if payload.sender.is_none() {
+            if let Some(sender) = self.state.broadcasts.sender(broadcast_id) {
+                payload = Arc::new(payload.as_ref().clone().with_sender(Some(sender)));
+            }
+        }
+        payload
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Deliver a single notification to a user in their locale, logging the outcome
+    ///
+    /// A non-zero `replaces_id` updates that notification in place. Returns the
+    /// notification id assigned by the user's notification daemon, or the
+    /// classified reason the delivery failed.
+    async fn deliver(
+        &self,
+        user: &TargetUser,
+        payload: Arc<BroadcastPayload>,
+        replaces_id: u32,
+        broadcast_id: Option<BroadcastId>,
+    ) -> Result<u32, DeliveryError> {
+        let user_span = tracing::info_span!("user_notification", uid = user.uid, username = %user.username);
+        let _enter = user_span.enter();
 
-    #[test]
-    fn test_notifier_service_creation() {
-        let service = NotifierService;
-        let debug_str = format!("{:?}", service);
-        assert!(!debug_str.is_empty());
+        let options = self.delivery_options(user, &payload, replaces_id, broadcast_id);
+        let retry = &self.state.config.retry;
+        let limit = self.state.config.user_delivery_timeout();
+        let mut attempt = 0;
+        let result = loop {
+            let started = Instant::now();
+            let notified = self.state.sink.notify(user, payload.clone(), &options);
+            // Abandon a hung attempt, so one stuck session does not hold up the others
+            let result = match limit {
+                Some(limit) => tokio::time::timeout(limit, notified).await.unwrap_or_else(|_| {
+                    warn!(?limit, "Delivery hung past the per-user timeout, abandoning it.");
+                    let message = format!("no outcome within {} ms", limit.as_millis());
+                    Err(DeliveryError::new(DeliveryErrorKind::Stalled, message))
+                }),
+                None => notified.await,
+            };
+            self.state.latency.record(user, started.elapsed());
+            match result {
+                Err(error) if retry.should_retry(attempt, error.kind()) => {
+                    attempt += 1;
+                    warn!(attempt, kind = %error.kind(), "Failed to send notification, retrying: {}", error.message());
+                    tokio::time::sleep(retry.delay_for(attempt - 1)).await;
+                }
+                result => break result,
+            }
+        };
+        match result {
+            Ok(notification_id) => {
+                info!(notification_id, "Notification sent successfully.");
+                Ok(notification_id)
+            }
+            Err(error) => {
+                error!(kind = %error.kind(), retryable = retry.retries_on(error.kind()), "Failed to send notification: {}", error.message());
+                *self.state.last_delivery_error.lock().unwrap() = Some((unix_now(), user.uid, error.clone()));
+                Err(error)
+            }
+        }
+    }
+
+    /// Send a broadcast, optionally posted to a channel and tagged, to all active graphical users
+    ///
+    /// Payloads offering actions are polls, whose answers are collected per user.
+    /// A broadcast repeating one sent within the deduplication window updates that
+    /// one instead, see [`Self::coalesce_duplicate`].
+    /// Returns the id of the broadcast and the users it was spooled for.
+    async fn broadcast(
+        &self,
+        channel: Option<String>,
+        tags: Vec<String>,
+        payload: Arc<BroadcastPayload>,
+    ) -> zbus::fdo::Result<(BroadcastId, Vec<Deferral>)> {
+        let users = self.active_users().await?;
+        if let Some(broadcast_id) = self.coalesce_duplicate(&payload, &users).await {
+            return Ok((broadcast_id, Vec::new()));
+        }
+        let sent = self.broadcast_to(channel, tags, payload, users).await?;
+        self.include_late_joiners(sent.0).await;
+        Ok(sent)
+    }
+
+    /// Send several broadcasts to all active graphical users, enumerated once for all of them
+    ///
+    /// The broadcasts are dispatched one after the other, sharing the connections
+    /// to the users' session buses. Each is coalesced with a recent duplicate like
+    /// one sent on its own.
+    async fn broadcast_batch(&self, payloads: Vec<Arc<BroadcastPayload>>) -> zbus::fdo::Result<Vec<BroadcastId>> {
+        let users = self.active_users().await?;
+        let mut broadcast_ids = Vec::with_capacity(payloads.len());
+        with_shared_connections(async {
+            for payload in payloads {
+                if let Some(broadcast_id) = self.coalesce_duplicate(&payload, &users).await {
+                    broadcast_ids.push(broadcast_id);
+                    continue;
+                }
+                let (broadcast_id, _) = self.broadcast_to(None, Vec::new(), payload, users.clone()).await?;
+                self.include_late_joiners(broadcast_id).await;
+                broadcast_ids.push(broadcast_id);
+            }
+            Ok(broadcast_ids)
+        })
+        .await
+    }
+
+    /// Send a broadcast to all active graphical users, even if it repeats a recent one
+    async fn broadcast_duplicate(
+        &self,
+        channel: Option<String>,
+        tags: Vec<String>,
+        payload: Arc<BroadcastPayload>,
+    ) -> zbus::fdo::Result<(BroadcastId, Vec<Deferral>)> {
+        let users = self.active_users().await?;
+        let sent = self.broadcast_to(channel, tags, payload, users).await?;
+        self.include_late_joiners(sent.0).await;
+        Ok(sent)
+    }
+
+    /// Send a broadcast meant for all active graphical users to those who logged in while it was dispatched
+    ///
+    /// With `late_join_grace_secs` set, the session cache is checked again once the
+    /// broadcast was dispatched, and once more when the grace window closes.
+    async fn include_late_joiners(&self, broadcast_id: BroadcastId) {
+        let Some(grace) = self.state.config.late_join_grace() else {
+            return;
+        };
+        self.top_up(broadcast_id).await;
+        if !grace.is_zero() {
+            self.state.late_joinable.lock().unwrap().insert(broadcast_id);
+            let service = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(grace).await;
+                service.top_up(broadcast_id).await;
+                service.state.late_joinable.lock().unwrap().remove(&broadcast_id);
+            });
+        }
+    }
+
+    /// Get the broadcasts still open to users logging in late that have no outcome for a user
+    ///
+    /// These are the broadcasts to all users within their late join grace, and the
+    /// broadcasts kept for absent users that the user is meant to receive.
+    fn owed_broadcasts(&self, user: &TargetUser) -> Vec<(BroadcastId, Arc<BroadcastPayload>)> {
+        let mut open = self.state.late_joinable.lock().unwrap().clone();
+        let is_member = |group: &str| {
+            self.state.nss.is_member(&user.username, group).unwrap_or_else(|e| {
+                warn!(uid = user.uid, %group, "Failed to check group membership: {}", e);
+                false
+            })
+        };
+        for (broadcast_id, audience) in self.state.absent.pending() {
+            if audience.includes(user, is_member) {
+                open.insert(broadcast_id);
+            }
+        }
+        let mut owed: Vec<_> = open
+            .into_iter()
+            .filter_map(|broadcast_id| self.state.broadcasts.get(broadcast_id))
+            .filter(|record| !record.report.iter().any(|(recipient, _)| recipient.uid == user.uid))
+            .filter_map(|record| Some((record.id, record.payload?)))
+            .collect();
+        owed.sort_by_key(|(broadcast_id, _)| *broadcast_id);
+        owed
     }
-}
\ No newline at end of file
+
+    /// Whether a spooled notification goes out to its recipient as soon as their session is ready
+    fn delivers_on_login(&self, entry: &SpooledNotification) -> bool {
+        entry.parked || self.route(&entry.user, &entry.payload).delivers_now()
+    }
+
+    /// Deliver what a user who just logged in is owed, returning the number of notifications sent
+    ///
+    /// These are the notifications spooled for them that may be delivered now,
+    /// including parked ones, and the broadcasts still open to late joiners.
+    pub async fn welcome(&self, user: &TargetUser) -> u32 {
+        if self.state.paused.is_enabled() {
+            return 0;
+        }
+        let ready = self
+            .state
+            .spool
+            .start_delivery(|entry| entry.user.uid == user.uid && self.delivers_on_login(entry));
+        let owed = self.owed_broadcasts(user);
+        let sent = (ready.len() + owed.len()) as u32;
+        self.deliver_spooled(ready).await;
+        for (broadcast_id, payload) in owed {
+            info!(broadcast_id, uid = user.uid, "Including a user whose session became ready.");
+            self.dispatch(broadcast_id, vec![user.clone()], payload)
+                .instrument(self.broadcast_span(broadcast_id))
+                .await;
+        }
+        sent
+    }
+
+    /// Count the notifications a user who just logged in is owed, see [`Self::welcome`]
+    fn waiting_on_login(&self, user: &TargetUser) -> u32 {
+        if self.state.paused.is_enabled() {
+            return 0;
+        }
+        let spooled = self.state.spool.entries().into_iter();
+        let spooled = spooled.filter(|entry| entry.user.uid == user.uid && self.delivers_on_login(entry));
+        (spooled.count() + self.owed_broadcasts(user).len()) as u32
+    }
+
+    /// Welcome a user who just logged in once their notification daemon is up, or after a timeout
+    ///
+    /// A user already being welcomed, e.g. when both the session hook and the
+    /// session tracker report the login, is left to the first welcome.
+    fn welcome_when_ready(&self, recipient: TargetUser) {
+        if !self.state.welcoming.lock().unwrap().insert(recipient.uid) {
+            debug!(uid = recipient.uid, "User is already being welcomed.");
+            return;
+        }
+        let service = self.clone();
+        tokio::spawn(
+            async move {
+                let bus_name = service.state.config.notification_bus_name(recipient.desktop()).to_string();
+                let daemon = wait_for_notification_server(&recipient, &bus_name, true);
+                match tokio::time::timeout(SESSION_READY_TIMEOUT, daemon).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => debug!(uid = recipient.uid, "Delivering without a notification daemon: {}", e),
+                    Err(_) => debug!(uid = recipient.uid, "No notification daemon appeared, delivering anyway."),
+                }
+                let sent = service.welcome(&recipient).await;
+                service.state.welcoming.lock().unwrap().remove(&recipient.uid);
+                info!(uid = recipient.uid, sent, "Delivered to a user whose session became ready.");
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// Deliver a broadcast to the active users it has no outcome for yet
+    ///
+    /// Unlike a replay, users whose delivery failed are not tried again.
+    async fn top_up(&self, broadcast_id: BroadcastId) {
+        // Closed broadcasts are gone from the registry and reach nobody new
+        let Some(BroadcastRecord { payload: Some(payload), report, .. }) = self.state.broadcasts.get(broadcast_id)
+        else {
+            return;
+        };
+        let users = match self.state.sessions.active_users().await {
+            Ok(users) => users,
+            Err(e) => {
+                warn!(broadcast_id, "Failed to check for users who logged in during the broadcast: {}", e);
+                return;
+            }
+        };
+        let joined: Vec<TargetUser> = users
+            .into_iter()
+            .filter(|user| !report.iter().any(|(recipient, _)| recipient.uid == user.uid))
+            .collect();
+        if joined.is_empty() {
+            return;
+        }
+        info!(broadcast_id, "Including {} users who logged in during the broadcast.", joined.len());
+        self.dispatch(broadcast_id, joined, payload)
+            .instrument(self.broadcast_span(broadcast_id))
+            .await;
+    }
+
+    /// Update the latest broadcast with the same title and body in place, if it was sent to
+    /// every one of `users` within the deduplication window, and return its id
+    async fn coalesce_duplicate(
+        &self,
+        payload: &Arc<BroadcastPayload>,
+        users: &HashSet<TargetUser>,
+    ) -> Option<BroadcastId> {
+        let window = self.state.config.dedup_window();
+        if window.is_zero() || users.is_empty() {
+            return None;
+        }
+        let now = unix_now();
+        let broadcast_id = self.state.broadcasts.latest_matching(|record| {
+            !record.older_than(window, now)
+                && record.payload.as_ref().is_some_and(|sent| sent.title == payload.title && sent.body == payload.body)
+                && users.iter().all(|user| record.report.iter().any(|(recipient, _)| recipient.uid == user.uid))
+        })?;
+        info!(broadcast_id, "Coalescing duplicate broadcast into the earlier one.");
+        self.update_broadcasts(vec![broadcast_id], payload.clone()).await;
+        for user in users {
+            self.note_suppressed(user);
+        }
+        Some(broadcast_id)
+    }
+
+    /// Count a notification suppressed for a user, to be summarized once the summary period is over
+    fn note_suppressed(&self, user: &TargetUser) {
+        let period = self.state.config.suppression_summary_period();
+        if period.is_zero() || !self.state.suppressed.record(user) {
+            return;
+        }
+        let service = self.clone();
+        let uid = user.uid;
+        tokio::spawn(
+            async move {
+                tokio::time::sleep(period).await;
+                service.summarize_suppressed(uid).await;
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// Tell a user how many of their notifications were suppressed in the summary period
+    ///
+    /// Returns the id of the summary broadcast, if anything was suppressed.
+    async fn summarize_suppressed(&self, uid: u32) -> Option<BroadcastId> {
+        let (user, count) = self.state.suppressed.take(uid)?;
+        let locale = effective_locale(user.username(), &self.state.config.language_overrides);
+        let minutes = self.state.config.suppression_summary_period().as_secs().div_ceil(60);
+        let localizer = &self.state.localizer;
+        let title = localizer.message(locale.as_deref(), "suppressed-title");
+        let args = [("count", u64::from(count)), ("minutes", minutes)];
+        let body = localizer.message_with_numbers(locale.as_deref(), "suppressed-body", &args);
+        info!(uid, count, "Summarizing suppressed notifications.");
+        let payload = Arc::new(BroadcastPayload::new(title, body));
+        let sent = self.broadcast_to(None, Vec::new(), payload, HashSet::from([user])).await;
+        sent.ok().map(|(broadcast_id, _)| broadcast_id)
+    }
+
+    /// Send a broadcast to the given users
+    async fn broadcast_to(
+        &self,
+        channel: Option<String>,
+        tags: Vec<String>,
+        payload: Arc<BroadcastPayload>,
+        users: HashSet<TargetUser>,
+    ) -> zbus::fdo::Result<(BroadcastId, Vec<Deferral>)> {
+        let broadcast_id = self.register_broadcast(channel, tags, &payload);
+        Ok((broadcast_id, self.deliver_registered(broadcast_id, payload, users).await))
+    }
+
+    /// Start tracking a broadcast, before it is delivered
+    fn register_broadcast(
+        &self,
+        channel: Option<String>,
+        tags: Vec<String>,
+        payload: &Arc<BroadcastPayload>,
+    ) -> BroadcastId {
+        let broadcast_id = self.state.broadcasts.register_with(channel, tags, Some(payload.clone()));
+        if let Some(correlation_id) = self.state.broadcasts.correlation_id(broadcast_id) {
+            info!(broadcast_id, %correlation_id, "Registered broadcast.");
+        }
+        if !payload.actions.is_empty() {
+            self.state.polls.register(broadcast_id, payload.actions.clone());
+        }
+        broadcast_id
+    }
+
+    /// Deliver a registered broadcast to the given users
+    async fn deliver_registered(
+        &self,
+        broadcast_id: BroadcastId,
+        payload: Arc<BroadcastPayload>,
+        users: HashSet<TargetUser>,
+    ) -> Vec<Deferral> {
+        if users.is_empty() {
+            warn!(broadcast_id, "No active graphical user sessions found to notify.");
+            // Clients following the broadcast still learn that it is done
+            if let Some(emitter) = self.state.emitter() {
+                log_signal_error(Self::completed(&emitter, broadcast_id, 0, 0, 0).await);
+            }
+            return Vec::new();
+        }
+        self.dispatch(broadcast_id, users.into_iter().collect(), payload)
+            .instrument(self.broadcast_span(broadcast_id))
+            .await
+    }
+
+    /// Send a broadcast with the parameters of a `SendWithOptions` request
+    async fn broadcast_with_options(
+        &self,
+        header: &Header<'_>,
+        title: String,
+        body: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> Result<(BroadcastId, Vec<Deferral>), SendError> {
+        let options = SendOptions::from_dict(&options).map_err(zbus::fdo::Error::InvalidArgs)?;
+        debug!(?options, "Parsed request options.");
+        let sender = self.sender(header).await?;
+        let image = match &options.image {
+            Some(path) => Some(self.load_image(header, path).await?),
+            None => None,
+        };
+        self.broadcast_options(title, body, options, sender, image).await
+    }
+
+    /// Load the image of a broadcast on behalf of its caller, who must be able to read the file
+    async fn load_image(&self, header: &Header<'_>, path: &str) -> zbus::fdo::Result<Arc<NotificationImage>> {
+        let uid = match self.state.connection.get() {
+            Some(_) => self.caller(header).await.and_then(|caller| caller.uid),
+            None => Some(nix::unistd::geteuid().as_raw()),
+        };
+        let path = PathBuf::from(path);
+        let loaded = tokio::task::spawn_blocking(move || {
+            let reader = uid.map_or_else(ImageReader::anyone, ImageReader::of_user);
+            NotificationImage::load_as(&path, &reader)
+        })
+        .await
+        .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to load the image: {}", e)))?;
+        let image = loaded.map_err(|e| {
+            warn!("Rejecting broadcast: {}", e);
+            zbus::fdo::Error::InvalidArgs(e.to_string())
+        })?;
+        Ok(Arc::new(image))
+    }
+
+    /// Check the options of a broadcast, before it is sent or scheduled
+    fn check_options(&self, options: &SendOptions) -> Result<(), SendError> {
+        if let Some(hook) = &options.hook {
+            self.state.hooks.resolve(hook).map_err(|e| {
+                warn!("Rejecting broadcast: {}", e);
+                zbus::fdo::Error::InvalidArgs(e)
+            })?;
+        }
+        normalize_tags(options.tags.clone())?;
+        parse_actions(&options.actions)?;
+        if options.group_key.as_ref().is_some_and(|key| key.trim().is_empty()) {
+            warn!("Rejecting broadcast with an empty group key.");
+            let rejection = Rejection::new(
+                "group_key",
+                RejectionRule::Empty,
+                "The group key must not be empty",
+                "Leave the group key out, or name the group",
+            );
+            return Err(rejection.into());
+        }
+        if let Some(group) = &options.group {
+            let found = self.state.nss.group(group).map_err(zbus::fdo::Error::Failed)?;
+            if found.is_none() {
+                warn!(%group, "Rejecting broadcast to an unknown group.");
+                return Err(zbus::fdo::Error::InvalidArgs(format!("Unknown group '{}'", group)).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the active graphical users who are members of a Unix group
+    ///
+    /// Fails if none of them are, so the sender learns the alert reached nobody.
+    async fn group_members(&self, group: &str) -> zbus::fdo::Result<HashSet<TargetUser>> {
+        let members = self.active_members(group).await?;
+        if members.is_empty() {
+            warn!(%group, "Rejecting broadcast to a group without an active graphical session.");
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "No member of group '{}' has an active graphical session",
+                group
+            )));
+        }
+        Ok(members)
+    }
+
+    /// Get the active graphical users who are members of a Unix group, if any
+    async fn active_members(&self, group: &str) -> zbus::fdo::Result<HashSet<TargetUser>> {
+        let mut members = HashSet::new();
+        for user in self.active_users().await? {
+            if self.state.nss.is_member(&user.username, group).map_err(zbus::fdo::Error::Failed)? {
+                members.insert(user);
+            }
+        }
+        Ok(members)
+    }
+
+    /// Get the active graphical users a broadcast is for, if its options narrow them down
+    ///
+    /// `None` means everyone, including users logging in during the broadcast.
+    async fn narrowed_recipients(&self, options: &SendOptions) -> zbus::fdo::Result<Option<HashSet<TargetUser>>> {
+        if options.group.is_none() && options.exclude_users.is_empty() && options.seat.is_none() {
+            return Ok(None);
+        }
+        let mut users = match &options.group {
+            // Members logging in later still receive a queued broadcast
+            Some(group) if options.queue_for_absent => self.active_members(group).await?,
+            Some(group) => self.group_members(group).await?,
+            None => self.active_users().await?,
+        };
+        users.retain(|user| {
+            options.seat.as_deref().is_none_or(|seat| user.seat() == Some(seat))
+                && !options.exclude_users.iter().any(|excluded| user.is(excluded))
+        });
+        debug!(recipients = users.len(), "Narrowed down the recipients of the broadcast.");
+        Ok(Some(users))
+    }
+
+    /// Send a broadcast with parsed options on behalf of a sender
+    ///
+    /// The image is the one loaded for the sender; without it, its path is passed to the daemon.
+    async fn broadcast_options(
+        &self,
+        title: String,
+        body: String,
+        options: SendOptions,
+        sender: Option<Arc<str>>,
+        image: Option<Arc<NotificationImage>>,
+    ) -> Result<(BroadcastId, Vec<Deferral>), SendError> {
+        self.check_options(&options)?;
+        let channel = self.state.config.broadcast_channel(&options, &title);
+        let recipients = self.narrowed_recipients(&options).await?;
+        let audience = options.queue_for_absent.then(|| Audience::from_options(&options));
+        let tags = normalize_tags(options.tags)?;
+        let (actions, labels) = parse_actions(&options.actions)?;
+        let body = self.compose_body(body, &options.body_commands).await?;
+        let payload = self.prepare_payload(title, body, actions, sender)?;
+        let group_key = options.group_key.map(|key| key.trim().to_string());
+        let mut payload = Arc::unwrap_or_clone(payload)
+            .with_hook(options.hook)
+            .with_action_labels(labels)
+            .with_group_key(group_key)
+            .with_category(options.category)
+            .with_markdown(options.markdown)
+            .with_image(options.image.map(PathBuf::from))
+            .with_loaded_image(image)
+            .with_progress(options.progress);
+        if let Some(urgency) = options.urgency {
+            payload = payload.with_urgency(Some(urgency));
+        }
+        // The options add to the content checked before
+        self.check_payload_size(&payload)?;
+        let payload = Arc::new(payload);
+        let replaced = match &options.replaces {
+            Some(replaces) => self.replaced_broadcasts(replaces)?,
+            None => Vec::new(),
+        };
+        let everyone = recipients.is_none();
+        let users = match recipients {
+            Some(users) => users,
+            None => self.active_users().await?,
+        };
+        // Replacing a broadcast with the same content still updates it
+        if !options.allow_duplicate && replaced.is_empty() {
+            if let Some(broadcast_id) = self.coalesce_duplicate(&payload, &users).await {
+                return Ok((broadcast_id, Vec::new()));
+            }
+        }
+        let broadcast_id = self.register_broadcast(channel, tags, &payload);
+        if !replaced.is_empty() {
+            let notifications = self.state.broadcasts.supersede(broadcast_id, &replaced);
+            info!(broadcast_id, ?replaced, notifications, "Replacing earlier broadcasts in place.");
+        }
+        if let Some(audience) = audience {
+            let ttl = self.state.config.absent_queue_ttl();
+            info!(broadcast_id, ttl_secs = ttl.as_secs(), "Keeping the broadcast for users who log in later.");
+            self.state.absent.queue(broadcast_id, audience, ttl);
+        }
+        let service = self.clone();
+        let delivery = async move {
+            let deferrals = service.deliver_registered(broadcast_id, payload, users).await;
+            // Broadcasts to some of the users are not extended to users logging in later
+            if everyone {
+                service.include_late_joiners(broadcast_id).await;
+            }
+            deferrals
+        };
+        if options.detach {
+            tokio::spawn(delivery.in_current_span());
+            return Ok((broadcast_id, Vec::new()));
+        }
+        Ok((broadcast_id, delivery.await))
+    }
+
+    /// Get the tracked broadcasts a broadcast replaces, given by broadcast id or by the batch id of a send on
+    /// remote hosts
+    fn replaced_broadcasts(&self, replaces: &str) -> zbus::fdo::Result<Vec<BroadcastId>> {
+        let batch = self.state.broadcasts.tagged_broadcasts(&fleet::batch_tag(replaces));
+        if !batch.is_empty() {
+            return Ok(batch);
+        }
+        match replaces.parse() {
+            Ok(broadcast_id) if self.state.broadcasts.get(broadcast_id).is_some() => Ok(vec![broadcast_id]),
+            _ => {
+                warn!(%replaces, "Rejecting replacement of an unknown broadcast.");
+                Err(zbus::fdo::Error::InvalidArgs(format!("Unknown broadcast or batch id '{}'", replaces)))
+            }
+        }
+    }
+
+    /// Append the output of allowlisted commands to a body
+    async fn compose_body(&self, body: String, commands: &[String]) -> zbus::fdo::Result<String> {
+        let allowed = &self.state.config.body_commands;
+        let argvs = commands
+            .iter()
+            .map(|command| resolve_command(allowed, command))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                warn!("Rejecting broadcast: {}", e);
+                zbus::fdo::Error::InvalidArgs(e)
+            })?;
+        let mut fragments = vec![body];
+        for argv in &argvs {
+            fragments.push(run_command(argv).await.map_err(zbus::fdo::Error::Failed)?);
+        }
+        Ok(join_fragments(fragments))
+    }
+
+    /// Deliver the latest critical broadcast again to every active user, see [`Self::re_announce`]
+    pub async fn re_announce_critical(&self) -> zbus::fdo::Result<u32> {
+        let latest = self.state.broadcasts
+            .list(None)
+            .into_iter()
+            .filter(|record| record.payload.as_ref().is_some_and(|payload| payload.urgency == Some(Urgency::Critical)))
+            .max_by_key(|record| record.id);
+        let Some(BroadcastRecord { id, payload: Some(payload), .. }) = latest else {
+            info!("No critical broadcast to re-announce.");
+            return Ok(0);
+        };
+        info!(broadcast_id = id, "Re-announcing the latest critical broadcast.");
+        let shown = self.update_broadcasts(vec![id], payload).await;
+        let replayed = self.replay_broadcasts(vec![id]).await?;
+        Ok(shown + replayed)
+    }
+
+    /// Withdraw the broadcasts sent at least `age` ago, optionally only those on a channel, from every desktop
+    async fn close_older(&self, age: Duration, channel: Option<&str>) -> u32 {
+        let now = unix_now();
+        let records = self.state.broadcasts.remove_matching(|record| {
+            record.older_than(age, now) && channel.is_none_or(|channel| record.channel.as_deref() == Some(channel))
+        });
+        self.close_records(records).await
+    }
+
+    /// Withdraw broadcasts the caller sent from every desktop, see [`Self::require_owner`]
+    async fn close_owned(&self, header: &Header<'_>, ids: Vec<BroadcastId>) -> Result<u32, SendError> {
+        self.require_owner(header, &ids).await?;
+        let records = ids.into_iter().filter_map(|id| self.state.broadcasts.remove(id)).collect();
+        Ok(self.close_records(records).await)
+    }
+
+    /// Reject callers other than root and the user running the server
+    async fn require_privileged(&self, header: &Header<'_>) -> zbus::fdo::Result<()> {
+        match self.caller(header).await.and_then(|caller| caller.uid) {
+            Some(uid) if is_privileged(uid) => Ok(()),
+            uid => {
+                warn!(?uid, "Rejecting privileged request.");
+                Err(zbus::fdo::Error::AccessDenied("Only root may make this request".to_string()))
+            }
+        }
+    }
+
+    /// Reject callers changing broadcasts they did not send, unless they are privileged
+    ///
+    /// Callers other than root and the user running the server must be the sender every
+    /// broadcast is attributed to. They are only identified, not authorized to send, so
+    /// changes cost neither rate limit budget nor a polkit check. Callers on the bus that
+    /// cannot be identified own no broadcasts.
+    async fn require_owner(&self, header: &Header<'_>, ids: &[BroadcastId]) -> Result<(), SendError> {
+        let sent = self.caller_sent(header).await;
+        match ids.iter().find(|&&id| !sent(self.state.broadcasts.sender(id).as_deref())) {
+            Some(&broadcast_id) => {
+                warn!(broadcast_id, "Rejecting change to a broadcast of another sender.");
+                let message = format!("Only the sender of broadcast {} may change it", broadcast_id);
+                Err(zbus::fdo::Error::AccessDenied(message).into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Get whether the caller sent a broadcast, from the sender the broadcast is attributed to
+    ///
+    /// Root and the user running the server count as the sender of every broadcast, and
+    /// callers on the bus that cannot be identified as the sender of none.
+    async fn caller_sent(&self, header: &Header<'_>) -> impl Fn(Option<&str>) -> bool {
+        let caller = self.caller(header).await;
+        let privileged = caller.as_ref().and_then(|caller| caller.uid).is_some_and(is_privileged);
+        let anonymous = caller.is_none() && self.state.connection.get().is_some();
+        let sender = caller.map(|caller| self.sender_name(&caller));
+        move |broadcast_sender| privileged || (!anonymous && broadcast_sender == sender.as_deref())
+    }
+
+    /// List the active graphical users a broadcast with some options would reach, by uid
+    async fn active_recipients(&self, options: &SendOptions) -> zbus::fdo::Result<Vec<(u32, String)>> {
+        let users = match self.narrowed_recipients(options).await? {
+            Some(users) => users,
+            None => self.active_users().await?,
+        };
+        let mut users: Vec<(u32, String)> = users.into_iter().map(|user| (user.uid, user.username)).collect();
+        users.sort_unstable();
+        Ok(users)
+    }
+
+    /// Release a broadcast held in quarantine
+    fn take_quarantined(&self, quarantine_id: u64) -> zbus::fdo::Result<QuarantinedBroadcast> {
+        self.state.quarantine.take(quarantine_id).ok_or_else(|| {
+            zbus::fdo::Error::InvalidArgs(format!("No broadcast is held in quarantine as {}", quarantine_id))
+        })
+    }
+
+    /// Create the span the work on a broadcast is traced in, carrying its correlation id
+    fn broadcast_span(&self, broadcast_id: BroadcastId) -> tracing::Span {
+        match self.state.broadcasts.correlation_id(broadcast_id) {
+            Some(correlation_id) => tracing::info_span!("broadcast", broadcast_id, %correlation_id),
+            None => tracing::info_span!("broadcast", broadcast_id),
+        }
+    }
+
+    /// Get the active graphical users, reporting a failure to enumerate them to the caller
+    async fn active_users(&self) -> zbus::fdo::Result<HashSet<TargetUser>> {
+        self.state.sessions.active_users().await.map_err(|e| {
+            error!("Failed to get active users: {}", e);
+            e.into()
+        })
+    }
+
+    /// Get when a notification spooled for a user will be delivered, if known
+    fn deferred_until(&self, user: &TargetUser, decision: RouteDecision) -> Option<DateTime<Local>> {
+        if decision != RouteDecision::OutsideWindow {
+            return None;
+        }
+        let window = self.state.config.delivery_window(&user.username)?;
+        // Windows open the jitter offset later on this host
+        let offset = chrono::TimeDelta::from_std(self.state.jitter.offset()).unwrap_or_default();
+        let now = self.state.jitter.shift(Local::now().naive_local());
+        let opening = window.next_opening(now)? + offset;
+        opening.and_local_timezone(Local).earliest()
+    }
+
+    /// Announce the users a broadcast was spooled for with the `BroadcastDeferred` signal
+    async fn announce_deferrals(&self, broadcast_id: BroadcastId, deferrals: &[Deferral]) {
+        let Some(emitter) = self.state.emitter() else {
+            return;
+        };
+        for deferral in deferrals {
+            let (user, until, reason) = (&deferral.user, deferral.until_rfc3339(), deferral.reason.to_string());
+            let announced =
+                Self::broadcast_deferred(&emitter, broadcast_id, user.uid, &user.username, &until, &reason).await;
+            log_signal_error(announced);
+        }
+    }
+
+    /// Deliver a registered broadcast to users, spooling it for those who cannot be notified now
+    ///
+    /// Returns the users the broadcast was spooled for. Progress is announced with
+    /// the `BroadcastStarted` and `Completed` signals.
+    async fn dispatch(
+        &self,
+        broadcast_id: BroadcastId,
+        users: Vec<TargetUser>,
+        payload: Arc<BroadcastPayload>,
+    ) -> Vec<Deferral> {
+        if let Some(emitter) = self.state.emitter() {
+            log_signal_error(Self::broadcast_started(&emitter, broadcast_id, users.len() as u32).await);
+        }
+        if self.state.paused.is_enabled() {
+            info!(broadcast_id, "Delivery is paused, spooling broadcast.");
+        } else if self.held_for_maintenance(&payload) {
+            info!(broadcast_id, "Maintenance mode is on, spooling non-critical broadcast.");
+        }
+        let (users, spooled): (Vec<_>, Vec<_>) = users
+            .into_iter()
+            .map(|user| (self.route(&user, &payload), user))
+            .partition(|(decision, _)| decision.delivers_now());
+        let users: Vec<TargetUser> = users.into_iter().map(|(_, user)| user).collect();
+
+        let mut deferrals = Vec::new();
+        let mut outcomes = Vec::new();
+        for (decision, user) in spooled {
+            match decision {
+                RouteDecision::OutsideWindow => {
+                    info!(uid = user.uid, username = %user.username, "Outside delivery window, spooling notification.");
+                }
+                RouteDecision::Crowded => {
+                    info!(uid = user.uid, username = %user.username, "Too many critical notifications shown, spooling.");
+                }
+                _ => {}
+            }
+            let entry = SpooledNotification::new(user.clone(), payload.clone()).with_broadcast_id(broadcast_id);
+            let status = match self.state.spool.push_coalesced(entry) {
+                Ok(superseded) => {
+                    for entry in superseded {
+                        let superseded = entry.broadcast_id;
+                        debug!(uid = user.uid, ?superseded, "Coalesced spooled notification of the same group.");
+                        self.note_suppressed(&entry.user);
+                        if let Some(id) = entry.broadcast_id {
+                            self.state.broadcasts.record_status(id, entry.user, DeliveryStatus::Superseded);
+                        }
+                    }
+                    deferrals.push(Deferral {
+                        until: self.deferred_until(&user, decision),
+                        user: user.clone(),
+                        reason: decision,
+                    });
+                    DeliveryStatus::Spooled
+                }
+                Err(e) => {
+                    warn!(broadcast_id, "Dropping notification that cannot be spooled: {}", e);
+                    DeliveryStatus::Failed(DeliveryErrorKind::Other)
+                }
+            };
+            outcomes.push(JournalOutcome::new(&user, &status));
+            self.state.broadcasts.record_status(broadcast_id, user, status);
+        }
+        self.announce_deferrals(broadcast_id, &deferrals).await;
+        info!(
+            spool_bytes = self.state.spool.memory_usage(),
+            history_bytes = self.state.broadcasts.memory_usage(),
+            "Memory held by pending notifications and broadcast history."
+        );
+
+        info!(broadcast_id, "Dispatching notifications to {} users: {:?}", users.len(), users);
+
+        // Hold off shutdown until every user has been notified
+        let inhibitor = match InhibitorLock::delay("Delivering a broadcast notification").await {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                warn!(broadcast_id, "Failed to take shutdown inhibitor, delivering without it: {}", e);
+                None
+            }
+        };
+
+        let notification_tasks = users.into_iter().map(|user| async {
+            let status = self.deliver_broadcast(broadcast_id, user.clone(), payload.clone()).await;
+            (user, status)
+        });
+
+        let delivered = self.run_limited(notification_tasks).await;
+        drop(inhibitor);
+        let stalled: Vec<u32> = delivered
+            .iter()
+            .filter(|(_, status)| *status == DeliveryStatus::Failed(DeliveryErrorKind::Stalled))
+            .map(|(user, _)| user.uid)
+            .collect();
+        if !stalled.is_empty() {
+            warn!(broadcast_id, ?stalled, "Abandoned deliveries to users that hung past the per-user timeout.");
+        }
+        outcomes.extend(delivered.iter().map(|(user, status)| JournalOutcome::new(user, status)));
+        self.record_in_journal(broadcast_id, &payload, outcomes);
+        self.persist_notification_ids();
+        if let Some(emitter) = self.state.emitter() {
+            let failed = delivered.iter().filter(|(_, status)| matches!(status, DeliveryStatus::Failed(_))).count();
+            let delivered = delivered.len() - failed;
+            let completed =
+                Self::completed(&emitter, broadcast_id, delivered as u32, failed as u32, deferrals.len() as u32);
+            log_signal_error(completed.await);
+        }
+        deferrals
+    }
+
+    /// Record a dispatch in the journal, if it is on
+    fn record_in_journal(&self, broadcast_id: BroadcastId, payload: &BroadcastPayload, outcomes: Vec<JournalOutcome>) {
+        if !self.state.journal.is_enabled() {
+            return;
+        }
+        let sender = payload.sender.clone().or_else(|| self.state.broadcasts.sender(broadcast_id));
+        let entry = JournalEntry {
+            timestamp: unix_now(),
+            broadcast_id,
+            sender: sender.map(|sender| sender.to_string()),
+            title_hash: title_hash(&payload.title),
+            outcomes,
+        };
+        if let Err(e) = self.state.journal.append(&entry) {
+            warn!(broadcast_id, "Failed to record the broadcast in the journal: {}", e);
+        }
+    }
+
+    /// Deliver tracked broadcasts again to active users not showing or awaiting them,
+    /// returning the number of users they were dispatched to
+    ///
+    /// This reaches users who logged in after a broadcast was sent or whose delivery failed.
+    async fn replay_broadcasts(&self, ids: Vec<BroadcastId>) -> zbus::fdo::Result<u32> {
+        let users = self.active_users().await?;
+        let mut dispatched = 0;
+        for record in ids.into_iter().filter_map(|id| self.state.broadcasts.get(id)) {
+            let Some(payload) = record.payload.clone() else {
+                continue;
+            };
+            let missing: Vec<TargetUser> = users
+                .iter()
+                .filter(|user| !record.deliveries.iter().any(|(recipient, _)| recipient.uid == user.uid))
+                .filter(|user| {
+                    !record
+                        .report
+                        .iter()
+                        .any(|(recipient, status)| recipient.uid == user.uid && *status == DeliveryStatus::Spooled)
+                })
+                .cloned()
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+            info!(broadcast_id = record.id, "Replaying broadcast to {} users.", missing.len());
+            dispatched += missing.len() as u32;
+            self.dispatch(record.id, missing, payload)
+                .instrument(self.broadcast_span(record.id))
+                .await;
+        }
+        Ok(dispatched)
+    }
+
+    /// Replace the content of broadcasts on every desktop, returning the number of notifications updated
+    async fn update_broadcasts(&self, ids: Vec<BroadcastId>, payload: Arc<BroadcastPayload>) -> u32 {
+        // Notifications still waiting in the spool will be delivered with the new content
+        self.state.spool.update(|entry| {
+            if let Some(id) = entry.broadcast_id.filter(|id| ids.contains(id)) {
+                entry.payload = self.payload_for(id, &payload);
+            }
+        });
+        for &id in &ids {
+            self.state.broadcasts.set_payload(id, self.payload_for(id, &payload));
+        }
+
+        let deliveries = ids.into_iter().flat_map(|id| {
+            self.state.broadcasts
+                .take_deliveries(id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |(user, notification_id)| (id, user, notification_id))
+        });
+        let update_tasks = deliveries.map(|(id, user, notification_id)| {
+            let payload = self.payload_for(id, &payload);
+            let span = self.broadcast_span(id);
+            async move {
+                let updated = self.deliver(&user, payload, notification_id, Some(id)).await;
+                // Keep tracking the old id if the update failed, so the notification can still be closed
+                let updated = updated.ok();
+                self.state.broadcasts.record_delivery(id, user, updated.unwrap_or(notification_id));
+                updated.is_some()
+            }
+            .instrument(span)
+        });
+        let updated = self.run_limited(update_tasks).await.into_iter().filter(|updated| *updated).count();
+        self.persist_notification_ids();
+        updated as u32
+    }
+
+    /// Get the delivery outcome and error message of a broadcast for each recipient
+    fn delivery_results(&self, broadcast_id: BroadcastId) -> Vec<DeliverySummary> {
+        let Some(record) = self.state.broadcasts.get(broadcast_id) else {
+            return Vec::new();
+        };
+        record
+            .report
+            .iter()
+            .map(|(user, status)| {
+                let error = record.error(user.uid).unwrap_or_default().to_string();
+                (user.uid, user.username.clone(), status.to_string(), error)
+            })
+            .collect()
+    }
+
+    /// Withdraw broadcasts from every desktop, returning the number of notifications closed
+    async fn close_records(&self, records: Vec<BroadcastRecord>) -> u32 {
+        // Notifications still waiting in the spool must not be delivered anymore
+        let ids: HashSet<BroadcastId> = records.iter().map(|record| record.id).collect();
+        self.state.spool
+            .take_ready(|entry| entry.broadcast_id.is_some_and(|id| ids.contains(&id)));
+
+        let close_tasks = records
+            .into_iter()
+            .flat_map(|record| record.deliveries)
+            .map(|(user, notification_id)| async move {
+                let bus_name = self.state.config.notification_bus_name(user.desktop());
+                match self.state.sink.close(&user, bus_name, notification_id).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!(uid = user.uid, notification_id, "Failed to close notification: {}", e);
+                        false
+                    }
+                }
+            });
+        let closed = self.run_limited(close_tasks).await.into_iter().filter(|closed| *closed).count();
+        self.persist_notification_ids();
+        closed as u32
+    }
+
+    /// Run tasks reaching users' sessions, at most `max_concurrent_deliveries` of them at a time
+    /// across every broadcast
+    ///
+    /// The outputs are collected in the order the tasks finish.
+    async fn run_limited<F: Future>(&self, tasks: impl IntoIterator<Item = F>) -> Vec<F::Output> {
+        let running = FuturesUnordered::new();
+        for task in tasks {
+            running.push(with_permit(&self.state.deliveries, task));
+        }
+        running.collect().await
+    }
+}
+
+/// Run a task once a permit is available
+async fn with_permit<F: Future>(permits: &Semaphore, task: F) -> F::Output {
+    // The semaphore is never closed
+    let _permit = permits.acquire().await;
+    task.await
+}
+
+/// Whether a uid is root or the user running the server
+fn is_privileged(uid: u32) -> bool {
+    uid == 0 || uid == nix::unistd::geteuid().as_raw()
+}
+
+/// Log a failure to emit a signal, which must not fail the request emitting it
+fn log_signal_error(result: zbus::Result<()>) {
+    if let Err(e) = result {
+        warn!("Failed to emit signal: {}", e);
+    }
+}
+
+/// Get the names of the options of a request, sorted, as recorded in the audit log
+fn option_keys(options: &HashMap<String, OwnedValue>) -> Vec<&str> {
+    let mut keys: Vec<&str> = options.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    keys
+}
+
+/// Check that tags are not empty and drop duplicates, keeping their order
+fn normalize_tags(tags: Vec<String>) -> zbus::fdo::Result<Vec<String>> {
+    if let Some(position) = tags.iter().position(|tag| tag.trim().is_empty()) {
+        let rejection =
+            Rejection::new("tags", RejectionRule::Empty, "Tags must not be empty", "Remove the empty tag");
+        return Err(rejection.at_index(position).into());
+    }
+    let mut unique = HashSet::new();
+    Ok(tags.into_iter().filter(|tag| unique.insert(tag.clone())).collect())
+}
+
+/// Split actions given as `key:label` into unique keys and the labels that differ from them
+fn parse_actions(specs: &[String]) -> zbus::fdo::Result<(Vec<String>, BTreeMap<String, String>)> {
+    let mut keys = Vec::new();
+    let mut labels = BTreeMap::new();
+    for (position, spec) in specs.iter().enumerate() {
+        let (key, label) = parse_action(spec).map_err(|e| {
+            Rejection::new("actions", RejectionRule::Malformed, e, "Write the action as KEY:LABEL").at_index(position)
+        })?;
+        if keys.contains(&key) {
+            let rejection = Rejection::new(
+                "actions",
+                RejectionRule::Duplicate,
+                format!("Duplicate action '{}'", key),
+                "Give each action a different key",
+            );
+            return Err(rejection.at_index(position).into());
+        }
+        if label != key {
+            labels.insert(key.clone(), label);
+        }
+        keys.push(key);
+    }
+    Ok((keys, labels))
+}
+
+impl ServiceState {
+    /// Get an emitter for the service's signals, once it is connected to a bus
+    fn emitter(&self) -> Option<SignalEmitter<'_>> {
+        SignalEmitter::new(self.connection.get()?, DBUS_PATH).ok()
+    }
+}
+
+impl Default for ServiceState {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+            localizer: Localizer::default(),
+            spool: Spool::default(),
+            daemon_watches: Mutex::default(),
+            broadcasts: BroadcastRegistry::default(),
+            polls: PollRegistry::default(),
+            scheduler: Scheduler::default(),
+            nss: NssCache::default(),
+            sessions: SessionCache::default(),
+            maintenance: PersistedSwitch::default(),
+            paused: PersistedSwitch::default(),
+            store: Arc::new(MemoryStore::new()),
+            hooks: HookDir::default(),
+            latency: LatencyTracker::default(),
+            helper_stats: Arc::default(),
+            jitter: Jitter::NONE,
+            journal: Journal::default(),
+            audit: Arc::default(),
+            rate_limiter: RateLimiter::default(),
+            suppressed: SuppressionCounter::default(),
+            quarantine: Quarantine::default(),
+            late_joinable: Mutex::default(),
+            absent: AbsentQueue::default(),
+            welcoming: Mutex::default(),
+            started_at: Instant::now(),
+            last_delivery_error: Mutex::default(),
+            deliveries: Semaphore::new(Config::default().max_concurrent_deliveries()),
+            connection: OnceLock::new(),
+            system_bus: tokio::sync::OnceCell::new(),
+            sink: Arc::new(DbusSink),
+            fallback_sink: Arc::new(TerminalSink::new()),
+        }
+    }
+}
+
+#[interface(name = "me.section.Notifier")]
+impl NotifierService {
+    /// Send notifications to all active graphical users.
+    /// 
+    /// # Arguments
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    /// 
+    /// # Returns
+    /// The id of the broadcast, which can be used to close it later, and the uid,
+    /// username, delivery status and error message (empty if none) of each recipient
+    pub async fn send_to_all(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        title: String,
+        body: String,
+    ) -> Result<(u64, Vec<DeliverySummary>), SendError> {
+        info!(%title, %body, "Received 'send_to_all' request via D-Bus.");
+        self.audited(&header, summarize(&title, &body), async {
+            let payload = self.prepare_payload(title, body, Vec::new(), self.sender(&header).await?)?;
+            let (broadcast_id, _) = self.broadcast(None, Vec::new(), payload).await?;
+            Ok((broadcast_id, self.delivery_results(broadcast_id)))
+        })
+        .await
+    }
+
+    /// Send a notification to a single user with an active graphical session.
+    ///
+    /// # Arguments
+    /// * `user` - The recipient, by username or uid
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    ///
+    /// # Returns
+    /// The id of the broadcast, which can be used to close it later
+    pub async fn send_to_user(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        user: String,
+        title: String,
+        body: String,
+    ) -> Result<u64, SendError> {
+        info!(%user, %title, %body, "Received 'send_to_user' request via D-Bus.");
+        let summary = format!("user={:?} {}", user, summarize(&title, &body));
+        self.audited(&header, summary, async {
+            let users = self.active_users().await?;
+            let recipient = find_user(&users, &user).cloned().ok_or_else(|| {
+                warn!(%user, "Rejecting broadcast to a user without an active graphical session.");
+                zbus::fdo::Error::InvalidArgs(format!("User {} has no active graphical session", user))
+            })?;
+            let payload = self.prepare_payload(title, body, Vec::new(), self.sender(&header).await?)?;
+            Ok(self.broadcast_to(None, Vec::new(), payload, HashSet::from([recipient])).await?.0)
+        })
+        .await
+    }
+
+    /// Send several notifications to all active graphical users at once.
+    ///
+    /// Active sessions are enumerated once for the whole batch and each user's
+    /// session bus is connected to once, rather than for every notification.
+    /// Every notification counts against the sender's rate limit, and nothing is
+    /// sent if any of them is invalid.
+    ///
+    /// # Arguments
+    /// * `notifications` - The title and body of each notification
+    ///
+    /// # Returns
+    /// The id of the broadcast of each notification, in order
+    pub async fn send_batch(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        notifications: Vec<(String, String)>,
+    ) -> Result<Vec<u64>, SendError> {
+        info!(count = notifications.len(), "Received 'send_batch' request via D-Bus.");
+        let summary = notifications.iter().map(|(title, body)| summarize(title, body)).collect::<Vec<_>>();
+        self.audited(&header, format!("batch=[{}]", summary.join(", ")), async {
+            if notifications.is_empty() {
+                return Err(zbus::fdo::Error::InvalidArgs("The batch contains no notifications".to_string()).into());
+            }
+            let caller = self.caller(&header).await;
+            self.authorize_polkit(caller.as_ref()).await?;
+            let mut payloads = Vec::with_capacity(notifications.len());
+            for (title, body) in notifications {
+                let sender = self.authorize_sender(caller.clone())?;
+                payloads.push(self.prepare_payload(title, body, Vec::new(), sender)?);
+            }
+            Ok(self.broadcast_batch(payloads).await?)
+        })
+        .await
+    }
+
+    /// Send notifications to the active graphical users who are members of a Unix group.
+    ///
+    /// Membership counts both the primary group and supplementary groups, as resolved
+    /// through NSS.
+    ///
+    /// # Arguments
+    /// * `group` - The name of the group, such as `wheel`
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    ///
+    /// # Returns
+    /// The id of the broadcast, which can be used to close it later
+    pub async fn send_to_group(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        group: String,
+        title: String,
+        body: String,
+    ) -> Result<u64, SendError> {
+        info!(%group, %title, %body, "Received 'send_to_group' request via D-Bus.");
+        let summary = format!("group={:?} {}", group, summarize(&title, &body));
+        self.audited(&header, summary, async {
+            let options = SendOptions { group: Some(group), ..SendOptions::default() };
+            let sender = self.sender(&header).await?;
+            Ok(self.broadcast_options(title, body, options, sender, None).await?.0)
+        })
+        .await
+    }
+
+    /// Send notifications to all active graphical users, posted to a named channel.
+    ///
+    /// # Arguments
+    /// * `channel` - The channel the broadcast belongs to
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    ///
+    /// # Returns
+    /// The id of the broadcast, which can be used to close it later
+    pub async fn send_to_channel(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        channel: String,
+        title: String,
+        body: String,
+    ) -> Result<u64, SendError> {
+        info!(%channel, %title, %body, "Received 'send_to_channel' request via D-Bus.");
+        let summary = format!("channel={:?} {}", channel, summarize(&title, &body));
+        self.audited(&header, summary, async {
+            let payload = self.prepare_payload(title, body, Vec::new(), self.sender(&header).await?)?;
+            Ok(self.broadcast(Some(channel), Vec::new(), payload).await?.0)
+        })
+        .await
+    }
+
+    /// Send notifications to all active graphical users, tagged so related broadcasts can be managed together.
+    ///
+    /// # Arguments
+    /// * `channel` - The channel the broadcast belongs to, or an empty string for none
+    /// * `tags` - Free-form tags, e.g. the id of an incident
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    ///
+    /// # Returns
+    /// The id of the broadcast, which can be used to close it later
+    pub async fn send_tagged(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        channel: String,
+        tags: Vec<String>,
+        title: String,
+        body: String,
+    ) -> Result<u64, SendError> {
+        info!(%channel, ?tags, %title, %body, "Received 'send_tagged' request via D-Bus.");
+        let summary = format!("channel={:?} tags={:?} {}", channel, tags, summarize(&title, &body));
+        self.audited(&header, summary, async {
+            let tags = normalize_tags(tags)?;
+            let channel = Some(channel).filter(|channel| !channel.is_empty());
+            let payload = self.prepare_payload(title, body, Vec::new(), self.sender(&header).await?)?;
+            Ok(self.broadcast(channel, tags, payload).await?.0)
+        })
+        .await
+    }
+
+    /// Send notifications to all active graphical users, with optional parameters.
+    ///
+    /// # Arguments
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    /// * `options` - Optional parameters: `channel` (s), `tags` (as), `hook` (s),
+    ///   the name of a hook from the hooks directory run after the notification is displayed,
+    ///   `body_commands` (as), allowlisted command lines whose output is appended to the body,
+    ///   `actions` (as), offered as `key:label` and reported with the `NotificationActionInvoked` signal,
+    ///   `urgency` (s), `low`, `normal` or `critical`, overriding the urgency inferred from the content,
+    ///   `markdown` (b), rendering bold, italic and links in the body for daemons supporting markup,
+    ///   `image` (s), the absolute path of an image the caller can read, shown with the notification,
+    ///   and `allow_duplicate` (b), sending the broadcast even if it repeats one sent within the deduplication window
+    ///
+    /// # Returns
+    /// The id of the broadcast, which can be used to close it later
+    pub async fn send_with_options(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        title: String,
+        body: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> Result<u64, SendError> {
+        info!(%title, %body, "Received 'send_with_options' request via D-Bus.");
+        let summary = format!("{} options={:?}", summarize(&title, &body), option_keys(&options));
+        self.audited(&header, summary, async {
+            Ok(self.broadcast_with_options(&header, title, body, options).await?.0)
+        })
+        .await
+    }
+
+    /// Send notifications to all active graphical users, reporting those they are deferred for.
+    ///
+    /// Takes the same arguments as `SendWithOptions`. Each user the broadcast is
+    /// spooled for, e.g. outside their delivery window, is also announced with
+    /// the `BroadcastDeferred` signal.
+    ///
+    /// # Returns
+    /// The id of the broadcast, and the uid, username, time delivery is deferred
+    /// until (RFC 3339, empty if unknown) and reason for each deferred user
+    pub async fn send_with_deferrals(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        title: String,
+        body: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> Result<(u64, Vec<DeferralSummary>), SendError> {
+        info!(%title, %body, "Received 'send_with_deferrals' request via D-Bus.");
+        let summary = format!("{} options={:?}", summarize(&title, &body), option_keys(&options));
+        self.audited(&header, summary, async {
+            let (broadcast_id, deferrals) = self.broadcast_with_options(&header, title, body, options).await?;
+            let deferrals = deferrals
+                .into_iter()
+                .map(|deferral| {
+                    let (until, reason) = (deferral.until_rfc3339(), deferral.reason.to_string());
+                    (deferral.user.uid, deferral.user.username, until, reason)
+                })
+                .collect();
+            Ok((broadcast_id, deferrals))
+        })
+        .await
+    }
+
+    /// Send notifications to all active graphical users at a later time.
+    ///
+    /// The broadcast is kept with the server state, so it is still sent after a
+    /// restart, late if its time passed while the server was down.
+    ///
+    /// # Arguments
+    /// * `at` - When to send the broadcast, in seconds since the Unix epoch
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    /// * `options` - The same optional parameters as `SendWithOptions`
+    ///
+    /// # Returns
+    /// The id of the scheduled broadcast, which can be used to cancel it
+    pub async fn schedule(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        at: u64,
+        title: String,
+        body: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> Result<u64, SendError> {
+        info!(at, %title, %body, "Received 'schedule' request via D-Bus.");
+        let summary = format!("at={} {} options={:?}", at, summarize(&title, &body), option_keys(&options));
+        self.audited(&header, summary, async {
+            let options = SendOptions::from_dict(&options).map_err(zbus::fdo::Error::InvalidArgs)?;
+            if at < unix_now() {
+                warn!(at, "Rejecting broadcast scheduled in the past.");
+                return Err(zbus::fdo::Error::InvalidArgs(format!("The time to send at ({}) has passed", at)).into());
+            }
+            let sender = self.sender(&header).await?;
+            check_content(&title, &body).inspect_err(|e| warn!("Rejecting broadcast: {}", e))?;
+            self.check_options(&options)?;
+            if let Some(path) = &options.image {
+                self.load_image(&header, path).await?;
+            }
+            let schedule_id = self.state.scheduler.add(at, title, body, options, sender);
+            info!(schedule_id, at, "Scheduled broadcast.");
+            self.save_state();
+            Ok(schedule_id)
+        })
+        .await
+    }
+
+    /// List the broadcasts scheduled for later, soonest first.
+    ///
+    /// # Returns
+    /// The id, time to send at (seconds since the Unix epoch), channel (empty if none)
+    /// and title of each scheduled broadcast
+    pub async fn list_scheduled(&self) -> Vec<ScheduledSummary> {
+        self.state
+            .scheduler
+            .list()
+            .into_iter()
+            .filter(|entry| entry.recurrence.is_none())
+            .map(|entry| (entry.id, entry.at, entry.options.channel.unwrap_or_default(), entry.title))
+            .collect()
+    }
+
+    /// Cancel a scheduled broadcast before it is sent.
+    ///
+    /// # Arguments
+    /// * `schedule_id` - The id returned by `Schedule`
+    pub async fn cancel_scheduled(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        schedule_id: u64,
+    ) -> zbus::fdo::Result<()> {
+        info!(schedule_id, "Received 'cancel_scheduled' request via D-Bus.");
+        self.audited(&header, format!("schedule_id={}", schedule_id), async {
+            self.state.scheduler.cancel(schedule_id).ok_or_else(|| {
+                zbus::fdo::Error::InvalidArgs(format!("Unknown scheduled broadcast id {}", schedule_id))
+            })?;
+            self.save_state();
+            Ok(())
+        })
+        .await
+    }
+
+    /// Send notifications to all active graphical users again and again.
+    ///
+    /// Like scheduled broadcasts, recurring ones are kept with the server state.
+    /// Occurrences missed while the server was down are made up for with a single
+    /// broadcast.
+    ///
+    /// # Arguments
+    /// * `rule` - When to send the broadcast: an interval such as `every 1h`, or a
+    ///   five-field cron expression such as `0 9 * * 1` evaluated in the server's time zone
+    /// * `title` - The notification title
+    /// * `body` - The notification body text
+    /// * `options` - The same optional parameters as `SendWithOptions`
+    ///
+    /// # Returns
+    /// The id of the recurring broadcast, which can be used to remove it
+    pub async fn schedule_recurring(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        rule: String,
+        title: String,
+        body: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> Result<u64, SendError> {
+        info!(%rule, %title, %body, "Received 'schedule_recurring' request via D-Bus.");
+        let summary = format!("rule={:?} {} options={:?}", rule, summarize(&title, &body), option_keys(&options));
+        self.audited(&header, summary, async {
+            let options = SendOptions::from_dict(&options).map_err(zbus::fdo::Error::InvalidArgs)?;
+            let recurrence: Recurrence = rule.parse().map_err(zbus::fdo::Error::InvalidArgs)?;
+            let now = unix_now();
+            let Some(at) = recurrence.next_after(now, now) else {
+                warn!(%rule, "Rejecting recurring broadcast that never occurs.");
+                return Err(zbus::fdo::Error::InvalidArgs(format!("'{}' never occurs", rule)).into());
+            };
+            let sender = self.sender(&header).await?;
+            check_content(&title, &body).inspect_err(|e| warn!("Rejecting broadcast: {}", e))?;
+            self.check_options(&options)?;
+            if let Some(path) = &options.image {
+                self.load_image(&header, path).await?;
+            }
+            let schedule_id = self.state.scheduler.add_recurring(at, recurrence, title, body, options, sender);
+            info!(schedule_id, at, "Scheduled recurring broadcast.");
+            self.save_state();
+            Ok(schedule_id)
+        })
+        .await
+    }
+
+    /// List the recurring broadcasts, soonest first.
+    ///
+    /// # Returns
+    /// The id, rule, next time to send at (seconds since the Unix epoch), channel
+    /// (empty if none) and title of each recurring broadcast
+    pub async fn list_recurring(&self) -> Vec<RecurringSummary> {
+        self.state
+            .scheduler
+            .list()
+            .into_iter()
+            .filter_map(|entry| {
+                let rule = entry.recurrence?.to_string();
+                Some((entry.id, rule, entry.at, entry.options.channel.unwrap_or_default(), entry.title))
+            })
+            .collect()
+    }
+
+    /// Stop a recurring broadcast.
+    ///
+    /// # Arguments
+    /// * `schedule_id` - The id returned by `ScheduleRecurring`
+    pub async fn remove_recurring(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        schedule_id: u64,
+    ) -> zbus::fdo::Result<()> {
+        info!(schedule_id, "Received 'remove_recurring' request via D-Bus.");
+        self.audited(&header, format!("schedule_id={}", schedule_id), async {
+            self.state.scheduler.remove_recurring(schedule_id).ok_or_else(|| {
+                zbus::fdo::Error::InvalidArgs(format!("Unknown recurring broadcast id {}", schedule_id))
+            })?;
+            self.save_state();
+            Ok(())
+        })
+        .await
+    }
+
+    /// Emitted when a broadcast is about to be delivered to its recipients
+    #[zbus(signal)]
+    pub async fn broadcast_started(emitter: &SignalEmitter<'_>, broadcast_id: u64, recipients: u32)
+        -> zbus::Result<()>;
+
+    /// Emitted when a broadcast was shown to a user, by their notification daemon or on their terminals
+    #[zbus(signal)]
+    pub async fn user_delivered(
+        emitter: &SignalEmitter<'_>,
+        broadcast_id: u64,
+        uid: u32,
+        username: &str,
+        status: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when a broadcast could not be delivered to a user
+    #[zbus(signal)]
+    pub async fn user_failed(
+        emitter: &SignalEmitter<'_>,
+        broadcast_id: u64,
+        uid: u32,
+        username: &str,
+        status: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when a user invokes an action of a broadcast, such as a poll answer
+    #[zbus(signal)]
+    pub async fn broadcast_action_invoked(
+        emitter: &SignalEmitter<'_>,
+        broadcast_id: u64,
+        uid: u32,
+        username: &str,
+        action: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when a user invokes an action of a notification, for automation that only needs the key
+    #[zbus(signal)]
+    pub async fn notification_action_invoked(emitter: &SignalEmitter<'_>, uid: u32, action_key: &str)
+        -> zbus::Result<()>;
+
+    /// Emitted once a broadcast was delivered to, failed for or spooled for each recipient
+    #[zbus(signal)]
+    pub async fn completed(
+        emitter: &SignalEmitter<'_>,
+        broadcast_id: u64,
+        delivered: u32,
+        failed: u32,
+        deferred: u32,
+    ) -> zbus::Result<()>;
+
+    /// Emitted for each user a broadcast is spooled for rather than delivered right away
+    #[zbus(signal)]
+    pub async fn broadcast_deferred(
+        emitter: &SignalEmitter<'_>,
+        broadcast_id: u64,
+        uid: u32,
+        username: &str,
+        until: &str,
+        reason: &str,
+    ) -> zbus::Result<()>;
+
+    /// Ask all active graphical users a question, offering each option as a notification action.
+    ///
+    /// # Arguments
+    /// * `title` - The notification title
+    /// * `body` - The question
+    /// * `options` - The possible answers
+    ///
+    /// # Returns
+    /// The id of the broadcast, which can be used to query the answers
+    pub async fn send_poll(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        title: String,
+        body: String,
+        options: Vec<String>,
+    ) -> Result<u64, SendError> {
+        info!(%title, %body, ?options, "Received 'send_poll' request via D-Bus.");
+        self.audited(&header, format!("{} options={:?}", summarize(&title, &body), options), async {
+            if options.is_empty() || options.iter().any(|option| option.is_empty()) {
+                let message = "A poll needs at least one non-empty option".to_string();
+                return Err(zbus::fdo::Error::InvalidArgs(message).into());
+            }
+            if options.iter().collect::<HashSet<_>>().len() != options.len() {
+                return Err(zbus::fdo::Error::InvalidArgs("Poll options must be unique".to_string()).into());
+            }
+            let payload = self.prepare_payload(title, body, options, self.sender(&header).await?)?;
+            Ok(self.broadcast(None, Vec::new(), payload).await?.0)
+        })
+        .await
+    }
+
+    /// Get the answers given to a poll so far.
+    ///
+    /// Only the sender of the poll, root and the user running the server may read its answers.
+    ///
+    /// # Returns
+    /// The number of answers per option, and the uid, username and answer of each respondent
+    pub async fn get_poll_results(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        broadcast_id: u64,
+    ) -> Result<(Vec<(String, u32)>, Vec<(u32, String, String)>), SendError> {
+        let poll = self.state.polls.get(broadcast_id).ok_or_else(|| {
+            zbus::fdo::Error::InvalidArgs(format!("Broadcast {} is not a known poll", broadcast_id))
+        })?;
+        self.require_owner(&header, &[broadcast_id]).await?;
+        let responses = poll
+            .responses
+            .iter()
+            .map(|(user, answer)| (user.uid, user.username.clone(), answer.clone()))
+            .collect();
+        Ok((poll.tally(), responses))
+    }
+
+    /// Withdraw a broadcast from every desktop it was delivered to.
+    ///
+    /// Only its sender, root and the user running the server may close a broadcast.
+    ///
+    /// # Returns
+    /// The number of notifications closed
+    pub async fn close_broadcast(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        broadcast_id: u64,
+    ) -> Result<u32, SendError> {
+        info!(broadcast_id, "Received 'close_broadcast' request via D-Bus.");
+        self.audited(&header, format!("broadcast_id={}", broadcast_id), async {
+            if self.state.broadcasts.get(broadcast_id).is_none() {
+                let message = format!("Unknown broadcast id {}", broadcast_id);
+                return Err(zbus::fdo::Error::InvalidArgs(message).into());
+            }
+            self.close_owned(&header, vec![broadcast_id]).await
+        })
+        .await
+    }
+
+    /// Replace the title and body of a broadcast on every desktop it was delivered to.
+    ///
+    /// Only its sender, root and the user running the server may update a broadcast.
+    ///
+    /// # Returns
+    /// The number of notifications updated
+    pub async fn update_broadcast(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        broadcast_id: u64,
+        title: String,
+        body: String,
+    ) -> Result<u32, SendError> {
+        info!(broadcast_id, %title, %body, "Received 'update_broadcast' request via D-Bus.");
+        self.audited(&header, format!("broadcast_id={} {}", broadcast_id, summarize(&title, &body)), async {
+            if self.state.broadcasts.get(broadcast_id).is_none() {
+                let message = format!("Unknown broadcast id {}", broadcast_id);
+                return Err(zbus::fdo::Error::InvalidArgs(message).into());
+            }
+            self.require_owner(&header, &[broadcast_id]).await?;
+            let payload = self.prepare_payload(title, body, Vec::new(), None)?;
+            Ok(self.update_broadcasts(vec![broadcast_id], payload).await)
+        })
+        .await
+    }
+
+    /// Replace the title and body of every broadcast posted to a channel.
+    ///
+    /// Only the sender of all of them, root and the user running the server may update a channel.
+    ///
+    /// # Returns
+    /// The number of notifications updated
+    pub async fn update_channel(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        channel: String,
+        title: String,
+        body: String,
+    ) -> Result<u32, SendError> {
+        info!(%channel, %title, %body, "Received 'update_channel' request via D-Bus.");
+        self.audited(&header, format!("channel={:?} {}", channel, summarize(&title, &body)), async {
+            let ids = self.state.broadcasts.channel_broadcasts(&channel);
+            self.require_owner(&header, &ids).await?;
+            let payload = self.prepare_payload(title, body, Vec::new(), None)?;
+            Ok(self.update_broadcasts(ids, payload).await)
+        })
+        .await
+    }
+
+    /// Drop all cached user and group lookups, e.g. after directory changes.
+    ///
+    /// # Returns
+    /// The number of cache entries dropped
+    pub async fn flush(&self) -> zbus::fdo::Result<u32> {
+        let flushed = self.state.nss.flush();
+        info!(flushed, "Flushed user and group lookup cache.");
+        Ok(flushed as u32)
+    }
+
+    /// Check that the service answers, e.g. from a monitoring script.
+    ///
+    /// # Returns
+    /// The version of the server and how long it has been running in seconds
+    pub async fn ping(&self) -> (String, u64) {
+        (env!("CARGO_PKG_VERSION").to_string(), self.state.started_at.elapsed().as_secs())
+    }
+
+    /// Report the state of the service.
+    ///
+    /// `session_cache_age_secs` is omitted until sessions have been enumerated. `sessions_tracked`
+    /// tells whether sessions are followed through logind's signals rather than enumerated again.
+    /// `last_delivery_error` and the time and uid it is reported with are omitted until a delivery fails.
+    ///
+    /// # Returns
+    /// A dictionary of status values keyed by name
+    pub async fn get_status(&self) -> HashMap<String, OwnedValue> {
+        let mut status = HashMap::new();
+        let (version, uptime) = self.ping().await;
+        status.insert("version".to_string(), OwnedValue::from(Str::from(version)));
+        status.insert("uptime_secs".to_string(), uptime.into());
+        if let Some(age) = self.state.sessions.age() {
+            status.insert("session_cache_age_secs".to_string(), age.as_secs().into());
+        }
+        if let Some((at, uid, error)) = self.state.last_delivery_error.lock().unwrap().clone() {
+            status.insert("last_delivery_error".to_string(), OwnedValue::from(Str::from(error.to_string())));
+            status.insert("last_delivery_error_at".to_string(), at.into());
+            status.insert("last_delivery_error_uid".to_string(), uid.into());
+        }
+        let counters = [
+            ("cached_sessions", self.state.sessions.len()),
+            ("tracked_broadcasts", self.state.broadcasts.len()),
+            ("history_bytes", self.state.broadcasts.memory_usage()),
+            ("pending_notifications", self.state.spool.len()),
+            ("pending_bytes", self.state.spool.memory_usage()),
+            ("nss_cache_entries", self.state.nss.len()),
+            ("quarantined_broadcasts", self.state.quarantine.len()),
+            ("queued_for_absent", self.state.absent.len()),
+        ];
+        for (name, value) in counters {
+            status.insert(name.to_string(), (value as u64).into());
+        }
+        status.insert("maintenance".to_string(), self.state.maintenance.is_enabled().into());
+        status.insert("paused".to_string(), self.state.paused.is_enabled().into());
+        status.insert("sessions_tracked".to_string(), self.state.sessions.is_live().into());
+        status.insert("fire_jitter_offset_secs".to_string(), self.state.jitter.offset().as_secs().into());
+        status
+    }
+
+    /// Get delivery latency statistics.
+    ///
+    /// # Returns
+    /// Latency percentiles over recent deliveries in milliseconds, and the users
+    /// whose recent deliveries were all slower than the configured threshold
+    pub async fn get_stats(&self) -> HashMap<String, OwnedValue> {
+        let latency = self.state.latency.stats();
+        let mut stats = HashMap::new();
+        let values = [
+            ("deliveries", latency.count as u64),
+            ("latency_p50_ms", latency.p50.as_millis() as u64),
+            ("latency_p95_ms", latency.p95.as_millis() as u64),
+            ("latency_p99_ms", latency.p99.as_millis() as u64),
+        ];
+        for (name, value) in values {
+            stats.insert(name.to_string(), value.into());
+        }
+        let slow_users: Vec<String> = self
+            .state
+            .latency
+            .slow_users()
+            .into_iter()
+            .map(|(uid, username)| format!("{}({})", username, uid))
+            .collect();
+        if let Ok(slow_users) = OwnedValue::try_from(zbus::zvariant::Value::from(slow_users)) {
+            stats.insert("slow_users".to_string(), slow_users);
+        }
+        stats
+    }
+
+    /// Get delivery latency, helper and session bus connection metrics in the Prometheus text exposition format.
+    pub async fn get_metrics(&self) -> String {
+        self.state.latency.to_prometheus()
+            + &self.state.helper_stats.to_prometheus()
+            + &session_bus_pool().to_prometheus()
+    }
+
+    /// Get statistics of the runs of the delivery helper since the server started.
+    ///
+    /// # Returns
+    /// The number of failed runs by reason (`spawn`, `timeout`, `exit-<code>`, `signal` or
+    /// `no-notification-id`), and the uid, username, number of runs, failed runs and average
+    /// runtime in milliseconds for each user the helper was run for
+    pub async fn get_helper_stats(&self) -> HelperStatsSummary {
+        let stats = &self.state.helper_stats;
+        let failures = stats.failures().into_iter().map(|(failure, count)| (failure.to_string(), count)).collect();
+        let users = stats
+            .users()
+            .into_iter()
+            .map(|(uid, user)| {
+                let average_runtime_ms = user.average_runtime().as_millis() as u64;
+                (uid, user.username, user.spawns, user.failures, average_runtime_ms)
+            })
+            .collect();
+        (failures, users)
+    }
+
+    /// Report the delivery outcome of a broadcast for each recipient.
+    ///
+    /// # Returns
+    /// The uid, username and delivery status of each recipient
+    pub async fn get_delivery_report(&self, broadcast_id: u64) -> zbus::fdo::Result<Vec<(u32, String, String)>> {
+        let record = self.state.broadcasts.get(broadcast_id).ok_or_else(|| {
+            zbus::fdo::Error::InvalidArgs(format!("Unknown broadcast id {}", broadcast_id))
+        })?;
+        Ok(record
+            .report
+            .into_iter()
+            .map(|(user, status)| (user.uid, user.username, status.to_string()))
+            .collect())
+    }
+
+    /// Report the delivery outcome of a broadcast for each recipient, with the reason deliveries failed.
+    ///
+    /// # Returns
+    /// The uid, username, delivery status and error message (empty if none) of each recipient
+    pub async fn get_delivery_results(&self, broadcast_id: u64) -> zbus::fdo::Result<Vec<DeliverySummary>> {
+        if self.state.broadcasts.get(broadcast_id).is_none() {
+            return Err(zbus::fdo::Error::InvalidArgs(format!("Unknown broadcast id {}", broadcast_id)));
+        }
+        Ok(self.delivery_results(broadcast_id))
+    }
+
+    /// Get the warnings the content lints raised for a broadcast.
+    ///
+    /// # Returns
+    /// One message per warning, empty if the content looked fine
+    pub async fn get_lint_warnings(&self, broadcast_id: u64) -> zbus::fdo::Result<Vec<String>> {
+        let record = self.state.broadcasts.get(broadcast_id).ok_or_else(|| {
+            zbus::fdo::Error::InvalidArgs(format!("Unknown broadcast id {}", broadcast_id))
+        })?;
+        Ok(record.payload.map(|payload| payload.lint_warnings.clone()).unwrap_or_default())
+    }
+
+    /// Withdraw every broadcast carrying a tag from every desktop.
+    ///
+    /// Only the sender of all of them, root and the user running the server may close a tag.
+    ///
+    /// # Returns
+    /// The number of notifications closed
+    pub async fn close_tag(&self, #[zbus(header)] header: Header<'_>, tag: String) -> Result<u32, SendError> {
+        info!(%tag, "Received 'close_tag' request via D-Bus.");
+        self.audited(&header, format!("tag={:?}", tag), async {
+            let ids = self.state.broadcasts.tagged_broadcasts(&tag);
+            self.close_owned(&header, ids).await
+        })
+        .await
+    }
+
+    /// Withdraw the broadcasts of a fleet-wide batch from every desktop.
+    ///
+    /// The broadcasts of a batch carry its tag, so this is [`Self::close_tag`] on it.
+    ///
+    /// # Returns
+    /// The number of notifications closed
+    pub async fn close_batch(&self, #[zbus(header)] header: Header<'_>, batch_id: String) -> Result<u32, SendError> {
+        info!(%batch_id, "Received 'close_batch' request via D-Bus.");
+        self.audited(&header, format!("batch_id={:?}", batch_id), async {
+            if batch_id.trim().is_empty() {
+                return Err(zbus::fdo::Error::InvalidArgs("Batch id cannot be empty".to_string()).into());
+            }
+            let ids = self.state.broadcasts.tagged_broadcasts(&fleet::batch_tag(&batch_id));
+            self.close_owned(&header, ids).await
+        })
+        .await
+    }
+
+    /// Show a progress bar for a long-running job on every desktop.
+    ///
+    /// The notification starts at 0% and is kept as a batch updated with `UpdateProgress`.
+    ///
+    /// # Arguments
+    /// * `title` - The notification title, such as the name of the job
+    /// * `text` - The notification body text, such as the current step
+    ///
+    /// # Returns
+    /// The id of the batch, used to update or close the progress notification
+    pub async fn start_progress(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        title: String,
+        text: String,
+    ) -> Result<String, SendError> {
+        info!(%title, %text, "Received 'start_progress' request via D-Bus.");
+        self.audited(&header, summarize(&title, &text), async {
+            let batch_id = fleet::new_batch_id();
+            let options = SendOptions {
+                tags: vec![fleet::batch_tag(&batch_id)],
+                progress: Some(0),
+                allow_duplicate: true,
+                ..SendOptions::default()
+            };
+            let sender = self.sender(&header).await?;
+            self.broadcast_options(title, text, options, sender, None).await?;
+            Ok(batch_id)
+        })
+        .await
+    }
+
+    /// Move the progress bar of a batch on every desktop, updating its notifications in place.
+    ///
+    /// Only the sender of the batch, root and the user running the server may move it.
+    ///
+    /// # Arguments
+    /// * `batch_id` - The batch id returned by `StartProgress`
+    /// * `percent` - The percentage of the job done, from 0 to 100
+    /// * `text` - The new body text, or empty to keep the current one
+    ///
+    /// # Returns
+    /// The number of notifications updated
+    pub async fn update_progress(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        batch_id: String,
+        percent: u32,
+        text: String,
+    ) -> Result<u32, SendError> {
+        info!(%batch_id, percent, %text, "Received 'update_progress' request via D-Bus.");
+        self.audited(&header, format!("batch_id={:?} percent={} text_bytes={}", batch_id, percent, text.len()), async {
+            let percent = u8::try_from(percent).ok().filter(|percent| *percent <= 100).ok_or_else(|| {
+                zbus::fdo::Error::InvalidArgs(format!("Invalid percentage {}, expected 0 to 100", percent))
+            })?;
+            let ids = self.state.broadcasts.tagged_broadcasts(&fleet::batch_tag(&batch_id));
+            if ids.is_empty() {
+                return Err(zbus::fdo::Error::InvalidArgs(format!("Unknown batch id '{}'", batch_id)).into());
+            }
+            self.require_owner(&header, &ids).await?;
+            let mut updated = 0;
+            for record in ids.into_iter().filter_map(|id| self.state.broadcasts.get(id)) {
+                let Some(payload) = record.payload else {
+                    continue;
+                };
+                let mut payload = Arc::unwrap_or_clone(payload).with_progress(Some(percent));
+                if !text.is_empty() {
+                    check_content(&payload.title, &text).inspect_err(|e| warn!("Rejecting progress update: {}", e))?;
+                    payload.body = Arc::from(text.as_str());
+                }
+                updated += self.update_broadcasts(vec![record.id], Arc::new(payload)).await;
+            }
+            Ok(updated)
+        })
+        .await
+    }
+
+    /// List the tracked broadcasts, oldest first.
+    ///
+    /// Callers other than root and the user running the server only see the broadcasts they sent.
+    ///
+    /// # Arguments
+    /// * `tag` - Only list broadcasts carrying this tag, or all if empty
+    ///
+    /// # Returns
+    /// The id, channel (empty if none), tags and current title of each broadcast
+    pub async fn list_broadcasts(&self, #[zbus(header)] header: Header<'_>, tag: String) -> Vec<BroadcastSummary> {
+        let tag = Some(tag.as_str()).filter(|tag| !tag.is_empty());
+        let sent = self.caller_sent(&header).await;
+        self.state.broadcasts
+            .list(tag)
+            .into_iter()
+            .filter(|record| sent(record.payload.as_ref().and_then(|payload| payload.sender.as_deref())))
+            .map(|record| {
+                let title = record.payload.as_ref().map_or_else(String::new, |payload| payload.title.to_string());
+                (record.id, record.channel.unwrap_or_default(), record.tags, title)
+            })
+            .collect()
+    }
+
+    /// Read the journal of dispatched broadcasts, oldest first.
+    ///
+    /// Callers other than root and the user running the server only see the broadcasts they sent.
+    ///
+    /// # Arguments
+    /// * `limit` - Only return this many of the latest entries, or all if 0
+    ///
+    /// # Returns
+    /// The time (seconds since the Unix epoch), broadcast id, sender (empty if unknown), title hash and
+    /// per-user outcomes of each dispatch
+    pub async fn get_history(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        limit: u32,
+    ) -> zbus::fdo::Result<Vec<HistoryEntry>> {
+        info!(limit, "Received 'get_history' request via D-Bus.");
+        if !self.state.journal.is_enabled() {
+            return Err(zbus::fdo::Error::NotSupported("The journal is off; set `journal = true` to keep one".into()));
+        }
+        let sent = self.caller_sent(&header).await;
+        let mut entries = self.state.journal
+            .read(0)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to read the journal: {}", e)))?;
+        entries.retain(|entry| sent(entry.sender.as_deref()));
+        let skipped = if limit == 0 { 0 } else { entries.len().saturating_sub(limit as usize) };
+        Ok(entries
+            .into_iter()
+            .skip(skipped)
+            .map(|entry| {
+                let outcomes = entry.outcomes.into_iter().map(|outcome| (outcome.uid, outcome.username, outcome.status));
+                (entry.timestamp, entry.broadcast_id, entry.sender.unwrap_or_default(), entry.title_hash, outcomes.collect())
+            })
+            .collect())
+    }
+
+    /// Deliver a broadcast again to active users who are not showing it, e.g. because they logged in later.
+    ///
+    /// Only its sender, root and the user running the server may replay a broadcast.
+    ///
+    /// # Returns
+    /// The number of users the broadcast was sent to
+    pub async fn replay_broadcast(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        broadcast_id: u64,
+    ) -> Result<u32, SendError> {
+        info!(broadcast_id, "Received 'replay_broadcast' request via D-Bus.");
+        self.audited(&header, format!("broadcast_id={}", broadcast_id), async {
+            if self.state.broadcasts.get(broadcast_id).is_none() {
+                let message = format!("Unknown broadcast id {}", broadcast_id);
+                return Err(zbus::fdo::Error::InvalidArgs(message).into());
+            }
+            self.require_owner(&header, &[broadcast_id]).await?;
+            Ok(self.replay_broadcasts(vec![broadcast_id]).await?)
+        })
+        .await
+    }
+
+    /// Deliver every broadcast carrying a tag again to active users who are not showing it.
+    ///
+    /// Only the sender of all of them, root and the user running the server may replay a tag.
+    ///
+    /// # Returns
+    /// The number of deliveries made
+    pub async fn replay_tag(&self, #[zbus(header)] header: Header<'_>, tag: String) -> Result<u32, SendError> {
+        info!(%tag, "Received 'replay_tag' request via D-Bus.");
+        self.audited(&header, format!("tag={:?}", tag), async {
+            let ids = self.state.broadcasts.tagged_broadcasts(&tag);
+            self.require_owner(&header, &ids).await?;
+            Ok(self.replay_broadcasts(ids).await?)
+        })
+        .await
+    }
+
+    /// Deliver the latest critical broadcast again to every active user, e.g. after a shift change or
+    /// after a display manager restart wiped the notifications off the desktops.
+    ///
+    /// Users still tracked as showing it have their notification replaced in place, so nobody sees it twice.
+    /// The server does the same when it receives `SIGUSR1`.
+    /// Only root and the user running the server may re-announce.
+    ///
+    /// # Returns
+    /// The number of users the broadcast was sent to; 0 if no critical broadcast is tracked
+    pub async fn re_announce(&self, #[zbus(header)] header: Header<'_>) -> zbus::fdo::Result<u32> {
+        info!("Received 're_announce' request via D-Bus.");
+        self.audited(&header, String::new(), async {
+            self.require_privileged(&header).await?;
+            self.re_announce_critical().await
+        })
+        .await
+    }
+
+    /// Tell the server a user's login session is ready, e.g. from a PAM session hook.
+    ///
+    /// Once the user's notification daemon is up, they get their spooled notifications that may be delivered now
+    /// and the broadcasts still open to users logging in late, rather than waiting for the next spool flush.
+    /// Only root and the user running the server may report sessions.
+    ///
+    /// # Arguments
+    /// * `user` - The user who logged in, by username or uid
+    ///
+    /// # Returns
+    /// The number of notifications waiting for the user; 0 if they have no active graphical session
+    pub async fn session_ready(&self, #[zbus(header)] header: Header<'_>, user: String) -> zbus::fdo::Result<u32> {
+        self.audited(&header, format!("user={:?}", user), async {
+            self.require_privileged(&header).await?;
+            info!(%user, "Received 'session_ready' request.");
+            // The cached sessions predate the login
+            let users = self.state.sessions.refresh().await.map_err(|e| {
+                error!("Failed to get active users: {}", e);
+                zbus::fdo::Error::from(e)
+            })?;
+            let Some(recipient) = find_user(&users, &user).cloned() else {
+                info!(%user, "User has no active graphical session to deliver to.");
+                return Ok(0);
+            };
+            let waiting = self.waiting_on_login(&recipient);
+            if waiting > 0 {
+                self.welcome_when_ready(recipient);
+            }
+            Ok(waiting)
+        })
+        .await
+    }
+
+    /// List the broadcasts from unknown senders held in quarantine, oldest first.
+    ///
+    /// Only root and the user running the server may review the quarantine.
+    ///
+    /// # Returns
+    /// The quarantine id, time of arrival (seconds since the Unix epoch), input, sender,
+    /// title and body of each held broadcast
+    pub async fn list_quarantined(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+    ) -> zbus::fdo::Result<Vec<QuarantineSummary>> {
+        self.require_privileged(&header).await?;
+        Ok(self
+            .state
+            .quarantine
+            .list()
+            .into_iter()
+            .map(|held| (held.id, held.received_at, held.input, held.caller, held.request.title, held.request.body))
+            .collect())
+    }
+
+    /// Send a broadcast held in quarantine, as if its sender were known.
+    ///
+    /// Only root and the user running the server may approve broadcasts.
+    ///
+    /// # Arguments
+    /// * `quarantine_id` - The id listed by `ListQuarantined`
+    ///
+    /// # Returns
+    /// The broadcast id
+    pub async fn approve_quarantined(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        quarantine_id: u64,
+    ) -> Result<u64, SendError> {
+        self.audited(&header, format!("quarantine_id={}", quarantine_id), async {
+            self.require_privileged(&header).await?;
+            let held = self.take_quarantined(quarantine_id)?;
+            info!(quarantine_id, caller = %held.caller, "Approved quarantined broadcast.");
+            self.send_request(held.request, held.sender).await
+        })
+        .await
+    }
+
+    /// Drop a broadcast held in quarantine without sending it.
+    ///
+    /// Only root and the user running the server may deny broadcasts.
+    ///
+    /// # Arguments
+    /// * `quarantine_id` - The id listed by `ListQuarantined`
+    pub async fn deny_quarantined(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        quarantine_id: u64,
+    ) -> zbus::fdo::Result<()> {
+        self.audited(&header, format!("quarantine_id={}", quarantine_id), async {
+            self.require_privileged(&header).await?;
+            let held = self.take_quarantined(quarantine_id)?;
+            info!(quarantine_id, caller = %held.caller, title = %held.request.title, "Denied quarantined broadcast.");
+            Ok(())
+        })
+        .await
+    }
+
+    /// Report what the server sees of a login session, to debug why its user is not notified.
+    ///
+    /// Only root and the user running the server may inspect sessions.
+    ///
+    /// # Arguments
+    /// * `session_id` - The logind session id, as listed by `loginctl`
+    ///
+    /// # Returns
+    /// A dictionary of the session's details, bus address, runtime directory and
+    /// notification daemon with its capabilities, or the error reaching it
+    pub async fn inspect_session(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        session_id: String,
+    ) -> zbus::fdo::Result<HashMap<String, OwnedValue>> {
+        info!(%session_id, "Received 'inspect_session' request via D-Bus.");
+        self.require_privileged(&header).await?;
+        let report = SessionReport::inspect(&session_id, &self.state.config).await.map_err(|e| match e {
+            NotifierError::SessionEnumeration(e) => {
+                zbus::fdo::Error::InvalidArgs(format!("Cannot inspect session {}: {}", session_id, e))
+            }
+            e => e.into(),
+        })?;
+        Ok(report.to_dict())
+    }
+
+    /// Get the optional features the notification daemon of a user supports, which
+    /// notifications sent to them are adapted to.
+    ///
+    /// Only root and the user running the server may inspect users' daemons.
+    ///
+    /// # Arguments
+    /// * `uid` - The uid of a user with an active graphical session
+    ///
+    /// # Returns
+    /// The capabilities the daemon reports, such as `actions`, `body-markup` or `body-images`
+    pub async fn get_user_capabilities(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        uid: u32,
+    ) -> zbus::fdo::Result<Vec<String>> {
+        info!(uid, "Received 'get_user_capabilities' request via D-Bus.");
+        self.require_privileged(&header).await?;
+        let users = self.active_users().await?;
+        let user = users.iter().find(|user| user.uid == uid).ok_or_else(|| {
+            zbus::fdo::Error::InvalidArgs(format!("User {} has no active graphical session", uid))
+        })?;
+        let bus_name = self.state.config.notification_bus_name(user.desktop());
+        let server = inspect_notification_server(user, bus_name).await.map_err(zbus::fdo::Error::from)?;
+        Ok(server.capabilities)
+    }
+
+    /// Evaluate how a broadcast would be routed, without sending anything.
+    ///
+    /// # Arguments
+    /// * `title` - The notification title, matched against the urgency rules
+    /// * `body` - The notification body text, matched against the urgency rules
+    /// * `urgency` - The urgency to assume (`low`, `normal` or `critical`), or empty to infer it
+    /// * `user` - Only evaluate this user, by name or uid, or all active users if empty
+    ///
+    /// # Returns
+    /// The effective urgency, and the uid, username, decision and notification service of each recipient
+    pub async fn route_test(
+        &self,
+        title: String,
+        body: String,
+        urgency: String,
+        user: String,
+    ) -> zbus::fdo::Result<(String, Vec<RouteSummary>)> {
+        let urgency = match urgency.as_str() {
+            "" => infer_urgency(&self.state.config.urgency_rules, &title, &body),
+            urgency => Some(urgency.parse::<Urgency>().map_err(zbus::fdo::Error::InvalidArgs)?),
+        };
+        let payload = BroadcastPayload::new(title, body).with_urgency(urgency);
+
+        let mut users: Vec<TargetUser> = self
+            .active_users()
+            .await?
+            .into_iter()
+            .filter(|candidate| user.is_empty() || candidate.username == user || candidate.uid.to_string() == user)
+            .collect();
+        if users.is_empty() && !user.is_empty() {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "User {} has no active graphical session",
+                user
+            )));
+        }
+        users.sort_by_key(|user| user.uid);
+
+        let routes = users
+            .into_iter()
+            .map(|user| {
+                let decision = self.route(&user, &payload);
+                let mut backend = self.state.config.notification_bus_name(user.desktop()).to_string();
+                if self.state.config.terminal_fallback {
+                    backend.push_str(", terminals if no daemon");
+                }
+                (user.uid, user.username, decision.to_string(), backend)
+            })
+            .collect();
+        Ok((payload.urgency.unwrap_or_default().to_string(), routes))
+    }
+
+    /// List the active graphical users a broadcast would reach, without sending anything.
+    ///
+    /// Only root and the user running the server may list users.
+    ///
+    /// # Arguments
+    /// * `options` - The options of `SendWithOptions`; only `group`, `exclude_users` and `seat` narrow the users
+    ///
+    /// # Returns
+    /// The uid and username of each user, by uid
+    pub async fn list_active_users(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        options: HashMap<String, OwnedValue>,
+    ) -> zbus::fdo::Result<Vec<(u32, String)>> {
+        self.require_privileged(&header).await?;
+        let options = SendOptions::from_dict(&options).map_err(zbus::fdo::Error::InvalidArgs)?;
+        self.active_recipients(&options).await
+    }
+
+    /// Dump the server state into a JSON archive.
+    ///
+    /// Only root and the user running the server may export the state, which holds private bodies.
+    ///
+    /// # Returns
+    /// The archive, holding pending notifications, tracked broadcasts, polls and the maintenance mode
+    pub async fn export_state(&self, #[zbus(header)] header: Header<'_>) -> zbus::fdo::Result<String> {
+        info!("Received 'export_state' request via D-Bus.");
+        self.require_privileged(&header).await?;
+        self.export_archive()
+            .to_json()
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to serialize state: {}", e)))
+    }
+
+    /// Restore server state from a JSON archive produced by `ExportState`.
+    ///
+    /// Only root and the user running the server may import state, which queues deliveries.
+    ///
+    /// # Returns
+    /// The number of broadcasts, pending notifications and polls restored
+    pub async fn import_state(&self, #[zbus(header)] header: Header<'_>, archive: String) -> zbus::fdo::Result<u32> {
+        info!(bytes = archive.len(), "Received 'import_state' request via D-Bus.");
+        self.audited(&header, format!("archive_bytes={}", archive.len()), async {
+            self.require_privileged(&header).await?;
+            let archive = StateArchive::from_json(&archive)
+                .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("Invalid state archive: {}", e)))?;
+            self.import_archive(archive).await
+        })
+        .await
+    }
+
+    /// Switch maintenance mode on or off.
+    ///
+    /// While it is on only critical broadcasts are delivered and everything else
+    /// is spooled until it is switched off again. The mode survives restarts.
+    /// Only root and the user running the server may switch it.
+    pub async fn set_maintenance(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        enabled: bool,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        info!(enabled, "Received 'set_maintenance' request via D-Bus.");
+        self.audited(&header, format!("enabled={}", enabled), async {
+            self.require_privileged(&header).await?;
+            let result = self.set_maintenance_mode(enabled).await;
+            if let Err(e) = self.maintenance_changed(&emitter).await {
+                warn!("Failed to announce maintenance mode change: {}", e);
+            }
+            result
+        })
+        .await
+    }
+
+    /// Whether the server is in maintenance mode.
+    #[zbus(property)]
+    pub async fn maintenance(&self) -> bool {
+        self.state.maintenance.is_enabled()
+    }
+
+    /// Hold all outgoing notifications in the spool, even critical ones.
+    ///
+    /// Delivery stays paused across restarts until it is resumed.
+    ///
+    /// Only root and the user running the server may pause delivery.
+    pub async fn pause_delivery(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        info!("Received 'pause_delivery' request via D-Bus.");
+        self.audited(&header, String::new(), async {
+            self.require_privileged(&header).await?;
+            let result = self.set_paused(true).await;
+            if let Err(e) = self.paused_changed(&emitter).await {
+                warn!("Failed to announce paused delivery: {}", e);
+            }
+            result
+        })
+        .await
+    }
+
+    /// Resume delivery, delivering the notifications held while it was paused.
+    ///
+    /// Only root and the user running the server may resume delivery.
+    pub async fn resume_delivery(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        info!("Received 'resume_delivery' request via D-Bus.");
+        self.audited(&header, String::new(), async {
+            self.require_privileged(&header).await?;
+            let result = self.set_paused(false).await;
+            if let Err(e) = self.paused_changed(&emitter).await {
+                warn!("Failed to announce resumed delivery: {}", e);
+            }
+            result
+        })
+        .await
+    }
+
+    /// Whether delivery is paused.
+    #[zbus(property)]
+    pub async fn paused(&self) -> bool {
+        self.state.paused.is_enabled()
+    }
+
+    /// Withdraw every broadcast posted to a channel from every desktop.
+    ///
+    /// Only the sender of all of them, root and the user running the server may close a channel.
+    ///
+    /// # Returns
+    /// The number of notifications closed
+    pub async fn close_channel(&self, #[zbus(header)] header: Header<'_>, channel: String) -> Result<u32, SendError> {
+        info!(%channel, "Received 'close_channel' request via D-Bus.");
+        self.audited(&header, format!("channel={:?}", channel), async {
+            let ids = self.state.broadcasts.channel_broadcasts(&channel);
+            self.close_owned(&header, ids).await
+        })
+        .await
+    }
+
+    /// Withdraw the broadcasts a notification with the same options would have been posted to.
+    ///
+    /// Used by `send --resolved` so a monitoring check closes its own alert once it recovers,
+    /// with the channel given explicitly or derived from the category and fingerprint.
+    /// Only the sender of the broadcasts, root and the user running the server may resolve them.
+    ///
+    /// # Returns
+    /// The number of notifications closed
+    pub async fn resolve(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        title: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> Result<u32, SendError> {
+        info!(%title, "Received 'resolve' request via D-Bus.");
+        let options = SendOptions::from_dict(&options).map_err(zbus::fdo::Error::InvalidArgs)?;
+        let Some(channel) = self.state.config.broadcast_channel(&options, &title) else {
+            let message = "A resolved notification needs a channel, or a category mapped to one".to_string();
+            return Err(zbus::fdo::Error::InvalidArgs(message).into());
+        };
+        self.audited(&header, format!("channel={:?}", channel), async {
+            let ids = self.state.broadcasts.channel_broadcasts(&channel);
+            self.close_owned(&header, ids).await
+        })
+        .await
+    }
+
+    /// Withdraw every tracked broadcast from every desktop, such as stale alerts after an incident.
+    ///
+    /// Only root and the user running the server may close the broadcasts of all senders.
+    ///
+    /// # Arguments
+    /// * `older_than_secs` - Only close broadcasts sent at least this many seconds ago, or all if 0
+    /// * `channel` - Only close broadcasts posted to this channel, or all if empty
+    ///
+    /// # Returns
+    /// The number of notifications closed
+    pub async fn close_all(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        older_than_secs: u64,
+        channel: String,
+    ) -> zbus::fdo::Result<u32> {
+        info!(older_than_secs, %channel, "Received 'close_all' request via D-Bus.");
+        self.audited(&header, format!("older_than_secs={} channel={:?}", older_than_secs, channel), async {
+            self.require_privileged(&header).await?;
+            let channel = Some(channel.as_str()).filter(|channel| !channel.is_empty());
+            Ok(self.close_older(Duration::from_secs(older_than_secs), channel).await)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use futures::future::BoxFuture;
+
+    use crate::helper_stats::HelperFailure;
+    use crate::sound::Sound;
+
+    /// Sink recording deliveries instead of talking to session buses
+    #[derive(Debug, Default)]
+    struct RecordingSink {
+        delivered: Mutex<Vec<(TargetUser, Arc<BroadcastPayload>, DeliveryOptions)>>,
+        closed: Mutex<Vec<(TargetUser, u32)>>,
+    }
+
+    impl NotificationSink for RecordingSink {
+        fn notify<'a>(
+            &'a self,
+            user: &'a TargetUser,
+            payload: Arc<BroadcastPayload>,
+            options: &'a DeliveryOptions,
+        ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+            let mut delivered = self.delivered.lock().unwrap();
+            delivered.push((user.clone(), payload, options.clone()));
+            let notification_id = delivered.len() as u32;
+            Box::pin(async move { Ok(notification_id) })
+        }
+
+        fn close<'a>(
+            &'a self,
+            user: &'a TargetUser,
+            _bus_name: &'a str,
+            notification_id: u32,
+        ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            self.closed.lock().unwrap().push((user.clone(), notification_id));
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Sink behaving like a session without a notification daemon
+    #[derive(Debug)]
+    struct NoDaemonSink;
+
+    impl NotificationSink for NoDaemonSink {
+        fn notify<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _payload: Arc<BroadcastPayload>,
+            _options: &'a DeliveryOptions,
+        ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+            Box::pin(async { Err(DeliveryError::new(DeliveryErrorKind::DaemonMissing, "no owner")) })
+        }
+
+        fn close<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _bus_name: &'a str,
+            _notification_id: u32,
+        ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// A method call as received from a client on the bus
+    fn call() -> zbus::Message {
+        zbus::Message::method_call(dbus::DBUS_PATH, "SendToAll")
+            .unwrap()
+            .sender(":1.42")
+            .unwrap()
+            .build(&())
+            .unwrap()
+    }
+
+    fn spooled(user: TargetUser, title: &str, body: &str) -> SpooledNotification {
+        SpooledNotification::new(user, Arc::new(BroadcastPayload::new(title, body)))
+    }
+
+    #[test]
+    fn test_notifier_service_creation() {
+        let service = NotifierService::default();
+        let debug_str = format!("{:?}", service);
+        assert!(!debug_str.is_empty());
+    }
+
+    #[test]
+    fn test_clones_share_state() {
+        let service = NotifierService::default();
+        let handle = service.clone();
+        let user = TargetUser::new(1000, "alice".to_string());
+        handle.spool().push(spooled(user, "title", "body")).unwrap();
+        let id = handle.broadcasts().register(None);
+
+        assert!(Arc::ptr_eq(&service.state, &handle.state));
+        assert_eq!(service.spool().len(), 1);
+        assert!(service.broadcasts().get(id).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "configured before it is cloned")]
+    fn test_configuring_shared_service_panics() {
+        let service = NotifierService::default();
+        let _handle = service.clone();
+        let _ = service.with_sink(Arc::new(RecordingSink::default()));
+    }
+
+    #[test]
+    fn test_users_without_window_always_deliverable() {
+        let service = NotifierService::default();
+        let user = TargetUser::new(1000, "alice".to_string());
+        assert!(service.is_deliverable_now(&user));
+    }
+
+    #[test]
+    fn test_users_outside_window_not_deliverable() {
+        // An empty window (start == end) never contains any time of day
+        let config = Config::from_toml_str("[delivery_windows]\nnightshift = \"00:00-00:00\"\n").unwrap();
+        let service = NotifierService::new(config);
+        let user = TargetUser::new(1000, "nightshift".to_string());
+        assert!(!service.is_deliverable_now(&user));
+    }
+
+    #[test]
+    fn test_jitter_delays_window_opening() {
+        let now = Local::now().time();
+        let opened = |minutes| (now - chrono::TimeDelta::minutes(minutes)).format("%H:%M");
+        let closes = (now + chrono::TimeDelta::minutes(60)).format("%H:%M");
+        let config = format!("[delivery_windows]\nalice = \"{}-{}\"\n", opened(2), closes);
+        let user = TargetUser::new(1000, "alice".to_string());
+
+        let service = NotifierService::new(Config::from_toml_str(&config).unwrap());
+        assert!(service.is_deliverable_now(&user));
+        let service = NotifierService::new(Config::from_toml_str(&config).unwrap())
+            .with_jitter(Jitter::fixed(std::time::Duration::from_secs(600)));
+        assert!(!service.is_deliverable_now(&user));
+    }
+
+    #[tokio::test]
+    async fn test_flush_spool_keeps_entries_outside_window() {
+        let config = Config::from_toml_str("[delivery_windows]\nnightshift = \"00:00-00:00\"\n").unwrap();
+        let service = NotifierService::new(config);
+        let user = TargetUser::new(1000, "nightshift".to_string());
+        service.spool().push(spooled(user, "title", "body")).unwrap();
+
+        service.flush_spool().await;
+        assert_eq!(service.spool().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_unknown_broadcast() {
+        let service = NotifierService::default();
+        assert!(service.update_broadcast(call().header(), 42, "title".to_string(), "body".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_requires_sender() {
+        let service = NotifierService::default();
+        let payload = BroadcastPayload::new("Backup", "Running").with_sender(Some(Arc::from("backup.service")));
+        let id = service.broadcasts().register_with(Some("backups".to_string()), Vec::new(), Some(Arc::new(payload)));
+        let (title, body) = ("Backup".to_string(), "Done".to_string());
+
+        let updated = service.update_broadcast(call().header(), id, title.clone(), body.clone()).await;
+        assert!(matches!(updated, Err(SendError::Fdo(zbus::fdo::Error::AccessDenied(_)))));
+        let updated = service.update_channel(call().header(), "backups".to_string(), title, body).await;
+        assert!(matches!(updated, Err(SendError::Fdo(zbus::fdo::Error::AccessDenied(_)))));
+        assert_eq!(&*service.broadcasts().get(id).unwrap().payload.unwrap().body, "Running");
+    }
+
+    #[tokio::test]
+    async fn test_listed_broadcasts_scoped_to_sender() {
+        let service = NotifierService::default();
+        let payload = BroadcastPayload::new("Backup", "Running").with_sender(Some(Arc::from("backup.service")));
+        service.broadcasts().register_with(None, Vec::new(), Some(Arc::new(payload)));
+        let ours = service.broadcasts().register(None);
+
+        let listed = service.list_broadcasts(call().header(), String::new()).await;
+        assert_eq!(listed.iter().map(|(id, ..)| *id).collect::<Vec<_>>(), vec![ours]);
+    }
+
+    #[tokio::test]
+    async fn test_close_requires_sender() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let user = TargetUser::new(1000, "alice".to_string());
+        let payload = BroadcastPayload::new("Backup", "Running").with_sender(Some(Arc::from("backup.service")));
+        let tags = vec!["nightly".to_string()];
+        let theirs = service.broadcasts().register_with(Some("backups".to_string()), tags, Some(Arc::new(payload)));
+        let ours = service.broadcasts().register(Some("backups".to_string()));
+        service.broadcasts().record_delivery(theirs, user.clone(), 1);
+        service.broadcasts().record_delivery(ours, user, 2);
+
+        let denied =
+            |result: Result<u32, SendError>| matches!(result, Err(SendError::Fdo(zbus::fdo::Error::AccessDenied(_))));
+        assert!(denied(service.close_broadcast(call().header(), theirs).await));
+        assert!(denied(service.close_channel(call().header(), "backups".to_string()).await));
+        assert!(denied(service.close_tag(call().header(), "nightly".to_string()).await));
+        assert!(denied(service.replay_broadcast(call().header(), theirs).await));
+        assert!(sink.closed.lock().unwrap().is_empty());
+        assert_eq!(service.broadcasts().len(), 2);
+
+        assert_eq!(service.close_broadcast(call().header(), ours).await.unwrap(), 1);
+        assert!(service.broadcasts().get(theirs).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_update_rewrites_spooled_notifications() {
+        let service = NotifierService::default();
+        let id = service.broadcasts().register(Some("status".to_string()));
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user, "old", "old body").with_broadcast_id(id)).unwrap();
+
+        let updated = service
+            .update_channel(call().header(), "status".to_string(), "new".to_string(), "new body".to_string())
+            .await
+            .unwrap();
+        assert_eq!(updated, 0);
+
+        let entries = service.spool().take_ready(|_| true);
+        assert_eq!(&*entries[0].payload.title, "new");
+        assert_eq!(&*entries[0].payload.body, "new body");
+        assert!(service.broadcasts().get(id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_lint_warnings_recorded() {
+        let service = NotifierService::default()
+            .with_sink(Arc::new(RecordingSink::default()))
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let (title, body) = ("REBOOT TONIGHT".to_string(), "On {host}".to_string());
+        let (id, _) = service.send_to_all(call().header(), title, body).await.unwrap();
+        let warnings = service.get_lint_warnings(id).await.unwrap();
+        assert_eq!(warnings, vec![
+            "The title is in all capitals".to_string(),
+            "Template variable {host} was not filled in".to_string(),
+        ]);
+
+        let (id, _) = service.send_to_all(call().header(), "Reboot".to_string(), "Tonight".to_string()).await.unwrap();
+        assert!(service.get_lint_warnings(id).await.unwrap().is_empty());
+        assert!(service.get_lint_warnings(id + 100).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_payload_rejected() {
+        let config = Config::from_toml_str("[limits]\nmax_payload_bytes = 16\n").unwrap();
+        let service = NotifierService::new(config);
+        let result = service.send_to_all(call().header(), "title".to_string(), "b".repeat(16)).await;
+        assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::LimitsExceeded(_)))));
+
+        let id = service.broadcasts().register(None);
+        let result = service.update_broadcast(call().header(), id, "title".to_string(), "b".repeat(16)).await;
+        assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::LimitsExceeded(_)))));
+        assert_eq!(service.broadcasts().len(), 1);
+
+        // Options count towards the limit too
+        let group_key = OwnedValue::try_from(zbus::zvariant::Value::from("g".repeat(16))).unwrap();
+        let options = HashMap::from([("group_key".to_string(), group_key)]);
+        let result = service.send_with_options(call().header(), "title".to_string(), "b".to_string(), options).await;
+        assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::LimitsExceeded(_)))));
+        assert_eq!(service.broadcasts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flush_nss_cache() {
+        let service = NotifierService::default();
+        service.nss().user("root").unwrap();
+        assert_eq!(service.flush().await.unwrap(), 1);
+        assert!(service.nss().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_session_cache_age() {
+        let service = NotifierService::default();
+        let status = service.get_status().await;
+        assert!(!status.contains_key("session_cache_age_secs"));
+        assert_eq!(u64::try_from(&status["cached_sessions"]).unwrap(), 0);
+
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        let status = service.get_status().await;
+        assert_eq!(u64::try_from(&status["session_cache_age_secs"]).unwrap(), 0);
+        assert_eq!(u64::try_from(&status["cached_sessions"]).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_health() {
+        let service = NotifierService::default()
+            .with_sink(Arc::new(NoDaemonSink))
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let (version, _uptime) = service.ping().await;
+        assert_eq!(version, env!("CARGO_PKG_VERSION"));
+        let status = service.get_status().await;
+        assert_eq!(String::try_from(status["version"].clone()).unwrap(), version);
+        assert!(status.contains_key("uptime_secs"));
+        assert!(!status.contains_key("last_delivery_error"));
+
+        service.send_to_all(call().header(), "title".to_string(), "body".to_string()).await.unwrap();
+        let status = service.get_status().await;
+        assert_eq!(String::try_from(status["last_delivery_error"].clone()).unwrap(), "daemon-missing: no owner");
+        assert_eq!(u32::try_from(&status["last_delivery_error_uid"]).unwrap(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_close_unknown_broadcast() {
+        let service = NotifierService::default();
+        assert!(service.close_broadcast(call().header(), 42).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_close_drops_spooled_notifications() {
+        let service = NotifierService::default();
+        let id = service.broadcasts().register(Some("backups".to_string()));
+        let other = service.broadcasts().register(None);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user.clone(), "a", "b").with_broadcast_id(id)).unwrap();
+        service.spool().push(spooled(user, "c", "d").with_broadcast_id(other)).unwrap();
+
+        assert_eq!(service.close_channel(call().header(), "backups".to_string()).await.unwrap(), 0);
+        assert_eq!(service.spool().len(), 1);
+        assert!(service.broadcasts().get(id).is_none());
+        assert!(service.broadcasts().get(other).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_close_all() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let user = TargetUser::new(1000, "alice".to_string());
+        let stale = service.broadcasts().register(Some("incidents".to_string()));
+        let fresh = service.broadcasts().register(Some("incidents".to_string()));
+        let other = service.broadcasts().register(Some("backups".to_string()));
+        for (id, notification_id) in [(stale, 1), (fresh, 2), (other, 3)] {
+            service.broadcasts().record_delivery(id, user.clone(), notification_id);
+        }
+        let mut record = service.broadcasts().get(stale).unwrap();
+        record.sent_at -= 7200;
+        service.broadcasts().import(vec![record]);
+
+        let denied = service.close_all(call().header(), 0, String::new()).await;
+        assert!(matches!(denied, Err(zbus::fdo::Error::AccessDenied(_))));
+        assert_eq!(service.broadcasts().len(), 3);
+
+        assert_eq!(service.close_older(Duration::from_secs(3600), Some("incidents")).await, 1);
+        assert_eq!(*sink.closed.lock().unwrap(), vec![(user.clone(), 1)]);
+        assert_eq!(service.close_older(Duration::ZERO, Some("incidents")).await, 1);
+        assert!(service.broadcasts().get(other).is_some());
+        assert_eq!(service.close_older(Duration::ZERO, None).await, 1);
+        assert!(service.broadcasts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flush_spool_shares_payload_through_sink() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let id = service.broadcasts().register(None);
+        let payload = Arc::new(BroadcastPayload::new("title", "body"));
+        for uid in [1000, 1001] {
+            let user = TargetUser::new(uid, format!("user{}", uid));
+            let entry = SpooledNotification::new(user, payload.clone()).with_broadcast_id(id);
+            service.spool().push(entry).unwrap();
+        }
+
+        service.flush_spool().await;
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 2);
+        assert!(delivered.iter().all(|(_, delivered, _)| Arc::ptr_eq(delivered, &payload)));
+        assert_eq!(delivered[0].2.replaces_id, 0);
+        assert_eq!(service.broadcasts().get(id).unwrap().deliveries.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_and_close_through_sink() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let id = service.broadcasts().register(None);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.broadcasts().record_delivery(id, user.clone(), 7);
+
+        let updated =
+            service.update_broadcast(call().header(), id, "new".to_string(), "body".to_string()).await.unwrap();
+        assert_eq!(updated, 1);
+        assert_eq!(sink.delivered.lock().unwrap()[0].2.replaces_id, 7);
+
+        assert_eq!(service.close_broadcast(call().header(), id).await.unwrap(), 1);
+        assert_eq!(*sink.closed.lock().unwrap(), vec![(user, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_send_replaces_earlier_broadcast() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        let replacing = |replaces: &str| {
+            let replaces = OwnedValue::try_from(zbus::zvariant::Value::from(replaces)).unwrap();
+            HashMap::from([("replaces".to_string(), replaces)])
+        };
+
+        let tags = vec![fleet::batch_tag("3f9c2a7b1d04")];
+        let (title, body) = ("Backup".to_string(), "20%".to_string());
+        let first = service.send_tagged(call().header(), String::new(), tags, title, body).await.unwrap();
+        let shown = service.broadcasts().get(first).unwrap().deliveries[0].1;
+
+        let (title, body) = ("Backup".to_string(), "40%".to_string());
+        let options = replacing(&first.to_string());
+        let second = service.send_with_options(call().header(), title, body, options).await.unwrap();
+        assert!(service.broadcasts().get(first).is_none());
+        let record = service.broadcasts().get(second).unwrap();
+        assert!(record.has_tag(&fleet::batch_tag("3f9c2a7b1d04")));
+
+        let (title, body) = ("Backup".to_string(), "Done".to_string());
+        let third = service.send_with_options(call().header(), title, body, replacing("3f9c2a7b1d04")).await.unwrap();
+        assert!(service.broadcasts().get(second).is_none());
+        let replaced: Vec<u32> =
+            sink.delivered.lock().unwrap().iter().map(|(_, _, options)| options.replaces_id).collect();
+        assert_eq!(replaced[0], 0);
+        assert_eq!(replaced[1], shown);
+        assert_eq!(replaced[2], record.deliveries[0].1);
+        assert_eq!(service.broadcasts().get(third).unwrap().deliveries.len(), 1);
+
+        let (title, body) = ("Backup".to_string(), "Done".to_string());
+        assert!(service.send_with_options(call().header(), title, body, replacing("999")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_progress_updated_in_place() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+
+        let (title, text) = ("Backup".to_string(), "Copying /home".to_string());
+        let batch_id = service.start_progress(call().header(), title, text).await.unwrap();
+        assert_eq!(service.update_progress(call().header(), batch_id.clone(), 40, String::new()).await.unwrap(), 1);
+        let closed = service.update_progress(call().header(), batch_id.clone(), 100, "Done".to_string()).await;
+        assert_eq!(closed.unwrap(), 1);
+        {
+            let delivered = sink.delivered.lock().unwrap();
+            let progress: Vec<_> =
+                delivered.iter().map(|(_, payload, _)| (payload.progress, payload.body.to_string())).collect();
+            assert_eq!(progress, vec![
+                (Some(0), "Copying /home".to_string()),
+                (Some(40), "Copying /home".to_string()),
+                (Some(100), "Done".to_string()),
+            ]);
+            assert_eq!(delivered[1].2.replaces_id, 1);
+            assert_eq!(delivered[2].2.replaces_id, 2);
+        }
+
+        assert!(service.update_progress(call().header(), batch_id.clone(), 101, String::new()).await.is_err());
+        assert!(service.update_progress(call().header(), "unknown".to_string(), 50, String::new()).await.is_err());
+        assert_eq!(service.close_batch(call().header(), batch_id).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_missing_daemon_reported() {
+        let service = NotifierService::default().with_sink(Arc::new(NoDaemonSink));
+        let id = service.broadcasts().register(None);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user, "title", "body").with_broadcast_id(id)).unwrap();
+
+        service.flush_spool().await;
+        let report = service.get_delivery_report(id).await.unwrap();
+        assert_eq!(report, vec![(1000, "alice".to_string(), "failed (no notification daemon)".to_string())]);
+        assert!(service.broadcasts().get(id).unwrap().deliveries.is_empty());
+        assert!(service.get_delivery_report(id + 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_to_all_reports_results() {
+        let service = NotifierService::default()
+            .with_sink(Arc::new(NoDaemonSink))
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let (id, results) = service.send_to_all(call().header(), "title".to_string(), "body".to_string()).await.unwrap();
+        let failed = (
+            1000,
+            "alice".to_string(),
+            "failed (no notification daemon)".to_string(),
+            "no owner".to_string(),
+        );
+        assert_eq!(results, vec![failed]);
+        assert_eq!(service.get_delivery_results(id).await.unwrap(), results);
+        assert!(service.get_delivery_results(id + 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_missing_daemon_falls_back_to_terminal() {
+        let config = Config::from_toml_str("terminal_fallback = true").unwrap();
+        let fallback = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config)
+            .with_sink(Arc::new(NoDaemonSink))
+            .with_fallback_sink(fallback.clone());
+        let id = service.broadcasts().register(None);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user, "title", "body").with_broadcast_id(id)).unwrap();
+
+        service.flush_spool().await;
+        assert_eq!(fallback.delivered.lock().unwrap().len(), 1);
+        let report = service.get_delivery_report(id).await.unwrap();
+        assert_eq!(report[0].2, "written to terminal (no notification daemon)");
+    }
+
+    #[tokio::test]
+    async fn test_poll_answers_recorded() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let options = vec!["Yes".to_string(), "No".to_string()];
+        let id = service.broadcasts().register(None);
+        service.polls().register(id, options.clone());
+        let user = TargetUser::new(1000, "alice".to_string());
+        let payload = BroadcastPayload::new("Reboot", "Tonight?").with_actions(options);
+        service
+            .spool()
+            .push(SpooledNotification::new(user, Arc::new(payload)).with_broadcast_id(id))
+            .unwrap();
+
+        service.flush_spool().await;
+        let responses = {
+            let delivered = sink.delivered.lock().unwrap();
+            assert_eq!(delivered[0].1.actions, vec!["Yes".to_string(), "No".to_string()]);
+            delivered[0].2.responses.clone().unwrap()
+        };
+        responses.send("Maybe".to_string()).unwrap();
+        responses.send("No".to_string()).unwrap();
+        drop(responses);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        let (tally, answers) = service.get_poll_results(call().header(), id).await.unwrap();
+        assert_eq!(tally, vec![("Yes".to_string(), 0), ("No".to_string(), 1)]);
+        assert_eq!(answers, vec![(1000, "alice".to_string(), "No".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_poll_requests_validated() {
+        let service = NotifierService::default();
+        assert!(service.send_poll(call().header(), "t".to_string(), "b".to_string(), Vec::new()).await.is_err());
+        let duplicated = vec!["Yes".to_string(), "Yes".to_string()];
+        assert!(service.send_poll(call().header(), "t".to_string(), "b".to_string(), duplicated).await.is_err());
+        assert!(service.get_poll_results(call().header(), 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plain_broadcasts_collect_no_answers() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let id = service.broadcasts().register(None);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user, "title", "body").with_broadcast_id(id)).unwrap();
+
+        service.flush_spool().await;
+        assert!(sink.delivered.lock().unwrap()[0].2.responses.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_state_migrates_between_services() {
+        let source = NotifierService::default();
+        let user = TargetUser::new(1000, "alice".to_string());
+        let tags = vec!["incident-421".to_string()];
+        let id = source.broadcasts().register_with(Some("ops".to_string()), tags, None);
+        source.polls().register(id, vec!["Yes".to_string()]);
+        source.spool().push(spooled(user, "title", "body").with_broadcast_id(id)).unwrap();
+        source.set_maintenance_mode(true).await.unwrap();
+        source.set_paused(true).await.unwrap();
+
+        let archive = source.export_archive().to_json().unwrap();
+        let target = NotifierService::default();
+        assert_eq!(target.import_archive(StateArchive::from_json(&archive).unwrap()).await.unwrap(), 3);
+        assert!(target.in_maintenance());
+        assert!(target.is_paused());
+        assert_eq!(target.spool().entries(), source.spool().entries());
+        assert_eq!(target.list_broadcasts(call().header(), "incident-421".to_string()).await.len(), 1);
+        assert!(target.polls().contains(id));
+        assert!(target.broadcasts().register(None) > id);
+
+        assert!(StateArchive::from_json("{}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_state_transfer_requires_privileged_caller() {
+        let service = NotifierService::default();
+        service.spool().push(spooled(TargetUser::new(1000, "alice".to_string()), "title", "private")).unwrap();
+        let exported = service.export_state(call().header()).await;
+        assert!(matches!(exported, Err(zbus::fdo::Error::AccessDenied(_))));
+
+        let archive = service.export_archive().to_json().unwrap();
+        let imported = NotifierService::default().import_state(call().header(), archive).await;
+        assert!(matches!(imported, Err(zbus::fdo::Error::AccessDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_route_test_reports_decisions() {
+        let config = Config::from_toml_str(
+            r#"
+            terminal_fallback = true
+
+            [delivery_windows]
+            bob = "00:00-00:00"
+
+            [notification_services]
+            sway = "org.example.Proxy"
+            "#,
+        )
+        .unwrap();
+        let service = NotifierService::new(config);
+        let alice = TargetUser::new(1000, "alice".to_string()).with_desktop("sway");
+        service.sessions().store(HashSet::from([alice, TargetUser::new(1001, "bob".to_string())]));
+
+        let (urgency, routes) = service
+            .route_test("t".to_string(), "b".to_string(), String::new(), String::new())
+            .await
+            .unwrap();
+        assert_eq!(urgency, "normal");
+        assert_eq!(
+            routes,
+            vec![
+                (
+                    1000,
+                    "alice".to_string(),
+                    "deliver now".to_string(),
+                    "org.example.Proxy, terminals if no daemon".to_string()
+                ),
+                (
+                    1001,
+                    "bob".to_string(),
+                    "spool until delivery window opens".to_string(),
+                    "org.freedesktop.Notifications, terminals if no daemon".to_string()
+                ),
+            ]
+        );
+
+        let (_, routes) = service
+            .route_test("t".to_string(), "b".to_string(), "critical".to_string(), "1001".to_string())
+            .await
+            .unwrap();
+        assert_eq!(routes.len(), 1);
+        assert!(service
+            .route_test("t".to_string(), "b".to_string(), "urgent".to_string(), String::new())
+            .await
+            .is_err());
+        assert!(service
+            .route_test("t".to_string(), "b".to_string(), String::new(), "carol".to_string())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_carry_correlation_id() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let id = service.broadcasts().register(None);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user.clone(), "title", "body").with_broadcast_id(id)).unwrap();
+        service.spool().push(spooled(user, "title", "body")).unwrap();
+
+        service.flush_spool().await;
+        let delivered = sink.delivered.lock().unwrap();
+        let correlation_ids: Vec<_> = delivered.iter().map(|(_, _, options)| options.correlation_id).collect();
+        assert!(correlation_ids.contains(&service.broadcasts().correlation_id(id)));
+        assert!(correlation_ids.contains(&None));
+    }
+
+    #[tokio::test]
+    async fn test_session_owner_is_only_recipient() {
+        let sink = Arc::new(RecordingSink::default());
+        let owner = TargetUser::new(1000, "alice".to_string());
+        let service = NotifierService::default()
+            .with_sink(sink.clone())
+            .with_session_owner(owner.clone());
+
+        let (id, results) = service.send_to_all(call().header(), "title".to_string(), "body".to_string()).await.unwrap();
+        assert_eq!(results, vec![(1000, "alice".to_string(), "delivered".to_string(), String::new())]);
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].0, owner);
+        assert_eq!(service.sessions().owner(), Some(&owner));
+        assert!(service.broadcasts().get(id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_deferred_recipients_reported() {
+        let now = Local::now().time();
+        let opens = (now + chrono::TimeDelta::minutes(30)).format("%H:%M");
+        let closes = (now + chrono::TimeDelta::minutes(60)).format("%H:%M");
+        let config = format!("[delivery_windows]\nalice = \"{}-{}\"\nbob = \"00:00-00:00\"\n", opens, closes);
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(Config::from_toml_str(&config).unwrap()).with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let bob = TargetUser::new(1001, "bob".to_string());
+        let carol = TargetUser::new(1002, "carol".to_string());
+        service.sessions().store(HashSet::from([alice, bob, carol]));
+
+        let (id, mut deferrals) = service
+            .send_with_deferrals(call().header(), "t".to_string(), "b".to_string(), HashMap::new())
+            .await
+            .unwrap();
+        deferrals.sort();
+        assert_eq!(deferrals.len(), 2);
+        let (uid, username, until, reason) = &deferrals[0];
+        assert_eq!((*uid, username.as_str()), (1000, "alice"));
+        assert!(chrono::DateTime::parse_from_rfc3339(until).unwrap() > Local::now());
+        assert_eq!(reason, "spool until delivery window opens");
+        assert_eq!(deferrals[1].1, "bob");
+        assert_eq!(deferrals[1].2, "");
+        assert_eq!(sink.delivered.lock().unwrap().len(), 1);
+        assert_eq!(service.spool().len(), 2);
+        assert!(service.broadcasts().get(id).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_displayed_notifications_capped() {
+        let config = Config::from_toml_str("[limits]\nmax_displayed_per_user = 2\n").unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        service.sessions().store(HashSet::from([alice.clone()]));
+        let mut sent = Vec::new();
+        for title in ["first", "second", "third"] {
+            let (title, body) = (title.to_string(), "b".to_string());
+            sent.push(service.send_with_options(call().header(), title, body, HashMap::new()).await.unwrap());
+        }
+        let first = sent[0];
+        assert_eq!(*sink.closed.lock().unwrap(), vec![(alice.clone(), 1)]);
+        assert!(service.broadcasts().get(first).unwrap().deliveries.is_empty());
+        assert_eq!(service.broadcasts().displayed(1000).len(), 2);
+
+        // Only critical notifications shown leaves the next one waiting
+        for shown in service.broadcasts().displayed(1000) {
+            let critical = BroadcastPayload::new("Power", "Down").with_urgency(Some(Urgency::Critical));
+            service.broadcasts().set_payload(shown.broadcast_id, Arc::new(critical));
+        }
+        let (title, body) = ("fourth".to_string(), "b".to_string());
+        service.send_with_options(call().header(), title, body, HashMap::new()).await.unwrap();
+        assert_eq!(sink.delivered.lock().unwrap().len(), 3);
+        assert_eq!(service.spool().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_inspect_session_requires_identified_caller() {
+        let service = NotifierService::default();
+        let result = service.inspect_session(call().header(), "1".to_string()).await;
+        assert!(matches!(result, Err(zbus::fdo::Error::AccessDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rejections_are_structured() {
+        let service = NotifierService::default()
+            .with_sink(Arc::new(RecordingSink::default()))
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let rejection = |error: SendError| match error {
+            SendError::Fdo(zbus::fdo::Error::InvalidArgs(message)) => Rejection::from_message(&message).unwrap(),
+            other => panic!("expected a rejection, got {:?}", other),
+        };
+
+        let result = service.send_to_all(call().header(), "Disk\u{7} full".to_string(), "b".to_string()).await;
+        let title = rejection(result.unwrap_err());
+        assert_eq!((title.field.as_str(), title.rule), ("title", RejectionRule::ControlCharacter));
+        assert_eq!((title.position, title.character), (Some(4), Some('\u{7}')));
+
+        let specs = OwnedValue::try_from(zbus::zvariant::Value::from(vec!["ack", "ack:Got it"])).unwrap();
+        let options = HashMap::from([("actions".to_string(), specs)]);
+        let result = service.send_with_options(call().header(), "t".to_string(), "b".to_string(), options).await;
+        let actions = rejection(result.unwrap_err());
+        assert_eq!((actions.field.as_str(), actions.rule), ("actions", RejectionRule::Duplicate));
+        assert_eq!(actions.position, Some(1));
+
+        let request = socket::SocketRequest {
+            title: "Reboot".to_string(),
+            body: "Tonight".to_string(),
+            channel: None,
+            tags: vec!["patching".to_string(), " ".to_string()],
+            allow_duplicate: false,
+        };
+        let response = service.socket_broadcast(request, None).await;
+        let tags = response.rejection.unwrap();
+        assert_eq!((tags.field.as_str(), tags.rule, tags.position), ("tags", RejectionRule::Empty, Some(1)));
+    }
+
+    #[tokio::test]
+    async fn test_socket_requests_authorized_by_polkit() {
+        let service = NotifierService::default().with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let request = socket::SocketRequest {
+            title: "Reboot".to_string(),
+            body: "Tonight".to_string(),
+            channel: None,
+            tags: Vec::new(),
+            allow_duplicate: false,
+        };
+        // Without a pid polkit cannot be asked, so the caller is not authorized
+        let response = service.socket_broadcast(request, Some(Caller::from_socket_peer(1000, None))).await;
+        assert!(response.error.is_some_and(|error| error.contains("authoriz")));
+        assert!(response.broadcast_id.is_none());
+        assert!(service.broadcasts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_senders_rate_limited() {
+        let config = "polkit = \"off\"\n[limits]\nsender_rate_per_minute = 1\nsender_burst = 2\n";
+        let config = Config::from_toml_str(config).unwrap();
+        let service = NotifierService::new(config)
+            .with_sink(Arc::new(RecordingSink::default()))
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let request = socket::SocketRequest {
+            title: "Reboot".to_string(),
+            body: "Tonight".to_string(),
+            channel: None,
+            tags: Vec::new(),
+            allow_duplicate: false,
+        };
+        let backup = Caller::from_socket_peer(990, None);
+        for _ in 0..2 {
+            assert!(service.socket_broadcast(request.clone(), Some(backup.clone())).await.broadcast_id.is_some());
+        }
+        let response = service.socket_broadcast(request.clone(), Some(backup.clone())).await;
+        assert_eq!(response.error.as_deref(), Some("uid:990 is sending broadcasts too fast, retry after 60 seconds"));
+        assert!(matches!(service.authorize_sender(Some(backup)), Err(SendError::RateLimited(_))));
+
+        // Other senders are not held back
+        let patching = Caller::from_socket_peer(991, None);
+        assert!(service.socket_broadcast(request, Some(patching)).await.broadcast_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_socket_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notifier.sock");
+        let sink = Arc::new(RecordingSink::default());
+        let config = Config::from_toml_str("polkit = \"off\"").unwrap();
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        let listener = socket::bind(&path, None).unwrap();
+        tokio::spawn({
+            let service = service.clone();
+            async move { service.serve_socket(listener).await }
+        });
+
+        let request = socket::SocketRequest {
+            title: "Reboot".to_string(),
+            body: "Tonight".to_string(),
+            channel: Some("patching".to_string()),
+            tags: Vec::new(),
+            allow_duplicate: false,
+        };
+        let sent = tokio::task::spawn_blocking({
+            let (path, request) = (path.clone(), request.clone());
+            move || socket::send_request(path, &request)
+        });
+        let id = sent.await.unwrap().unwrap();
+        assert_eq!(service.broadcasts().get(id).unwrap().channel.as_deref(), Some("patching"));
+        let delivered = sink.delivered.lock().unwrap().clone();
+        assert_eq!(delivered.len(), 1);
+        // The sender is identified from the socket's peer credentials
+        assert!(delivered[0].1.sender.is_some());
+
+        let invalid = socket::SocketRequest { tags: vec![" ".to_string()], ..request };
+        let sent = tokio::task::spawn_blocking(move || socket::send_request(path, &invalid));
+        assert!(sent.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allowed_senders() {
+        let config = Config::from_toml_str("polkit = \"off\"\nallowed_senders = [\"backup.service\"]").unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config)
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let request = SocketRequest {
+            title: "Backup".to_string(),
+            body: "Done".to_string(),
+            channel: None,
+            tags: Vec::new(),
+            allow_duplicate: false,
+        };
+
+        let backup = Caller {
+            bus_name: "unix-socket".to_string(),
+            uid: Some(990),
+            unit: Some("backup.service".to_string()),
+            ..Caller::default()
+        };
+        let response = service.socket_broadcast(request.clone(), Some(backup)).await;
+        assert!(response.broadcast_id.is_some());
+
+        let other = Caller { uid: Some(991), ..Caller::default() };
+        let response = service.socket_broadcast(request.clone(), Some(other)).await;
+        assert_eq!(response.error.as_deref(), Some("Not allowed to send broadcasts"));
+        assert!(service.socket_broadcast(request, None).await.broadcast_id.is_none());
+        let result = service.send_to_all(call().header(), "Backup".to_string(), "Done".to_string()).await;
+        assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::AccessDenied(_)))));
+        assert_eq!(sink.delivered.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_allowed_uids() {
+        let service = NotifierService::new(Config::from_toml_str("allowed_uids = [1001]").unwrap());
+        let caller = |uid| Some(Caller { uid: Some(uid), ..Caller::default() });
+        assert!(service.authorize_sender(caller(1001)).is_ok());
+        let denied = service.authorize_sender(caller(1002));
+        assert!(matches!(denied, Err(SendError::Fdo(zbus::fdo::Error::AccessDenied(_)))));
+        assert!(service.authorize_sender(None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_senders_quarantined() {
+        let config = Config::from_toml_str(
+            "polkit = \"off\"\nquarantine_unknown_senders = true\n[sender_names]\n\"backup.service\" = \"Backups\"",
+        )
+        .unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config)
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let request = SocketRequest {
+            title: "Disk full".to_string(),
+            body: "On db-01".to_string(),
+            channel: None,
+            tags: Vec::new(),
+            allow_duplicate: false,
+        };
+        let backup = Caller { uid: Some(990), unit: Some("backup.service".to_string()), ..Caller::default() };
+        assert!(service.socket_broadcast(request.clone(), Some(backup)).await.broadcast_id.is_some());
+
+        let relay = Caller { uid: Some(991), unit: Some("relay.service".to_string()), ..Caller::default() };
+        let response = service.socket_broadcast(request.clone(), Some(relay.clone())).await;
+        let quarantine_id = response.quarantine_id.unwrap();
+        assert!(response.broadcast_id.is_none());
+        let invalid = SocketRequest { tags: vec![" ".to_string()], ..request.clone() };
+        assert!(service.socket_broadcast(invalid, Some(relay)).await.rejection.is_some());
+        assert_eq!(sink.delivered.lock().unwrap().len(), 1);
+        assert_eq!(service.state.quarantine.len(), 1);
+
+        // Only root may approve
+        let result = service.approve_quarantined(call().header(), quarantine_id).await;
+        assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::AccessDenied(_)))));
+        let held = service.take_quarantined(quarantine_id).unwrap();
+        assert_eq!(held.input, "unix-socket");
+        service.send_request(held.request, held.sender).await.unwrap();
+        let delivered = sink.delivered.lock().unwrap().clone();
+        assert_eq!(delivered.len(), 2);
+        assert_eq!(delivered[1].1.sender.as_deref(), Some("relay.service"));
+        assert!(service.take_quarantined(quarantine_id).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_configured_notification_defaults() {
+        let config = Config::from_toml_str("[notification]\napp_name = 'IT'\nicon = 'dialog-warning'\ntimeout_ms = 0");
+        let config = config.unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        service.spool().push(spooled(TargetUser::new(1000, "alice".to_string()), "title", "body")).unwrap();
+
+        service.flush_spool().await;
+        let options = sink.delivered.lock().unwrap()[0].2.clone();
+        assert_eq!(options.app_name, "IT");
+        assert_eq!(options.icon, "dialog-warning");
+        assert_eq!(options.timeout, 0);
+    }
+
+    #[tokio::test]
+    async fn test_critical_notifications_ignore_configured_timeout() {
+        let config = Config::from_toml_str("[notification]\ntimeout_ms = 10000").unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let critical = BroadcastPayload::new("Power", "Shutting down").with_urgency(Some(Urgency::Critical));
+        service.spool().push(SpooledNotification::new(alice.clone(), Arc::new(critical))).unwrap();
+        service.spool().push(spooled(alice, "Backup", "Done")).unwrap();
+
+        service.flush_spool().await;
+        let delivered = sink.delivered.lock().unwrap();
+        let timeout = |title: &str| {
+            let (_, _, options) = delivered.iter().find(|(_, payload, _)| &*payload.title == title).unwrap();
+            options.timeout
+        };
+        assert_eq!(timeout("Power"), 0);
+        assert_eq!(timeout("Backup"), 10000);
+    }
+
+    #[tokio::test]
+    async fn test_language_override() {
+        let config = Config::from_toml_str("[language_overrides]\nkiosk = 'de_DE.UTF-8'").unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        service.spool().push(spooled(TargetUser::new(1000, "kiosk".to_string()), "title", "body")).unwrap();
+
+        service.flush_spool().await;
+        assert_eq!(sink.delivered.lock().unwrap()[0].2.app_name, "Systembenachrichtigung");
+    }
+
+    #[tokio::test]
+    async fn test_channel_identity() {
+        let config = Config::from_toml_str(
+            "[notification]\napp_name = 'IT'\n[channels.backups]\napp_name = 'Backup Service'\n\
+             icon = 'drive-harddisk'\ndesktop_entry = 'backup-service'",
+        );
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config.unwrap()).with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+
+        for channel in ["backups", "status"] {
+            let (title, body) = ("Update".to_string(), "Done".to_string());
+            service.send_to_channel(call().header(), channel.to_string(), title, body).await.unwrap();
+        }
+        let delivered = sink.delivered.lock().unwrap();
+        let (backups, status) = (&delivered[0].2, &delivered[1].2);
+        assert_eq!(backups.app_name, "Backup Service");
+        assert_eq!(backups.icon, "drive-harddisk");
+        assert_eq!(backups.desktop_entry.as_deref(), Some("backup-service"));
+        assert_eq!(status.app_name, "IT");
+        assert_eq!(status.icon, crate::notification::DEFAULT_APP_ICON);
+        assert_eq!(status.desktop_entry, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_closes_category_channel() {
+        let config = Config::from_toml_str("[category_channels]\n\"monitoring.*\" = \"alert-{fingerprint}\"\n");
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config.unwrap()).with_sink(sink.clone());
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.sessions().store(HashSet::from([user.clone()]));
+        let options = |category: &str| {
+            let category = OwnedValue::try_from(zbus::zvariant::Value::from(category)).unwrap();
+            HashMap::from([("category".to_string(), category)])
+        };
+
+        let (title, body) = ("Disk full".to_string(), "/var at 98%".to_string());
+        let id = service.send_with_options(call().header(), title, body, options("monitoring.disk")).await.unwrap();
+        assert_eq!(service.broadcasts().get(id).unwrap().channel.as_deref(), Some("alert-Disk full"));
+
+        let resolved = service.resolve(call().header(), "Disk full".to_string(), options("monitoring.disk")).await;
+        assert_eq!(resolved.unwrap(), 1);
+        assert_eq!(*sink.closed.lock().unwrap(), vec![(user, 1)]);
+        assert!(service.broadcasts().get(id).is_none());
+        let unmapped = service.resolve(call().header(), "Disk full".to_string(), options("network")).await;
+        assert!(matches!(unmapped, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_category_picks_sound() {
+        let config = "[sound]\ndefault = 'bell'\n[sound.category]\nmonitoring = 'alarm-clock-elapsed'";
+        let config = Config::from_toml_str(config);
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config.unwrap()).with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        let category = OwnedValue::try_from(zbus::zvariant::Value::from("monitoring.disk")).unwrap();
+        let options = HashMap::from([("category".to_string(), category)]);
+
+        service.send_with_options(call().header(), "Disk full".to_string(), "/var".to_string(), options).await.unwrap();
+        let (title, body) = ("Backup".to_string(), "Done".to_string());
+        service.send_with_options(call().header(), title, body, HashMap::new()).await.unwrap();
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered[0].2.sound, Sound::Named("alarm-clock-elapsed".to_string()));
+        assert_eq!(delivered[1].2.sound, Sound::Named("bell".to_string()));
+    }
+
+    /// Sink timing out a number of times before delivering
+    #[derive(Debug, Default)]
+    struct FlakySink {
+        failures: Mutex<u32>,
+        attempts: Mutex<u32>,
+    }
+
+    impl NotificationSink for FlakySink {
+        fn notify<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _payload: Arc<BroadcastPayload>,
+            _options: &'a DeliveryOptions,
+        ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+            *self.attempts.lock().unwrap() += 1;
+            let mut failures = self.failures.lock().unwrap();
+            let result = match *failures {
+                0 => Ok(7),
+                _ => Err(DeliveryError::new(DeliveryErrorKind::Timeout, "no answer")),
+            };
+            *failures = failures.saturating_sub(1);
+            Box::pin(async move { result })
+        }
+
+        fn close<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _bus_name: &'a str,
+            _notification_id: u32,
+        ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retryable_failures_retried() {
+        let config = Config::from_toml_str("[retry]\nretries = 2\ndelay_ms = 1\n").unwrap();
+        let sink = Arc::new(FlakySink { failures: Mutex::new(2), ..FlakySink::default() });
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let user = TargetUser::new(1000, "alice".to_string());
+        let payload = Arc::new(BroadcastPayload::new("title", "body"));
+        assert_eq!(service.deliver(&user, payload.clone(), 0, None).await.unwrap(), 7);
+        assert_eq!(*sink.attempts.lock().unwrap(), 3);
+
+        *sink.failures.lock().unwrap() = 3;
+        let error = service.deliver(&user, payload, 0, None).await.unwrap_err();
+        assert_eq!(error.kind(), DeliveryErrorKind::Timeout);
+        assert_eq!(*sink.attempts.lock().unwrap(), 6);
+    }
+
+    /// Sink never answering for one user, like a hung helper or stuck session bus
+    #[derive(Debug)]
+    struct HangingSink {
+        hung_uid: u32,
+    }
+
+    impl NotificationSink for HangingSink {
+        fn notify<'a>(
+            &'a self,
+            user: &'a TargetUser,
+            _payload: Arc<BroadcastPayload>,
+            _options: &'a DeliveryOptions,
+        ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+            match user.uid == self.hung_uid {
+                true => Box::pin(futures::future::pending()),
+                false => Box::pin(async { Ok(7) }),
+            }
+        }
+
+        fn close<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _bus_name: &'a str,
+            _notification_id: u32,
+        ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hung_delivery_abandoned() {
+        let config = Config::from_toml_str("user_delivery_timeout_ms = 50").unwrap();
+        let service = NotifierService::new(config).with_sink(Arc::new(HangingSink { hung_uid: 1001 }));
+        let (alice, bob) = (TargetUser::new(1000, "alice".to_string()), TargetUser::new(1001, "bob".to_string()));
+        service.sessions().store(HashSet::from([alice.clone(), bob.clone()]));
+
+        let (title, body) = ("Reboot".to_string(), "Tonight".to_string());
+        let (id, _) = service.send_to_all(call().header(), title, body).await.unwrap();
+        let report = service.broadcasts().get(id).unwrap().report;
+        assert!(report.contains(&(alice, DeliveryStatus::Delivered)));
+        assert!(report.contains(&(bob, DeliveryStatus::Failed(DeliveryErrorKind::Stalled))));
+        let (_, uid, error) = service.state.last_delivery_error.lock().unwrap().clone().unwrap();
+        assert_eq!((uid, error.kind()), (1001, DeliveryErrorKind::Stalled));
+    }
+
+    /// Sink recording the most deliveries it was running at the same time
+    #[derive(Debug, Default)]
+    struct CountingSink {
+        running: Mutex<usize>,
+        peak: Mutex<usize>,
+    }
+
+    impl NotificationSink for CountingSink {
+        fn notify<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _payload: Arc<BroadcastPayload>,
+            _options: &'a DeliveryOptions,
+        ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+            Box::pin(async {
+                {
+                    let mut running = self.running.lock().unwrap();
+                    *running += 1;
+                    let mut peak = self.peak.lock().unwrap();
+                    *peak = (*peak).max(*running);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                *self.running.lock().unwrap() -= 1;
+                Ok(7)
+            })
+        }
+
+        fn close<'a>(
+            &'a self,
+            _user: &'a TargetUser,
+            _bus_name: &'a str,
+            _notification_id: u32,
+        ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_deliveries_limited() {
+        let config = Config::from_toml_str("max_concurrent_deliveries = 2").unwrap();
+        let sink = Arc::new(CountingSink::default());
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let users = (1000..1006).map(|uid| TargetUser::new(uid, format!("user{}", uid)));
+        service.sessions().store(users.collect());
+
+        let (title, body) = ("Reboot".to_string(), "Tonight".to_string());
+        let (id, _) = service.send_to_all(call().header(), title, body).await.unwrap();
+        assert_eq!(*sink.peak.lock().unwrap(), 2);
+        let report = service.broadcasts().get(id).unwrap().report;
+        assert_eq!(report.iter().filter(|(_, status)| *status == DeliveryStatus::Delivered).count(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_deliveries_limited_across_broadcasts() {
+        let config = Config::from_toml_str("max_concurrent_deliveries = 2").unwrap();
+        let sink = Arc::new(CountingSink::default());
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let users = (1000..1004).map(|uid| TargetUser::new(uid, format!("user{}", uid)));
+        service.sessions().store(users.collect());
+
+        let send = |title: &str| {
+            let title = title.to_string();
+            async { service.send_to_all(call().header(), title, "Tonight".to_string()).await }
+        };
+        let (first, second) = tokio::join!(send("Reboot"), send("Patching"));
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(*sink.peak.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_parked_until_daemon_returns() {
+        let config = Config::from_toml_str("park_failed = true").unwrap();
+        let sink = Arc::new(FlakySink { failures: Mutex::new(1), ..FlakySink::default() });
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let id = service.broadcasts().register(None);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user, "title", "body").with_broadcast_id(id)).unwrap();
+
+        service.flush_spool().await;
+        let report = service.get_delivery_report(id).await.unwrap();
+        assert_eq!(report[0].2, "failed (notification daemon timed out)");
+        assert!(service.spool().entries()[0].parked);
+        // Parked notifications are not delivered with the rest of the spool
+        service.flush_spool().await;
+        assert_eq!(*sink.attempts.lock().unwrap(), 1);
+
+        service.retry_parked(1000).await;
+        assert_eq!(*sink.attempts.lock().unwrap(), 2);
+        assert!(service.spool().is_empty());
+        assert_eq!(service.get_delivery_report(id).await.unwrap()[0].2, "delivered");
+    }
+
+    #[tokio::test]
+    async fn test_delivery_latency_recorded() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink);
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.spool().push(spooled(user, "title", "body")).unwrap();
+
+        service.flush_spool().await;
+        assert_eq!(service.latency().stats().count, 1);
+        let stats = service.get_stats().await;
+        assert_eq!(u64::try_from(&stats["deliveries"]).unwrap(), 1);
+        assert!(stats.contains_key("latency_p99_ms"));
+        assert!(service.get_metrics().await.contains("dots_notifier_delivery_latency_seconds_count 1"));
+
+        // The recording sink runs no helper
+        assert_eq!(service.get_helper_stats().await, (Vec::new(), Vec::new()));
+        let alice = TargetUser::new(1000, "alice".to_string());
+        service.state.helper_stats.record(&alice, Duration::from_millis(40), Err(HelperFailure::Exit(11)));
+        let (failures, users) = service.get_helper_stats().await;
+        assert_eq!(failures, vec![("exit-11".to_string(), 1)]);
+        assert_eq!(users, vec![(1000, "alice".to_string(), 1, 1, 40)]);
+        assert!(service.get_metrics().await.contains("dots_notifier_helper_failures_total{reason=\"exit-11\"} 1"));
+        assert!(service.get_metrics().await.contains("# TYPE dots_notifier_session_bus_pool_hits_total counter"));
+    }
+
+    #[tokio::test]
+    async fn test_sender_named_in_footer_and_kept_on_update() {
+        let config = Config::from_toml_str("sender_footer = true").unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let id = service.broadcasts().register(None);
+        let payload = BroadcastPayload::new("Reboot", "Tonight").with_sender(Some("Patching system".into()));
+        let user = TargetUser::new(1000, "alice".to_string());
+        service.broadcasts().set_payload(id, Arc::new(payload.clone()));
+        service.spool().push(SpooledNotification::new(user, Arc::new(payload)).with_broadcast_id(id)).unwrap();
+
+        service.flush_spool().await;
+        service.update_broadcasts(vec![id], Arc::new(BroadcastPayload::new("Reboot", "Now"))).await;
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 2);
+        for (_, payload, options) in delivered.iter() {
+            assert_eq!(payload.sender.as_deref(), Some("Patching system"));
+            assert_eq!(options.footer.as_deref(), Some("Sent by Patching system"));
+        }
+        let shown = delivered[1].2.apply_footer(delivered[1].1.clone());
+        assert_eq!(&*shown.body, "Now\n\nSent by Patching system");
+    }
+
+    #[tokio::test]
+    async fn test_send_to_user() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default()
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        for user in ["alice", "1000"] {
+            service.send_to_user(call().header(), user.to_string(), "t".to_string(), "b".to_string()).await.unwrap();
+        }
+        assert_eq!(sink.delivered.lock().unwrap().len(), 2);
+
+        let result = service.send_to_user(call().header(), "bob".to_string(), "t".to_string(), "b".to_string()).await;
+        assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+        assert_eq!(sink.delivered.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_image_checked_and_passed_on() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default()
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let send = |image: &str| {
+            let service = service.clone();
+            let options = HashMap::from([("image".to_string(), OwnedValue::from(zbus::zvariant::Str::from(image)))]);
+            async move {
+                let message = call();
+                service.broadcast_with_options(&message.header(), "t".to_string(), "b".to_string(), options).await
+            }
+        };
+        for invalid in ["logo.png", "/nonexistent/logo.png"] {
+            assert!(matches!(send(invalid).await, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let logo = dir.path().join("logo.png");
+        std::fs::write(&logo, b"png").unwrap();
+        send(logo.to_str().unwrap()).await.unwrap();
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].1.image.as_deref(), Some(logo.as_path()));
+        assert_eq!(delivered[0].1.loaded_image.as_deref(), Some(&NotificationImage::Path(logo.clone())));
+    }
+
+    /// Resolver placing alice in wheel as a supplementary and carol as a primary member
+    struct WheelResolver;
+
+    impl NssResolver for WheelResolver {
+        fn user_by_name(&self, name: &str) -> Result<Option<nss::UserEntry>, String> {
+            let gid = if name == "carol" { 10 } else { 100 };
+            Ok(Some(nss::UserEntry { name: name.to_string(), uid: 1000, gid }))
+        }
+
+        fn group_by_name(&self, name: &str) -> Result<Option<nss::GroupEntry>, String> {
+            let members = vec!["alice".to_string()];
+            Ok((name == "wheel").then(|| nss::GroupEntry { name: name.to_string(), gid: 10, members }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_to_group() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone()).with_nss_resolver(Arc::new(WheelResolver));
+        let (alice, bob) = (TargetUser::new(1000, "alice".to_string()), TargetUser::new(1001, "bob".to_string()));
+        let carol = TargetUser::new(1002, "carol".to_string());
+        service.sessions().store(HashSet::from([alice.clone(), bob.clone(), carol.clone()]));
+
+        let message = call();
+        let send = |group: &str| {
+            service.send_to_group(message.header(), group.to_string(), "t".to_string(), "b".to_string())
+        };
+        let broadcast_id = send("wheel").await.unwrap();
+        let mut delivered: Vec<u32> = sink.delivered.lock().unwrap().iter().map(|(user, ..)| user.uid).collect();
+        delivered.sort();
+        assert_eq!(delivered, vec![1000, 1002]);
+        assert_eq!(service.broadcasts().get(broadcast_id).unwrap().report.len(), 2);
+
+        assert!(matches!(send("nobody").await, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+        service.sessions().store(HashSet::from([bob]));
+        assert!(matches!(send("wheel").await, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+        assert_eq!(sink.delivered.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_excluded_users_and_seat_filter() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string()).with_seat("seat0");
+        let kiosk = TargetUser::new(1001, "kiosk".to_string()).with_seat("seat1");
+        let remote = TargetUser::new(1002, "carol".to_string());
+        service.sessions().store(HashSet::from([alice, kiosk, remote]));
+
+        let send = |options: Vec<(&str, zbus::zvariant::Value<'static>)>| {
+            let options = options
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), OwnedValue::try_from(value).unwrap()))
+                .collect();
+            let (service, title, body) = (service.clone(), "Reboot".to_string(), "At noon".to_string());
+            async move { service.send_with_options(call().header(), title, body, options).await.unwrap() }
+        };
+        let delivered = || {
+            let mut uids: Vec<u32> = sink.delivered.lock().unwrap().drain(..).map(|(user, ..)| user.uid).collect();
+            uids.sort();
+            uids
+        };
+
+        send(vec![("exclude_users", vec!["kiosk", "1002"].into())]).await;
+        assert_eq!(delivered(), vec![1000]);
+        send(vec![("seat", "seat1".into()), ("allow_duplicate", true.into())]).await;
+        assert_eq!(delivered(), vec![1001]);
+        send(vec![("seat", "seat1".into()), ("exclude_users", vec!["kiosk"].into())]).await;
+        assert!(delivered().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_active_users_narrowed_by_options() {
+        let service = NotifierService::default();
+        let alice = TargetUser::new(1000, "alice".to_string()).with_seat("seat0");
+        let kiosk = TargetUser::new(1001, "kiosk".to_string()).with_seat("seat1");
+        service.sessions().store(HashSet::from([kiosk, alice]));
+
+        let everyone = service.active_recipients(&SendOptions::default()).await.unwrap();
+        assert_eq!(everyone, vec![(1000, "alice".to_string()), (1001, "kiosk".to_string())]);
+        let on_seat = SendOptions { seat: Some("seat1".to_string()), ..SendOptions::default() };
+        assert_eq!(service.active_recipients(&on_seat).await.unwrap(), vec![(1001, "kiosk".to_string())]);
+
+        // Only privileged callers may see who is logged in
+        let listed = service.list_active_users(call().header(), HashMap::new()).await;
+        assert!(matches!(listed, Err(zbus::fdo::Error::AccessDenied(_))));
+        let capabilities = service.get_user_capabilities(call().header(), 1000).await;
+        assert!(matches!(capabilities, Err(zbus::fdo::Error::AccessDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_detached_broadcast_delivered_in_background() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+
+        let detach = OwnedValue::try_from(zbus::zvariant::Value::from(true)).unwrap();
+        let options = HashMap::from([("detach".to_string(), detach)]);
+        let (title, body) = ("Reboot".to_string(), "At noon".to_string());
+        let broadcast_id = service.send_with_options(call().header(), title, body, options).await.unwrap();
+        // The current-thread runtime has not run the delivery yet
+        assert!(sink.delivered.lock().unwrap().is_empty());
+
+        while sink.delivered.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(service.broadcasts().get(broadcast_id).unwrap().report.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unidentified_senders_not_attributed() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default()
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        service.send_to_all(call().header(), "title".to_string(), "body".to_string()).await.unwrap();
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered[0].1.sender, None);
+        assert_eq!(delivered[0].2.footer, None);
+    }
+
+    #[tokio::test]
+    async fn test_explicit_urgency_overrides_inferred() {
+        let config = Config::from_toml_str("[[urgency_rules]]\nkeywords = [\"Outage\"]\nurgency = \"critical\"\n");
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config.unwrap())
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let urgency = OwnedValue::try_from(zbus::zvariant::Value::from("low")).unwrap();
+        let options = HashMap::from([("urgency".to_string(), urgency)]);
+        let (title, body) = ("Outage".to_string(), "Resolved".to_string());
+        service.send_with_options(call().header(), title, body, options).await.unwrap();
+        assert_eq!(sink.delivered.lock().unwrap()[0].1.urgency, Some(Urgency::Low));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_actions() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default()
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        let actions = |specs: Vec<&str>| {
+            let specs = OwnedValue::try_from(zbus::zvariant::Value::from(specs)).unwrap();
+            HashMap::from([("actions".to_string(), specs)])
+        };
+        let options = actions(vec!["ack:Acknowledge", "ok"]);
+        let id = service.send_with_options(call().header(), "t".to_string(), "b".to_string(), options).await.unwrap();
+        {
+            let delivered = sink.delivered.lock().unwrap();
+            let payload = &delivered[0].1;
+            assert_eq!(payload.actions, vec!["ack".to_string(), "ok".to_string()]);
+            assert_eq!(payload.action_label("ack"), "Acknowledge");
+            assert_eq!(payload.action_label("ok"), "ok");
+            assert!(delivered[0].2.responses.is_some());
+        }
+        assert!(service.get_poll_results(call().header(), id).await.is_ok());
+
+        for specs in [vec!["ack:Acknowledge", "ack:Again"], vec![":Acknowledge"]] {
+            let (title, body) = ("t".to_string(), "b".to_string());
+            let result = service.send_with_options(call().header(), title, body, actions(specs)).await;
+            assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_options_validated() {
+        let service = NotifierService::default().with_hooks(HookDir::new("/nonexistent"));
+        let unknown = HashMap::from([("colour".to_string(), OwnedValue::from(1u32))]);
+        assert!(service.send_with_options(call().header(), "t".to_string(), "b".to_string(), unknown).await.is_err());
+
+        for hook in ["../../bin/sh", "flash-backlight"] {
+            let hook = OwnedValue::try_from(zbus::zvariant::Value::from(hook)).unwrap();
+            let options = HashMap::from([("hook".to_string(), hook)]);
+            assert!(service.send_with_options(call().header(), "t".to_string(), "b".to_string(), options).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_body_commands_allowlisted() {
+        let config = Config::from_toml_str("body_commands = [\"echo 42% used\"]").unwrap();
+        let service = NotifierService::new(config);
+        let body = service.compose_body("Disk".to_string(), &["echo 42% used".to_string()]).await.unwrap();
+        assert_eq!(body, "Disk\n42% used");
+
+        let result = service.compose_body("Disk".to_string(), &["echo pwned".to_string()]).await;
+        assert!(matches!(result, Err(zbus::fdo::Error::InvalidArgs(_))));
+    }
+
+    #[tokio::test]
+    async fn test_hook_runs_after_delivery() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        let output = dir.path().join("output");
+        let hook = dir.path().join("record");
+        std::fs::write(&hook, format!("#!/bin/sh\necho \"$DOTS_NOTIFIER_NOTIFICATION_ID\" > {}\n", output.display())).unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default()
+            .with_sink(sink.clone())
+            .with_hooks(HookDir::with_owner(dir.path(), nix::unistd::getuid().as_raw()));
+        let account = nix::unistd::User::from_uid(nix::unistd::getuid()).unwrap().unwrap();
+        let user = TargetUser::new(account.uid.as_raw(), account.name);
+        let payload = BroadcastPayload::new("title", "body").with_hook(Some("record".to_string()));
+        let id = service.broadcasts().register(None);
+        service
+            .spool()
+            .push(SpooledNotification::new(user, Arc::new(payload)).with_broadcast_id(id))
+            .unwrap();
+
+        service.flush_spool().await;
+        for _ in 0..100 {
+            if let Ok(contents) = std::fs::read_to_string(&output) {
+                assert_eq!(contents.trim(), "1");
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("hook did not run");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_holds_back_non_critical() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        service.set_maintenance_mode(true).await.unwrap();
+        assert!(service.in_maintenance());
+
+        let user = TargetUser::new(1000, "alice".to_string());
+        let critical = BroadcastPayload::new("Power", "Shutting down").with_urgency(Some(Urgency::Critical));
+        service.spool().push(spooled(user.clone(), "title", "body")).unwrap();
+        service.spool().push(SpooledNotification::new(user, Arc::new(critical))).unwrap();
+
+        service.flush_spool().await;
+        assert_eq!(service.spool().len(), 1);
+        assert_eq!(&*sink.delivered.lock().unwrap()[0].1.title, "Power");
+
+        service.set_maintenance_mode(false).await.unwrap();
+        assert!(service.spool().is_empty());
+        assert_eq!(sink.delivered.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_paused_delivery_holds_back_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = format!("state_dir = {:?}\n", dir.path());
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(Config::from_toml_str(&config).unwrap()).with_sink(sink.clone());
+        service.set_paused(true).await.unwrap();
+        assert!(bool::try_from(service.get_status().await["paused"].clone()).unwrap());
+
+        let user = TargetUser::new(1000, "alice".to_string());
+        let critical = BroadcastPayload::new("Power", "Shutting down").with_urgency(Some(Urgency::Critical));
+        service.spool().push(spooled(user.clone(), "title", "body")).unwrap();
+        service.spool().push(SpooledNotification::new(user.clone(), Arc::new(critical))).unwrap();
+        service.flush_spool().await;
+        service.retry_parked(user.uid).await;
+        assert_eq!(service.spool().len(), 2);
+        assert!(sink.delivered.lock().unwrap().is_empty());
+
+        // A restarted server is still paused
+        let restarted = NotifierService::new(Config::from_toml_str(&config).unwrap());
+        assert!(restarted.is_paused());
+
+        service.set_paused(false).await.unwrap();
+        assert!(service.spool().is_empty());
+        assert_eq!(sink.delivered.lock().unwrap().len(), 2);
+        assert!(!NotifierService::new(Config::from_toml_str(&config).unwrap()).is_paused());
+    }
+
+    #[tokio::test]
+    async fn test_grouped_broadcasts_coalesced_in_spool() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default()
+            .with_sink(sink.clone())
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        service.set_maintenance_mode(true).await.unwrap();
+        let group = |key: &str| {
+            let key = OwnedValue::try_from(zbus::zvariant::Value::from(key)).unwrap();
+            HashMap::from([("group_key".to_string(), key)])
+        };
+        let (title, body) = ("Backup".to_string(), "Started".to_string());
+        let started = service.send_with_options(call().header(), title, body, group("backups")).await.unwrap();
+        let (title, body) = ("Backup".to_string(), "Finished".to_string());
+        service.send_with_options(call().header(), title, body, group("backups")).await.unwrap();
+        assert_eq!(service.spool().len(), 1);
+        let report = service.get_delivery_report(started).await.unwrap();
+        assert_eq!(report[0].2, "superseded by a newer broadcast of its group");
+
+        service.set_maintenance_mode(false).await.unwrap();
+        {
+            let delivered = sink.delivered.lock().unwrap();
+            assert_eq!(delivered.len(), 1);
+            assert_eq!(&*delivered[0].1.body, "Finished");
+            assert_eq!(delivered[0].1.group_key.as_deref(), Some("backups"));
+        }
+        let (title, body) = ("Backup".to_string(), "Failed".to_string());
+        let result = service.send_with_options(call().header(), title, body, group(" ")).await;
+        assert!(matches!(result, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_persisted_in_state_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            state_dir: Some(dir.path().to_path_buf()),
+            ..Config::default()
+        };
+        let service = NotifierService::new(config.clone());
+        service.set_maintenance_mode(true).await.unwrap();
+
+        let restarted = NotifierService::new(config);
+        assert!(restarted.in_maintenance());
+        assert_eq!(restarted.get_status().await["maintenance"], OwnedValue::from(true));
+    }
+
+    #[tokio::test]
+    async fn test_dispatches_recorded_in_journal() {
+        assert!(NotifierService::default().get_history(call().header(), 0).await.is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            state_dir: Some(dir.path().to_path_buf()),
+            journal: true,
+            ..Config::default()
+        };
+        let service = NotifierService::new(config)
+            .with_sink(Arc::new(RecordingSink::default()))
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        assert!(service.get_history(call().header(), 0).await.unwrap().is_empty());
+        service.send_to_all(call().header(), "Backup done".to_string(), "All good".to_string()).await.unwrap();
+        let (id, _) =
+            service.send_to_all(call().header(), "Backup failed".to_string(), "Disk full".to_string()).await.unwrap();
+
+        assert_eq!(service.get_history(call().header(), 0).await.unwrap().len(), 2);
+        let history = service.get_history(call().header(), 1).await.unwrap();
+        let (_, broadcast_id, sender, hash, outcomes) = &history[0];
+        assert_eq!(*broadcast_id, id);
+        assert_eq!(sender, "");
+        assert_eq!(hash, &crate::journal::title_hash("Backup failed"));
+        assert_eq!(outcomes, &vec![(1000, "alice".to_string(), "delivered".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_requests_recorded_in_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let config = Config::from_toml_str(&format!("[audit]\npath = {:?}", path)).unwrap();
+        let service = NotifierService::new(config)
+            .with_sink(Arc::new(RecordingSink::default()))
+            .with_session_owner(TargetUser::new(1000, "alice".to_string()));
+        service.send_to_all(call().header(), "Backup done".to_string(), "All good".to_string()).await.unwrap();
+        assert!(service.close_broadcast(call().header(), 42).await.is_err());
+        service.get_status().await;
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].method, "SendToAll");
+        assert_eq!(entries[0].summary, summarize("Backup done", "All good"));
+        assert_eq!(entries[0].outcome, AUDIT_OK);
+        assert_eq!(entries[1].summary, "broadcast_id=42");
+        assert!(entries[1].outcome.contains("Unknown broadcast id 42"));
+    }
+
+    #[tokio::test]
+    async fn test_notification_ids_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            state_dir: Some(dir.path().to_path_buf()),
+            store: StoreBackend::File,
+            persist_notification_ids: true,
+            ..Config::default()
+        };
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::new(config.clone()).with_sink(sink.clone()).with_session_owner(alice.clone());
+        let (title, body) = ("DB down".to_string(), "Investigating".to_string());
+        let (id, _) = service.send_to_all(call().header(), title, body).await.unwrap();
+
+        // Nothing saved the state periodically before the restart
+        let restarted = NotifierService::new(config).with_sink(sink.clone()).with_session_owner(alice);
+        assert_eq!(restarted.restore_state().await.unwrap(), 1);
+        let updated = restarted.update_broadcast(call().header(), id, "DB back".to_string(), "Resolved".to_string()).await;
+        assert_eq!(updated.unwrap(), 1);
+        assert_eq!(sink.delivered.lock().unwrap()[1].2.replaces_id, 1);
+        assert_eq!(restarted.close_broadcast(call().header(), id).await.unwrap(), 1);
+        assert_eq!(sink.closed.lock().unwrap()[0].1, 2);
+    }
+
+    #[tokio::test]
+    async fn test_state_restored_from_store() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let service = NotifierService::default().with_store(store.clone());
+        assert_eq!(service.restore_state().await.unwrap(), 0);
+        let id = service.broadcasts().register(Some("ops".to_string()));
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let payload = Arc::new(BroadcastPayload::new("Reboot", "Tonight"));
+        service.spool().push(SpooledNotification::new(alice, payload).with_broadcast_id(id)).unwrap();
+        service.save_state();
+
+        let restarted = NotifierService::default().with_store(store);
+        assert_eq!(restarted.restore_state().await.unwrap(), 2);
+        assert_eq!(restarted.broadcasts().channel_broadcasts("ops"), vec![id]);
+        assert_eq!(restarted.spool().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_deliveries_not_repeated() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let service = NotifierService::default().with_store(store.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let bob = TargetUser::new(1001, "bob".to_string());
+        let id = service.broadcasts().register(None);
+        let payload = Arc::new(BroadcastPayload::new("Reboot", "Tonight"));
+        for user in [&alice, &bob] {
+            let entry = SpooledNotification::new(user.clone(), payload.clone()).with_broadcast_id(id);
+            service.spool().push(entry).unwrap();
+        }
+        // The server stops while delivering to alice
+        service.spool().start_delivery(|entry| entry.user == alice);
+        service.save_state();
+
+        let sink = Arc::new(RecordingSink::default());
+        let restarted = NotifierService::default().with_store(store).with_sink(sink.clone());
+        restarted.restore_state().await.unwrap();
+        restarted.flush_spool().await;
+        {
+            let delivered = sink.delivered.lock().unwrap();
+            assert_eq!(delivered.len(), 1);
+            assert_eq!(delivered[0].0, bob);
+        }
+        let report = restarted.get_delivery_report(id).await.unwrap();
+        assert!(report.contains(&(1000, "alice".to_string(), "interrupted by a server restart".to_string())));
+        assert!(restarted.spool().in_flight().is_empty());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_state_survives_restart_with_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            state_dir: Some(dir.path().to_path_buf()),
+            store: store::StoreBackend::Sqlite,
+            ..Config::default()
+        };
+        let service = NotifierService::new(config.clone());
+        let id = service.broadcasts().register(Some("ops".to_string()));
+        service.save_state();
+
+        let restarted = NotifierService::new(config);
+        assert_eq!(restarted.restore_state().await.unwrap(), 1);
+        assert_eq!(restarted.broadcasts().channel_broadcasts("ops"), vec![id]);
+    }
+
+    #[tokio::test]
+    async fn test_tagged_broadcasts_listed_and_closed() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        service.sessions().store(HashSet::from([alice.clone()]));
+
+        let tags = vec!["incident-421".to_string(), "db".to_string(), "db".to_string()];
+        let tagged = service
+            .send_tagged(call().header(), String::new(), tags, "DB down".to_string(), "Investigating".to_string())
+            .await
+            .unwrap();
+        service.send_to_all(call().header(), "Lunch".to_string(), "Pizza".to_string()).await.unwrap();
+
+        let listed = service.list_broadcasts(call().header(), "incident-421".to_string()).await;
+        assert_eq!(
+            listed,
+            vec![(
+                tagged,
+                String::new(),
+                vec!["incident-421".to_string(), "db".to_string()],
+                "DB down".to_string()
+            )]
+        );
+        assert_eq!(service.list_broadcasts(call().header(), String::new()).await.len(), 2);
+
+        assert_eq!(service.close_tag(call().header(), "incident-421".to_string()).await.unwrap(), 1);
+        assert_eq!(*sink.closed.lock().unwrap(), vec![(alice, 1)]);
+        assert!(service.broadcasts().get(tagged).is_none());
+        assert_eq!(service.close_tag(call().header(), "incident-421".to_string()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_close_batch() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        service.sessions().store(HashSet::from([alice.clone()]));
+
+        let tags = vec![fleet::batch_tag("3f9c2a7b1d04")];
+        let batched = service
+            .send_tagged(call().header(), String::new(), tags, "Maintenance".to_string(), "Starting".to_string())
+            .await
+            .unwrap();
+        service.send_to_all(call().header(), "Lunch".to_string(), "Pizza".to_string()).await.unwrap();
+
+        assert!(service.close_batch(call().header(), " ".to_string()).await.is_err());
+        assert_eq!(service.close_batch(call().header(), "3f9c2a7b1d04".to_string()).await.unwrap(), 1);
+        assert_eq!(*sink.closed.lock().unwrap(), vec![(alice, 1)]);
+        assert!(service.broadcasts().get(batched).is_none());
+        assert_eq!(service.list_broadcasts(call().header(), String::new()).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_empty_tags_rejected() {
+        let service = NotifierService::default();
+        let tags = vec![" ".to_string()];
+        assert!(service.send_tagged(call().header(), String::new(), tags, "t".to_string(), "b".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay_reaches_late_users() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let bob = TargetUser::new(1001, "bob".to_string());
+        service.sessions().store(HashSet::from([alice.clone()]));
+        let tags = vec!["incident-421".to_string()];
+        let id = service
+            .send_tagged(call().header(), String::new(), tags, "DB down".to_string(), "Investigating".to_string())
+            .await
+            .unwrap();
+        service.update_broadcast(call().header(), id, "DB back".to_string(), "Resolved".to_string()).await.unwrap();
+
+        service.sessions().store(HashSet::from([alice, bob.clone()]));
+        assert_eq!(service.replay_tag(call().header(), "incident-421".to_string()).await.unwrap(), 1);
+        {
+            let delivered = sink.delivered.lock().unwrap();
+            let (user, payload, _) = delivered.last().unwrap();
+            assert_eq!(user, &bob);
+            assert_eq!(&*payload.title, "DB back");
+        }
+
+        assert_eq!(service.replay_broadcast(call().header(), id).await.unwrap(), 0);
+        assert!(service.replay_broadcast(call().header(), id + 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_re_announce_latest_critical_broadcast() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let bob = TargetUser::new(1001, "bob".to_string());
+        service.sessions().store(HashSet::from([alice.clone()]));
+        assert_eq!(service.re_announce_critical().await.unwrap(), 0);
+
+        let critical = OwnedValue::try_from(zbus::zvariant::Value::from("critical")).unwrap();
+        let options = HashMap::from([("urgency".to_string(), critical)]);
+        let (title, body) = ("Evacuate".to_string(), "Fire alarm in building B".to_string());
+        service.send_with_options(call().header(), title, body, options).await.unwrap();
+        service.send_to_all(call().header(), "Lunch".to_string(), "Pizza is here".to_string()).await.unwrap();
+
+        service.sessions().store(HashSet::from([alice, bob.clone()]));
+        assert_eq!(service.re_announce_critical().await.unwrap(), 2);
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 4);
+        for (_, payload, _) in &delivered[2..] {
+            assert_eq!(&*payload.title, "Evacuate");
+        }
+        // Alice's notification is replaced in place, Bob gets a new one
+        let replaces: HashMap<u32, u32> =
+            delivered[2..].iter().map(|(user, _, options)| (user.uid, options.replaces_id)).collect();
+        assert_eq!(replaces, HashMap::from([(1000, 1), (bob.uid, 0)]));
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_coalesced() {
+        let sink = Arc::new(RecordingSink::default());
+        let config = Config::from_toml_str("dedup_window_secs = 300").unwrap();
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        let alice = TargetUser::new(1000, "alice".to_string());
+        service.sessions().store(HashSet::from([alice.clone()]));
+        let message = call();
+        let send = || service.send_to_all(message.header(), "Disk full".to_string(), "/var is at 99%".to_string());
+
+        let first = send().await.unwrap().0;
+        assert_eq!(send().await.unwrap().0, first);
+        {
+            let delivered = sink.delivered.lock().unwrap();
+            assert_eq!(delivered.len(), 2);
+            assert_eq!(delivered[1].2.replaces_id, 1);
+        }
+
+        // Bob never saw the first one, so he gets a broadcast of his own
+        service.sessions().store(HashSet::from([alice, TargetUser::new(1001, "bob".to_string())]));
+        let second = send().await.unwrap().0;
+        assert_ne!(second, first);
+
+        let allow = OwnedValue::try_from(zbus::zvariant::Value::from(true)).unwrap();
+        let options = HashMap::from([("allow_duplicate".to_string(), allow)]);
+        let (title, body) = ("Disk full".to_string(), "/var is at 99%".to_string());
+        let third = service.send_with_options(call().header(), title, body, options).await.unwrap();
+        assert!(third > second);
+        let delivered = sink.delivered.lock().unwrap();
+        assert!(delivered[5..].iter().all(|(_, _, options)| options.replaces_id == 0));
+    }
+
+    #[tokio::test]
+    async fn test_send_batch() {
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_sink(sink.clone());
+        let users = [TargetUser::new(1000, "alice".to_string()), TargetUser::new(1001, "bob".to_string())];
+        service.sessions().store(HashSet::from(users));
+        let batch = |titles: &[&str]| {
+            titles.iter().map(|title| (title.to_string(), format!("{} body", title))).collect::<Vec<_>>()
+        };
+
+        let ids = service.send_batch(call().header(), batch(&["Backup done", "Updates ready", "Reboot"])).await;
+        let ids = ids.unwrap();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        {
+            let delivered = sink.delivered.lock().unwrap();
+            assert_eq!(delivered.len(), 6);
+            assert_eq!(&*delivered[5].1.title, "Reboot");
+        }
+
+        // A single invalid notification rejects the whole batch
+        assert!(service.send_batch(call().header(), batch(&["Fine", ""])).await.is_err());
+        assert!(service.send_batch(call().header(), Vec::new()).await.is_err());
+        assert_eq!(sink.delivered.lock().unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_suppressed_duplicates_summarized() {
+        let sink = Arc::new(RecordingSink::default());
+        let config = Config::from_toml_str("dedup_window_secs = 300\nsuppression_summary_secs = 300").unwrap();
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        let message = call();
+        let send = || service.send_to_all(message.header(), "Disk full".to_string(), "/var is at 99%".to_string());
+
+        let first = send().await.unwrap().0;
+        for _ in 0..3 {
+            assert_eq!(send().await.unwrap().0, first);
+        }
+        let summary = service.summarize_suppressed(1000).await.unwrap();
+        assert_ne!(summary, first);
+        assert_eq!(service.summarize_suppressed(1000).await, None);
+
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 5);
+        let (_, payload, options) = &delivered[4];
+        assert_eq!(&*payload.title, "Similar alerts suppressed");
+        assert!(payload.body.starts_with("3 similar alerts were suppressed in the last 5 minutes."));
+        assert_eq!(options.replaces_id, 0);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_broadcasts_survive_restart() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_store(store.clone()).with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        let message = call();
+        let schedule = |at: u64, title: &str, options: HashMap<String, OwnedValue>| {
+            service.schedule(message.header(), at, title.to_string(), "Save your work".to_string(), options)
+        };
+
+        let past = schedule(unix_now() - 60, "Reboot", HashMap::new()).await;
+        assert!(matches!(past, Err(SendError::Fdo(zbus::fdo::Error::InvalidArgs(_)))));
+        let empty_tag = OwnedValue::try_from(zbus::zvariant::Value::from(vec![" "])).unwrap();
+        let invalid = schedule(unix_now() + 60, "Reboot", HashMap::from([("tags".to_string(), empty_tag)])).await;
+        assert!(invalid.is_err());
+
+        let channel = OwnedValue::try_from(zbus::zvariant::Value::from("patching")).unwrap();
+        let later = schedule(unix_now() + 3600, "Reboot", HashMap::from([("channel".to_string(), channel)]));
+        let later = later.await.unwrap();
+        let due = schedule(unix_now(), "Reboot now", HashMap::new()).await.unwrap();
+        let cancelled = schedule(unix_now() + 60, "Reboot soon", HashMap::new()).await.unwrap();
+        service.cancel_scheduled(call().header(), cancelled).await.unwrap();
+        assert!(service.cancel_scheduled(call().header(), cancelled).await.is_err());
+        let scheduled = service.list_scheduled().await;
+        assert_eq!(scheduled.iter().map(|entry| entry.0).collect::<Vec<_>>(), vec![due, later]);
+        assert_eq!(scheduled[1].2, "patching");
+        assert!(sink.delivered.lock().unwrap().is_empty());
+
+        // Scheduling saved the state, so a restart keeps both
+        let restarted = NotifierService::default().with_store(store).with_sink(sink.clone());
+        restarted.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        assert_eq!(restarted.restore_state().await.unwrap(), 2);
+        assert_eq!(restarted.fire_scheduled().await, 1);
+        assert_eq!(&*sink.delivered.lock().unwrap()[0].1.title, "Reboot now");
+        assert_eq!(restarted.fire_scheduled().await, 0);
+        assert_eq!(restarted.list_scheduled().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_recurring_broadcasts_repeat() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let sink = Arc::new(RecordingSink::default());
+        let service = NotifierService::default().with_store(store.clone()).with_sink(sink.clone());
+        service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        let message = call();
+        let schedule = |rule: &str| {
+            let (title, body) = ("Backup".to_string(), "Leave your laptop on".to_string());
+            service.schedule_recurring(message.header(), rule.to_string(), title, body, HashMap::new())
+        };
+
+        assert!(schedule("every 0s").await.is_err());
+        assert!(schedule("0 0 30 2 *").await.is_err());
+        let hourly = schedule("every 1h").await.unwrap();
+        let weekly = schedule("0 9 * * 1").await.unwrap();
+        let (title, at) = ("Reboot".to_string(), unix_now() + 60);
+        let once = service.schedule(message.header(), at, title, String::new(), HashMap::new());
+        let once = once.await.unwrap();
+
+        let recurring = service.list_recurring().await;
+        assert_eq!(recurring.iter().map(|entry| entry.0).collect::<HashSet<_>>(), HashSet::from([hourly, weekly]));
+        let (_, rule, at, ..) = recurring.iter().find(|entry| entry.0 == hourly).unwrap();
+        assert_eq!(rule, "every 1h");
+        assert!(at.abs_diff(unix_now() + 3600) <= 1);
+        assert_eq!(service.list_scheduled().await.iter().map(|entry| entry.0).collect::<Vec<_>>(), vec![once]);
+        assert!(service.remove_recurring(call().header(), once).await.is_err());
+        assert!(service.cancel_scheduled(call().header(), hourly).await.is_err());
+
+        // Recurring broadcasts are kept across restarts and stay after being sent
+        let restarted = NotifierService::default().with_store(store).with_sink(sink.clone());
+        restarted.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        assert_eq!(restarted.restore_state().await.unwrap(), 3);
+        restarted.state.scheduler.remove_recurring(hourly).unwrap();
+        let due = restarted.state.scheduler.add_recurring(
+            unix_now(),
+            Recurrence::Every(3600),
+            "Backup".to_string(),
+            String::new(),
+            SendOptions::default(),
+            None,
+        );
+        assert_eq!(restarted.fire_scheduled().await, 1);
+        assert_eq!(&*sink.delivered.lock().unwrap()[0].1.title, "Backup");
+        assert_eq!(restarted.fire_scheduled().await, 0);
+        assert_eq!(restarted.list_recurring().await.len(), 2);
+        restarted.remove_recurring(call().header(), due).await.unwrap();
+        assert_eq!(restarted.list_recurring().await.len(), 1);
+    }
+
+    /// Sink logging a user in while the first notification is being delivered
+    #[derive(Debug, Default)]
+    struct LoginSink {
+        recording: RecordingSink,
+        service: OnceLock<NotifierService>,
+        joining: Mutex<Option<TargetUser>>,
+    }
+
+    impl NotificationSink for LoginSink {
+        fn notify<'a>(
+            &'a self,
+            user: &'a TargetUser,
+            payload: Arc<BroadcastPayload>,
+            options: &'a DeliveryOptions,
+        ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+            if let Some(joining) = self.joining.lock().unwrap().take() {
+                let sessions = self.service.get().unwrap().sessions();
+                let mut users = sessions.cached().unwrap();
+                users.insert(joining);
+                sessions.store(users);
+            }
+            self.recording.notify(user, payload, options)
+        }
+
+        fn close<'a>(
+            &'a self,
+            user: &'a TargetUser,
+            bus_name: &'a str,
+            notification_id: u32,
+        ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            self.recording.close(user, bus_name, notification_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_late_joiners_included() {
+        let (alice, bob) = (TargetUser::new(1000, "alice".to_string()), TargetUser::new(1001, "bob".to_string()));
+        for (grace, expected) in [(None, vec![1000]), (Some(0), vec![1000, 1001])] {
+            let sink = Arc::new(LoginSink::default());
+            *sink.joining.lock().unwrap() = Some(bob.clone());
+            let config = Config { late_join_grace_secs: grace, ..Config::default() };
+            let service = NotifierService::new(config).with_sink(sink.clone());
+            sink.service.set(service.clone()).unwrap();
+            service.sessions().store(HashSet::from([alice.clone()]));
+
+            let (title, body) = ("Reboot".to_string(), "At noon".to_string());
+            let broadcast_id = service.send_to_all(call().header(), title, body).await.unwrap().0;
+            let uids: Vec<u32> = sink.recording.delivered.lock().unwrap().iter().map(|(user, ..)| user.uid).collect();
+            assert_eq!(uids, expected);
+            let report = service.broadcasts().get(broadcast_id).unwrap().report;
+            assert_eq!(report.len(), expected.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_welcome_delivers_what_waits() {
+        let (alice, bob) = (TargetUser::new(1000, "alice".to_string()), TargetUser::new(1001, "bob".to_string()));
+        let sink = Arc::new(RecordingSink::default());
+        let config = Config { late_join_grace_secs: Some(600), ..Config::default() };
+        let service = NotifierService::new(config).with_sink(sink.clone());
+        service.sessions().store(HashSet::from([alice.clone()]));
+        let (title, body) = ("Reboot".to_string(), "At noon".to_string());
+        let broadcast_id = service.send_to_all(call().header(), title, body).await.unwrap().0;
+        service.spool().push(spooled(bob.clone(), "Parked", "Earlier").parked()).unwrap();
+        service.spool().push(spooled(alice.clone(), "Parked", "For alice")).unwrap();
+
+        // Bob logs in after the broadcast
+        assert_eq!(service.welcome(&bob).await, 2);
+        let delivered = sink.delivered.lock().unwrap().clone();
+        let to_bob: Vec<&str> =
+            delivered.iter().filter(|(user, ..)| user.uid == 1001).map(|(_, payload, _)| &*payload.title).collect();
+        assert_eq!(to_bob.len(), 2);
+        assert!(to_bob.contains(&"Reboot") && to_bob.contains(&"Parked"));
+        assert_eq!(service.broadcasts().get(broadcast_id).unwrap().report.len(), 2);
+        assert_eq!(service.spool().len(), 1);
+
+        // Nothing is delivered twice
+        assert_eq!(service.welcome(&bob).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_queued_for_absent_users() {
+        let queued = |ttl_secs| async move {
+            let sink = Arc::new(RecordingSink::default());
+            let config = Config { absent_queue_ttl_secs: Some(ttl_secs), ..Config::default() };
+            let service = NotifierService::new(config).with_sink(sink.clone());
+            service.sessions().store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+            let excluded = OwnedValue::try_from(zbus::zvariant::Value::from(vec!["carol"])).unwrap();
+            let options = HashMap::from([
+                ("queue_for_absent".to_string(), OwnedValue::from(true)),
+                ("exclude_users".to_string(), excluded),
+            ]);
+            let (title, body) = ("Disk failing".to_string(), "Save your work".to_string());
+            service.send_with_options(call().header(), title, body, options).await.unwrap();
+            let (title, body) = ("Lunch".to_string(), "In the kitchen".to_string());
+            service.send_to_all(call().header(), title, body).await.unwrap();
+            (service, sink)
+        };
+
+        let (service, sink) = queued(3600).await;
+        let (bob, carol) = (TargetUser::new(1001, "bob".to_string()), TargetUser::new(1002, "carol".to_string()));
+        assert_eq!(service.welcome(&carol).await, 0, "carol is excluded");
+        assert_eq!(service.welcome(&bob).await, 1);
+        let delivered = sink.delivered.lock().unwrap().clone();
+        let to_bob: Vec<&str> =
+            delivered.iter().filter(|(user, ..)| user.uid == 1001).map(|(_, payload, _)| &*payload.title).collect();
+        assert_eq!(to_bob, vec!["Disk failing"]);
+        assert_eq!(service.welcome(&bob).await, 0);
+
+        // Users logging in after the TTL miss it
+        let (service, _) = queued(0).await;
+        assert_eq!(service.welcome(&bob).await, 0);
+    }
+}
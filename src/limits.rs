@@ -0,0 +1,151 @@
+//! Limits on broadcast payloads and pending notifications
+//!
+//! A broadcast held back for users outside their delivery window is copied
+//! once per user, so a single caller embedding huge payloads could otherwise
+//! balloon server memory. Payloads are checked against a size limit before
+//! dispatch, and the memory held by pending notifications is capped.
+
+use std::error::Error;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::ratelimit::RateLimit;
+
+/// Default limit on the size of a single broadcast payload
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 256 * 1024;
+
+/// Default limit on the memory held by pending notifications
+pub const DEFAULT_MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+
+/// Configured payload and memory limits
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Maximum size of a broadcast payload (title, body and hints), in bytes
+    pub max_payload_bytes: Option<usize>,
+    /// Maximum memory held by notifications waiting in the spool, in bytes
+    pub max_pending_bytes: Option<usize>,
+    /// Broadcasts a single sender may make per minute on average; unlimited if unset
+    pub sender_rate_per_minute: Option<u32>,
+    /// Broadcasts a sender may make in a row before the average applies; defaults to the rate per minute
+    pub sender_burst: Option<u32>,
+    /// Broadcasts shown on a user's desktop at once; older non-critical ones are closed to make room
+    /// for new ones, and new non-critical ones wait in the spool while only critical ones are shown.
+    /// Unlimited if unset or 0
+    pub max_displayed_per_user: Option<usize>,
+}
+
+impl LimitsConfig {
+    /// Get the maximum size of a broadcast payload
+    pub fn max_payload_bytes(&self) -> usize {
+        self.max_payload_bytes.unwrap_or(DEFAULT_MAX_PAYLOAD_BYTES)
+    }
+
+    /// Get the maximum memory held by pending notifications
+    pub fn max_pending_bytes(&self) -> usize {
+        self.max_pending_bytes.unwrap_or(DEFAULT_MAX_PENDING_BYTES)
+    }
+
+    /// Get how many broadcasts may be shown on a user's desktop at once, if they are limited
+    pub fn max_displayed_per_user(&self) -> Option<usize> {
+        self.max_displayed_per_user.filter(|&max| max > 0)
+    }
+
+    /// Get how many broadcasts a single sender may make, if senders are limited
+    pub fn sender_rate_limit(&self) -> Option<RateLimit> {
+        self.sender_rate_per_minute.map(|per_minute| RateLimit {
+            per_minute,
+            burst: self.sender_burst.unwrap_or(per_minute),
+        })
+    }
+
+    /// Check the size of a broadcast payload against the configured limit
+    pub fn check_payload(&self, size: usize) -> Result<(), LimitExceeded> {
+        let limit = self.max_payload_bytes();
+        if size > limit {
+            return Err(LimitExceeded::Payload { size, limit });
+        }
+        Ok(())
+    }
+}
+
+/// A payload or memory limit that would be exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The broadcast payload is larger than allowed
+    Payload { size: usize, limit: usize },
+    /// Holding the notification would exceed the memory allowed for pending notifications
+    Pending { size: usize, limit: usize },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Payload { size, limit } => write!(
+                f,
+                "Broadcast payload of {} bytes exceeds the limit of {} bytes",
+                size, limit
+            ),
+            LimitExceeded::Pending { size, limit } => write!(
+                f,
+                "Pending notifications would use {} bytes, exceeding the limit of {} bytes",
+                size, limit
+            ),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+impl From<LimitExceeded> for zbus::fdo::Error {
+    fn from(error: LimitExceeded) -> Self {
+        zbus::fdo::Error::LimitsExceeded(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let limits = LimitsConfig::default();
+        assert_eq!(limits.max_payload_bytes(), DEFAULT_MAX_PAYLOAD_BYTES);
+        assert_eq!(limits.max_pending_bytes(), DEFAULT_MAX_PENDING_BYTES);
+        assert_eq!(limits.sender_rate_limit(), None);
+        assert_eq!(limits.max_displayed_per_user(), None);
+        let limits: LimitsConfig = toml::from_str("max_displayed_per_user = 0").unwrap();
+        assert_eq!(limits.max_displayed_per_user(), None);
+    }
+
+    #[test]
+    fn test_sender_rate_limit() {
+        let limits: LimitsConfig = toml::from_str("sender_rate_per_minute = 10").unwrap();
+        assert_eq!(limits.sender_rate_limit(), Some(RateLimit { per_minute: 10, burst: 10 }));
+        let limits: LimitsConfig = toml::from_str("sender_rate_per_minute = 10\nsender_burst = 3").unwrap();
+        assert_eq!(limits.sender_rate_limit(), Some(RateLimit { per_minute: 10, burst: 3 }));
+    }
+
+    #[test]
+    fn test_check_payload() {
+        let limits = LimitsConfig {
+            max_payload_bytes: Some(10),
+            ..LimitsConfig::default()
+        };
+        assert!(limits.check_payload(10).is_ok());
+        assert_eq!(
+            limits.check_payload(11),
+            Err(LimitExceeded::Payload { size: 11, limit: 10 })
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let error = LimitExceeded::Payload { size: 11, limit: 10 };
+        assert_eq!(error.to_string(), "Broadcast payload of 11 bytes exceeds the limit of 10 bytes");
+
+        let error: zbus::fdo::Error = LimitExceeded::Pending { size: 20, limit: 16 }.into();
+        assert!(matches!(error, zbus::fdo::Error::LimitsExceeded(_)));
+    }
+}
@@ -0,0 +1,145 @@
+//! Content checks run before a broadcast is sent
+//!
+//! Broadcasts are linted for common mistakes, such as markup that will be
+//! shown literally or template variables a script forgot to fill in. Lints
+//! never reject a broadcast: their warnings are logged, kept with the
+//! broadcast in the history and returned to the sender.
+
+use std::fmt;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Longest line that reads comfortably in a notification bubble
+pub const MAX_LINE_LENGTH: usize = 120;
+
+/// Fewest letters a text must have before being all capitals counts as shouting
+const MIN_SHOUTING_LETTERS: usize = 8;
+
+/// Tags of the markup subset some notification daemons render, and HTML entities
+static MARKUP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)</?(b|i|u|a|img|br)\b[^>]*>|&(amp|lt|gt|quot|apos|#\d+);").unwrap());
+
+/// `{name}`, `{{ name }}` and `${NAME}` placeholders of common templating tools
+static TEMPLATE_VARIABLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{[A-Za-z_]\w*\}|\{\{\s*[A-Za-z_][\w.]*\s*\}\}|\{[A-Za-z_]\w*\}").unwrap());
+
+/// A possible problem with the content of a broadcast
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintWarning {
+    /// A line of the body is longer than [`MAX_LINE_LENGTH`] characters
+    LongLine { line: usize, length: usize },
+    /// The content contains markup, which is sent as plain text
+    Markup(String),
+    /// The title has no words, only emoji or symbols
+    EmojiOnlyTitle,
+    /// The title or body is written in capitals only
+    Shouting { field: &'static str },
+    /// A template variable was left unresolved
+    UnresolvedVariable(String),
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintWarning::LongLine { line, length } => {
+                write!(f, "Line {} of the body is {} characters long (over {})", line, length, MAX_LINE_LENGTH)
+            }
+            LintWarning::Markup(markup) => write!(f, "Markup '{}' will be shown as plain text", markup),
+            LintWarning::EmojiOnlyTitle => write!(f, "The title has no words"),
+            LintWarning::Shouting { field } => write!(f, "The {} is in all capitals", field),
+            LintWarning::UnresolvedVariable(variable) => write!(f, "Template variable {} was not filled in", variable),
+        }
+    }
+}
+
+/// Lint the title and body of a broadcast
+pub fn lint(title: &str, body: &str) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    for (index, line) in body.lines().enumerate() {
+        let length = line.chars().count();
+        if length > MAX_LINE_LENGTH {
+            warnings.push(LintWarning::LongLine { line: index + 1, length });
+        }
+    }
+    for text in [title, body] {
+        warnings.extend(MARKUP.find_iter(text).map(|m| LintWarning::Markup(m.as_str().to_string())));
+    }
+    let title = title.trim();
+    if !title.is_empty() && !title.chars().any(char::is_alphanumeric) && !title.is_ascii() {
+        warnings.push(LintWarning::EmojiOnlyTitle);
+    }
+    for (field, text) in [("title", title), ("body", body)] {
+        if is_shouting(text) {
+            warnings.push(LintWarning::Shouting { field });
+        }
+    }
+    for text in [title, body] {
+        warnings.extend(
+            TEMPLATE_VARIABLE
+                .find_iter(text)
+                .map(|m| LintWarning::UnresolvedVariable(m.as_str().to_string())),
+        );
+    }
+    warnings
+}
+
+/// Whether a text has enough letters to read as words and all of them are capitals
+fn is_shouting(text: &str) -> bool {
+    let letters: Vec<char> = text.chars().filter(|c| c.is_alphabetic()).collect();
+    letters.len() >= MIN_SHOUTING_LETTERS && letters.iter().all(|c| c.is_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_content() {
+        assert!(lint("Reboot tonight", "The file server restarts at 22:00 for patching.").is_empty());
+        assert!(lint("Disk usage", "Check /var (NFS) before 5 PM; RAID is degraded").is_empty());
+    }
+
+    #[test]
+    fn test_long_lines() {
+        let body = format!("Short line\n{}", "x".repeat(MAX_LINE_LENGTH + 1));
+        assert_eq!(lint("Title", &body), vec![LintWarning::LongLine { line: 2, length: MAX_LINE_LENGTH + 1 }]);
+        assert!(lint("Title", &"é".repeat(MAX_LINE_LENGTH)).is_empty());
+    }
+
+    #[test]
+    fn test_markup() {
+        assert_eq!(lint("Title", "Save <b>now</b>"), vec![
+            LintWarning::Markup("<b>".to_string()),
+            LintWarning::Markup("</b>".to_string()),
+        ]);
+        assert_eq!(lint("Fish &amp; chips", "Lunch"), vec![LintWarning::Markup("&amp;".to_string())]);
+        assert!(lint("Title", "x < y and y > z").is_empty());
+    }
+
+    #[test]
+    fn test_emoji_only_title() {
+        assert_eq!(lint("🔥🔥", "The server room is warm"), vec![LintWarning::EmojiOnlyTitle]);
+        assert!(lint("🔥 Fire drill", "Leave at 10:00").is_empty());
+        assert!(lint("!!!", "Look").is_empty());
+    }
+
+    #[test]
+    fn test_shouting() {
+        assert_eq!(lint("REBOOT NOW", "Save your work"), vec![LintWarning::Shouting { field: "title" }]);
+        assert_eq!(lint("Reboot", "SAVE YOUR WORK"), vec![LintWarning::Shouting { field: "body" }]);
+        assert!(lint("VPN down", "NFS OK").is_empty());
+    }
+
+    #[test]
+    fn test_unresolved_variables() {
+        let warnings = lint("Backup of {host}", "Finished at {{ time }}, see ${LOG_URL}");
+        assert_eq!(warnings, vec![
+            LintWarning::UnresolvedVariable("{host}".to_string()),
+            LintWarning::UnresolvedVariable("{{ time }}".to_string()),
+            LintWarning::UnresolvedVariable("${LOG_URL}".to_string()),
+        ]);
+        assert_eq!(warnings[0].to_string(), "Template variable {host} was not filled in");
+        assert!(lint("Costs", "Spent $40 on {} braces").is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! Diagnostics for deliveries denied by mandatory access control
+//!
+//! SELinux and AppArmor policies confining the server can deny it spawning the
+//! delivery helper or connecting to a user's session bus. The kernel and the
+//! bus daemon report such denials as plain permission errors, which read like
+//! wrong file modes. When a delivery fails with one and the server runs under
+//! an enforcing policy, the failure is reported as [`DeliveryErrorKind::PolicyDenied`]
+//! with a hint on where to find the denial, and `doctor` shows the policy
+//! confining the running server.
+//!
+//! [`DeliveryErrorKind::PolicyDenied`]: crate::delivery::DeliveryErrorKind::PolicyDenied
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::NotifierError;
+
+/// Whether SELinux is enabled, and enforcing its policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SelinuxMode {
+    #[default]
+    Disabled,
+    /// Denials are logged but not enforced
+    Permissive,
+    Enforcing,
+}
+
+impl fmt::Display for SelinuxMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelinuxMode::Disabled => f.write_str("disabled"),
+            SelinuxMode::Permissive => f.write_str("permissive"),
+            SelinuxMode::Enforcing => f.write_str("enforcing"),
+        }
+    }
+}
+
+/// The mandatory access control policies a process runs under
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MacStatus {
+    pub selinux: SelinuxMode,
+    /// SELinux context of the process, if SELinux is enabled
+    pub selinux_context: Option<String>,
+    /// AppArmor profile confining the process and its mode, such as `enforce` or `complain`
+    pub apparmor: Option<(String, String)>,
+}
+
+impl MacStatus {
+    /// Detect the policies the current process runs under
+    pub fn detect() -> Self {
+        Self::read(Path::new("/"), Path::new("/proc/self"))
+    }
+
+    /// Detect the policies another process runs under, such as the running server
+    pub fn detect_for_pid(pid: u32) -> Self {
+        Self::read(Path::new("/"), &PathBuf::from(format!("/proc/{}", pid)))
+    }
+
+    /// Read the policies of the process at `proc_dir` from the file systems under `root`
+    ///
+    /// Files that cannot be read, such as those of another user's process, leave their policy out.
+    fn read(root: &Path, proc_dir: &Path) -> Self {
+        let read = |path: PathBuf| fs::read_to_string(path).ok().map(|s| s.trim_end_matches(['\0', '\n']).to_string());
+        let selinux = match read(root.join("sys/fs/selinux/enforce")).as_deref() {
+            Some("1") => SelinuxMode::Enforcing,
+            Some(_) => SelinuxMode::Permissive,
+            None => SelinuxMode::Disabled,
+        };
+        let selinux_context = match selinux {
+            SelinuxMode::Disabled => None,
+            _ => read(proc_dir.join("attr/selinux/current")).or_else(|| read(proc_dir.join("attr/current"))),
+        };
+        let apparmor_enabled = read(root.join("sys/module/apparmor/parameters/enabled")).as_deref() == Some("Y");
+        let apparmor = read(proc_dir.join("attr/apparmor/current"))
+            .or_else(|| read(proc_dir.join("attr/current")).filter(|_| apparmor_enabled && selinux_context.is_none()))
+            .and_then(|label| parse_apparmor_label(&label));
+        Self { selinux, selinux_context, apparmor }
+    }
+
+    /// Get the AppArmor profile enforced on the process, if any
+    pub fn enforced_apparmor_profile(&self) -> Option<&str> {
+        match &self.apparmor {
+            Some((profile, mode)) if mode == "enforce" || mode == "kill" => Some(profile),
+            _ => None,
+        }
+    }
+
+    /// Whether a policy may deny the process anything
+    pub fn is_enforcing(&self) -> bool {
+        self.selinux == SelinuxMode::Enforcing || self.enforced_apparmor_profile().is_some()
+    }
+
+    /// Describe what to do about a permission error, if a policy explains it
+    ///
+    /// A denial naming SELinux or AppArmor is attributed to it even if the policy was not
+    /// detected, such as one enforced by the bus daemon on the user's session bus.
+    pub fn hint(&self, denial: &str) -> Option<String> {
+        let denial = denial.to_lowercase();
+        if denial.contains("selinux") || self.selinux == SelinuxMode::Enforcing {
+            let context = self.selinux_context.as_deref().map_or_else(String::new, |c| format!(" for {}", c));
+            return Some(format!(
+                "SELinux is enforcing{}; look for the denial with `ausearch -m avc -ts recent` and allow it \
+                 with a local policy module, e.g. built by `audit2allow -M dots-notifier`",
+                context
+            ));
+        }
+        if denial.contains("apparmor") || self.enforced_apparmor_profile().is_some() {
+            let profile = self.enforced_apparmor_profile().map_or_else(String::new, |p| format!(" {}", p));
+            return Some(format!(
+                "AppArmor profile{} is enforced; look for apparmor=\"DENIED\" in `journalctl -k` and extend \
+                 the profile, or try it in complain mode with `aa-complain`",
+                profile
+            ));
+        }
+        None
+    }
+
+    /// Describe the policies for `doctor`, one finding per line
+    pub fn report(&self) -> Vec<String> {
+        let mut lines = vec![match &self.selinux_context {
+            Some(context) => format!("selinux: {} ({})", self.selinux, context),
+            None => format!("selinux: {}", self.selinux),
+        }];
+        lines.push(match &self.apparmor {
+            Some((profile, mode)) => format!("apparmor: profile {} ({})", profile, mode),
+            None => "apparmor: not confined".to_string(),
+        });
+        if let Some(hint) = self.hint("") {
+            lines.push(format!("hint: if deliveries fail with permission errors, {}", hint));
+        }
+        lines
+    }
+}
+
+/// Parse an AppArmor label such as `/usr/bin/dots-notifier (enforce)` into profile and mode
+///
+/// Unconfined processes have no profile.
+fn parse_apparmor_label(label: &str) -> Option<(String, String)> {
+    let label = label.trim();
+    if label.is_empty() || label == "unconfined" {
+        return None;
+    }
+    match label.strip_suffix(')').and_then(|label| label.rsplit_once(" (")) {
+        Some((profile, mode)) => Some((profile.to_string(), mode.to_string())),
+        None => Some((label.to_string(), "enforce".to_string())),
+    }
+}
+
+/// Get the message of a permission error in the chain of `error`, if any
+///
+/// Both errors of the kernel, such as spawning the helper or opening a bus socket, and
+/// `AccessDenied` errors of the bus daemon count.
+pub fn permission_denial(error: &(dyn Error + 'static)) -> Option<String> {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        let denial = if let Some(error) = error.downcast_ref::<io::Error>() {
+            (error.kind() == io::ErrorKind::PermissionDenied).then(|| error.to_string())
+        } else if let Some(error) = error.downcast_ref::<zbus::Error>() {
+            match error {
+                zbus::Error::InputOutput(error) => permission_denial(error.as_ref()),
+                zbus::Error::MethodError(name, message, _) if name.as_str().ends_with(".AccessDenied") => {
+                    Some(message.clone().unwrap_or_else(|| name.to_string()))
+                }
+                zbus::Error::FDO(error) => permission_denial(error.as_ref()),
+                _ => None,
+            }
+        } else if let Some(zbus::fdo::Error::AccessDenied(message)) = error.downcast_ref::<zbus::fdo::Error>() {
+            Some(message.clone())
+        } else if let Some(NotifierError::DbusConnect { source, .. }) = error.downcast_ref::<NotifierError>() {
+            permission_denial(source)
+        } else {
+            None
+        };
+        if denial.is_some() {
+            return denial;
+        }
+        current = error.source();
+    }
+    None
+}
+
+/// Explain a delivery failure denied by an access control policy of this process
+pub fn diagnose(error: &(dyn Error + 'static)) -> Option<String> {
+    let denial = permission_denial(error)?;
+    MacStatus::detect().hint(&denial)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_read_selinux() {
+        let dir = tempfile::tempdir().unwrap();
+        let proc_dir = dir.path().join("proc/42");
+        assert_eq!(MacStatus::read(dir.path(), &proc_dir), MacStatus::default());
+
+        write(dir.path(), "sys/fs/selinux/enforce", "1");
+        write(&proc_dir, "attr/current", "system_u:system_r:dots_notifier_t:s0\0");
+        let status = MacStatus::read(dir.path(), &proc_dir);
+        assert_eq!(status.selinux, SelinuxMode::Enforcing);
+        assert_eq!(status.selinux_context.as_deref(), Some("system_u:system_r:dots_notifier_t:s0"));
+        assert_eq!(status.apparmor, None);
+        assert!(status.is_enforcing());
+
+        write(dir.path(), "sys/fs/selinux/enforce", "0");
+        assert!(!MacStatus::read(dir.path(), &proc_dir).is_enforcing());
+    }
+
+    #[test]
+    fn test_read_apparmor() {
+        let dir = tempfile::tempdir().unwrap();
+        let proc_dir = dir.path().join("proc/42");
+        write(dir.path(), "sys/module/apparmor/parameters/enabled", "Y\n");
+        write(&proc_dir, "attr/current", "/usr/bin/dots-notifier (enforce)\n");
+        let status = MacStatus::read(dir.path(), &proc_dir);
+        assert_eq!(status.enforced_apparmor_profile(), Some("/usr/bin/dots-notifier"));
+        assert!(status.is_enforcing());
+
+        write(&proc_dir, "attr/apparmor/current", "dots-notifier (complain)\n");
+        let status = MacStatus::read(dir.path(), &proc_dir);
+        assert_eq!(status.apparmor, Some(("dots-notifier".to_string(), "complain".to_string())));
+        assert!(!status.is_enforcing());
+
+        write(&proc_dir, "attr/apparmor/current", "unconfined\n");
+        assert_eq!(MacStatus::read(dir.path(), &proc_dir).apparmor, None);
+    }
+
+    #[test]
+    fn test_hint() {
+        let unconfined = MacStatus::default();
+        assert_eq!(unconfined.hint("Permission denied (os error 13)"), None);
+        let hint = unconfined.hint("An SELinux policy prevents this sender from sending this message").unwrap();
+        assert!(hint.contains("ausearch -m avc"));
+
+        let confined = MacStatus {
+            apparmor: Some(("dots-notifier".to_string(), "enforce".to_string())),
+            ..MacStatus::default()
+        };
+        let hint = confined.hint("Permission denied (os error 13)").unwrap();
+        assert!(hint.starts_with("AppArmor profile dots-notifier is enforced"));
+        assert_eq!(confined.report().len(), 3);
+        assert_eq!(unconfined.report(), vec!["selinux: disabled", "apparmor: not confined"]);
+    }
+
+    #[test]
+    fn test_permission_denial() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(permission_denial(&NotifierError::HelperSpawn(denied)).is_some());
+
+        let socket = zbus::Error::InputOutput(Arc::new(io::Error::from(io::ErrorKind::PermissionDenied)));
+        assert!(permission_denial(&NotifierError::connect("unix:path=/run/user/1000/bus", socket)).is_some());
+
+        let message = "An AppArmor policy prevents this sender from sending this message to this recipient";
+        let refused = zbus::fdo::Error::AccessDenied(message.to_string());
+        assert_eq!(permission_denial(&refused).as_deref(), Some(message));
+
+        let missing = zbus::Error::InputOutput(Arc::new(io::Error::from(io::ErrorKind::NotFound)));
+        assert_eq!(permission_denial(&missing), None);
+    }
+}
@@ -1,59 +1,1302 @@
+use std::collections::BTreeMap;
 use std::error::Error;
-use tracing::{info};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{Local, NaiveDateTime};
+use futures::StreamExt;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Interval;
+use tracing::{info, warn};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
-use zbus::Connection;
+use zbus::zvariant::Value;
 
 use dots_notifier::{
-    cli::{Cli, Commands},
-    dbus::{DBUS_INTERFACE_NAME, DBUS_PATH, NotifierProxy},
+    bench,
+    bus::BusType,
+    cli::{
+        parse_update_args, BroadcastTarget, Cli, Commands, ExportFormat, HistoryCommand, RecurringCommand, RemoteArgs,
+        ProgressCommand, QuarantineCommand, ReportFormat, ScheduleCommand, Switch,
+    },
+    client::NotifierClient,
+    compose,
+    config::Config,
+    dbus::{DeliverySummary, HistoryEntry, DBUS_INTERFACE_NAME, DBUS_PATH, NotifierProxy},
+    fleet,
+    i18n::{process_locale, Localizer},
+    mac::MacStatus,
+    maintenance::user_state_dir,
+    notification::{render_preview, session_bus_pool, BroadcastOptions},
+    ratelimit::RATE_LIMITED_ERROR,
+    recovery,
+    rejection::Rejection,
+    request::{BatchNotification, SendOptions},
+    session::{owning_user, user_sessions},
+    socket, standby, store, systemd,
+    types::Urgency,
     NotifierService,
 };
 
 /// Main application entry point
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Initialize tracing
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let cli = Cli::parse();
+
+    // Initialize tracing; benchmarks would mostly measure logging at the info level
+    let default_level = if matches!(cli.command, Commands::Bench { .. }) { "warn" } else { "info" };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level));
     let subscriber = FmtSubscriber::builder().with_env_filter(filter).finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let cli = Cli::parse();
-
     match cli.command {
-        Commands::Server => run_server().await?,
-        Commands::Send { title, body } => run_client(&title, &body).await?,
+        Commands::Server => run_server(cli.bus, &cli.config).await?,
+        Commands::Send {
+            title,
+            body,
+            extra_bodies,
+            body_files,
+            body_commands,
+            channel,
+            category,
+            fingerprint,
+            resolved,
+            tags,
+            hook,
+            urgency,
+            actions,
+            group_key,
+            allow_duplicate,
+            markdown,
+            image,
+            group,
+            exclude_users,
+            seat,
+            queue_for_absent,
+            replace,
+            at,
+            delay,
+            every,
+            cron,
+            follow_report,
+            dry_run,
+            yes,
+            remote,
+        } => {
+            let body = compose_body(body, extra_bodies, &body_files)?;
+            let options = SendOptions {
+                channel,
+                category,
+                fingerprint,
+                tags,
+                hook,
+                body_commands,
+                urgency,
+                actions,
+                group_key,
+                allow_duplicate,
+                markdown,
+                image: image.map(std::path::absolute).transpose()?.map(|image| image.display().to_string()),
+                group,
+                exclude_users,
+                seat,
+                detach: false,
+                replaces: replace,
+                progress: None,
+                queue_for_absent,
+            };
+            if dry_run {
+                print_preview(&cli.config, title, body, options)
+            } else if resolved {
+                run_resolve(cli.bus, &title, options).await?
+            } else if let Some(recurrence) = every.or(cron) {
+                run_schedule_recurring(cli.bus, &recurrence.to_string(), &title, &body, options).await?
+            } else if let Some(at) = schedule_time(at, delay)? {
+                run_schedule(cli.bus, at, &title, &body, options).await?
+            } else if remote.is_remote() {
+                if !yes {
+                    confirm_fleet(&cli.config, &remote)?;
+                }
+                run_fleet(cli.bus, &title, &body, options, &remote).await?
+            } else {
+                if !yes {
+                    confirm_recipients(cli.bus, &cli.config, &options).await?;
+                }
+                match follow_report {
+                    Some(format) => run_follow_report(cli.bus, &title, &body, options, format).await?,
+                    None => run_client(cli.bus, &title, &body, options).await?,
+                }
+            }
+        }
+        Commands::SendToUser { user, title, body } => run_send_to_user(cli.bus, &user, &title, &body).await?,
+        Commands::SendBatch { file } => run_send_batch(cli.bus, &file).await?,
+        Commands::Close { broadcast_id, channel, tag, batch, remote } => {
+            if remote.is_remote() {
+                let batch = batch.ok_or("--batch is required to close notifications on remote hosts")?;
+                run_fleet_close(cli.bus, &batch, &remote).await?
+            } else {
+                run_close(cli.bus, broadcast_id, channel.as_deref(), tag.as_deref(), batch.as_deref()).await?
+            }
+        }
+        Commands::CloseAll { older_than, channel } => run_close_all(cli.bus, older_than, channel.as_deref()).await?,
+        Commands::Update { channel, args } => {
+            let (target, title, body) = parse_update_args(channel, &args)?;
+            run_update(cli.bus, target, &title, &body).await?
+        }
+        Commands::Status { ping } => run_status(cli.bus, ping).await?,
+        Commands::Doctor => run_doctor(cli.bus).await,
+        Commands::Inspect { user } => run_inspect(cli.bus, &user).await?,
+        Commands::Capabilities { uid } => run_capabilities(cli.bus, uid).await?,
+        Commands::Stats { prometheus, helper: false } => run_stats(cli.bus, prometheus).await?,
+        Commands::Stats { helper: true, .. } => run_helper_stats(cli.bus).await?,
+        Commands::History { command: Some(HistoryCommand::Export { format, since, output }), .. } => {
+            run_history_export(cli.bus, format, since, output.as_deref()).await?
+        }
+        Commands::History { tag, journal: false, .. } => run_history(cli.bus, tag.as_deref()).await?,
+        Commands::History { limit, .. } => run_journal(cli.bus, limit.unwrap_or_default()).await?,
+        Commands::Replay { broadcast_id, tag } => run_replay(cli.bus, broadcast_id, tag.as_deref()).await?,
+        Commands::ReAnnounce => run_re_announce(cli.bus).await?,
+        Commands::SessionHook { user } => run_session_hook(cli.bus, user).await?,
+        Commands::Report { broadcast_id } => run_report(cli.bus, broadcast_id).await?,
+        Commands::Poll { title, body, options } => run_poll(cli.bus, &title, &body, &options).await?,
+        Commands::PollResults { broadcast_id } => run_poll_results(cli.bus, broadcast_id).await?,
+        Commands::RouteTest { title, body, urgency, user } => {
+            run_route_test(cli.bus, &title, &body, urgency, user.as_deref()).await?
+        }
+        Commands::ExportState { output } => run_export_state(cli.bus, output.as_deref()).await?,
+        Commands::ImportState { input } => run_import_state(cli.bus, &input).await?,
+        Commands::Maintenance { state } => run_maintenance(cli.bus, state).await?,
+        Commands::Pause => run_pause(cli.bus, true).await?,
+        Commands::Resume => run_pause(cli.bus, false).await?,
+        Commands::VerifyStore { repair } => run_verify_store(cli.bus, &cli.config, repair)?,
+        Commands::Schedule { command: ScheduleCommand::List } => run_schedule_list(cli.bus).await?,
+        Commands::Schedule { command: ScheduleCommand::Cancel { id } } => run_schedule_cancel(cli.bus, id).await?,
+        Commands::Quarantine { command: QuarantineCommand::List } => run_quarantine_list(cli.bus).await?,
+        Commands::Quarantine { command: QuarantineCommand::Approve { id } } => {
+            run_quarantine_approve(cli.bus, id).await?
+        }
+        Commands::Quarantine { command: QuarantineCommand::Deny { id } } => run_quarantine_deny(cli.bus, id).await?,
+        Commands::Recurring { command: RecurringCommand::List } => run_recurring_list(cli.bus).await?,
+        Commands::Recurring { command: RecurringCommand::Remove { id } } => run_recurring_remove(cli.bus, id).await?,
+        Commands::NotifyProgress { command: ProgressCommand::Start { title, text } } => {
+            run_progress_start(cli.bus, &title, &text).await?
+        }
+        Commands::NotifyProgress { command: ProgressCommand::Update { batch_id, percent, text } } => {
+            run_progress_update(cli.bus, &batch_id, percent, &text).await?
+        }
+        Commands::Bench { users, messages } => run_bench(&cli.config, users, messages).await?,
+        #[cfg(feature = "echo-daemon")]
+        Commands::EchoDaemon { address, json } => run_echo_daemon(address, json).await?,
     }
 
     Ok(())
 }
 
+/// How often spooled notifications are checked against their delivery windows and the state is saved
+const SPOOL_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often scheduled broadcasts are checked for being due
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Run the D-Bus server
-async fn run_server() -> Result<(), Box<dyn Error>> {
-    info!("Starting in server mode...");
-    let _conn = zbus::connection::Builder::system()?
-        .name(DBUS_INTERFACE_NAME)?
-        .serve_at(DBUS_PATH, NotifierService)?
-        .build()
-        .await?;
+async fn run_server(bus: BusType, config_path: &Path) -> Result<(), Box<dyn Error>> {
+    info!(config = %config_path.display(), "Starting in server mode...");
+    let mut config = Config::load(config_path)?;
+    let socket_path = config.socket_path.clone();
+    let socket_group = match &config.socket_group {
+        Some(name) => match nix::unistd::Group::from_name(name)? {
+            Some(group) => Some(group.gid.as_raw()),
+            None => return Err(format!("Unknown socket group '{}'", name).into()),
+        },
+        None => None,
+    };
+    let warm_standby = config.warm_standby;
+    session_bus_pool().set_idle_timeout(config.session_bus_idle_timeout());
+    let service = match bus {
+        BusType::System => NotifierService::new(config),
+        BusType::Session => {
+            // Unprivileged servers keep their state in the user's home
+            if config.state_dir.is_none() {
+                config.state_dir = user_state_dir();
+            }
+            let owner = owning_user()?;
+            info!(uid = owner.uid, username = %owner.username, "Only notifying the owner of the session bus.");
+            NotifierService::new(config).with_session_owner(owner)
+        }
+    };
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    // Fed from the loops below, so systemd restarts a server whose main loop hangs
+    let mut watchdog = systemd::watchdog_interval().map(tokio::time::interval);
+    let conn = if warm_standby {
+        let conn = bus.builder()?.build().await?;
+        let queued = || {
+            info!("Another instance owns the bus name, waiting as its warm standby.");
+            sd_notify("READY=1\nSTATUS=Waiting as warm standby");
+        };
+        {
+            let acquired = standby::acquire_name(&conn, DBUS_INTERFACE_NAME, queued);
+            tokio::pin!(acquired);
+            loop {
+                tokio::select! {
+                    acquired = &mut acquired => break acquired?,
+                    _ = terminate.recv() => {
+                        info!("Received SIGTERM while in standby, exiting.");
+                        sd_notify("STOPPING=1");
+                        return Ok(());
+                    }
+                    _ = watchdog_tick(&mut watchdog) => sd_notify("WATCHDOG=1"),
+                }
+            }
+        }
+        // Restore only now, as the previous owner kept saving its state while this instance waited
+        restore_state(&service).await;
+        conn.object_server().at(DBUS_PATH, service.clone()).await?;
+        conn
+    } else {
+        restore_state(&service).await;
+        bus.builder()?
+            .name(DBUS_INTERFACE_NAME)?
+            .serve_at(DBUS_PATH, service.clone())?
+            .build()
+            .await?
+    };
+    service.set_connection(conn.clone());
+    if bus == BusType::System {
+        service.set_system_bus(conn.clone());
+    }
+
+    info!("Notifier service is up and listening on the {} bus.", bus);
+
+    if let Some(path) = socket_path {
+        let listener = socket::bind(&path, socket_group)
+            .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+        info!(path = %path.display(), "Accepting broadcasts on the Unix socket.");
+        let service = service.clone();
+        tokio::spawn(async move { service.serve_socket(listener).await });
+    }
+
+    // Follow logind so broadcasts neither wait for enumeration nor miss users who just logged in
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.track_sessions().await }
+    });
+
+    sd_notify(&format!("READY=1\nSTATUS={}", systemd::status_line(service.sessions().len(), service.spool().len())));
+
+    // SIGUSR1 re-announces the latest critical broadcast, e.g. from a shift change hook
+    let mut reannounce = signal(SignalKind::user_defined1())?;
+    let mut interval = tokio::time::interval(SPOOL_FLUSH_INTERVAL);
+    let mut schedule_check = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = terminate.recv() => {
+                // Save what was delivered since the last tick, so a restart can still update and close it
+                info!("Received SIGTERM, saving the server state before exiting.");
+                sd_notify("STOPPING=1");
+                service.save_state();
+                return Ok(());
+            }
+            _ = interval.tick() => {
+                session_bus_pool().evict_idle();
+                service.flush_spool().await;
+                service.save_state();
+                sd_notify(&format!("STATUS={}", systemd::status_line(service.sessions().len(), service.spool().len())));
+            }
+            _ = watchdog_tick(&mut watchdog) => sd_notify("WATCHDOG=1"),
+            _ = schedule_check.tick() => {
+                // Sending may take a while, which must not hold up the other events
+                let service = service.clone();
+                tokio::spawn(async move { service.fire_scheduled().await });
+            }
+            _ = reannounce.recv() => {
+                info!("Received SIGUSR1, re-announcing the latest critical broadcast.");
+                let service = service.clone();
+                tokio::spawn(async move {
+                    match service.re_announce_critical().await {
+                        Ok(announced) => info!(announced, "Re-announce completed."),
+                        Err(e) => warn!("Failed to re-announce: {}", e),
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Notify systemd of the server's state, if it runs the server as a notify service
+fn sd_notify(state: &str) {
+    if let Err(e) = systemd::notify(state) {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Wait until the systemd watchdog is due to be fed, or forever if systemd does not watch the server
+async fn watchdog_tick(watchdog: &mut Option<Interval>) {
+    match watchdog {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Restore the state the server saved last, logging the outcome
+async fn restore_state(service: &NotifierService) {
+    match service.restore_state().await {
+        Ok(restored) => info!(restored, "Restored the saved server state."),
+        Err(e) => warn!("Failed to restore the saved server state: {}", e),
+    }
+}
+
+/// Describe an error of a request, spelling out why the server rejected it if it did
+fn rejected(error: zbus::Error) -> Box<dyn Error> {
+    let zbus::Error::MethodError(name, Some(description), _) = &error else {
+        return error.into();
+    };
+    if name.as_str() == RATE_LIMITED_ERROR {
+        return format!("Rate limited: {}", description).into();
+    }
+    match Rejection::from_message(description) {
+        Some(rejection) => format!("Rejected: {}", rejection).into(),
+        None => error.into(),
+    }
+}
+
+/// Connect to the server on a bus
+async fn connect(bus: BusType) -> Result<NotifierProxy<'static>, Box<dyn Error>> {
+    let connection = bus.connect().await?;
+    Ok(NotifierProxy::new(&connection).await?)
+}
+
+/// Join the body given on the command line with extra lines and the contents of files
+fn compose_body(body: String, extra_bodies: Vec<String>, body_files: &[PathBuf]) -> Result<String, Box<dyn Error>> {
+    let mut fragments = vec![body];
+    fragments.extend(extra_bodies);
+    for path in body_files {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read body file {}: {}", path.display(), e))?;
+        fragments.push(contents);
+    }
+    Ok(compose::join_fragments(fragments))
+}
+
+/// Print a notification as the server configured in `config_path` would show it
+fn print_preview(config_path: &Path, title: String, body: String, options: SendOptions) {
+    let config = Config::load(config_path).unwrap_or_else(|e| {
+        warn!("Failed to read {}, previewing with the default configuration: {}", config_path.display(), e);
+        Config::default()
+    });
+    print!("{}", render_preview(&BroadcastOptions::new(title, body, options).with_config(&config)));
+}
+
+/// Ask for confirmation before sending to more users than configured in `confirm_above_users`
+///
+/// Without a terminal to ask on, the send is refused, so scripts must pass `--yes`.
+async fn confirm_recipients(bus: BusType, config_path: &Path, options: &SendOptions) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(config_path).unwrap_or_else(|e| {
+        warn!("Failed to read {}, sending without confirmation: {}", config_path.display(), e);
+        Config::default()
+    });
+    let Some(threshold) = config.confirm_above_users else {
+        return Ok(());
+    };
+    let proxy = connect(bus).await?;
+    let recipients = match proxy.list_active_users(options.to_dict()).await {
+        Ok(users) => users.len(),
+        // Only privileged users may see who is logged in, so the others confirm every send
+        Err(zbus::Error::MethodError(name, ..)) if name.as_str().ends_with(".AccessDenied") => {
+            return confirm(&config, "confirm-recipients-unknown", &[("threshold", threshold as u64)]);
+        }
+        Err(e) => return Err(rejected(e)),
+    };
+    if recipients <= threshold {
+        return Ok(());
+    }
+    confirm(&config, "confirm-recipients", &[("recipients", recipients as u64)])
+}
+
+/// Ask for confirmation once before sending on remote hosts, if `confirm_above_users` is set
+///
+/// The hosts cannot ask themselves, having no terminal, so they are told the send is confirmed.
+fn confirm_fleet(config_path: &Path, remote: &RemoteArgs) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(config_path).unwrap_or_else(|e| {
+        warn!("Failed to read {}, sending without confirmation: {}", config_path.display(), e);
+        Config::default()
+    });
+    if config.confirm_above_users.is_none() {
+        return Ok(());
+    }
+    let hosts = remote_hosts(remote)?.len();
+    confirm(&config, "confirm-hosts", &[("hosts", hosts as u64)])
+}
 
-    info!("Notifier service is up and listening on the system bus.");
-    std::future::pending::<()>().await;
+/// Ask whether to go ahead on the terminal, refusing without one
+///
+/// The situation is described by the catalog message `id`, in the locale of the process.
+fn confirm(config: &Config, id: &str, args: &[(&str, u64)]) -> Result<(), Box<dyn Error>> {
+    let localizer = Localizer::load(config.locales_dir());
+    let locale = process_locale();
+    let situation = localizer.message_with_numbers(locale.as_deref(), id, args);
+    if !std::io::stdin().is_terminal() {
+        return Err(format!("{} {}", situation, localizer.message(locale.as_deref(), "confirm-refused")).into());
+    }
+    eprint!("{} {} ", situation, localizer.message(locale.as_deref(), "confirm-question"));
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !is_confirmed(&answer) {
+        return Err(localizer.message(locale.as_deref(), "not-sent").into());
+    }
+    Ok(())
+}
+
+/// Whether an answer to a confirmation prompt is yes
+fn is_confirmed(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Close the notifications of the channel a send would post to, printing how many were closed
+async fn run_resolve(bus: BusType, title: &str, options: SendOptions) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+    let closed = proxy.resolve(title, options.to_dict()).await.map_err(rejected)?;
+    println!("{}", closed);
     Ok(())
 }
 
 /// Run the D-Bus client
-async fn run_client(title: &str, body: &str) -> Result<(), Box<dyn Error>> {
+async fn run_client(bus: BusType, title: &str, body: &str, options: SendOptions) -> Result<(), Box<dyn Error>> {
     info!("Starting in client mode...");
-    let connection = Connection::system().await?;
-    let proxy = NotifierProxy::new(&connection).await?;
+    let proxy = connect(bus).await?;
+
+    info!("Sending notification request to the service on the {} bus...", bus);
+    let (broadcast_id, deferrals) = proxy.send_with_deferrals(title, body, options.to_dict()).await.map_err(rejected)?;
+    info!(broadcast_id, "Request sent successfully.");
+    print_lint_warnings(&proxy, broadcast_id).await;
+    for (uid, username, until, reason) in deferrals {
+        match until.as_str() {
+            "" => eprintln!("Deferred for {} (uid {}): {}", username, uid, reason),
+            until => eprintln!("Deferred for {} (uid {}) until {}: {}", username, uid, until, reason),
+        }
+    }
+    print_delivery_results(&proxy, broadcast_id).await;
+
+    // Print the id on stdout so scripts can close the broadcast later
+    println!("{}", broadcast_id);
+    Ok(())
+}
+
+/// Send a notification and print its delivery events as they happen
+async fn run_follow_report(
+    bus: BusType,
+    title: &str,
+    body: &str,
+    mut options: SendOptions,
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    let client = NotifierClient::connect(bus).await?;
+    // Subscribe before sending, so no event of the broadcast is missed
+    let mut events = client.subscribe_events().await?;
+
+    // The server replies before delivering, and a duplicate would update an earlier
+    // broadcast instead, which has no events left to follow
+    options.detach = true;
+    options.allow_duplicate = true;
+    let follow_actions = !options.actions.is_empty();
+    let (broadcast_id, _) =
+        client.proxy().send_with_deferrals(title, body, options.to_dict()).await.map_err(rejected)?;
+    info!(broadcast_id, "Request sent successfully, following its delivery.");
+
+    while let Some(event) = events.next().await {
+        if event.broadcast_id() != broadcast_id {
+            continue;
+        }
+        match format {
+            ReportFormat::Human => println!("{}", event),
+            ReportFormat::Json => println!("{}", serde_json::to_string(&event)?),
+        }
+        if event.is_completed() && !follow_actions {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Get when a notification sent with `--at` or `--in` is due, in seconds since the Unix epoch
+fn schedule_time(at: Option<NaiveDateTime>, delay: Option<Duration>) -> Result<Option<u64>, Box<dyn Error>> {
+    if let Some(at) = at {
+        return local_timestamp(at).map(Some);
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(delay.map(|delay| (now + delay).as_secs()))
+}
+
+/// Get a local time in seconds since the Unix epoch
+fn local_timestamp(time: NaiveDateTime) -> Result<u64, Box<dyn Error>> {
+    let time = time
+        .and_local_timezone(Local)
+        .earliest()
+        .ok_or_else(|| format!("{} does not exist in the local time zone", time))?;
+    Ok(u64::try_from(time.timestamp()).unwrap_or_default())
+}
+
+/// Format a time in seconds since the Unix epoch in the local time zone
+fn format_local_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0).map_or_else(
+        || timestamp.to_string(),
+        |time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+    )
+}
+
+/// Have the server send a notification later and print the schedule id
+async fn run_schedule(
+    bus: BusType,
+    at: u64,
+    title: &str,
+    body: &str,
+    options: SendOptions,
+) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let schedule_id = proxy.schedule(at, title, body, options.to_dict()).await.map_err(rejected)?;
+    eprintln!("Scheduled for {}", format_local_time(at));
+    // Print the id on stdout so scripts can cancel it later
+    println!("{}", schedule_id);
+    Ok(())
+}
+
+/// Print the notifications waiting to be sent
+async fn run_schedule_list(bus: BusType) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    for (schedule_id, at, channel, title) in proxy.list_scheduled().await? {
+        let channel = if channel.is_empty() { String::new() } else { format!("[{}]", channel) };
+        println!("{}	{}	{}	{}", schedule_id, format_local_time(at), channel, title);
+    }
+    Ok(())
+}
+
+/// Cancel a scheduled notification
+async fn run_schedule_cancel(bus: BusType, schedule_id: u64) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    proxy.cancel_scheduled(schedule_id).await?;
+    info!(schedule_id, "Cancelled scheduled notification.");
+    Ok(())
+}
+
+/// Print the broadcasts held in quarantine
+async fn run_quarantine_list(bus: BusType) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    for (quarantine_id, received_at, input, caller, title, body) in proxy.list_quarantined().await? {
+        println!("{}	{}	{}	{}	{}	{}", quarantine_id, format_local_time(received_at), input, caller, title, body);
+    }
+    Ok(())
+}
+
+/// Send a broadcast held in quarantine and print its broadcast id
+async fn run_quarantine_approve(bus: BusType, quarantine_id: u64) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let broadcast_id = proxy.approve_quarantined(quarantine_id).await.map_err(rejected)?;
+    println!("{}", broadcast_id);
+    Ok(())
+}
+
+/// Drop a broadcast held in quarantine
+async fn run_quarantine_deny(bus: BusType, quarantine_id: u64) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    proxy.deny_quarantined(quarantine_id).await?;
+    info!(quarantine_id, "Denied quarantined broadcast.");
+    Ok(())
+}
+
+/// Show a progress bar on every desktop and print the batch id to update it with
+async fn run_progress_start(bus: BusType, title: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let batch_id = proxy.start_progress(title, text).await.map_err(rejected)?;
+    println!("{}", batch_id);
+    Ok(())
+}
+
+/// Move the progress bar of a batch on every desktop
+async fn run_progress_update(bus: BusType, batch_id: &str, percent: u8, text: &str) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let updated = proxy.update_progress(batch_id, percent.into(), text).await?;
+    info!(updated, percent, "Progress update completed.");
+    Ok(())
+}
+
+/// Have the server send a notification again and again and print the schedule id
+async fn run_schedule_recurring(
+    bus: BusType,
+    rule: &str,
+    title: &str,
+    body: &str,
+    options: SendOptions,
+) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let schedule_id = proxy.schedule_recurring(rule, title, body, options.to_dict()).await.map_err(rejected)?;
+    eprintln!("Recurring {}", rule);
+    // Print the id on stdout so scripts can remove it later
+    println!("{}", schedule_id);
+    Ok(())
+}
+
+/// Print the recurring notifications
+async fn run_recurring_list(bus: BusType) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    for (schedule_id, rule, at, channel, title) in proxy.list_recurring().await? {
+        let channel = if channel.is_empty() { String::new() } else { format!("[{}]", channel) };
+        println!("{}	{}	{}	{}	{}", schedule_id, rule, format_local_time(at), channel, title);
+    }
+    Ok(())
+}
+
+/// Stop a recurring notification
+async fn run_recurring_remove(bus: BusType, schedule_id: u64) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    proxy.remove_recurring(schedule_id).await?;
+    info!(schedule_id, "Removed recurring notification.");
+    Ok(())
+}
+
+/// Send the notification on remote hosts over SSH and print the aggregated delivery report
+async fn run_fleet(
+    bus: BusType,
+    title: &str,
+    body: &str,
+    mut options: SendOptions,
+    remote: &RemoteArgs,
+) -> Result<(), Box<dyn Error>> {
+    let hosts = remote_hosts(remote)?;
+    // A replacement keeps the batch id, so the batch can still be closed or replaced by it
+    let batch_id = options.replaces.clone().unwrap_or_else(fleet::new_batch_id);
+    options.tags.push(fleet::batch_tag(&batch_id));
+
+    info!(hosts = hosts.len(), parallel = remote.parallel, %batch_id, "Sending notification on remote hosts...");
+    eprintln!("Batch id: {}", batch_id);
+    let script = fleet::remote_script(bus, title, body, &options);
+    let results = fleet::send_to_hosts(&hosts, &script, remote.parallel).await;
+    if remote.json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print!("{}", fleet::format_table(&results));
+    }
+
+    let failed = results.iter().filter(|result| !result.is_sent()).count();
+    if failed > 0 {
+        return Err(format!("Failed to notify {} of {} hosts", failed, results.len()).into());
+    }
+    Ok(())
+}
+
+/// Withdraw the broadcasts of a batch from every desktop of the remote hosts over SSH
+async fn run_fleet_close(bus: BusType, batch_id: &str, remote: &RemoteArgs) -> Result<(), Box<dyn Error>> {
+    let hosts = remote_hosts(remote)?;
+
+    info!(hosts = hosts.len(), parallel = remote.parallel, batch_id, "Closing batch on remote hosts...");
+    let script = fleet::remote_close_script(bus, batch_id);
+    let results = fleet::close_on_hosts(&hosts, &script, remote.parallel).await;
+    if remote.json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for result in &results {
+            println!("{}\t{}", result.host, result.error.as_deref().unwrap_or("closed"));
+        }
+    }
+
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    if failed > 0 {
+        return Err(format!("Failed to close the batch on {} of {} hosts", failed, results.len()).into());
+    }
+    Ok(())
+}
+
+/// Get the remote hosts given on the command line and in the hosts file
+fn remote_hosts(remote: &RemoteArgs) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut hosts = remote.hosts.clone();
+    if let Some(path) = &remote.hosts_file {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read hosts file {}: {}", path.display(), e))?;
+        hosts.extend(fleet::parse_hosts(&contents));
+    }
+    if hosts.is_empty() {
+        return Err("No remote hosts given".into());
+    }
+    Ok(hosts)
+}
+
+/// Print the warnings the server's content lints raised for a broadcast
+async fn print_lint_warnings(proxy: &NotifierProxy<'_>, broadcast_id: u64) {
+    match proxy.get_lint_warnings(broadcast_id).await {
+        Ok(warnings) => warnings.iter().for_each(|warning| eprintln!("Warning: {}", warning)),
+        Err(e) => warn!(broadcast_id, "Failed to get lint warnings: {}", e),
+    }
+}
+
+/// Print a table of the delivery outcome of a broadcast for each recipient
+async fn print_delivery_results(proxy: &NotifierProxy<'_>, broadcast_id: u64) {
+    match proxy.get_delivery_results(broadcast_id).await {
+        Ok(results) if results.is_empty() => {}
+        Ok(results) => eprint!("{}", format_delivery_table(&results)),
+        Err(e) => warn!(broadcast_id, "Failed to get delivery results: {}", e),
+    }
+}
+
+/// Format the delivery outcome of a broadcast as a table with a row per recipient
+fn format_delivery_table(results: &[DeliverySummary]) -> String {
+    let mut rows = vec![["USER".to_string(), "UID".to_string(), "STATUS".to_string(), "ERROR".to_string()]];
+    let mut results = results.to_vec();
+    results.sort();
+    rows.extend(results.into_iter().map(|(uid, username, status, error)| [username, uid.to_string(), status, error]));
+
+    let widths: Vec<usize> = (0..3)
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for (cell, width) in row.iter().zip(&widths) {
+                line.push_str(&format!("{:<width$}  ", cell, width = width));
+            }
+            line.push_str(&row[3]);
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+/// Send a notification to a single user
+async fn run_send_to_user(bus: BusType, user: &str, title: &str, body: &str) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let broadcast_id = proxy.send_to_user(user, title, body).await.map_err(rejected)?;
+    info!(broadcast_id, %user, "Request sent successfully.");
+    print_lint_warnings(&proxy, broadcast_id).await;
+    print_delivery_results(&proxy, broadcast_id).await;
+    println!("{}", broadcast_id);
+    Ok(())
+}
+
+/// Send the notifications of a batch file in one request and print their broadcast ids
+async fn run_send_batch(bus: BusType, file: &Path) -> Result<(), Box<dyn Error>> {
+    let json = std::fs::read_to_string(file)
+        .map_err(|e| format!("Failed to read batch {}: {}", file.display(), e))?;
+    let batch = BatchNotification::parse_batch(&json)
+        .map_err(|e| format!("Invalid batch {}: {}", file.display(), e))?;
+    let notifications: Vec<(&str, &str)> =
+        batch.iter().map(|notification| (notification.title.as_str(), notification.body.as_str())).collect();
+    let proxy = connect(bus).await?;
+
+    let broadcast_ids = proxy.send_batch(&notifications).await.map_err(rejected)?;
+    info!(count = broadcast_ids.len(), "Batch sent successfully.");
+    for broadcast_id in broadcast_ids {
+        println!("{}", broadcast_id);
+    }
+    Ok(())
+}
+
+/// Withdraw a broadcast, or every broadcast on a channel, from all desktops
+async fn run_close(
+    bus: BusType,
+    broadcast_id: Option<u64>,
+    channel: Option<&str>,
+    tag: Option<&str>,
+    batch: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let closed = match (broadcast_id, channel, tag, batch) {
+        (_, _, _, Some(batch)) => proxy.close_batch(batch).await?,
+        (_, _, Some(tag), None) => proxy.close_tag(tag).await?,
+        (_, Some(channel), None, None) => proxy.close_channel(channel).await?,
+        (Some(broadcast_id), None, None, None) => proxy.close_broadcast(broadcast_id).await?,
+        (None, None, None, None) => return Err("Either a broadcast id, --channel, --tag or --batch is required".into()),
+    };
+    info!(closed, "Close request completed.");
+    Ok(())
+}
+
+/// Withdraw every tracked broadcast, optionally only old ones or those on a channel, from all desktops
+async fn run_close_all(bus: BusType, older_than: Option<Duration>, channel: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let older_than_secs = older_than.map_or(0, |age| age.as_secs());
+    let closed = proxy.close_all(older_than_secs, channel.unwrap_or_default()).await?;
+    info!(closed, "Close-all request completed.");
+    Ok(())
+}
+
+/// Replace the content of a broadcast, or every broadcast on a channel, on all desktops
+async fn run_update(bus: BusType, target: BroadcastTarget, title: &str, body: &str) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let updated = match target {
+        BroadcastTarget::Channel(channel) => proxy.update_channel(&channel, title, body).await.map_err(rejected)?,
+        BroadcastTarget::Broadcast(broadcast_id) => proxy.update_broadcast(broadcast_id, title, body).await.map_err(rejected)?,
+    };
+    info!(updated, "Update request completed.");
+    Ok(())
+}
+
+/// Print the state of the running server, or only its version and uptime
+async fn run_status(bus: BusType, ping: bool) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    if ping {
+        let (version, uptime) = proxy.ping().await?;
+        println!("version: {}", version);
+        println!("uptime_secs: {}", uptime);
+        return Ok(());
+    }
+    let status: BTreeMap<_, _> = proxy.get_status().await?.into_iter().collect();
+    for (name, value) in status {
+        println!("{}: {}", name, format_status_value(&value));
+    }
+    Ok(())
+}
+
+/// Print what may keep the running server from delivering, one finding per line
+///
+/// The policies confining the server are read from its process, or from this one if
+/// the server is not running, as the policy confining a service is usually host-wide.
+async fn run_doctor(bus: BusType) {
+    let server_pid = match server_pid(bus).await {
+        Ok(pid) => {
+            println!("server: running as pid {}", pid);
+            Some(pid)
+        }
+        Err(e) => {
+            println!("server: not reachable on the {} bus ({})", bus.as_str(), e);
+            None
+        }
+    };
+    let status = server_pid.map_or_else(MacStatus::detect, MacStatus::detect_for_pid);
+    for line in status.report() {
+        println!("{}", line);
+    }
+}
+
+/// Get the process id of the server owning its name on a bus
+async fn server_pid(bus: BusType) -> zbus::Result<u32> {
+    let connection = bus.connect().await?;
+    let dbus_proxy = zbus::fdo::DBusProxy::new(&connection).await?;
+    let name = zbus::names::BusName::try_from(DBUS_INTERFACE_NAME)?;
+    Ok(dbus_proxy.get_connection_unix_process_id(name).await?)
+}
+
+/// Print what the server sees of each session of a user
+async fn run_inspect(bus: BusType, user: &str) -> Result<(), Box<dyn Error>> {
+    let sessions = user_sessions(user).await?;
+    if sessions.is_empty() {
+        return Err(format!("User {} has no sessions", user).into());
+    }
+    let proxy = connect(bus).await?;
+
+    for (index, session_id) in sessions.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        let report: BTreeMap<_, _> = proxy.inspect_session(session_id).await?.into_iter().collect();
+        for (name, value) in report {
+            println!("{}: {}", name, format_status_value(&value));
+        }
+    }
+    Ok(())
+}
+
+/// Print the capabilities of a user's notification daemon, one per line
+async fn run_capabilities(bus: BusType, uid: u32) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+    for capability in proxy.get_user_capabilities(uid).await? {
+        println!("{}", capability);
+    }
+    Ok(())
+}
+
+/// Print delivery latency statistics, or the metrics in the Prometheus text format
+async fn run_stats(bus: BusType, prometheus: bool) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    if prometheus {
+        print!("{}", proxy.get_metrics().await?);
+        return Ok(());
+    }
+    let stats: BTreeMap<_, _> = proxy.get_stats().await?.into_iter().collect();
+    for (name, value) in stats {
+        println!("{}: {}", name, format_status_value(&value));
+    }
+    Ok(())
+}
+
+/// Print how often the delivery helper ran and failed, overall and for each user
+async fn run_helper_stats(bus: BusType) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let (failures, users) = proxy.get_helper_stats().await?;
+    for (reason, count) in failures {
+        println!("failed ({}): {}", reason, count);
+    }
+    for (uid, username, spawns, failed, average_runtime_ms) in users {
+        println!("{} ({}): {} runs, {} failed, {} ms on average", username, uid, spawns, failed, average_runtime_ms);
+    }
+    Ok(())
+}
+
+/// Print the tracked broadcasts, optionally only those carrying a tag
+async fn run_history(bus: BusType, tag: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    for (broadcast_id, channel, tags, title) in proxy.list_broadcasts(tag.unwrap_or_default()).await? {
+        let mut labels: Vec<String> = tags.iter().map(|tag| format!("#{}", tag)).collect();
+        if !channel.is_empty() {
+            labels.insert(0, format!("[{}]", channel));
+        }
+        println!("{}\t{}\t{}", broadcast_id, labels.join(" "), title);
+        for warning in proxy.get_lint_warnings(broadcast_id).await.unwrap_or_default() {
+            println!("\t\twarning: {}", warning);
+        }
+    }
+    Ok(())
+}
+
+/// List the dispatches recorded in the server's journal, with their outcome for each user
+async fn run_journal(bus: BusType, limit: u32) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    for (timestamp, broadcast_id, sender, title_hash, outcomes) in proxy.get_history(limit).await? {
+        let time = chrono::DateTime::from_timestamp(timestamp as i64, 0)
+            .map_or_else(|| timestamp.to_string(), |time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+        let sender = if sender.is_empty() { "-" } else { sender.as_str() };
+        println!("{}\t{}\t{}\t{}", time, broadcast_id, sender, title_hash);
+        for (uid, username, status) in outcomes {
+            println!("\t\t{} ({}): {}", username, uid, status);
+        }
+    }
+    Ok(())
+}
+
+/// Export the dispatches recorded in the server's journal, optionally only those since a local time
+async fn run_history_export(
+    bus: BusType,
+    format: ExportFormat,
+    since: Option<NaiveDateTime>,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let since = since.map(local_timestamp).transpose()?.unwrap_or_default();
+    let proxy = connect(bus).await?;
+
+    let mut entries = proxy.get_history(0).await?;
+    entries.retain(|(timestamp, ..)| *timestamp >= since);
+    let export = match format {
+        ExportFormat::Csv => format_history_csv(&entries),
+    };
+    match output {
+        Some(path) => {
+            std::fs::write(path, export)?;
+            info!(path = %path.display(), exported = entries.len(), "History exported.");
+        }
+        None => print!("{}", export),
+    }
+    Ok(())
+}
+
+/// Format journal entries as CSV, one row per recipient, or one without a recipient if there were none
+///
+/// Times are in UTC as RFC 3339, which spreadsheets recognize.
+fn format_history_csv(entries: &[HistoryEntry]) -> String {
+    let mut csv = String::from("time,broadcast_id,sender,title_hash,uid,username,status\r\n");
+    for (timestamp, broadcast_id, sender, title_hash, outcomes) in entries {
+        let time = chrono::DateTime::from_timestamp(*timestamp as i64, 0)
+            .map_or_else(|| timestamp.to_string(), |time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+        let dispatch = [time, broadcast_id.to_string(), sender.clone(), title_hash.clone()];
+        let recipients: Vec<[String; 3]> = if outcomes.is_empty() {
+            vec![Default::default()]
+        } else {
+            outcomes
+                .iter()
+                .map(|(uid, username, status)| [uid.to_string(), username.clone(), status.clone()])
+                .collect()
+        };
+        for recipient in recipients {
+            let row: Vec<String> = dispatch.iter().chain(&recipient).map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push_str("\r\n");
+        }
+    }
+    csv
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Send a broadcast, or every broadcast carrying a tag, again to users not showing it
+async fn run_replay(bus: BusType, broadcast_id: Option<u64>, tag: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let replayed = match (broadcast_id, tag) {
+        (_, Some(tag)) => proxy.replay_tag(tag).await?,
+        (Some(broadcast_id), None) => proxy.replay_broadcast(broadcast_id).await?,
+        (None, None) => return Err("Either a broadcast id or --tag is required".into()),
+    };
+    info!(replayed, "Replay request completed.");
+    Ok(())
+}
+
+/// Send the latest critical broadcast again to every user
+async fn run_re_announce(bus: BusType) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let announced = proxy.re_announce().await?;
+    info!(announced, "Re-announce request completed.");
+    Ok(())
+}
+
+/// Get the user a session hook reports as logged in, if it was run for a session opening
+///
+/// Under pam_exec, `PAM_TYPE` tells which PAM event the hook runs for and `PAM_USER` who it is about.
+fn session_hook_user(
+    user: Option<String>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if env("PAM_TYPE").is_some_and(|pam_type| pam_type != "open_session") {
+        return Ok(None);
+    }
+    match user.or_else(|| env("PAM_USER")) {
+        Some(user) => Ok(Some(user)),
+        None => Err("No user given and PAM_USER is not set".into()),
+    }
+}
+
+/// Tell the server a user's session is ready
+async fn run_session_hook(bus: BusType, user: Option<String>) -> Result<(), Box<dyn Error>> {
+    let Some(user) = session_hook_user(user, |name| std::env::var(name).ok())? else {
+        return Ok(());
+    };
+    let proxy = connect(bus).await?;
+
+    let waiting = proxy.session_ready(&user).await?;
+    info!(%user, waiting, "Reported the session ready.");
+    Ok(())
+}
+
+/// Print the delivery outcome of a broadcast for each recipient
+async fn run_report(bus: BusType, broadcast_id: u64) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let mut report = proxy.get_delivery_report(broadcast_id).await?;
+    report.sort();
+    for (uid, username, status) in report {
+        println!("{}({}): {}", username, uid, status);
+    }
+    Ok(())
+}
+
+/// Send a poll and print the broadcast id its results can be queried with
+async fn run_poll(bus: BusType, title: &str, body: &str, options: &[String]) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let broadcast_id = proxy.send_poll(title, body, options).await.map_err(rejected)?;
+    info!(broadcast_id, "Poll sent.");
+    println!("{}", broadcast_id);
+    Ok(())
+}
+
+/// Print the answer counts of a poll followed by each user's answer
+async fn run_poll_results(bus: BusType, broadcast_id: u64) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let (tally, mut responses) = proxy.get_poll_results(broadcast_id).await?;
+    for (option, count) in tally {
+        println!("{}: {}", option, count);
+    }
+    responses.sort();
+    for (uid, username, answer) in responses {
+        println!("{}({}): {}", username, uid, answer);
+    }
+    Ok(())
+}
+
+/// Print how a broadcast would be routed to each user
+async fn run_route_test(
+    bus: BusType,
+    title: &str,
+    body: &str,
+    urgency: Option<Urgency>,
+    user: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let urgency = urgency.map(Urgency::as_str).unwrap_or_default();
+    let (urgency, routes) = proxy.route_test(title, body, urgency, user.unwrap_or_default()).await?;
+    println!("urgency: {}", urgency);
+    for (uid, username, decision, backend) in routes {
+        println!("{}({}): {} via {}", username, uid, decision, backend);
+    }
+    Ok(())
+}
+
+/// Write the server state to a file, or standard output
+async fn run_export_state(bus: BusType, output: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    let archive = proxy.export_state().await?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, archive)?;
+            info!(path = %path.display(), "State exported.");
+        }
+        None => println!("{}", archive),
+    }
+    Ok(())
+}
+
+/// Restore the server state from an archive file
+async fn run_import_state(bus: BusType, input: &Path) -> Result<(), Box<dyn Error>> {
+    let archive = std::fs::read_to_string(input)
+        .map_err(|e| format!("Failed to read archive {}: {}", input.display(), e))?;
+    let proxy = connect(bus).await?;
+
+    let imported = proxy.import_state(&archive).await?;
+    info!(imported, "State imported.");
+    Ok(())
+}
+
+/// Switch maintenance mode, or print whether it is on
+async fn run_maintenance(bus: BusType, state: Option<Switch>) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
+
+    match state {
+        Some(state) => {
+            proxy.set_maintenance(state.is_on()).await?;
+            info!(enabled = state.is_on(), "Maintenance mode switched.");
+        }
+        None => {
+            let enabled = proxy.maintenance().await?;
+            println!("{}", if enabled { "on" } else { "off" });
+        }
+    }
+    Ok(())
+}
 
-    info!("Sending notification request to the system service...");
-    proxy.send_to_all(title, body).await?;
-    info!("Request sent successfully.");
+/// Pause or resume all delivery
+async fn run_pause(bus: BusType, paused: bool) -> Result<(), Box<dyn Error>> {
+    let proxy = connect(bus).await?;
 
+    if paused {
+        proxy.pause_delivery().await?;
+        info!("Delivery paused.");
+    } else {
+        proxy.resume_delivery().await?;
+        info!("Delivery resumed.");
+    }
     Ok(())
 }
 
+/// Measure the server pipeline with made-up sessions and print a summary
+async fn run_bench(config_path: &Path, users: u32, messages: u32) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(config_path)?;
+    eprintln!("Sending {} broadcasts to {} made-up users...", messages, users);
+    println!("{}", bench::run(config, users, messages).await?);
+    Ok(())
+}
+
+/// Serve a notification daemon printing what it receives until interrupted
+#[cfg(feature = "echo-daemon")]
+async fn run_echo_daemon(address: Option<String>, json: bool) -> Result<(), Box<dyn Error>> {
+    use dots_notifier::echo::{EchoDaemon, PrivateBus};
+
+    let private_bus = match address {
+        Some(_) => None,
+        None => Some(PrivateBus::start().await.map_err(|e| format!("Failed to start dbus-daemon: {}", e))?),
+    };
+    let address = address.or_else(|| private_bus.as_ref().map(|bus| bus.address.clone())).unwrap_or_default();
+    let connection = zbus::connection::Builder::address(address.as_str())?.build().await?;
+    let (daemon, mut events) = EchoDaemon::new();
+    daemon.serve(&connection).await?;
+    eprintln!("DBUS_SESSION_BUS_ADDRESS={}", address);
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            Some(event) = events.recv() => {
+                if json {
+                    println!("{}", serde_json::to_string(&event)?);
+                } else {
+                    println!("{}", event);
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+    Ok(())
+}
+
+/// Check the state saved by the server, optionally fixing the problems found
+fn run_verify_store(bus: BusType, config_path: &Path, repair: bool) -> Result<(), Box<dyn Error>> {
+    let mut config = Config::load(config_path)?;
+    if bus == BusType::Session && config.state_dir.is_none() {
+        config.state_dir = user_state_dir();
+    }
+    let store = store::open(config.store, config.state_dir())
+        .map_err(|e| format!("Failed to open the {:?} store: {}", config.store, e))?;
+
+    let mut problems = store.verify().map_err(|e| format!("Failed to verify the store: {}", e))?;
+    let snapshot = store.load().unwrap_or_else(|e| {
+        problems.push(format!("the saved snapshot cannot be read: {}", e));
+        None
+    });
+    if let Some(snapshot) = &snapshot {
+        problems.extend(recovery::check(snapshot).iter().map(ToString::to_string));
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let mut snapshot = match snapshot {
+        Some(snapshot) if problems.is_empty() => {
+            println!(
+                "{} pending notifications and {} broadcasts are consistent.",
+                snapshot.spool.len(),
+                snapshot.broadcasts.len()
+            );
+            return Ok(());
+        }
+        None if problems.is_empty() => {
+            println!("No saved state.");
+            return Ok(());
+        }
+        Some(snapshot) if repair => snapshot,
+        Some(_) => return Err(format!("Found {} problems, run with --repair to fix them", problems.len()).into()),
+        None => return Err(format!("Found {} problems", problems.len()).into()),
+    };
+    recovery::repair(&mut snapshot);
+    store.save(&snapshot).map_err(|e| format!("Failed to save the repaired state: {}", e))?;
+    println!("Repaired the saved state.");
+    Ok(())
+}
+
+/// Format a status value without the type annotations D-Bus text formatting adds
+fn format_status_value(value: &Value<'_>) -> String {
+    match value {
+        Value::U64(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Str(s) => s.to_string(),
+        Value::Array(items) => items.iter().map(format_status_value).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[tokio::test]
@@ -62,7 +1305,85 @@ mod tests {
         // We can't easily test the actual main function due to its side effects
     }
 
-    // Note: run_server() and run_client() require actual D-Bus connections
-    // and are tested in integration tests
+    #[test]
+    fn test_compose_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("du");
+        std::fs::write(&path, "12G\t/var/log\n").unwrap();
+        let body = super::compose_body("Disk is filling up".to_string(), vec!["Check logs".to_string()], &[path]);
+        assert_eq!(body.unwrap(), "Disk is filling up\nCheck logs\n12G\t/var/log");
+        assert!(super::compose_body(String::new(), vec![], &[dir.path().join("missing")]).is_err());
+    }
+
+    #[test]
+    fn test_is_confirmed() {
+        assert!(super::is_confirmed("y\n"));
+        assert!(super::is_confirmed(" Yes\n"));
+        assert!(!super::is_confirmed("\n"));
+        assert!(!super::is_confirmed("no\n"));
+    }
+
+    #[test]
+    fn test_format_delivery_table() {
+        let results = vec![
+            (1001, "bob".to_string(), "failed (no notification daemon)".to_string(), "no owner".to_string()),
+            (1000, "alice".to_string(), "delivered".to_string(), String::new()),
+        ];
+        assert_eq!(
+            super::format_delivery_table(&results),
+            "USER   UID   STATUS                           ERROR\n\
+             alice  1000  delivered\n\
+             bob    1001  failed (no notification daemon)  no owner\n"
+        );
+    }
+
+    #[test]
+    fn test_format_history_csv() {
+        let entries = vec![
+            (
+                1_717_232_400,
+                7,
+                "ops, night shift".to_string(),
+                "a1b2c3d4e5f60718".to_string(),
+                vec![
+                    (1000, "alice".to_string(), "delivered".to_string()),
+                    (1001, "bob".to_string(), "failed (no notification daemon)".to_string()),
+                ],
+            ),
+            (1_717_236_000, 8, String::new(), "0123456789abcdef".to_string(), vec![]),
+        ];
+        assert_eq!(
+            super::format_history_csv(&entries),
+            "time,broadcast_id,sender,title_hash,uid,username,status\r\n\
+             2024-06-01T09:00:00Z,7,\"ops, night shift\",a1b2c3d4e5f60718,1000,alice,delivered\r\n\
+             2024-06-01T09:00:00Z,7,\"ops, night shift\",a1b2c3d4e5f60718,1001,bob,failed (no notification daemon)\r\n\
+             2024-06-01T10:00:00Z,8,,0123456789abcdef,,,\r\n"
+        );
+        assert_eq!(super::csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_session_hook_user() {
+        let pam = |pam_type: &'static str| {
+            move |name: &str| match name {
+                "PAM_TYPE" => Some(pam_type.to_string()),
+                "PAM_USER" => Some("alice".to_string()),
+                _ => None,
+            }
+        };
+        assert_eq!(super::session_hook_user(None, pam("open_session")).unwrap(), Some("alice".to_string()));
+        assert_eq!(super::session_hook_user(None, pam("close_session")).unwrap(), None);
+        assert_eq!(super::session_hook_user(Some("bob".to_string()), |_| None).unwrap(), Some("bob".to_string()));
+        assert!(super::session_hook_user(None, |_| None).is_err());
+    }
+
+    // Note: run_server(), run_client(), run_send_to_user(), run_send_batch(), run_close(), run_close_all(),
+    // run_update(), run_status(), run_inspect(), run_capabilities(), run_stats(), run_helper_stats(), run_history(),
+    // run_history_export(), run_journal(), run_replay(), run_re_announce(), run_session_hook(), run_report(),
+    // run_poll(), run_poll_results(), run_route_test(), run_export_state(), run_import_state(), run_maintenance(),
+    // run_pause(), run_quarantine_list(), run_quarantine_approve(), run_quarantine_deny(), run_progress_start() and
+    // run_progress_update() require actual D-Bus connections and are tested in integration tests; run_fleet() and
+    // run_fleet_close() require SSH access to remote hosts; run_verify_store() only wraps the store and recovery
+    // checks tested in their modules; run_echo_daemon() only wraps the echo daemon tested in its module
 }
 
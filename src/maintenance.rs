@@ -0,0 +1,108 @@
+//! Maintenance mode and paused delivery
+//!
+//! While in maintenance mode only critical broadcasts are delivered; everything
+//! else waits in the spool until the mode is switched off. While delivery is
+//! paused, e.g. during an exam in a computer lab, even critical broadcasts wait.
+//! Both are kept as marker files in the state directory so they survive restarts
+//! of the server.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::warn;
+
+/// Default directory holding state persisted across restarts
+pub const DEFAULT_STATE_DIR: &str = "/var/lib/dots-notifier";
+
+/// Name of the marker file present while maintenance mode is on
+pub const MAINTENANCE_FILE: &str = "maintenance";
+
+/// Name of the marker file present while delivery is paused
+pub const PAUSED_FILE: &str = "paused";
+
+/// Get the state directory of a server running as an unprivileged user
+///
+/// This is `$XDG_STATE_HOME/dots-notifier`, or `~/.local/state/dots-notifier`.
+pub fn user_state_dir() -> Option<PathBuf> {
+    let non_empty = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    non_empty("XDG_STATE_HOME")
+        .or_else(|| non_empty("HOME").map(|home| home.join(".local/state")))
+        .map(|state_home| state_home.join("dots-notifier"))
+}
+
+/// A mode of the server switched on or off, such as maintenance mode, optionally persisted to disk
+#[derive(Debug, Default)]
+pub struct PersistedSwitch {
+    marker: Option<PathBuf>,
+    enabled: AtomicBool,
+}
+
+impl PersistedSwitch {
+    /// Load the mode persisted as the marker file `name` in a state directory
+    pub fn load(state_dir: &Path, name: &str) -> Self {
+        let marker = state_dir.join(name);
+        let enabled = marker.exists();
+        Self {
+            marker: Some(marker),
+            enabled: AtomicBool::new(enabled),
+        }
+    }
+
+    /// Whether the mode is on
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Switch the mode on or off, persisting the change
+    ///
+    /// The mode is changed even if it cannot be persisted; the error is returned
+    /// so the caller can report that it will not survive a restart.
+    pub fn set(&self, enabled: bool) -> io::Result<()> {
+        self.enabled.store(enabled, Ordering::SeqCst);
+        let Some(marker) = &self.marker else {
+            return Ok(());
+        };
+        let result = if enabled {
+            marker
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::write(marker, ""))
+        } else {
+            match std::fs::remove_file(marker) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        };
+        result.inspect_err(|e| warn!(marker = %marker.display(), "Failed to persist mode: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_memory_mode() {
+        let mode = PersistedSwitch::default();
+        assert!(!mode.is_enabled());
+        mode.set(true).unwrap();
+        assert!(mode.is_enabled());
+    }
+
+    #[test]
+    fn test_mode_survives_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = dir.path().join("state");
+        let mode = PersistedSwitch::load(&state_dir, MAINTENANCE_FILE);
+        assert!(!mode.is_enabled());
+
+        mode.set(true).unwrap();
+        assert!(PersistedSwitch::load(&state_dir, MAINTENANCE_FILE).is_enabled());
+        assert!(!PersistedSwitch::load(&state_dir, PAUSED_FILE).is_enabled());
+
+        mode.set(false).unwrap();
+        mode.set(false).unwrap();
+        assert!(!PersistedSwitch::load(&state_dir, MAINTENANCE_FILE).is_enabled());
+    }
+}
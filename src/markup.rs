@@ -0,0 +1,195 @@
+//! Rendering of Markdown bodies
+//!
+//! Bodies sent as Markdown support a small subset: `**bold**` or `__bold__`,
+//! `*italic*` or `_italic_`, and `[links](https://example.com)`, with a backslash
+//! escaping the character after it. Daemons advertising the `body-markup`
+//! capability get the Pango-style markup of the specification, with everything
+//! else escaped; others get plain text with the syntax removed and link targets
+//! spelled out.
+//!
+//! Markup in bodies sent as is can be stripped for daemons that would show the
+//! tags literally.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Tags of the markup subset of the specification
+static MARKUP_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)</?(b|i|u|a|img|br)\b[^>]*>").unwrap());
+
+/// Image tags, rendered only by daemons advertising the `body-images` capability
+static IMAGE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<img\b[^>]*>").unwrap());
+
+/// Render a Markdown body as markup if the daemon renders it, or as plain text otherwise
+pub fn render_markdown(text: &str, markup: bool) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    render_into(&mut rendered, &text.chars().collect::<Vec<_>>(), markup);
+    rendered
+}
+
+/// Remove the tags of the markup subset from a body, keeping their content
+pub fn strip_markup(body: &str) -> String {
+    MARKUP_TAG.replace_all(body, "").into_owned()
+}
+
+/// Remove image tags from a body
+pub fn strip_images(body: &str) -> String {
+    IMAGE_TAG.replace_all(body, "").into_owned()
+}
+
+/// Render a span of a Markdown body into `out`
+fn render_into(out: &mut String, text: &[char], markup: bool) {
+    let mut i = 0;
+    while i < text.len() {
+        let c = text[i];
+        if c == '\\' && i + 1 < text.len() && text[i + 1].is_ascii_punctuation() {
+            push_text(out, text[i + 1], markup);
+            i += 2;
+        } else if c == '[' {
+            match link_at(text, i) {
+                Some((label, target, end)) => {
+                    push_link(out, label, &target.iter().collect::<String>(), markup);
+                    i = end;
+                }
+                None => {
+                    push_text(out, c, markup);
+                    i += 1;
+                }
+            }
+        } else if let Some((inner, tag, end)) = emphasis_at(text, i) {
+            if markup {
+                out.push_str(&format!("<{}>", tag));
+                render_into(out, inner, markup);
+                out.push_str(&format!("</{}>", tag));
+            } else {
+                render_into(out, inner, markup);
+            }
+            i = end;
+        } else {
+            push_text(out, c, markup);
+            i += 1;
+        }
+    }
+}
+
+/// Append a character, escaped if the output is markup
+fn push_text(out: &mut String, c: char, markup: bool) {
+    match c {
+        '&' if markup => out.push_str("&amp;"),
+        '<' if markup => out.push_str("&lt;"),
+        '>' if markup => out.push_str("&gt;"),
+        '"' if markup => out.push_str("&quot;"),
+        c => out.push(c),
+    }
+}
+
+/// Append a link, spelling out its target in plain text unless it is the label itself
+fn push_link(out: &mut String, label: &[char], target: &str, markup: bool) {
+    if markup {
+        out.push_str("<a href=\"");
+        target.chars().for_each(|c| push_text(out, c, true));
+        out.push_str("\">");
+        render_into(out, label, true);
+        out.push_str("</a>");
+    } else {
+        let start = out.len();
+        render_into(out, label, false);
+        if out[start..] != *target {
+            out.push_str(&format!(" ({})", target));
+        }
+    }
+}
+
+/// Find a link `[label](target)` starting at `start`, returning its label, target and end
+fn link_at(text: &[char], start: usize) -> Option<(&[char], &[char], usize)> {
+    let label_end = start + 1 + text[start + 1..].iter().position(|&c| c == ']')?;
+    if text.get(label_end + 1) != Some(&'(') {
+        return None;
+    }
+    let target_start = label_end + 2;
+    let target_end = target_start + text[target_start..].iter().position(|&c| c == ')')?;
+    let target = &text[target_start..target_end];
+    if label_end == start + 1 || target.is_empty() || target.iter().any(|c| c.is_whitespace()) {
+        return None;
+    }
+    Some((&text[start + 1..label_end], target, target_end + 1))
+}
+
+/// Find emphasis starting at `start`, returning its content, the markup tag and its end
+///
+/// Emphasis must hug its content, so `2 * 3 * 4` stays as it is, and underscores
+/// only count at word boundaries, so `snake_case_names` do too.
+fn emphasis_at(text: &[char], start: usize) -> Option<(&[char], &'static str, usize)> {
+    let delimiter = text[start];
+    if delimiter != '*' && delimiter != '_' {
+        return None;
+    }
+    let is_word = |index: Option<usize>| index.and_then(|i| text.get(i)).is_some_and(|c| c.is_alphanumeric());
+    if delimiter == '_' && is_word(start.checked_sub(1)) {
+        return None;
+    }
+    let (width, tag) = match text.get(start + 1) {
+        Some(&c) if c == delimiter => (2, "b"),
+        _ => (1, "i"),
+    };
+    let content_start = start + width;
+    if text.get(content_start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+    let mut i = content_start + 1;
+    while i + width <= text.len() {
+        let closes = text[i..i + width].iter().all(|&c| c == delimiter)
+            && !text[i - 1].is_whitespace()
+            && text[i - 1] != delimiter
+            && text.get(i + width) != Some(&delimiter)
+            && !(delimiter == '_' && is_word(Some(i + width)));
+        if closes {
+            return Some((&text[content_start..i], tag, i + width));
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_to_markup() {
+        assert_eq!(render_markdown("**Reboot** at *noon*", true), "<b>Reboot</b> at <i>noon</i>");
+        assert_eq!(render_markdown("__bold__ and _italic_", true), "<b>bold</b> and <i>italic</i>");
+        assert_eq!(
+            render_markdown("See [the *wiki*](https://wiki.example.com/a?b=1&c=2)", true),
+            "See <a href=\"https://wiki.example.com/a?b=1&amp;c=2\">the <i>wiki</i></a>"
+        );
+        assert_eq!(render_markdown("a < b & c > d", true), "a &lt; b &amp; c &gt; d");
+    }
+
+    #[test]
+    fn test_markdown_to_plain_text() {
+        assert_eq!(render_markdown("**Reboot** at *noon*", false), "Reboot at noon");
+        assert_eq!(
+            render_markdown("See [the wiki](https://wiki.example.com)", false),
+            "See the wiki (https://wiki.example.com)"
+        );
+        assert_eq!(render_markdown("[https://example.com](https://example.com)", false), "https://example.com");
+        assert_eq!(render_markdown("a < b", false), "a < b");
+    }
+
+    #[test]
+    fn test_literal_text_kept() {
+        assert_eq!(render_markdown("2 * 3 * 4", true), "2 * 3 * 4");
+        assert_eq!(render_markdown("restart snake_case_service now", true), "restart snake_case_service now");
+        assert_eq!(render_markdown("\\*not italic\\*", true), "*not italic*");
+        assert_eq!(render_markdown("[not a link] (here)", false), "[not a link] (here)");
+        assert_eq!(render_markdown("**unclosed", true), "**unclosed");
+    }
+
+    #[test]
+    fn test_strip_markup() {
+        let body = "<b>Disk</b> full <img src=\"/usr/share/icons/disk.png\" alt=\"disk\"/><br>";
+        assert_eq!(strip_markup(body), "Disk full ");
+        assert_eq!(strip_images(body), "<b>Disk</b> full <br>");
+    }
+}
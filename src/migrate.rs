@@ -0,0 +1,173 @@
+//! Migration of saved state to the current archive format
+//!
+//! Snapshots in the store and exported archives carry the version of the
+//! format they were written in. When the format changes, [`ARCHIVE_VERSION`]
+//! is bumped and a [`Migration`] upgrading the previous version is added to
+//! [`MIGRATIONS`], so state saved by any earlier release is read by upgrading
+//! it one version at a time. Stores keep a copy of the state as it was before
+//! migrating it, see [`backup_path`].
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::archive::ARCHIVE_VERSION;
+
+/// An upgrade of saved state from one archive version to the next
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// Version upgraded from, to the one after it
+    pub from: u32,
+    /// What the upgrade changes, shown when it fails
+    pub description: &'static str,
+    /// Rewrite the fields of an archive in version `from`
+    pub apply: fn(&mut Map<String, Value>) -> Result<(), String>,
+}
+
+/// Upgrades from each earlier archive version, in order
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// Saved state that cannot be upgraded to the current archive format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationError {
+    /// The state is not an object with a version
+    MissingVersion,
+    /// The state was written by a newer release
+    Unsupported { version: u32 },
+    /// No upgrade from a version is known
+    MissingStep { version: u32 },
+    /// An upgrade failed
+    Failed {
+        from: u32,
+        description: &'static str,
+        reason: String,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::MissingVersion => write!(f, "Saved state has no archive version"),
+            MigrationError::Unsupported { version } => write!(
+                f,
+                "Unsupported archive version {} (this build reads up to version {})",
+                version, ARCHIVE_VERSION
+            ),
+            MigrationError::MissingStep { version } => {
+                write!(f, "No migration from archive version {} is known", version)
+            }
+            MigrationError::Failed { from, description, reason } => write!(
+                f,
+                "Failed to migrate saved state from archive version {} to {} ({}): {}",
+                from,
+                from + 1,
+                description,
+                reason
+            ),
+        }
+    }
+}
+
+impl Error for MigrationError {}
+
+/// Get the archive version saved state was written in
+pub fn archive_version(state: &Value) -> Result<u32, MigrationError> {
+    state
+        .get("version")
+        .and_then(Value::as_u64)
+        .and_then(|version| u32::try_from(version).ok())
+        .ok_or(MigrationError::MissingVersion)
+}
+
+/// Upgrade saved state to the current archive version, returning the version it was in
+pub fn migrate(state: &mut Value) -> Result<u32, MigrationError> {
+    migrate_with(state, MIGRATIONS, ARCHIVE_VERSION)
+}
+
+/// Upgrade saved state to version `target` with `migrations`, returning the version it was in
+fn migrate_with(state: &mut Value, migrations: &[Migration], target: u32) -> Result<u32, MigrationError> {
+    let original = archive_version(state)?;
+    if original > target {
+        return Err(MigrationError::Unsupported { version: original });
+    }
+    let fields = state.as_object_mut().ok_or(MigrationError::MissingVersion)?;
+    for version in original..target {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or(MigrationError::MissingStep { version })?;
+        (migration.apply)(fields).map_err(|reason| MigrationError::Failed {
+            from: version,
+            description: migration.description,
+            reason,
+        })?;
+        fields.insert("version".to_string(), Value::from(version + 1));
+    }
+    Ok(original)
+}
+
+/// Get where a store keeps its state as it was in archive version `version` before migrating it
+pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn rename_spool(fields: &mut Map<String, Value>) -> Result<(), String> {
+        let pending = fields.remove("pending").ok_or("no pending notifications")?;
+        fields.insert("spool".to_string(), pending);
+        Ok(())
+    }
+
+    fn add_paused(fields: &mut Map<String, Value>) -> Result<(), String> {
+        fields.insert("paused".to_string(), Value::from(false));
+        Ok(())
+    }
+
+    const STEPS: &[Migration] = &[
+        Migration { from: 1, description: "rename pending to spool", apply: rename_spool },
+        Migration { from: 2, description: "add paused", apply: add_paused },
+    ];
+
+    #[test]
+    fn test_migrated_step_by_step() {
+        let mut state = json!({"version": 1, "pending": []});
+        assert_eq!(migrate_with(&mut state, STEPS, 3), Ok(1));
+        assert_eq!(state, json!({"version": 3, "spool": [], "paused": false}));
+
+        // Current state is left alone
+        assert_eq!(migrate_with(&mut state, STEPS, 3), Ok(3));
+        assert_eq!(state["version"], 3);
+    }
+
+    #[test]
+    fn test_migration_failures_explained() {
+        let mut state = json!({"version": 1, "spool": []});
+        let error = migrate_with(&mut state, STEPS, 3).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to migrate saved state from archive version 1 to 2 (rename pending to spool): \
+             no pending notifications"
+        );
+
+        let mut state = json!({"version": 0});
+        assert_eq!(migrate_with(&mut state, STEPS, 3), Err(MigrationError::MissingStep { version: 0 }));
+        let mut state = json!({"version": 4});
+        assert_eq!(migrate_with(&mut state, STEPS, 3), Err(MigrationError::Unsupported { version: 4 }));
+        assert_eq!(migrate(&mut json!({"spool": []})), Err(MigrationError::MissingVersion));
+    }
+
+    #[test]
+    fn test_backup_path() {
+        let path = Path::new("/var/lib/dots-notifier/state.json");
+        assert_eq!(backup_path(path, 1), Path::new("/var/lib/dots-notifier/state.json.v1.bak"));
+    }
+}
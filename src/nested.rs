@@ -0,0 +1,111 @@
+//! Discovery of nested session buses
+//!
+//! Developers testing a compositor often run it nested in their desktop, such
+//! as sway inside sway or a window manager in Xephyr, started through
+//! `dbus-run-session` so it gets a session bus and notification daemon of its
+//! own. logind only knows the user's primary session, whose bus lives in their
+//! runtime directory; nested buses are found in the environment of the user's
+//! processes instead. Which of the buses are notified is a configured policy.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::session::user_bus_address;
+
+/// Which session buses of a user are notified
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NestedBusPolicy {
+    /// Only the primary session bus, so a nested session under test stays quiet
+    #[default]
+    Primary,
+    /// The primary session bus and every nested one
+    All,
+}
+
+/// Get the addresses of the nested session buses of a user, in a stable order
+///
+/// Reading the environment of other users' processes needs root; processes that
+/// cannot be read are skipped.
+pub fn nested_bus_addresses(uid: u32) -> Vec<String> {
+    nested_bus_addresses_in(Path::new("/proc"), uid)
+}
+
+/// Get the addresses of the nested session buses of a user from a proc filesystem at `proc_dir`
+fn nested_bus_addresses_in(proc_dir: &Path, uid: u32) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(proc_dir) else {
+        return Vec::new();
+    };
+    let mut addresses = BTreeSet::new();
+    for entry in entries.flatten() {
+        let is_process = entry.file_name().to_str().is_some_and(|name| name.bytes().all(|c| c.is_ascii_digit()));
+        if !is_process || !entry.metadata().is_ok_and(|metadata| metadata.uid() == uid) {
+            continue;
+        }
+        let Ok(environ) = fs::read(entry.path().join("environ")) else {
+            continue;
+        };
+        if let Some(address) = environ_bus_address(&environ).filter(|address| !is_primary(address, uid)) {
+            addresses.insert(address);
+        }
+    }
+    addresses.into_iter().collect()
+}
+
+/// Get the session bus address set in the NUL separated environment of a process
+fn environ_bus_address(environ: &[u8]) -> Option<String> {
+    environ
+        .split(|&c| c == 0)
+        .find_map(|variable| variable.strip_prefix(b"DBUS_SESSION_BUS_ADDRESS="))
+        .and_then(|address| String::from_utf8(address.to_vec()).ok())
+        .filter(|address| !address.is_empty())
+}
+
+/// Whether an address is the user's primary session bus, with or without further keys such as `guid`
+fn is_primary(address: &str, uid: u32) -> bool {
+    let primary = user_bus_address(uid);
+    address.strip_prefix(&primary).is_some_and(|rest| rest.is_empty() || rest.starts_with(','))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environ_bus_address() {
+        let environ = b"HOME=/home/alice\0DBUS_SESSION_BUS_ADDRESS=unix:path=/tmp/dbus-nested\0TERM=xterm\0";
+        assert_eq!(environ_bus_address(environ).as_deref(), Some("unix:path=/tmp/dbus-nested"));
+        assert_eq!(environ_bus_address(b"HOME=/home/alice\0"), None);
+        assert_eq!(environ_bus_address(b"DBUS_SESSION_BUS_ADDRESS=\0"), None);
+    }
+
+    #[test]
+    fn test_primary_bus_recognized() {
+        assert!(is_primary("unix:path=/run/user/1000/bus", 1000));
+        assert!(is_primary("unix:path=/run/user/1000/bus,guid=0123", 1000));
+        assert!(!is_primary("unix:path=/run/user/1000/bus-nested", 1000));
+        assert!(!is_primary("unix:path=/run/user/1001/bus", 1000));
+    }
+
+    #[test]
+    fn test_nested_buses_found_in_proc() {
+        let proc_dir = tempfile::tempdir().unwrap();
+        let uid = nix::unistd::getuid().as_raw();
+        let process = |pid: &str, environ: &str| {
+            fs::create_dir(proc_dir.path().join(pid)).unwrap();
+            fs::write(proc_dir.path().join(pid).join("environ"), environ.replace(';', "\0")).unwrap();
+        };
+        process("100", &format!("DBUS_SESSION_BUS_ADDRESS={};", user_bus_address(uid)));
+        process("200", "DBUS_SESSION_BUS_ADDRESS=unix:path=/tmp/dbus-sway;WAYLAND_DISPLAY=wayland-2;");
+        process("201", "DBUS_SESSION_BUS_ADDRESS=unix:path=/tmp/dbus-sway;");
+        process("300", "HOME=/home/alice;");
+        process("self", "DBUS_SESSION_BUS_ADDRESS=unix:path=/tmp/dbus-ignored;");
+
+        assert_eq!(nested_bus_addresses_in(proc_dir.path(), uid), vec!["unix:path=/tmp/dbus-sway".to_string()]);
+        assert!(nested_bus_addresses_in(proc_dir.path(), uid + 1).is_empty());
+    }
+}
@@ -1,22 +1,258 @@
 //! Notification sending functionality
 
 use std::collections::HashMap;
-use zbus::Address;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::debug;
+use zbus::{names::{BusName, OwnedUniqueName}, zvariant::Value, Address, Connection};
 
-use crate::types::TargetUser;
-use crate::dbus::NotificationsProxy;
+use crate::config::Config;
+use crate::host::HostInfo;
+use crate::request::{parse_action, SendOptions};
+use crate::session::session_bus_address;
+use crate::sound::Sound;
+use crate::types::{TargetUser, Urgency};
+use crate::dbus::{NotificationsProxy, NOTIFICATIONS_BUS_NAME};
+use crate::delivery::{DeliveryError, DeliveryErrorKind};
+use crate::error::{NotifierError, NotifierResult};
+use crate::image::{ImageData, NotificationImage};
+use crate::markup::{render_markdown, strip_images, strip_markup};
+use crate::pool::ConnectionPool;
+use crate::profile::{RenderingProfile, RenderingProfiles};
+use crate::urgency::{infer_urgency, UrgencyRule};
+
+/// Hint carrying the correlation id of a broadcast, so the notification daemon's
+/// logs can be matched with the server's
+pub const CORRELATION_ID_HINT: &str = "x-dots-notifier-correlation-id";
+
+/// Hint naming who sent a broadcast
+pub const SENDER_HINT: &str = "x-dots-notifier-sender";
+
+/// Hints asking the daemon to stack notifications with the same value under one group;
+/// GNOME and notify-osd honor the first, dunst the second
+pub const GROUP_HINTS: [&str; 2] = ["x-canonical-private-synchronous", "x-dunst-stack-tag"];
+
+/// Icon shown with notifications unless configured otherwise
+pub const DEFAULT_APP_ICON: &str = "dialog-information-symbolic";
+
+/// Expiration timeout of notifications unless configured otherwise, leaving it to the server
+pub const DEFAULT_EXPIRE_TIMEOUT: i32 = -1;
+
+/// Configured defaults of delivered notifications
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationDefaults {
+    /// Application name shown to every user instead of the localized one
+    pub app_name: Option<String>,
+    /// Icon name shown with notifications
+    pub icon: Option<String>,
+    /// Desktop entry of the application notifications are shown as, without `.desktop`
+    pub desktop_entry: Option<String>,
+    /// Expiration timeout in milliseconds; -1 leaves it to the server and 0 never expires.
+    /// Critical notifications never expire regardless.
+    pub timeout_ms: Option<i32>,
+}
+
+impl NotificationDefaults {
+    /// Get these defaults with the ones set in `overrides` taking precedence
+    pub fn merged(&self, overrides: &Self) -> Self {
+        Self {
+            app_name: overrides.app_name.clone().or_else(|| self.app_name.clone()),
+            icon: overrides.icon.clone().or_else(|| self.icon.clone()),
+            desktop_entry: overrides.desktop_entry.clone().or_else(|| self.desktop_entry.clone()),
+            timeout_ms: overrides.timeout_ms.or(self.timeout_ms),
+        }
+    }
+
+    /// Get the icon name shown with notifications
+    pub fn icon(&self) -> &str {
+        self.icon.as_deref().unwrap_or(DEFAULT_APP_ICON)
+    }
+
+    /// Get the expiration timeout of notifications
+    pub fn timeout(&self) -> i32 {
+        self.timeout_ms.unwrap_or(DEFAULT_EXPIRE_TIMEOUT)
+    }
+
+    /// Get the expiration timeout of notifications with an urgency
+    ///
+    /// Critical notifications never expire, whatever timeout is configured.
+    pub fn timeout_for(&self, urgency: Option<Urgency>) -> i32 {
+        match urgency {
+            Some(Urgency::Critical) => 0,
+            _ => self.timeout(),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Session bus connections shared by the deliveries of a batch, keyed by address
+    static SHARED_CONNECTIONS: Mutex<HashMap<String, Connection>>;
+}
+
+/// Connections to session buses kept by the server, see [`crate::pool`]
+static SESSION_BUS_POOL: LazyLock<ConnectionPool> = LazyLock::new(ConnectionPool::new);
+
+/// Get the connections to session buses kept for later deliveries
+///
+/// Pooling is off until the server sets an idle timeout, so one-shot commands
+/// do not hold on to connections.
+pub fn session_bus_pool() -> &'static ConnectionPool {
+    &SESSION_BUS_POOL
+}
+
+/// Run deliveries sharing their connections to session buses
+///
+/// Each session bus is connected to once for all notifications sent by
+/// `deliveries` in the current task, rather than once per notification.
+pub async fn with_shared_connections<F: Future>(deliveries: F) -> F::Output {
+    SHARED_CONNECTIONS.scope(Mutex::default(), deliveries).await
+}
+
+/// Connect to a user's session bus
+async fn connect_user_session_bus(user: &TargetUser) -> NotifierResult<Connection> {
+    connect_session_bus(user.uid(), &session_bus_address(user.uid())).await
+}
+
+/// Connect to a session bus of a user by its address
+///
+/// A connection shared within [`with_shared_connections`] or kept in the
+/// [`session_bus_pool`] is reused.
+async fn connect_session_bus(uid: u32, address: &str) -> NotifierResult<Connection> {
+    match reused_session_bus(address) {
+        Some(connection) => Ok(connection),
+        None => open_session_bus(uid, address).await,
+    }
+}
+
+/// Get a connection to the session bus at `address` made earlier
+fn reused_session_bus(address: &str) -> Option<Connection> {
+    let shared = SHARED_CONNECTIONS.try_with(|connections| connections.lock().unwrap().get(address).cloned());
+    match shared {
+        Ok(Some(connection)) => Some(connection),
+        _ => SESSION_BUS_POOL.get(address),
+    }
+}
+
+/// Make a new connection to a session bus of a user, keeping it for reuse
+async fn open_session_bus(uid: u32, address: &str) -> NotifierResult<Connection> {
+    let connect_error = |e| NotifierError::connect(address, e);
+    let dbus_address: Address = address.parse().map_err(connect_error)?;
+    let connection = zbus::connection::Builder::address(dbus_address)
+        .map_err(connect_error)?
+        .build()
+        .await
+        .map_err(connect_error)?;
+    // Outside a batch there is nothing to share the connection with
+    let _ = SHARED_CONNECTIONS.try_with(|connections| {
+        connections.lock().unwrap().insert(address.to_string(), connection.clone())
+    });
+    SESSION_BUS_POOL.insert(uid, address, connection.clone());
+    Ok(connection)
+}
+
+/// Stop reusing the connection to the session bus at `address`, returning whether it was reused
+fn forget_session_bus(address: &str) -> bool {
+    let shared = SHARED_CONNECTIONS.try_with(|connections| connections.lock().unwrap().remove(address).is_some());
+    let pooled = SESSION_BUS_POOL.remove(address);
+    shared.unwrap_or(false) || pooled
+}
+
+/// Connect to the notification server owning `bus_name` on a user's session bus
+async fn connect_notification_server(
+    user: &TargetUser,
+    bus_name: &str,
+) -> NotifierResult<NotificationsProxy<'static>> {
+    connect_notification_server_at(user.uid(), &session_bus_address(user.uid()), bus_name).await
+}
+
+/// Connect to the notification server owning `bus_name` on a user's session bus at `address`
+///
+/// The current owner of the name is looked up first and addressed directly, so
+/// a name owned by nobody fails explicitly and the server that answers is the
+/// one that was verified.
+async fn connect_notification_server_at(
+    uid: u32,
+    address: &str,
+    bus_name: &str,
+) -> NotifierResult<NotificationsProxy<'static>> {
+    let bus_name = parse_bus_name(bus_name)?;
+    let mut user_session_bus = connect_session_bus(uid, address).await?;
+    let mut owner = name_owner(&user_session_bus, &bus_name).await;
+    let failed = matches!(&owner, Err(e) if !matches!(e, zbus::fdo::Error::NameHasNoOwner(_)));
+    if failed && forget_session_bus(address) {
+        // A reused connection goes stale when the bus restarts, which a new one gets past
+        debug!(uid, %address, "Reconnecting to the session bus after a failed call: {:?}", owner);
+        user_session_bus = open_session_bus(uid, address).await?;
+        owner = name_owner(&user_session_bus, &bus_name).await;
+    }
+    let owner = match owner {
+        Ok(owner) => owner,
+        Err(zbus::fdo::Error::NameHasNoOwner(_)) => {
+            let message = format!("no notification daemon owns {} on the session bus", bus_name);
+            return Err(DeliveryError::new(DeliveryErrorKind::DaemonMissing, message).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let notifications_proxy = NotificationsProxy::builder(&user_session_bus)
+        .destination(owner.clone())?
+        .build()
+        .await?;
+
+    if tracing::enabled!(tracing::Level::DEBUG) {
+        let (server, vendor, version, _spec_version) = notifications_proxy.get_server_information().await?;
+        debug!(%bus_name, %owner, %server, %vendor, %version, "Resolved notification server.");
+    }
+    Ok(notifications_proxy)
+}
+
+/// Look up the unique name of the owner of `bus_name` on a session bus
+async fn name_owner(connection: &Connection, bus_name: &BusName<'_>) -> zbus::fdo::Result<OwnedUniqueName> {
+    zbus::fdo::DBusProxy::new(connection).await?.get_name_owner(bus_name.clone()).await
+}
+
+/// Notification server answering for a bus name on a user's session bus
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NotificationServerInfo {
+    /// Unique bus name of the server
+    pub owner: String,
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub spec_version: String,
+    /// Optional features the server supports, such as `actions` or `body-markup`
+    pub capabilities: Vec<String>,
+}
+
+/// Ask the notification server owning `bus_name` on a user's session bus about itself
+pub async fn inspect_notification_server(
+    user: &TargetUser,
+    bus_name: &str,
+) -> NotifierResult<NotificationServerInfo> {
+    let notifications_proxy = connect_notification_server(user, bus_name).await?;
+    let (name, vendor, version, spec_version) = notifications_proxy.get_server_information().await?;
+    Ok(NotificationServerInfo {
+        owner: notifications_proxy.inner().destination().to_string(),
+        name,
+        vendor,
+        version,
+        spec_version,
+        capabilities: notifications_proxy.get_capabilities().await?,
+    })
+}
 
 /// Send a notification to a specific user's session bus
 pub async fn send_notification_to_user(
     user: &TargetUser,
     summary: &str,
     body: &str,
-) -> Result<u32, Box<dyn std::error::Error>> {
-    let dbus_address: Address = format!("unix:path=/run/user/{}/bus", user.uid()).parse()?;
-    
-    let user_session_bus = zbus::connection::Builder::address(dbus_address)?
-        .build()
-        .await?;
+) -> NotifierResult<u32> {
+    let user_session_bus = connect_user_session_bus(user).await?;
 
     let notifications_proxy = NotificationsProxy::new(&user_session_bus).await?;
     let notification_id = notifications_proxy
@@ -34,31 +270,138 @@ pub async fn send_notification_to_user(
     Ok(notification_id)
 }
 
+/// Close a notification previously sent to a user through the server owning `bus_name`
+pub async fn close_notification_for_user(
+    user: &TargetUser,
+    bus_name: &str,
+    notification_id: u32,
+) -> NotifierResult<()> {
+    let notifications_proxy = connect_notification_server(user, bus_name).await?;
+    notifications_proxy.close_notification(notification_id).await?;
+    Ok(())
+}
+
+/// Wait until a notification server owns `bus_name` on a user's session bus
+///
+/// Returns once the name gains a new owner, such as a notification daemon restarted
+/// after a crash, or right away if `accept_current` is set and it already has one.
+/// Fails if the session bus cannot be reached or goes away in the meantime.
+pub async fn wait_for_notification_server(
+    user: &TargetUser,
+    bus_name: &str,
+    accept_current: bool,
+) -> NotifierResult<()> {
+    let user_session_bus = connect_user_session_bus(user).await?;
+    let dbus_proxy = zbus::fdo::DBusProxy::new(&user_session_bus).await?;
+    // Subscribe before checking, so an owner appearing in between is not missed
+    let mut owner_changes = dbus_proxy.receive_name_owner_changed_with_args(&[(0, bus_name)]).await?;
+    let bus_name = parse_bus_name(bus_name)?;
+    if accept_current && dbus_proxy.name_has_owner(bus_name).await? {
+        return Ok(());
+    }
+    while let Some(signal) = owner_changes.next().await {
+        if signal.args()?.new_owner().is_some() {
+            return Ok(());
+        }
+    }
+    let message = "the session bus closed before a notification server appeared";
+    Err(DeliveryError::new(DeliveryErrorKind::NoSessionBus, message).into())
+}
+
+/// Parse the bus name a notification server is expected to own
+fn parse_bus_name(bus_name: &str) -> NotifierResult<BusName<'_>> {
+    bus_name
+        .try_into()
+        .map_err(|e| NotifierError::Validation(format!("invalid bus name '{}': {}", bus_name, e)))
+}
+
+/// Value of a notification hint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HintValue {
+    String(String),
+    Boolean(bool),
+    Byte(u8),
+    Int32(i32),
+    /// Raw pixels, sent as the `(iiibiiay)` structure of the `image-data` hint
+    Image(ImageData),
+}
+
+impl HintValue {
+    /// Convert the hint into a D-Bus value
+    pub fn to_value(&self) -> Value<'_> {
+        match self {
+            HintValue::String(s) => Value::from(s.as_str()),
+            HintValue::Boolean(b) => Value::from(*b),
+            HintValue::Byte(b) => Value::from(*b),
+            HintValue::Int32(i) => Value::from(*i),
+            HintValue::Image(image) => image.to_value(),
+        }
+    }
+}
+
+impl From<&str> for HintValue {
+    fn from(value: &str) -> Self {
+        HintValue::String(value.to_string())
+    }
+}
+
+impl From<String> for HintValue {
+    fn from(value: String) -> Self {
+        HintValue::String(value)
+    }
+}
+
+impl From<bool> for HintValue {
+    fn from(value: bool) -> Self {
+        HintValue::Boolean(value)
+    }
+}
+
+impl From<u8> for HintValue {
+    fn from(value: u8) -> Self {
+        HintValue::Byte(value)
+    }
+}
+
+impl From<i32> for HintValue {
+    fn from(value: i32) -> Self {
+        HintValue::Int32(value)
+    }
+}
+
 /// Create a notification with custom parameters
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NotificationBuilder {
     app_name: String,
     replaces_id: u32,
     app_icon: String,
-    summary: String,
-    body: String,
+    bus_name: String,
+    summary: Arc<str>,
+    body: Arc<str>,
     actions: Vec<String>,
-    hints: HashMap<String, String>,
+    hints: HashMap<String, HintValue>,
     expire_timeout: i32,
+    profiles: Option<Arc<RenderingProfiles>>,
+    markdown: bool,
 }
 
 impl NotificationBuilder {
     /// Create a new notification builder with default values
-    pub fn new(summary: impl Into<String>, body: impl Into<String>) -> Self {
+    ///
+    /// Passing `Arc<str>` shares the summary and body instead of copying them.
+    pub fn new(summary: impl Into<Arc<str>>, body: impl Into<Arc<str>>) -> Self {
         Self {
             app_name: "System Notifier".to_string(),
             replaces_id: 0,
-            app_icon: "dialog-information-symbolic".to_string(),
+            app_icon: DEFAULT_APP_ICON.to_string(),
+            bus_name: NOTIFICATIONS_BUS_NAME.to_string(),
             summary: summary.into(),
             body: body.into(),
             actions: Vec::new(),
             hints: HashMap::new(),
-            expire_timeout: -1,
+            expire_timeout: DEFAULT_EXPIRE_TIMEOUT,
+            profiles: None,
+            markdown: false,
         }
     }
 
@@ -68,13 +411,25 @@ impl NotificationBuilder {
         self
     }
 
+    /// Set the bus name of the notification server to deliver to
+    pub fn bus_name(mut self, bus_name: impl Into<String>) -> Self {
+        self.bus_name = bus_name.into();
+        self
+    }
+
     /// Set the icon name
     pub fn icon(mut self, icon: impl Into<String>) -> Self {
         self.app_icon = icon.into();
         self
     }
 
-    /// Set the expiration timeout
+    /// Set the id of an existing notification to replace
+    pub fn replaces(mut self, replaces_id: u32) -> Self {
+        self.replaces_id = replaces_id;
+        self
+    }
+
+    /// Set the expiration timeout in milliseconds; -1 leaves it to the server and 0 never expires
     pub fn timeout(mut self, timeout: i32) -> Self {
         self.expire_timeout = timeout;
         self
@@ -88,31 +443,214 @@ impl NotificationBuilder {
     }
 
     /// Add a hint
-    pub fn hint(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+    pub fn hint(mut self, key: impl Into<String>, value: impl Into<HintValue>) -> Self {
         self.hints.insert(key.into(), value.into());
         self
     }
 
+    /// Render the notification with the profile of the daemon it is delivered to, see [`crate::profile`]
+    pub fn profiles(mut self, profiles: Arc<RenderingProfiles>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
+    /// Treat the body as Markdown, see [`crate::markup`]
+    ///
+    /// It is sent as markup to daemons advertising the `body-markup` capability and
+    /// as plain text to others.
+    pub fn markdown(mut self, markdown: bool) -> Self {
+        self.markdown = markdown;
+        self
+    }
+
+    /// Show an image with the notification, via the `image-data` or `image-path` hint
+    pub fn image(self, image: NotificationImage) -> Self {
+        match image {
+            NotificationImage::Data(data) => self.hint("image-data", HintValue::Image(data)),
+            NotificationImage::Path(path) => self.hint("image-path", format!("file://{}", path.display())),
+        }
+    }
+
+    /// Tag the notification with the correlation id of its broadcast
+    pub fn correlation_id(self, correlation_id: impl Into<String>) -> Self {
+        self.hint(CORRELATION_ID_HINT, correlation_id.into())
+    }
+
+    /// Name who sent the notification
+    pub fn sender(self, sender: impl Into<String>) -> Self {
+        self.hint(SENDER_HINT, sender.into())
+    }
+
+    /// Name the desktop entry of the application via the `desktop-entry` hint
+    pub fn desktop_entry(self, desktop_entry: impl Into<String>) -> Self {
+        self.hint("desktop-entry", desktop_entry.into())
+    }
+
+    /// Group the notification with others of the same key via the [`GROUP_HINTS`]
+    pub fn group(self, group_key: &str) -> Self {
+        GROUP_HINTS.into_iter().fold(self, |builder, hint| builder.hint(hint, group_key))
+    }
+
+    /// Set the urgency via the `urgency` byte hint
+    pub fn urgency(self, urgency: Urgency) -> Self {
+        self.hint("urgency", urgency.as_byte())
+    }
+
+    /// Show a progress bar at a percentage via the `value` hint
+    pub fn progress(self, percent: u8) -> Self {
+        self.hint("value", i32::from(percent.min(100)))
+    }
+
+    /// Apply a sound theme choice via the `sound-name`/`suppress-sound` hints
+    pub fn sound(self, sound: &Sound) -> Self {
+        match sound {
+            Sound::DaemonDefault => self,
+            Sound::Muted => self.hint("suppress-sound", true),
+            Sound::Named(name) => self.hint("sound-name", name.as_str()),
+        }
+    }
+
     /// Send the notification to a user
-    pub async fn send_to_user(self, user: &TargetUser) -> Result<u32, Box<dyn std::error::Error>> {
-        let dbus_address: Address = format!("unix:path=/run/user/{}/bus", user.uid()).parse()?;
-        
-        let user_session_bus = zbus::connection::Builder::address(dbus_address)?
-            .build()
-            .await?;
+    pub async fn send_to_user(mut self, user: &TargetUser) -> NotifierResult<u32> {
+        let notifications_proxy = connect_notification_server(user, &self.bus_name).await?;
+        self.render_for(&notifications_proxy).await;
+        Ok(self.notify(&notifications_proxy).await?)
+    }
 
-        let notifications_proxy = NotificationsProxy::new(&user_session_bus).await?;
-        
+    /// Send the notification to a user's session bus at `address`, such as a nested session's
+    pub async fn send_to_bus(mut self, user: &TargetUser, address: &str) -> NotifierResult<u32> {
+        let notifications_proxy = connect_notification_server_at(user.uid(), address, &self.bus_name).await?;
+        self.render_for(&notifications_proxy).await;
+        Ok(self.notify(&notifications_proxy).await?)
+    }
+
+    /// Send the notification to a user and forward the keys of invoked actions to `responses`
+    ///
+    /// Invoked actions are listened for in the background until one is invoked,
+    /// the notification is closed, or `timeout` passes.
+    pub async fn send_to_user_with_responses(
+        mut self,
+        user: &TargetUser,
+        responses: UnboundedSender<String>,
+        timeout: Duration,
+    ) -> NotifierResult<u32> {
+        let notifications_proxy = connect_notification_server(user, &self.bus_name).await?;
+        self.render_for(&notifications_proxy).await;
+        // Subscribe before notifying so an immediate answer is not missed
+        let mut actions = notifications_proxy.receive_action_invoked().await?;
+        let mut closed = notifications_proxy.receive_notification_closed().await?;
+        let notification_id = self.notify(&notifications_proxy).await?;
+
+        tokio::spawn(async move {
+            let listen = async {
+                loop {
+                    tokio::select! {
+                        Some(signal) = actions.next() => {
+                            let Ok(args) = signal.args() else { continue };
+                            if args.id == notification_id {
+                                let _ = responses.send(args.action_key.to_string());
+                                return;
+                            }
+                        }
+                        Some(signal) = closed.next() => {
+                            if signal.args().is_ok_and(|args| args.id == notification_id) {
+                                return;
+                            }
+                        }
+                        else => return,
+                    }
+                }
+            };
+            if tokio::time::timeout(timeout, listen).await.is_err() {
+                debug!(notification_id, "Stopped waiting for a response to the notification.");
+            }
+        });
+        Ok(notification_id)
+    }
+
+    /// Adapt the notification to what the notification server supports and apply its
+    /// rendering profile, if one applies to it
+    async fn render_for(&mut self, notifications_proxy: &NotificationsProxy<'_>) {
+        match notifications_proxy.get_capabilities().await {
+            Ok(capabilities) => {
+                debug!(?capabilities, "Adapting notification to the server's capabilities.");
+                self.adapt_to(&capabilities);
+            }
+            Err(e) => {
+                debug!("Failed to get the notification server's capabilities, sending as is: {}", e);
+                if self.markdown {
+                    self.body = render_markdown(&self.body, false).into();
+                }
+            }
+        }
+        let Some(profiles) = self.profiles.clone() else {
+            return;
+        };
+        match notifications_proxy.get_server_information().await {
+            Ok((server, ..)) => {
+                if let Some(profile) = profiles.for_server(&server) {
+                    debug!(%server, ?profile, "Rendering notification with the daemon's profile.");
+                    self.apply_profile(&profile);
+                }
+            }
+            Err(e) => debug!("Failed to identify the notification server, rendering without a profile: {}", e),
+        }
+    }
+
+    /// Drop what a notification server with these capabilities would not render
+    ///
+    /// Markdown bodies become markup or plain text, markup and images in other bodies
+    /// are stripped, and actions are dropped if the server cannot offer them.
+    fn adapt_to(&mut self, capabilities: &[String]) {
+        let supports = |capability: &str| capabilities.iter().any(|supported| supported == capability);
+        let markup = supports("body-markup");
+        if self.markdown {
+            self.body = render_markdown(&self.body, markup).into();
+        } else if !markup {
+            self.body = strip_markup(&self.body).into();
+        }
+        if markup && !supports("body-images") {
+            self.body = strip_images(&self.body).into();
+        }
+        if !supports("actions") {
+            self.actions.clear();
+        }
+    }
+
+    /// Render the notification as a profile asks
+    fn apply_profile(&mut self, profile: &RenderingProfile) {
+        self.body = profile.body(&self.body).into();
+        self.app_icon = profile.icon(&self.app_icon);
+        if !profile.offers_actions() {
+            self.actions.clear();
+        }
+    }
+
+    /// Get the expiration timeout sent to the server
+    ///
+    /// Critical notifications never expire unless a timeout was set explicitly,
+    /// as the specification recommends.
+    fn effective_timeout(&self) -> i32 {
+        let critical = self.hints.get("urgency") == Some(&HintValue::Byte(Urgency::Critical.as_byte()));
+        if critical && self.expire_timeout < 0 {
+            0
+        } else {
+            self.expire_timeout
+        }
+    }
+
+    /// Send the notification through a connected notification server
+    async fn notify(&self, notifications_proxy: &NotificationsProxy<'_>) -> zbus::Result<u32> {
         // Convert actions to slice of string refs
         let action_refs: Vec<&str> = self.actions.iter().map(|s| s.as_str()).collect();
         
         // Convert hints to the required format
-        let hint_refs: HashMap<&str, zbus::zvariant::Value<'_>> = self.hints
+        let hint_refs: HashMap<&str, Value<'_>> = self.hints
             .iter()
-            .map(|(k, v)| (k.as_str(), zbus::zvariant::Value::from(v.as_str())))
+            .map(|(k, v)| (k.as_str(), v.to_value()))
             .collect();
 
-        let notification_id = notifications_proxy
+        notifications_proxy
             .notify(
                 &self.app_name,
                 self.replaces_id,
@@ -121,25 +659,106 @@ impl NotificationBuilder {
                 &self.body,
                 &action_refs,
                 &hint_refs,
-                self.expire_timeout,
+                self.effective_timeout(),
             )
-            .await?;
-        Ok(notification_id)
+            .await
+    }
+}
+
+/// A broadcast as the server would send it, for previewing it before sending
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastOptions {
+    pub title: String,
+    pub body: String,
+    pub options: SendOptions,
+    /// Line about the sending host appended to the body, already rendered from its template
+    pub host_context: Option<String>,
+    /// Rules inferring the urgency of broadcasts sent without one
+    pub urgency_rules: Vec<UrgencyRule>,
+    /// Application identity and timeout of the notification
+    pub defaults: NotificationDefaults,
+}
+
+impl BroadcastOptions {
+    /// Describe a broadcast as a server with the default configuration would send it
+    pub fn new(title: impl Into<String>, body: impl Into<String>, options: SendOptions) -> Self {
+        Self {
+            title: title.into(),
+            body: body.into(),
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Apply the host context, urgency rules and notification defaults of a server configuration
+    pub fn with_config(mut self, config: &Config) -> Self {
+        self.host_context = config
+            .host_context
+            .template()
+            .and_then(|template| HostInfo::read().ok().map(|host| host.render(template)));
+        self.urgency_rules = config.urgency_rules.clone();
+        self.defaults = config.notification_defaults(self.options.channel.as_deref());
+        self
+    }
+
+    /// Get the urgency the broadcast is sent with, given or inferred from its content
+    pub fn effective_urgency(&self) -> Urgency {
+        self.options
+            .urgency
+            .or_else(|| infer_urgency(&self.urgency_rules, &self.title, &self.body))
+            .unwrap_or_default()
+    }
+}
+
+/// Render a broadcast as plain text for a terminal, such as for `send --dry-run`
+///
+/// The body is shown with the host context appended and Markdown or markup turned
+/// into plain text, along with the urgency and timeout the notification gets.
+pub fn render_preview(broadcast: &BroadcastOptions) -> String {
+    let body = match &broadcast.host_context {
+        Some(line) => format!("{}\n\n{}", broadcast.body, line),
+        None => broadcast.body.clone(),
+    };
+    let body = if broadcast.options.markdown { render_markdown(&body, false) } else { strip_markup(&body) };
+    let timeout = match broadcast.defaults.timeout_for(Some(broadcast.effective_urgency())) {
+        timeout if timeout < 0 => "server default".to_string(),
+        0 => "never".to_string(),
+        timeout => format!("{} ms", timeout),
+    };
+
+    // Writing to a String cannot fail
+    let mut preview = String::new();
+    let mut lines = body.lines();
+    let _ = writeln!(preview, "Title:    {}", broadcast.title);
+    let _ = writeln!(preview, "Body:     {}", lines.next().unwrap_or_default());
+    for line in lines {
+        let _ = writeln!(preview, "          {}", line);
+    }
+    let _ = writeln!(preview, "Urgency:  {}", broadcast.effective_urgency());
+    let _ = writeln!(preview, "Timeout:  {}", timeout);
+    if let Some(channel) = &broadcast.options.channel {
+        let _ = writeln!(preview, "Channel:  {}", channel);
     }
+    for action in &broadcast.options.actions {
+        let (key, label) = parse_action(action).unwrap_or_else(|_| (action.clone(), action.clone()));
+        let _ = writeln!(preview, "Action:   {} ({})", label, key);
+    }
+    preview
 }
 
 /// Validate notification content
-pub fn validate_notification_content(summary: &str, body: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn validate_notification_content(summary: &str, body: &str) -> NotifierResult<()> {
+    let invalid = |message: &str| Err(NotifierError::Validation(message.to_string()));
     if summary.is_empty() {
-        return Err("Notification summary cannot be empty".into());
+        return invalid("Notification summary cannot be empty");
     }
     
     if summary.len() > 1000 {
-        return Err("Notification summary too long (max 1000 characters)".into());
+        return invalid("Notification summary too long (max 1000 characters)");
     }
     
     if body.len() > 5000 {
-        return Err("Notification body too long (max 5000 characters)".into());
+        return invalid("Notification body too long (max 5000 characters)");
     }
     
     Ok(())
@@ -152,10 +771,11 @@ mod tests {
     #[test]
     fn test_notification_builder_defaults() {
         let builder = NotificationBuilder::new("Test Summary", "Test Body");
-        assert_eq!(builder.summary, "Test Summary");
-        assert_eq!(builder.body, "Test Body");
+        assert_eq!(&*builder.summary, "Test Summary");
+        assert_eq!(&*builder.body, "Test Body");
         assert_eq!(builder.app_name, "System Notifier");
         assert_eq!(builder.app_icon, "dialog-information-symbolic");
+        assert_eq!(builder.bus_name, NOTIFICATIONS_BUS_NAME);
         assert_eq!(builder.replaces_id, 0);
         assert_eq!(builder.expire_timeout, -1);
         assert!(builder.actions.is_empty());
@@ -167,18 +787,146 @@ mod tests {
         let builder = NotificationBuilder::new("Summary", "Body")
             .app_name("Custom App")
             .icon("custom-icon")
+            .bus_name("org.example.NotificationProxy")
             .timeout(5000)
+            .replaces(17)
             .action("action1", "Action 1")
             .action("action2", "Action 2")
             .hint("urgency", "critical")
-            .hint("category", "device");
+            .hint("category", "device")
+            .desktop_entry("backup-service");
 
         assert_eq!(builder.app_name, "Custom App");
         assert_eq!(builder.app_icon, "custom-icon");
+        assert_eq!(builder.bus_name, "org.example.NotificationProxy");
         assert_eq!(builder.expire_timeout, 5000);
+        assert_eq!(builder.replaces_id, 17);
         assert_eq!(builder.actions, vec!["action1", "Action 1", "action2", "Action 2"]);
-        assert_eq!(builder.hints.get("urgency"), Some(&"critical".to_string()));
-        assert_eq!(builder.hints.get("category"), Some(&"device".to_string()));
+        assert_eq!(builder.hints.get("urgency"), Some(&HintValue::from("critical")));
+        assert_eq!(builder.hints.get("desktop-entry"), Some(&HintValue::from("backup-service")));
+        assert_eq!(builder.hints.get("category"), Some(&HintValue::from("device")));
+    }
+
+    #[test]
+    fn test_notification_builder_typed_hints() {
+        let builder = NotificationBuilder::new("Summary", "Body")
+            .hint("transient", true)
+            .hint("urgency", 2u8)
+            .hint("value", 42);
+
+        assert_eq!(builder.hints.get("transient"), Some(&HintValue::Boolean(true)));
+        assert_eq!(builder.hints.get("urgency"), Some(&HintValue::Byte(2)));
+        assert_eq!(builder.hints.get("value"), Some(&HintValue::Int32(42)));
+        assert_eq!(HintValue::Byte(2).to_value(), Value::U8(2));
+        assert_eq!(HintValue::from("x").to_value(), Value::from("x"));
+    }
+
+    #[test]
+    fn test_notification_builder_correlation_id() {
+        let builder = NotificationBuilder::new("Summary", "Body").correlation_id("0f6c");
+        assert_eq!(builder.hints.get(CORRELATION_ID_HINT), Some(&HintValue::from("0f6c")));
+    }
+
+    #[test]
+    fn test_notification_builder_sender() {
+        let builder = NotificationBuilder::new("Summary", "Body").sender("Patching system");
+        assert_eq!(builder.hints.get(SENDER_HINT), Some(&HintValue::from("Patching system")));
+    }
+
+    #[test]
+    fn test_notification_builder_image() {
+        let builder = NotificationBuilder::new("Summary", "Body").image(NotificationImage::Path("/tmp/logo.png".into()));
+        assert_eq!(builder.hints.get("image-path"), Some(&HintValue::from("file:///tmp/logo.png")));
+        let pixels = ImageData::new(1, 1, false, vec![0, 0, 0]).unwrap();
+        let builder = NotificationBuilder::new("Summary", "Body").image(NotificationImage::Data(pixels.clone()));
+        assert_eq!(builder.hints.get("image-data"), Some(&HintValue::Image(pixels)));
+    }
+
+    #[test]
+    fn test_notification_builder_group() {
+        let builder = NotificationBuilder::new("Summary", "Body").group("backups");
+        for hint in GROUP_HINTS {
+            assert_eq!(builder.hints.get(hint), Some(&HintValue::from("backups")));
+        }
+    }
+
+    #[test]
+    fn test_notification_builder_urgency() {
+        let builder = NotificationBuilder::new("Summary", "Body").urgency(Urgency::Critical);
+        assert_eq!(builder.hints.get("urgency"), Some(&HintValue::Byte(2)));
+    }
+
+    #[test]
+    fn test_notification_builder_progress() {
+        let builder = NotificationBuilder::new("Backup", "Copying /home").progress(40);
+        assert_eq!(builder.hints.get("value"), Some(&HintValue::Int32(40)));
+        let builder = NotificationBuilder::new("Backup", "Copying /home").progress(250);
+        assert_eq!(builder.hints.get("value"), Some(&HintValue::Int32(100)));
+    }
+
+    #[test]
+    fn test_critical_notifications_never_expire() {
+        assert_eq!(NotificationBuilder::new("Summary", "Body").effective_timeout(), -1);
+        let builder = NotificationBuilder::new("Summary", "Body").urgency(Urgency::Low);
+        assert_eq!(builder.effective_timeout(), -1);
+        let builder = NotificationBuilder::new("Summary", "Body").urgency(Urgency::Critical);
+        assert_eq!(builder.effective_timeout(), 0);
+        let builder = NotificationBuilder::new("Summary", "Body").urgency(Urgency::Critical).timeout(5000);
+        assert_eq!(builder.effective_timeout(), 5000);
+    }
+
+    #[test]
+    fn test_notification_builder_profile() {
+        let mut builder = NotificationBuilder::new("Summary", "<b>Disk</b> is full").action("ack", "Acknowledge");
+        builder.apply_profile(&RenderingProfile::builtin("gnome").unwrap());
+        assert_eq!(&*builder.body, "<b>Disk</b> is full");
+        assert_eq!(builder.app_icon, "dialog-information-symbolic");
+
+        builder.apply_profile(&RenderingProfile {
+            markup: Some(false),
+            actions: Some(false),
+            ..RenderingProfile::builtin("kde").unwrap()
+        });
+        assert_eq!(&*builder.body, "Disk is full");
+        assert_eq!(builder.app_icon, "dialog-information");
+        assert!(builder.actions.is_empty());
+    }
+
+    #[test]
+    fn test_notification_adapted_to_capabilities() {
+        let capabilities = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let body = "<b>Disk</b> is full <img src=\"disk.png\"/>";
+        let mut builder = NotificationBuilder::new("Summary", body).action("ack", "Acknowledge");
+        builder.adapt_to(&capabilities(&["actions", "body", "body-markup", "body-images"]));
+        assert_eq!(&*builder.body, body);
+        assert_eq!(builder.actions.len(), 2);
+
+        builder.adapt_to(&capabilities(&["body", "body-markup"]));
+        assert_eq!(&*builder.body, "<b>Disk</b> is full ");
+        assert!(builder.actions.is_empty());
+        builder.adapt_to(&capabilities(&["body"]));
+        assert_eq!(&*builder.body, "Disk is full ");
+
+        let mut builder = NotificationBuilder::new("Summary", "**Disk** is full").markdown(true);
+        builder.adapt_to(&capabilities(&["body-markup"]));
+        assert_eq!(&*builder.body, "<b>Disk</b> is full");
+        let mut builder = NotificationBuilder::new("Summary", "**Disk** is full").markdown(true);
+        builder.adapt_to(&[]);
+        assert_eq!(&*builder.body, "Disk is full");
+    }
+
+    #[test]
+    fn test_notification_builder_sound() {
+        let builder = NotificationBuilder::new("Summary", "Body").sound(&Sound::DaemonDefault);
+        assert!(builder.hints.is_empty());
+
+        let builder = NotificationBuilder::new("Summary", "Body").sound(&Sound::Muted);
+        assert_eq!(builder.hints.get("suppress-sound"), Some(&HintValue::Boolean(true)));
+        assert!(!builder.hints.contains_key("sound-name"));
+
+        let builder = NotificationBuilder::new("Summary", "Body")
+            .sound(&Sound::Named("message-new-instant".to_string()));
+        assert_eq!(builder.hints.get("sound-name"), Some(&HintValue::from("message-new-instant")));
     }
 
     #[test]
@@ -219,6 +967,60 @@ mod tests {
         assert!(validate_notification_content("Title with \"quotes\"", "Body with\nnewlines\ttabs").is_ok());
     }
 
-    // Note: send_notification_to_user() and NotificationBuilder::send_to_user() 
-    // require actual D-Bus connection and are tested in integration tests
+    #[test]
+    fn test_notification_defaults() {
+        let defaults = NotificationDefaults::default();
+        assert_eq!(defaults.icon(), DEFAULT_APP_ICON);
+        assert_eq!(defaults.timeout(), DEFAULT_EXPIRE_TIMEOUT);
+        let defaults = NotificationDefaults {
+            icon: Some("dialog-warning".to_string()),
+            timeout_ms: Some(10000),
+            ..NotificationDefaults::default()
+        };
+        assert_eq!(defaults.icon(), "dialog-warning");
+        assert_eq!(defaults.timeout(), 10000);
+        assert_eq!(defaults.timeout_for(Some(Urgency::Normal)), 10000);
+        assert_eq!(defaults.timeout_for(Some(Urgency::Critical)), 0);
+    }
+
+
+    #[test]
+    fn test_render_preview() {
+        let options = SendOptions {
+            markdown: true,
+            channel: Some("backups".to_string()),
+            actions: vec!["retry:Retry now".to_string()],
+            ..SendOptions::default()
+        };
+        let broadcast = BroadcastOptions::new("Backup failed", "Disk **full**\nSee logs", options);
+        assert_eq!(
+            render_preview(&broadcast),
+            "Title:    Backup failed\n\
+             Body:     Disk full\n          See logs\n\
+             Urgency:  normal\n\
+             Timeout:  server default\n\
+             Channel:  backups\n\
+             Action:   Retry now (retry)\n"
+        );
+    }
+
+    #[test]
+    fn test_render_preview_with_config() {
+        let config = Config::from_toml_str(
+            "[[urgency_rules]]\nkeywords = [\"outage\"]\nurgency = \"critical\"\n[notification]\ntimeout_ms = 0\n",
+        )
+        .unwrap();
+        let broadcast = BroadcastOptions::new("Outage", "<b>VPN</b> down", SendOptions::default()).with_config(&config);
+        assert_eq!(broadcast.effective_urgency(), Urgency::Critical);
+        let preview = render_preview(&broadcast);
+        assert!(preview.contains("Body:     VPN down\n"));
+        assert!(preview.contains("Urgency:  critical\n"));
+        assert!(preview.contains("Timeout:  never\n"));
+
+        let low = SendOptions { urgency: Some(Urgency::Low), ..SendOptions::default() };
+        assert_eq!(BroadcastOptions { options: low, ..broadcast }.effective_urgency(), Urgency::Low);
+    }
+
+    // Note: send_notification_to_user(), close_notification_for_user(), NotificationBuilder::send_to_user()
+    // and NotificationBuilder::send_to_user_with_responses() require actual D-Bus connection and are tested in integration tests
 }
\ No newline at end of file
@@ -0,0 +1,303 @@
+//! Cached user and group lookups
+//!
+//! User and group resolution goes through NSS, which may be backed by LDAP or
+//! SSSD on networked sites. Results (including misses) are cached for a
+//! configurable time so dispatch latency stays predictable; the cache can be
+//! flushed explicitly when directory data changes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default time lookup results are cached for
+pub const DEFAULT_NSS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// A user account as resolved through NSS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// A group as resolved through NSS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+/// Source of user and group information
+pub trait NssResolver: Send + Sync {
+    /// Look up a user by name
+    fn user_by_name(&self, name: &str) -> Result<Option<UserEntry>, String>;
+
+    /// Look up a group by name
+    fn group_by_name(&self, name: &str) -> Result<Option<GroupEntry>, String>;
+}
+
+/// Resolver using the system's NSS configuration
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl NssResolver for SystemResolver {
+    fn user_by_name(&self, name: &str) -> Result<Option<UserEntry>, String> {
+        nix::unistd::User::from_name(name)
+            .map(|user| {
+                user.map(|user| UserEntry {
+                    name: user.name,
+                    uid: user.uid.as_raw(),
+                    gid: user.gid.as_raw(),
+                })
+            })
+            .map_err(|e| format!("Failed to look up user '{}': {}", name, e))
+    }
+
+    fn group_by_name(&self, name: &str) -> Result<Option<GroupEntry>, String> {
+        nix::unistd::Group::from_name(name)
+            .map(|group| {
+                group.map(|group| GroupEntry {
+                    name: group.name,
+                    gid: group.gid.as_raw(),
+                    members: group.mem,
+                })
+            })
+            .map_err(|e| format!("Failed to look up group '{}': {}", name, e))
+    }
+}
+
+/// Map of cached lookup results with their insertion times
+struct TtlMap<K, V> {
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlMap<K, V> {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &K, ttl: Duration) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: K, value: V) {
+        self.entries.lock().unwrap().insert(key, (Instant::now(), value));
+    }
+
+    fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+/// TTL cache in front of an [`NssResolver`]
+pub struct NssCache {
+    ttl: Duration,
+    resolver: Arc<dyn NssResolver>,
+    users: TtlMap<String, Option<UserEntry>>,
+    groups: TtlMap<String, Option<GroupEntry>>,
+}
+
+impl NssCache {
+    /// Create a cache using the system resolver
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_resolver(ttl, Arc::new(SystemResolver))
+    }
+
+    /// Create a cache in front of a custom resolver
+    pub fn with_resolver(ttl: Duration, resolver: Arc<dyn NssResolver>) -> Self {
+        Self {
+            ttl,
+            resolver,
+            users: TtlMap::new(),
+            groups: TtlMap::new(),
+        }
+    }
+
+    /// Look up a user by name
+    ///
+    /// Lookup failures are not cached, so a temporarily unreachable directory is retried.
+    pub fn user(&self, name: &str) -> Result<Option<UserEntry>, String> {
+        let key = name.to_string();
+        if let Some(entry) = self.users.get(&key, self.ttl) {
+            return Ok(entry);
+        }
+        let entry = self.resolver.user_by_name(name)?;
+        self.users.insert(key, entry.clone());
+        Ok(entry)
+    }
+
+    /// Look up a group by name
+    ///
+    /// Lookup failures are not cached, so a temporarily unreachable directory is retried.
+    pub fn group(&self, name: &str) -> Result<Option<GroupEntry>, String> {
+        let key = name.to_string();
+        if let Some(entry) = self.groups.get(&key, self.ttl) {
+            return Ok(entry);
+        }
+        let entry = self.resolver.group_by_name(name)?;
+        self.groups.insert(key, entry.clone());
+        Ok(entry)
+    }
+
+    /// Check whether a user belongs to a group, either as primary or supplementary group
+    pub fn is_member(&self, username: &str, group: &str) -> Result<bool, String> {
+        let Some(group) = self.group(group)? else {
+            return Ok(false);
+        };
+        if group.members.iter().any(|member| member == username) {
+            return Ok(true);
+        }
+        Ok(self.user(username)?.is_some_and(|user| user.gid == group.gid))
+    }
+
+    /// Drop every cached entry, returning how many were dropped
+    pub fn flush(&self) -> usize {
+        self.users.clear() + self.groups.clear()
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.users.len() + self.groups.len()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for NssCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_NSS_CACHE_TTL)
+    }
+}
+
+impl fmt::Debug for NssCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NssCache")
+            .field("ttl", &self.ttl)
+            .field("entries", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct FakeResolver {
+        lookups: AtomicUsize,
+    }
+
+    impl NssResolver for FakeResolver {
+        fn user_by_name(&self, name: &str) -> Result<Option<UserEntry>, String> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(match name {
+                "alice" => Some(UserEntry { name: "alice".to_string(), uid: 1000, gid: 100 }),
+                "bob" => Some(UserEntry { name: "bob".to_string(), uid: 1001, gid: 10 }),
+                "ldap-down" => return Err("directory unreachable".to_string()),
+                _ => None,
+            })
+        }
+
+        fn group_by_name(&self, name: &str) -> Result<Option<GroupEntry>, String> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(match name {
+                "wheel" => Some(GroupEntry { name: "wheel".to_string(), gid: 10, members: vec!["alice".to_string()] }),
+                _ => None,
+            })
+        }
+    }
+
+    fn cache(ttl: Duration) -> (NssCache, Arc<FakeResolver>) {
+        let resolver = Arc::new(FakeResolver::default());
+        (NssCache::with_resolver(ttl, resolver.clone()), resolver)
+    }
+
+    #[test]
+    fn test_lookups_are_cached() {
+        let (cache, resolver) = cache(Duration::from_secs(60));
+        assert_eq!(cache.user("alice").unwrap().unwrap().uid, 1000);
+        assert_eq!(cache.user("alice").unwrap().unwrap().uid, 1000);
+        assert!(cache.group("wheel").unwrap().is_some());
+        assert!(cache.group("wheel").unwrap().is_some());
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_misses_are_cached() {
+        let (cache, resolver) = cache(Duration::from_secs(60));
+        assert!(cache.user("nobody-here").unwrap().is_none());
+        assert!(cache.user("nobody-here").unwrap().is_none());
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_errors_are_not_cached() {
+        let (cache, resolver) = cache(Duration::from_secs(60));
+        assert!(cache.user("ldap-down").is_err());
+        assert!(cache.user("ldap-down").is_err());
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_expired_entries_are_refreshed() {
+        let (cache, resolver) = cache(Duration::ZERO);
+        cache.user("alice").unwrap();
+        cache.user("alice").unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_flush() {
+        let (cache, resolver) = cache(Duration::from_secs(60));
+        cache.user("alice").unwrap();
+        cache.group("wheel").unwrap();
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.flush(), 2);
+        assert!(cache.is_empty());
+        cache.user("alice").unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_is_member() {
+        let (cache, _) = cache(Duration::from_secs(60));
+        // Supplementary member
+        assert!(cache.is_member("alice", "wheel").unwrap());
+        // Primary group
+        assert!(cache.is_member("bob", "wheel").unwrap());
+        assert!(!cache.is_member("carol", "wheel").unwrap());
+        assert!(!cache.is_member("alice", "no-such-group").unwrap());
+    }
+
+    #[test]
+    fn test_system_resolver_root() {
+        let root = SystemResolver.user_by_name("root").unwrap().unwrap();
+        assert_eq!(root.uid, 0);
+    }
+}
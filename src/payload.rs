@@ -0,0 +1,205 @@
+//! Broadcast payloads shared across per-user deliveries
+//!
+//! A broadcast is delivered to every active user, so its content is built once
+//! and shared behind an [`Arc`] instead of being copied into each delivery task.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::image::NotificationImage;
+use crate::types::Urgency;
+
+/// The content of a broadcast, identical for every recipient
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastPayload {
+    pub title: Arc<str>,
+    pub body: Arc<str>,
+    pub urgency: Option<Urgency>,
+    /// Answers offered as notification actions, for polls
+    #[serde(default)]
+    pub actions: Vec<String>,
+    /// Labels shown for actions, keyed by action; actions without one show their key
+    #[serde(default)]
+    pub action_labels: BTreeMap<String, String>,
+    /// Name of a hook run for each recipient after the notification is displayed
+    #[serde(default)]
+    pub hook: Option<String>,
+    /// Name of the caller the broadcast is attributed to
+    #[serde(default)]
+    pub sender: Option<Arc<str>>,
+    /// Key grouping related broadcasts, so desktops stack them and pending ones are coalesced
+    #[serde(default)]
+    pub group_key: Option<String>,
+    /// Category of the alert the broadcast is about, such as `monitoring.disk`, picking its sound
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Whether the body is written in Markdown, rendered as markup where the daemon supports it
+    #[serde(default)]
+    pub markdown: bool,
+    /// Image shown with the notification, see [`crate::image`]
+    #[serde(default)]
+    pub image: Option<PathBuf>,
+    /// The image, loaded once on behalf of the sender; not kept, so restored broadcasts pass its path
+    #[serde(skip)]
+    pub loaded_image: Option<Arc<NotificationImage>>,
+    /// Percentage of a job done, shown as a progress bar
+    #[serde(default)]
+    pub progress: Option<u8>,
+    /// Warnings of the content lints, kept for the history
+    #[serde(default)]
+    pub lint_warnings: Vec<String>,
+}
+
+impl BroadcastPayload {
+    /// Create a new payload
+    pub fn new(title: impl Into<Arc<str>>, body: impl Into<Arc<str>>) -> Self {
+        Self {
+            title: title.into(),
+            body: body.into(),
+            urgency: None,
+            actions: Vec::new(),
+            action_labels: BTreeMap::new(),
+            hook: None,
+            sender: None,
+            group_key: None,
+            category: None,
+            markdown: false,
+            image: None,
+            loaded_image: None,
+            progress: None,
+            lint_warnings: Vec::new(),
+        }
+    }
+
+    /// Set the urgency the payload is delivered with
+    pub fn with_urgency(mut self, urgency: Option<Urgency>) -> Self {
+        self.urgency = urgency;
+        self
+    }
+
+    /// Set the answers offered as notification actions
+    pub fn with_actions(mut self, actions: Vec<String>) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    /// Set the labels shown for actions
+    pub fn with_action_labels(mut self, action_labels: BTreeMap<String, String>) -> Self {
+        self.action_labels = action_labels;
+        self
+    }
+
+    /// Get the label shown for an action
+    pub fn action_label<'a>(&'a self, action: &'a str) -> &'a str {
+        self.action_labels.get(action).map_or(action, String::as_str)
+    }
+
+    /// Set the hook run after the notification is displayed
+    pub fn with_hook(mut self, hook: Option<String>) -> Self {
+        self.hook = hook;
+        self
+    }
+
+    /// Set the name of the caller the payload is attributed to
+    pub fn with_sender(mut self, sender: Option<Arc<str>>) -> Self {
+        self.sender = sender;
+        self
+    }
+
+    /// Set the key grouping the payload with related broadcasts
+    pub fn with_group_key(mut self, group_key: Option<String>) -> Self {
+        self.group_key = group_key;
+        self
+    }
+
+    /// Whether the payload belongs to a group
+    pub fn in_group(&self, group_key: &str) -> bool {
+        self.group_key.as_deref() == Some(group_key)
+    }
+
+    /// Set the category of the alert the broadcast is about
+    pub fn with_category(mut self, category: Option<String>) -> Self {
+        self.category = category;
+        self
+    }
+
+    /// Set whether the body is written in Markdown
+    pub fn with_markdown(mut self, markdown: bool) -> Self {
+        self.markdown = markdown;
+        self
+    }
+
+    /// Set the image shown with the notification
+    pub fn with_image(mut self, image: Option<PathBuf>) -> Self {
+        self.image = image;
+        self
+    }
+
+    /// Set the image loaded for the notification, see [`NotificationImage::load_as`]
+    pub fn with_loaded_image(mut self, image: Option<Arc<NotificationImage>>) -> Self {
+        self.loaded_image = image;
+        self
+    }
+
+    /// Set the percentage of a job done, shown as a progress bar
+    pub fn with_progress(mut self, progress: Option<u8>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Set the warnings the content lints raised
+    pub fn with_lint_warnings(mut self, lint_warnings: Vec<String>) -> Self {
+        self.lint_warnings = lint_warnings;
+        self
+    }
+
+    /// Number of content bytes checked against the payload limit
+    pub fn content_size(&self) -> usize {
+        self.title.len() + self.body.len() + self.actions.iter().map(String::len).sum::<usize>()
+            + self.action_labels.values().map(String::len).sum::<usize>()
+            + self.hook.as_ref().map_or(0, String::len)
+            + self.group_key.as_ref().map_or(0, String::len)
+            + self.category.as_ref().map_or(0, String::len)
+            + self.image.as_ref().map_or(0, |image| image.as_os_str().len())
+    }
+
+    /// Approximate number of bytes of memory held by this payload
+    pub fn size(&self) -> usize {
+        mem::size_of::<Self>() + self.content_size() + self.lint_warnings.iter().map(String::len).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_sizes() {
+        let payload = BroadcastPayload::new("title", "body").with_urgency(Some(Urgency::Low));
+        assert_eq!(payload.content_size(), 9);
+        assert!(payload.size() > payload.content_size());
+        assert_eq!(payload.urgency, Some(Urgency::Low));
+
+        let payload = payload.with_actions(vec!["Yes".to_string(), "No".to_string()]);
+        assert_eq!(payload.content_size(), 14);
+
+        let payload = payload.with_hook(Some("flash".to_string()));
+        assert_eq!(payload.content_size(), 19);
+
+        let payload = payload.with_action_labels(BTreeMap::from([("Yes".to_string(), "Reboot now".to_string())]));
+        assert_eq!(payload.content_size(), 29);
+        assert_eq!(payload.action_label("Yes"), "Reboot now");
+        assert_eq!(payload.action_label("No"), "No");
+    }
+
+    #[test]
+    fn test_clones_share_content() {
+        let payload = BroadcastPayload::new("title".to_string(), "body".to_string());
+        let copy = payload.clone();
+        assert!(Arc::ptr_eq(&payload.body, &copy.body));
+    }
+}
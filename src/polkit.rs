@@ -0,0 +1,114 @@
+//! PolicyKit authorization of broadcasts
+//!
+//! Before a broadcast requested over D-Bus or the Unix socket is sent, polkit is
+//! asked whether the caller may perform [`SEND_ACTION_ID`]. Administrators decide who
+//! may broadcast with polkit rules, e.g. members of a group, rather than in the
+//! server configuration. Trusted environments, such as containers without a
+//! polkit daemon, can switch the check off with `polkit = "off"`.
+
+use std::collections::HashMap;
+use std::fs;
+
+use serde::Deserialize;
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+use crate::caller::Caller;
+use crate::dbus::PolicyKitAuthorityProxy;
+
+/// Polkit action a caller must be authorized for to send broadcasts
+pub const SEND_ACTION_ID: &str = "me.section.notifier.send";
+
+/// Whether broadcasts are authorized through polkit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolkitMode {
+    /// Callers must be authorized for [`SEND_ACTION_ID`]
+    #[default]
+    Enforce,
+    /// Polkit is not asked, leaving authorization to `allowed_senders`, `allowed_uids` and `allowed_groups`
+    Off,
+}
+
+/// A polkit subject, its kind and details
+type Subject<'a> = (&'a str, HashMap<&'a str, Value<'a>>);
+
+/// Describe a caller as a polkit subject
+///
+/// Callers on the system bus are described by their unique bus name, which is never
+/// reused. Others, such as socket clients, are described as a `unix-process` by their
+/// pid, its start time and their uid, so polkit notices a pid reused by another process.
+/// Callers without a known pid and start time cannot be described.
+fn subject(caller: &Caller, on_system_bus: bool) -> Option<Subject<'_>> {
+    if on_system_bus && caller.bus_name.starts_with(':') {
+        return Some(("system-bus-name", HashMap::from([("name", Value::from(caller.bus_name.as_str()))])));
+    }
+    let pid = caller.pid?;
+    let start_time = fs::read_to_string(format!("/proc/{}/stat", pid)).ok().and_then(|stat| start_time(&stat))?;
+    let mut details = HashMap::from([("pid", Value::from(pid)), ("start-time", Value::from(start_time))]);
+    if let Some(uid) = caller.uid.and_then(|uid| i32::try_from(uid).ok()) {
+        details.insert("uid", Value::from(uid));
+    }
+    Some(("unix-process", details))
+}
+
+/// Get the start time of a process from its `/proc/<pid>/stat`, in clock ticks since boot
+fn start_time(stat: &str) -> Option<u64> {
+    // The command name may contain spaces and parentheses, the fields after it do not
+    let (_, fields) = stat.rsplit_once(')')?;
+    // The start time is the 22nd field, the 20th after the command name
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Ask polkit whether a caller may send broadcasts
+///
+/// `on_system_bus` tells whether the caller's bus name is one on the system bus.
+/// Callers are never prompted to authenticate, so an action requiring it is denied.
+pub async fn may_send(system_bus: &Connection, caller: &Caller, on_system_bus: bool) -> zbus::Result<bool> {
+    let Some(subject) = subject(caller, on_system_bus) else {
+        return Ok(false);
+    };
+    let authority = PolicyKitAuthorityProxy::new(system_bus).await?;
+    let (authorized, _challenge, _details) =
+        authority.check_authorization(&subject, SEND_ACTION_ID, &HashMap::new(), 0, "").await?;
+    Ok(authorized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject() {
+        let caller = Caller {
+            bus_name: ":1.42".to_string(),
+            uid: Some(1000),
+            pid: Some(std::process::id()),
+            ..Caller::default()
+        };
+        let (kind, details) = subject(&caller, true).unwrap();
+        assert_eq!(kind, "system-bus-name");
+        assert_eq!(details["name"], Value::from(":1.42"));
+
+        let (kind, details) = subject(&caller, false).unwrap();
+        assert_eq!(kind, "unix-process");
+        assert_eq!(details["pid"], Value::from(std::process::id()));
+        assert_ne!(details["start-time"], Value::from(0u64));
+        assert_eq!(details["uid"], Value::from(1000i32));
+
+        let socket_peer = Caller::from_socket_peer(1000, Some(std::process::id()));
+        assert_eq!(subject(&socket_peer, true).unwrap().0, "unix-process");
+        let anonymous = Caller { uid: None, ..caller };
+        let (_, details) = subject(&anonymous, false).unwrap();
+        assert!(!details.contains_key("uid"));
+        assert!(subject(&Caller::default(), true).is_none());
+    }
+
+    #[test]
+    fn test_start_time() {
+        let stat = "4242 (my (odd) cmd) S 1 4242 4242 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 1 0 987654 0 0";
+        assert_eq!(start_time(stat), Some(987654));
+        assert_eq!(start_time("4242 (cmd) S 1"), None);
+        assert_eq!(start_time(""), None);
+    }
+}
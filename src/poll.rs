@@ -0,0 +1,176 @@
+//! Interactive polls
+//!
+//! A poll is a broadcast whose notification carries one action per answer.
+//! Answers are collected per user as the actions are invoked on each desktop.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::broadcast::{BroadcastId, MAX_TRACKED_BROADCASTS};
+use crate::types::TargetUser;
+
+/// Answers offered when a poll is sent without explicit options
+pub const DEFAULT_POLL_OPTIONS: [&str; 3] = ["Yes", "No", "Later"];
+
+/// How long each desktop is listened to for an answer
+pub const POLL_RESPONSE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A poll and the answers received so far
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Poll {
+    pub options: Vec<String>,
+    /// Latest answer of each user who responded, in order of first response
+    pub responses: Vec<(TargetUser, String)>,
+}
+
+impl Poll {
+    /// Count the answers given for each option, in option order
+    pub fn tally(&self) -> Vec<(String, u32)> {
+        self.options
+            .iter()
+            .map(|option| {
+                let count = self.responses.iter().filter(|(_, answer)| answer == option).count();
+                (option.clone(), count as u32)
+            })
+            .collect()
+    }
+}
+
+/// Registry of recent polls, keyed by broadcast id
+#[derive(Debug, Default)]
+pub struct PollRegistry {
+    polls: Mutex<BTreeMap<BroadcastId, Poll>>,
+}
+
+impl PollRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start collecting answers for a broadcast
+    pub fn register(&self, id: BroadcastId, options: Vec<String>) {
+        let mut polls = self.polls.lock().unwrap();
+        polls.insert(
+            id,
+            Poll {
+                options,
+                responses: Vec::new(),
+            },
+        );
+        while polls.len() > MAX_TRACKED_BROADCASTS {
+            polls.pop_first();
+        }
+    }
+
+    /// Whether a broadcast is a poll
+    pub fn contains(&self, id: BroadcastId) -> bool {
+        self.polls.lock().unwrap().contains_key(&id)
+    }
+
+    /// Record a user's answer, replacing any earlier one
+    ///
+    /// Returns false if the broadcast is not a poll or the answer is not one of its options.
+    pub fn record_response(&self, id: BroadcastId, user: &TargetUser, answer: &str) -> bool {
+        let mut polls = self.polls.lock().unwrap();
+        let Some(poll) = polls.get_mut(&id) else {
+            return false;
+        };
+        if !poll.options.iter().any(|option| option == answer) {
+            return false;
+        }
+        match poll.responses.iter_mut().find(|(respondent, _)| respondent.uid == user.uid) {
+            Some((_, previous)) => *previous = answer.to_string(),
+            None => poll.responses.push((user.clone(), answer.to_string())),
+        }
+        true
+    }
+
+    /// Get a copy of a poll
+    pub fn get(&self, id: BroadcastId) -> Option<Poll> {
+        self.polls.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Get copies of every tracked poll, oldest first
+    pub fn list(&self) -> Vec<(BroadcastId, Poll)> {
+        self.polls.lock().unwrap().iter().map(|(id, poll)| (*id, poll.clone())).collect()
+    }
+
+    /// Track a poll restored from elsewhere, replacing any with the same id
+    pub fn insert(&self, id: BroadcastId, poll: Poll) {
+        let mut polls = self.polls.lock().unwrap();
+        polls.insert(id, poll);
+        while polls.len() > MAX_TRACKED_BROADCASTS {
+            polls.pop_first();
+        }
+    }
+
+    /// Stop tracking a poll
+    pub fn remove(&self, id: BroadcastId) -> Option<Poll> {
+        self.polls.lock().unwrap().remove(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(uid: u32) -> TargetUser {
+        TargetUser::new(uid, format!("user{}", uid))
+    }
+
+    fn options() -> Vec<String> {
+        DEFAULT_POLL_OPTIONS.iter().map(|option| option.to_string()).collect()
+    }
+
+    #[test]
+    fn test_record_and_tally() {
+        let polls = PollRegistry::new();
+        polls.register(1, options());
+        assert!(polls.record_response(1, &user(1000), "Yes"));
+        assert!(polls.record_response(1, &user(1001), "Yes"));
+        assert!(polls.record_response(1, &user(1002), "Later"));
+
+        let poll = polls.get(1).unwrap();
+        assert_eq!(
+            poll.tally(),
+            vec![("Yes".to_string(), 2), ("No".to_string(), 0), ("Later".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_latest_answer_wins() {
+        let polls = PollRegistry::new();
+        polls.register(1, options());
+        polls.record_response(1, &user(1000), "Later");
+        polls.record_response(1, &user(1000), "No");
+
+        let poll = polls.get(1).unwrap();
+        assert_eq!(poll.responses, vec![(user(1000), "No".to_string())]);
+    }
+
+    #[test]
+    fn test_invalid_responses_ignored() {
+        let polls = PollRegistry::new();
+        polls.register(1, options());
+        assert!(!polls.record_response(1, &user(1000), "Maybe"));
+        assert!(!polls.record_response(2, &user(1000), "Yes"));
+        assert!(polls.get(1).unwrap().responses.is_empty());
+        assert!(!polls.contains(2));
+    }
+
+    #[test]
+    fn test_oldest_polls_evicted() {
+        let polls = PollRegistry::new();
+        for id in 0..=MAX_TRACKED_BROADCASTS as BroadcastId {
+            polls.register(id, options());
+        }
+        assert!(!polls.contains(0));
+        assert!(polls.contains(1));
+        assert!(polls.remove(1).is_some());
+        assert!(!polls.contains(1));
+    }
+}
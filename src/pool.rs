@@ -0,0 +1,235 @@
+//! Pool of connections to users' session buses
+//!
+//! Connecting to a session bus means authenticating and registering with the
+//! bus before the first call, which costs more than delivering the notification
+//! itself. The server keeps the connections it made per user and reuses them for
+//! later deliveries, dropping one once it went unused for
+//! `session_bus_idle_secs`, when the user's session ends or when a call on it
+//! fails. Hits and misses are reported as Prometheus metrics.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an unused connection is kept unless configured otherwise
+pub const DEFAULT_SESSION_BUS_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A pooled connection and who it belongs to
+#[derive(Debug)]
+struct Pooled<C> {
+    uid: u32,
+    connection: C,
+    last_used: Instant,
+}
+
+#[derive(Debug)]
+struct Entries<C> {
+    idle_timeout: Duration,
+    /// Connections by the address of the bus, since a user may have nested session buses
+    connections: HashMap<String, Pooled<C>>,
+}
+
+/// Use of the pool since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Connections reused
+    pub hits: u64,
+    /// Connections that had to be made
+    pub misses: u64,
+    /// Connections dropped for being idle, stale or of an ended session
+    pub evictions: u64,
+    /// Connections currently pooled
+    pub open: usize,
+}
+
+/// Connections to session buses kept for reuse, by user
+///
+/// Pooling is off until an idle timeout is set.
+#[derive(Debug)]
+pub struct ConnectionPool<C = zbus::Connection> {
+    entries: Mutex<Entries<C>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<C> Default for ConnectionPool<C> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                idle_timeout: Duration::ZERO,
+                connections: HashMap::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<C: Clone> ConnectionPool<C> {
+    /// Create a pool that keeps no connections
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep connections until they go unused for `idle_timeout`; zero stops pooling
+    pub fn set_idle_timeout(&self, idle_timeout: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.idle_timeout = idle_timeout;
+        if idle_timeout.is_zero() {
+            entries.connections.clear();
+        }
+    }
+
+    /// Whether connections are kept at all
+    pub fn is_enabled(&self) -> bool {
+        !self.entries.lock().unwrap().idle_timeout.is_zero()
+    }
+
+    /// Take a connection to the bus at `address` for another use, if one is pooled and not idle for too long
+    pub fn get(&self, address: &str) -> Option<C> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.idle_timeout.is_zero() {
+            return None;
+        }
+        let idle_timeout = entries.idle_timeout;
+        match entries.connections.get_mut(address) {
+            Some(pooled) if pooled.last_used.elapsed() < idle_timeout => {
+                pooled.last_used = Instant::now();
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(pooled.connection.clone());
+            }
+            Some(_) => {
+                entries.connections.remove(address);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {}
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// Keep a connection a user made to the bus at `address`
+    pub fn insert(&self, uid: u32, address: &str, connection: C) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.idle_timeout.is_zero() {
+            return;
+        }
+        let pooled = Pooled { uid, connection, last_used: Instant::now() };
+        entries.connections.insert(address.to_string(), pooled);
+    }
+
+    /// Drop the connection to the bus at `address`, such as one a call failed on
+    ///
+    /// Returns whether one was pooled.
+    pub fn remove(&self, address: &str) -> bool {
+        let removed = self.entries.lock().unwrap().connections.remove(address).is_some();
+        if removed {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Drop the connections of users other than `uids`, whose sessions ended
+    pub fn retain_users(&self, uids: &HashSet<u32>) -> usize {
+        self.evict(|pooled| !uids.contains(&pooled.uid))
+    }
+
+    /// Drop the connections that went unused for longer than the idle timeout
+    pub fn evict_idle(&self) -> usize {
+        let idle_timeout = self.entries.lock().unwrap().idle_timeout;
+        self.evict(|pooled| pooled.last_used.elapsed() >= idle_timeout)
+    }
+
+    fn evict(&self, evicted: impl Fn(&Pooled<C>) -> bool) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.connections.len();
+        entries.connections.retain(|_, pooled| !evicted(pooled));
+        let count = before - entries.connections.len();
+        self.evictions.fetch_add(count as u64, Ordering::Relaxed);
+        count
+    }
+
+    /// Get how the pool was used
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            open: self.entries.lock().unwrap().connections.len(),
+        }
+    }
+
+    /// Render the use of the pool in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let stats = self.stats();
+        let mut out = String::new();
+        let counters = [
+            ("hits", "Deliveries that reused a pooled session bus connection.", stats.hits),
+            ("misses", "Deliveries that connected to a session bus.", stats.misses),
+            ("evictions", "Pooled session bus connections dropped.", stats.evictions),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP dots_notifier_session_bus_pool_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE dots_notifier_session_bus_pool_{}_total counter", name);
+            let _ = writeln!(out, "dots_notifier_session_bus_pool_{}_total {}", name, value);
+        }
+        out.push_str("# HELP dots_notifier_session_bus_pool_connections Pooled session bus connections.\n");
+        out.push_str("# TYPE dots_notifier_session_bus_pool_connections gauge\n");
+        let _ = writeln!(out, "dots_notifier_session_bus_pool_connections {}", stats.open);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE_BUS: &str = "unix:path=/run/user/1000/bus";
+    const BOB_BUS: &str = "unix:path=/run/user/1001/bus";
+
+    #[test]
+    fn test_connections_reused() {
+        let pool = ConnectionPool::new();
+        pool.insert(1000, ALICE_BUS, "alice");
+        assert_eq!(pool.get(ALICE_BUS), None, "nothing is pooled without an idle timeout");
+
+        pool.set_idle_timeout(Duration::from_secs(60));
+        assert_eq!(pool.get(ALICE_BUS), None);
+        pool.insert(1000, ALICE_BUS, "alice");
+        assert_eq!(pool.get(ALICE_BUS), Some("alice"));
+        assert_eq!(pool.get(ALICE_BUS), Some("alice"));
+
+        assert!(pool.remove(ALICE_BUS));
+        assert!(!pool.remove(ALICE_BUS));
+        assert_eq!(pool.stats(), PoolStats { hits: 2, misses: 1, evictions: 1, open: 0 });
+
+        let metrics = pool.to_prometheus();
+        assert!(metrics.contains("dots_notifier_session_bus_pool_hits_total 2\n"));
+        assert!(metrics.contains("dots_notifier_session_bus_pool_connections 0\n"));
+    }
+
+    #[test]
+    fn test_connections_evicted() {
+        let pool = ConnectionPool::new();
+        pool.set_idle_timeout(Duration::from_secs(60));
+        pool.insert(1000, ALICE_BUS, "alice");
+        pool.insert(1001, BOB_BUS, "bob");
+
+        // Bob logged out
+        assert_eq!(pool.retain_users(&HashSet::from([1000])), 1);
+        assert_eq!(pool.get(BOB_BUS), None);
+        assert_eq!(pool.evict_idle(), 0);
+
+        pool.set_idle_timeout(Duration::from_nanos(1));
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(pool.evict_idle(), 1);
+        assert_eq!(pool.stats().open, 0);
+
+        pool.set_idle_timeout(Duration::ZERO);
+        assert!(!pool.is_enabled());
+    }
+}
@@ -0,0 +1,198 @@
+//! Rendering profiles of notification daemons
+//!
+//! Desktops render the same notification differently: GNOME Shell expects
+//! symbolic icons, Plasma draws full-color ones, and the popups of minimal
+//! daemons such as mako and dunst only fit a few lines. A profile tunes how a
+//! notification is rendered for the daemon answering on a user's session,
+//! which is recognized by the name it reports from `GetServerInformation`.
+//!
+//! Built-in profiles cover GNOME, KDE, mako and dunst. Profiles configured in
+//! `[profiles.<name>]` override them field by field, or add profiles for other
+//! daemons keyed by their lowercased server name.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::markup::strip_markup;
+
+/// Suffix of symbolic icon names in freedesktop icon themes
+const SYMBOLIC_SUFFIX: &str = "-symbolic";
+
+/// How icon names are chosen for a daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconScheme {
+    /// Monochrome `-symbolic` icons, as GNOME Shell shows them
+    Symbolic,
+    /// Regular full-color icons
+    FullColor,
+}
+
+impl IconScheme {
+    /// Get the name of an icon in this scheme
+    pub fn icon_name(self, icon: &str) -> String {
+        match self {
+            IconScheme::Symbolic if !icon.ends_with(SYMBOLIC_SUFFIX) => format!("{}{}", icon, SYMBOLIC_SUFFIX),
+            IconScheme::FullColor => icon.strip_suffix(SYMBOLIC_SUFFIX).unwrap_or(icon).to_string(),
+            IconScheme::Symbolic => icon.to_string(),
+        }
+    }
+}
+
+/// How notifications are rendered for a daemon; unset fields leave the notification as it is
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderingProfile {
+    /// Whether the daemon renders body markup; markup tags are stripped if not
+    pub markup: Option<bool>,
+    /// Number of characters the body is shortened to
+    pub max_body_length: Option<usize>,
+    /// Whether the daemon can show actions; they are dropped if not
+    pub actions: Option<bool>,
+    /// How icon names are chosen
+    pub icon_scheme: Option<IconScheme>,
+}
+
+impl RenderingProfile {
+    /// Get the built-in profile of a daemon, if there is one
+    pub fn builtin(name: &str) -> Option<Self> {
+        let profile = match name {
+            "gnome" => Self {
+                markup: Some(true),
+                actions: Some(true),
+                icon_scheme: Some(IconScheme::Symbolic),
+                ..Self::default()
+            },
+            "kde" => Self {
+                markup: Some(true),
+                actions: Some(true),
+                icon_scheme: Some(IconScheme::FullColor),
+                ..Self::default()
+            },
+            "mako" | "dunst" => Self {
+                max_body_length: Some(300),
+                icon_scheme: Some(IconScheme::FullColor),
+                ..Self::default()
+            },
+            _ => return None,
+        };
+        Some(profile)
+    }
+
+    /// Override the fields of this profile that are set in another
+    pub fn merged(&self, overrides: &Self) -> Self {
+        Self {
+            markup: overrides.markup.or(self.markup),
+            max_body_length: overrides.max_body_length.or(self.max_body_length),
+            actions: overrides.actions.or(self.actions),
+            icon_scheme: overrides.icon_scheme.or(self.icon_scheme),
+        }
+    }
+
+    /// Render a body, stripping markup and shortening it as the profile asks
+    pub fn body(&self, body: &str) -> String {
+        let mut body = match self.markup {
+            Some(false) => strip_markup(body),
+            _ => body.to_string(),
+        };
+        if let Some(max) = self.max_body_length {
+            if body.chars().count() > max {
+                body = body.chars().take(max.saturating_sub(1)).collect();
+                body.push('…');
+            }
+        }
+        body
+    }
+
+    /// Get the name of an icon in the profile's scheme
+    pub fn icon(&self, icon: &str) -> String {
+        self.icon_scheme.map_or_else(|| icon.to_string(), |scheme| scheme.icon_name(icon))
+    }
+
+    /// Whether actions are offered
+    pub fn offers_actions(&self) -> bool {
+        self.actions != Some(false)
+    }
+}
+
+/// Get the name of the profile for a daemon from the server name it reports
+pub fn profile_name(server_name: &str) -> String {
+    let name = server_name.to_lowercase();
+    match name.as_str() {
+        "gnome shell" | "gnome-shell" => "gnome".to_string(),
+        "plasma" | "kde" | "knotify" => "kde".to_string(),
+        _ => name,
+    }
+}
+
+/// Rendering profiles configured in `[profiles.<name>]`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct RenderingProfiles(HashMap<String, RenderingProfile>);
+
+impl RenderingProfiles {
+    /// Get the profile applying to a daemon, from its server name
+    pub fn for_server(&self, server_name: &str) -> Option<RenderingProfile> {
+        let name = profile_name(server_name);
+        match (RenderingProfile::builtin(&name), self.0.get(&name)) {
+            (Some(builtin), Some(configured)) => Some(builtin.merged(configured)),
+            (builtin, configured) => builtin.or_else(|| configured.cloned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_names() {
+        assert_eq!(profile_name("gnome-shell"), "gnome");
+        assert_eq!(profile_name("Plasma"), "kde");
+        assert_eq!(profile_name("mako"), "mako");
+        assert_eq!(profile_name("SwayNotificationCenter"), "swaynotificationcenter");
+    }
+
+    #[test]
+    fn test_icon_schemes() {
+        assert_eq!(IconScheme::Symbolic.icon_name("dialog-warning"), "dialog-warning-symbolic");
+        assert_eq!(IconScheme::Symbolic.icon_name("dialog-warning-symbolic"), "dialog-warning-symbolic");
+        assert_eq!(IconScheme::FullColor.icon_name("dialog-warning-symbolic"), "dialog-warning");
+        assert_eq!(RenderingProfile::default().icon("dialog-warning-symbolic"), "dialog-warning-symbolic");
+    }
+
+    #[test]
+    fn test_body_rendering() {
+        let mut profile = RenderingProfile {
+            markup: Some(false),
+            ..RenderingProfile::default()
+        };
+        assert_eq!(profile.body("<b>Disk</b> &amp; CPU"), "Disk &amp; CPU");
+        profile.max_body_length = Some(10);
+        assert_eq!(profile.body("<b>Disk</b> &amp; CPU"), "Disk &amp…");
+        assert_eq!(profile.body("<i>Disk</i>"), "Disk");
+        assert_eq!(RenderingProfile::default().body("<b>Disk</b>"), "<b>Disk</b>");
+    }
+
+    #[test]
+    fn test_configured_profiles_override_builtin() {
+        let profiles: RenderingProfiles = toml::from_str(
+            r#"
+            [gnome]
+            max_body_length = 80
+
+            [swaynotificationcenter]
+            actions = false
+            "#,
+        )
+        .unwrap();
+        let gnome = profiles.for_server("gnome-shell").unwrap();
+        assert_eq!(gnome.max_body_length, Some(80));
+        assert_eq!(gnome.icon_scheme, Some(IconScheme::Symbolic));
+        assert!(!profiles.for_server("SwayNotificationCenter").unwrap().offers_actions());
+        assert_eq!(profiles.for_server("mako"), RenderingProfile::builtin("mako"));
+        assert_eq!(profiles.for_server("xfce4-notifyd"), None);
+        assert!(toml::from_str::<RenderingProfiles>("[gnome]\nmarkdown = true").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Quarantine of broadcasts from unknown senders
+//!
+//! The Unix socket may be fed by relays forwarding broadcasts of upstream
+//! systems. With `quarantine_unknown_senders` set, broadcasts arriving on it
+//! from senders without a `sender_names` entry are held here rather than sent,
+//! until an administrator approves or denies them with `dots-notifier
+//! quarantine`, so a misconfigured upstream system cannot reach any desktop on
+//! its own. Held broadcasts are only kept in memory; a restart denies them.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::socket::SocketRequest;
+
+/// Most broadcasts held at once; more are rejected until some are approved or denied
+pub const MAX_QUARANTINED: usize = 256;
+
+/// A broadcast awaiting approval
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedBroadcast {
+    pub id: u64,
+    /// When the broadcast arrived, in seconds since the Unix epoch
+    pub received_at: u64,
+    /// Input the broadcast arrived on, such as `unix-socket`
+    pub input: String,
+    /// Who sent the broadcast, as far as they could be identified
+    pub caller: String,
+    /// Name the broadcast is attributed to once approved
+    pub sender: Option<Arc<str>>,
+    pub request: SocketRequest,
+}
+
+#[derive(Debug, Default)]
+struct Held {
+    last_id: u64,
+    broadcasts: BTreeMap<u64, QuarantinedBroadcast>,
+}
+
+/// Broadcasts held until approved or denied
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    held: Mutex<Held>,
+}
+
+impl Quarantine {
+    /// Create an empty quarantine
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold a broadcast, returning the id it is approved or denied by, unless the quarantine is full
+    pub fn hold(
+        &self,
+        input: &str,
+        caller: String,
+        sender: Option<Arc<str>>,
+        request: SocketRequest,
+        received_at: u64,
+    ) -> Option<u64> {
+        let mut held = self.held.lock().unwrap();
+        if held.broadcasts.len() >= MAX_QUARANTINED {
+            return None;
+        }
+        held.last_id += 1;
+        let id = held.last_id;
+        let broadcast = QuarantinedBroadcast { id, received_at, input: input.to_string(), caller, sender, request };
+        held.broadcasts.insert(id, broadcast);
+        Some(id)
+    }
+
+    /// Get copies of the held broadcasts, oldest first
+    pub fn list(&self) -> Vec<QuarantinedBroadcast> {
+        self.held.lock().unwrap().broadcasts.values().cloned().collect()
+    }
+
+    /// Release a held broadcast, to be sent or dropped
+    pub fn take(&self, id: u64) -> Option<QuarantinedBroadcast> {
+        self.held.lock().unwrap().broadcasts.remove(&id)
+    }
+
+    /// Number of held broadcasts
+    pub fn len(&self) -> usize {
+        self.held.lock().unwrap().broadcasts.len()
+    }
+
+    /// Whether no broadcast is held
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(title: &str) -> SocketRequest {
+        SocketRequest {
+            title: title.to_string(),
+            body: String::new(),
+            channel: None,
+            tags: Vec::new(),
+            allow_duplicate: false,
+        }
+    }
+
+    #[test]
+    fn test_held_until_released() {
+        let quarantine = Quarantine::new();
+        let first = quarantine.hold("unix-socket", "uid:990".to_string(), None, request("Disk full"), 100).unwrap();
+        let second = quarantine.hold("unix-socket", "uid:990".to_string(), None, request("Disk ok"), 160).unwrap();
+        assert_ne!(first, second);
+        let titles: Vec<String> = quarantine.list().into_iter().map(|held| held.request.title).collect();
+        assert_eq!(titles, vec!["Disk full", "Disk ok"]);
+
+        assert_eq!(quarantine.take(first).unwrap().received_at, 100);
+        assert!(quarantine.take(first).is_none());
+        assert_eq!(quarantine.len(), 1);
+    }
+
+    #[test]
+    fn test_full_quarantine_rejects() {
+        let quarantine = Quarantine::new();
+        for _ in 0..MAX_QUARANTINED {
+            assert!(quarantine.hold("unix-socket", String::new(), None, request("Spam"), 0).is_some());
+        }
+        assert!(quarantine.hold("unix-socket", String::new(), None, request("Spam"), 0).is_none());
+    }
+}
@@ -0,0 +1,101 @@
+//! Compositor-specific notification tweaks
+//!
+//! Notification daemons do not all treat the same request alike. Some Wayland
+//! daemons drop the actions of a notification once it times out or unless
+//! extra hints are set, which would make polls unanswerable there. The tweaks
+//! needed for each kind of session are collected here and applied as hints.
+
+use crate::notification::HintValue;
+use crate::payload::BroadcastPayload;
+use crate::types::TargetUser;
+
+/// A tweak applied to notifications delivered to matching sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirk {
+    /// Session type the tweak applies to (`x11` or `wayland`)
+    pub session_type: &'static str,
+    /// Desktops the tweak is limited to, or all desktops of the session type if empty
+    pub desktops: &'static [&'static str],
+    /// Whether the tweak only applies to notifications offering actions
+    pub with_actions: bool,
+    /// Hints set on matching notifications
+    pub hints: &'static [(&'static str, bool)],
+}
+
+impl Quirk {
+    /// Whether the tweak applies to a delivery of a payload to a user
+    pub fn applies_to(&self, user: &TargetUser, payload: &BroadcastPayload) -> bool {
+        user.session_type() == Some(self.session_type)
+            && (self.desktops.is_empty() || user.desktop().is_some_and(|desktop| self.desktops.contains(&desktop)))
+            && (!self.with_actions || !payload.actions.is_empty())
+    }
+}
+
+/// Known tweaks, applied in order
+pub const QUIRKS: &[Quirk] = &[
+    // Wayland daemons such as mako remove expired notifications together with
+    // their actions, so notifications awaiting an answer must stay resident
+    Quirk {
+        session_type: "wayland",
+        desktops: &[],
+        with_actions: true,
+        hints: &[("resident", true)],
+    },
+    // wlroots compositors' daemons only offer actions as buttons when asked to
+    // render them as text
+    Quirk {
+        session_type: "wayland",
+        desktops: &["sway", "Hyprland", "river", "wayfire"],
+        with_actions: true,
+        hints: &[("action-icons", false)],
+    },
+];
+
+/// Get the hints to add to a delivery of a payload to a user
+pub fn quirk_hints(user: &TargetUser, payload: &BroadcastPayload) -> Vec<(&'static str, HintValue)> {
+    QUIRKS
+        .iter()
+        .filter(|quirk| quirk.applies_to(user, payload))
+        .flat_map(|quirk| quirk.hints.iter().map(|&(key, value)| (key, HintValue::from(value))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll() -> BroadcastPayload {
+        BroadcastPayload::new("Reboot", "Tonight?").with_actions(vec!["Yes".to_string(), "No".to_string()])
+    }
+
+    #[test]
+    fn test_wayland_polls_stay_resident() {
+        let user = TargetUser::new(1000, "alice".to_string())
+            .with_session_type("wayland")
+            .with_desktop("GNOME");
+        assert_eq!(quirk_hints(&user, &poll()), vec![("resident", HintValue::Boolean(true))]);
+    }
+
+    #[test]
+    fn test_wlroots_desktops_get_text_actions() {
+        let user = TargetUser::new(1000, "alice".to_string())
+            .with_session_type("wayland")
+            .with_desktop("sway");
+        assert_eq!(
+            quirk_hints(&user, &poll()),
+            vec![("resident", HintValue::Boolean(true)), ("action-icons", HintValue::Boolean(false))]
+        );
+    }
+
+    #[test]
+    fn test_no_tweaks_without_match() {
+        let wayland = TargetUser::new(1000, "alice".to_string()).with_session_type("wayland");
+        assert!(quirk_hints(&wayland, &BroadcastPayload::new("title", "body")).is_empty());
+
+        let x11 = TargetUser::new(1001, "bob".to_string())
+            .with_session_type("x11")
+            .with_desktop("sway");
+        assert!(quirk_hints(&x11, &poll()).is_empty());
+        assert!(quirk_hints(&TargetUser::new(1002, "carol".to_string()), &poll()).is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+//! Rate limiting of broadcasts per sender
+//!
+//! Each sender, identified by uid when the bus daemon or socket peer credentials
+//! vouch for one and by unique bus name otherwise, gets a token bucket: it may
+//! send `sender_burst` broadcasts in a row, after which tokens come back at
+//! `sender_rate_per_minute`. A sender out of tokens is answered with the
+//! `me.section.Notifier.Error.RateLimited` error, whose body carries the number
+//! of seconds to wait after the message, so one client cannot flood every
+//! desktop on the host.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use zbus::message::{Header, Message};
+use zbus::names::ErrorName;
+use zbus::DBusError;
+
+use crate::caller::Caller;
+use crate::rejection::Rejection;
+
+/// Name of the error answering a sender that exceeded its rate limit
+pub const RATE_LIMITED_ERROR: &str = "me.section.Notifier.Error.RateLimited";
+
+/// Number of buckets kept before full ones, which are the same as no bucket, are dropped
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+/// How many broadcasts a sender may make
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Broadcasts per minute on average
+    pub per_minute: u32,
+    /// Broadcasts in a row before the average applies
+    pub burst: u32,
+}
+
+/// Get the key a caller's broadcasts are counted under
+pub fn sender_key(caller: &Caller) -> String {
+    caller.uid.map_or_else(|| caller.bus_name.clone(), |uid| format!("uid:{}", uid))
+}
+
+/// A sender ran out of broadcasts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimited {
+    /// Time until the sender may broadcast again
+    pub retry_after: Duration,
+    message: String,
+}
+
+impl RateLimited {
+    fn new(sender: &str, retry_after: Duration) -> Self {
+        let message = format!(
+            "{} is sending broadcasts too fast, retry after {} seconds",
+            sender,
+            retry_after_secs(retry_after)
+        );
+        Self { retry_after, message }
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Round a delay up to whole seconds, so a client waiting that long is not limited again
+fn retry_after_secs(retry_after: Duration) -> u32 {
+    retry_after.as_secs_f64().ceil() as u32
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets of the senders seen recently
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limit: Option<RateLimit>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Create a limiter, which lets everything through without a limit
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a broadcast from a sender's bucket
+    pub fn check(&self, sender: &str) -> Result<(), RateLimited> {
+        self.check_at(sender, Instant::now())
+    }
+
+    fn check_at(&self, sender: &str, now: Instant) -> Result<(), RateLimited> {
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        let burst = f64::from(limit.burst.max(1));
+        let per_second = f64::from(limit.per_minute.max(1)) / 60.0;
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * per_second).min(burst)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| refill(bucket) < burst);
+        }
+        let bucket = buckets.entry(sender.to_string()).or_insert(Bucket { tokens: burst, updated: now });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / per_second);
+            return Err(RateLimited::new(sender, retry_after));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Error of a request to send a broadcast, which may be rate limited
+#[derive(Debug)]
+pub enum SendError {
+    /// A standard D-Bus error
+    Fdo(zbus::fdo::Error),
+    /// The sender exceeded its rate limit
+    RateLimited(RateLimited),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Fdo(e) => e.fmt(f),
+            SendError::RateLimited(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl From<zbus::fdo::Error> for SendError {
+    fn from(error: zbus::fdo::Error) -> Self {
+        SendError::Fdo(error)
+    }
+}
+
+impl From<Rejection> for SendError {
+    fn from(rejection: Rejection) -> Self {
+        SendError::Fdo(rejection.into())
+    }
+}
+
+impl From<RateLimited> for SendError {
+    fn from(error: RateLimited) -> Self {
+        SendError::RateLimited(error)
+    }
+}
+
+impl DBusError for SendError {
+    fn create_reply(&self, call: &Header<'_>) -> zbus::Result<Message> {
+        match self {
+            SendError::Fdo(e) => e.create_reply(call),
+            SendError::RateLimited(e) => {
+                Message::error(call, self.name())?.build(&(e.message.as_str(), retry_after_secs(e.retry_after)))
+            }
+        }
+    }
+
+    fn name(&self) -> ErrorName<'_> {
+        match self {
+            SendError::Fdo(e) => e.name(),
+            SendError::RateLimited(_) => ErrorName::from_static_str_unchecked(RATE_LIMITED_ERROR),
+        }
+    }
+
+    fn description(&self) -> Option<&str> {
+        match self {
+            SendError::Fdo(e) => e.description(),
+            SendError::RateLimited(e) => Some(&e.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(Some(RateLimit { per_minute: 6, burst: 2 }));
+        let start = Instant::now();
+        assert!(limiter.check_at("uid:1000", start).is_ok());
+        assert!(limiter.check_at("uid:1000", start).is_ok());
+        let limited = limiter.check_at("uid:1000", start).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_secs(10));
+        assert_eq!(limited.to_string(), "uid:1000 is sending broadcasts too fast, retry after 10 seconds");
+        // Other senders have their own bucket
+        assert!(limiter.check_at("uid:1001", start).is_ok());
+
+        let later = start + Duration::from_secs(5);
+        assert_eq!(limiter.check_at("uid:1000", later).unwrap_err().retry_after, Duration::from_secs(5));
+        assert!(limiter.check_at("uid:1000", start + Duration::from_secs(10)).is_ok());
+        // The bucket never holds more than the burst
+        let idle = start + Duration::from_secs(3600);
+        assert!(limiter.check_at("uid:1000", idle).is_ok());
+        assert!(limiter.check_at("uid:1000", idle).is_ok());
+        assert!(limiter.check_at("uid:1000", idle).is_err());
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = RateLimiter::default();
+        for _ in 0..100 {
+            assert!(limiter.check("uid:1000").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_sender_keys() {
+        let caller = Caller { bus_name: ":1.42".to_string(), ..Caller::default() };
+        assert_eq!(sender_key(&caller), ":1.42");
+        assert_eq!(sender_key(&Caller { uid: Some(1000), ..caller }), "uid:1000");
+    }
+
+    #[test]
+    fn test_error_names() {
+        let limited = SendError::from(RateLimited::new("uid:1000", Duration::from_millis(1500)));
+        assert_eq!(limited.name().as_str(), RATE_LIMITED_ERROR);
+        assert_eq!(limited.description(), Some("uid:1000 is sending broadcasts too fast, retry after 2 seconds"));
+        let invalid = SendError::from(zbus::fdo::Error::InvalidArgs("bad".to_string()));
+        assert_eq!(invalid.name().as_str(), "org.freedesktop.DBus.Error.InvalidArgs");
+        assert_eq!(invalid.description(), Some("bad"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Recovery of saved state after an unclean shutdown
+//!
+//! A snapshot saved while spooled notifications were being delivered lists them
+//! as in flight. Their recipients may or may not have seen them, so after a
+//! restart they are reported as interrupted rather than delivered again. Pending
+//! notifications that the broadcast history already records as delivered, and
+//! duplicates, are dropped for the same reason.
+
+use std::fmt;
+
+use crate::archive::StateArchive;
+use crate::broadcast::BroadcastId;
+use crate::delivery::DeliveryStatus;
+use crate::spool::SpooledNotification;
+
+/// An inconsistency in a snapshot that would cause a notification to be shown twice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A notification was being delivered when the snapshot was saved
+    InFlight {
+        broadcast_id: Option<BroadcastId>,
+        uid: u32,
+    },
+    /// A pending notification was already delivered to its recipient
+    AlreadyDelivered { broadcast_id: BroadcastId, uid: u32 },
+    /// The same notification is pending more than once for a recipient
+    Duplicate {
+        broadcast_id: Option<BroadcastId>,
+        uid: u32,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let broadcast = |id: &Option<BroadcastId>| {
+            id.map_or_else(|| "notification".to_string(), |id| format!("broadcast {}", id))
+        };
+        match self {
+            Problem::InFlight { broadcast_id, uid } => {
+                write!(f, "{} to uid {} was interrupted during delivery", broadcast(broadcast_id), uid)
+            }
+            Problem::AlreadyDelivered { broadcast_id, uid } => {
+                write!(f, "broadcast {} is pending for uid {} but was already delivered", broadcast_id, uid)
+            }
+            Problem::Duplicate { broadcast_id, uid } => {
+                write!(f, "{} is pending more than once for uid {}", broadcast(broadcast_id), uid)
+            }
+        }
+    }
+}
+
+/// Whether the history of a snapshot records a pending notification as delivered
+fn already_delivered(archive: &StateArchive, entry: &SpooledNotification) -> Option<BroadcastId> {
+    let id = entry.broadcast_id?;
+    let record = archive.broadcasts.iter().find(|record| record.id == id)?;
+    record
+        .deliveries
+        .iter()
+        .any(|(user, _)| user.uid == entry.user.uid)
+        .then_some(id)
+}
+
+/// Find the problems of a snapshot, without changing it
+pub fn check(archive: &StateArchive) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = archive
+        .in_flight
+        .iter()
+        .map(|entry| Problem::InFlight {
+            broadcast_id: entry.broadcast_id,
+            uid: entry.user.uid,
+        })
+        .collect();
+    for (index, entry) in archive.spool.iter().enumerate() {
+        let uid = entry.user.uid;
+        if let Some(broadcast_id) = already_delivered(archive, entry) {
+            problems.push(Problem::AlreadyDelivered { broadcast_id, uid });
+        } else if archive.spool[..index].contains(entry) {
+            problems.push(Problem::Duplicate {
+                broadcast_id: entry.broadcast_id,
+                uid,
+            });
+        }
+    }
+    problems
+}
+
+/// Fix the problems of a snapshot, returning the problems fixed
+///
+/// Interrupted deliveries are recorded in their broadcast's report, and pending
+/// notifications that were delivered already or are duplicates are dropped.
+pub fn repair(archive: &mut StateArchive) -> Vec<Problem> {
+    let problems = check(archive);
+    for entry in std::mem::take(&mut archive.in_flight) {
+        let Some(record) = archive.broadcasts.iter_mut().find(|record| Some(record.id) == entry.broadcast_id) else {
+            continue;
+        };
+        match record.report.iter_mut().find(|(recipient, _)| recipient.uid == entry.user.uid) {
+            Some((_, status)) => *status = DeliveryStatus::Interrupted,
+            None => record.report.push((entry.user, DeliveryStatus::Interrupted)),
+        }
+    }
+    let spool = std::mem::take(&mut archive.spool);
+    for entry in spool {
+        if already_delivered(archive, &entry).is_none() && !archive.spool.contains(&entry) {
+            archive.spool.push(entry);
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::archive::ARCHIVE_VERSION;
+    use crate::broadcast::BroadcastRegistry;
+    use crate::payload::BroadcastPayload;
+    use crate::types::TargetUser;
+
+    fn archive() -> (StateArchive, BroadcastId) {
+        let registry = BroadcastRegistry::new();
+        let alice = TargetUser::new(1000, "alice".to_string());
+        let bob = TargetUser::new(1001, "bob".to_string());
+        let id = registry.register(None);
+        registry.record_delivery(id, alice.clone(), 7);
+        registry.record_status(id, bob.clone(), DeliveryStatus::Spooled);
+        let entry = |user: &TargetUser| {
+            SpooledNotification::new(user.clone(), Arc::new(BroadcastPayload::new("Reboot", "Tonight")))
+                .with_broadcast_id(id)
+        };
+        let archive = StateArchive {
+            version: ARCHIVE_VERSION,
+            maintenance: false,
+            paused: false,
+            broadcasts: registry.list(None),
+            spool: vec![entry(&alice), entry(&bob), entry(&bob)],
+            in_flight: vec![entry(&bob)],
+            polls: Vec::new(),
+            scheduled: Vec::new(),
+        };
+        (archive, id)
+    }
+
+    #[test]
+    fn test_check() {
+        let (archive, id) = archive();
+        assert_eq!(
+            check(&archive),
+            vec![
+                Problem::InFlight { broadcast_id: Some(id), uid: 1001 },
+                Problem::AlreadyDelivered { broadcast_id: id, uid: 1000 },
+                Problem::Duplicate { broadcast_id: Some(id), uid: 1001 },
+            ]
+        );
+        assert_eq!(
+            check(&archive)[1].to_string(),
+            format!("broadcast {} is pending for uid 1000 but was already delivered", id)
+        );
+    }
+
+    #[test]
+    fn test_repair() {
+        let (mut archive, _) = archive();
+        assert_eq!(repair(&mut archive).len(), 3);
+        assert!(archive.in_flight.is_empty());
+        assert_eq!(archive.spool.len(), 1);
+        assert_eq!(archive.spool[0].user.uid, 1001);
+        assert_eq!(archive.broadcasts[0].report[0].1, DeliveryStatus::Interrupted);
+
+        assert!(check(&archive).is_empty());
+        assert!(repair(&mut archive).is_empty());
+    }
+}
@@ -0,0 +1,289 @@
+//! Rules repeating a scheduled broadcast
+//!
+//! A rule is either a fixed interval, written `every 1h` with the same units as
+//! ages elsewhere, or a five-field cron expression (`minute hour day-of-month
+//! month day-of-week`) evaluated in the server's local time zone. Cron fields
+//! accept `*`, numbers, ranges, lists and `/step`, with Sunday as 0 or 7; like
+//! cron, an expression restricting both the day of the month and the day of the
+//! week matches days satisfying either.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::parse_age;
+
+/// How far ahead the next time matching a cron expression is searched for, in days
+const CRON_HORIZON_DAYS: i64 = 5 * 366;
+
+/// When a recurring broadcast is sent again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Recurrence {
+    /// Repeat after this many seconds
+    Every(u64),
+    /// Repeat whenever the local time matches a cron expression
+    Cron(CronExpr),
+}
+
+impl Recurrence {
+    /// Get the first occurrence after `now`, continuing from the one due at `previous`,
+    /// both in seconds since the Unix epoch
+    ///
+    /// Intervals keep their phase, so occurrences missed while the server was down
+    /// are skipped rather than sent in a burst. `None` if a cron expression never
+    /// matches again, such as one for February 30th.
+    pub fn next_after(&self, previous: u64, now: u64) -> Option<u64> {
+        self.next_after_in(previous, now, &Local)
+    }
+
+    /// Get the first occurrence after `now` like [`Recurrence::next_after`], evaluating cron
+    /// expressions in the time zone `tz`
+    pub fn next_after_in<Tz: TimeZone>(&self, previous: u64, now: u64, tz: &Tz) -> Option<u64> {
+        match self {
+            Self::Every(secs) => {
+                let elapsed = now.saturating_sub(previous);
+                previous.checked_add((elapsed / secs + 1).checked_mul(*secs)?)
+            }
+            Self::Cron(expr) => expr.next_after(now, tz),
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let Some(interval) = rule.trim().strip_prefix("every ") else {
+            return rule.parse().map(Self::Cron);
+        };
+        match parse_age(interval.trim())?.as_secs() {
+            0 => Err("The interval of a recurring broadcast must not be zero".to_string()),
+            secs => Ok(Self::Every(secs)),
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> Self {
+        recurrence.to_string()
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Every(secs) => {
+                let (amount, unit) = [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")]
+                    .into_iter()
+                    .find(|(unit_secs, _)| secs % unit_secs == 0)
+                    .map_or((*secs, "s"), |(unit_secs, unit)| (secs / unit_secs, unit));
+                write!(f, "every {}{}", amount, unit)
+            }
+            Self::Cron(expr) => f.write_str(&expr.expression),
+        }
+    }
+}
+
+/// A parsed five-field cron expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    /// The expression as written, with fields separated by single spaces
+    expression: String,
+    /// One bit per matching minute, hour, day of the month, month and day of the week (Sunday first)
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of the month or of the week is left unrestricted with `*`
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronExpr {
+    /// Whether broadcasts are due on `date`
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// Get the first matching time after `after`, in seconds since the Unix epoch
+    fn next_after<Tz: TimeZone>(&self, after: u64, tz: &Tz) -> Option<u64> {
+        let start = tz.timestamp_opt(i64::try_from(after).ok()?, 0).single()?.naive_local();
+        let mut time = start.date().and_hms_opt(start.hour(), start.minute(), 0)? + TimeDelta::minutes(1);
+        let horizon = time + TimeDelta::days(CRON_HORIZON_DAYS);
+        while time < horizon {
+            if self.months & (1 << time.month()) == 0 {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_date(time.date()) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = time.date().and_hms_opt(time.hour(), 0, 0)? + TimeDelta::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += TimeDelta::minutes(1);
+            } else {
+                // Times skipped by a daylight saving change never come
+                match local_timestamp(tz, &time) {
+                    Some(timestamp) if timestamp > after => return Some(timestamp),
+                    _ => time += TimeDelta::minutes(1),
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Get a local time in seconds since the Unix epoch, the earlier one if it is ambiguous
+fn local_timestamp<Tz: TimeZone>(tz: &Tz, time: &NaiveDateTime) -> Option<u64> {
+    u64::try_from(tz.from_local_datetime(time).earliest()?.timestamp()).ok()
+}
+
+impl FromStr for CronExpr {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "Invalid cron expression '{}', expected minute, hour, day of month, month and day of week",
+                expression
+            ));
+        };
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits = (weekday_bits | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// Parse a cron field into one bit per matching value between `min` and `max`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("Invalid cron field '{}', expected values from {} to {}", field, min, max);
+    let number = |value: &str| {
+        let value = value.parse::<u32>().ok().filter(|value| (min..=max).contains(value));
+        value.ok_or_else(invalid)
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse::<u32>().ok().filter(|&step| step > 0).ok_or_else(invalid)?;
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // A single value with a step runs to the end of the range, as in `5/15`
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for value in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn timestamp(time: &str) -> u64 {
+        let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap();
+        time.and_utc().timestamp() as u64
+    }
+
+    fn next(rule: &str, after: &str) -> Option<u64> {
+        let after = timestamp(after);
+        rule.parse::<Recurrence>().unwrap().next_after_in(after, after, &Utc)
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!("every 1h".parse(), Ok(Recurrence::Every(3600)));
+        assert_eq!("every 90".parse::<Recurrence>().unwrap().to_string(), "every 90s");
+        assert_eq!("every 2d".parse::<Recurrence>().unwrap().to_string(), "every 2d");
+        assert_eq!("0  9 * *  1-5".parse::<Recurrence>().unwrap().to_string(), "0 9 * * 1-5");
+        assert!("every 0m".parse::<Recurrence>().is_err());
+        assert!("every soon".parse::<Recurrence>().is_err());
+        assert!("0 9 * *".parse::<Recurrence>().is_err());
+        assert!("60 9 * * *".parse::<Recurrence>().is_err());
+        assert!("0 9 * * 5-1".parse::<Recurrence>().is_err());
+        assert!("*/0 * * * *".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn test_serialized_as_rule() {
+        let recurrence: Recurrence = "*/15 8-18 * * 1".parse().unwrap();
+        let json = serde_json::to_string(&recurrence).unwrap();
+        assert_eq!(serde_json::from_str::<Recurrence>(&json).unwrap(), recurrence);
+        assert_eq!(serde_json::to_string(&Recurrence::Every(3600)).unwrap(), "\"every 1h\"");
+        assert!(serde_json::from_str::<Recurrence>("\"every never\"").is_err());
+    }
+
+    #[test]
+    fn test_intervals_keep_phase() {
+        let every = Recurrence::Every(3600);
+        assert_eq!(every.next_after_in(1000, 1000, &Utc), Some(4600));
+        // Occurrences missed while the server was down are skipped
+        assert_eq!(every.next_after_in(1000, 12_000, &Utc), Some(15_400));
+    }
+
+    #[test]
+    fn test_cron_next_occurrence() {
+        assert_eq!(next("*/15 * * * *", "2024-06-01 09:07"), Some(timestamp("2024-06-01 09:15")));
+        assert_eq!(next("0 9 * * *", "2024-06-01 09:00"), Some(timestamp("2024-06-02 09:00")));
+        // 2024-06-01 is a Saturday
+        assert_eq!(next("30 8 * * 1-5", "2024-06-01 12:00"), Some(timestamp("2024-06-03 08:30")));
+        assert_eq!(next("0 0 * * 7", "2024-06-01 12:00"), Some(timestamp("2024-06-02 00:00")));
+        assert_eq!(next("0 12 1 1,7 *", "2024-06-01 12:00"), Some(timestamp("2024-07-01 12:00")));
+        assert_eq!(next("0 0 29 2 *", "2024-03-01 00:00"), Some(timestamp("2028-02-29 00:00")));
+        assert_eq!(next("0 0 30 2 *", "2024-03-01 00:00"), None);
+    }
+
+    #[test]
+    fn test_cron_day_of_month_or_week() {
+        // Restricting both matches either: the 15th, or any Monday
+        assert_eq!(next("0 0 15 * 1", "2024-06-01 12:00"), Some(timestamp("2024-06-03 00:00")));
+        assert_eq!(next("0 0 15 * 1", "2024-06-10 12:00"), Some(timestamp("2024-06-15 00:00")));
+        // With the day of the week unrestricted, only the 15th
+        assert_eq!(next("0 0 15 * *", "2024-06-01 12:00"), Some(timestamp("2024-06-15 00:00")));
+    }
+}
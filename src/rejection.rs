@@ -0,0 +1,207 @@
+//! Structured reasons for rejecting the content of a broadcast
+//!
+//! A rejected broadcast is answered with a [`Rejection`] naming the field at
+//! fault, the rule it breaks, where in the field the problem is and how to fix
+//! it, so automation can sanitize the content and retry without parsing prose.
+//! D-Bus errors only carry a message, so the rejection is sent as a JSON object
+//! in the message of an `InvalidArgs` error, or `LimitsExceeded` for content
+//! that is too large; [`Rejection::from_message`] reads it back.
+
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::limits::LimitExceeded;
+
+/// The rule a rejected broadcast breaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RejectionRule {
+    /// The field must not be empty
+    Empty,
+    /// The field contains a control character daemons would show as garbage
+    ControlCharacter,
+    /// The content is larger than allowed
+    TooLarge,
+    /// The same value appears more than once
+    Duplicate,
+    /// The value is not written as the field expects
+    Malformed,
+}
+
+impl fmt::Display for RejectionRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rule = match self {
+            RejectionRule::Empty => "empty",
+            RejectionRule::ControlCharacter => "control-character",
+            RejectionRule::TooLarge => "too-large",
+            RejectionRule::Duplicate => "duplicate",
+            RejectionRule::Malformed => "malformed",
+        };
+        f.write_str(rule)
+    }
+}
+
+/// Why the content of a broadcast was rejected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rejection {
+    /// Field at fault, e.g. `title`, `body`, `tags`, `actions` or `group_key`, or `payload` for the
+    /// content as a whole
+    pub field: String,
+    pub rule: RejectionRule,
+    /// Position of the offending character in the field, counted in characters from 0, or of the
+    /// offending item of a list such as `tags`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    /// The offending character
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub character: Option<char>,
+    /// What is wrong, for people
+    pub message: String,
+    /// How to change the content so it is accepted
+    pub suggestion: String,
+}
+
+impl Rejection {
+    /// Reject a field, with a message and a suggested fix
+    pub fn new(
+        field: impl Into<String>,
+        rule: RejectionRule,
+        message: impl Into<String>,
+        suggestion: impl Into<String>,
+    ) -> Self {
+        Self {
+            field: field.into(),
+            rule,
+            position: None,
+            character: None,
+            message: message.into(),
+            suggestion: suggestion.into(),
+        }
+    }
+
+    /// Point at the offending character of the field
+    pub fn at(mut self, position: usize, character: char) -> Self {
+        self.position = Some(position);
+        self.character = Some(character);
+        self
+    }
+
+    /// Point at the offending item of a list field
+    pub fn at_index(mut self, index: usize) -> Self {
+        self.position = Some(index);
+        self
+    }
+
+    /// Encode the rejection as the JSON message of a D-Bus error
+    pub fn to_message(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| self.message.clone())
+    }
+
+    /// Read a rejection back from the message of a D-Bus error, if it carries one
+    pub fn from_message(message: &str) -> Option<Self> {
+        serde_json::from_str(message).ok()
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.message, self.field)?;
+        if let Some(position) = self.position {
+            write!(f, " at position {}", position)?;
+        }
+        if let Some(character) = self.character {
+            write!(f, ", character U+{:04X}", u32::from(character))?;
+        }
+        write!(f, ", rule {}); {}", self.rule, self.suggestion)
+    }
+}
+
+impl Error for Rejection {}
+
+impl From<Rejection> for zbus::fdo::Error {
+    fn from(rejection: Rejection) -> Self {
+        match rejection.rule {
+            RejectionRule::TooLarge => zbus::fdo::Error::LimitsExceeded(rejection.to_message()),
+            _ => zbus::fdo::Error::InvalidArgs(rejection.to_message()),
+        }
+    }
+}
+
+impl From<LimitExceeded> for Rejection {
+    fn from(error: LimitExceeded) -> Self {
+        let suggestion = match error {
+            LimitExceeded::Payload { limit, .. } => format!("Shorten the title and body to at most {} bytes", limit),
+            LimitExceeded::Pending { .. } => "Retry once pending notifications have been delivered".to_string(),
+        };
+        Rejection::new("payload", RejectionRule::TooLarge, error.to_string(), suggestion)
+    }
+}
+
+/// Check the title and body of a broadcast
+///
+/// The title must have some text, and neither field may contain control
+/// characters other than tabs, or line breaks in the body.
+pub fn check_content(title: &str, body: &str) -> Result<(), Rejection> {
+    if title.trim().is_empty() {
+        return Err(Rejection::new(
+            "title",
+            RejectionRule::Empty,
+            "The title must not be empty",
+            "Give the notification a short title",
+        ));
+    }
+    for (field, text, allowed) in [("title", title, &['\t'][..]), ("body", body, &['\t', '\n'][..])] {
+        let control = text.chars().enumerate().find(|(_, c)| c.is_control() && !allowed.contains(c));
+        if let Some((position, character)) = control {
+            let rejection = Rejection::new(
+                field,
+                RejectionRule::ControlCharacter,
+                format!("The {} contains a control character", field),
+                "Remove the character or replace it with a space",
+            );
+            return Err(rejection.at(position, character));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_content() {
+        assert!(check_content("Reboot tonight", "At 22:00.\n\tSave your work.").is_ok());
+        assert_eq!(check_content("  ", "Body").unwrap_err().rule, RejectionRule::Empty);
+
+        let rejection = check_content("Disk\u{7} full", "Body").unwrap_err();
+        assert_eq!((rejection.field.as_str(), rejection.rule), ("title", RejectionRule::ControlCharacter));
+        assert_eq!((rejection.position, rejection.character), (Some(4), Some('\u{7}')));
+        assert_eq!(check_content("Disk\nfull", "Body").unwrap_err().position, Some(4));
+
+        let rejection = check_content("Disk full", "Über\r\nline").unwrap_err();
+        assert_eq!((rejection.field.as_str(), rejection.position), ("body", Some(4)));
+        assert_eq!(
+            rejection.to_string(),
+            "The body contains a control character (body at position 4, character U+000D, rule control-character); \
+             Remove the character or replace it with a space"
+        );
+    }
+
+    #[test]
+    fn test_round_trip_through_dbus_error() {
+        let rejection = check_content("Disk\u{1b}[31m full", "Body").unwrap_err();
+        let error = zbus::fdo::Error::from(rejection.clone());
+        let zbus::fdo::Error::InvalidArgs(message) = &error else {
+            panic!("unexpected error {:?}", error);
+        };
+        assert_eq!(Rejection::from_message(message), Some(rejection));
+        assert_eq!(Rejection::from_message("Unknown broadcast id 4"), None);
+
+        let too_large = Rejection::from(LimitExceeded::Payload { size: 11, limit: 10 });
+        assert_eq!(too_large.suggestion, "Shorten the title and body to at most 10 bytes");
+        assert!(matches!(zbus::fdo::Error::from(too_large), zbus::fdo::Error::LimitsExceeded(_)));
+    }
+}
@@ -0,0 +1,307 @@
+//! Options of a broadcast request
+//!
+//! `SendWithOptions` takes its optional parameters as a string-keyed
+//! dictionary, so new ones can be added without another D-Bus method. Unknown
+//! keys and values of the wrong type are rejected rather than ignored.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use zbus::zvariant::{OwnedValue, Value};
+
+use crate::types::Urgency;
+
+/// Optional parameters of a broadcast
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SendOptions {
+    /// Channel the broadcast is posted to (`channel`, a string)
+    pub channel: Option<String>,
+    /// Category of the broadcast, such as `monitoring.disk`, picking its sound from `[sound.category]`
+    /// and its channel from the server's `category_channels` unless it names one (`category`, a string)
+    pub category: Option<String>,
+    /// Identity of the alert the broadcast is about, naming its channel through `category_channels`
+    /// (`fingerprint`, a string)
+    pub fingerprint: Option<String>,
+    /// Tags of the broadcast (`tags`, an array of strings)
+    pub tags: Vec<String>,
+    /// Hook run for each recipient after the notification is displayed (`hook`, a string)
+    pub hook: Option<String>,
+    /// Allowlisted commands whose output is appended to the body (`body_commands`, an array of strings)
+    pub body_commands: Vec<String>,
+    /// Urgency overriding the one inferred from the content (`urgency`, `low`, `normal` or `critical`)
+    pub urgency: Option<Urgency>,
+    /// Actions offered with the notification, as `key:label` or a bare key (`actions`, an array of strings)
+    pub actions: Vec<String>,
+    /// Key grouping the broadcast with related ones (`group_key`, a string)
+    pub group_key: Option<String>,
+    /// Send the broadcast even if it repeats one sent within the deduplication window
+    /// (`allow_duplicate`, a boolean)
+    pub allow_duplicate: bool,
+    /// Render bold, italic and links written in Markdown in the body (`markdown`, a boolean)
+    pub markdown: bool,
+    /// Absolute path of an image shown with the notification (`image`, a string)
+    pub image: Option<String>,
+    /// Unix group whose members alone receive the broadcast (`group`, a string)
+    pub group: Option<String>,
+    /// Users skipped, by username or uid (`exclude_users`, an array of strings)
+    pub exclude_users: Vec<String>,
+    /// Seat whose sessions alone receive the broadcast, such as `seat0` (`seat`, a string)
+    pub seat: Option<String>,
+    /// Reply as soon as the broadcast is registered and deliver it in the background, for
+    /// clients following its delivery events (`detach`, a boolean)
+    pub detach: bool,
+    /// Broadcast id, or batch id of a send on remote hosts, whose notifications are updated in place
+    /// instead of stacking a new one on each desktop (`replaces`, a string)
+    pub replaces: Option<String>,
+    /// Percentage of a job done, from 0 to 100, shown as a progress bar (`progress`, an unsigned integer)
+    pub progress: Option<u8>,
+    /// Keep the broadcast for users it is meant for who log in later, until `absent_queue_ttl_secs`
+    /// pass (`queue_for_absent`, a boolean)
+    pub queue_for_absent: bool,
+}
+
+impl SendOptions {
+    /// Parse the options dictionary of a request
+    pub fn from_dict(options: &HashMap<String, OwnedValue>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for (key, value) in options {
+            match key.as_str() {
+                "channel" => parsed.channel = Some(string_option(key, value)?),
+                "category" => parsed.category = Some(string_option(key, value)?),
+                "fingerprint" => parsed.fingerprint = Some(string_option(key, value)?),
+                "tags" => parsed.tags = string_array_option(key, value)?,
+                "hook" => parsed.hook = Some(string_option(key, value)?),
+                "body_commands" => parsed.body_commands = string_array_option(key, value)?,
+                "urgency" => parsed.urgency = Some(string_option(key, value)?.parse()?),
+                "actions" => parsed.actions = string_array_option(key, value)?,
+                "group_key" => parsed.group_key = Some(string_option(key, value)?),
+                "allow_duplicate" => parsed.allow_duplicate = bool_option(key, value)?,
+                "markdown" => parsed.markdown = bool_option(key, value)?,
+                "image" => parsed.image = Some(string_option(key, value)?),
+                "group" => parsed.group = Some(string_option(key, value)?),
+                "exclude_users" => parsed.exclude_users = string_array_option(key, value)?,
+                "seat" => parsed.seat = Some(string_option(key, value)?),
+                "detach" => parsed.detach = bool_option(key, value)?,
+                "replaces" => parsed.replaces = Some(string_option(key, value)?),
+                "progress" => parsed.progress = Some(percent_option(key, value)?),
+                "queue_for_absent" => parsed.queue_for_absent = bool_option(key, value)?,
+                _ => return Err(format!("Unknown option '{}'", key)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Build the options dictionary sent with a request
+    pub fn to_dict(&self) -> HashMap<&'static str, Value<'_>> {
+        let mut options = HashMap::new();
+        if let Some(channel) = &self.channel {
+            options.insert("channel", Value::from(channel.as_str()));
+        }
+        if let Some(category) = &self.category {
+            options.insert("category", Value::from(category.as_str()));
+        }
+        if let Some(fingerprint) = &self.fingerprint {
+            options.insert("fingerprint", Value::from(fingerprint.as_str()));
+        }
+        if !self.tags.is_empty() {
+            options.insert("tags", Value::from(self.tags.clone()));
+        }
+        if let Some(hook) = &self.hook {
+            options.insert("hook", Value::from(hook.as_str()));
+        }
+        if let Some(urgency) = self.urgency {
+            options.insert("urgency", Value::from(urgency.as_str()));
+        }
+        if !self.actions.is_empty() {
+            options.insert("actions", Value::from(self.actions.clone()));
+        }
+        if !self.body_commands.is_empty() {
+            options.insert("body_commands", Value::from(self.body_commands.clone()));
+        }
+        if let Some(group_key) = &self.group_key {
+            options.insert("group_key", Value::from(group_key.as_str()));
+        }
+        if self.allow_duplicate {
+            options.insert("allow_duplicate", Value::from(true));
+        }
+        if self.markdown {
+            options.insert("markdown", Value::from(true));
+        }
+        if let Some(image) = &self.image {
+            options.insert("image", Value::from(image.as_str()));
+        }
+        if let Some(group) = &self.group {
+            options.insert("group", Value::from(group.as_str()));
+        }
+        if !self.exclude_users.is_empty() {
+            options.insert("exclude_users", Value::from(self.exclude_users.clone()));
+        }
+        if let Some(seat) = &self.seat {
+            options.insert("seat", Value::from(seat.as_str()));
+        }
+        if self.detach {
+            options.insert("detach", Value::from(true));
+        }
+        if let Some(replaces) = &self.replaces {
+            options.insert("replaces", Value::from(replaces.as_str()));
+        }
+        if let Some(progress) = self.progress {
+            options.insert("progress", Value::from(u32::from(progress)));
+        }
+        if self.queue_for_absent {
+            options.insert("queue_for_absent", Value::from(true));
+        }
+        options
+    }
+}
+
+/// Split an action given as `key:label` into its key and label; a bare key is its own label
+pub fn parse_action(spec: &str) -> Result<(String, String), String> {
+    let (key, label) = spec.split_once(':').unwrap_or((spec, spec));
+    let (key, label) = (key.trim(), label.trim());
+    if key.is_empty() || label.is_empty() {
+        return Err(format!("Invalid action '{}': expected KEY:LABEL", spec));
+    }
+    Ok((key.to_string(), label.to_string()))
+}
+
+/// Read an option that must be a string
+fn string_option(key: &str, value: &Value<'_>) -> Result<String, String> {
+    match value {
+        Value::Str(s) => Ok(s.to_string()),
+        _ => Err(format!("Option '{}' must be a string", key)),
+    }
+}
+
+/// Read an option that must be a boolean
+fn bool_option(key: &str, value: &Value<'_>) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        _ => Err(format!("Option '{}' must be a boolean", key)),
+    }
+}
+
+/// Read an option that must be a percentage, an unsigned integer up to 100
+fn percent_option(key: &str, value: &Value<'_>) -> Result<u8, String> {
+    match value {
+        Value::U32(percent) if *percent <= 100 => Ok(*percent as u8),
+        _ => Err(format!("Option '{}' must be a percentage from 0 to 100", key)),
+    }
+}
+
+/// Read an option that must be an array of strings
+fn string_array_option(key: &str, value: &Value<'_>) -> Result<Vec<String>, String> {
+    let invalid = || format!("Option '{}' must be an array of strings", key);
+    let Value::Array(array) = value else {
+        return Err(invalid());
+    };
+    array
+        .iter()
+        .map(|item| match item {
+            Value::Str(s) => Ok(s.to_string()),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// A notification of a `SendBatch` request, as written in a batch file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchNotification {
+    /// Title of the notification
+    pub title: String,
+    /// Body text of the notification, empty if omitted
+    #[serde(default)]
+    pub body: String,
+}
+
+impl BatchNotification {
+    /// Parse a batch file, a JSON array of objects with a `title` and an optional `body`
+    pub fn parse_batch(json: &str) -> Result<Vec<Self>, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(options: HashMap<&'static str, Value<'_>>) -> HashMap<String, OwnedValue> {
+        options
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.try_to_owned().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let options = SendOptions {
+            channel: Some("ops".to_string()),
+            category: Some("monitoring.disk".to_string()),
+            fingerprint: Some("a1b2c3".to_string()),
+            tags: vec!["incident-421".to_string()],
+            hook: Some("flash-backlight".to_string()),
+            body_commands: vec!["df -h /".to_string()],
+            urgency: Some(Urgency::Critical),
+            actions: vec!["ack:Acknowledge".to_string()],
+            group_key: Some("backups".to_string()),
+            allow_duplicate: true,
+            markdown: true,
+            image: Some("/usr/share/pixmaps/backup.png".to_string()),
+            group: Some("wheel".to_string()),
+            exclude_users: vec!["kiosk".to_string(), "1001".to_string()],
+            seat: Some("seat0".to_string()),
+            detach: true,
+            replaces: Some("42".to_string()),
+            progress: Some(40),
+            queue_for_absent: true,
+        };
+        assert_eq!(SendOptions::from_dict(&owned(options.to_dict())).unwrap(), options);
+        assert_eq!(SendOptions::from_dict(&HashMap::new()).unwrap(), SendOptions::default());
+        assert!(SendOptions::default().to_dict().is_empty());
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(parse_action("ack:Acknowledge").unwrap(), ("ack".to_string(), "Acknowledge".to_string()));
+        assert_eq!(parse_action("snooze: Remind me: later").unwrap().1, "Remind me: later");
+        assert_eq!(parse_action("ok").unwrap(), ("ok".to_string(), "ok".to_string()));
+        assert!(parse_action(":Acknowledge").is_err());
+        assert!(parse_action("ack:").is_err());
+        assert!(parse_action("").is_err());
+    }
+
+    #[test]
+    fn test_invalid_options_rejected() {
+        let unknown = owned(HashMap::from([("colour", Value::from("red"))]));
+        assert!(SendOptions::from_dict(&unknown).unwrap_err().contains("Unknown option"));
+
+        let wrong_type = owned(HashMap::from([("hook", Value::from(1u32))]));
+        assert!(SendOptions::from_dict(&wrong_type).unwrap_err().contains("must be a string"));
+
+        let invalid_urgency = owned(HashMap::from([("urgency", Value::from("urgent"))]));
+        assert!(SendOptions::from_dict(&invalid_urgency).unwrap_err().contains("Invalid urgency"));
+
+        let wrong_flag = owned(HashMap::from([("allow_duplicate", Value::from("yes"))]));
+        assert!(SendOptions::from_dict(&wrong_flag).unwrap_err().contains("must be a boolean"));
+
+        let overflowing = owned(HashMap::from([("progress", Value::from(101u32))]));
+        assert!(SendOptions::from_dict(&overflowing).unwrap_err().contains("percentage"));
+
+        let wrong_items = owned(HashMap::from([("tags", Value::from(vec![1u32]))]));
+        assert!(SendOptions::from_dict(&wrong_items).is_err());
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let json = r#"[{"title": "Backup done", "body": "/home"}, {"title": "Reboot"}]"#;
+        let batch = BatchNotification::parse_batch(json).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!((batch[0].title.as_str(), batch[0].body.as_str()), ("Backup done", "/home"));
+        assert_eq!(batch[1].body, "");
+
+        assert!(BatchNotification::parse_batch(r#"[{"body": "untitled"}]"#).is_err());
+        assert!(BatchNotification::parse_batch(r#"[{"title": "t", "colour": "red"}]"#).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! Routing decisions for individual recipients
+//!
+//! Every recipient of a broadcast is either notified right away or has the
+//! notification spooled. The decision is made in one place so dry runs
+//! (`route-test`) report exactly what a real broadcast would do.
+
+use std::fmt;
+
+use chrono::{DateTime, Local};
+
+use crate::types::TargetUser;
+
+/// What happens to a broadcast for one recipient
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteDecision {
+    /// Delivered right away
+    Deliver,
+    /// Spooled until the recipient's delivery window opens
+    OutsideWindow,
+    /// Spooled until maintenance mode is switched off
+    Maintenance,
+    /// Spooled until delivery is resumed
+    Paused,
+    /// Spooled until the recipient is shown fewer critical broadcasts than `max_displayed_per_user`
+    Crowded,
+}
+
+impl RouteDecision {
+    /// Whether the notification is delivered right away
+    pub fn delivers_now(self) -> bool {
+        self == RouteDecision::Deliver
+    }
+}
+
+/// A recipient for whom a broadcast was spooled rather than delivered right away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deferral {
+    pub user: TargetUser,
+    pub reason: RouteDecision,
+    /// When the broadcast will be delivered, if known
+    pub until: Option<DateTime<Local>>,
+}
+
+impl Deferral {
+    /// Get the time the broadcast will be delivered as RFC 3339, or an empty string if unknown
+    pub fn until_rfc3339(&self) -> String {
+        self.until.map(|until| until.to_rfc3339()).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for RouteDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteDecision::Deliver => f.write_str("deliver now"),
+            RouteDecision::OutsideWindow => f.write_str("spool until delivery window opens"),
+            RouteDecision::Maintenance => f.write_str("spool until maintenance mode ends"),
+            RouteDecision::Paused => f.write_str("spool until delivery is resumed"),
+            RouteDecision::Crowded => f.write_str("spool until fewer notifications are shown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decisions() {
+        assert!(RouteDecision::Deliver.delivers_now());
+        assert!(!RouteDecision::OutsideWindow.delivers_now());
+        assert_eq!(RouteDecision::Maintenance.to_string(), "spool until maintenance mode ends");
+    }
+
+    #[test]
+    fn test_deferral_until() {
+        let user = TargetUser::new(1000, "alice".to_string());
+        let deferral = Deferral {
+            user,
+            reason: RouteDecision::Maintenance,
+            until: None,
+        };
+        assert_eq!(deferral.until_rfc3339(), "");
+
+        let until = DateTime::parse_from_rfc3339("2024-03-01T09:00:00+01:00").unwrap().with_timezone(&Local);
+        let deferral = Deferral { until: Some(until), ..deferral };
+        assert_eq!(DateTime::parse_from_rfc3339(&deferral.until_rfc3339()).unwrap(), until);
+    }
+}
@@ -0,0 +1,237 @@
+//! Broadcasts scheduled for later
+//!
+//! `send --at` and `send --in` hand the broadcast to the server, which keeps it
+//! here until its time comes and then sends it as if it had just been asked to.
+//! Scheduled broadcasts are part of the saved state, so they survive restarts;
+//! one that fell due while the server was down is sent as soon as it is back.
+//! Recurring broadcasts stay scheduled after being sent, moved on to their next
+//! occurrence.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::recurrence::Recurrence;
+use crate::request::SendOptions;
+
+/// Identifier assigned to each scheduled broadcast
+pub type ScheduleId = u64;
+
+/// A broadcast waiting for its time to come
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledBroadcast {
+    pub id: ScheduleId,
+    /// When to send the broadcast, in seconds since the Unix epoch
+    pub at: u64,
+    pub title: String,
+    pub body: String,
+    pub options: SendOptions,
+    /// Name of the caller who scheduled the broadcast, if identified
+    #[serde(default)]
+    pub sender: Option<Arc<str>>,
+    /// When to send the broadcast again, if it recurs
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+}
+
+#[derive(Debug, Default)]
+struct SchedulerInner {
+    next_id: ScheduleId,
+    entries: BTreeMap<ScheduleId, ScheduledBroadcast>,
+}
+
+/// Broadcasts scheduled for later, in the order they were scheduled
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    inner: Mutex<SchedulerInner>,
+}
+
+impl Scheduler {
+    /// Create an empty scheduler
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedule a broadcast and return its id
+    pub fn add(
+        &self,
+        at: u64,
+        title: String,
+        body: String,
+        options: SendOptions,
+        sender: Option<Arc<str>>,
+    ) -> ScheduleId {
+        self.insert(at, None, title, body, options, sender)
+    }
+
+    /// Schedule a broadcast recurring from `at` on and return its id
+    pub fn add_recurring(
+        &self,
+        at: u64,
+        recurrence: Recurrence,
+        title: String,
+        body: String,
+        options: SendOptions,
+        sender: Option<Arc<str>>,
+    ) -> ScheduleId {
+        self.insert(at, Some(recurrence), title, body, options, sender)
+    }
+
+    fn insert(
+        &self,
+        at: u64,
+        recurrence: Option<Recurrence>,
+        title: String,
+        body: String,
+        options: SendOptions,
+        sender: Option<Arc<str>>,
+    ) -> ScheduleId {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.entries.insert(id, ScheduledBroadcast { id, at, title, body, options, sender, recurrence });
+        id
+    }
+
+    /// Cancel a broadcast scheduled once and return it
+    pub fn cancel(&self, id: ScheduleId) -> Option<ScheduledBroadcast> {
+        self.remove_if(id, false)
+    }
+
+    /// Stop a recurring broadcast and return it
+    pub fn remove_recurring(&self, id: ScheduleId) -> Option<ScheduledBroadcast> {
+        self.remove_if(id, true)
+    }
+
+    fn remove_if(&self, id: ScheduleId, recurring: bool) -> Option<ScheduledBroadcast> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.get(&id)?.recurrence.is_some() != recurring {
+            return None;
+        }
+        inner.entries.remove(&id)
+    }
+
+    /// Get copies of every scheduled broadcast, soonest first
+    pub fn list(&self) -> Vec<ScheduledBroadcast> {
+        let mut entries: Vec<_> = self.inner.lock().unwrap().entries.values().cloned().collect();
+        entries.sort_by_key(|entry| (entry.at, entry.id));
+        entries
+    }
+
+    /// Take the broadcasts due at `now`, in seconds since the Unix epoch, soonest first
+    ///
+    /// Recurring broadcasts are moved on to their first occurrence after `now` instead,
+    /// and dropped once they have none.
+    pub fn take_due(&self, now: u64) -> Vec<ScheduledBroadcast> {
+        let mut inner = self.inner.lock().unwrap();
+        let due: Vec<ScheduleId> = inner.entries.values().filter(|entry| entry.at <= now).map(|e| e.id).collect();
+        let mut taken = Vec::with_capacity(due.len());
+        for id in due {
+            let Some(entry) = inner.entries.remove(&id) else {
+                continue;
+            };
+            let next = entry.recurrence.as_ref().and_then(|recurrence| recurrence.next_after(entry.at, now));
+            if let Some(next) = next {
+                inner.entries.insert(id, ScheduledBroadcast { at: next, ..entry.clone() });
+            }
+            taken.push(entry);
+        }
+        taken.sort_by_key(|entry| (entry.at, entry.id));
+        taken
+    }
+
+    /// Schedule broadcasts restored from elsewhere, replacing any with the same id
+    ///
+    /// Ids assigned afterwards continue after the highest imported id.
+    pub fn import(&self, entries: Vec<ScheduledBroadcast>) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let imported = entries.len();
+        for entry in entries {
+            inner.next_id = inner.next_id.max(entry.id);
+            inner.entries.insert(entry.id, entry);
+        }
+        imported
+    }
+
+    /// Number of scheduled broadcasts
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Whether nothing is scheduled
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(scheduler: &Scheduler, at: u64, title: &str) -> ScheduleId {
+        scheduler.add(at, title.to_string(), "Body".to_string(), SendOptions::default(), None)
+    }
+
+    #[test]
+    fn test_take_due() {
+        let scheduler = Scheduler::new();
+        let late = schedule(&scheduler, 300, "Late");
+        let early = schedule(&scheduler, 100, "Early");
+        let never = schedule(&scheduler, 900, "Later still");
+        let ids: Vec<_> = scheduler.list().iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![early, late, never]);
+
+        assert!(scheduler.take_due(99).is_empty());
+        let due: Vec<_> = scheduler.take_due(300).iter().map(|entry| entry.id).collect();
+        assert_eq!(due, vec![early, late]);
+        assert!(scheduler.take_due(300).is_empty());
+        assert_eq!(scheduler.len(), 1);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let scheduler = Scheduler::new();
+        let id = schedule(&scheduler, 100, "Reboot");
+        assert!(scheduler.remove_recurring(id).is_none());
+        assert_eq!(scheduler.cancel(id).unwrap().title, "Reboot");
+        assert!(scheduler.cancel(id).is_none());
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn test_recurring_moved_on() {
+        let scheduler = Scheduler::new();
+        let options = SendOptions::default();
+        let recurring =
+            scheduler.add_recurring(100, Recurrence::Every(60), "Backup".to_string(), String::new(), options, None);
+        let once = schedule(&scheduler, 100, "Reboot");
+
+        let due: Vec<_> = scheduler.take_due(100).iter().map(|entry| (entry.id, entry.at)).collect();
+        assert_eq!(due, vec![(recurring, 100), (once, 100)]);
+        let next: Vec<_> = scheduler.list().iter().map(|entry| (entry.id, entry.at)).collect();
+        assert_eq!(next, vec![(recurring, 160)]);
+
+        // Occurrences missed in between are sent once
+        assert_eq!(scheduler.take_due(400).len(), 1);
+        assert_eq!(scheduler.list()[0].at, 460);
+
+        assert!(scheduler.cancel(recurring).is_none());
+        assert_eq!(scheduler.remove_recurring(recurring).unwrap().title, "Backup");
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn test_import_continues_ids() {
+        let scheduler = Scheduler::new();
+        let other = Scheduler::new();
+        for at in [100, 200, 300] {
+            schedule(&other, at, "Reboot");
+        }
+        assert_eq!(scheduler.import(other.list()), 3);
+        assert_eq!(schedule(&scheduler, 400, "Reboot"), 4);
+
+        let json = serde_json::to_string(&scheduler.list()).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<ScheduledBroadcast>>(&json).unwrap(), scheduler.list());
+    }
+}
@@ -1,39 +1,393 @@
 //! User session detection and management
 
-use std::collections::HashSet;
-use tracing::{debug, debug_span};
-use zbus::Connection;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, debug_span, Instrument};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::fdo::PropertiesChanged;
+use zbus::{Connection, MatchRule, MessageStream};
 
 use crate::types::TargetUser;
 use crate::dbus::{LoginManagerProxy, SessionProxy, is_graphical_session};
+use crate::error::{NotifierError, NotifierResult};
+use crate::notification::session_bus_pool;
+
+/// Connect to the system bus, where logind lives
+pub async fn connect_system_bus() -> NotifierResult<Connection> {
+    Connection::system().await.map_err(|e| NotifierError::connect("the system bus", e))
+}
 
 /// Get all active graphical user sessions
-pub async fn get_active_graphical_users() -> Result<HashSet<TargetUser>, Box<dyn std::error::Error>> {
+pub async fn get_active_graphical_users() -> NotifierResult<HashSet<TargetUser>> {
+    let sys_bus = connect_system_bus().await?;
+    enumerate_graphical_users(&sys_bus).await.map_err(NotifierError::SessionEnumeration)
+}
+
+/// Enumerate the users with an active graphical session through logind
+async fn enumerate_graphical_users(sys_bus: &Connection) -> zbus::Result<HashSet<TargetUser>> {
     let mut active_users = HashSet::new();
-    let sys_bus = Connection::system().await?;
-    let manager_proxy = LoginManagerProxy::new(&sys_bus).await?;
+    let manager_proxy = LoginManagerProxy::new(sys_bus).await?;
     let sessions = manager_proxy.list_sessions().await?;
-    
-    for (session_id, _uid, username, _seat, session_path) in sessions {
-        let session_span = debug_span!("session_check", id = %session_id, user = %username);
-        let _enter = session_span.enter();
-
-        let session_proxy = SessionProxy::builder(&sys_bus)
-            .path(session_path)?
-            .build()
-            .await?;
-            
-        if session_proxy.active().await?
-            && is_graphical_session(&session_proxy.session_type().await?)
-        {
-            let (uid, _user_path) = session_proxy.user().await?;
-            debug!(uid, "Found active graphical session for user.");
-            active_users.insert(TargetUser::new(uid, username));
+
+    for (_session_id, _uid, _username, _seat, session_path) in sessions {
+        if let Some(user) = graphical_session_user(sys_bus, session_path).await? {
+            // Users with several graphical sessions are notified once, on the first one found
+            if !active_users.iter().any(|active: &TargetUser| active.uid == user.uid) {
+                active_users.insert(user);
+            }
         }
     }
     Ok(active_users)
 }
 
+/// Get the user of a session, if it is an active graphical one
+async fn graphical_session_user(
+    sys_bus: &Connection,
+    session_path: OwnedObjectPath,
+) -> zbus::Result<Option<TargetUser>> {
+    let session_span = debug_span!("session_check", path = %session_path);
+    let session_proxy = SessionProxy::builder(sys_bus)
+        .path(session_path)?
+        .build()
+        .await?;
+    read_graphical_session_user(session_proxy).instrument(session_span).await
+}
+
+/// Read the user of a session through its proxy, if it is an active graphical one
+async fn read_graphical_session_user(session_proxy: SessionProxy<'_>) -> zbus::Result<Option<TargetUser>> {
+
+    if !session_proxy.active().await? {
+        return Ok(None);
+    }
+    let session_type = session_proxy.session_type().await?;
+    if !is_graphical_session(&session_type) {
+        return Ok(None);
+    }
+    let (uid, _user_path) = session_proxy.user().await?;
+    let username = session_proxy.name().await?;
+    let desktop = session_proxy.desktop().await?;
+    let (seat, _seat_path) = session_proxy.seat().await?;
+    debug!(uid, %username, %desktop, %session_type, "Found active graphical session for user.");
+    let mut user = TargetUser::new(uid, username).with_session_type(session_type);
+    if !desktop.is_empty() {
+        user = user.with_desktop(desktop);
+    }
+    Ok(Some(if seat.is_empty() { user } else { user.with_seat(seat) }))
+}
+
+/// Find a user among active users, by name or uid
+pub fn find_user<'a>(users: impl IntoIterator<Item = &'a TargetUser>, user: &str) -> Option<&'a TargetUser> {
+    users.into_iter().find(|candidate| candidate.is(user))
+}
+
+/// Get the ids of the sessions of a user, by name or uid
+pub async fn user_sessions(user: &str) -> NotifierResult<Vec<String>> {
+    let sys_bus = connect_system_bus().await?;
+    let sessions = async { LoginManagerProxy::new(&sys_bus).await?.list_sessions().await }
+        .await
+        .map_err(NotifierError::SessionEnumeration)?;
+    Ok(sessions
+        .into_iter()
+        .filter(|(_, uid, username, _, _)| username == user || uid.to_string() == user)
+        .map(|(session_id, ..)| session_id)
+        .collect())
+}
+
+/// Default time an enumerated session list is reused for
+pub const DEFAULT_SESSION_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// How long a user's notification daemon is waited for once a session hook reported their session ready
+pub const SESSION_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the server waits before following logind's signals again after it lost them
+pub const SESSION_TRACKING_RETRY: Duration = Duration::from_secs(5);
+
+/// Get a user's runtime directory
+pub fn user_runtime_dir(uid: u32) -> String {
+    format!("/run/user/{}", uid)
+}
+
+/// Get the address of a user's session bus
+pub fn user_bus_address(uid: u32) -> String {
+    format!("unix:path={}/bus", user_runtime_dir(uid))
+}
+
+/// Get the address notifications to a user are sent to
+///
+/// A server running as the user itself, on their session bus, uses the bus it
+/// was started on, which need not be the systemd user bus.
+pub fn session_bus_address(uid: u32) -> String {
+    match std::env::var("DBUS_SESSION_BUS_ADDRESS") {
+        Ok(address) if uid == nix::unistd::getuid().as_raw() && !address.is_empty() => address,
+        _ => user_bus_address(uid),
+    }
+}
+
+/// Get the user running this process, with the session details of its environment
+pub fn owning_user() -> Result<TargetUser, Box<dyn std::error::Error>> {
+    let uid = nix::unistd::getuid();
+    let account = nix::unistd::User::from_uid(uid)?.ok_or_else(|| format!("Unknown uid {}", uid))?;
+    Ok(user_from_env(uid.as_raw(), account.name, |name| std::env::var(name).ok()))
+}
+
+/// Build a user from the session variables of their environment
+fn user_from_env(uid: u32, username: String, var: impl Fn(&str) -> Option<String>) -> TargetUser {
+    let mut user = TargetUser::new(uid, username);
+    if let Some(session_type) = var("XDG_SESSION_TYPE").filter(|session_type| !session_type.is_empty()) {
+        user = user.with_session_type(session_type);
+    }
+    if let Some(seat) = var("XDG_SEAT").filter(|seat| !seat.is_empty()) {
+        user = user.with_seat(seat);
+    }
+    // XDG_CURRENT_DESKTOP may list several names, the first being the most specific
+    let desktop = var("XDG_SESSION_DESKTOP")
+        .or_else(|| var("XDG_CURRENT_DESKTOP").and_then(|desktops| desktops.split(':').next().map(str::to_string)))
+        .filter(|desktop| !desktop.is_empty());
+    match desktop {
+        Some(desktop) => user.with_desktop(desktop),
+        None => user,
+    }
+}
+
+/// Recently enumerated active graphical users
+///
+/// Enumerating sessions through logind costs a round trip per session, so the
+/// result is reused for a short time. The server warms the cache on startup so
+/// the first broadcast after boot does not pay the full enumeration latency.
+///
+/// A cache for a server on a session bus only ever holds the bus's owner.
+///
+/// While a [`SessionTracker`] keeps the cache up to date, it never goes stale.
+#[derive(Debug)]
+pub struct SessionCache {
+    ttl: Duration,
+    owner: Option<TargetUser>,
+    entry: Mutex<Option<(Instant, HashSet<TargetUser>)>>,
+    live: AtomicBool,
+}
+
+impl SessionCache {
+    /// Create an empty cache
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            owner: None,
+            entry: Mutex::new(None),
+            live: AtomicBool::new(false),
+        }
+    }
+
+    /// Create a cache that only ever holds the user owning the session bus
+    pub fn for_owner(ttl: Duration, owner: TargetUser) -> Self {
+        Self {
+            owner: Some(owner),
+            ..Self::new(ttl)
+        }
+    }
+
+    /// Get the user owning the session bus, if the server runs on one
+    pub fn owner(&self) -> Option<&TargetUser> {
+        self.owner.as_ref()
+    }
+
+    /// Get the active graphical users, enumerating them again if the cache is stale
+    pub async fn active_users(&self) -> NotifierResult<HashSet<TargetUser>> {
+        match self.cached() {
+            Some(users) => Ok(users),
+            None => self.refresh().await,
+        }
+    }
+
+    /// Enumerate the active graphical users and cache the result
+    pub async fn refresh(&self) -> NotifierResult<HashSet<TargetUser>> {
+        let users = match &self.owner {
+            Some(owner) => HashSet::from([owner.clone()]),
+            None => get_active_graphical_users().await?,
+        };
+        self.store(users.clone());
+        Ok(users)
+    }
+
+    /// Get the cached users, unless the cache is empty or stale
+    pub fn cached(&self) -> Option<HashSet<TargetUser>> {
+        match &*self.entry.lock().unwrap() {
+            Some((fetched, users)) if self.is_live() || fetched.elapsed() < self.ttl => Some(users.clone()),
+            _ => None,
+        }
+    }
+
+    /// Replace the cached users
+    ///
+    /// Pooled connections to the session buses of users no longer found are dropped.
+    pub fn store(&self, users: HashSet<TargetUser>) {
+        let ended = session_bus_pool().retain_users(&users.iter().map(|user| user.uid).collect());
+        if ended > 0 {
+            debug!(ended, "Dropped the session bus connections of ended sessions.");
+        }
+        *self.entry.lock().unwrap() = Some((Instant::now(), users));
+    }
+
+    /// Whether a tracker keeps the cached users up to date, so they never go stale
+    pub fn is_live(&self) -> bool {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Mark the cached users as kept up to date by a tracker, or as enumerated on demand again
+    pub fn set_live(&self, live: bool) {
+        self.live.store(live, Ordering::Relaxed);
+    }
+
+    /// Time since the users were last enumerated, if they ever were
+    pub fn age(&self) -> Option<Duration> {
+        self.entry.lock().unwrap().as_ref().map(|(fetched, _)| fetched.elapsed())
+    }
+
+    /// Number of users in the last enumeration
+    pub fn len(&self) -> usize {
+        self.entry.lock().unwrap().as_ref().map_or(0, |(_, users)| users.len())
+    }
+
+    /// Whether the last enumeration found no users, or none happened yet
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for SessionCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_CACHE_TTL)
+    }
+}
+
+/// Active graphical sessions followed through logind's signals
+///
+/// Instead of listing every session for each broadcast, the tracker lists them
+/// once and then follows logind's `SessionNew` and `SessionRemoved` signals and
+/// the changes of the sessions' properties, such as a session becoming active
+/// when its seat switches to it, keeping a [`SessionCache`] live.
+#[derive(Debug, Default)]
+pub struct SessionTracker {
+    /// Users of the active graphical sessions, by object path of the session
+    sessions: BTreeMap<String, TargetUser>,
+}
+
+impl SessionTracker {
+    /// Create a tracker without sessions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the user of a session if it is an active graphical one, or forget it otherwise
+    ///
+    /// Returns the user if they had no active graphical session before, as they just logged in.
+    pub fn update(&mut self, session_path: &str, user: Option<TargetUser>) -> Option<TargetUser> {
+        let Some(user) = user else {
+            self.sessions.remove(session_path);
+            return None;
+        };
+        let logged_in = !self.sessions.values().any(|active| active.uid == user.uid);
+        self.sessions.insert(session_path.to_string(), user.clone());
+        logged_in.then_some(user)
+    }
+
+    /// Forget a session that ended
+    pub fn remove(&mut self, session_path: &str) {
+        self.sessions.remove(session_path);
+    }
+
+    /// Get the users with an active graphical session, each on the first of their sessions
+    pub fn users(&self) -> HashSet<TargetUser> {
+        let mut users: HashSet<TargetUser> = HashSet::new();
+        for user in self.sessions.values() {
+            if !users.iter().any(|active| active.uid == user.uid) {
+                users.insert(user.clone());
+            }
+        }
+        users
+    }
+
+    /// List the sessions, then follow logind's signals, keeping `cache` up to date
+    ///
+    /// Users logging in while the signals are followed are sent to `logins`.
+    /// Returns once the signals can no longer be followed, leaving the cache to
+    /// enumerate sessions on demand again.
+    pub async fn run(mut self, cache: &SessionCache, logins: &UnboundedSender<TargetUser>) -> NotifierResult<()> {
+        let sys_bus = connect_system_bus().await?;
+        let result = self.follow(&sys_bus, cache, logins).await.map_err(NotifierError::SessionEnumeration);
+        cache.set_live(false);
+        result
+    }
+
+    async fn follow(
+        &mut self,
+        sys_bus: &Connection,
+        cache: &SessionCache,
+        logins: &UnboundedSender<TargetUser>,
+    ) -> zbus::Result<()> {
+        // Subscribe before listing, so a session changing in between is not missed
+        let manager_proxy = LoginManagerProxy::new(sys_bus).await?;
+        let mut created = manager_proxy.receive_session_new().await?;
+        let mut removed = manager_proxy.receive_session_removed().await?;
+        let rule = MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .sender("org.freedesktop.login1")?
+            .interface("org.freedesktop.DBus.Properties")?
+            .member("PropertiesChanged")?
+            .path_namespace("/org/freedesktop/login1/session")?
+            .build();
+        let mut changed = MessageStream::for_match_rule(rule, sys_bus, None).await?;
+
+        for (_session_id, _uid, _username, _seat, session_path) in manager_proxy.list_sessions().await? {
+            let user = graphical_session_user(sys_bus, session_path.clone()).await?;
+            self.update(session_path.as_str(), user);
+        }
+        cache.store(self.users());
+        cache.set_live(true);
+        debug!(sessions = self.sessions.len(), "Following logind for session changes.");
+
+        loop {
+            let session_path = tokio::select! {
+                Some(signal) = created.next() => signal.args()?.object_path.clone(),
+                Some(signal) = removed.next() => {
+                    let session_path = signal.args()?.object_path.clone();
+                    self.remove(session_path.as_str());
+                    cache.store(self.users());
+                    continue;
+                }
+                Some(message) = changed.next() => {
+                    let Some(changes) = PropertiesChanged::from_message(message?) else { continue };
+                    let args = changes.args()?;
+                    let mut names = args.changed_properties().keys().chain(args.invalidated_properties().iter());
+                    // Other properties, such as the idle hint, change often without affecting delivery
+                    if !names.any(|name| matches!(*name, "Active" | "Type")) {
+                        continue;
+                    }
+                    match changes.message().header().path() {
+                        Some(path) => OwnedObjectPath::from(path.to_owned()),
+                        None => continue,
+                    }
+                }
+                else => return Ok(()),
+            };
+            // A session may already be gone again by the time it is looked at
+            let user = graphical_session_user(sys_bus, session_path.clone()).await.unwrap_or_else(|e| {
+                debug!(path = %session_path, "Failed to look at a changed session: {}", e);
+                None
+            });
+            let logged_in = self.update(session_path.as_str(), user);
+            cache.store(self.users());
+            if let Some(user) = logged_in {
+                debug!(uid = user.uid, "User logged in.");
+                let _ = logins.send(user);
+            }
+        }
+    }
+}
+
 /// Filter active sessions to only include graphical ones
 pub fn filter_graphical_sessions<'a>(sessions: impl Iterator<Item = (&'a str, bool, &'a str)>) -> Vec<&'a str> {
     sessions
@@ -45,6 +399,51 @@ pub fn filter_graphical_sessions<'a>(sessions: impl Iterator<Item = (&'a str, bo
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_find_user() {
+        let users = HashSet::from([
+            TargetUser::new(1000, "alice".to_string()),
+            TargetUser::new(1001, "bob".to_string()),
+        ]);
+        assert_eq!(find_user(&users, "alice").unwrap().uid, 1000);
+        assert_eq!(find_user(&users, "1001").unwrap().username, "bob");
+        assert!(find_user(&users, "carol").is_none());
+        assert!(find_user(&users, "0").is_none());
+    }
+
+    #[test]
+    fn test_tracker_follows_sessions() {
+        let mut tracker = SessionTracker::new();
+        let alice = TargetUser::new(1000, "alice".to_string()).with_seat("seat0");
+        assert_eq!(tracker.update("/org/freedesktop/login1/session/_31", Some(alice.clone())), Some(alice.clone()));
+        let second = Some(TargetUser::new(1000, "alice".to_string()));
+        assert_eq!(tracker.update("/org/freedesktop/login1/session/_32", second), None);
+        let bob = TargetUser::new(1001, "bob".to_string());
+        assert_eq!(tracker.update("/org/freedesktop/login1/session/_33", Some(bob.clone())), Some(bob.clone()));
+        assert_eq!(tracker.update("/org/freedesktop/login1/session/_33", Some(bob)), None);
+        assert_eq!(tracker.users().len(), 2);
+        assert!(tracker.users().contains(&alice));
+
+        // Bob switched away from his session, then alice logged out of her first one
+        tracker.update("/org/freedesktop/login1/session/_33", None);
+        tracker.remove("/org/freedesktop/login1/session/_31");
+        let users = tracker.users();
+        assert_eq!(users.len(), 1);
+        assert!(users.iter().all(|user| user.uid == 1000 && user.seat().is_none()));
+    }
+
+    #[test]
+    fn test_live_cache_never_stale() {
+        let cache = SessionCache::new(Duration::ZERO);
+        cache.store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        assert!(cache.cached().is_none());
+        cache.set_live(true);
+        assert_eq!(cache.cached().unwrap().len(), 1);
+        cache.set_live(false);
+        assert!(cache.cached().is_none());
+    }
 
     #[test]
     fn test_filter_graphical_sessions() {
@@ -113,5 +512,57 @@ mod tests {
         assert!(filtered.contains(&"session-6"));
     }
 
-    // Note: get_active_graphical_users() requires actual D-Bus connection and is tested in integration tests
+    #[test]
+    fn test_user_bus_address() {
+        assert_eq!(user_bus_address(1000), "unix:path=/run/user/1000/bus");
+    }
+
+    #[test]
+    fn test_user_from_env() {
+        let env = HashMap::from([
+            ("XDG_SESSION_TYPE", "wayland"),
+            ("XDG_CURRENT_DESKTOP", "sway:wlroots"),
+            ("XDG_SEAT", "seat0"),
+        ]);
+        let user = user_from_env(1000, "alice".to_string(), |name| env.get(name).map(|v| v.to_string()));
+        assert_eq!(user.session_type(), Some("wayland"));
+        assert_eq!(user.desktop(), Some("sway"));
+        assert_eq!(user.seat(), Some("seat0"));
+
+        let user = user_from_env(1000, "alice".to_string(), |_| None);
+        assert_eq!(user.session_type(), None);
+        assert_eq!(user.desktop(), None);
+        assert_eq!(user.seat(), None);
+    }
+
+    #[tokio::test]
+    async fn test_owner_cache_holds_only_owner() {
+        let owner = TargetUser::new(1000, "alice".to_string());
+        let cache = SessionCache::for_owner(Duration::from_secs(60), owner.clone());
+        assert_eq!(cache.owner(), Some(&owner));
+        assert_eq!(cache.active_users().await.unwrap(), HashSet::from([owner]));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_session_cache_store() {
+        let cache = SessionCache::new(Duration::from_secs(60));
+        assert!(cache.cached().is_none());
+        assert!(cache.age().is_none());
+
+        cache.store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        assert_eq!(cache.cached().unwrap().len(), 1);
+        assert!(cache.age().unwrap() < Duration::from_secs(60));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_session_cache_stale() {
+        let cache = SessionCache::new(Duration::ZERO);
+        cache.store(HashSet::from([TargetUser::new(1000, "alice".to_string())]));
+        assert!(cache.cached().is_none());
+        assert!(cache.age().is_some());
+    }
+
+    // Note: get_active_graphical_users() and SessionCache::refresh() require actual D-Bus connection and are tested in integration tests
 }
\ No newline at end of file
@@ -0,0 +1,204 @@
+//! Destinations notifications are delivered to
+//!
+//! The server hands each per-user delivery to a [`NotificationSink`]. The
+//! payload is passed as an `Arc` so a broadcast to hundreds of sessions shares
+//! one copy of its content.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use tracing::debug;
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+use crate::delivery::DeliveryError;
+use crate::image::NotificationImage;
+use crate::nested::{nested_bus_addresses, NestedBusPolicy};
+use crate::notification::{close_notification_for_user, NotificationBuilder};
+use crate::payload::BroadcastPayload;
+use crate::poll::POLL_RESPONSE_TIMEOUT;
+use crate::profile::RenderingProfiles;
+use crate::quirks::quirk_hints;
+use crate::sound::Sound;
+use crate::types::TargetUser;
+
+/// Per-recipient parameters of a delivery
+#[derive(Debug, Clone)]
+pub struct DeliveryOptions {
+    /// Application name shown to the user, in their locale
+    pub app_name: String,
+    /// Icon name shown with the notification
+    pub icon: String,
+    /// Desktop entry of the application the notification is shown as
+    pub desktop_entry: Option<String>,
+    /// Expiration timeout in milliseconds; -1 leaves it to the server and 0 never expires
+    pub timeout: i32,
+    /// Notification to replace in place, or 0 for a new notification
+    pub replaces_id: u32,
+    /// Sound to request
+    pub sound: Sound,
+    /// Bus name of the notification server to deliver to
+    pub bus_name: String,
+    /// Receives the key of the action the user invokes, if responses are collected
+    pub responses: Option<UnboundedSender<String>>,
+    /// Correlation id of the broadcast being delivered, passed on to the notification daemon
+    pub correlation_id: Option<Uuid>,
+    /// Line appended to the body, naming who sent the broadcast
+    pub footer: Option<String>,
+    /// Line written above the notification on the user's terminals, in their locale
+    pub terminal_heading: Option<String>,
+    /// Rendering profiles picked from by the notification daemon answering
+    pub profiles: Arc<RenderingProfiles>,
+    /// Which of the user's session buses are notified, see [`crate::nested`]
+    pub nested_buses: NestedBusPolicy,
+}
+
+impl DeliveryOptions {
+    /// Get the payload as shown to the user, with the footer appended to its body
+    pub fn apply_footer(&self, payload: Arc<BroadcastPayload>) -> Arc<BroadcastPayload> {
+        match &self.footer {
+            Some(footer) => {
+                let mut payload = Arc::unwrap_or_clone(payload);
+                payload.body = format!("{}\n\n{}", payload.body, footer).into();
+                Arc::new(payload)
+            }
+            None => payload,
+        }
+    }
+}
+
+/// A destination notifications can be delivered to
+pub trait NotificationSink: Send + Sync + fmt::Debug {
+    /// Deliver a payload to a user, returning the id assigned to the notification
+    fn notify<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        payload: Arc<BroadcastPayload>,
+        options: &'a DeliveryOptions,
+    ) -> BoxFuture<'a, Result<u32, DeliveryError>>;
+
+    /// Close a notification previously delivered to a user through the server owning `bus_name`
+    fn close<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        bus_name: &'a str,
+        notification_id: u32,
+    ) -> BoxFuture<'a, Result<(), DeliveryError>>;
+}
+
+/// Sink delivering to the notification daemon on each user's session bus
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DbusSink;
+
+impl NotificationSink for DbusSink {
+    fn notify<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        payload: Arc<BroadcastPayload>,
+        options: &'a DeliveryOptions,
+    ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+        Box::pin(async move {
+            let payload = options.apply_footer(payload);
+            let mut notification = NotificationBuilder::new(payload.title.clone(), payload.body.clone())
+                .app_name(options.app_name.as_str())
+                .icon(options.icon.as_str())
+                .timeout(options.timeout)
+                .bus_name(options.bus_name.as_str())
+                .replaces(options.replaces_id)
+                .sound(&options.sound)
+                .profiles(options.profiles.clone())
+                .markdown(payload.markdown);
+            if let Some(desktop_entry) = &options.desktop_entry {
+                notification = notification.desktop_entry(desktop_entry.as_str());
+            }
+            if let Some(urgency) = payload.urgency {
+                notification = notification.urgency(urgency);
+            }
+            if let Some(correlation_id) = options.correlation_id {
+                notification = notification.correlation_id(correlation_id.to_string());
+            }
+            if let Some(sender) = &payload.sender {
+                notification = notification.sender(sender.as_ref());
+            }
+            if let Some(group_key) = &payload.group_key {
+                notification = notification.group(group_key);
+            }
+            if let Some(progress) = payload.progress {
+                notification = notification.progress(progress);
+            }
+            for action in &payload.actions {
+                notification = notification.action(action.as_str(), payload.action_label(action));
+            }
+            // The image was loaded on behalf of the sender; the file is never read here
+            match (&payload.loaded_image, &payload.image) {
+                (Some(image), _) => notification = notification.image(NotificationImage::clone(image)),
+                (None, Some(path)) => notification = notification.image(NotificationImage::Path(path.clone())),
+                (None, None) => {}
+            }
+            for (key, value) in quirk_hints(user, &payload) {
+                notification = notification.hint(key, value);
+            }
+            // Nested sessions get a copy; the notification tracked is the one on the primary bus
+            let nested = (options.nested_buses == NestedBusPolicy::All).then(|| notification.clone());
+            let sent = match options.responses.clone() {
+                Some(responses) => {
+                    notification
+                        .send_to_user_with_responses(user, responses, POLL_RESPONSE_TIMEOUT)
+                        .await
+                }
+                None => notification.send_to_user(user).await,
+            };
+            let notification_id = sent.map_err(|e| DeliveryError::from_error(&e))?;
+            if let Some(nested) = nested {
+                for address in nested_bus_addresses(user.uid) {
+                    match nested.clone().send_to_bus(user, &address).await {
+                        Ok(id) => debug!(uid = user.uid, %address, id, "Notified nested session."),
+                        Err(e) => debug!(uid = user.uid, %address, "Failed to notify nested session: {}", e),
+                    }
+                }
+            }
+            Ok(notification_id)
+        })
+    }
+
+    fn close<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        bus_name: &'a str,
+        notification_id: u32,
+    ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+        Box::pin(async move {
+            close_notification_for_user(user, bus_name, notification_id)
+                .await
+                .map_err(|e| DeliveryError::from_error(&e))
+        })
+    }
+}
+
+/// Sink discarding every notification, for measuring the server without any desktop
+#[derive(Debug, Default)]
+pub struct NullSink {
+    last_id: AtomicU32,
+}
+
+impl NotificationSink for NullSink {
+    fn notify<'a>(
+        &'a self,
+        _user: &'a TargetUser,
+        _payload: Arc<BroadcastPayload>,
+        _options: &'a DeliveryOptions,
+    ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+        Box::pin(async move { Ok(self.last_id.fetch_add(1, Ordering::Relaxed) + 1) })
+    }
+
+    fn close<'a>(
+        &'a self,
+        _user: &'a TargetUser,
+        _bus_name: &'a str,
+        _notification_id: u32,
+    ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+        Box::pin(async { Ok(()) })
+    }
+}
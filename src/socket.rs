@@ -0,0 +1,203 @@
+//! JSON interface on a Unix socket
+//!
+//! Besides D-Bus, the server can accept broadcasts on a Unix socket, one JSON
+//! request per connection answered by one JSON response line. The protocol
+//! and its client only need the standard library and serde, so callers without
+//! a D-Bus stack (initramfs, minimal containers) can still notify the host.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rejection::Rejection;
+
+/// Default location of the socket
+pub const DEFAULT_SOCKET_PATH: &str = "/run/dots-notifier/notifier.sock";
+
+/// Name of the socket as an input of broadcasts, in place of a bus name
+pub const SOCKET_INPUT: &str = "unix-socket";
+
+/// Largest request the server reads, so a client cannot exhaust its memory
+pub const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+/// Longest the server waits for a client to send its request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most clients the server answers at the same time; others wait to be accepted
+pub const MAX_CLIENTS: usize = 32;
+
+/// A broadcast sent over the socket
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocketRequest {
+    pub title: String,
+    pub body: String,
+    /// Channel to post the broadcast to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Send the broadcast even if it repeats one sent within the deduplication window
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_duplicate: bool,
+}
+
+/// The server's answer to a request
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SocketResponse {
+    /// Id of the broadcast, if it was sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_id: Option<u64>,
+    /// Id the broadcast is held in quarantine under, if it awaits approval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine_id: Option<u64>,
+    /// Why the request failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Why the content was rejected, for clients fixing it up and retrying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejection: Option<Rejection>,
+}
+
+impl SocketResponse {
+    /// Answer a request that sent a broadcast
+    pub fn sent(broadcast_id: u64) -> Self {
+        Self { broadcast_id: Some(broadcast_id), ..Self::default() }
+    }
+
+    /// Answer a request held in quarantine until an administrator approves it
+    pub fn quarantined(quarantine_id: u64) -> Self {
+        Self { quarantine_id: Some(quarantine_id), ..Self::default() }
+    }
+
+    /// Answer a request that failed
+    pub fn error(message: impl Into<String>) -> Self {
+        Self { error: Some(message.into()), ..Self::default() }
+    }
+
+    /// Answer a request whose content was rejected
+    pub fn rejected(rejection: Rejection) -> Self {
+        Self { error: Some(rejection.to_string()), rejection: Some(rejection), ..Self::default() }
+    }
+
+    /// Encode the response as a line of JSON
+    pub fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string());
+        line.push('\n');
+        line
+    }
+}
+
+/// Parse a request line
+pub fn parse_request(line: &str) -> Result<SocketRequest, String> {
+    serde_json::from_str(line.trim()).map_err(|e| format!("Invalid request: {}", e))
+}
+
+/// Create the socket the server listens on, replacing a stale one
+///
+/// Only its owner and the members of `group` may connect, and their broadcasts are
+/// authorized like those sent on D-Bus.
+pub fn bind(path: &Path, group: Option<u32>) -> io::Result<tokio::net::UnixListener> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::os::unix::fs::chown(path, None, group)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+/// Send a request to the server listening on a socket, returning the id of the broadcast
+pub fn send_request(path: impl AsRef<Path>, request: &SocketRequest) -> io::Result<u64> {
+    let mut stream = UnixStream::connect(path)?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    let response: SocketResponse = serde_json::from_str(&answer)?;
+    match (response.broadcast_id, response.error) {
+        (_, Some(error)) => Err(io::Error::other(error)),
+        (Some(broadcast_id), None) => Ok(broadcast_id),
+        (None, None) => match response.quarantine_id {
+            Some(id) => Err(io::Error::other(format!("Held in quarantine as {} until approved on the server", id))),
+            None => Err(io::Error::other("The server sent an empty response")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rejection::RejectionRule;
+
+    #[test]
+    fn test_request_round_trip() {
+        let request = SocketRequest {
+            title: "Reboot".to_string(),
+            body: "Tonight".to_string(),
+            channel: None,
+            tags: vec!["patching".to_string()],
+            allow_duplicate: false,
+        };
+        let line = serde_json::to_string(&request).unwrap();
+        assert_eq!(line, r#"{"title":"Reboot","body":"Tonight","tags":["patching"]}"#);
+        assert_eq!(parse_request(&line).unwrap(), request);
+        assert!(parse_request(r#"{"title":"t","body":"b","urgency":"critical"}"#).is_err());
+        assert!(parse_request("reboot").is_err());
+    }
+
+    #[test]
+    fn test_response_lines() {
+        assert_eq!(SocketResponse::sent(42).to_line(), "{\"broadcast_id\":42}\n");
+        assert_eq!(SocketResponse::error("nope").to_line(), "{\"error\":\"nope\"}\n");
+        assert_eq!(SocketResponse::quarantined(3).to_line(), "{\"quarantine_id\":3}\n");
+        let rejection = Rejection::new("tags", RejectionRule::Empty, "Tags must not be empty", "Remove the empty tag");
+        let line = SocketResponse::rejected(rejection.clone().at_index(1)).to_line();
+        assert_eq!(
+            line,
+            "{\"error\":\"Tags must not be empty (tags at position 1, rule empty); Remove the empty tag\",\
+             \"rejection\":{\"field\":\"tags\",\"rule\":\"empty\",\"position\":1,\
+             \"message\":\"Tags must not be empty\",\"suggestion\":\"Remove the empty tag\"}}\n"
+        );
+    }
+
+    #[test]
+    fn test_send_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notifier.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let error = SocketResponse::error("Payload too large");
+            for answer in [SocketResponse::sent(7), error, SocketResponse::quarantined(2)] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                assert_eq!(parse_request(&line).unwrap().title, "t");
+                (&stream).write_all(answer.to_line().as_bytes()).unwrap();
+            }
+        });
+
+        let request = SocketRequest {
+            title: "t".to_string(),
+            body: "b".to_string(),
+            channel: None,
+            tags: Vec::new(),
+            allow_duplicate: false,
+        };
+        assert_eq!(send_request(&path, &request).unwrap(), 7);
+        assert_eq!(send_request(&path, &request).unwrap_err().to_string(), "Payload too large");
+        let quarantined = send_request(&path, &request).unwrap_err().to_string();
+        assert_eq!(quarantined, "Held in quarantine as 2 until approved on the server");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Sound theme integration
+//!
+//! Notifications name an event from the user's XDG sound theme via the
+//! `sound-name` hint instead of pointing at sound files, so audible alerts
+//! follow whatever theme the user has selected.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::types::Urgency;
+
+/// Sound to request for a notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sound {
+    /// Leave the choice to the notification daemon
+    DaemonDefault,
+    /// Ask the daemon not to play any sound
+    Muted,
+    /// Play the named sound theme event
+    Named(String),
+}
+
+/// Mapping of notifications to XDG sound theme event names
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoundConfig {
+    /// Suppress sounds for all notifications
+    pub mute: bool,
+    /// Event name used when no urgency or category mapping applies
+    pub default: Option<String>,
+    /// Event names keyed by urgency
+    pub urgency: HashMap<Urgency, String>,
+    /// Event names keyed by notification category (e.g. `device.added` or `device`)
+    pub category: HashMap<String, String>,
+}
+
+impl SoundConfig {
+    /// Pick the sound for a notification
+    ///
+    /// A category mapping takes precedence over an urgency mapping. Categories
+    /// are matched exactly first, then by their class (the part before the dot).
+    pub fn resolve(&self, urgency: Option<Urgency>, category: Option<&str>) -> Sound {
+        if self.mute {
+            return Sound::Muted;
+        }
+
+        let by_category = category.and_then(|category| {
+            self.category.get(category).or_else(|| {
+                category
+                    .split_once('.')
+                    .and_then(|(class, _)| self.category.get(class))
+            })
+        });
+        let by_urgency = urgency.and_then(|urgency| self.urgency.get(&urgency));
+
+        by_category
+            .or(by_urgency)
+            .or(self.default.as_ref())
+            .map_or(Sound::DaemonDefault, |name| Sound::Named(name.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SoundConfig {
+        toml::from_str(
+            r#"
+            default = "message-new-instant"
+
+            [urgency]
+            critical = "dialog-warning"
+
+            [category]
+            "device.added" = "device-added"
+            network = "network-connectivity-established"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_default_config_leaves_choice_to_daemon() {
+        let config = SoundConfig::default();
+        assert_eq!(config.resolve(None, None), Sound::DaemonDefault);
+        assert_eq!(config.resolve(Some(Urgency::Critical), Some("device")), Sound::DaemonDefault);
+    }
+
+    #[test]
+    fn test_mute_overrides_everything() {
+        let config = SoundConfig {
+            mute: true,
+            ..config()
+        };
+        assert_eq!(config.resolve(None, None), Sound::Muted);
+        assert_eq!(config.resolve(Some(Urgency::Critical), Some("device.added")), Sound::Muted);
+    }
+
+    #[test]
+    fn test_resolve_precedence() {
+        let config = config();
+        let named = |name: &str| Sound::Named(name.to_string());
+
+        assert_eq!(config.resolve(None, None), named("message-new-instant"));
+        assert_eq!(config.resolve(Some(Urgency::Normal), None), named("message-new-instant"));
+        assert_eq!(config.resolve(Some(Urgency::Critical), None), named("dialog-warning"));
+        assert_eq!(config.resolve(Some(Urgency::Critical), Some("device.added")), named("device-added"));
+        assert_eq!(config.resolve(None, Some("network.connected")), named("network-connectivity-established"));
+        assert_eq!(config.resolve(Some(Urgency::Critical), Some("device.removed")), named("dialog-warning"));
+    }
+
+    #[test]
+    fn test_invalid_urgency_key_rejected() {
+        assert!(toml::from_str::<SoundConfig>("[urgency]\nurgent = \"bell\"").is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! In-memory spool for notifications that cannot be delivered yet
+//!
+//! Notifications taken from the spool for delivery are tracked as in flight
+//! until their delivery finishes, so a snapshot saved in between records that
+//! they may already have been shown, and a restart does not deliver them twice.
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::broadcast::BroadcastId;
+use crate::limits::{LimitExceeded, DEFAULT_MAX_PENDING_BYTES};
+use crate::payload::BroadcastPayload;
+use crate::types::TargetUser;
+
+/// A notification held back for later delivery to a single user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpooledNotification {
+    pub user: TargetUser,
+    pub payload: Arc<BroadcastPayload>,
+    pub broadcast_id: Option<BroadcastId>,
+    /// Held until a notification daemon appears on the user's session bus, after delivery failed
+    #[serde(default)]
+    pub parked: bool,
+}
+
+impl SpooledNotification {
+    /// Create a new spooled notification
+    pub fn new(user: TargetUser, payload: Arc<BroadcastPayload>) -> Self {
+        Self {
+            user,
+            payload,
+            broadcast_id: None,
+            parked: false,
+        }
+    }
+
+    /// Set the broadcast the notification belongs to
+    pub fn with_broadcast_id(mut self, broadcast_id: BroadcastId) -> Self {
+        self.broadcast_id = Some(broadcast_id);
+        self
+    }
+
+    /// Hold the notification until the user's notification daemon returns
+    pub fn parked(mut self) -> Self {
+        self.parked = true;
+        self
+    }
+
+    /// Approximate number of bytes of memory held by this notification
+    ///
+    /// The payload is counted in full even when shared with other entries,
+    /// so the accounting errs on the side of overestimating.
+    pub fn size(&self) -> usize {
+        mem::size_of::<Self>() + self.user.username.len() + self.payload.size()
+    }
+}
+
+#[derive(Debug, Default)]
+struct SpoolInner {
+    entries: Vec<SpooledNotification>,
+    in_flight: Vec<SpooledNotification>,
+    bytes: usize,
+}
+
+/// Queue of notifications waiting to be delivered
+///
+/// The memory held by spooled notifications is accounted, and notifications
+/// that would exceed the spool's limit are refused.
+#[derive(Debug)]
+pub struct Spool {
+    inner: Mutex<SpoolInner>,
+    max_bytes: usize,
+}
+
+impl Spool {
+    /// Create an empty spool with the default memory limit
+    pub fn new() -> Self {
+        Self::with_limit(DEFAULT_MAX_PENDING_BYTES)
+    }
+
+    /// Create an empty spool holding at most `max_bytes` of notifications
+    pub fn with_limit(max_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(SpoolInner::default()),
+            max_bytes,
+        }
+    }
+
+    /// Add a notification to the spool, unless it would exceed the memory limit
+    pub fn push(&self, notification: SpooledNotification) -> Result<(), LimitExceeded> {
+        let mut inner = self.inner.lock().unwrap();
+        let size = inner.bytes + notification.size();
+        if size > self.max_bytes {
+            return Err(LimitExceeded::Pending {
+                size,
+                limit: self.max_bytes,
+            });
+        }
+        inner.bytes = size;
+        inner.entries.push(notification);
+        Ok(())
+    }
+
+    /// Add a notification to the spool, replacing pending notifications of its group for the same user
+    ///
+    /// Returns the notifications replaced, so their broadcasts can record that they were superseded.
+    pub fn push_coalesced(&self, notification: SpooledNotification) -> Result<Vec<SpooledNotification>, LimitExceeded> {
+        let Some(group_key) = notification.payload.group_key.clone() else {
+            return self.push(notification).map(|()| Vec::new());
+        };
+        let uid = notification.user.uid;
+        let superseded = self.take_ready(|entry| entry.user.uid == uid && entry.payload.in_group(&group_key));
+        self.push(notification)?;
+        Ok(superseded)
+    }
+
+    /// Number of notifications currently spooled
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Approximate number of bytes of memory held by spooled notifications
+    pub fn memory_usage(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+
+    /// Whether the spool is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Modify spooled notifications in place
+    ///
+    /// The memory limit is not enforced here, as the notifications are already spooled.
+    pub fn update(&self, f: impl FnMut(&mut SpooledNotification)) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.iter_mut().for_each(f);
+        inner.bytes = inner.entries.iter().map(SpooledNotification::size).sum();
+    }
+
+    /// Get copies of the spooled notifications, in the order they were spooled
+    pub fn entries(&self) -> Vec<SpooledNotification> {
+        self.inner.lock().unwrap().entries.clone()
+    }
+
+    /// Remove and return every spooled notification for which `ready` returns true,
+    /// preserving the order in which they were spooled
+    pub fn take_ready(&self, mut ready: impl FnMut(&SpooledNotification) -> bool) -> Vec<SpooledNotification> {
+        let mut inner = self.inner.lock().unwrap();
+        let (taken, kept): (Vec<_>, Vec<_>) = inner.entries.drain(..).partition(|entry| ready(entry));
+        inner.entries = kept;
+        inner.bytes -= taken.iter().map(SpooledNotification::size).sum::<usize>();
+        taken
+    }
+
+    /// Like [`Spool::take_ready`], but keep the taken notifications in flight
+    /// until [`Spool::finish_delivery`] is called for them
+    pub fn start_delivery(&self, ready: impl FnMut(&SpooledNotification) -> bool) -> Vec<SpooledNotification> {
+        let taken = self.take_ready(ready);
+        self.inner.lock().unwrap().in_flight.extend(taken.iter().cloned());
+        taken
+    }
+
+    /// Stop tracking notifications whose delivery finished, successfully or not
+    pub fn finish_delivery(&self, delivered: &[SpooledNotification]) {
+        let mut inner = self.inner.lock().unwrap();
+        for entry in delivered {
+            if let Some(index) = inner.in_flight.iter().position(|in_flight| in_flight == entry) {
+                inner.in_flight.remove(index);
+            }
+        }
+    }
+
+    /// Get copies of the notifications currently being delivered
+    pub fn in_flight(&self) -> Vec<SpooledNotification> {
+        self.inner.lock().unwrap().in_flight.clone()
+    }
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(uid: u32, title: &str) -> SpooledNotification {
+        let payload = Arc::new(BroadcastPayload::new(title, "body"));
+        SpooledNotification::new(TargetUser::new(uid, format!("user{}", uid)), payload)
+    }
+
+    #[test]
+    fn test_spool_push_and_len() {
+        let spool = Spool::new();
+        assert!(spool.is_empty());
+
+        spool.push(notification(1000, "first")).unwrap();
+        spool.push(notification(1001, "second")).unwrap();
+        assert_eq!(spool.len(), 2);
+        assert!(!spool.is_empty());
+    }
+
+    #[test]
+    fn test_take_ready_partitions_entries() {
+        let spool = Spool::new();
+        spool.push(notification(1000, "first")).unwrap();
+        spool.push(notification(1001, "second")).unwrap();
+        spool.push(notification(1000, "third")).unwrap();
+
+        let ready = spool.take_ready(|n| n.user.uid() == 1000);
+        assert_eq!(ready.len(), 2);
+        assert_eq!(&*ready[0].payload.title, "first");
+        assert_eq!(&*ready[1].payload.title, "third");
+
+        assert_eq!(spool.len(), 1);
+        let remaining = spool.take_ready(|_| true);
+        assert_eq!(&*remaining[0].payload.title, "second");
+        assert!(spool.is_empty());
+    }
+
+    #[test]
+    fn test_push_coalesced() {
+        let grouped = |uid, title: &str| {
+            let payload = BroadcastPayload::new(title, "body").with_group_key(Some("backups".to_string()));
+            SpooledNotification::new(TargetUser::new(uid, format!("user{}", uid)), Arc::new(payload))
+        };
+        let spool = Spool::new();
+        assert!(spool.push_coalesced(grouped(1000, "started")).unwrap().is_empty());
+        assert!(spool.push_coalesced(grouped(1001, "started")).unwrap().is_empty());
+        assert!(spool.push_coalesced(notification(1000, "ungrouped")).unwrap().is_empty());
+
+        let superseded = spool.push_coalesced(grouped(1000, "finished")).unwrap();
+        assert_eq!(superseded, vec![grouped(1000, "started")]);
+        let titles: Vec<String> = spool.entries().iter().map(|entry| entry.payload.title.to_string()).collect();
+        assert_eq!(titles, vec!["started", "ungrouped", "finished"]);
+        assert_eq!(spool.memory_usage(), spool.entries().iter().map(SpooledNotification::size).sum::<usize>());
+    }
+
+    #[test]
+    fn test_update() {
+        let spool = Spool::new();
+        spool.push(notification(1000, "first")).unwrap();
+        spool.push(notification(1001, "second")).unwrap();
+
+        spool.update(|n| {
+            if n.user.uid() == 1001 {
+                n.payload = Arc::new(BroadcastPayload::new("updated", "body"));
+            }
+        });
+
+        let entries = spool.take_ready(|_| true);
+        assert_eq!(&*entries[0].payload.title, "first");
+        assert_eq!(&*entries[1].payload.title, "updated");
+    }
+
+    #[test]
+    fn test_take_ready_none() {
+        let spool = Spool::new();
+        spool.push(notification(1000, "first")).unwrap();
+        assert!(spool.take_ready(|_| false).is_empty());
+        assert_eq!(spool.len(), 1);
+    }
+
+    #[test]
+    fn test_memory_accounting() {
+        let spool = Spool::new();
+        let first = notification(1000, "first");
+        let second = notification(1001, "second");
+        let expected = first.size() + second.size();
+        spool.push(first).unwrap();
+        spool.push(second).unwrap();
+        assert_eq!(spool.memory_usage(), expected);
+
+        spool.update(|n| n.payload = Arc::new(BroadcastPayload::new("first", "a much longer body than before")));
+        assert!(spool.memory_usage() > expected);
+
+        let taken: usize = spool.take_ready(|n| n.user.uid() == 1000).iter().map(SpooledNotification::size).sum();
+        assert_eq!(spool.memory_usage(), taken);
+        spool.take_ready(|_| true);
+        assert_eq!(spool.memory_usage(), 0);
+    }
+
+    #[test]
+    fn test_in_flight_tracking() {
+        let spool = Spool::new();
+        spool.push(notification(1000, "first")).unwrap();
+        spool.push(notification(1001, "second")).unwrap();
+
+        let taken = spool.start_delivery(|n| n.user.uid() == 1000);
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.in_flight(), taken);
+
+        spool.finish_delivery(&taken);
+        assert!(spool.in_flight().is_empty());
+        assert_eq!(spool.entries().len(), 1);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let entry = notification(1000, "first");
+        let spool = Spool::with_limit(entry.size() + 1);
+        spool.push(entry.clone()).unwrap();
+
+        let error = spool.push(entry.clone()).unwrap_err();
+        assert_eq!(error, LimitExceeded::Pending { size: 2 * entry.size(), limit: entry.size() + 1 });
+        assert_eq!(spool.len(), 1);
+    }
+}
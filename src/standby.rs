@@ -0,0 +1,82 @@
+//! Warm standby of a second server instance
+//!
+//! A crashed server must not take the scheduled and queued notifications of a
+//! terminal server down with it. With `warm_standby`, two instances sharing a
+//! persistent store run side by side: the first to start owns the server's bus
+//! name and works as usual, while the other queues for the name with the bus
+//! daemon and idles. When the primary exits or crashes, the bus daemon hands the
+//! name to the standby, which restores the state the primary last saved to the
+//! store and takes over. Callers keep addressing the same name throughout,
+//! though requests accepted by the primary after its last save are lost.
+
+use futures::StreamExt;
+use zbus::fdo::{DBusProxy, RequestNameReply};
+use zbus::names::WellKnownName;
+use zbus::Connection;
+
+/// Acquire a bus name, waiting as a standby while another instance owns it
+///
+/// `queued` is called if the name is owned by another instance, before waiting
+/// for the bus daemon to hand it over. Once acquired, the name is never given up
+/// to a restarted primary, which queues as the new standby instead.
+pub async fn acquire_name(connection: &Connection, name: &str, queued: impl FnOnce()) -> zbus::Result<()> {
+    let name = WellKnownName::try_from(name)?;
+    let dbus_proxy = DBusProxy::new(connection).await?;
+    // Subscribe before requesting, so the name being handed over in between is not missed
+    let mut acquired = dbus_proxy.receive_name_acquired().await?;
+    match connection.request_name_with_flags(name.clone(), Default::default()).await? {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => return Ok(()),
+        RequestNameReply::InQueue => queued(),
+        RequestNameReply::Exists => return Err(zbus::Error::NameTaken),
+    }
+    while let Some(signal) = acquired.next().await {
+        if signal.args()?.name() == &name {
+            return Ok(());
+        }
+    }
+    Err(zbus::Error::Failure(format!("the bus closed before {} was handed over", name)))
+}
+
+#[cfg(all(test, feature = "echo-daemon"))]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::echo::PrivateBus;
+
+    #[tokio::test]
+    async fn test_standby_takes_over_name() {
+        let Ok(bus) = PrivateBus::start().await else {
+            // dbus-daemon is not installed
+            return;
+        };
+        let connect = || async { zbus::connection::Builder::address(bus.address.as_str()).unwrap().build().await };
+        let primary = connect().await.unwrap();
+        acquire_name(&primary, "org.example.Notifier", || panic!("the name is free")).await.unwrap();
+
+        let standby = connect().await.unwrap();
+        let was_queued = Arc::new(AtomicBool::new(false));
+        let takeover = tokio::spawn({
+            let standby = standby.clone();
+            let was_queued = was_queued.clone();
+            async move {
+                acquire_name(&standby, "org.example.Notifier", || was_queued.store(true, Ordering::SeqCst)).await
+            }
+        });
+        while !was_queued.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+        assert!(!takeover.is_finished());
+
+        drop(primary);
+        takeover.await.unwrap().unwrap();
+        let owner = DBusProxy::new(&standby)
+            .await
+            .unwrap()
+            .get_name_owner(WellKnownName::try_from("org.example.Notifier").unwrap().into())
+            .await
+            .unwrap();
+        assert_eq!(Some(owner.as_ref()), standby.unique_name().map(|name| name.as_ref()));
+    }
+}
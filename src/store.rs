@@ -0,0 +1,474 @@
+//! Persistence of the spool and broadcast history
+//!
+//! The server works on pending notifications, tracked broadcasts and polls in
+//! memory, and periodically saves a snapshot of them to a [`Store`], restoring
+//! the latest one at startup. The in-memory store keeps nothing across restarts,
+//! which suits tests and hosts where losing pending notifications is fine; the
+//! file and SQLite stores keep them in the state directory. Other backends, such
+//! as a database shared by a large fleet, implement the same trait.
+//!
+//! Saving must never leave a half-written snapshot behind, even on power loss:
+//! the file store writes a new file and atomically renames it over the old one,
+//! and the SQLite store replaces the snapshot in one transaction of a database in
+//! write-ahead logging mode.
+//!
+//! State saved by an earlier release is migrated when loaded, after the file or
+//! database was copied next to it, so a failed migration never loses the state.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::archive::{StateArchive, ARCHIVE_VERSION};
+use crate::migrate::{archive_version, backup_path};
+
+/// Name of the SQLite database in the state directory
+pub const DEFAULT_STORE_FILE: &str = "state.sqlite";
+
+/// Name of the JSON snapshot in the state directory
+pub const DEFAULT_SNAPSHOT_FILE: &str = "state.json";
+
+/// Result of a storage operation
+pub type StoreResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Where snapshots of the server state are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StoreBackend {
+    /// Keep nothing across restarts
+    #[default]
+    Memory,
+    /// Keep snapshots in a JSON file, replaced atomically
+    File,
+    /// Keep snapshots in an SQLite database
+    Sqlite,
+}
+
+/// Storage of server state snapshots
+pub trait Store: fmt::Debug + Send + Sync {
+    /// Load the latest snapshot, if one was saved
+    fn load(&self) -> StoreResult<Option<StateArchive>>;
+
+    /// Replace the saved snapshot
+    fn save(&self, snapshot: &StateArchive) -> StoreResult<()>;
+
+    /// Check the integrity of the stored data, returning a description of each problem found
+    fn verify(&self) -> StoreResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Open the store of a backend, keeping its files in `state_dir`
+pub fn open(backend: StoreBackend, state_dir: &Path) -> StoreResult<Arc<dyn Store>> {
+    match backend {
+        StoreBackend::Memory => Ok(Arc::new(MemoryStore::new())),
+        StoreBackend::File => Ok(Arc::new(FileStore::new(state_dir.join(DEFAULT_SNAPSHOT_FILE)))),
+        #[cfg(feature = "sqlite")]
+        StoreBackend::Sqlite => Ok(Arc::new(SqliteStore::open(state_dir.join(DEFAULT_STORE_FILE))?)),
+        #[cfg(not(feature = "sqlite"))]
+        StoreBackend::Sqlite => {
+            let _ = state_dir;
+            Err("This build has no SQLite support".into())
+        }
+    }
+}
+
+/// Store keeping the latest snapshot in memory
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    snapshot: Mutex<Option<StateArchive>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn load(&self) -> StoreResult<Option<StateArchive>> {
+        Ok(self.snapshot.lock().unwrap().clone())
+    }
+
+    fn save(&self, snapshot: &StateArchive) -> StoreResult<()> {
+        *self.snapshot.lock().unwrap() = Some(snapshot.clone());
+        Ok(())
+    }
+}
+
+/// Store keeping the latest snapshot in a JSON file
+///
+/// Snapshots are written to a temporary file next to it, synced to disk and
+/// renamed over the previous one, so the file always holds a complete snapshot.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    /// Create a store keeping its snapshot at `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Get the path of the snapshot
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the path snapshots are written to before they replace the previous one
+    fn temporary_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        self.path.with_file_name(name)
+    }
+}
+
+impl Store for FileStore {
+    fn load(&self) -> StoreResult<Option<StateArchive>> {
+        let json = match std::fs::read_to_string(&self.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let state: serde_json::Value = serde_json::from_str(&json)?;
+        let version = archive_version(&state)?;
+        if version >= ARCHIVE_VERSION {
+            return Ok(Some(StateArchive::from_value(state)?));
+        }
+        let backup = backup_path(&self.path, version);
+        std::fs::copy(&self.path, &backup)
+            .map_err(|e| format!("Failed to back up {} before migrating it: {}", self.path.display(), e))?;
+        info!(version, backup = %backup.display(), "Migrating state saved by an earlier release.");
+        let archive = StateArchive::from_value(state)
+            .map_err(|e| format!("{}; the state as saved is kept in {}", e, backup.display()))?;
+        Ok(Some(archive))
+    }
+
+    fn save(&self, snapshot: &StateArchive) -> StoreResult<()> {
+        let directory = self.path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(directory)?;
+        let temporary = self.temporary_path();
+        let mut file = File::create(&temporary)?;
+        file.write_all(snapshot.to_json()?.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, &self.path)?;
+        // Make the rename itself durable
+        File::open(directory)?.sync_all()?;
+        Ok(())
+    }
+
+    fn verify(&self) -> StoreResult<Vec<String>> {
+        let temporary = self.temporary_path();
+        Ok(match temporary.exists() {
+            true => vec![format!("{} was left behind by an interrupted save", temporary.display())],
+            false => Vec::new(),
+        })
+    }
+}
+
+/// Store keeping the latest snapshot in an SQLite database
+///
+/// Each broadcast, pending notification, poll and scheduled broadcast is a row holding its JSON
+/// serialization, so they can be inspected with the `sqlite3` shell.
+#[cfg(feature = "sqlite")]
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Open or create the database at `path`
+    pub fn open(path: impl Into<PathBuf>) -> StoreResult<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let connection = rusqlite::Connection::open(&path)?;
+        connection.pragma_update_and_check(None, "journal_mode", "wal", |row| row.get::<_, String>(0))?;
+        connection.pragma_update(None, "synchronous", "full")?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS settings (name TEXT PRIMARY KEY, value TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS broadcasts (id INTEGER PRIMARY KEY, record TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS spool (position INTEGER PRIMARY KEY, entry TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS in_flight (position INTEGER PRIMARY KEY, entry TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS polls (broadcast_id INTEGER PRIMARY KEY, poll TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS scheduled (id INTEGER PRIMARY KEY, entry TEXT NOT NULL);",
+        )?;
+        Ok(Self {
+            path,
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Get the path of the database
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Copy the database to `backup`, replacing an earlier copy
+    fn back_up(connection: &rusqlite::Connection, backup: &Path) -> StoreResult<()> {
+        match std::fs::remove_file(backup) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        connection.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl fmt::Debug for SqliteStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteStore").field("path", &self.path).finish()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Store for SqliteStore {
+    fn load(&self) -> StoreResult<Option<StateArchive>> {
+        use rusqlite::OptionalExtension;
+
+        let connection = self.connection.lock().unwrap();
+        let setting = |name: &str| -> rusqlite::Result<Option<String>> {
+            connection
+                .query_row("SELECT value FROM settings WHERE name = ?1", [name], |row| row.get(0))
+                .optional()
+        };
+        let Some(version) = setting("version")? else {
+            return Ok(None);
+        };
+        let version: u32 = version.parse()?;
+        let backup = match version < ARCHIVE_VERSION {
+            true => {
+                let backup = backup_path(&self.path, version);
+                Self::back_up(&connection, &backup)
+                    .map_err(|e| format!("Failed to back up {} before migrating it: {}", self.path.display(), e))?;
+                info!(version, backup = %backup.display(), "Migrating state saved by an earlier release.");
+                Some(backup)
+            }
+            false => None,
+        };
+        // Rows are read as plain JSON, so ones written in an earlier format can be migrated
+        let rows = |sql: &str| -> StoreResult<Vec<serde_json::Value>> {
+            let mut statement = connection.prepare(sql)?;
+            let values = statement.query_map([], |row| row.get::<_, String>(0))?;
+            values.map(|value| Ok(serde_json::from_str(&value?)?)).collect()
+        };
+        let mut statement = connection.prepare("SELECT broadcast_id, poll FROM polls ORDER BY broadcast_id")?;
+        let polls = statement
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .map(|row| {
+                let (id, poll) = row?;
+                Ok(serde_json::json!([id as u64, serde_json::from_str::<serde_json::Value>(&poll)?]))
+            })
+            .collect::<StoreResult<Vec<_>>>()?;
+        let state = serde_json::json!({
+            "version": version,
+            "maintenance": setting("maintenance")?.is_some_and(|value| value == "true"),
+            "paused": setting("paused")?.is_some_and(|value| value == "true"),
+            "broadcasts": rows("SELECT record FROM broadcasts ORDER BY id")?,
+            "spool": rows("SELECT entry FROM spool ORDER BY position")?,
+            "in_flight": rows("SELECT entry FROM in_flight ORDER BY position")?,
+            "polls": polls,
+            "scheduled": rows("SELECT entry FROM scheduled ORDER BY id")?,
+        });
+        let archive = StateArchive::from_value(state).map_err(|e| match &backup {
+            Some(backup) => format!("{}; the state as saved is kept in {}", e, backup.display()).into(),
+            None => e,
+        })?;
+        Ok(Some(archive))
+    }
+
+    fn save(&self, snapshot: &StateArchive) -> StoreResult<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(
+            "DELETE FROM settings; DELETE FROM broadcasts; DELETE FROM spool; DELETE FROM in_flight;
+             DELETE FROM polls; DELETE FROM scheduled;",
+        )?;
+        let mut setting = transaction.prepare("INSERT INTO settings (name, value) VALUES (?1, ?2)")?;
+        setting.execute(("version", snapshot.version.to_string()))?;
+        setting.execute(("maintenance", snapshot.maintenance.to_string()))?;
+        setting.execute(("paused", snapshot.paused.to_string()))?;
+        let mut broadcast = transaction.prepare("INSERT INTO broadcasts (id, record) VALUES (?1, ?2)")?;
+        for record in &snapshot.broadcasts {
+            broadcast.execute((record.id as i64, serde_json::to_string(record)?))?;
+        }
+        let mut entry = transaction.prepare("INSERT INTO spool (position, entry) VALUES (?1, ?2)")?;
+        for (position, notification) in snapshot.spool.iter().enumerate() {
+            entry.execute((position as i64, serde_json::to_string(notification)?))?;
+        }
+        let mut in_flight = transaction.prepare("INSERT INTO in_flight (position, entry) VALUES (?1, ?2)")?;
+        for (position, notification) in snapshot.in_flight.iter().enumerate() {
+            in_flight.execute((position as i64, serde_json::to_string(notification)?))?;
+        }
+        let mut poll = transaction.prepare("INSERT INTO polls (broadcast_id, poll) VALUES (?1, ?2)")?;
+        for (id, item) in &snapshot.polls {
+            poll.execute((*id as i64, serde_json::to_string(item)?))?;
+        }
+        let mut scheduled = transaction.prepare("INSERT INTO scheduled (id, entry) VALUES (?1, ?2)")?;
+        for item in &snapshot.scheduled {
+            scheduled.execute((item.id as i64, serde_json::to_string(item)?))?;
+        }
+        drop((setting, broadcast, entry, in_flight, poll, scheduled));
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn verify(&self) -> StoreResult<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("PRAGMA integrity_check")?;
+        let messages = statement.query_map([], |row| row.get::<_, String>(0))?;
+        Ok(messages
+            .filter(|message| !matches!(message.as_deref(), Ok("ok")))
+            .map(|message| message.unwrap_or_else(|e| e.to_string()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::archive::ARCHIVE_VERSION;
+    use crate::broadcast::BroadcastRegistry;
+    use crate::payload::BroadcastPayload;
+    use crate::poll::Poll;
+    use crate::request::SendOptions;
+    use crate::schedule::Scheduler;
+    use crate::spool::SpooledNotification;
+    use crate::types::TargetUser;
+
+    fn snapshot() -> StateArchive {
+        let scheduler = Scheduler::new();
+        let options = SendOptions { channel: Some("ops".to_string()), ..SendOptions::default() };
+        scheduler.add(1_900_000_000, "Patching".to_string(), "At 22:00".to_string(), options, None);
+        let registry = BroadcastRegistry::new();
+        let user = TargetUser::new(1000, "alice".to_string());
+        let id = registry.register_with(Some("ops".to_string()), vec!["incident-421".to_string()], None);
+        registry.record_delivery(id, user.clone(), 7);
+        StateArchive {
+            version: ARCHIVE_VERSION,
+            maintenance: true,
+            paused: true,
+            broadcasts: registry.list(None),
+            spool: vec![
+                SpooledNotification::new(user.clone(), Arc::new(BroadcastPayload::new("Reboot", "Tonight")))
+                    .with_broadcast_id(id),
+                SpooledNotification::new(user.clone(), Arc::new(BroadcastPayload::new("Lunch", "Pizza"))),
+            ],
+            in_flight: vec![SpooledNotification::new(user.clone(), Arc::new(BroadcastPayload::new("Fire", "Drill")))],
+            polls: vec![(
+                id,
+                Poll {
+                    options: vec!["Yes".to_string()],
+                    responses: vec![(user, "Yes".to_string())],
+                },
+            )],
+            scheduled: scheduler.list(),
+        }
+    }
+
+    fn assert_round_trip(store: &dyn Store) {
+        assert_eq!(store.load().unwrap(), None);
+        let mut snapshot = snapshot();
+        store.save(&snapshot).unwrap();
+        assert_eq!(store.load().unwrap(), Some(snapshot.clone()));
+
+        snapshot.spool.remove(0);
+        snapshot.in_flight.clear();
+        snapshot.scheduled.clear();
+        snapshot.maintenance = false;
+        snapshot.paused = false;
+        store.save(&snapshot).unwrap();
+        assert_eq!(store.load().unwrap(), Some(snapshot));
+    }
+
+    #[test]
+    fn test_memory_store() {
+        assert_round_trip(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(dir.path().join("nested").join(DEFAULT_SNAPSHOT_FILE));
+        assert_round_trip(&store);
+        assert!(store.verify().unwrap().is_empty());
+
+        // A save interrupted before the rename leaves the previous snapshot intact
+        std::fs::write(store.temporary_path(), "{\"version\": 1, \"maint").unwrap();
+        assert_eq!(store.load().unwrap().unwrap().spool.len(), 1);
+        assert_eq!(store.verify().unwrap().len(), 1);
+        store.save(&snapshot()).unwrap();
+        assert!(store.verify().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_file_store_backed_up_before_migrating() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(dir.path().join(DEFAULT_SNAPSHOT_FILE));
+        let mut json: serde_json::Value = serde_json::from_str(&snapshot().to_json().unwrap()).unwrap();
+        json["version"] = 0.into();
+        std::fs::write(store.path(), json.to_string()).unwrap();
+
+        // No release wrote version 0, so there is nothing to migrate it with
+        let error = store.load().unwrap_err().to_string();
+        let backup = backup_path(store.path(), 0);
+        assert!(error.starts_with("No migration from archive version 0 is known"), "{}", error);
+        assert!(error.ends_with(&format!("kept in {}", backup.display())), "{}", error);
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), json.to_string());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_store_backed_up_before_migrating() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_STORE_FILE);
+        let store = SqliteStore::open(&path).unwrap();
+        store.save(&StateArchive { version: 0, ..snapshot() }).unwrap();
+
+        let error = store.load().unwrap_err().to_string();
+        assert!(error.starts_with("No migration from archive version 0 is known"), "{}", error);
+        let backup = SqliteStore::open(backup_path(&path, 0)).unwrap();
+        assert!(backup.load().is_err());
+        let spooled: i64 =
+            backup.connection.lock().unwrap().query_row("SELECT count(*) FROM spool", [], |row| row.get(0)).unwrap();
+        assert_eq!(spooled, 2);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join(DEFAULT_STORE_FILE);
+        assert_round_trip(&SqliteStore::open(&path).unwrap());
+
+        // The snapshot survives reopening the database
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.path(), path);
+        assert_eq!(store.load().unwrap().unwrap().spool.len(), 1);
+        assert!(store.verify().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(StoreBackend::Memory, dir.path()).unwrap();
+        assert_eq!(store.load().unwrap(), None);
+        let store = open(StoreBackend::File, dir.path()).unwrap();
+        assert_eq!(store.load().unwrap(), None);
+        let store = open(StoreBackend::Sqlite, dir.path());
+        assert_eq!(store.is_ok(), cfg!(feature = "sqlite"));
+    }
+}
@@ -0,0 +1,59 @@
+//! Summaries of suppressed notifications
+//!
+//! Broadcasts coalesced into an earlier duplicate and spooled notifications
+//! superseded by a newer one of their group are never shown on their own. With
+//! `suppression_summary_secs` set, they are counted per user, and once that long
+//! has passed since the first one the user gets a single notification telling
+//! how many were suppressed, so suppression is visible rather than silent.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::types::TargetUser;
+
+/// Notifications suppressed per user since their summary period started
+#[derive(Debug, Default)]
+pub struct SuppressionCounter {
+    counts: Mutex<HashMap<u32, (TargetUser, u32)>>,
+}
+
+impl SuppressionCounter {
+    /// Create a counter without suppressed notifications
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a notification suppressed for a user
+    ///
+    /// Returns whether it is the first one since the user's last summary, which
+    /// starts a new summary period.
+    pub fn record(&self, user: &TargetUser) -> bool {
+        let mut counts = self.counts.lock().unwrap();
+        let (_, count) = counts.entry(user.uid).or_insert_with(|| (user.clone(), 0));
+        *count += 1;
+        *count == 1
+    }
+
+    /// Take the number of notifications suppressed for a user since the summary period started
+    pub fn take(&self, uid: u32) -> Option<(TargetUser, u32)> {
+        self.counts.lock().unwrap().remove(&uid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suppressed_counted_per_period() {
+        let counter = SuppressionCounter::new();
+        let alice = TargetUser::new(1000, "alice".to_string());
+        assert!(counter.record(&alice));
+        assert!(!counter.record(&alice));
+        assert!(counter.record(&TargetUser::new(1001, "bob".to_string())));
+
+        assert_eq!(counter.take(1000), Some((alice.clone(), 2)));
+        assert_eq!(counter.take(1000), None);
+        assert!(counter.record(&alice));
+    }
+}
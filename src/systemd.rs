@@ -0,0 +1,82 @@
+//! Readiness, watchdog and status notifications to systemd
+//!
+//! Run as a `Type=notify` service, the server tells systemd it is ready once it
+//! serves requests, keeps a `WatchdogSec` watchdog fed from its main loop so a
+//! hung server is restarted, and describes what it is doing in `STATUS=`, shown
+//! by `systemctl status`. Messages go to the datagram socket systemd passes in
+//! `NOTIFY_SOCKET`; without one, as when run by hand, nothing is sent.
+
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// Send a notification such as `READY=1` to systemd, returning whether it expects them
+pub fn notify(state: &str) -> io::Result<bool> {
+    match env::var("NOTIFY_SOCKET") {
+        Ok(socket) => send(&socket, state).map(|()| true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Send a notification to the socket at `path`
+fn send(path: &str, state: &str) -> io::Result<()> {
+    // A leading @ names a socket in the abstract namespace
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+/// Get how often the watchdog must be fed, if systemd watches this process
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok();
+    let pid = env::var("WATCHDOG_PID").ok();
+    parse_watchdog(usec.as_deref(), pid.as_deref(), std::process::id())
+}
+
+/// Get how often to feed a watchdog of `WATCHDOG_USEC`, meant for `WATCHDOG_PID` if set
+///
+/// The watchdog is fed twice per timeout, so a late tick does not trip it.
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(own_pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// Describe the server for `STATUS=`
+pub fn status_line(sessions: usize, pending: usize) -> String {
+    format!("Serving {} user sessions, {} notifications pending", sessions, pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(parse_watchdog(Some("30000000"), None, 42), Some(Duration::from_secs(15)));
+        assert_eq!(parse_watchdog(Some("30000000"), Some("42"), 42), Some(Duration::from_secs(15)));
+        assert_eq!(parse_watchdog(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog(Some("soon"), None, 42), None);
+        assert_eq!(parse_watchdog(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_notify_sends_datagram() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        send(path.to_str().unwrap(), "READY=1\nSTATUS=Serving 0 user sessions").unwrap();
+
+        let mut buffer = [0; 64];
+        let len = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1\nSTATUS=Serving 0 user sessions");
+    }
+}
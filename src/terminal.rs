@@ -0,0 +1,187 @@
+//! Fallback delivery to a user's terminals
+//!
+//! Users on bare window manager setups may have no notification daemon at all.
+//! For them a broadcast can be written to every terminal they own, much like
+//! `wall(1)` does. Terminals are opened non-blocking so a stalled one cannot
+//! hold up the broadcast.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use tracing::{debug, warn};
+
+use crate::delivery::{DeliveryError, DeliveryErrorKind};
+use crate::payload::BroadcastPayload;
+use crate::sink::{DeliveryOptions, NotificationSink};
+use crate::types::TargetUser;
+
+/// Directory searched for pseudo-terminals
+pub const PTS_DIR: &str = "/dev/pts";
+
+/// Directory searched for virtual consoles (`tty1`, `tty2`, ...)
+pub const DEV_DIR: &str = "/dev";
+
+/// Sink writing notifications to the terminals a user owns
+#[derive(Debug, Clone)]
+pub struct TerminalSink {
+    dirs: Vec<PathBuf>,
+}
+
+impl TerminalSink {
+    /// Create a sink searching the system's terminal directories
+    pub fn new() -> Self {
+        Self::with_dirs(vec![PathBuf::from(PTS_DIR), PathBuf::from(DEV_DIR)])
+    }
+
+    /// Create a sink searching custom directories for terminals
+    pub fn with_dirs(dirs: Vec<PathBuf>) -> Self {
+        Self { dirs }
+    }
+
+    /// Find the terminals owned by a user
+    pub fn user_terminals(&self, uid: u32) -> Vec<PathBuf> {
+        self.dirs
+            .iter()
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| is_terminal_name(path))
+            .filter(|path| std::fs::metadata(path).is_ok_and(|meta| meta.uid() == uid))
+            .collect()
+    }
+
+    /// Write a payload under a heading to every terminal a user owns, returning how many were written to
+    pub fn write_to_user(&self, user: &TargetUser, payload: &BroadcastPayload, heading: &str) -> usize {
+        let message = format_message(heading, &payload.title, &payload.body);
+        self.user_terminals(user.uid)
+            .into_iter()
+            .filter(|path| {
+                let result = OpenOptions::new()
+                    .write(true)
+                    .custom_flags(nix::libc::O_NONBLOCK | nix::libc::O_NOCTTY)
+                    .open(path)
+                    .and_then(|mut terminal| terminal.write_all(message.as_bytes()));
+                match result {
+                    Ok(()) => {
+                        debug!(terminal = %path.display(), "Wrote notification to terminal.");
+                        true
+                    }
+                    Err(e) => {
+                        warn!(terminal = %path.display(), "Failed to write notification to terminal: {}", e);
+                        false
+                    }
+                }
+            })
+            .count()
+    }
+}
+
+impl Default for TerminalSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NotificationSink for TerminalSink {
+    fn notify<'a>(
+        &'a self,
+        user: &'a TargetUser,
+        payload: Arc<BroadcastPayload>,
+        options: &'a DeliveryOptions,
+    ) -> BoxFuture<'a, Result<u32, DeliveryError>> {
+        Box::pin(async move {
+            let payload = options.apply_footer(payload);
+            let heading = match &options.terminal_heading {
+                Some(heading) => heading.clone(),
+                None => format!("Broadcast message from {}:", options.app_name),
+            };
+            match self.write_to_user(user, &payload, &heading) {
+                0 => Err(DeliveryError::new(DeliveryErrorKind::Other, "no writable terminals")),
+                // Terminal messages cannot be replaced or closed, so they get no notification id
+                _ => Ok(0),
+            }
+        })
+    }
+
+    fn close<'a>(
+        &'a self,
+        _user: &'a TargetUser,
+        _bus_name: &'a str,
+        _notification_id: u32,
+    ) -> BoxFuture<'a, Result<(), DeliveryError>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Whether a path names a pseudo-terminal (`/dev/pts/N`) or virtual console (`/dev/ttyN`)
+fn is_terminal_name(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let number = name.strip_prefix("tty").unwrap_or(name);
+    !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Format a terminal message, stripping control characters so the payload cannot
+/// inject escape sequences into the user's terminal
+pub fn format_message(heading: &str, title: &str, body: &str) -> String {
+    let clean = |text: &str| -> String {
+        text.lines()
+            .map(|line| line.chars().filter(|c| !c.is_control()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    };
+    format!(
+        "\r\n\x07{}\r\n\r\n{}\r\n{}\r\n",
+        clean(heading),
+        clean(title),
+        clean(body)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_terminal_name() {
+        assert!(is_terminal_name(Path::new("/dev/pts/3")));
+        assert!(is_terminal_name(Path::new("/dev/tty2")));
+        assert!(!is_terminal_name(Path::new("/dev/pts/ptmx")));
+        assert!(!is_terminal_name(Path::new("/dev/tty")));
+        assert!(!is_terminal_name(Path::new("/dev/ttyS0")));
+        assert!(!is_terminal_name(Path::new("/dev/null")));
+    }
+
+    #[test]
+    fn test_format_message_strips_control_characters() {
+        let message = format_message("Broadcast message from Notifier:", "Disk\x1b[2J full", "line one\nline\x07 two");
+        assert_eq!(
+            message,
+            "\r\n\x07Broadcast message from Notifier:\r\n\r\nDisk[2J full\r\nline one\r\nline two\r\n"
+        );
+    }
+
+    #[test]
+    fn test_write_to_user_terminals() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("3"), "").unwrap();
+        std::fs::write(dir.path().join("ptmx"), "").unwrap();
+        let sink = TerminalSink::with_dirs(vec![dir.path().to_path_buf()]);
+
+        let uid = nix::unistd::getuid().as_raw();
+        let user = TargetUser::new(uid, "alice".to_string());
+        let payload = BroadcastPayload::new("title", "body");
+        assert_eq!(sink.write_to_user(&user, &payload, "Rundnachricht von Notifier:"), 1);
+
+        let written = std::fs::read_to_string(dir.path().join("3")).unwrap();
+        assert!(written.contains("Rundnachricht von Notifier:\r\n\r\ntitle\r\nbody"));
+
+        let other = TargetUser::new(uid.wrapping_add(1), "bob".to_string());
+        assert_eq!(sink.write_to_user(&other, &payload, "Rundnachricht von Notifier:"), 0);
+    }
+}
@@ -1,18 +1,50 @@
 //! Core types used throughout the application
 
 use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
 
 /// Represents a target user for notifications
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct TargetUser {
     pub uid: u32,
     pub username: String,
+    pub desktop: Option<String>,
+    pub session_type: Option<String>,
+    /// The logind seat of the user's session, such as `seat0`; none for remote sessions
+    #[serde(default)]
+    pub seat: Option<String>,
 }
 
 impl TargetUser {
     /// Create a new TargetUser
     pub fn new(uid: u32, username: String) -> Self {
-        Self { uid, username }
+        Self {
+            uid,
+            username,
+            desktop: None,
+            session_type: None,
+            seat: None,
+        }
+    }
+
+    /// Set the desktop environment of the user's session (`XDG_SESSION_DESKTOP`)
+    pub fn with_desktop(mut self, desktop: impl Into<String>) -> Self {
+        self.desktop = Some(desktop.into());
+        self
+    }
+
+    /// Set the type of the user's session (`x11` or `wayland`)
+    pub fn with_session_type(mut self, session_type: impl Into<String>) -> Self {
+        self.session_type = Some(session_type.into());
+        self
+    }
+
+    /// Set the seat of the user's session
+    pub fn with_seat(mut self, seat: impl Into<String>) -> Self {
+        self.seat = Some(seat.into());
+        self
     }
 
     /// Get the user ID
@@ -24,6 +56,26 @@ impl TargetUser {
     pub fn username(&self) -> &str {
         &self.username
     }
+
+    /// Get the desktop environment of the user's session, if known
+    pub fn desktop(&self) -> Option<&str> {
+        self.desktop.as_deref()
+    }
+
+    /// Get the type of the user's session, if known
+    pub fn session_type(&self) -> Option<&str> {
+        self.session_type.as_deref()
+    }
+
+    /// Get the seat of the user's session, if it has one
+    pub fn seat(&self) -> Option<&str> {
+        self.seat.as_deref()
+    }
+
+    /// Check whether a username or uid refers to this user
+    pub fn is(&self, user: &str) -> bool {
+        self.username == user || self.uid.to_string() == user
+    }
 }
 
 impl fmt::Display for TargetUser {
@@ -32,6 +84,55 @@ impl fmt::Display for TargetUser {
     }
 }
 
+/// Notification urgency levels as defined by the freedesktop notification specification
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    /// Get the value of the `urgency` hint for this level
+    pub fn as_byte(self) -> u8 {
+        match self {
+            Urgency::Low => 0,
+            Urgency::Normal => 1,
+            Urgency::Critical => 2,
+        }
+    }
+
+    /// Get the lowercase name of this level
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+impl FromStr for Urgency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Urgency::Low),
+            "normal" => Ok(Urgency::Normal),
+            "critical" => Ok(Urgency::Critical),
+            other => Err(format!("Invalid urgency '{}': expected low, normal or critical", other)),
+        }
+    }
+}
+
+impl fmt::Display for Urgency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -42,6 +143,16 @@ mod tests {
         let user = TargetUser::new(1000, "testuser".to_string());
         assert_eq!(user.uid(), 1000);
         assert_eq!(user.username(), "testuser");
+        assert_eq!(user.desktop(), None);
+    }
+
+    #[test]
+    fn test_target_user_desktop() {
+        let user = TargetUser::new(1000, "testuser".to_string()).with_desktop("sway");
+        assert_eq!(user.desktop(), Some("sway"));
+        assert_eq!(user.session_type(), None);
+        assert_eq!(user.clone().with_session_type("wayland").session_type(), Some("wayland"));
+        assert_eq!(format!("{}", user), "testuser(1000)");
     }
 
     #[test]
@@ -114,10 +225,41 @@ mod tests {
         assert_eq!(format!("{}", user), format!("maxuser({})", u32::MAX));
     }
 
+    #[test]
+    fn test_target_user_is() {
+        let user = TargetUser::new(1000, "alice".to_string()).with_seat("seat0");
+        assert!(user.is("alice") && user.is("1000"));
+        assert!(!user.is("bob") && !user.is("1001"));
+        assert_eq!(user.seat(), Some("seat0"));
+    }
+
     #[test]
     fn test_target_user_unicode() {
         let user = TargetUser::new(1000, "用户".to_string());
         assert_eq!(user.username(), "用户");
         assert_eq!(format!("{}", user), "用户(1000)");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_urgency_bytes() {
+        assert_eq!(Urgency::Low.as_byte(), 0);
+        assert_eq!(Urgency::Normal.as_byte(), 1);
+        assert_eq!(Urgency::Critical.as_byte(), 2);
+        assert_eq!(Urgency::default(), Urgency::Normal);
+    }
+
+    #[test]
+    fn test_urgency_parse_and_display() {
+        for urgency in [Urgency::Low, Urgency::Normal, Urgency::Critical] {
+            assert_eq!(urgency.to_string().parse::<Urgency>().unwrap(), urgency);
+        }
+        assert!("urgent".parse::<Urgency>().is_err());
+        assert!("Critical".parse::<Urgency>().is_err());
+    }
+
+    #[test]
+    fn test_urgency_ordering() {
+        assert!(Urgency::Low < Urgency::Normal);
+        assert!(Urgency::Normal < Urgency::Critical);
+    }
+}
@@ -0,0 +1,179 @@
+//! Automatic urgency inference from notification content
+//!
+//! Upstream tools often cannot be taught to pass an urgency. Rules in the
+//! configuration match keywords or regular expressions against the title and
+//! body, and raise the urgency of matching broadcasts that did not specify one.
+
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use crate::types::Urgency;
+
+/// A rule raising the urgency of notifications whose content matches
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawUrgencyRule")]
+pub struct UrgencyRule {
+    matcher: Regex,
+    urgency: Urgency,
+}
+
+/// Urgency rule as written in the configuration file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawUrgencyRule {
+    /// Regular expression matched against the title and body
+    pattern: Option<String>,
+    /// Case-insensitive keywords matched against the title and body
+    #[serde(default)]
+    keywords: Vec<String>,
+    /// Urgency assigned to matching notifications
+    urgency: Urgency,
+}
+
+impl UrgencyRule {
+    /// Create a rule from a regular expression
+    pub fn pattern(pattern: &str, urgency: Urgency) -> Result<Self, String> {
+        let matcher = Regex::new(pattern)
+            .map_err(|e| format!("Invalid urgency rule pattern '{}': {}", pattern, e))?;
+        Ok(Self { matcher, urgency })
+    }
+
+    /// Create a rule matching any of the given keywords, ignoring case
+    pub fn keywords<S: AsRef<str>>(keywords: &[S], urgency: Urgency) -> Result<Self, String> {
+        if keywords.is_empty() || keywords.iter().any(|k| k.as_ref().is_empty()) {
+            return Err("Urgency rule keywords must not be empty".to_string());
+        }
+        let alternation = keywords
+            .iter()
+            .map(|k| regex::escape(k.as_ref()))
+            .collect::<Vec<_>>()
+            .join("|");
+        let matcher = RegexBuilder::new(&alternation)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid urgency rule keywords: {}", e))?;
+        Ok(Self { matcher, urgency })
+    }
+
+    /// Get the urgency assigned by this rule
+    pub fn urgency(&self) -> Urgency {
+        self.urgency
+    }
+
+    /// Check whether the rule matches the notification content
+    pub fn matches(&self, title: &str, body: &str) -> bool {
+        self.matcher.is_match(title) || self.matcher.is_match(body)
+    }
+}
+
+impl TryFrom<RawUrgencyRule> for UrgencyRule {
+    type Error = String;
+
+    fn try_from(raw: RawUrgencyRule) -> Result<Self, Self::Error> {
+        match (raw.pattern, raw.keywords.is_empty()) {
+            (Some(pattern), true) => Self::pattern(&pattern, raw.urgency),
+            (None, false) => Self::keywords(&raw.keywords, raw.urgency),
+            _ => Err("Urgency rule needs exactly one of 'pattern' or 'keywords'".to_string()),
+        }
+    }
+}
+
+impl PartialEq for UrgencyRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.matcher.as_str() == other.matcher.as_str() && self.urgency == other.urgency
+    }
+}
+
+/// Infer the urgency of a notification from its content
+///
+/// Returns the highest urgency of all matching rules, or `None` if no rule matches.
+pub fn infer_urgency(rules: &[UrgencyRule], title: &str, body: &str) -> Option<Urgency> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(title, body))
+        .map(UrgencyRule::urgency)
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<UrgencyRule> {
+        vec![
+            UrgencyRule::keywords(&["failed", "disk full"], Urgency::Critical).unwrap(),
+            UrgencyRule::pattern(r"\bWARN(ING)?\b", Urgency::Normal).unwrap(),
+            UrgencyRule::pattern(r"^\[info\]", Urgency::Low).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_no_match() {
+        assert_eq!(infer_urgency(&rules(), "Backup complete", "All good"), None);
+        assert_eq!(infer_urgency(&[], "FAILED", "disk full"), None);
+    }
+
+    #[test]
+    fn test_keywords_case_insensitive() {
+        assert_eq!(infer_urgency(&rules(), "Backup FAILED", ""), Some(Urgency::Critical));
+        assert_eq!(infer_urgency(&rules(), "Storage", "Disk Full on /var"), Some(Urgency::Critical));
+    }
+
+    #[test]
+    fn test_pattern_matches_title_or_body() {
+        assert_eq!(infer_urgency(&rules(), "[info] rotated logs", ""), Some(Urgency::Low));
+        assert_eq!(infer_urgency(&rules(), "Cron", "WARNING: slow job"), Some(Urgency::Normal));
+        assert_eq!(infer_urgency(&rules(), "Cron", "WARNINGS"), None);
+    }
+
+    #[test]
+    fn test_highest_urgency_wins() {
+        assert_eq!(infer_urgency(&rules(), "[info] WARN", "job failed"), Some(Urgency::Critical));
+    }
+
+    #[test]
+    fn test_keywords_are_literal() {
+        let rule = UrgencyRule::keywords(&["a.b"], Urgency::Critical).unwrap();
+        assert!(rule.matches("a.b", ""));
+        assert!(!rule.matches("axb", ""));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(UrgencyRule::pattern("(", Urgency::Critical).is_err());
+        assert!(UrgencyRule::keywords::<&str>(&[], Urgency::Critical).is_err());
+        assert!(UrgencyRule::keywords(&[""], Urgency::Critical).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_rules() {
+        #[derive(Deserialize)]
+        struct Rules {
+            rule: Vec<UrgencyRule>,
+        }
+
+        let parsed: Rules = toml::from_str(
+            r#"
+            [[rule]]
+            keywords = ["CRITICAL"]
+            urgency = "critical"
+
+            [[rule]]
+            pattern = "^low:"
+            urgency = "low"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(parsed.rule.len(), 2);
+        assert_eq!(parsed.rule[0].urgency(), Urgency::Critical);
+        assert!(parsed.rule[1].matches("low: disk trim", ""));
+
+        let both = toml::from_str::<Rules>(
+            "[[rule]]\npattern = \"x\"\nkeywords = [\"y\"]\nurgency = \"low\"",
+        );
+        assert!(both.is_err());
+
+        let neither = toml::from_str::<Rules>("[[rule]]\nurgency = \"low\"");
+        assert!(neither.is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! Per-user delivery windows
+//!
+//! A delivery window restricts the time of day at which a user may receive
+//! notifications (e.g. shift workers who should only be notified during their
+//! shift). Windows may wrap around midnight, such as `20:00-06:00`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{NaiveDateTime, NaiveTime, TimeDelta};
+use serde::Deserialize;
+
+/// A daily time range during which notifications may be delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DeliveryWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl DeliveryWindow {
+    /// Create a new delivery window from its start (inclusive) and end (exclusive) times
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        Self { start, end }
+    }
+
+    /// Get the start of the window
+    pub fn start(&self) -> NaiveTime {
+        self.start
+    }
+
+    /// Get the end of the window
+    pub fn end(&self) -> NaiveTime {
+        self.end
+    }
+
+    /// Check whether the given time of day falls inside the window
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // The window wraps around midnight
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Get the first moment at or after `now` at which the window is open
+    ///
+    /// Returns `None` for an empty window, which never opens.
+    pub fn next_opening(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        if self.start == self.end {
+            return None;
+        }
+        if self.contains(now.time()) {
+            return Some(now);
+        }
+        let opening = now.date().and_time(self.start);
+        Some(if opening > now { opening } else { opening + TimeDelta::days(1) })
+    }
+}
+
+impl FromStr for DeliveryWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid delivery window '{}': expected HH:MM-HH:MM", s))?;
+        let parse = |part: &str| {
+            NaiveTime::parse_from_str(part.trim(), "%H:%M")
+                .map_err(|e| format!("Invalid time '{}' in delivery window: {}", part.trim(), e))
+        };
+        Ok(Self::new(parse(start)?, parse(end)?))
+    }
+}
+
+impl TryFrom<String> for DeliveryWindow {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for DeliveryWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn test_next_opening() {
+        let day = chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let at = |h, m| day.and_time(time(h, m));
+        let window: DeliveryWindow = "09:00-17:00".parse().unwrap();
+        assert_eq!(window.next_opening(at(7, 0)), Some(at(9, 0)));
+        assert_eq!(window.next_opening(at(12, 0)), Some(at(12, 0)));
+        assert_eq!(window.next_opening(at(18, 0)), Some(at(9, 0) + TimeDelta::days(1)));
+
+        let night: DeliveryWindow = "22:00-06:00".parse().unwrap();
+        assert_eq!(night.next_opening(at(12, 0)), Some(at(22, 0)));
+        assert_eq!(night.next_opening(at(23, 0)), Some(at(23, 0)));
+
+        let empty: DeliveryWindow = "00:00-00:00".parse().unwrap();
+        assert_eq!(empty.next_opening(at(12, 0)), None);
+    }
+
+    #[test]
+    fn test_parse_delivery_window() {
+        let window: DeliveryWindow = "09:00-17:30".parse().unwrap();
+        assert_eq!(window.start(), time(9, 0));
+        assert_eq!(window.end(), time(17, 30));
+    }
+
+    #[test]
+    fn test_parse_delivery_window_invalid() {
+        assert!("".parse::<DeliveryWindow>().is_err());
+        assert!("09:00".parse::<DeliveryWindow>().is_err());
+        assert!("25:00-06:00".parse::<DeliveryWindow>().is_err());
+        assert!("nine-five".parse::<DeliveryWindow>().is_err());
+    }
+
+    #[test]
+    fn test_contains_same_day() {
+        let window: DeliveryWindow = "09:00-17:00".parse().unwrap();
+        assert!(window.contains(time(9, 0)));
+        assert!(window.contains(time(12, 0)));
+        assert!(!window.contains(time(17, 0)));
+        assert!(!window.contains(time(8, 59)));
+        assert!(!window.contains(time(23, 0)));
+    }
+
+    #[test]
+    fn test_contains_wraps_midnight() {
+        let window: DeliveryWindow = "20:00-06:00".parse().unwrap();
+        assert!(window.contains(time(20, 0)));
+        assert!(window.contains(time(23, 59)));
+        assert!(window.contains(time(0, 0)));
+        assert!(window.contains(time(5, 59)));
+        assert!(!window.contains(time(6, 0)));
+        assert!(!window.contains(time(12, 0)));
+        assert!(!window.contains(time(19, 59)));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let window: DeliveryWindow = "20:00-06:00".parse().unwrap();
+        assert_eq!(window.to_string(), "20:00-06:00");
+        assert_eq!(window.to_string().parse::<DeliveryWindow>().unwrap(), window);
+    }
+}
@@ -27,7 +27,7 @@ fn test_cli_integration() {
     // Test send command
     let cli = Cli::try_parse_from(["dots-notifier", "send", "Hello", "World"]).unwrap();
     match cli.command {
-        Commands::Send { title, body } => {
+        Commands::Send { title, body, .. } => {
             assert_eq!(title, "Hello");
             assert_eq!(body, "World");
         }
@@ -126,7 +126,7 @@ fn test_session_type_detection_comprehensive() {
 /// Test NotifierService creation and basic interface
 #[test]
 fn test_notifier_service_interface() {
-    let service = NotifierService;
+    let service = NotifierService::default();
     
     // Test that it implements Debug
     let debug_str = format!("{:?}", service);